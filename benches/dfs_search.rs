@@ -0,0 +1,57 @@
+//! Searches over a directory-backed store, run with and without the `parallel` feature to compare
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tbf::testing::Dataset;
+use tbf::{
+    DirectoryBackedFs, FileSystemRead, FileSystemWrite, Group, ProvidedTags, Tag, TagPredicate,
+};
+use tempdir::TempDir;
+
+const SIZES: [usize; 2] = [1_000, 10_000];
+
+fn populate(dir: &TempDir, set: &Dataset) -> DirectoryBackedFs {
+    let dfs = DirectoryBackedFs::new(dir.path()).unwrap();
+    dfs.transaction(|fs| set.populate(fs)).unwrap();
+    dfs
+}
+
+fn search(c: &mut Criterion) {
+    let mut group = c.benchmark_group("dfs_search");
+    group.sample_size(10);
+
+    for size in SIZES {
+        let set = Dataset::new(size).data_len(16);
+        let pred =
+            TagPredicate::and([TagPredicate::tag(set.tag(0)), TagPredicate::not(set.tag(1))]);
+
+        let dir = TempDir::new("bench_dfs").unwrap();
+        let dfs = populate(&dir, &set);
+
+        group.bench_with_input(BenchmarkId::new("indexed", size), &dfs, |b, dfs| {
+            b.iter(|| dfs.search_tags(pred.clone()).unwrap());
+        });
+
+        // Provided tags need every file's data read, which is where threads help most
+        dfs.register_provider(Group::custom("data"), |data: &[u8]| {
+            vec![Tag::named("first").with_value(i64::from(data[0]))]
+        })
+        .unwrap();
+        let provided = TagPredicate::value_lt(Group::custom("data"), "first", 16);
+
+        group.bench_with_input(BenchmarkId::new("provided", size), &dfs, |b, dfs| {
+            b.iter(|| dfs.search_tags(provided.clone()).unwrap());
+        });
+        group.bench_with_input(BenchmarkId::new("provided_iter", size), &dfs, |b, dfs| {
+            b.iter(|| {
+                dfs.search_tags_iter(provided.clone())
+                    .collect::<Result<Vec<_>, _>>()
+                    .unwrap()
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, search);
+criterion_main!(benches);
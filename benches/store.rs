@@ -0,0 +1,97 @@
+//! Adding, reading and searching files in the builtin stores, at a few sizes and tag cardinalities.
+//! Other backends can be compared by populating them from the same [`Dataset`]s.
+
+use std::cell::Cell;
+use std::fmt::Debug;
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion};
+use tbf::testing::Dataset;
+use tbf::{DirectoryBackedFs, FileSystem, InMemoryFs, TagPredicate};
+use tempdir::TempDir;
+
+const SIZES: [usize; 3] = [100, 1_000, 10_000];
+const CARDINALITIES: [usize; 2] = [10, 1_000];
+
+/// Every dataset to run against, with a label and how many tags it has
+fn datasets() -> impl Iterator<Item = (String, usize, Dataset)> {
+    SIZES.iter().flat_map(|&size| {
+        CARDINALITIES.iter().map(move |&tags| {
+            (
+                format!("{size}x{tags}"),
+                tags,
+                Dataset::new(size).cardinality(tags),
+            )
+        })
+    })
+}
+
+/// Run the benchmarks for one kind of store, made fresh by `make` for each dataset
+fn bench_store<F, M>(c: &mut Criterion, name: &str, make: M)
+where
+    F: FileSystem,
+    F::Error: Debug,
+    M: Fn() -> F,
+{
+    let mut group = c.benchmark_group(name);
+    group.sample_size(10);
+
+    for (label, tags, set) in datasets() {
+        group.bench_with_input(BenchmarkId::new("add", &label), &set, |b, set| {
+            b.iter_with_large_drop(|| {
+                let fs = make();
+                fs.transaction(|fs| set.populate(fs)).unwrap();
+                fs
+            });
+        });
+
+        let fs = make();
+        let ids = fs.transaction(|fs| set.populate(fs)).unwrap();
+
+        group.bench_with_input(BenchmarkId::new("get", &label), &ids, |b, ids| {
+            b.iter(|| {
+                for &id in ids.iter().step_by(ids.len().div_ceil(100)) {
+                    fs.get_info(id).unwrap();
+                }
+            });
+        });
+
+        // The most and least common tags, and a mix needing more than one lookup
+        let searches = [
+            ("search_common", TagPredicate::tag(set.tag(0))),
+            ("search_rare", TagPredicate::tag(set.tag(tags - 1))),
+            (
+                "search_mixed",
+                TagPredicate::or([
+                    TagPredicate::and([
+                        TagPredicate::tag(set.tag(1)),
+                        TagPredicate::not(set.tag(0)),
+                    ]),
+                    TagPredicate::group(set.tag(2).group().clone()),
+                ]),
+            ),
+        ];
+        for (search, pred) in searches {
+            group.bench_with_input(BenchmarkId::new(search, &label), &pred, |b, pred| {
+                b.iter(|| fs.search_tags(pred.clone()).unwrap());
+            });
+        }
+    }
+
+    group.finish();
+}
+
+fn imfs(c: &mut Criterion) {
+    bench_store(c, "imfs", InMemoryFs::new);
+}
+
+fn dfs(c: &mut Criterion) {
+    let dir = TempDir::new("bench_store").unwrap();
+    let count = Cell::new(0);
+    bench_store(c, "dfs", || {
+        count.set(count.get() + 1);
+        DirectoryBackedFs::new(dir.path().join(count.get().to_string())).unwrap()
+    });
+}
+
+criterion_group!(benches, imfs, dfs);
+criterion_main!(benches);
@@ -0,0 +1,564 @@
+//! Access control, checking who may see and change the tags of each group
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::ops::Range;
+use std::sync::mpsc::Receiver;
+
+use crate::error::{is_not_found, ErrorKind, GroupName};
+use crate::events::Event;
+use crate::metadata::Metadata;
+use crate::pattern::{glob_match, group_name};
+use crate::{
+    FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, Group, InferredTags,
+    ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer, TagPattern, TagProvider,
+    WrappedWriter,
+};
+
+/// Error for a filesystem with access control
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner filesystem returned an error
+    Fs(E),
+    /// The caller isn't permitted this access to tags in this group
+    Denied(Access, Group),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Denied(..) => ErrorKind::PermissionDenied,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Denied(Access::Read, group) => {
+                write!(f, "not permitted to read tags in {}", GroupName(group))
+            }
+            Error::Denied(Access::Write, group) => {
+                write!(f, "not permitted to change tags in {}", GroupName(group))
+            }
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Denied(..) => None,
+        }
+    }
+}
+
+/// A kind of access to the tags of a group
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Access {
+    /// Seeing files with tags in the group
+    Read,
+    /// Adding or removing tags in the group, or changing files with them
+    Write,
+}
+
+/// Who is using a filesystem: a name, and the roles they have. Rules can permit either.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Identity {
+    name: String,
+    roles: BTreeSet<String>,
+}
+
+impl Identity {
+    /// Create an identity with a name and no roles
+    pub fn new(name: &str) -> Identity {
+        Identity {
+            name: name.to_owned(),
+            roles: BTreeSet::new(),
+        }
+    }
+
+    /// Give the identity a role
+    #[must_use]
+    pub fn with_role(mut self, role: &str) -> Self {
+        self.roles.insert(role.to_owned());
+        self
+    }
+
+    /// The name of the identity
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The roles the identity has
+    pub fn roles(&self) -> &BTreeSet<String> {
+        &self.roles
+    }
+
+    /// Whether this is the identity with a name, or has it as a role
+    fn is(&self, principal: &str) -> bool {
+        self.name == principal || self.roles.contains(principal)
+    }
+}
+
+/// Rules for who may access the tags of each group. Rules name groups by glob, where `*`
+/// matches any run of characters and `?` a single one, and permit names or roles one kind of
+/// access to them.
+///
+/// Access to a group is open to everyone until a rule for that kind of access matches it, then
+/// only to those permitted by a matching rule. Reading and writing are separate, so limiting who
+/// may write to a group doesn't stop anyone seeing it.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AccessRules {
+    rules: BTreeMap<(String, Access), BTreeSet<String>>,
+}
+
+impl AccessRules {
+    /// Create rules which permit anyone any access
+    pub fn new() -> AccessRules {
+        AccessRules::default()
+    }
+
+    /// Permit names or roles some access to the groups matching a glob. Permitting more for the
+    /// same glob and access adds to those already permitted.
+    #[must_use]
+    pub fn allow<I, S>(mut self, groups: &str, access: Access, principals: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.rules
+            .entry((groups.to_owned(), access))
+            .or_default()
+            .extend(principals.into_iter().map(Into::into));
+        self
+    }
+
+    /// Check whether an identity may access the tags of a group
+    pub fn permits(&self, caller: &Identity, access: Access, group: &Group) -> bool {
+        let mut matched = false;
+        for ((glob, kind), principals) in &self.rules {
+            if *kind != access || !glob_match(glob, group_name(group)) {
+                continue;
+            }
+            if principals.iter().any(|principal| caller.is(principal)) {
+                return true;
+            }
+            matched = true;
+        }
+        !matched
+    }
+}
+
+/// A wrapper around another filesystem which checks a caller's [`Identity`] against
+/// [`AccessRules`] before passing anything on.
+///
+/// Files with a tag in a group the caller can't read are hidden: they're left out of searches,
+/// and looking them up fails as if they don't exist. Adding or removing a tag needs permission to
+/// write to its group, and so does adding a file with it. Editing the data of a file or removing
+/// it needs permission to write to every group it has tags in.
+///
+/// Special files have no tags, so aren't checked, and neither are events from `subscribe`, which
+/// are passed on as the inner filesystem sends them.
+pub struct PermissionedFs<F> {
+    inner: F,
+    rules: AccessRules,
+    caller: Identity,
+}
+
+impl<F: FileSystem> PermissionedFs<F> {
+    /// Wrap a filesystem, checking a caller against some rules
+    pub fn new(inner: F, rules: AccessRules, caller: Identity) -> PermissionedFs<F> {
+        PermissionedFs {
+            inner,
+            rules,
+            caller,
+        }
+    }
+
+    /// Get the rules the caller is checked against
+    pub fn rules(&self) -> &AccessRules {
+        &self.rules
+    }
+
+    /// Get the identity of the caller
+    pub fn caller(&self) -> &Identity {
+        &self.caller
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, no longer checking access
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn permits(&self, access: Access, group: &Group) -> bool {
+        self.rules.permits(&self.caller, access, group)
+    }
+
+    /// Check the caller may access every group of some tags
+    fn check<'t, I>(&self, access: Access, tags: I) -> Result<(), Error<F::Error>>
+    where
+        I: IntoIterator<Item = &'t Tag>,
+    {
+        match tags
+            .into_iter()
+            .find(|tag| !self.permits(access, tag.group()))
+        {
+            Some(tag) => Err(Error::Denied(access, tag.group().clone())),
+            None => Ok(()),
+        }
+    }
+
+    fn readable(&self, tags: &BTreeSet<Tag>) -> bool {
+        tags.iter()
+            .all(|tag| self.permits(Access::Read, tag.group()))
+    }
+
+    /// Get the tags of a file the caller can see, failing as if it doesn't exist otherwise
+    fn visible_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Error<F::Error>> {
+        let tags = self.inner.get_tags(id)?;
+        if self.readable(&tags) {
+            Ok(tags)
+        } else {
+            Err(crate::Error::file_not_found(id))
+        }
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for PermissionedFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            inner: self.inner.search_tags_iter(tags),
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let info = self.inner.get_info(id)?;
+        if self.readable(&info.tags) {
+            Ok(info)
+        } else {
+            Err(crate::Error::file_not_found(id))
+        }
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.visible_tags(id)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for PermissionedFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.check(Access::Write, &tags)?;
+        Ok(self.inner.add_file(data, tags)?)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.check(Access::Write, &tags)?;
+        Ok(self.inner.add_file_with_id(id, data, tags)?)
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let old = self.visible_tags(id)?;
+        if data.is_some() {
+            self.check(Access::Write, &old)?;
+        }
+        let tags = tags.map(|tags| tags.into_iter().collect::<BTreeSet<_>>());
+        if let Some(new) = &tags {
+            self.check(Access::Write, old.symmetric_difference(new))?;
+        }
+        Ok(self.inner.edit_file(id, data, tags)?)
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let tags = self.visible_tags(id)?;
+        self.check(Access::Write, &tags)?;
+        Ok(self.inner.remove_file(id)?)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done
+        let mut failed = None;
+        let out = self.inner.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Fs(err)) => Err(err),
+            Err(err) => {
+                failed = Some(err);
+                Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        match (out, failed) {
+            (Err(_), Some(err)) => Err(err),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        Ok(self.inner.set_special_data(file, data)?)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for PermissionedFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl<F: InferredTags> InferredTags for PermissionedFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for PermissionedFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.visible_tags(id)?;
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for PermissionedFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.check(Access::Write, &tags)?;
+        Ok(WrappedWriter::new(self.inner.create_file(tags)?))
+    }
+}
+
+/// A lazy search over a [`PermissionedFs`], which is a search of the inner filesystem skipping
+/// the files the caller can't see
+pub struct SearchIter<'a, F: FileSystem, P: TagPattern + 'a> {
+    fs: &'a PermissionedFs<F>,
+    inner: F::SearchIter<'a, P>,
+}
+
+impl<F: FileSystem, P: TagPattern> Iterator for SearchIter<'_, F, P> {
+    type Item = Result<FileId, Error<F::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let id = match self.inner.next()? {
+                Ok(id) => id,
+                Err(err) => return Some(Err(Error::Fs(err))),
+            };
+            match self.fs.inner.get_tags(id) {
+                Ok(tags) if self.fs.readable(&tags) => return Some(Ok(id)),
+                Ok(_) => (),
+                // Removed since it was found
+                Err(err) if is_not_found(&err) => (),
+                Err(err) => return Some(Err(Error::Fs(err))),
+            }
+        }
+    }
+}
+
+/// A handle streaming data into a new file of a [`PermissionedFs`], which is a handle of the
+/// inner filesystem. The caller's access to its tags was checked when it was created.
+pub type Writer<'a, F> =
+    WrappedWriter<<F as StreamWrite>::Writer<'a>, Error<<F as FileSystemRead>::Error>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{Error as _, InMemoryFs, TagPredicate};
+
+    fn rules() -> AccessRules {
+        AccessRules::new()
+            .allow("system*", Access::Write, ["admin"])
+            .allow("private", Access::Read, ["alice"])
+            .allow("private", Access::Write, ["alice"])
+    }
+
+    #[test]
+    fn test_rules() {
+        let rules = rules();
+        let admin = Identity::new("bob").with_role("admin");
+        let alice = Identity::new("alice");
+        let system = Group::custom("system");
+
+        assert!(rules.permits(&admin, Access::Write, &Group::custom("system.users")));
+        assert!(!rules.permits(&alice, Access::Write, &system));
+        assert!(rules.permits(&alice, Access::Read, &system));
+        assert!(rules.permits(&alice, Access::Write, &Group::Default));
+        assert!(!rules.permits(&admin, Access::Read, &Group::custom("private")));
+        assert!(rules.permits(&alice, Access::Read, &Group::custom("private")));
+    }
+
+    #[test]
+    fn test_permissioned_fs() {
+        let system = Tag::new(Group::custom("system"), "locked");
+        let private = Tag::new(Group::custom("private"), "diary");
+        let plain = Tag::named("plain");
+
+        let admin = PermissionedFs::new(
+            InMemoryFs::new(),
+            rules(),
+            Identity::new("bob").with_role("admin"),
+        );
+        let locked = admin.add_file(b"locked", [system.clone()]).unwrap();
+        let open = admin.add_file(b"open", [plain.clone()]).unwrap();
+        let fs = PermissionedFs::new(admin.into_inner(), rules(), Identity::new("alice"));
+        let diary = fs.add_file(b"diary", [private.clone()]).unwrap();
+
+        // Only admins may change system tags, or files with them
+        let err = fs.add_file(b"", [system.clone()]).unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::PermissionDenied));
+        assert!(matches!(
+            fs.add_tags(open, [system.clone()]),
+            Err(Error::Denied(Access::Write, _))
+        ));
+        assert!(fs.remove_tags(locked, [system.clone()]).is_err());
+        assert!(fs.edit_file(locked, Some(b"x"), None::<[Tag; 0]>).is_err());
+        assert!(fs.remove_file(locked).is_err());
+        assert_eq!(fs.get_data(locked).unwrap(), b"locked");
+        fs.add_tags(locked, [plain.clone()]).unwrap();
+        fs.edit_file(open, Some(b"edited"), None::<[Tag; 0]>)
+            .unwrap();
+
+        // Others can't see private files at all
+        let admin = PermissionedFs::new(
+            fs.into_inner(),
+            rules(),
+            Identity::new("bob").with_role("admin"),
+        );
+        assert_eq!(
+            admin.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+            [locked, open]
+        );
+        assert!(admin.search_tags(private.clone()).unwrap().is_empty());
+        assert!(crate::error::is_not_found(
+            &admin.get_info(diary).unwrap_err()
+        ));
+        assert!(admin.remove_file(diary).is_err());
+        assert_eq!(admin.list_tags(&Group::custom("private")).unwrap().len(), 0);
+        admin.remove_file(locked).unwrap();
+        assert_eq!(admin.inner().search_tags(private).unwrap(), [diary]);
+
+        // Failed changes in a transaction are undone
+        let err = admin
+            .transaction(|fs| {
+                fs.add_tags(open, [Tag::named("new")])?;
+                fs.edit_file(diary, Some(b""), None::<[Tag; 0]>)
+            })
+            .unwrap_err();
+        assert!(crate::error::is_not_found(&err));
+        assert_eq!(admin.get_tags(open).unwrap(), BTreeSet::from([plain]));
+    }
+}
@@ -0,0 +1,659 @@
+//! Alternative names for tags, kept inside a filesystem, and a wrapper searching by them
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+
+use crate::codec;
+use crate::error::ErrorKind;
+use crate::events::Event;
+use crate::metadata::Metadata;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, Group,
+    InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer, TagPattern,
+    TagPredicate, TagProvider, UsageReport, WrappedWriter,
+};
+
+/// Error for an alias table, or a filesystem searching by one
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The filesystem returned an error
+    Fs(E),
+    /// The stored aliases couldn't be read, as they were malformed
+    Corrupt(io::Error),
+    /// This tag would have been made an alias of itself
+    Cycle(Tag),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+            Error::Cycle(_) => ErrorKind::InvalidTags,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored aliases are malformed: {err}"),
+            Error::Cycle(tag) => write!(f, "tag `{tag}` would be an alias of itself"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+            Error::Cycle(_) => None,
+        }
+    }
+}
+
+/// The aliases of tags in a filesystem, stored in its [`SpecialFile::Aliases`] file. An alias,
+/// like `pic`, stands for a canonical tag, like `picture`, and searches through an [`AliasFs`]
+/// treat the two as the same tag.
+///
+/// Aliases always stand for a canonical tag, never another alias, so adding an alias of an alias
+/// makes it an alias of the tag that one stands for. Tags are matched exactly, including their
+/// value.
+///
+/// Changes read every alias and write them all back, so two made at once from different handles
+/// can lose one of them.
+pub struct TagAliases<'a, F: ?Sized> {
+    fs: &'a F,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> TagAliases<'a, F> {
+    /// Get the aliases of a filesystem
+    pub fn new(fs: &'a F) -> TagAliases<'a, F> {
+        TagAliases { fs }
+    }
+
+    /// Get every alias, mapped to the tag it stands for
+    pub fn aliases(&self) -> Result<BTreeMap<Tag, Tag>, Error<F::Error>> {
+        let data = self.fs.special_data(SpecialFile::Aliases)?;
+        let mut input = &*data;
+        let mut aliases = BTreeMap::new();
+        while let Some(alias) = codec::read_tag(&mut input).map_err(Error::Corrupt)? {
+            let tag = codec::read_tag(&mut input)
+                .map_err(Error::Corrupt)?
+                .ok_or_else(|| Error::Corrupt(io::ErrorKind::UnexpectedEof.into()))?;
+            aliases.insert(alias, tag);
+        }
+        Ok(aliases)
+    }
+
+    /// Get the tag an alias stands for, or the tag itself if it isn't an alias
+    pub fn resolve(&self, tag: Tag) -> Result<Tag, Error<F::Error>> {
+        Ok(resolve(&self.aliases()?, tag))
+    }
+
+    /// Replace every alias in a set of tags with the tag it stands for
+    pub fn canonicalize<I>(&self, tags: I) -> Result<BTreeSet<Tag>, Error<F::Error>>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let aliases = self.aliases()?;
+        Ok(tags.into_iter().map(|tag| resolve(&aliases, tag)).collect())
+    }
+
+    /// Rewrite a predicate so that it matches tags through their aliases, and aliases through
+    /// the tags they stand for
+    pub fn expand(&self, pred: &TagPredicate) -> Result<TagPredicate, Error<F::Error>> {
+        Ok(expand(&self.aliases()?, pred))
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> TagAliases<'_, F> {
+    fn store(&self, aliases: &BTreeMap<Tag, Tag>) -> Result<(), Error<F::Error>> {
+        let mut data = Vec::new();
+        for (alias, tag) in aliases {
+            codec::write_tag(&mut data, alias).map_err(Error::Corrupt)?;
+            codec::write_tag(&mut data, tag).map_err(Error::Corrupt)?;
+        }
+        self.fs.set_special_data(SpecialFile::Aliases, &data)?;
+        Ok(())
+    }
+
+    /// Make a tag an alias of another, replacing what it stood for if it already was one. Any
+    /// aliases of the new alias become aliases of the tag it stands for.
+    pub fn add_alias(&self, alias: Tag, tag: Tag) -> Result<(), Error<F::Error>> {
+        let mut aliases = self.aliases()?;
+        let tag = resolve(&aliases, tag);
+        if tag == alias {
+            return Err(Error::Cycle(alias));
+        }
+        for target in aliases.values_mut() {
+            if *target == alias {
+                *target = tag.clone();
+            }
+        }
+        aliases.insert(alias, tag);
+        self.store(&aliases)
+    }
+
+    /// Stop a tag being an alias. Returns whether it was one.
+    pub fn remove_alias(&self, alias: &Tag) -> Result<bool, Error<F::Error>> {
+        let mut aliases = self.aliases()?;
+        if aliases.remove(alias).is_none() {
+            return Ok(false);
+        }
+        self.store(&aliases)?;
+        Ok(true)
+    }
+}
+
+fn resolve(aliases: &BTreeMap<Tag, Tag>, tag: Tag) -> Tag {
+    match aliases.get(&tag) {
+        Some(tag) => tag.clone(),
+        None => tag,
+    }
+}
+
+fn expand(aliases: &BTreeMap<Tag, Tag>, pred: &TagPredicate) -> TagPredicate {
+    match pred {
+        TagPredicate::And(preds) => {
+            TagPredicate::And(preds.iter().map(|pred| expand(aliases, pred)).collect())
+        }
+        TagPredicate::Or(preds) => {
+            TagPredicate::Or(preds.iter().map(|pred| expand(aliases, pred)).collect())
+        }
+        TagPredicate::Not(pred) => TagPredicate::not(expand(aliases, pred)),
+        // Every other predicate matches a file if any one tag matches, so it should also match
+        // through any other name of a tag it matches
+        leaf => {
+            let mut names = BTreeMap::<&Tag, Vec<&Tag>>::new();
+            for (alias, tag) in aliases {
+                names.entry(tag).or_insert_with(|| vec![tag]).push(alias);
+            }
+            let others = names
+                .into_values()
+                .filter(|names| names.iter().any(|tag| leaf.match_tags([*tag])))
+                .flatten()
+                .filter(|tag| !leaf.match_tags([*tag]))
+                .cloned()
+                .map(TagPredicate::Tag)
+                .collect::<Vec<_>>();
+            if others.is_empty() {
+                leaf.clone()
+            } else {
+                TagPredicate::Or(core::iter::once(leaf.clone()).chain(others).collect())
+            }
+        }
+    }
+}
+
+/// A wrapper around another filesystem which searches using its [`TagAliases`], so a search for
+/// an alias also finds files with the tag it stands for, and the other way around.
+///
+/// With [`AliasFs::with_canonicalize`], aliases given to a file are also replaced with the tag
+/// they stand for before being passed on, so stored files only use canonical tags. Otherwise
+/// tags are stored as given. Either way, tags read back are those stored.
+///
+/// The aliases are read again for every search and change, so changes to them apply straight
+/// away, whether made through this wrapper or not.
+pub struct AliasFs<F> {
+    inner: F,
+    canonicalize: bool,
+}
+
+impl<F: FileSystem> AliasFs<F> {
+    /// Wrap a filesystem, searching using its aliases
+    pub fn new(inner: F) -> AliasFs<F> {
+        AliasFs {
+            inner,
+            canonicalize: false,
+        }
+    }
+
+    /// Set whether aliases given to files are replaced with the tags they stand for
+    #[must_use]
+    pub fn with_canonicalize(mut self, canonicalize: bool) -> Self {
+        self.canonicalize = canonicalize;
+        self
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, no longer searching using its aliases
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn expand<P: TagPattern>(&self, tags: &P) -> Result<TagPredicate, Error<F::Error>> {
+        TagAliases::new(&self.inner).expand(&tags.to_predicate())
+    }
+
+    /// The tags to pass on to the inner filesystem
+    fn tags<I>(&self, tags: I) -> Result<BTreeSet<Tag>, Error<F::Error>>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if self.canonicalize {
+            TagAliases::new(&self.inner).canonicalize(tags)
+        } else {
+            Ok(tags.into_iter().collect())
+        }
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for AliasFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pred = self.expand(&tags)?;
+        Ok(self.inner.search_tags_with(pred, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            inner: self
+                .expand(&tags)
+                .map(|pred| self.inner.search_tags_iter(pred))
+                .map_err(Some),
+            pattern: PhantomData,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pred = self.expand(&pattern)?;
+        Ok(self.inner.tag_counts(pred)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pred = self.expand(&pattern)?;
+        Ok(self.inner.count_tags(pred)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pred = self.expand(&pattern)?;
+        Ok(self.inner.any_match(pred)?)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        Ok(self.inner.usage()?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for AliasFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.tags(tags)?;
+        Ok(self.inner.add_file(data, tags)?)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.tags(tags)?;
+        Ok(self.inner.add_file_with_id(id, data, tags)?)
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| self.tags(tags)).transpose()?;
+        Ok(self.inner.edit_file(id, data, tags)?)
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        Ok(self.inner.remove_file(id)?)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done
+        let mut failed = None;
+        let out = self.inner.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Fs(err)) => Err(err),
+            Err(err) => {
+                failed = Some(err);
+                Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        match (out, failed) {
+            (Err(_), Some(err)) => Err(err),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.tags(tags)?;
+        Ok(self.inner.add_tags(id, tags)?)
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(self.inner.remove_tags(id, tags)?)
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        Ok(self.inner.rename_tag(old, new)?)
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        Ok(self.inner.rename_group(old, new)?)
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        Ok(self.inner.revert(id, version)?)
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        Ok(self.inner.set_special_data(file, data)?)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for AliasFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl<F: InferredTags> InferredTags for AliasFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for AliasFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for AliasFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.tags(tags)?;
+        Ok(WrappedWriter::new(self.inner.create_file(tags)?))
+    }
+}
+
+/// A lazy search over an [`AliasFs`], which is a search of the inner filesystem for the pattern
+/// expanded by its aliases
+pub struct SearchIter<'a, F: FileSystemRead + 'a, P: TagPattern + 'a> {
+    /// The inner search, or the error reading the aliases if it couldn't be started
+    inner: Result<F::SearchIter<'a, TagPredicate>, Option<Error<F::Error>>>,
+    pattern: PhantomData<P>,
+}
+
+impl<F: FileSystemRead, P: TagPattern> Iterator for SearchIter<'_, F, P> {
+    type Item = Result<FileId, Error<F::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match &mut self.inner {
+            Ok(inner) => inner.next().map(|id| id.map_err(Error::Fs)),
+            Err(err) => err.take().map(Err),
+        }
+    }
+}
+
+/// A handle streaming data into a new file of an [`AliasFs`], which is a handle of the inner
+/// filesystem. Its tags were canonicalized when it was created, if the wrapper does so.
+pub type Writer<'a, F> =
+    WrappedWriter<<F as StreamWrite>::Writer<'a>, Error<<F as FileSystemRead>::Error>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{Error as _, InMemoryFs};
+
+    #[test]
+    fn test_aliases() {
+        let fs = InMemoryFs::new();
+        let aliases = TagAliases::new(&fs);
+        let pic = Tag::named("pic");
+        let image = Tag::named("image");
+        let picture = Tag::named("picture");
+
+        aliases.add_alias(pic.clone(), image.clone()).unwrap();
+        // Aliasing the tag an alias stands for moves the alias along with it
+        aliases.add_alias(image.clone(), picture.clone()).unwrap();
+        assert_eq!(
+            aliases.aliases().unwrap(),
+            BTreeMap::from([
+                (pic.clone(), picture.clone()),
+                (image.clone(), picture.clone())
+            ])
+        );
+        assert_eq!(aliases.resolve(pic.clone()).unwrap(), picture);
+        assert_eq!(aliases.resolve(picture.clone()).unwrap(), picture);
+        assert_eq!(
+            aliases
+                .canonicalize([pic.clone(), Tag::named("other")])
+                .unwrap(),
+            BTreeSet::from([picture.clone(), Tag::named("other")])
+        );
+
+        let err = aliases.add_alias(picture.clone(), pic.clone()).unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::InvalidTags));
+
+        assert!(aliases.remove_alias(&image).unwrap());
+        assert!(!aliases.remove_alias(&image).unwrap());
+        assert_eq!(aliases.aliases().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_alias_fs() {
+        let fs = AliasFs::new(InMemoryFs::new());
+        let pic = Tag::named("pic");
+        let picture = Tag::named("picture");
+        TagAliases::new(&fs)
+            .add_alias(pic.clone(), picture.clone())
+            .unwrap();
+
+        let a = fs.add_file(&[1], [pic.clone()]).unwrap();
+        let b = fs.add_file(&[2], [picture.clone()]).unwrap();
+        let c = fs.add_file(&[3], [Tag::named("other")]).unwrap();
+        // Tags are stored as given
+        assert_eq!(fs.get_tags(a).unwrap(), BTreeSet::from([pic.clone()]));
+
+        assert_eq!(fs.search_tags(pic.clone()).unwrap(), vec![a, b]);
+        assert_eq!(fs.search_tags(picture.clone()).unwrap(), vec![a, b]);
+        assert_eq!(
+            fs.search_tags(TagPredicate::name_glob("pict*")).unwrap(),
+            vec![a, b]
+        );
+        assert_eq!(
+            fs.search_tags(TagPredicate::not(pic.clone())).unwrap(),
+            vec![c]
+        );
+        assert_eq!(
+            fs.search_tags_iter(pic.clone())
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            vec![a, b]
+        );
+        assert_eq!(fs.inner().search_tags(pic.clone()).unwrap(), vec![a]);
+
+        let fs = fs.with_canonicalize(true);
+        let d = fs.add_file(&[4], [pic.clone()]).unwrap();
+        assert_eq!(fs.get_tags(d).unwrap(), BTreeSet::from([picture.clone()]));
+        fs.add_tags(c, [pic.clone()]).unwrap();
+        assert!(fs.get_tags(c).unwrap().contains(&picture));
+        fs.edit_file(a, None, Some([pic])).unwrap();
+        assert_eq!(fs.get_tags(a).unwrap(), BTreeSet::from([picture]));
+    }
+}
@@ -0,0 +1,205 @@
+//! Asynchronous variant of the main filesystem trait
+
+use alloc::vec::Vec;
+use core::future::Future;
+
+use crate::{Error, FileId, FileInfo, Tag, TagPattern};
+
+/// An asynchronous implementation of a tag-based filesystem. This mirrors
+/// [`FileSystem`](crate::FileSystem), but every operation returns a future, so implementations
+/// can avoid blocking the executor.
+pub trait AsyncFileSystem {
+    /// The error type to use with this filesystem.
+    type Error: Error;
+
+    // Add/Remove/Edit files
+
+    /// Add a new file with the given data and tags
+    fn add_file<I>(
+        &self,
+        data: &[u8],
+        tags: I,
+    ) -> impl Future<Output = Result<FileId, Self::Error>> + Send
+    where
+        I: IntoIterator<Item = Tag> + Send;
+
+    /// Edit an existing file, altering the data or tags
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> impl Future<Output = Result<(), Self::Error>> + Send
+    where
+        I: IntoIterator<Item = Tag> + Send;
+
+    /// Remove an existing file
+    fn remove_file(&self, id: FileId) -> impl Future<Output = Result<(), Self::Error>> + Send;
+
+    // Lookup files
+
+    /// Search for files matching a given tag pattern
+    fn search_tags<P>(
+        &self,
+        tags: P,
+    ) -> impl Future<Output = Result<Vec<FileId>, Self::Error>> + Send
+    where
+        P: TagPattern + Send + 'static;
+
+    /// Get info about an existing file
+    fn get_info(&self, id: FileId) -> impl Future<Output = Result<FileInfo, Self::Error>> + Send;
+}
+
+#[cfg(feature = "imfs")]
+mod imfs {
+    use super::{AsyncFileSystem, FileId, FileInfo, Tag, TagPattern, Vec};
+    use crate::{FileSystemRead, FileSystemWrite, ImfsError, InMemoryFs};
+
+    /// The in-memory filesystem never blocks, so its operations complete immediately
+    impl AsyncFileSystem for InMemoryFs {
+        type Error = ImfsError;
+
+        async fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+        where
+            I: IntoIterator<Item = Tag> + Send,
+        {
+            FileSystemWrite::add_file(self, data, tags)
+        }
+
+        async fn edit_file<I>(
+            &self,
+            id: FileId,
+            data: Option<&[u8]>,
+            tags: Option<I>,
+        ) -> Result<(), Self::Error>
+        where
+            I: IntoIterator<Item = Tag> + Send,
+        {
+            FileSystemWrite::edit_file(self, id, data, tags)
+        }
+
+        async fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+            FileSystemWrite::remove_file(self, id)
+        }
+
+        async fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+        where
+            P: TagPattern + Send + 'static,
+        {
+            FileSystemRead::search_tags(self, tags)
+        }
+
+        async fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+            FileSystemRead::get_info(self, id)
+        }
+    }
+}
+
+#[cfg(feature = "dfs")]
+pub use dfs::AsyncDirectoryBackedFs;
+
+#[cfg(feature = "dfs")]
+mod dfs {
+    use std::io;
+    use std::path::PathBuf;
+    use std::sync::Arc;
+
+    use super::{AsyncFileSystem, FileId, FileInfo, Future, Tag, TagPattern, Vec};
+    use crate::{DfsError, DirectoryBackedFs, FileSystemRead, FileSystemWrite};
+
+    /// A [`DirectoryBackedFs`] usable from async code. Every operation is run on tokio's blocking
+    /// thread pool, so callers don't have to do so themselves.
+    #[derive(Clone)]
+    pub struct AsyncDirectoryBackedFs {
+        inner: Arc<DirectoryBackedFs>,
+    }
+
+    impl AsyncDirectoryBackedFs {
+        /// Create or load a directory-backed filesystem, in the provided directory.
+        pub async fn new<P: Into<PathBuf>>(dir: P) -> Result<AsyncDirectoryBackedFs, DfsError> {
+            let dir = dir.into();
+            let inner = run_blocking(move || DirectoryBackedFs::new(dir)).await?;
+            Ok(AsyncDirectoryBackedFs::from(inner))
+        }
+
+        /// Get the synchronous filesystem this wraps
+        pub fn inner(&self) -> &DirectoryBackedFs {
+            &self.inner
+        }
+    }
+
+    impl From<DirectoryBackedFs> for AsyncDirectoryBackedFs {
+        fn from(inner: DirectoryBackedFs) -> Self {
+            AsyncDirectoryBackedFs {
+                inner: Arc::new(inner),
+            }
+        }
+    }
+
+    async fn run_blocking<T, F>(f: F) -> Result<T, DfsError>
+    where
+        T: Send + 'static,
+        F: FnOnce() -> Result<T, DfsError> + Send + 'static,
+    {
+        tokio::task::spawn_blocking(f)
+            .await
+            .map_err(|err| DfsError::IoError(io::Error::other(err)))?
+    }
+
+    impl AsyncFileSystem for AsyncDirectoryBackedFs {
+        type Error = DfsError;
+
+        fn add_file<I>(
+            &self,
+            data: &[u8],
+            tags: I,
+        ) -> impl Future<Output = Result<FileId, Self::Error>> + Send
+        where
+            I: IntoIterator<Item = Tag> + Send,
+        {
+            let inner = Arc::clone(&self.inner);
+            let data = data.to_vec();
+            let tags = tags.into_iter().collect::<Vec<_>>();
+            run_blocking(move || inner.add_file(&data, tags))
+        }
+
+        fn edit_file<I>(
+            &self,
+            id: FileId,
+            data: Option<&[u8]>,
+            tags: Option<I>,
+        ) -> impl Future<Output = Result<(), Self::Error>> + Send
+        where
+            I: IntoIterator<Item = Tag> + Send,
+        {
+            let inner = Arc::clone(&self.inner);
+            let data = data.map(<[u8]>::to_vec);
+            let tags = tags.map(|tags| tags.into_iter().collect::<Vec<_>>());
+            run_blocking(move || inner.edit_file(id, data.as_deref(), tags))
+        }
+
+        fn remove_file(&self, id: FileId) -> impl Future<Output = Result<(), Self::Error>> + Send {
+            let inner = Arc::clone(&self.inner);
+            run_blocking(move || inner.remove_file(id))
+        }
+
+        fn search_tags<P>(
+            &self,
+            tags: P,
+        ) -> impl Future<Output = Result<Vec<FileId>, Self::Error>> + Send
+        where
+            P: TagPattern + Send + 'static,
+        {
+            let inner = Arc::clone(&self.inner);
+            run_blocking(move || inner.search_tags(tags))
+        }
+
+        fn get_info(
+            &self,
+            id: FileId,
+        ) -> impl Future<Output = Result<FileInfo, Self::Error>> + Send {
+            let inner = Arc::clone(&self.inner);
+            run_blocking(move || inner.get_info(id))
+        }
+    }
+}
@@ -0,0 +1,714 @@
+//! A record of who changed what, kept inside the filesystem it's about
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::codec;
+use crate::error::ErrorKind;
+use crate::events::Event;
+use crate::metadata::{now, Metadata};
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, FileWriter, Group,
+    InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer, TagPattern,
+    TagProvider, UsageReport, WrappedSearchIter,
+};
+
+/// Error for a filesystem keeping an audit log
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner filesystem returned an error
+    Fs(E),
+    /// The stored log couldn't be read, as it was malformed
+    Corrupt(io::Error),
+    /// The log was going to be replaced, which would lose what it recorded
+    AppendOnly,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+            Error::AppendOnly => ErrorKind::ReadOnly,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored audit log is malformed: {err}"),
+            Error::AppendOnly => write!(f, "audit log can't be replaced"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+            Error::AppendOnly => None,
+        }
+    }
+}
+
+/// A change to a file, as recorded in an audit log
+#[derive(Debug, Clone, PartialEq)]
+pub enum AuditAction {
+    /// The file was added with these tags
+    Added {
+        /// The tags it was given
+        tags: BTreeSet<Tag>,
+    },
+    /// The file's data, tags, or both were changed
+    Edited {
+        /// Whether its data was replaced
+        data: bool,
+        /// The tags it was given
+        added: BTreeSet<Tag>,
+        /// The tags it lost
+        removed: BTreeSet<Tag>,
+    },
+    /// The file was removed
+    Removed,
+}
+
+/// One change recorded in an audit log: who made it, when, and to which file
+#[derive(Debug, Clone, PartialEq)]
+pub struct AuditEntry {
+    time: SystemTime,
+    actor: String,
+    id: FileId,
+    action: AuditAction,
+}
+
+impl AuditEntry {
+    /// When the change was made
+    pub fn time(&self) -> SystemTime {
+        self.time
+    }
+
+    /// Who made the change
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    /// The file which was changed
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// What the change was
+    pub fn action(&self) -> &AuditAction {
+        &self.action
+    }
+}
+
+/// A wrapper around another filesystem which records every file added, edited, or removed
+/// through it in an append-only log, along with who by and when. The log is stored in the
+/// [`SpecialFile::AuditLog`] file, so it persists wherever special files do, and can't be
+/// replaced through the wrapper.
+///
+/// Changes made in a transaction are only recorded once it succeeds. Recording a change rewrites
+/// the whole log, and two wrappers recording at once can lose one of their changes, so each
+/// filesystem should have a single audited wrapper, used by every caller.
+pub struct AuditedFs<F> {
+    inner: F,
+    actor: String,
+    /// Changes made in the running transaction, recorded once it succeeds
+    pending: Mutex<Option<Vec<AuditEntry>>>,
+}
+
+impl<F: FileSystem> AuditedFs<F> {
+    /// Wrap a filesystem, recording changes as made by an actor
+    pub fn new(inner: F, actor: &str) -> AuditedFs<F> {
+        AuditedFs {
+            inner,
+            actor: actor.to_owned(),
+            pending: Mutex::new(None),
+        }
+    }
+
+    /// Change who changes are recorded as made by
+    #[must_use]
+    pub fn with_actor(mut self, actor: &str) -> Self {
+        actor.clone_into(&mut self.actor);
+        self
+    }
+
+    /// Get who changes are recorded as made by
+    pub fn actor(&self) -> &str {
+        &self.actor
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, no longer recording changes
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Get every change recorded at or after a time, oldest first
+    pub fn audit_log(
+        &self,
+        since: SystemTime,
+    ) -> Result<impl Iterator<Item = AuditEntry>, Error<F::Error>> {
+        let data = self.inner.special_data(SpecialFile::AuditLog)?;
+        let mut input = &*data;
+        let mut entries = Vec::new();
+        while !input.is_empty() {
+            entries.push(read_entry(&mut input).map_err(Error::Corrupt)?);
+        }
+        Ok(entries.into_iter().filter(move |entry| entry.time >= since))
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Option<Vec<AuditEntry>>> {
+        self.pending.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Record a change, or hold on to it if a transaction is running
+    fn record(&self, id: FileId, action: AuditAction) -> Result<(), Error<F::Error>> {
+        let entry = AuditEntry {
+            time: now(),
+            actor: self.actor.clone(),
+            id,
+            action,
+        };
+        match &mut *self.lock() {
+            Some(pending) => {
+                pending.push(entry);
+                Ok(())
+            }
+            None => self.append(&[entry]),
+        }
+    }
+
+    fn append(&self, entries: &[AuditEntry]) -> Result<(), Error<F::Error>> {
+        if entries.is_empty() {
+            return Ok(());
+        }
+        let mut data = self.inner.special_data(SpecialFile::AuditLog)?;
+        for entry in entries {
+            write_entry(&mut data, entry).map_err(Error::Corrupt)?;
+        }
+        self.inner.set_special_data(SpecialFile::AuditLog, &data)?;
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for AuditedFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.search_tags_with(tags, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        WrappedSearchIter::new(self.inner.search_tags_iter(tags))
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.tag_counts(pattern)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.count_tags(pattern)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.any_match(pattern)?)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        Ok(self.inner.usage()?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for AuditedFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        let id = self.inner.add_file(data, tags.iter().cloned())?;
+        self.record(id, AuditAction::Added { tags })?;
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.inner
+            .add_file_with_id(id, data, tags.iter().cloned())?;
+        self.record(id, AuditAction::Added { tags })
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let (added, removed) = if let Some(tags) = tags {
+            let new = tags.into_iter().collect::<BTreeSet<_>>();
+            let old = self.inner.get_stored_tags(id)?;
+            self.inner.edit_file(id, data, Some(new.iter().cloned()))?;
+            (&new - &old, &old - &new)
+        } else {
+            self.inner.edit_file(id, data, None::<[Tag; 0]>)?;
+            (BTreeSet::new(), BTreeSet::new())
+        };
+        self.record(
+            id,
+            AuditAction::Edited {
+                data: data.is_some(),
+                added,
+                removed,
+            },
+        )
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.inner.remove_file(id)?;
+        self.record(id, AuditAction::Removed)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut pending = self.lock();
+            if pending.is_some() {
+                drop(pending);
+                return f(self);
+            }
+            *pending = Some(Vec::new());
+        }
+
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done. The changes are recorded
+        // last, so a failure to record them undoes them too.
+        let mut failed = None;
+        let out = self.inner.transaction(|_| {
+            let out = f(self).and_then(|val| {
+                let entries = self.lock().take().unwrap_or_default();
+                self.append(&entries)?;
+                Ok(val)
+            });
+            match out {
+                Ok(val) => Ok(val),
+                Err(Error::Fs(err)) => Err(err),
+                Err(err) => {
+                    failed = Some(err);
+                    Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+                }
+            }
+        });
+        self.lock().take();
+        match (out, failed) {
+            (Err(_), Some(err)) => Err(err),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        if file == SpecialFile::AuditLog {
+            return Err(Error::AppendOnly);
+        }
+        Ok(self.inner.set_special_data(file, data)?)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for AuditedFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl<F: InferredTags> InferredTags for AuditedFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for AuditedFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for AuditedFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        Ok(Writer {
+            fs: self,
+            inner: self.inner.create_file(tags.iter().cloned())?,
+            tags,
+        })
+    }
+}
+
+/// A lazy search over an [`AuditedFs`], which is a search of the inner filesystem
+pub type SearchIter<'a, F, P> = WrappedSearchIter<
+    <F as FileSystemRead>::SearchIter<'a, P>,
+    Error<<F as FileSystemRead>::Error>,
+>;
+
+/// A handle streaming data into a new file of an [`AuditedFs`], which is a handle of the inner
+/// filesystem. The file is recorded as added once it's committed.
+pub struct Writer<'a, F: StreamWrite> {
+    fs: &'a AuditedFs<F>,
+    inner: F::Writer<'a>,
+    tags: BTreeSet<Tag>,
+}
+
+impl<F: StreamWrite> io::Write for Writer<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<F: StreamWrite> FileWriter for Writer<'_, F> {
+    type Error = Error<F::Error>;
+
+    fn commit(self) -> Result<FileId, Self::Error> {
+        let id = self.inner.commit()?;
+        self.fs.record(id, AuditAction::Added { tags: self.tags })?;
+        Ok(id)
+    }
+}
+
+fn write_tags(out: &mut Vec<u8>, tags: &BTreeSet<Tag>) -> io::Result<()> {
+    codec::write_len(out, tags.len())?;
+    for tag in tags {
+        codec::write_tag(out, tag)?;
+    }
+    Ok(())
+}
+
+fn write_entry(out: &mut Vec<u8>, entry: &AuditEntry) -> io::Result<()> {
+    let nanos = entry
+        .time
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0);
+    codec::write_u64(out, nanos)?;
+    codec::write_string(out, &entry.actor)?;
+    codec::write_id(out, entry.id)?;
+    match &entry.action {
+        AuditAction::Added { tags } => {
+            out.push(0);
+            write_tags(out, tags)
+        }
+        AuditAction::Edited {
+            data,
+            added,
+            removed,
+        } => {
+            out.extend([1, u8::from(*data)]);
+            write_tags(out, added)?;
+            write_tags(out, removed)
+        }
+        AuditAction::Removed => {
+            out.push(2);
+            Ok(())
+        }
+    }
+}
+
+fn read_byte(input: &mut &[u8]) -> io::Result<u8> {
+    let (&byte, rest) = input.split_first().ok_or(io::ErrorKind::UnexpectedEof)?;
+    *input = rest;
+    Ok(byte)
+}
+
+fn read_tags(input: &mut &[u8]) -> io::Result<BTreeSet<Tag>> {
+    let len = codec::read_u32(input)?;
+    (0..len)
+        .map(|_| codec::read_tag(input)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into()))
+        .collect()
+}
+
+fn read_entry(input: &mut &[u8]) -> io::Result<AuditEntry> {
+    let time = UNIX_EPOCH + Duration::from_nanos(codec::read_u64(input)?);
+    let actor = codec::read_string(input)?;
+    let id = codec::read_id(input)?;
+    let action = match read_byte(input)? {
+        0 => AuditAction::Added {
+            tags: read_tags(input)?,
+        },
+        1 => AuditAction::Edited {
+            data: read_byte(input)? != 0,
+            added: read_tags(input)?,
+            removed: read_tags(input)?,
+        },
+        2 => AuditAction::Removed,
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid audit action",
+            ))
+        }
+    };
+    Ok(AuditEntry {
+        time,
+        actor,
+        id,
+        action,
+    })
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::InMemoryFs;
+
+    #[test]
+    fn test_audited_fs() {
+        let start = SystemTime::now();
+        let fs = AuditedFs::new(InMemoryFs::new(), "alice");
+        let a = fs.add_file(b"a", [Tag::named("x")]).unwrap();
+        fs.add_tags(a, [Tag::named("y")]).unwrap();
+        fs.edit_file(a, Some(b"b"), Some([Tag::named("y")]))
+            .unwrap();
+
+        let fs = fs.with_actor("bob");
+        let mut writer = fs.create_file([]).unwrap();
+        io::Write::write_all(&mut writer, b"c").unwrap();
+        let b = writer.commit().unwrap();
+        fs.remove_file(a).unwrap();
+
+        // Failed transactions aren't recorded
+        fs.transaction(|fs| {
+            fs.add_file(b"d", [])?;
+            fs.get_info(a)
+        })
+        .unwrap_err();
+
+        let log = fs.audit_log(start).unwrap().collect::<Vec<_>>();
+        assert_eq!(
+            log.iter()
+                .map(|entry| (entry.actor(), entry.id(), entry.action().clone()))
+                .collect::<Vec<_>>(),
+            [
+                (
+                    "alice",
+                    a,
+                    AuditAction::Added {
+                        tags: BTreeSet::from([Tag::named("x")])
+                    }
+                ),
+                (
+                    "alice",
+                    a,
+                    AuditAction::Edited {
+                        data: false,
+                        added: BTreeSet::from([Tag::named("y")]),
+                        removed: BTreeSet::new(),
+                    }
+                ),
+                (
+                    "alice",
+                    a,
+                    AuditAction::Edited {
+                        data: true,
+                        added: BTreeSet::new(),
+                        removed: BTreeSet::from([Tag::named("x")]),
+                    }
+                ),
+                (
+                    "bob",
+                    b,
+                    AuditAction::Added {
+                        tags: BTreeSet::new()
+                    }
+                ),
+                ("bob", a, AuditAction::Removed),
+            ]
+        );
+        assert!(log.windows(2).all(|pair| pair[0].time() <= pair[1].time()));
+        assert_eq!(fs.audit_log(log[3].time()).unwrap().count(), 2);
+
+        fs.transaction(|fs| fs.add_file(b"e", [])).unwrap();
+        assert_eq!(fs.audit_log(start).unwrap().count(), 6);
+
+        assert!(matches!(
+            fs.set_special_data(SpecialFile::AuditLog, b""),
+            Err(Error::AppendOnly)
+        ));
+    }
+}
@@ -0,0 +1,219 @@
+//! Backing up a whole filesystem into a tar archive, and restoring it into any implementation
+//!
+//! An archive holds a `format` entry with the archive version, each special file which isn't
+//! generated under its [name](crate::SpecialFile::name), like `config`, and for every file a `files/ID.tag` entry with its tags followed by a `files/ID.dat`
+//! entry with its data. Tags use the same binary encoding as [`DirectoryBackedFs`] tag files.
+//!
+//! [`DirectoryBackedFs`]: crate::DirectoryBackedFs
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::time::UNIX_EPOCH;
+
+use tar::{Archive, Builder, EntryType, Header};
+
+use crate::{codec, FileId, FileSystemRead, FileWriter, SpecialFile, StreamWrite, TagPredicate};
+
+/// The version of the archive layout written by [`export`]
+const FORMAT_VERSION: &[u8] = b"1";
+
+/// Error while backing up or restoring a filesystem
+#[derive(Debug)]
+pub enum Error<E> {
+    /// An I/O error occurred reading or writing the archive, or the archive was malformed
+    Io(io::Error),
+    /// The filesystem returned an error
+    Fs(E),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "archive error: {err}"),
+            Error::Fs(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => std::error::Error::source(err),
+            Error::Fs(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+impl<E> From<io::Error> for Error<E> {
+    fn from(err: io::Error) -> Error<E> {
+        Error::Io(err)
+    }
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn append<W: Write>(out: &mut Builder<W>, path: &str, mtime: u64, data: &[u8]) -> io::Result<()> {
+    let mut header = Header::new_gnu();
+    header.set_entry_type(EntryType::Regular);
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_mtime(mtime);
+    out.append_data(&mut header, path, data)
+}
+
+/// Write every file in a filesystem, along with its stored special files, into a tar archive. Returns the
+/// number of files written.
+///
+/// Tags from registered [`TagProvider`](crate::TagProvider)s can't be told apart from the
+/// stored ones, so they're written too.
+pub fn export<F, W>(fs: &F, writer: W) -> Result<usize, Error<F::Error>>
+where
+    F: FileSystemRead,
+    W: Write,
+{
+    let mut out = Builder::new(writer);
+    append(&mut out, "format", 0, FORMAT_VERSION)?;
+    for &file in SpecialFile::ALL.iter().filter(|file| !file.is_generated()) {
+        let data = fs.special_data(file).map_err(Error::Fs)?;
+        append(&mut out, file.name(), 0, &data)?;
+    }
+
+    let ids = fs
+        .search_tags(TagPredicate::And(Vec::new()))
+        .map_err(Error::Fs)?;
+    for &id in &ids {
+        let info = fs.get_info(id).map_err(Error::Fs)?;
+        let mtime = fs
+            .get_metadata(id)
+            .map_err(Error::Fs)?
+            .modified()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |time| time.as_secs());
+
+        let mut tags = Vec::new();
+        for tag in info.tags() {
+            codec::write_tag(&mut tags, tag)?;
+        }
+
+        let name = format!("files/{:016X}", id.into_u64_unchecked());
+        append(&mut out, &format!("{name}.tag"), mtime, &tags)?;
+        append(&mut out, &format!("{name}.dat"), mtime, info.data())?;
+    }
+
+    out.finish()?;
+    Ok(ids.len())
+}
+
+/// Restore the files and stored special files in a tar archive written by [`export`] into a filesystem.
+/// Files are given new IDs by the filesystem, so this returns a map from each file's ID in the
+/// archive to its new one. File data is streamed in, so it needn't fit in memory.
+///
+/// The restore isn't atomic: if the archive is malformed part way through, files already
+/// restored are kept.
+pub fn import<R, F>(reader: R, fs: &F) -> Result<BTreeMap<FileId, FileId>, Error<F::Error>>
+where
+    R: Read,
+    F: StreamWrite,
+{
+    let mut archive = Archive::new(reader);
+    let mut ids = BTreeMap::new();
+    let mut seen_format = false;
+    let mut pending = None;
+
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let path = entry.path()?.into_owned();
+
+        if !seen_format {
+            let mut format = Vec::new();
+            entry.read_to_end(&mut format)?;
+            if path != Path::new("format") || format != FORMAT_VERSION {
+                return Err(invalid("Not a supported TBF backup").into());
+            }
+            seen_format = true;
+            continue;
+        }
+
+        let special = path
+            .to_str()
+            .and_then(SpecialFile::from_name)
+            .filter(|file| !file.is_generated());
+        if let Some(file) = special {
+            let mut data = Vec::new();
+            entry.read_to_end(&mut data)?;
+            fs.set_special_data(file, &data).map_err(Error::Fs)?;
+            continue;
+        }
+
+        let (Some(stem), Some(ext)) = (path.file_stem(), path.extension()) else {
+            return Err(invalid("Unexpected entry in backup").into());
+        };
+        let old_id = stem
+            .to_str()
+            .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+            .map(FileId::from_u64_unchecked)
+            .ok_or_else(|| invalid("Invalid file ID in backup"))?;
+
+        if ext == "tag" {
+            let mut tags = Vec::new();
+            while let Some(tag) = codec::read_tag(&mut entry)? {
+                tags.push(tag);
+            }
+            pending = Some((old_id, tags));
+        } else if ext == "dat" {
+            let tags = match pending.take() {
+                Some((id, tags)) if id == old_id => tags,
+                _ => return Err(invalid("File data in backup without its tags").into()),
+            };
+            let mut writer = fs.create_file(tags).map_err(Error::Fs)?;
+            io::copy(&mut entry, &mut writer)?;
+            ids.insert(old_id, writer.commit().map_err(Error::Fs)?);
+        } else {
+            return Err(invalid("Unexpected entry in backup").into());
+        }
+    }
+
+    if !seen_format {
+        return Err(invalid("Not a supported TBF backup").into());
+    }
+    Ok(ids)
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{FileSystemWrite, Group, InMemoryFs, Tag};
+
+    #[test]
+    fn test_round_trip() {
+        let ifs = InMemoryFs::new();
+        let removed = ifs.add_file(&[0], []).unwrap();
+        let first = ifs
+            .add_file(&[1, 2], [Tag::new(Group::custom("g"), "a").with_value(3)])
+            .unwrap();
+        let second = ifs.add_file(&[], [Tag::named("b")]).unwrap();
+        ifs.remove_file(removed).unwrap();
+        ifs.set_config(b"x = 1").unwrap();
+
+        let mut archive = Vec::new();
+        assert_eq!(export(&ifs, &mut archive).unwrap(), 2);
+
+        let restored = InMemoryFs::new();
+        let ids = import(&*archive, &restored).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        for old in [first, second] {
+            let old_info = ifs.get_info(old).unwrap();
+            let new_info = restored.get_info(ids[&old]).unwrap();
+            assert_eq!(old_info.tags(), new_info.tags());
+            assert_eq!(old_info.data(), new_info.data());
+        }
+        assert_eq!(restored.config().unwrap(), b"x = 1");
+
+        assert!(import(&[0u8; 1024][..], &InMemoryFs::new()).is_err());
+    }
+}
@@ -0,0 +1,698 @@
+//! Caching wrapper around another TBF, keeping recently used files in memory
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::mem::size_of;
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::metadata::Metadata;
+use crate::{
+    FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, FileWriter, Group, InferredTags,
+    ProvidedTags, SearchOptions, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer,
+    TagPattern, TagPredicate, TagProvider,
+};
+
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum Key {
+    Tags(FileId),
+    Data(FileId),
+    /// A search, numbered in the order it was cached. Its pattern is kept with its results.
+    Search(u64),
+}
+
+enum Value {
+    Tags(BTreeSet<Tag>),
+    Data(Box<[u8]>),
+    Search(TagPredicate, Vec<FileId>),
+}
+
+impl Value {
+    /// Roughly how many bytes of memory this value takes up
+    fn size(&self) -> usize {
+        match self {
+            Value::Tags(tags) => tags
+                .iter()
+                .map(|tag| size_of::<Tag>() + tag.name().len())
+                .sum(),
+            Value::Data(data) => data.len(),
+            Value::Search(_, ids) => size_of::<TagPredicate>() + ids.len() * size_of::<FileId>(),
+        }
+    }
+}
+
+struct Entry {
+    value: Value,
+    size: usize,
+    last_used: u64,
+}
+
+/// A least-recently-used cache, holding values up to a total size
+struct Lru {
+    budget: usize,
+    used: usize,
+    clock: u64,
+    next_search: u64,
+    /// Bumped whenever anything is invalidated, so values looked up from before then aren't
+    /// cached after it
+    generation: u64,
+    entries: BTreeMap<Key, Entry>,
+    order: BTreeMap<u64, Key>,
+}
+
+impl Lru {
+    fn new(budget: usize) -> Lru {
+        Lru {
+            budget,
+            used: 0,
+            clock: 0,
+            next_search: 0,
+            generation: 0,
+            entries: BTreeMap::new(),
+            order: BTreeMap::new(),
+        }
+    }
+
+    fn get(&mut self, key: &Key) -> Option<&Value> {
+        let entry = self.entries.get_mut(key)?;
+        self.order.remove(&entry.last_used);
+        self.clock += 1;
+        entry.last_used = self.clock;
+        self.order.insert(self.clock, key.clone());
+        Some(&entry.value)
+    }
+
+    fn find_search(&mut self, pattern: &TagPredicate) -> Option<Vec<FileId>> {
+        let key = self
+            .entries
+            .range(Key::Search(0)..)
+            .find(|(_, entry)| matches!(&entry.value, Value::Search(p, _) if p == pattern))
+            .map(|(key, _)| key.clone())?;
+        match self.get(&key) {
+            Some(Value::Search(_, ids)) => Some(ids.clone()),
+            _ => None,
+        }
+    }
+
+    /// Cache a value, evicting the least recently used ones to make room. Values looked up
+    /// before the cache was last invalidated, or larger than the whole budget, aren't cached.
+    fn insert(&mut self, generation: u64, key: Key, value: Value) {
+        let size = value.size();
+        if generation != self.generation || size > self.budget {
+            return;
+        }
+        self.remove(&key);
+        while self.used + size > self.budget {
+            let Some((_, oldest)) = self.order.pop_first() else {
+                break;
+            };
+            if let Some(entry) = self.entries.remove(&oldest) {
+                self.used -= entry.size;
+            }
+        }
+
+        self.clock += 1;
+        self.used += size;
+        self.order.insert(self.clock, key.clone());
+        self.entries.insert(
+            key,
+            Entry {
+                value,
+                size,
+                last_used: self.clock,
+            },
+        );
+    }
+
+    fn insert_search(&mut self, generation: u64, pattern: TagPredicate, ids: Vec<FileId>) {
+        let key = Key::Search(self.next_search);
+        self.next_search += 1;
+        self.insert(generation, key, Value::Search(pattern, ids));
+    }
+
+    fn remove(&mut self, key: &Key) {
+        if let Some(entry) = self.entries.remove(key) {
+            self.used -= entry.size;
+            self.order.remove(&entry.last_used);
+        }
+    }
+
+    /// Forget everything cached about a file, and every search, as the file may now match
+    /// different ones
+    fn invalidate(&mut self, id: FileId) {
+        self.generation += 1;
+        self.remove(&Key::Tags(id));
+        self.remove(&Key::Data(id));
+        self.invalidate_searches();
+    }
+
+    fn invalidate_searches(&mut self) {
+        self.generation += 1;
+        let searches = self
+            .entries
+            .range(Key::Search(0)..)
+            .map(|(key, _)| key.clone())
+            .collect::<Vec<_>>();
+        for key in searches {
+            self.remove(&key);
+        }
+    }
+
+    fn clear(&mut self) {
+        self.generation += 1;
+        self.used = 0;
+        self.entries.clear();
+        self.order.clear();
+    }
+}
+
+/// A wrapper around another filesystem which keeps the data and tags of recently used files, and
+/// the results of recent searches, in memory. This speeds up repeated lookups against slow
+/// filesystems, such as ones stored on disk or over a network.
+///
+/// The cache holds up to a budget of bytes, roughly counted, evicting whatever was least recently
+/// used to make room. Cached files are forgotten when they're changed through the wrapper, as are
+/// all cached searches when any file is, so the inner filesystem shouldn't be changed directly
+/// while wrapped. Lazy and paged searches aren't cached, and are passed straight to the inner
+/// filesystem.
+pub struct CachedFs<F> {
+    inner: F,
+    cache: Mutex<Lru>,
+}
+
+impl<F: FileSystem> CachedFs<F> {
+    /// Wrap a filesystem, caching up to `budget` bytes of it
+    pub fn new(inner: F, budget: usize) -> CachedFs<F> {
+        CachedFs {
+            inner,
+            cache: Mutex::new(Lru::new(budget)),
+        }
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, dropping the cache
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Get roughly how many bytes are currently cached
+    pub fn cached_bytes(&self) -> usize {
+        self.lock().used
+    }
+
+    /// Forget everything cached. Needed if the inner filesystem was changed directly.
+    pub fn clear_cache(&self) {
+        self.lock().clear();
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Lru> {
+        self.cache.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Get a cached value, or the current generation of the cache if it isn't cached
+    fn cached<T>(&self, key: &Key, f: impl FnOnce(&Value) -> Option<T>) -> Result<T, u64> {
+        let mut cache = self.lock();
+        let generation = cache.generation;
+        cache.get(key).and_then(f).ok_or(generation)
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for CachedFs<F> {
+    type Error = F::Error;
+    type SearchIter<'a, P>
+        = F::SearchIter<'a, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = tags.to_predicate();
+        let generation = {
+            let mut cache = self.lock();
+            if let Some(ids) = cache.find_search(&pattern) {
+                return Ok(ids);
+            }
+            cache.generation
+        };
+
+        let ids = self.inner.search_tags(tags)?;
+        self.lock().insert_search(generation, pattern, ids.clone());
+        Ok(ids)
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.search_tags_with(tags, options)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        self.inner.search_tags_iter(tags)
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let (tags, data) = {
+            let mut cache = self.lock();
+            let tags = match cache.get(&Key::Tags(id)) {
+                Some(Value::Tags(tags)) => Some(tags.clone()),
+                _ => None,
+            };
+            let data = match cache.get(&Key::Data(id)) {
+                Some(Value::Data(data)) => Some(data.clone()),
+                _ => None,
+            };
+            (tags, data)
+        };
+
+        match (tags, data) {
+            (Some(tags), Some(data)) => Ok(FileInfo { id, tags, data }),
+            (tags, None) => {
+                let generation = self.lock().generation;
+                let info = self.inner.get_info(id)?;
+                let mut cache = self.lock();
+                if tags.is_none() {
+                    cache.insert(generation, Key::Tags(id), Value::Tags(info.tags.clone()));
+                }
+                cache.insert(generation, Key::Data(id), Value::Data(info.data.clone()));
+                Ok(info)
+            }
+            (None, Some(data)) => Ok(FileInfo {
+                id,
+                tags: self.get_tags(id)?,
+                data,
+            }),
+        }
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let generation = match self.cached(&Key::Tags(id), |value| match value {
+            Value::Tags(tags) => Some(tags.clone()),
+            _ => None,
+        }) {
+            Ok(tags) => return Ok(tags),
+            Err(generation) => generation,
+        };
+
+        let tags = self.inner.get_tags(id)?;
+        self.lock()
+            .insert(generation, Key::Tags(id), Value::Tags(tags.clone()));
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.get_stored_tags(id)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        let generation = match self.cached(&Key::Data(id), |value| match value {
+            Value::Data(data) => Some(data.to_vec()),
+            _ => None,
+        }) {
+            Ok(data) => return Ok(data),
+            Err(generation) => generation,
+        };
+
+        let data = self.inner.get_data(id)?;
+        self.lock().insert(
+            generation,
+            Key::Data(id),
+            Value::Data(data.clone().into_boxed_slice()),
+        );
+        Ok(data)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        // Only data which is already cached is used, so reading part of a file never caches all
+        // of it
+        self.cached(&Key::Data(id), |value| match value {
+            Value::Data(data) => Some(data[crate::clamp_range(range.clone(), data.len())].to_vec()),
+            _ => None,
+        })
+        .or_else(|_| self.inner.read_range(id, range))
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.inner.get_metadata(id)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        self.inner.last_id()
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.inner.ids_after(after)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.tag_counts(pattern)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.count_tags(pattern)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.any_match(pattern)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.inner.find_duplicates()
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.inner.find_by_hash(hash)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        self.inner.list_groups()
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.list_tags(group)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.inner.list_versions(id)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.inner.get_version(id, version)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        self.inner.special(file)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.inner.special_data(file)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<crate::Event>, Self::Error> {
+        self.inner.subscribe()
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for CachedFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let out = self.inner.add_file(data, tags);
+        self.lock().invalidate_searches();
+        out
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let out = self.inner.add_file_with_id(id, data, tags);
+        self.lock().invalidate(id);
+        out
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let out = self.inner.edit_file(id, data, tags);
+        self.lock().invalidate(id);
+        out
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let out = self.inner.remove_file(id);
+        self.lock().invalidate(id);
+        out
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        let out = self.inner.transaction(|_| f(self));
+        if out.is_err() {
+            // Anything cached while it ran may have been undone
+            self.clear_cache();
+        }
+        out
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let out = self.inner.add_tags(id, tags);
+        self.lock().invalidate(id);
+        out
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let out = self.inner.remove_tags(id, tags);
+        self.lock().invalidate(id);
+        out
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        let out = self.inner.rename_tag(old, new);
+        self.clear_cache();
+        out
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        let out = self.inner.rename_group(old, new);
+        self.clear_cache();
+        out
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        let out = self.inner.revert(id, version);
+        self.lock().invalidate(id);
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.set_special_data(file, data)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for CachedFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.inner.register_provider(group, provider)?;
+        // Every file's tags may have changed
+        self.clear_cache();
+        Ok(())
+    }
+}
+
+impl<F: InferredTags> InferredTags for CachedFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inner.register_inferrer(inferrer)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for CachedFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.inner.read_file(id)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for CachedFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(Writer {
+            fs: self,
+            inner: Some(self.inner.create_file(tags)?),
+        })
+    }
+}
+
+/// A handle streaming data into a new file of a [`CachedFs`], which is a handle of the inner
+/// filesystem. The cache is cleared whenever it may have stored data.
+pub struct Writer<'a, F: StreamWrite> {
+    fs: &'a CachedFs<F>,
+    /// Only taken when the handle is dropped
+    inner: Option<F::Writer<'a>>,
+}
+
+impl<'a, F: StreamWrite> Writer<'a, F> {
+    fn inner(&mut self) -> &mut F::Writer<'a> {
+        self.inner
+            .as_mut()
+            .expect("Writer is only taken when dropped")
+    }
+}
+
+impl<F: StreamWrite> io::Write for Writer<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let out = self.inner().flush();
+        // The file's ID isn't known until it's committed, so everything has to be forgotten
+        self.fs.clear_cache();
+        out
+    }
+}
+
+impl<F: StreamWrite> FileWriter for Writer<'_, F> {
+    type Error = F::Error;
+
+    fn commit(mut self) -> Result<FileId, Self::Error> {
+        let out = self
+            .inner
+            .take()
+            .expect("Writer is only taken when dropped")
+            .commit();
+        if let Ok(id) = out {
+            self.fs.lock().invalidate(id);
+        }
+        out
+    }
+}
+
+impl<F: StreamWrite> Drop for Writer<'_, F> {
+    fn drop(&mut self) {
+        // The inner handle may store its data when dropped
+        if let Some(inner) = self.inner.take() {
+            drop(inner);
+            self.fs.clear_cache();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::InMemoryFs;
+
+    #[test]
+    fn test_cached() {
+        let fs = CachedFs::new(InMemoryFs::new(), 1024);
+        let a = fs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+        let b = fs.add_file(&[3], [Tag::named("b")]).unwrap();
+
+        assert_eq!(fs.get_info(a).unwrap().data(), &[1, 2]);
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a]);
+        assert!(fs.cached_bytes() > 0);
+
+        // Changes made directly to the inner filesystem aren't seen until the cache is cleared
+        fs.inner()
+            .edit_file(a, Some(&[4]), Some([Tag::named("b")]))
+            .unwrap();
+        assert_eq!(fs.get_data(a).unwrap(), [1, 2]);
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a]);
+        fs.clear_cache();
+        assert_eq!(fs.cached_bytes(), 0);
+        assert_eq!(fs.get_data(a).unwrap(), [4]);
+        assert_eq!(fs.search_tags(Tag::named("b")).unwrap(), [a, b]);
+
+        // Changes made through the wrapper are
+        fs.edit_file(a, Some(&[5]), Some([Tag::named("a")]))
+            .unwrap();
+        assert_eq!(fs.get_info(a).unwrap().data(), &[5]);
+        assert_eq!(fs.search_tags(Tag::named("b")).unwrap(), [b]);
+        fs.remove_file(b).unwrap();
+        assert!(fs.get_tags(b).is_err());
+        assert!(fs.search_tags(Tag::named("b")).unwrap().is_empty());
+
+        let mut writer = fs.create_file([Tag::named("a")]).unwrap();
+        writer.write_all(&[6]).unwrap();
+        let c = writer.commit().unwrap();
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a, c]);
+    }
+
+    #[test]
+    fn test_cached_eviction() {
+        let fs = CachedFs::new(InMemoryFs::new(), 100);
+        let a = fs.add_file(&[0; 60], []).unwrap();
+        let b = fs.add_file(&[1; 60], []).unwrap();
+        let big = fs.add_file(&[2; 200], []).unwrap();
+
+        fs.get_data(a).unwrap();
+        assert_eq!(fs.cached_bytes(), 60);
+        fs.get_data(b).unwrap();
+        assert_eq!(fs.cached_bytes(), 60);
+        // Too big to ever be cached
+        fs.get_data(big).unwrap();
+        assert_eq!(fs.cached_bytes(), 60);
+
+        // `a` was evicted, so changing it behind the cache's back is seen
+        fs.inner()
+            .edit_file(a, Some(&[3]), None::<[Tag; 0]>)
+            .unwrap();
+        fs.inner()
+            .edit_file(b, Some(&[3]), None::<[Tag; 0]>)
+            .unwrap();
+        assert_eq!(fs.get_data(a).unwrap(), [3]);
+        assert_eq!(fs.get_data(b).unwrap(), [1; 60]);
+    }
+
+    #[test]
+    fn test_cached_transaction() {
+        let fs = CachedFs::new(InMemoryFs::new(), 1024);
+        let a = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+
+        let res = fs.transaction(|fs| {
+            fs.edit_file(a, Some(&[2]), None::<[Tag; 0]>)?;
+            assert_eq!(fs.get_data(a)?, [2]);
+            fs.remove_file(FileId::from_u64_unchecked(1000))
+        });
+        assert!(res.is_err());
+        assert_eq!(fs.get_data(a).unwrap(), [1]);
+    }
+}
@@ -0,0 +1,319 @@
+//! Binary encoding of the values stored on disk and in backups
+
+use alloc::borrow::Cow;
+use core::convert::TryFrom;
+use std::io::{self, Read, Write};
+
+use crate::{FileId, Group, Tag, TagPredicate, TagValue};
+
+/// Tag flag set when the tag is in a custom group, followed by the group name
+const FLAG_GROUP: u8 = 1;
+/// Tag flag set when the tag has a value, stored after its name
+const FLAG_VALUE: u8 = 2;
+
+pub(crate) fn write_u32<W: Write>(out: &mut W, val: u32) -> io::Result<()> {
+    out.write_all(&val.to_le_bytes())
+}
+
+pub(crate) fn write_u64<W: Write>(out: &mut W, val: u64) -> io::Result<()> {
+    out.write_all(&val.to_le_bytes())
+}
+
+pub(crate) fn write_len<W: Write>(out: &mut W, len: usize) -> io::Result<()> {
+    let len = u32::try_from(len)
+        .map_err(|_| io::Error::new(io::ErrorKind::InvalidInput, "Value too long to store"))?;
+    write_u32(out, len)
+}
+
+pub(crate) fn write_string<W: Write>(out: &mut W, val: &str) -> io::Result<()> {
+    write_len(out, val.len())?;
+    out.write_all(val.as_bytes())
+}
+
+pub(crate) fn write_id<W: Write>(out: &mut W, id: FileId) -> io::Result<()> {
+    write_u64(out, id.into_u64_unchecked())
+}
+
+pub(crate) fn write_tag<W: Write>(out: &mut W, tag: &Tag) -> io::Result<()> {
+    let mut flags = 0;
+    if let Group::Custom(_) = tag.group() {
+        flags |= FLAG_GROUP;
+    }
+    if tag.value().is_some() {
+        flags |= FLAG_VALUE;
+    }
+    out.write_all(&[flags])?;
+
+    if let Group::Custom(group) = tag.group() {
+        write_string(out, group)?;
+    }
+    write_string(out, tag.name())?;
+    if let Some(value) = tag.value() {
+        write_value(out, value)?;
+    }
+    Ok(())
+}
+
+pub(crate) fn write_value<W: Write>(out: &mut W, value: &TagValue) -> io::Result<()> {
+    match value {
+        TagValue::String(val) => {
+            out.write_all(&[0])?;
+            write_string(out, val)
+        }
+        TagValue::Int(val) => {
+            out.write_all(&[1])?;
+            out.write_all(&val.to_le_bytes())
+        }
+        TagValue::Float(val) => {
+            out.write_all(&[2])?;
+            write_u64(out, val.to_bits())
+        }
+        TagValue::Bool(val) => out.write_all(&[3, u8::from(*val)]),
+        TagValue::Timestamp(val) => {
+            out.write_all(&[4])?;
+            out.write_all(&val.to_le_bytes())
+        }
+    }
+}
+
+pub(crate) fn write_group<W: Write>(out: &mut W, group: &Group) -> io::Result<()> {
+    match group {
+        Group::Default => write_string(out, ""),
+        Group::Custom(group) => write_string(out, group),
+    }
+}
+
+fn write_predicates<W: Write>(out: &mut W, preds: &[TagPredicate]) -> io::Result<()> {
+    write_len(out, preds.len())?;
+    preds.iter().try_for_each(|pred| write_predicate(out, pred))
+}
+
+/// Write a predicate as a kind byte followed by its contents, with groups as their name, empty
+/// for the default group, and regular expressions as their pattern. Custom predicates can't be
+/// written, and fail with [`io::ErrorKind::InvalidInput`].
+pub(crate) fn write_predicate<W: Write>(out: &mut W, pred: &TagPredicate) -> io::Result<()> {
+    match pred {
+        TagPredicate::And(preds) => {
+            out.write_all(&[0])?;
+            write_predicates(out, preds)
+        }
+        TagPredicate::Or(preds) => {
+            out.write_all(&[1])?;
+            write_predicates(out, preds)
+        }
+        TagPredicate::Not(pred) => {
+            out.write_all(&[2])?;
+            write_predicate(out, pred)
+        }
+        TagPredicate::Group(group) => {
+            out.write_all(&[3])?;
+            write_group(out, group)
+        }
+        TagPredicate::Name(name) => {
+            out.write_all(&[4])?;
+            write_string(out, name)
+        }
+        TagPredicate::NameContains(substr) => {
+            out.write_all(&[5])?;
+            write_string(out, substr)
+        }
+        TagPredicate::NameGlob(glob) => {
+            out.write_all(&[6])?;
+            write_string(out, glob)
+        }
+        TagPredicate::GroupGlob(glob) => {
+            out.write_all(&[7])?;
+            write_string(out, glob)
+        }
+        #[cfg(feature = "regex")]
+        TagPredicate::NameRegex(regex) => {
+            out.write_all(&[8])?;
+            write_string(out, regex.as_str())
+        }
+        #[cfg(feature = "regex")]
+        TagPredicate::GroupRegex(regex) => {
+            out.write_all(&[9])?;
+            write_string(out, regex.as_str())
+        }
+        TagPredicate::Tag(tag) => {
+            out.write_all(&[10])?;
+            write_tag(out, tag)
+        }
+        TagPredicate::ValueGt { group, name, value } => {
+            out.write_all(&[11])?;
+            write_group(out, group)?;
+            write_string(out, name)?;
+            write_value(out, value)
+        }
+        TagPredicate::ValueLt { group, name, value } => {
+            out.write_all(&[12])?;
+            write_group(out, group)?;
+            write_string(out, name)?;
+            write_value(out, value)
+        }
+        TagPredicate::ValueRange {
+            group,
+            name,
+            min,
+            max,
+        } => {
+            out.write_all(&[13])?;
+            write_group(out, group)?;
+            write_string(out, name)?;
+            write_value(out, min)?;
+            write_value(out, max)
+        }
+        TagPredicate::Custom(_) => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "custom predicates can't be stored",
+        )),
+    }
+}
+
+pub(crate) fn read_u32<R: Read>(input: &mut R) -> io::Result<u32> {
+    let mut buf = [0; 4];
+    input.read_exact(&mut buf)?;
+    Ok(u32::from_le_bytes(buf))
+}
+
+pub(crate) fn read_u64<R: Read>(input: &mut R) -> io::Result<u64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+fn read_i64<R: Read>(input: &mut R) -> io::Result<i64> {
+    let mut buf = [0; 8];
+    input.read_exact(&mut buf)?;
+    Ok(i64::from_le_bytes(buf))
+}
+
+pub(crate) fn read_string<R: Read>(input: &mut R) -> io::Result<String> {
+    let len = read_u32(input)?;
+    let mut bytes = Vec::new();
+    input.take(u64::from(len)).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != u64::from(len) {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    String::from_utf8(bytes).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub(crate) fn read_id<R: Read>(input: &mut R) -> io::Result<FileId> {
+    read_u64(input).map(FileId::from_u64_unchecked)
+}
+
+/// Read a tag, or `None` if the input is already at its end
+pub(crate) fn read_tag<R: Read>(input: &mut R) -> io::Result<Option<Tag>> {
+    let mut flags = [0; 1];
+    if input.read(&mut flags)? == 0 {
+        return Ok(None);
+    }
+
+    let flags = flags[0];
+    if flags & !(FLAG_GROUP | FLAG_VALUE) != 0 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid tag flags",
+        ));
+    }
+
+    let group = if flags & FLAG_GROUP == 0 {
+        Group::Default
+    } else {
+        Group::Custom(Cow::Owned(read_string(input)?))
+    };
+    let tag = Tag::new(group, read_string(input)?);
+
+    if flags & FLAG_VALUE == 0 {
+        Ok(Some(tag))
+    } else {
+        Ok(Some(tag.with_value(read_value(input)?)))
+    }
+}
+
+pub(crate) fn read_value<R: Read>(input: &mut R) -> io::Result<TagValue> {
+    let mut kind = [0; 1];
+    input.read_exact(&mut kind)?;
+    match kind[0] {
+        0 => read_string(input).map(TagValue::from),
+        1 => read_i64(input).map(TagValue::Int),
+        2 => read_u64(input).map(|bits| TagValue::Float(f64::from_bits(bits))),
+        3 => {
+            let mut val = [0; 1];
+            input.read_exact(&mut val)?;
+            Ok(TagValue::Bool(val[0] != 0))
+        }
+        4 => read_i64(input).map(TagValue::Timestamp),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Invalid tag value kind",
+        )),
+    }
+}
+
+pub(crate) fn read_group<R: Read>(input: &mut R) -> io::Result<Group> {
+    read_string(input).map(Group::from)
+}
+
+fn read_predicates<R: Read>(input: &mut R) -> io::Result<Vec<TagPredicate>> {
+    let len = read_u32(input)?;
+    (0..len).map(|_| read_predicate(input)).collect()
+}
+
+#[cfg(feature = "regex")]
+fn read_regex<R: Read>(input: &mut R) -> io::Result<crate::TagRegex> {
+    crate::TagRegex::new(&read_string(input)?)
+        .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+pub(crate) fn read_predicate<R: Read>(input: &mut R) -> io::Result<TagPredicate> {
+    let mut kind = [0; 1];
+    input.read_exact(&mut kind)?;
+    Ok(match kind[0] {
+        0 => TagPredicate::And(read_predicates(input)?),
+        1 => TagPredicate::Or(read_predicates(input)?),
+        2 => TagPredicate::Not(Box::new(read_predicate(input)?)),
+        3 => TagPredicate::Group(read_group(input)?),
+        4 => TagPredicate::Name(read_string(input)?),
+        5 => TagPredicate::NameContains(read_string(input)?),
+        6 => TagPredicate::NameGlob(read_string(input)?),
+        7 => TagPredicate::GroupGlob(read_string(input)?),
+        #[cfg(feature = "regex")]
+        8 => TagPredicate::NameRegex(read_regex(input)?),
+        #[cfg(feature = "regex")]
+        9 => TagPredicate::GroupRegex(read_regex(input)?),
+        #[cfg(not(feature = "regex"))]
+        8 | 9 => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Regular expressions need the regex feature",
+            ))
+        }
+        10 => {
+            let tag = read_tag(input)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+            TagPredicate::Tag(tag)
+        }
+        11 => TagPredicate::ValueGt {
+            group: read_group(input)?,
+            name: read_string(input)?,
+            value: read_value(input)?,
+        },
+        12 => TagPredicate::ValueLt {
+            group: read_group(input)?,
+            name: read_string(input)?,
+            value: read_value(input)?,
+        },
+        13 => TagPredicate::ValueRange {
+            group: read_group(input)?,
+            name: read_string(input)?,
+            min: read_value(input)?,
+            max: read_value(input)?,
+        },
+        _ => {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid predicate kind",
+            ))
+        }
+    })
+}
@@ -0,0 +1,310 @@
+//! Ordered lists of files, stored as files of their own
+
+use alloc::collections::BTreeSet;
+use core::fmt;
+use std::io;
+
+use crate::error::ErrorKind;
+use crate::{codec, FileId, FileInfo, FileSystemRead, FileSystemWrite, Group, Tag, TagPredicate};
+
+/// The tag marking a file as a collection
+fn marker() -> Tag {
+    Tag::new(Group::custom("tbf"), "collection")
+}
+
+/// Error while reading or changing a collection
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The filesystem returned an error
+    Fs(E),
+    /// The items of a collection couldn't be read, as they were malformed
+    Corrupt(io::Error),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored collection items are malformed: {err}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// An ordered list of files, like an album or a playlist, with tags of its own
+#[derive(Debug, Clone, PartialEq)]
+pub struct Collection {
+    id: FileId,
+    tags: BTreeSet<Tag>,
+    items: Vec<FileId>,
+}
+
+impl Collection {
+    /// The ID of the file storing the collection
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// The tags of the collection itself
+    pub fn tags(&self) -> &BTreeSet<Tag> {
+        &self.tags
+    }
+
+    /// The files in the collection, in order. A file may be in a collection more than once.
+    pub fn items(&self) -> &[FileId] {
+        &self.items
+    }
+}
+
+/// The collections of a filesystem. Each is stored as a file whose data is the IDs of its items,
+/// with the collection's own tags and one more marking it as a collection, so collections can be
+/// found by their tags like any other file.
+///
+/// Items must exist when they're added, but removing a file doesn't remove it from collections.
+/// Getting something which isn't a collection fails as if it didn't exist.
+pub struct Collections<'a, F: ?Sized> {
+    fs: &'a F,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> Collections<'a, F> {
+    /// Get the collections of a filesystem
+    pub fn new(fs: &'a F) -> Collections<'a, F> {
+        Collections { fs }
+    }
+
+    /// Find every collection whose own tags match a predicate
+    pub fn search(&self, pred: TagPredicate) -> Result<Vec<FileId>, F::Error> {
+        self.fs
+            .search_tags(TagPredicate::And(vec![TagPredicate::Tag(marker()), pred]))
+    }
+
+    /// Find every collection
+    pub fn list(&self) -> Result<Vec<FileId>, F::Error> {
+        self.fs.search_tags(marker())
+    }
+
+    /// Get a collection, with its tags and items
+    pub fn get(&self, id: FileId) -> Result<Collection, Error<F::Error>> {
+        let info = self.fs.get_info(id)?;
+        let mut tags = info.tags;
+        if !tags.remove(&marker()) {
+            return Err(crate::Error::file_not_found(id));
+        }
+        Ok(Collection {
+            id,
+            tags,
+            items: decode(&info.data)?,
+        })
+    }
+
+    /// Get the items of a collection, in order
+    pub fn items(&self, id: FileId) -> Result<Vec<FileId>, Error<F::Error>> {
+        Ok(self.get(id)?.items)
+    }
+
+    /// Iterate over the files in a collection, in order, getting each as it's reached. Files
+    /// removed since they were added give an error when reached.
+    pub fn iter_items(
+        &self,
+        id: FileId,
+    ) -> Result<impl Iterator<Item = Result<FileInfo, F::Error>> + 'a, Error<F::Error>> {
+        let fs = self.fs;
+        Ok(self.items(id)?.into_iter().map(move |id| fs.get_info(id)))
+    }
+
+    fn check_items(&self, items: &[FileId]) -> Result<(), F::Error> {
+        for &item in items {
+            if !crate::exists(self.fs, item)? {
+                return Err(crate::Error::file_not_found(item));
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> Collections<'_, F> {
+    /// Create a collection of files with some tags of its own
+    pub fn create<I>(&self, items: &[FileId], tags: I) -> Result<FileId, Error<F::Error>>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.check_items(items)?;
+        let tags = tags.into_iter().chain([marker()]);
+        Ok(self.fs.add_file(&encode(items)?, tags)?)
+    }
+
+    /// Replace the items of a collection
+    pub fn set_items(&self, id: FileId, items: &[FileId]) -> Result<(), Error<F::Error>> {
+        self.get(id)?;
+        self.check_items(items)?;
+        self.fs
+            .edit_file(id, Some(&encode(items)?), None::<[Tag; 0]>)?;
+        Ok(())
+    }
+
+    /// Replace the tags of a collection, keeping it a collection
+    pub fn set_tags<I>(&self, id: FileId, tags: I) -> Result<(), Error<F::Error>>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.get(id)?;
+        let tags = tags.into_iter().chain([marker()]);
+        self.fs.edit_file(id, None, Some(tags))?;
+        Ok(())
+    }
+
+    /// Add a file to the end of a collection
+    pub fn push_item(&self, id: FileId, item: FileId) -> Result<(), Error<F::Error>> {
+        let mut items = self.items(id)?;
+        items.push(item);
+        self.set_items(id, &items)
+    }
+
+    /// Remove every occurrence of a file from a collection. Returns whether it was in it.
+    pub fn remove_item(&self, id: FileId, item: FileId) -> Result<bool, Error<F::Error>> {
+        let mut items = self.items(id)?;
+        let len = items.len();
+        items.retain(|&other| other != item);
+        if items.len() == len {
+            return Ok(false);
+        }
+        self.set_items(id, &items)?;
+        Ok(true)
+    }
+
+    /// Move the item at one position of a collection to another, shifting those between
+    ///
+    /// # Panics
+    ///
+    /// If either position is past the end of the collection
+    pub fn move_item(&self, id: FileId, from: usize, to: usize) -> Result<(), Error<F::Error>> {
+        let mut items = self.items(id)?;
+        let item = items.remove(from);
+        items.insert(to, item);
+        self.set_items(id, &items)
+    }
+}
+
+fn encode<E>(items: &[FileId]) -> Result<Vec<u8>, Error<E>> {
+    let mut data = Vec::with_capacity(items.len() * 8);
+    for &item in items {
+        codec::write_id(&mut data, item).map_err(Error::Corrupt)?;
+    }
+    Ok(data)
+}
+
+fn decode<E>(mut data: &[u8]) -> Result<Vec<FileId>, Error<E>> {
+    let mut items = Vec::with_capacity(data.len() / 8);
+    while !data.is_empty() {
+        items.push(codec::read_id(&mut data).map_err(Error::Corrupt)?);
+    }
+    Ok(items)
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::error::is_not_found;
+    use crate::InMemoryFs;
+
+    #[test]
+    fn test_collections() {
+        let fs = InMemoryFs::new();
+        let songs = (0..4)
+            .map(|i| fs.add_file(&[i], [Tag::named("song")]).unwrap())
+            .collect::<Vec<_>>();
+        let collections = Collections::new(&fs);
+
+        let playlist = collections
+            .create(&songs[..3], [Tag::named("playlist")])
+            .unwrap();
+        let album = collections.create(&songs[2..], []).unwrap();
+        assert_eq!(collections.list().unwrap(), [playlist, album]);
+        assert_eq!(
+            collections.search(TagPredicate::name("playlist")).unwrap(),
+            [playlist]
+        );
+        // Collections don't show up as their items
+        assert_eq!(fs.search_tags(Tag::named("song")).unwrap(), songs);
+
+        let got = collections.get(playlist).unwrap();
+        assert_eq!(got.id(), playlist);
+        assert_eq!(got.tags(), &BTreeSet::from([Tag::named("playlist")]));
+        assert_eq!(got.items(), &songs[..3]);
+
+        collections.move_item(playlist, 0, 2).unwrap();
+        collections.push_item(playlist, songs[1]).unwrap();
+        assert_eq!(
+            collections.items(playlist).unwrap(),
+            [songs[1], songs[2], songs[0], songs[1]]
+        );
+        let data = collections
+            .iter_items(playlist)
+            .unwrap()
+            .map(|info| info.unwrap().data.to_vec())
+            .collect::<Vec<_>>();
+        assert_eq!(data, [vec![1], vec![2], vec![0], vec![1]]);
+
+        assert!(collections.remove_item(playlist, songs[1]).unwrap());
+        assert!(!collections.remove_item(playlist, songs[1]).unwrap());
+        assert_eq!(collections.items(playlist).unwrap(), [songs[2], songs[0]]);
+
+        collections.set_tags(album, [Tag::named("album")]).unwrap();
+        assert_eq!(collections.list().unwrap(), [playlist, album]);
+        assert_eq!(
+            collections.search(TagPredicate::name("album")).unwrap(),
+            [album]
+        );
+
+        let missing = FileId::from_u64_unchecked(0xFFFF);
+        let err = collections.push_item(album, missing).unwrap_err();
+        assert!(is_not_found(&err));
+        let err = collections.get(songs[0]).unwrap_err();
+        assert!(is_not_found(&err));
+
+        // Removed items are an error once reached
+        fs.remove_file(songs[0]).unwrap();
+        let mut iter = collections.iter_items(playlist).unwrap();
+        assert!(iter.next().unwrap().is_ok());
+        assert!(is_not_found(&iter.next().unwrap().unwrap_err()));
+    }
+}
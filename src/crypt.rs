@@ -0,0 +1,590 @@
+//! Encrypting wrapper around another TBF
+
+use alloc::collections::BTreeSet;
+use core::fmt::{self, Write as _};
+use std::io::Cursor;
+use std::sync::mpsc::Receiver;
+use std::sync::{PoisonError, RwLock};
+use std::vec;
+
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{Key, XChaCha20Poly1305, XNonce};
+
+use crate::error::ErrorKind;
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::{hash_data, Metadata};
+use crate::provider::{provide_tags, Providers};
+use crate::{
+    codec, BufferedWriter, Event, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite,
+    Group, InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer,
+    TagPattern, TagPredicate, TagProvider,
+};
+
+/// Group of the tags in the inner filesystem holding an encrypted tag. The tag's name is the hex
+/// nonce and ciphertext of the encoded tag.
+const TAG_GROUP: &str = "tbf-enc";
+const NONCE_LEN: usize = 24;
+
+fn to_hex(data: &[u8]) -> String {
+    let mut out = String::with_capacity(data.len() * 2);
+    for byte in data {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Error for an encrypted filesystem
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner filesystem returned an error
+    Fs(E),
+    /// Stored data couldn't be decrypted, because the key is wrong or it was tampered with
+    Crypto,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Crypto => ErrorKind::State,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Crypto => write!(f, "stored data couldn't be decrypted"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Crypto => None,
+        }
+    }
+}
+
+/// A wrapper around another filesystem which encrypts file data and config before storing them,
+/// using XChaCha20-Poly1305 with a key provided by the user. Tags can be encrypted too with
+/// [`EncryptedFs::encrypt_tags`], though searches then have to decrypt the tags of every file.
+///
+/// Files keep their IDs from the inner filesystem, and IDs, data sizes, and modification times
+/// aren't hidden. Encrypted tags are stored in the `tbf-enc` group, so the inner filesystem
+/// shouldn't be edited directly while wrapped.
+pub struct EncryptedFs<F> {
+    inner: F,
+    cipher: XChaCha20Poly1305,
+    encrypt_tags: bool,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+}
+
+impl<F: FileSystem> EncryptedFs<F> {
+    /// Wrap a filesystem, encrypting with the given key. The filesystem should either be empty
+    /// or previously wrapped with the same key.
+    pub fn new(inner: F, key: &[u8; 32]) -> EncryptedFs<F> {
+        EncryptedFs {
+            inner,
+            cipher: XChaCha20Poly1305::new(Key::from_slice(key)),
+            encrypt_tags: false,
+            providers: RwLock::new(Providers::new()),
+            inferrers: RwLock::new(Inferrers::new()),
+        }
+    }
+
+    /// Set whether tags are encrypted when written. Tags already stored are read either way.
+    #[must_use]
+    pub fn encrypt_tags(mut self, encrypt: bool) -> Self {
+        self.encrypt_tags = encrypt;
+        self
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Encrypt some data, with a fresh nonce stored before it
+    fn seal(&self, data: &[u8]) -> Result<Vec<u8>, Error<F::Error>> {
+        let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+        let mut out = nonce.to_vec();
+        out.extend(
+            self.cipher
+                .encrypt(&nonce, data)
+                .map_err(|_| Error::Crypto)?,
+        );
+        Ok(out)
+    }
+
+    fn open(&self, data: &[u8]) -> Result<Vec<u8>, Error<F::Error>> {
+        if data.len() < NONCE_LEN {
+            return Err(Error::Crypto);
+        }
+        let (nonce, data) = data.split_at(NONCE_LEN);
+        self.cipher
+            .decrypt(XNonce::from_slice(nonce), data)
+            .map_err(|_| Error::Crypto)
+    }
+
+    fn seal_tags<I>(&self, tags: I) -> Result<Vec<Tag>, Error<F::Error>>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if !self.encrypt_tags {
+            return Ok(tags.into_iter().collect());
+        }
+        tags.into_iter()
+            .map(|tag| {
+                let mut encoded = Vec::new();
+                codec::write_tag(&mut encoded, &tag).map_err(|_| Error::Crypto)?;
+                Ok(Tag::new(TAG_GROUP, to_hex(&self.seal(&encoded)?)))
+            })
+            .collect()
+    }
+
+    fn open_tags(&self, tags: BTreeSet<Tag>) -> Result<BTreeSet<Tag>, Error<F::Error>> {
+        tags.into_iter()
+            .map(|tag| {
+                if *tag.group() != TAG_GROUP {
+                    return Ok(tag);
+                }
+                let encoded = self.open(&from_hex(tag.name()).ok_or(Error::Crypto)?)?;
+                codec::read_tag(&mut &*encoded)
+                    .ok()
+                    .flatten()
+                    .ok_or(Error::Crypto)
+            })
+            .collect()
+    }
+
+    fn has_providers(&self) -> bool {
+        !self
+            .providers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty()
+    }
+
+    fn inferred(&self, data: &[u8]) -> Vec<Tag> {
+        infer_tags(
+            &self
+                .inferrers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        )
+    }
+
+    fn provided(&self, data: &[u8]) -> Vec<Tag> {
+        provide_tags(
+            &self
+                .providers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        )
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for EncryptedFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        // Encrypted and provided tags can only be checked one file at a time
+        let state = if self.encrypt_tags || self.has_providers() {
+            SearchState::Scan {
+                pattern: tags,
+                ids: None,
+                done: false,
+            }
+        } else {
+            SearchState::Inner(self.inner.search_tags_iter(tags))
+        };
+        SearchIter { fs: self, state }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let info = self.inner.get_info(id)?;
+        let data = self.open(&info.data)?;
+        let mut tags = self.open_tags(info.tags)?;
+        tags.extend(self.provided(&data));
+        Ok(FileInfo::new(id, tags, data))
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let mut tags = self.open_tags(self.inner.get_tags(id)?)?;
+        if self.has_providers() {
+            tags.extend(self.provided(&self.get_data(id)?));
+        }
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.open_tags(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.open(&self.inner.get_data(id)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let meta = self.inner.get_metadata(id)?;
+        let data = self.get_data(id)?;
+        Ok(Metadata {
+            size: data.len() as u64,
+            hash: hash_data(&data),
+            ..meta
+        })
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        Ok(self
+            .open(&self.inner.get_version(id, version)?)?
+            .into_boxed_slice())
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        // Nothing is stored until the file is first set
+        let data = self.inner.special_data(file)?;
+        if data.is_empty() {
+            Ok(data)
+        } else {
+            self.open(&data)
+        }
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for EncryptedFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.seal_tags(tags.into_iter().chain(self.inferred(data)))?;
+        Ok(self.inner.add_file(&self.seal(data)?, tags)?)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.seal_tags(tags.into_iter().chain(self.inferred(data)))?;
+        Ok(self.inner.add_file_with_id(id, &self.seal(data)?, tags)?)
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let data = data.map(|data| self.seal(data)).transpose()?;
+        let tags = tags.map(|tags| self.seal_tags(tags)).transpose()?;
+        Ok(self.inner.edit_file(id, data.as_deref(), tags)?)
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        Ok(self.inner.remove_file(id)?)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done
+        let mut crypto_err = false;
+        let out = self.inner.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Fs(err)) => Err(err),
+            Err(Error::Crypto) => {
+                crypto_err = true;
+                Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        match out {
+            Err(_) if crypto_err => Err(Error::Crypto),
+            out => Ok(out?),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.get_stored_tags(id)?;
+        new.extend(tags);
+        self.edit_file(id, None, Some(new))
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.get_stored_tags(id)?;
+        for tag in tags {
+            new.remove(&tag);
+        }
+        self.edit_file(id, None, Some(new))
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        Ok(self.inner.set_special_data(file, &self.seal(data)?)?)
+    }
+}
+
+impl<F: FileSystem> ProvidedTags for EncryptedFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> InferredTags for EncryptedFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> StreamRead for EncryptedFs<F> {
+    type Reader<'a>
+        = Cursor<Vec<u8>>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(Cursor::new(self.get_data(id)?))
+    }
+}
+
+impl<F: FileSystem> StreamWrite for EncryptedFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+enum SearchState<'a, F: FileSystem + 'a, P: TagPattern + 'a> {
+    Inner(F::SearchIter<'a, P>),
+    Scan {
+        pattern: P,
+        ids: Option<vec::IntoIter<FileId>>,
+        done: bool,
+    },
+}
+
+/// A lazy search over an [`EncryptedFs`]. With tags stored in the clear and no providers, this
+/// is a search of the inner filesystem. Otherwise every file is checked in turn.
+pub struct SearchIter<'a, F: FileSystem, P: TagPattern> {
+    fs: &'a EncryptedFs<F>,
+    state: SearchState<'a, F, P>,
+}
+
+impl<F: FileSystem, P: TagPattern> Iterator for SearchIter<'_, F, P> {
+    type Item = Result<FileId, Error<F::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fs = self.fs;
+        match &mut self.state {
+            SearchState::Inner(iter) => iter.next().map(|id| id.map_err(Error::Fs)),
+            SearchState::Scan { pattern, ids, done } => {
+                while !*done {
+                    if ids.is_none() {
+                        match fs.inner.search_tags(TagPredicate::And(Vec::new())) {
+                            Ok(found) => *ids = Some(found.into_iter()),
+                            Err(err) => {
+                                *done = true;
+                                return Some(Err(Error::Fs(err)));
+                            }
+                        }
+                    }
+                    let Some(id) = ids.as_mut().and_then(Iterator::next) else {
+                        *done = true;
+                        break;
+                    };
+
+                    match fs.get_tags(id) {
+                        Ok(tags) if pattern.match_tags(&tags) => return Some(Ok(id)),
+                        Ok(_) => (),
+                        Err(err) => {
+                            *done = true;
+                            return Some(Err(err));
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// A handle streaming data into a new file of an [`EncryptedFs`]. Data is buffered until the
+/// handle is flushed, as it's encrypted as a whole.
+pub type Writer<'a, F> = BufferedWriter<'a, EncryptedFs<F>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::InMemoryFs;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn test_encrypted() {
+        let fs = EncryptedFs::new(InMemoryFs::new(), &KEY);
+
+        let id = fs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+        assert_ne!(fs.inner().get_info(id).unwrap().data(), &[1, 2, 3]);
+        assert_eq!(fs.get_info(id).unwrap().data(), &[1, 2, 3]);
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [id]);
+        assert_eq!(fs.get_metadata(id).unwrap().size(), 3);
+
+        let mut data = Vec::new();
+        fs.read_file(id).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, [1, 2, 3]);
+
+        fs.set_config(b"x = 1").unwrap();
+        assert_ne!(fs.inner().config().unwrap(), b"x = 1");
+        assert_eq!(fs.config().unwrap(), b"x = 1");
+
+        let other = EncryptedFs::new(fs.inner, &[8; 32]);
+        assert!(matches!(other.get_info(id), Err(Error::Crypto)));
+    }
+
+    #[test]
+    fn test_encrypted_tags() {
+        let fs = EncryptedFs::new(InMemoryFs::new(), &KEY).encrypt_tags(true);
+
+        let tag = Tag::new(Group::custom("g"), "b").with_value(3);
+        let id = fs.add_file(&[1], [Tag::named("a"), tag.clone()]).unwrap();
+        fs.add_file(&[2], [Tag::named("c")]).unwrap();
+
+        assert!(fs
+            .inner()
+            .get_info(id)
+            .unwrap()
+            .tags()
+            .iter()
+            .all(|tag| *tag.group() == TAG_GROUP));
+        assert_eq!(
+            fs.get_tags(id).unwrap(),
+            BTreeSet::from([Tag::named("a"), tag.clone()])
+        );
+        assert_eq!(fs.search_tags(tag.clone()).unwrap(), [id]);
+
+        fs.remove_tags(id, [Tag::named("a")]).unwrap();
+        assert_eq!(fs.get_tags(id).unwrap(), BTreeSet::from([tag]));
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), []);
+    }
+
+    #[test]
+    fn test_provided_tags() {
+        let fs = EncryptedFs::new(InMemoryFs::new(), &KEY).encrypt_tags(true);
+        let len = Tag::new(Group::custom("len"), "1");
+        fs.register_provider(Group::custom("len"), |data: &[u8]| {
+            vec![Tag::new(Group::custom("len"), data.len().to_string())]
+        })
+        .unwrap();
+
+        let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        fs.add_tags(id, [Tag::named("b")]).unwrap();
+        fs.remove_tags(id, [Tag::named("a")]).unwrap();
+        fs.rename_tag(&Tag::named("b"), Tag::named("c")).unwrap();
+        fs.rename_group(&Group::custom("len"), Group::custom("size"))
+            .unwrap();
+
+        assert_eq!(
+            fs.get_stored_tags(id).unwrap(),
+            BTreeSet::from([Tag::named("c")])
+        );
+        assert_eq!(
+            fs.get_tags(id).unwrap(),
+            BTreeSet::from([Tag::named("c"), len])
+        );
+    }
+}
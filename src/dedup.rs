@@ -0,0 +1,760 @@
+//! Content-addressed deduplicating wrapper around another TBF
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt::Write as _;
+use core::ops::{Bound, Range};
+use std::sync::mpsc::Receiver;
+use std::sync::{PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::events::{Event, Subscribers};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::{hash_data, Metadata};
+use crate::provider::{provide_tags, Providers};
+use crate::{
+    generate_special, BufferedWriter, Error as _, FileId, FileInfo, FileSystem, FileSystemRead,
+    FileSystemWrite, Group, InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag,
+    TagInferrer, TagPattern, TagPredicate, TagProvider, TagValue,
+};
+
+/// Group of the tag marking a file in the inner filesystem as a data blob. The tag's name is the
+/// hex hash of the data.
+const BLOB_GROUP: &str = "tbf-blob";
+/// Group of the tags on a file in the inner filesystem recording which blob holds its data
+const REF_GROUP: &str = "tbf-ref";
+
+type Hash = [u8; 32];
+
+fn to_hex(hash: &Hash) -> String {
+    let mut out = String::with_capacity(64);
+    for byte in hash {
+        let _ = write!(out, "{byte:02x}");
+    }
+    out
+}
+
+fn from_hex(hex: &str) -> Option<Hash> {
+    let mut out = [0; 32];
+    if hex.len() != 64 {
+        return None;
+    }
+    for (i, byte) in out.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(i * 2..i * 2 + 2)?, 16).ok()?;
+    }
+    Some(out)
+}
+
+// IDs and sizes are stored as int tag values, keeping all their bits
+fn to_value(val: u64) -> TagValue {
+    TagValue::Int(i64::from_ne_bytes(val.to_ne_bytes()))
+}
+
+fn from_value(val: &TagValue) -> Option<u64> {
+    match val {
+        TagValue::Int(val) => Some(u64::from_ne_bytes(val.to_ne_bytes())),
+        _ => None,
+    }
+}
+
+/// Where a file's data is stored
+#[derive(Clone, Copy)]
+struct DataRef {
+    blob: FileId,
+    hash: Hash,
+    size: u64,
+}
+
+impl DataRef {
+    fn tags(self) -> [Tag; 3] {
+        [
+            Tag::new(REF_GROUP, "blob").with_value(to_value(self.blob.into_u64_unchecked())),
+            Tag::new(REF_GROUP, "hash").with_value(to_hex(&self.hash)),
+            Tag::new(REF_GROUP, "size").with_value(to_value(self.size)),
+        ]
+    }
+
+    /// Split the tags of a file in the inner filesystem into the user's tags and the data ref
+    fn split(tags: BTreeSet<Tag>) -> (BTreeSet<Tag>, Option<DataRef>) {
+        let (hidden, user) = tags
+            .into_iter()
+            .partition::<BTreeSet<_>, _>(|tag| *tag.group() == REF_GROUP);
+
+        let find = |name| {
+            hidden
+                .iter()
+                .find(|tag| tag.name() == name)
+                .and_then(Tag::value)
+        };
+        let data = (|| {
+            let hash = match find("hash")? {
+                TagValue::String(hex) => from_hex(hex)?,
+                _ => return None,
+            };
+            Some(DataRef {
+                blob: FileId::from_u64_unchecked(from_value(find("blob")?)?),
+                hash,
+                size: from_value(find("size")?)?,
+            })
+        })();
+
+        (user, data)
+    }
+}
+
+/// Which blobs exist, and which files use them
+#[derive(Default)]
+struct Blobs {
+    by_hash: BTreeMap<Hash, FileId>,
+    refs: BTreeMap<Hash, BTreeSet<FileId>>,
+    files: BTreeMap<FileId, DataRef>,
+}
+
+/// A wrapper around another filesystem which stores identical data only once. Each distinct
+/// piece of data is stored as a single blob in the inner filesystem, keyed by its BLAKE3 hash,
+/// and shared by every file with that data. Blobs are removed once no file refers to them.
+/// Files can also be added sharing the data of another directly, with [`DedupFs::add_link`].
+///
+/// Files keep their IDs from the inner filesystem. Bookkeeping is done with tags in the
+/// `tbf-blob` and `tbf-ref` groups, which are hidden from [`FileSystemRead::get_info`], so the inner
+/// filesystem shouldn't be edited directly while wrapped.
+pub struct DedupFs<F> {
+    inner: F,
+    blobs: RwLock<Blobs>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    subscribers: Subscribers,
+}
+
+impl<F: FileSystem> DedupFs<F> {
+    /// Wrap a filesystem, which should either be empty or previously wrapped by a `DedupFs`.
+    /// Any blobs no longer referred to by a file are removed.
+    pub fn new(inner: F) -> Result<DedupFs<F>, F::Error> {
+        let out = DedupFs {
+            inner,
+            blobs: RwLock::new(Blobs::default()),
+            providers: RwLock::new(Providers::new()),
+            inferrers: RwLock::new(Inferrers::new()),
+            subscribers: Subscribers::new(),
+        };
+        out.reload()?;
+
+        let blob_ids = out
+            .inner
+            .search_tags(TagPredicate::group(Group::custom(BLOB_GROUP)))?;
+        let blobs = out.read_blobs();
+        for id in blob_ids {
+            if !blobs.by_hash.values().any(|&blob| blob == id) {
+                out.inner.remove_file(id)?;
+            }
+        }
+        drop(blobs);
+
+        Ok(out)
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Add a file sharing the data of an existing one, but with its own tags, like a hard link.
+    /// The data is kept until the last file sharing it is removed. Unlike a hard link, editing
+    /// the data of either file gives it data of its own, leaving the other unchanged.
+    pub fn add_link<I>(&self, target: FileId, tags: I) -> Result<FileId, F::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let id = self.atomic(|| {
+            // Held until the link is counted, so the target's blob can't be removed in between
+            let mut blobs = self.write_blobs();
+            let data = blobs
+                .files
+                .get(&target)
+                .copied()
+                .ok_or_else(|| F::Error::file_not_found(target))?;
+            let id = self
+                .inner
+                .add_file(&[], tags.into_iter().chain(data.tags()))?;
+            blobs.refs.entry(data.hash).or_default().insert(id);
+            blobs.files.insert(id, data);
+            Ok(id)
+        })?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    /// Count the files sharing the data of a file, including itself
+    pub fn link_count(&self, id: FileId) -> Result<usize, F::Error> {
+        let data = self.data_ref(id)?;
+        Ok(self
+            .read_blobs()
+            .refs
+            .get(&data.hash)
+            .map_or(0, BTreeSet::len))
+    }
+
+    fn read_blobs(&self) -> RwLockReadGuard<'_, Blobs> {
+        self.blobs.read().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_blobs(&self) -> RwLockWriteGuard<'_, Blobs> {
+        self.blobs.write().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Rebuild the blob bookkeeping from the tags in the inner filesystem
+    fn reload(&self) -> Result<(), F::Error> {
+        let mut blobs = Blobs::default();
+        for id in self
+            .inner
+            .search_tags(TagPredicate::group(Group::custom(REF_GROUP)))?
+        {
+            if let (_, Some(data)) = DataRef::split(self.inner.get_info(id)?.tags) {
+                blobs.by_hash.insert(data.hash, data.blob);
+                blobs.refs.entry(data.hash).or_default().insert(id);
+                blobs.files.insert(id, data);
+            }
+        }
+        *self.write_blobs() = blobs;
+        Ok(())
+    }
+
+    /// Run a closure as a transaction of the inner filesystem, reloading the bookkeeping if it
+    /// fails and changes are undone
+    fn atomic<T>(&self, f: impl FnOnce() -> Result<T, F::Error>) -> Result<T, F::Error> {
+        let out = self.inner.transaction(|_| f());
+        if out.is_err() {
+            self.reload()?;
+        }
+        out
+    }
+
+    fn data_ref(&self, id: FileId) -> Result<DataRef, F::Error> {
+        self.read_blobs()
+            .files
+            .get(&id)
+            .copied()
+            .ok_or_else(|| F::Error::file_not_found(id))
+    }
+
+    /// Store some data, reusing an existing blob if there is one. The blob isn't referenced by
+    /// anything until [`DedupFs::link`] is called.
+    fn store(&self, data: &[u8]) -> Result<DataRef, F::Error> {
+        let hash = hash_data(data);
+        let mut blobs = self.write_blobs();
+        let blob = if let Some(&blob) = blobs.by_hash.get(&hash) {
+            blob
+        } else {
+            let blob = self
+                .inner
+                .add_file(data, [Tag::new(BLOB_GROUP, to_hex(&hash))])?;
+            blobs.by_hash.insert(hash, blob);
+            blob
+        };
+        Ok(DataRef {
+            blob,
+            hash,
+            size: data.len() as u64,
+        })
+    }
+
+    fn link(&self, id: FileId, data: DataRef) {
+        let mut blobs = self.write_blobs();
+        blobs.refs.entry(data.hash).or_default().insert(id);
+        blobs.files.insert(id, data);
+    }
+
+    /// Stop a file referring to its blob, removing the blob if nothing else uses it
+    fn unlink(&self, id: FileId) -> Result<(), F::Error> {
+        let mut blobs = self.write_blobs();
+        let Some(data) = blobs.files.remove(&id) else {
+            return Ok(());
+        };
+        if let Some(ids) = blobs.refs.get_mut(&data.hash) {
+            ids.remove(&id);
+            if !ids.is_empty() {
+                return Ok(());
+            }
+        }
+        blobs.refs.remove(&data.hash);
+        blobs.by_hash.remove(&data.hash);
+        drop(blobs);
+        self.inner.remove_file(data.blob)
+    }
+
+    /// Get a file's tags as stored in the inner filesystem, without its data ref
+    fn user_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, F::Error> {
+        self.data_ref(id)?;
+        Ok(DataRef::split(self.inner.get_tags(id)?).0)
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for DedupFs<F> {
+    type Error = F::Error;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        let has_providers = !self
+            .providers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty();
+
+        // Provided tags depend on the real data, so have to be checked one file at a time
+        let state = if has_providers {
+            SearchState::Scan {
+                pattern: tags,
+                cursor: Bound::Unbounded,
+                done: false,
+            }
+        } else {
+            SearchState::Inner(self.inner.search_tags_iter(tags))
+        };
+        SearchIter { fs: self, state }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let data_ref = self.data_ref(id)?;
+        let mut tags = self.user_tags(id)?;
+        let data = self.inner.get_info(data_ref.blob)?.data;
+        tags.extend(provide_tags(
+            &self
+                .providers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            &data,
+        ));
+        Ok(FileInfo { id, tags, data })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let mut tags = self.user_tags(id)?;
+        let providers = self
+            .providers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !providers.is_empty() {
+            let data = self.inner.get_data(self.data_ref(id)?.blob)?;
+            tags.extend(provide_tags(&providers, &data));
+        }
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.user_tags(id)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.inner.get_data(self.data_ref(id)?.blob)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.inner.read_range(self.data_ref(id)?.blob, range)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let data = self.data_ref(id)?;
+        let meta = self.inner.get_metadata(id)?;
+        Ok(Metadata {
+            size: data.size,
+            hash: data.hash,
+            ..meta
+        })
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        let mut groups = self
+            .read_blobs()
+            .refs
+            .values()
+            .filter(|ids| ids.len() > 1)
+            .map(|ids| ids.iter().copied().collect::<Vec<_>>())
+            .collect::<Vec<_>>();
+        groups.sort_unstable();
+        Ok(groups)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self
+            .read_blobs()
+            .refs
+            .get(hash)
+            .and_then(|ids| ids.first().copied()))
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        match file {
+            // Blobs are live files in the inner filesystem, so only its trash is accurate
+            SpecialFile::Trash => self.inner.special(file),
+            _ => generate_special(self, file),
+        }
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.inner.special_data(file)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for DedupFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<Vec<_>>();
+        let inferrers = self
+            .inferrers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        tags.extend(infer_tags(&inferrers, data));
+        drop(inferrers);
+
+        let id = self.atomic(|| {
+            let data = self.store(data)?;
+            let id = self
+                .inner
+                .add_file(&[], tags.into_iter().chain(data.tags()))?;
+            self.link(id, data);
+            Ok(id)
+        })?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<Vec<_>>();
+        let inferrers = self
+            .inferrers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        tags.extend(infer_tags(&inferrers, data));
+        drop(inferrers);
+
+        self.atomic(|| {
+            // Claimed before storing the data, so a new blob can't be given the ID
+            self.inner.add_file_with_id(id, &[], tags)?;
+            let data = self.store(data)?;
+            self.inner.add_tags(id, data.tags())?;
+            self.link(id, data);
+            Ok(())
+        })?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let old = self.data_ref(id)?;
+        let tags_changed = tags.is_some();
+        let tags = match tags {
+            Some(tags) => tags.into_iter().collect(),
+            None => self.user_tags(id)?,
+        };
+
+        self.atomic(|| {
+            let new = match data {
+                Some(data) => self.store(data)?,
+                None => old,
+            };
+            // Rewriting the empty data keeps the inner file's modification time accurate
+            let touch = data.map(|_| &[][..]);
+            self.inner
+                .edit_file(id, touch, Some(tags.into_iter().chain(new.tags())))?;
+
+            if data.is_some() {
+                self.unlink(id)?;
+                self.link(id, new);
+            }
+            Ok(())
+        })?;
+
+        if data.is_some() {
+            self.subscribers.emit(Event::FileEdited(id));
+        }
+        if tags_changed {
+            self.subscribers.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.data_ref(id)?;
+        self.atomic(|| {
+            self.inner.remove_file(id)?;
+            self.unlink(id)
+        })?;
+        self.subscribers.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        self.atomic(|| f(self))
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.set_special_data(file, data)
+    }
+}
+
+impl<F: FileSystem> ProvidedTags for DedupFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> InferredTags for DedupFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for DedupFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.inner.read_file(self.data_ref(id)?.blob)
+    }
+}
+
+impl<F: FileSystem> StreamWrite for DedupFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+enum SearchState<'a, F: FileSystem + 'a, P: TagPattern + 'a> {
+    Inner(F::SearchIter<'a, P>),
+    Scan {
+        pattern: P,
+        cursor: Bound<FileId>,
+        done: bool,
+    },
+}
+
+/// A lazy search over a [`DedupFs`]. Without any providers, this is a search of the inner
+/// filesystem with blobs skipped. Otherwise every file is checked in turn.
+pub struct SearchIter<'a, F: FileSystem, P: TagPattern> {
+    fs: &'a DedupFs<F>,
+    state: SearchState<'a, F, P>,
+}
+
+impl<F: FileSystem, P: TagPattern> Iterator for SearchIter<'_, F, P> {
+    type Item = Result<FileId, F::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let fs = self.fs;
+        match &mut self.state {
+            SearchState::Inner(iter) => iter.find(|id| match id {
+                Ok(id) => fs.read_blobs().files.contains_key(id),
+                Err(_) => true,
+            }),
+            SearchState::Scan {
+                pattern,
+                cursor,
+                done,
+            } => {
+                while !*done {
+                    let next = fs
+                        .read_blobs()
+                        .files
+                        .range((*cursor, Bound::Unbounded))
+                        .next()
+                        .map(|(&id, _)| id);
+                    let Some(id) = next else {
+                        *done = true;
+                        break;
+                    };
+                    *cursor = Bound::Excluded(id);
+
+                    match fs.get_info(id) {
+                        Ok(info) if pattern.match_tags(info.tags()) => return Some(Ok(id)),
+                        Ok(_) => (),
+                        Err(err) => {
+                            *done = true;
+                            return Some(Err(err));
+                        }
+                    }
+                }
+                None
+            }
+        }
+    }
+}
+
+/// A handle streaming data into a new file of a [`DedupFs`]. Data is buffered until the handle is
+/// flushed, as it has to be hashed before it can be stored.
+pub type Writer<'a, F> = BufferedWriter<'a, DedupFs<F>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Read;
+
+    use super::*;
+    use crate::InMemoryFs;
+
+    #[test]
+    fn test_dedup() {
+        let fs = DedupFs::new(InMemoryFs::new()).unwrap();
+
+        let first = fs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+        let second = fs.add_file(&[1, 2, 3], [Tag::named("b")]).unwrap();
+        let third = fs.add_file(&[4], [Tag::named("a")]).unwrap();
+
+        // Two files and two blobs
+        assert_eq!(
+            fs.inner()
+                .search_tags(TagPredicate::And(Vec::new()))
+                .unwrap()
+                .len(),
+            5
+        );
+        assert_eq!(fs.find_duplicates().unwrap(), [vec![first, second]]);
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [first, third]);
+        assert_eq!(
+            fs.get_info(second).unwrap().tags(),
+            &BTreeSet::from([Tag::named("b")])
+        );
+        assert_eq!(fs.get_metadata(second).unwrap().size(), 3);
+
+        let mut data = Vec::new();
+        fs.read_file(second)
+            .unwrap()
+            .read_to_end(&mut data)
+            .unwrap();
+        assert_eq!(data, [1, 2, 3]);
+
+        fs.edit_file(third, Some(&[1, 2, 3]), None::<[Tag; 0]>)
+            .unwrap();
+        assert_eq!(fs.find_duplicates().unwrap(), [vec![first, second, third]]);
+        assert_eq!(
+            fs.find_by_hash(&hash_data(&[1, 2, 3])).unwrap(),
+            Some(first)
+        );
+        assert_eq!(fs.find_by_hash(&hash_data(&[4])).unwrap(), None);
+        assert_eq!(
+            fs.inner()
+                .search_tags(TagPredicate::And(Vec::new()))
+                .unwrap()
+                .len(),
+            4
+        );
+
+        fs.remove_file(first).unwrap();
+        fs.remove_file(second).unwrap();
+        fs.remove_file(third).unwrap();
+        assert!(fs
+            .inner()
+            .search_tags(TagPredicate::And(Vec::new()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_links() {
+        let fs = DedupFs::new(InMemoryFs::new()).unwrap();
+        let events = fs.subscribe().unwrap();
+        let target = fs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+        let link = fs.add_link(target, [Tag::named("b")]).unwrap();
+        assert_eq!(events.try_iter().count(), 2);
+
+        assert_eq!(fs.get_data(link).unwrap(), [1, 2]);
+        assert_eq!(
+            fs.get_tags(link).unwrap(),
+            BTreeSet::from([Tag::named("b")])
+        );
+        assert_eq!(fs.link_count(target).unwrap(), 2);
+        assert!(fs.add_link(FileId::from_u64_unchecked(1000), []).is_err());
+
+        fs.remove_file(target).unwrap();
+        assert_eq!(fs.link_count(link).unwrap(), 1);
+        assert_eq!(fs.get_data(link).unwrap(), [1, 2]);
+
+        let other = fs.add_link(link, []).unwrap();
+        fs.edit_file(other, Some(&[3]), None::<[Tag; 0]>).unwrap();
+        assert_eq!(fs.get_data(link).unwrap(), [1, 2]);
+        assert_eq!(fs.link_count(link).unwrap(), 1);
+
+        // Reloading counts links from the tags left in the inner filesystem
+        let fs = DedupFs::new(fs.inner).unwrap();
+        assert_eq!(fs.link_count(link).unwrap(), 1);
+        fs.remove_file(link).unwrap();
+        fs.remove_file(other).unwrap();
+        assert!(fs
+            .inner()
+            .search_tags(TagPredicate::And(Vec::new()))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_dedup_reload() {
+        let fs = DedupFs::new(InMemoryFs::new()).unwrap();
+        let first = fs.add_file(&[1], []).unwrap();
+        let second = fs.add_file(&[1], []).unwrap();
+
+        let fs = DedupFs::new(fs.inner).unwrap();
+        assert_eq!(fs.find_duplicates().unwrap(), [vec![first, second]]);
+        assert_eq!(fs.get_info(first).unwrap().data(), &[1]);
+
+        let err = fs.transaction(|fs| {
+            fs.add_file(&[2], [])?;
+            fs.remove_file(first)?;
+            Err::<(), _>(crate::ImfsError::Poisoned)
+        });
+        assert!(err.is_err());
+        assert_eq!(fs.find_duplicates().unwrap(), [vec![first, second]]);
+        assert_eq!(
+            fs.inner()
+                .search_tags(TagPredicate::And(Vec::new()))
+                .unwrap()
+                .len(),
+            3
+        );
+    }
+}
@@ -0,0 +1,387 @@
+//! Data derived from files, like thumbnails, stored alongside them and kept until they change
+
+use alloc::collections::BTreeMap;
+use core::convert::TryFrom;
+use core::fmt;
+use std::io;
+
+use crate::error::ErrorKind;
+use crate::{FileId, FileSystemRead, FileSystemWrite, Group, Tag, TagPredicate};
+
+/// The start of the group of a derived file's tag, before the hex ID of its source
+const GROUP_PREFIX: &str = "derived.";
+
+/// The length of the hash of the source's data stored before derived data
+const HASH_LEN: usize = 32;
+
+/// The group of the tags of data derived from a file
+fn derived_group(source: FileId) -> Group {
+    Group::custom(format!("{}{:x}", GROUP_PREFIX, source.into_u64_unchecked()))
+}
+
+/// The tag marking a file as data of some kind derived from another
+fn derived_tag(source: FileId, kind: &str) -> Tag {
+    Tag::new(derived_group(source), kind.to_owned())
+}
+
+/// The file some derived data is from, if the tag is in a derived group
+fn derived_source(group: &Group) -> Option<FileId> {
+    let Group::Custom(name) = group else {
+        return None;
+    };
+    let id = u64::from_str_radix(name.strip_prefix(GROUP_PREFIX)?, 16).ok()?;
+    FileId::try_from(id).ok()
+}
+
+/// Error while reading or storing derived data
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The filesystem returned an error
+    Fs(E),
+    /// Stored derived data couldn't be read, as it was malformed
+    Corrupt(io::Error),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored derived data is malformed: {err}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// A source of data of one kind derived from a file's data, such as a thumbnail of an image
+pub trait Deriver: Send + Sync {
+    /// The kind of data this derives, which it's stored and looked up by
+    fn kind(&self) -> &str;
+
+    /// Derive data from a file's data, or `None` if nothing can be derived from it
+    fn derive(&self, data: &[u8]) -> Option<Vec<u8>>;
+}
+
+/// The data derived from the files of a filesystem. Derived data is stored as a file of its own,
+/// tagged with the kind of data and the file it's from, and starting with the hash of the data
+/// it was derived from. It's invalid once that file's data changes, so is never returned after
+/// its source is edited, and is derived again if a [`Deriver`] for its kind is registered.
+///
+/// Invalid data is only removed when it's next looked up, or by [`DerivedData::prune`], which
+/// also removes data derived from files since removed.
+pub struct DerivedData<'a, F: ?Sized> {
+    fs: &'a F,
+    derivers: BTreeMap<String, Box<dyn Deriver + 'a>>,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> DerivedData<'a, F> {
+    /// Get the data derived from the files of a filesystem, with no derivers registered
+    pub fn new(fs: &'a F) -> DerivedData<'a, F> {
+        DerivedData {
+            fs,
+            derivers: BTreeMap::new(),
+        }
+    }
+
+    /// Register a deriver, used to derive data of its kind when it isn't stored. Replaces any
+    /// already registered for the same kind.
+    #[must_use]
+    pub fn with_deriver<D: Deriver + 'a>(mut self, deriver: D) -> Self {
+        self.derivers
+            .insert(deriver.kind().to_owned(), Box::new(deriver));
+        self
+    }
+
+    fn find(&self, source: FileId, kind: &str) -> Result<Option<FileId>, F::Error> {
+        Ok(self
+            .fs
+            .search_tags(derived_tag(source, kind))?
+            .into_iter()
+            .next())
+    }
+
+    /// Get stored data of some kind derived from a file, if there is any and the file hasn't
+    /// changed since, without deriving it
+    pub fn get_stored(
+        &self,
+        source: FileId,
+        kind: &str,
+    ) -> Result<Option<Vec<u8>>, Error<F::Error>> {
+        let Some(id) = self.find(source, kind)? else {
+            return Ok(None);
+        };
+        let hash = *self.fs.get_metadata(source)?.hash();
+        valid_data(self.fs.get_data(id)?, &hash)
+    }
+
+    /// List the kinds of data stored for a file, whether still valid or not
+    pub fn stored_kinds(&self, source: FileId) -> Result<Vec<String>, F::Error> {
+        let group = derived_group(source);
+        let mut kinds = Vec::new();
+        for id in self.fs.search_tags(TagPredicate::Group(group.clone()))? {
+            kinds.extend(
+                self.fs
+                    .get_tags(id)?
+                    .into_iter()
+                    .filter(|tag| *tag.group() == group)
+                    .map(|tag| tag.name().to_owned()),
+            );
+        }
+        kinds.sort();
+        Ok(kinds)
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> DerivedData<'_, F> {
+    /// Get data of some kind derived from a file. Stored data is returned if the file hasn't
+    /// changed since it was derived. Otherwise, it's derived again with the registered deriver
+    /// and stored, or `None` is returned if there's no deriver for the kind or it can't derive
+    /// anything from the file.
+    pub fn get_derived(
+        &self,
+        source: FileId,
+        kind: &str,
+    ) -> Result<Option<Vec<u8>>, Error<F::Error>> {
+        let hash = *self.fs.get_metadata(source)?.hash();
+        let stored = self.find(source, kind)?;
+        if let Some(id) = stored {
+            if let Some(data) = valid_data(self.fs.get_data(id)?, &hash)? {
+                return Ok(Some(data));
+            }
+        }
+
+        let derived = match self.derivers.get(kind) {
+            Some(deriver) => deriver.derive(&self.fs.get_data(source)?),
+            None => None,
+        };
+        match (derived, stored) {
+            (Some(data), stored) => {
+                self.store(source, kind, stored, &hash, &data)?;
+                Ok(Some(data))
+            }
+            (None, Some(id)) => {
+                self.fs.remove_file(id)?;
+                Ok(None)
+            }
+            (None, None) => Ok(None),
+        }
+    }
+
+    /// Store data of some kind derived from a file's current data, replacing any already stored
+    pub fn set_derived(
+        &self,
+        source: FileId,
+        kind: &str,
+        data: &[u8],
+    ) -> Result<(), Error<F::Error>> {
+        let hash = *self.fs.get_metadata(source)?.hash();
+        let stored = self.find(source, kind)?;
+        self.store(source, kind, stored, &hash, data)
+    }
+
+    fn store(
+        &self,
+        source: FileId,
+        kind: &str,
+        stored: Option<FileId>,
+        hash: &[u8; HASH_LEN],
+        data: &[u8],
+    ) -> Result<(), Error<F::Error>> {
+        let mut full = Vec::with_capacity(HASH_LEN + data.len());
+        full.extend_from_slice(hash);
+        full.extend_from_slice(data);
+        match stored {
+            Some(id) => self.fs.edit_file(id, Some(&full), None::<[Tag; 0]>)?,
+            None => {
+                self.fs.add_file(&full, [derived_tag(source, kind)])?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Remove stored data of some kind derived from a file. Returns whether there was any.
+    pub fn remove_derived(&self, source: FileId, kind: &str) -> Result<bool, F::Error> {
+        match self.find(source, kind)? {
+            Some(id) => {
+                self.fs.remove_file(id)?;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    /// Remove every kind of data derived from a file
+    pub fn invalidate(&self, source: FileId) -> Result<(), F::Error> {
+        for id in self
+            .fs
+            .search_tags(TagPredicate::Group(derived_group(source)))?
+        {
+            self.fs.remove_file(id)?;
+        }
+        Ok(())
+    }
+
+    /// Remove all stored data which is no longer valid, because the file it was derived from
+    /// has changed or been removed. Returns how many were removed.
+    pub fn prune(&self) -> Result<usize, Error<F::Error>> {
+        let pattern = TagPredicate::group_glob(&format!("{GROUP_PREFIX}*"));
+        let mut removed = 0;
+        for id in self.fs.search_tags(pattern)? {
+            let source = self
+                .fs
+                .get_tags(id)?
+                .iter()
+                .find_map(|tag| derived_source(tag.group()));
+            let valid = match source {
+                Some(source) => match self.fs.get_metadata(source) {
+                    Ok(meta) => valid_data(self.fs.get_data(id)?, meta.hash())?.is_some(),
+                    Err(err) if crate::error::is_not_found(&err) => false,
+                    Err(err) => return Err(Error::Fs(err)),
+                },
+                None => true,
+            };
+            if !valid {
+                self.fs.remove_file(id)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
+
+/// Get the derived part of stored data, if it was derived from data with the given hash
+fn valid_data<E>(mut stored: Vec<u8>, hash: &[u8; HASH_LEN]) -> Result<Option<Vec<u8>>, Error<E>> {
+    if stored.len() < HASH_LEN {
+        return Err(Error::Corrupt(io::ErrorKind::UnexpectedEof.into()));
+    }
+    if stored[..HASH_LEN] != hash[..] {
+        return Ok(None);
+    }
+    stored.drain(..HASH_LEN);
+    Ok(Some(stored))
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::InMemoryFs;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    /// Derives the data reversed, counting how often it's run
+    struct Reverse<'a>(&'a AtomicUsize);
+
+    impl Deriver for Reverse<'_> {
+        fn kind(&self) -> &'static str {
+            "reverse"
+        }
+
+        fn derive(&self, data: &[u8]) -> Option<Vec<u8>> {
+            self.0.fetch_add(1, Ordering::Relaxed);
+            if data.is_empty() {
+                None
+            } else {
+                Some(data.iter().rev().copied().collect())
+            }
+        }
+    }
+
+    #[test]
+    fn test_derived() {
+        let fs = InMemoryFs::new();
+        let runs = AtomicUsize::new(0);
+        let derived = DerivedData::new(&fs).with_deriver(Reverse(&runs));
+        let a = fs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+        let empty = fs.add_file(&[], []).unwrap();
+
+        assert_eq!(derived.get_stored(a, "reverse").unwrap(), None);
+        assert_eq!(
+            derived.get_derived(a, "reverse").unwrap(),
+            Some(vec![3, 2, 1])
+        );
+        // Derived data is stored, so it isn't derived again
+        assert_eq!(
+            derived.get_derived(a, "reverse").unwrap(),
+            Some(vec![3, 2, 1])
+        );
+        assert_eq!(runs.load(Ordering::Relaxed), 1);
+        assert_eq!(derived.stored_kinds(a).unwrap(), ["reverse"]);
+
+        assert_eq!(derived.get_derived(empty, "reverse").unwrap(), None);
+        assert_eq!(derived.get_derived(a, "unknown").unwrap(), None);
+
+        // Editing the source invalidates what was derived from it
+        fs.edit_file(a, Some(&[4, 5]), None::<[Tag; 0]>).unwrap();
+        assert_eq!(derived.get_stored(a, "reverse").unwrap(), None);
+        assert_eq!(derived.get_derived(a, "reverse").unwrap(), Some(vec![5, 4]));
+        assert_eq!(runs.load(Ordering::Relaxed), 3);
+
+        derived.set_derived(a, "thumbnail", &[0xAB]).unwrap();
+        assert_eq!(
+            derived.get_derived(a, "thumbnail").unwrap(),
+            Some(vec![0xAB])
+        );
+        assert_eq!(derived.stored_kinds(a).unwrap(), ["reverse", "thumbnail"]);
+
+        // Without a deriver, invalid data is removed instead
+        fs.edit_file(a, Some(&[6]), None::<[Tag; 0]>).unwrap();
+        assert_eq!(derived.get_derived(a, "thumbnail").unwrap(), None);
+        assert_eq!(derived.stored_kinds(a).unwrap(), ["reverse"]);
+
+        assert_eq!(derived.prune().unwrap(), 1);
+        assert!(derived.stored_kinds(a).unwrap().is_empty());
+
+        derived.get_derived(a, "reverse").unwrap();
+        fs.remove_file(a).unwrap();
+        assert_eq!(derived.prune().unwrap(), 1);
+        assert_eq!(
+            fs.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+            [empty]
+        );
+
+        let b = fs.add_file(&[1], []).unwrap();
+        derived.set_derived(b, "thumbnail", &[1]).unwrap();
+        assert!(derived.remove_derived(b, "thumbnail").unwrap());
+        assert!(!derived.remove_derived(b, "thumbnail").unwrap());
+        derived.set_derived(b, "thumbnail", &[1]).unwrap();
+        derived.invalidate(b).unwrap();
+        assert!(derived.stored_kinds(b).unwrap().is_empty());
+    }
+}
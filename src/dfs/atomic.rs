@@ -0,0 +1,120 @@
+//! Writing files atomically, so a crash never leaves one half written
+//!
+//! Files are written in full to a temporary file beside the target, named with an extra `.tmp`
+//! extension, which is then renamed over the target. A crash part way through leaves only the
+//! temporary file behind, which is cleaned up next time the directory is opened. Files changed
+//! only in part are copied to the temporary file and changed there instead.
+
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const TEMP_EXT: &str = "tmp";
+
+/// The temporary file a path is written through
+pub(super) fn temp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".");
+    name.push(TEMP_EXT);
+    path.with_file_name(name)
+}
+
+fn write_inner<F>(path: &Path, sync: bool, f: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    let temp = temp_path(path);
+    let res = File::create(&temp).and_then(|file| {
+        let mut out = BufWriter::new(file);
+        f(&mut out)?;
+        out.flush()?;
+        if sync {
+            out.get_ref().sync_all()?;
+        }
+        Ok(())
+    });
+    finish(&temp, path, sync, res)
+}
+
+/// Rename a written temporary file over its target, or remove it if writing it failed
+fn finish(temp: &Path, path: &Path, sync: bool, res: io::Result<()>) -> io::Result<()> {
+    match res {
+        Ok(()) => {
+            fs::rename(temp, path)?;
+            if sync {
+                sync_dir(path)?;
+            }
+            Ok(())
+        }
+        Err(err) => {
+            let _ = fs::remove_file(temp);
+            Err(err)
+        }
+    }
+}
+
+/// Write a file atomically, with the contents written by a closure. If `sync` is set, this only
+/// returns once it's synced to disk.
+pub(super) fn write_with<F>(path: &Path, sync: bool, f: F) -> io::Result<()>
+where
+    F: FnOnce(&mut BufWriter<File>) -> io::Result<()>,
+{
+    write_inner(path, sync, f)
+}
+
+/// Write a file atomically. If `sync` is set, this only returns once it's synced to disk.
+pub(super) fn write(path: &Path, sync: bool, data: &[u8]) -> io::Result<()> {
+    write_inner(path, sync, |out| out.write_all(data))
+}
+
+/// Change part of a file atomically, by changing a copy of it which is then renamed over it
+pub(super) fn patch<F>(path: &Path, sync: bool, f: F) -> io::Result<()>
+where
+    F: FnOnce(&mut File) -> io::Result<()>,
+{
+    let temp = temp_path(path);
+    let res = fs::copy(path, &temp).and_then(|_| {
+        let mut file = OpenOptions::new().write(true).open(&temp)?;
+        f(&mut file)?;
+        if sync {
+            file.sync_all()?;
+        }
+        Ok(())
+    });
+    finish(&temp, path, sync, res)
+}
+
+/// Sync a file which was written earlier, along with the directory holding it. Files which no
+/// longer exist are skipped.
+pub(super) fn sync_file(path: &Path) -> io::Result<()> {
+    match OpenOptions::new().write(true).open(path) {
+        Ok(file) => file.sync_all()?,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+        Err(err) => return Err(err),
+    }
+    sync_dir(path)
+}
+
+/// Sync the directory holding a file, so a rename into it is on disk. Directories can't be
+/// synced everywhere, so this does nothing on other platforms.
+pub(super) fn sync_dir(path: &Path) -> io::Result<()> {
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        File::open(dir)?.sync_all()?;
+    }
+    #[cfg(not(unix))]
+    let _ = path;
+    Ok(())
+}
+
+/// Remove every temporary file left in a directory by an interrupted write
+pub(super) fn recover(dir: &Path) -> io::Result<()> {
+    for item in fs::read_dir(dir)? {
+        let path = item?.path();
+        if path.extension().is_some_and(|ext| ext == TEMP_EXT) && path.is_file() {
+            fs::remove_file(path)?;
+        }
+    }
+    Ok(())
+}
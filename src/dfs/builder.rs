@@ -0,0 +1,273 @@
+//! Configuring how a [`DirectoryBackedFs`] is opened
+
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, RwLock};
+use std::{fmt, fs};
+
+use super::feed::Feed;
+use super::index::Index;
+use super::locks::FileLocks;
+use super::shard::{self, Sharding};
+use super::{atomic, format, journal};
+use super::{Compression, DirectoryBackedFs, Error, SavedState};
+use crate::events::Subscribers;
+use crate::inference::Inferrers;
+use crate::provider::Providers;
+use crate::Retention;
+
+/// When a [`DirectoryBackedFs`] makes sure changes have reached the disk. Whichever is used,
+/// changes are never left half done by a crash or power loss, but ones which weren't synced yet
+/// can be lost.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum SyncPolicy {
+    /// Nothing is synced, so changes are written out whenever the OS gets to them
+    #[default]
+    Relaxed,
+    /// Changes made in a [transaction](crate::FileSystemWrite::transaction) are synced together
+    /// when it's committed, which is much cheaper than syncing each of them when making many.
+    /// Changes made outside of one are synced before they return, as with
+    /// [`Always`](SyncPolicy::Always).
+    OnBatch,
+    /// Every file written, including a file's data, tags, and checksums, and the store's state
+    /// and index, is synced to disk before the change writing it returns
+    Always,
+}
+
+/// How a [`DirectoryBackedFs`] loads its tag index when opened
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum IndexMode {
+    /// The saved index is used, unless it's missing or out of date, in which case it's rebuilt
+    #[default]
+    Load,
+    /// The index is always rebuilt from the tag files, such as if it's suspected to be wrong in
+    /// ways that can't be detected
+    Rebuild,
+}
+
+/// A problem with how a [`DirectoryBackedFs`] was configured, found when opening it
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum ConfigError {
+    /// The path exists, but isn't a directory
+    NotADirectory(PathBuf),
+    /// The directory doesn't exist, and can't be created when opening read-only
+    MissingDirectory(PathBuf),
+    /// The store was left part way through a change, which can't be recovered from when opening
+    /// read-only. Opening it writable once fixes this.
+    NeedsRecovery,
+    /// More levels of sharding were asked for than are supported
+    TooManyShardLevels(u8),
+    /// The store is sharded differently to what was asked for, and can't be resharded when
+    /// opening read-only
+    ShardingMismatch {
+        /// How the store is sharded
+        stored: Sharding,
+        /// How the store was asked to be sharded
+        requested: Sharding,
+    },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::NotADirectory(path) => write!(f, "{} isn't a directory", path.display()),
+            ConfigError::MissingDirectory(path) => write!(
+                f,
+                "{} doesn't exist, and can't be created read-only",
+                path.display()
+            ),
+            ConfigError::NeedsRecovery => write!(
+                f,
+                "store needs recovering from an unfinished change, which can't be done read-only"
+            ),
+            ConfigError::TooManyShardLevels(levels) => {
+                write!(
+                    f,
+                    "{levels} levels of sharding were asked for, at most 8 are supported"
+                )
+            }
+            ConfigError::ShardingMismatch { stored, requested } => write!(
+                f,
+                "store is sharded {stored}, not {requested}, and can't be resharded read-only"
+            ),
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// Options for opening a [`DirectoryBackedFs`], created with [`DirectoryBackedFs::builder`]
+#[derive(Debug, Default, Clone)]
+#[must_use]
+pub struct Builder {
+    sharding: Option<Sharding>,
+    compression: Compression,
+    sync: SyncPolicy,
+    read_only: bool,
+    index: IndexMode,
+    reuse_ids: bool,
+    retention: Retention,
+    verify_reads: bool,
+}
+
+impl Builder {
+    /// Set how files are sharded into subdirectories. Existing stores sharded differently are
+    /// [resharded](DirectoryBackedFs::reshard) when opened. If not set, stores keep however
+    /// they're sharded, and new stores are flat.
+    pub fn sharding(mut self, sharding: Sharding) -> Self {
+        self.sharding = Some(sharding);
+        self
+    }
+
+    /// Set how the data of files is compressed when it's written. See
+    /// [`DirectoryBackedFs::compression`].
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set when changes are synced to disk
+    pub fn sync(mut self, sync: SyncPolicy) -> Self {
+        self.sync = sync;
+        self
+    }
+
+    /// Set whether the store is opened read-only. Nothing in the directory is changed, even
+    /// while opening, and every change fails with [`Error::ReadOnly`].
+    pub fn read_only(mut self, read_only: bool) -> Self {
+        self.read_only = read_only;
+        self
+    }
+
+    /// Set how the tag index is loaded when opened
+    pub fn index(mut self, index: IndexMode) -> Self {
+        self.index = index;
+        self
+    }
+
+    /// Set whether IDs of removed files are handed out again. See
+    /// [`DirectoryBackedFs::reuse_ids`].
+    pub fn reuse_ids(mut self, reuse: bool) -> Self {
+        self.reuse_ids = reuse;
+        self
+    }
+
+    /// Set how many prior versions of file data are kept. See [`DirectoryBackedFs::retention`].
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Set whether checksums are checked on every read. See
+    /// [`DirectoryBackedFs::verify_reads`].
+    pub fn verify_reads(mut self, verify: bool) -> Self {
+        self.verify_reads = verify;
+        self
+    }
+
+    /// Create or load a directory-backed filesystem in the provided directory, with these
+    /// options
+    pub fn open<P: AsRef<Path>>(&self, dir: P) -> Result<DirectoryBackedFs, Error> {
+        let dir = dir.as_ref();
+        if let Some(Sharding::Nested(levels)) = self.sharding {
+            if levels > shard::MAX_LEVELS {
+                return Err(ConfigError::TooManyShardLevels(levels).into());
+            }
+        }
+        if !dir.exists() {
+            if self.read_only {
+                return Err(ConfigError::MissingDirectory(dir.to_owned()).into());
+            }
+            fs::create_dir_all(dir)?;
+        } else if !dir.is_dir() {
+            return Err(ConfigError::NotADirectory(dir.to_owned()).into());
+        }
+
+        let format = format::load(dir)?;
+        if format.version > format::VERSION {
+            return Err(Error::UnsupportedFormat(format.version));
+        }
+
+        let mut out = DirectoryBackedFs {
+            dir: dir.to_owned(),
+            sharding: format.sharding,
+            read_only: self.read_only,
+            sync: self.sync,
+            reuse_ids: self.reuse_ids,
+            retention: self.retention,
+            compression: self.compression,
+            verify_reads: self.verify_reads,
+            state: RwLock::new(SavedState::from_path(&dir.join("tbf.dat"))?),
+            index: RwLock::new(Index::new()),
+            providers: RwLock::new(Providers::new()),
+            inferrers: RwLock::new(Inferrers::new()),
+            journal: Mutex::new(None),
+            locks: FileLocks::new(),
+            feed: Mutex::new(Feed::open(dir.join("tbf.changes"))?),
+            subscribers: Subscribers::new(),
+        };
+
+        if self.read_only {
+            if format.resharding || out.journal_path().exists() {
+                return Err(ConfigError::NeedsRecovery.into());
+            }
+            match self.sharding {
+                Some(requested) if requested.levels() != out.sharding.levels() => {
+                    return Err(ConfigError::ShardingMismatch {
+                        stored: out.sharding,
+                        requested,
+                    }
+                    .into());
+                }
+                _ => (),
+            }
+            self.load_index(&out)?;
+            return Ok(out);
+        }
+
+        // Files may be left in the wrong shards if moving them was interrupted
+        if format.resharding {
+            out.gather_files()?;
+            format::save(dir, out.sharding, false)?;
+        }
+        for dir in out.sharding.dirs(dir)? {
+            atomic::recover(&dir)?;
+        }
+
+        // A journal left behind means a transaction was interrupted, so undo it
+        if out.journal_path().exists() {
+            journal::rollback(&out.journal_path(), |id| out.file_name(id))?;
+            out.rebuild_index()?;
+        } else {
+            self.load_index(&out)?;
+        }
+
+        if format.version < format::VERSION {
+            out.migrate(format.version)?;
+        }
+        out.recover_state()?;
+        if let Some(sharding) = self.sharding {
+            out.reshard(sharding)?;
+        }
+        Ok(out)
+    }
+
+    /// Load the saved index, or rebuild it if it's out of date or asked to be. The rebuilt index
+    /// is only saved if the filesystem isn't read-only.
+    fn load_index(&self, fs: &DirectoryBackedFs) -> Result<(), Error> {
+        let saved = match self.index {
+            IndexMode::Load => Index::load(&fs.index_path()).ok(),
+            IndexMode::Rebuild => None,
+        };
+        match saved {
+            Some(index) if index.files().keys().copied().eq(fs.scan_ids()?) => {
+                *fs.index.write()? = index;
+            }
+            _ if fs.read_only => *fs.index.write()? = fs.read_index()?,
+            _ => fs.rebuild_index()?,
+        }
+        Ok(())
+    }
+}
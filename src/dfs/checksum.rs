@@ -0,0 +1,164 @@
+//! Checksums of stored files, used to find corruption
+//!
+//! Each file has an `ID.sum` file beside it, holding the BLAKE3 hashes of its data and tag files
+//! exactly as stored, so they can be checked without decoding either. Since format version 3,
+//! it's followed by the hash and size of the file's decoded data, and when the file was created,
+//! so its metadata can be read without decoding the data either.
+
+use core::convert::TryFrom;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use super::atomic;
+use crate::{codec, FileId};
+
+pub(super) type Hash = [u8; 32];
+
+/// The hash recorded for a file which was missing when its checksums were written
+pub(super) const MISSING: Hash = [0; 32];
+
+/// Hash everything read from a reader, also returning how many bytes were read
+pub(super) fn hash_reader<R: Read>(mut input: R) -> io::Result<(Hash, u64)> {
+    let mut hasher = blake3::Hasher::new();
+    let mut len = 0;
+    let mut buf = [0; 8192];
+    loop {
+        match input.read(&mut buf)? {
+            0 => break,
+            read => {
+                hasher.update(&buf[..read]);
+                len += read as u64;
+            }
+        }
+    }
+    Ok((*hasher.finalize().as_bytes(), len))
+}
+
+/// Hash a stored file, or get [`MISSING`] if it doesn't exist
+pub(super) fn hash_file(path: &Path) -> io::Result<Hash> {
+    match File::open(path) {
+        Ok(file) => Ok(hash_reader(BufReader::new(file))?.0),
+        Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(MISSING),
+        Err(err) => Err(err),
+    }
+}
+
+/// What's held in a file's data once decoded, rather than how it's stored
+#[derive(Copy, Clone)]
+pub(super) struct Content {
+    pub(super) hash: Hash,
+    pub(super) size: u64,
+    pub(super) created: SystemTime,
+}
+
+/// The checksums of a stored file
+#[derive(Copy, Clone)]
+pub(super) struct Sums {
+    pub(super) data: Hash,
+    pub(super) tags: Hash,
+    /// Missing from checksums written before format version 3
+    pub(super) content: Option<Content>,
+}
+
+impl Sums {
+    /// Load the checksums of a file, if it has any which can be read
+    pub(super) fn load(path: &Path) -> io::Result<Option<Sums>> {
+        let bytes = match std::fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err),
+        };
+        let (Some(data), Some(tags)) = (bytes.get(..32), bytes.get(32..64)) else {
+            return Ok(None);
+        };
+        let (Ok(data), Ok(tags)) = (Hash::try_from(data), Hash::try_from(tags)) else {
+            return Ok(None);
+        };
+        // Content which can't be read is worked out again from the data instead
+        let content = match &bytes[64..] {
+            [] => None,
+            rest => Content::read(rest).ok(),
+        };
+        Ok(Some(Sums {
+            data,
+            tags,
+            content,
+        }))
+    }
+
+    pub(super) fn save(&self, path: &Path, sync: bool) -> io::Result<()> {
+        let mut out = Vec::with_capacity(112);
+        out.extend_from_slice(&self.data);
+        out.extend_from_slice(&self.tags);
+        if let Some(content) = &self.content {
+            out.extend_from_slice(&content.hash);
+            codec::write_u64(&mut out, content.size)?;
+            codec::write_u64(&mut out, to_nanos(content.created))?;
+        }
+        atomic::write(path, sync, &out)
+    }
+}
+
+impl Content {
+    fn read(mut input: &[u8]) -> io::Result<Content> {
+        let mut hash = MISSING;
+        input.read_exact(&mut hash)?;
+        Ok(Content {
+            hash,
+            size: codec::read_u64(&mut input)?,
+            created: from_nanos(codec::read_u64(&mut input)?),
+        })
+    }
+}
+
+pub(super) fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0)
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+/// A problem with a stored file, found by [`DirectoryBackedFs::verify`] or
+/// [`DirectoryBackedFs::check_and_repair`]
+///
+/// [`DirectoryBackedFs::verify`]: super::DirectoryBackedFs::verify
+/// [`DirectoryBackedFs::check_and_repair`]: super::DirectoryBackedFs::check_and_repair
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum CorruptionReport {
+    /// The data file is missing
+    DataMissing(FileId),
+    /// The tag file is missing
+    TagsMissing(FileId),
+    /// The data file doesn't match its checksum, so was changed or damaged since it was written
+    DataChanged(FileId),
+    /// The tag file doesn't match its checksum, so was changed or damaged since it was written
+    TagsChanged(FileId),
+    /// The tag file can't be decoded
+    TagsUnreadable(FileId),
+    /// The ID is past the saved ID counter, so could be handed out again
+    IdNotAllocated(FileId),
+    /// Only prior versions or checksums are left of a file which no longer exists
+    Leftover(FileId),
+}
+
+impl CorruptionReport {
+    /// Get the ID of the file with the problem
+    pub fn id(&self) -> FileId {
+        match self {
+            CorruptionReport::DataMissing(id)
+            | CorruptionReport::TagsMissing(id)
+            | CorruptionReport::DataChanged(id)
+            | CorruptionReport::TagsChanged(id)
+            | CorruptionReport::TagsUnreadable(id)
+            | CorruptionReport::IdNotAllocated(id)
+            | CorruptionReport::Leftover(id) => *id,
+        }
+    }
+}
@@ -0,0 +1,185 @@
+//! Compression of stored file data
+//!
+//! Data files are normally stored as-is. A file stored any other way starts with a header, the
+//! bytes of [`MAGIC`] followed by a byte naming its [`Compression`]. Plain data which happens to
+//! start with the magic bytes is given a header too, so it's never mistaken for one.
+
+use std::borrow::Cow;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::ops::Range;
+use std::path::Path;
+
+#[cfg(feature = "compress")]
+use lz4_flex::frame::{FrameDecoder, FrameEncoder};
+#[cfg(feature = "compress")]
+use std::io::Write;
+
+const MAGIC: [u8; 4] = *b"\x89TBF";
+const HEADER_LEN: usize = MAGIC.len() + 1;
+
+/// How a [`DirectoryBackedFs`](super::DirectoryBackedFs) compresses file data when storing it
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Compression {
+    /// Data is stored as-is
+    #[default]
+    None,
+    /// Data is compressed with LZ4, which is fast but doesn't compress as well as others
+    #[cfg(feature = "compress")]
+    Lz4,
+}
+
+impl Compression {
+    fn id(self) -> u8 {
+        match self {
+            Compression::None => 0,
+            #[cfg(feature = "compress")]
+            Compression::Lz4 => 1,
+        }
+    }
+
+    fn from_id(id: u8) -> io::Result<Compression> {
+        match id {
+            0 => Ok(Compression::None),
+            #[cfg(feature = "compress")]
+            1 => Ok(Compression::Lz4),
+            #[cfg(not(feature = "compress"))]
+            1 => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "File data is compressed with LZ4, which needs the `compress` feature",
+            )),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "File data has an unknown compression",
+            )),
+        }
+    }
+}
+
+/// The header of data stored with the given compression
+pub(super) fn header(compression: Compression) -> [u8; HEADER_LEN] {
+    let mut out = [0; HEADER_LEN];
+    out[..MAGIC.len()].copy_from_slice(&MAGIC);
+    out[MAGIC.len()] = compression.id();
+    out
+}
+
+/// Encode data for storing with the given compression. Data which doesn't get any smaller when
+/// compressed is stored plain instead.
+#[cfg_attr(not(feature = "compress"), allow(clippy::unnecessary_wraps))]
+pub(super) fn encode(data: &[u8], compression: Compression) -> io::Result<Cow<'_, [u8]>> {
+    match compression {
+        Compression::None if data.starts_with(&MAGIC) => {
+            let mut out = header(compression).to_vec();
+            out.extend_from_slice(data);
+            Ok(Cow::Owned(out))
+        }
+        Compression::None => Ok(Cow::Borrowed(data)),
+        #[cfg(feature = "compress")]
+        Compression::Lz4 => {
+            let mut out = FrameEncoder::new(header(compression).to_vec());
+            out.write_all(data)?;
+            let out = out.finish().map_err(io::Error::other)?;
+            if out.len() < data.len() {
+                Ok(Cow::Owned(out))
+            } else {
+                encode(data, Compression::None)
+            }
+        }
+    }
+}
+
+/// Open a stored data file, positioned at the start of its data, along with its compression
+pub(super) fn open(path: &Path) -> io::Result<(File, Compression)> {
+    let mut file = File::open(path)?;
+    let mut header = Vec::with_capacity(HEADER_LEN);
+    (&mut file)
+        .take(HEADER_LEN as u64)
+        .read_to_end(&mut header)?;
+
+    if header.len() == HEADER_LEN && header.starts_with(&MAGIC) {
+        Ok((file, Compression::from_id(header[MAGIC.len()])?))
+    } else {
+        file.seek(SeekFrom::Start(0))?;
+        Ok((file, Compression::None))
+    }
+}
+
+/// Find where the data of a stored file starts, if it's stored plain and can be changed from
+/// offset `first` on without being mistaken for data with a header
+pub(super) fn plain_start(path: &Path, first: u64) -> io::Result<Option<u64>> {
+    let (mut file, compression) = open(path)?;
+    let start = file.stream_position()?;
+    // Without a header, changing the first few bytes could make them the magic bytes
+    let safe = start > 0 || first >= MAGIC.len() as u64;
+    Ok((compression == Compression::None && safe).then_some(start))
+}
+
+/// Get the size of the data of a stored file, once decompressed
+pub(super) fn size(path: &Path) -> io::Result<u64> {
+    let (mut file, compression) = open(path)?;
+    match compression {
+        Compression::None => Ok(file.metadata()?.len() - file.stream_position()?),
+        #[cfg(feature = "compress")]
+        Compression::Lz4 => io::copy(&mut Reader::open(path)?, &mut io::sink()),
+    }
+}
+
+/// Read the data of a stored file in full, decompressing it
+pub(super) fn read(path: &Path) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    Reader::open(path)?.read_to_end(&mut out)?;
+    Ok(out)
+}
+
+/// Read part of the data of a stored file, cut short at its end. Plain data is read from where
+/// the range starts, while compressed data has to be decompressed up to it.
+pub(super) fn read_range(path: &Path, range: Range<u64>) -> io::Result<Vec<u8>> {
+    let len = range.end.saturating_sub(range.start);
+    let mut out = Vec::new();
+    let mut reader = Reader::open(path)?;
+    match &mut reader.0 {
+        ReaderInner::Plain(file) => {
+            let start = file.stream_position()?;
+            file.seek(SeekFrom::Start(start.saturating_add(range.start)))?;
+        }
+        #[cfg(feature = "compress")]
+        ReaderInner::Lz4(decoder) => {
+            io::copy(&mut decoder.take(range.start), &mut io::sink())?;
+        }
+    }
+    reader.take(len).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+enum ReaderInner {
+    Plain(File),
+    #[cfg(feature = "compress")]
+    Lz4(Box<FrameDecoder<File>>),
+}
+
+/// A handle streaming the data of a file in a [`DirectoryBackedFs`](super::DirectoryBackedFs),
+/// decompressing it as it's read
+pub struct Reader(ReaderInner);
+
+impl Reader {
+    pub(super) fn open(path: &Path) -> io::Result<Reader> {
+        let (file, compression) = open(path)?;
+        Ok(Reader(match compression {
+            Compression::None => ReaderInner::Plain(file),
+            #[cfg(feature = "compress")]
+            Compression::Lz4 => ReaderInner::Lz4(Box::new(FrameDecoder::new(file))),
+        }))
+    }
+}
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match &mut self.0 {
+            ReaderInner::Plain(file) => file.read(buf),
+            #[cfg(feature = "compress")]
+            ReaderInner::Lz4(decoder) => decoder.read(buf),
+        }
+    }
+}
@@ -0,0 +1,111 @@
+//! The change feed, recording every change made to a store in order
+//!
+//! Changes are appended to `tbf.changes` as a kind byte followed by the little-endian ID of the
+//! file changed. Every record is the same size, so a change's sequence number is its position in
+//! the file, counting from 1, and the changes after one can be read by seeking straight to them.
+
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::PathBuf;
+
+use crate::codec;
+use crate::events::{Change, Event};
+
+const RECORD_LEN: u64 = 9;
+
+/// The change feed of a store, opened for appending
+pub(super) struct Feed {
+    path: PathBuf,
+    file: Option<File>,
+    len: u64,
+}
+
+impl Feed {
+    /// Open the feed at a path, without creating it until the first change is recorded. A record
+    /// cut short by a crash is dropped.
+    pub(super) fn open(path: PathBuf) -> io::Result<Feed> {
+        let len = match path.metadata() {
+            Ok(meta) => meta.len() / RECORD_LEN,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => 0,
+            Err(err) => return Err(err),
+        };
+        Ok(Feed {
+            path,
+            file: None,
+            len,
+        })
+    }
+
+    /// The sequence number of the last change recorded, or 0 if there are none
+    pub(super) fn last_seq(&self) -> u64 {
+        self.len
+    }
+
+    /// Record a change, returning its sequence number
+    pub(super) fn append(&mut self, event: Event, sync: bool) -> io::Result<u64> {
+        let file = match &mut self.file {
+            Some(file) => file,
+            file @ None => {
+                let opened = OpenOptions::new()
+                    .create(true)
+                    .write(true)
+                    .truncate(false)
+                    .open(&self.path)?;
+                // Drop a partial record left by a crash
+                opened.set_len(self.len * RECORD_LEN)?;
+                file.insert(opened)
+            }
+        };
+
+        let (kind, id) = match event {
+            Event::FileAdded(id) => (0, id),
+            Event::FileEdited(id) => (1, id),
+            Event::FileRemoved(id) => (2, id),
+            Event::TagsChanged(id) => (3, id),
+        };
+        let mut record = Vec::new();
+        record.push(kind);
+        codec::write_id(&mut record, id)?;
+        file.seek(SeekFrom::Start(self.len * RECORD_LEN))?;
+        file.write_all(&record)?;
+        if sync {
+            file.sync_data()?;
+        }
+        self.len += 1;
+        Ok(self.len)
+    }
+
+    /// Read every change after the one with a sequence number
+    pub(super) fn since(&self, seq: u64) -> io::Result<Vec<Change>> {
+        if seq >= self.len {
+            return Ok(Vec::new());
+        }
+        let mut file = File::open(&self.path)?;
+        file.seek(SeekFrom::Start(seq * RECORD_LEN))?;
+        let mut data = Vec::new();
+        file.take((self.len - seq) * RECORD_LEN)
+            .read_to_end(&mut data)?;
+
+        let mut data = &data[..];
+        let mut out = Vec::new();
+        for seq in seq + 1..=self.len {
+            let mut kind = [0];
+            data.read_exact(&mut kind)?;
+            let id = codec::read_id(&mut data)?;
+            let event = match kind[0] {
+                0 => Event::FileAdded(id),
+                1 => Event::FileEdited(id),
+                2 => Event::FileRemoved(id),
+                3 => Event::TagsChanged(id),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Unknown kind of change in the change feed",
+                    ))
+                }
+            };
+            out.push(Change::new(seq, event));
+        }
+        Ok(out)
+    }
+}
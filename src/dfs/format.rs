@@ -0,0 +1,104 @@
+//! The on-disk format of a store, and its version
+//!
+//! A store is a single directory, holding:
+//!
+//! - `tbf.fmt`: the format version, as the bytes `TBFS` followed by a little-endian `u32`, then
+//!   the number of levels of sharding, and a byte which is 1 while files are being moved
+//!   between shards
+//! - `tbf.dat`: the ID counter, followed by the IDs free to be reused
+//! - `tbf.idx`: the search index, which can be rebuilt from the tag files
+//! - `tbf.idx.log`: the changes to the search index since it was last saved in full (see
+//!   [`index`](super::index))
+//! - `tbf.cfg`: the config file, if one is set
+//! - `tbf.NAME`: every other special file which isn't generated, if it's set, named as by
+//!   [`SpecialFile::name`](crate::SpecialFile::name)
+//! - `tbf.journal`: the undo journal of a transaction in progress, if any
+//! - `tbf.changes`: the change feed, if any changes have been made (see [`feed`](super::feed))
+//!
+//! And for each file, named by its ID as 16 upper-case hex digits, either in the store directory
+//! or in nested subdirectories named by the bytes of its ID (see [`shard`](super::shard)):
+//!
+//! - `ID.dat`: the file data, possibly compressed (see [`compress`](super::compress))
+//! - `ID.tag`: the file's tags, encoded one after another
+//! - `ID.sum`: the checksums of the data and tag files (see [`checksum`](super::checksum))
+//! - `ID.vN.dat`: prior version `N` of the data, if kept
+//!
+//! The version is raised whenever the layout changes in a way older versions of this crate can't
+//! read. Older stores are migrated to the current version when opened, and newer ones are refused.
+//!
+//! - Version 0: stores written before the version file was added, which may lack checksums
+//! - Version 1: every file has checksums
+//! - Version 2: files may be sharded into subdirectories
+//! - Version 3: checksum files also record the content of each file's data
+
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::atomic;
+use super::shard::Sharding;
+use crate::codec;
+
+const MAGIC: [u8; 4] = *b"TBFS";
+
+/// The format version written by this version of the crate
+pub(super) const VERSION: u32 = 3;
+
+fn path(dir: &Path) -> PathBuf {
+    dir.join("tbf.fmt")
+}
+
+/// The format of a store, as recorded in its version file
+#[derive(Copy, Clone)]
+pub(super) struct Format {
+    pub(super) version: u32,
+    pub(super) sharding: Sharding,
+    /// Whether files were being moved to the shards they belong in, and may not all be there yet
+    pub(super) resharding: bool,
+}
+
+/// Load the format of the store in a directory. Stores without a version file are from before
+/// it was added, so are version 0.
+pub(super) fn load(dir: &Path) -> io::Result<Format> {
+    let bytes = match std::fs::read(path(dir)) {
+        Ok(bytes) => bytes,
+        Err(err) if err.kind() == io::ErrorKind::NotFound => {
+            return Ok(Format {
+                version: 0,
+                sharding: Sharding::Flat,
+                resharding: false,
+            })
+        }
+        Err(err) => return Err(err),
+    };
+
+    let mut input = &bytes[..];
+    let mut magic = [0; 4];
+    input.read_exact(&mut magic)?;
+    if magic != MAGIC {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Version file is not a TBF version file",
+        ));
+    }
+    let version = codec::read_u32(&mut input)?;
+
+    // Older versions are always flat, and newer ones are never read past the version
+    let mut rest = [0; 2];
+    if (2..=VERSION).contains(&version) {
+        input.read_exact(&mut rest)?;
+    }
+    Ok(Format {
+        version,
+        sharding: Sharding::from_levels(rest[0]),
+        resharding: rest[1] != 0,
+    })
+}
+
+/// Save the store in a directory as the current format version
+pub(super) fn save(dir: &Path, sharding: Sharding, resharding: bool) -> io::Result<()> {
+    atomic::write_with(&path(dir), false, |out| {
+        out.write_all(&MAGIC)?;
+        codec::write_u32(out, VERSION)?;
+        out.write_all(&[sharding.levels(), u8::from(resharding)])
+    })
+}
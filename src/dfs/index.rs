@@ -0,0 +1,386 @@
+//! Inverted tag index, so searches don't need to read every tag file. The hash of each file's
+//! content is kept too, so files can be found by their data without reading any checksums.
+//!
+//! The index is saved in full now and then, and each change since is appended to a log beside
+//! it, so changing a file doesn't rewrite the whole index. Once the log holds more changes than
+//! there are files, the index is saved in full again and the log is started afresh. Each full
+//! save is given a new generation, which the log starts with, so a log left behind from before
+//! the last full save is ignored.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::convert::Infallible;
+use std::ffi::OsString;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use super::atomic;
+use super::checksum::{self, Hash};
+use crate::codec::{read_id, read_tag, read_u64, write_id, write_tag, write_u64};
+use crate::metadata;
+use crate::{FileId, Lookup, Tag, TagIndex, TagPattern, TagPredicate};
+
+const MAGIC: &[u8; 4] = b"TBI3";
+const LOG_MAGIC: &[u8; 4] = b"TBIL";
+
+/// Log record setting a file's tags and content hash, followed by the number of tags, each tag,
+/// and whether the hash is known followed by the hash if so
+const LOG_SET: u8 = 1;
+/// Log record removing a file
+const LOG_REMOVE: u8 = 2;
+
+/// The fewest changes the log holds before the index is saved in full again
+const MIN_LOG: u64 = 1024;
+
+/// The log of changes beside an index
+pub(super) fn log_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().map(OsString::from).unwrap_or_default();
+    name.push(".log");
+    path.with_file_name(name)
+}
+
+/// Both directions of the mapping between files and tags. Only the inverted direction
+/// (tag to files) is stored on disk, along with the set of all files and their content hashes.
+#[derive(Default, Clone)]
+pub(super) struct Index {
+    files: BTreeMap<FileId, BTreeSet<Tag>>,
+    tags: BTreeMap<Tag, BTreeSet<FileId>>,
+    /// The hash of each file's content, which can be set before the file's tags are
+    hashes: BTreeMap<FileId, Hash>,
+    by_hash: BTreeMap<Hash, BTreeSet<FileId>>,
+    /// The generation of the last full save
+    generation: u64,
+    /// How many changes have been logged since the last full save
+    logged: u64,
+}
+
+impl Index {
+    pub(super) fn new() -> Index {
+        Index::default()
+    }
+
+    /// Set the tags for a file, replacing any it previously had
+    pub(super) fn insert(&mut self, id: FileId, tags: BTreeSet<Tag>) {
+        self.remove_tags(id);
+        for tag in &tags {
+            self.tags.entry(tag.clone()).or_default().insert(id);
+        }
+        self.files.insert(id, tags);
+    }
+
+    /// Set the hash of a file's content, replacing any it previously had
+    pub(super) fn set_hash(&mut self, id: FileId, hash: Hash) {
+        self.remove_hash(id);
+        self.by_hash.entry(hash).or_default().insert(id);
+        self.hashes.insert(id, hash);
+    }
+
+    pub(super) fn remove(&mut self, id: FileId) {
+        self.remove_tags(id);
+        self.remove_hash(id);
+    }
+
+    fn remove_tags(&mut self, id: FileId) {
+        let Some(old) = self.files.remove(&id) else {
+            return;
+        };
+        for tag in old {
+            if let Some(ids) = self.tags.get_mut(&tag) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.tags.remove(&tag);
+                }
+            }
+        }
+    }
+
+    fn remove_hash(&mut self, id: FileId) {
+        let Some(old) = self.hashes.remove(&id) else {
+            return;
+        };
+        if let Some(ids) = self.by_hash.get_mut(&old) {
+            ids.remove(&id);
+            if ids.is_empty() {
+                self.by_hash.remove(&old);
+            }
+        }
+    }
+
+    pub(super) fn files(&self) -> &BTreeMap<FileId, BTreeSet<Tag>> {
+        &self.files
+    }
+
+    pub(super) fn tags(&self) -> &BTreeMap<Tag, BTreeSet<FileId>> {
+        &self.tags
+    }
+
+    pub(super) fn tags_of(&self, id: FileId) -> Option<&BTreeSet<Tag>> {
+        self.files.get(&id)
+    }
+
+    pub(super) fn hash_of(&self, id: FileId) -> Option<&Hash> {
+        self.hashes.get(&id)
+    }
+
+    /// Get the files whose content has a hash, in order
+    pub(super) fn with_hash(&self, hash: &Hash) -> impl Iterator<Item = FileId> + '_ {
+        self.by_hash
+            .get(hash)
+            .into_iter()
+            .flatten()
+            .copied()
+            .filter(move |id| self.files.contains_key(id))
+    }
+
+    /// Get the groups of files with the same content, each in order
+    pub(super) fn duplicates(&self) -> Vec<Vec<FileId>> {
+        let mut groups = self
+            .by_hash
+            .keys()
+            .map(|hash| self.with_hash(hash).collect::<Vec<_>>())
+            .filter(|ids| ids.len() > 1)
+            .collect::<Vec<_>>();
+        groups.sort_unstable();
+        groups
+    }
+
+    /// Load an index and replay the changes logged since it was saved, failing if either is
+    /// malformed in any way
+    pub(super) fn load(path: &Path) -> io::Result<Index> {
+        let mut index = Index::load_saved(path)?;
+        match File::open(log_path(path)) {
+            Ok(log) => index.replay(&mut BufReader::new(log))?,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => (),
+            Err(err) => return Err(err),
+        }
+        Ok(index)
+    }
+
+    fn load_saved(path: &Path) -> io::Result<Index> {
+        let mut input = BufReader::new(File::open(path)?);
+
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid index header",
+            ));
+        }
+
+        let mut index = Index::new();
+        index.generation = read_u64(&mut input)?;
+
+        let num_files = read_u64(&mut input)?;
+        for _ in 0..num_files {
+            index.files.insert(read_id(&mut input)?, BTreeSet::new());
+        }
+
+        let num_tags = read_u64(&mut input)?;
+        for _ in 0..num_tags {
+            let tag = read_tag(&mut input)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+            let num_ids = read_u64(&mut input)?;
+            let mut ids = BTreeSet::new();
+            for _ in 0..num_ids {
+                let id = read_id(&mut input)?;
+                index
+                    .files
+                    .get_mut(&id)
+                    .ok_or_else(|| {
+                        io::Error::new(io::ErrorKind::InvalidData, "Index references unknown file")
+                    })?
+                    .insert(tag.clone());
+                ids.insert(id);
+            }
+            index.tags.insert(tag, ids);
+        }
+
+        let num_hashes = read_u64(&mut input)?;
+        for _ in 0..num_hashes {
+            let id = read_id(&mut input)?;
+            if !index.files.contains_key(&id) {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Index references unknown file",
+                ));
+            }
+            let mut hash = checksum::MISSING;
+            input.read_exact(&mut hash)?;
+            index.set_hash(id, hash);
+        }
+
+        if input.read(&mut [0])? != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Trailing data in index",
+            ));
+        }
+
+        Ok(index)
+    }
+
+    /// Apply the changes in a log, unless it's from before the index was last saved
+    fn replay<R: Read>(&mut self, input: &mut R) -> io::Result<()> {
+        let mut magic = [0; 4];
+        input.read_exact(&mut magic)?;
+        if &magic != LOG_MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid index log header",
+            ));
+        }
+        if read_u64(input)? != self.generation {
+            return Ok(());
+        }
+
+        let mut kind = [0];
+        while input.read(&mut kind)? != 0 {
+            let id = read_id(input)?;
+            match kind[0] {
+                LOG_SET => {
+                    let mut tags = BTreeSet::new();
+                    for _ in 0..read_u64(input)? {
+                        tags.insert(read_tag(input)?.ok_or(io::ErrorKind::UnexpectedEof)?);
+                    }
+                    self.insert(id, tags);
+                    let mut known = [0];
+                    input.read_exact(&mut known)?;
+                    if known[0] != 0 {
+                        let mut hash = checksum::MISSING;
+                        input.read_exact(&mut hash)?;
+                        self.set_hash(id, hash);
+                    }
+                }
+                LOG_REMOVE => self.remove(id),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        "Invalid index log record",
+                    ))
+                }
+            }
+            self.logged += 1;
+        }
+        Ok(())
+    }
+
+    /// Save the index in full, as a new generation, and drop the log of changes to the last one
+    pub(super) fn save(&mut self, path: &Path, sync: bool) -> io::Result<()> {
+        let now = checksum::to_nanos(metadata::now());
+        self.generation = now.max(self.generation + 1);
+        atomic::write_with(path, sync, |out| self.write(out))?;
+        match fs::remove_file(log_path(path)) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+        self.logged = 0;
+        Ok(())
+    }
+
+    /// Persist the current tags and hash of a file after changing them, by appending them to the
+    /// log.
+    /// Once the log holds more changes than there are files, the index is saved in full instead.
+    pub(super) fn log(&mut self, path: &Path, sync: bool, id: FileId) -> io::Result<()> {
+        if self.logged >= MIN_LOG.max(self.files.len() as u64) {
+            return self.save(path, sync);
+        }
+
+        let mut record = Vec::new();
+        if let Some(tags) = self.files.get(&id) {
+            record.push(LOG_SET);
+            write_id(&mut record, id)?;
+            write_u64(&mut record, tags.len() as u64)?;
+            for tag in tags {
+                write_tag(&mut record, tag)?;
+            }
+            match self.hashes.get(&id) {
+                Some(hash) => {
+                    record.push(1);
+                    record.extend_from_slice(hash);
+                }
+                None => record.push(0),
+            }
+        } else {
+            record.push(LOG_REMOVE);
+            write_id(&mut record, id)?;
+        }
+
+        let path = log_path(path);
+        let mut log = if self.logged == 0 {
+            // Replaces any log left from an older generation
+            let mut log = File::create(&path)?;
+            log.write_all(LOG_MAGIC)?;
+            write_u64(&mut log, self.generation)?;
+            log
+        } else {
+            OpenOptions::new().append(true).open(&path)?
+        };
+        log.write_all(&record)?;
+        if sync {
+            log.sync_data()?;
+            if self.logged == 0 {
+                atomic::sync_dir(&path)?;
+            }
+        }
+        self.logged += 1;
+        Ok(())
+    }
+
+    fn write<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        out.write_all(MAGIC)?;
+        write_u64(out, self.generation)?;
+
+        write_u64(out, self.files.len() as u64)?;
+        for id in self.files.keys() {
+            write_id(out, *id)?;
+        }
+
+        write_u64(out, self.tags.len() as u64)?;
+        for (tag, ids) in &self.tags {
+            write_tag(out, tag)?;
+            write_u64(out, ids.len() as u64)?;
+            for id in ids {
+                write_id(out, *id)?;
+            }
+        }
+
+        // Hashes set for files still being added are logged along with the file's tags
+        let hashes = self
+            .hashes
+            .iter()
+            .filter(|(id, _)| self.files.contains_key(id))
+            .collect::<Vec<_>>();
+        write_u64(out, hashes.len() as u64)?;
+        for (id, hash) in hashes {
+            write_id(out, *id)?;
+            out.write_all(hash)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Every tag is in memory, so any atom can be looked up exactly by checking each distinct tag
+impl TagIndex for Index {
+    type Error = Infallible;
+
+    fn lookup(&self, atom: &TagPredicate) -> Result<Lookup, Infallible> {
+        let mut out = BTreeSet::new();
+        if let TagPredicate::Tag(tag) = atom {
+            if let Some(ids) = self.tags.get(tag) {
+                out.clone_from(ids);
+            }
+        } else {
+            for (tag, ids) in &self.tags {
+                if atom.match_tags([tag]) {
+                    out.extend(ids);
+                }
+            }
+        }
+        Ok(Lookup::Exact(out))
+    }
+
+    fn all_files(&self) -> Result<Option<BTreeSet<FileId>>, Infallible> {
+        Ok(Some(self.files.keys().copied().collect()))
+    }
+}
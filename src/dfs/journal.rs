@@ -0,0 +1,126 @@
+//! Undo journal, used to roll back transactions
+
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use super::atomic;
+use super::index::Index;
+use crate::events::Event;
+use crate::FileId;
+
+/// Records how to undo every change made during a transaction. Before a file is first changed,
+/// its current data, tag, and checksum files are copied into the journal directory. New files are instead
+/// recorded with an empty `.new` marker, so rolling back knows to delete them.
+pub(super) struct Journal {
+    dir: PathBuf,
+    ids: BTreeSet<FileId>,
+    index: Index,
+    /// Files written without being synced, to be synced once the transaction is committed
+    unsynced: BTreeSet<PathBuf>,
+    /// Changes made in the transaction, recorded in the change feed once it's committed
+    events: Vec<Event>,
+}
+
+impl Journal {
+    /// Start a new journal, in a directory which must not already exist
+    pub(super) fn create(dir: PathBuf, index: Index) -> io::Result<Journal> {
+        fs::create_dir(&dir)?;
+        Ok(Journal {
+            dir,
+            ids: BTreeSet::new(),
+            index,
+            unsynced: BTreeSet::new(),
+            events: Vec::new(),
+        })
+    }
+
+    /// The state of the index when this journal was started
+    pub(super) fn index(&self) -> &Index {
+        &self.index
+    }
+
+    /// Record a file, stored under the given name, before it's changed. Only the first change to a
+    /// file is recorded.
+    pub(super) fn record(&mut self, file: &Path, id: FileId, is_new: bool) -> io::Result<()> {
+        if !self.ids.insert(id) {
+            return Ok(());
+        }
+
+        let name = format!("{:016X}", id.into_u64_unchecked());
+        if is_new {
+            fs::write(self.dir.join(name).with_extension("new"), [])?;
+        } else {
+            for ext in ["dat", "tag", "sum"] {
+                let dest = self.dir.join(&name).with_extension(ext);
+                match fs::copy(file.with_extension(ext), dest) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => (),
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Record a file written without being synced, so it's synced when committing
+    pub(super) fn defer_sync(&mut self, file: &Path) {
+        if !self.unsynced.contains(file) {
+            self.unsynced.insert(file.to_owned());
+        }
+    }
+
+    /// Hold a change made in the transaction, to be recorded once it's committed
+    pub(super) fn defer_event(&mut self, event: Event) {
+        self.events.push(event);
+    }
+
+    /// Take the changes held so far, to be recorded before committing
+    pub(super) fn take_events(&mut self) -> Vec<Event> {
+        mem::take(&mut self.events)
+    }
+
+    /// Keep all recorded changes, discarding the journal. Files written without being synced are
+    /// synced first, so a crash can't lose part of a committed transaction.
+    pub(super) fn commit(self) -> io::Result<()> {
+        for file in &self.unsynced {
+            atomic::sync_file(file)?;
+        }
+        fs::remove_dir_all(&self.dir)
+    }
+}
+
+/// Undo every change recorded in a journal directory, then remove it. Works from the directory
+/// alone, so a journal left behind by a crash can also be rolled back. Files are found by the
+/// name they're stored under, without an extension.
+pub(super) fn rollback<F>(journal: &Path, file_name: F) -> io::Result<()>
+where
+    F: Fn(FileId) -> PathBuf,
+{
+    for item in fs::read_dir(journal)? {
+        let path = item?.path();
+        let (Some(stem), Some(ext)) = (path.file_stem(), path.extension()) else {
+            continue;
+        };
+        let Some(id) = stem
+            .to_str()
+            .and_then(|stem| u64::from_str_radix(stem, 16).ok())
+        else {
+            continue;
+        };
+        let file = file_name(FileId::from_u64_unchecked(id));
+
+        if ext == "new" {
+            for ext in ["dat", "tag", "sum"] {
+                match fs::remove_file(file.with_extension(ext)) {
+                    Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+                    _ => (),
+                }
+            }
+        } else {
+            fs::rename(&path, file.with_extension(ext))?;
+        }
+    }
+    fs::remove_dir_all(journal)
+}
@@ -0,0 +1,39 @@
+//! Per-file locks, so concurrent changes to the same file don't interleave
+
+use core::convert::TryFrom;
+use std::sync::{LockResult, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::FileId;
+
+/// How many locks files are spread across
+const SHARDS: usize = 64;
+
+/// A fixed set of locks, each shared by every file whose ID falls in its shard. Reading a file
+/// takes its lock shared, and changing it takes it exclusively, so a change to a file is never
+/// seen half done. Only one file's lock may be held at a time, as two files can share a lock.
+pub(super) struct FileLocks {
+    shards: [RwLock<()>; SHARDS],
+}
+
+impl FileLocks {
+    pub(super) fn new() -> FileLocks {
+        FileLocks {
+            shards: std::array::from_fn(|_| RwLock::new(())),
+        }
+    }
+
+    fn shard(&self, id: FileId) -> &RwLock<()> {
+        let idx = id.into_u64_unchecked() % SHARDS as u64;
+        &self.shards[usize::try_from(idx).unwrap_or_default()]
+    }
+
+    /// Lock a file for reading
+    pub(super) fn read(&self, id: FileId) -> LockResult<RwLockReadGuard<'_, ()>> {
+        self.shard(id).read()
+    }
+
+    /// Lock a file for changing
+    pub(super) fn write(&self, id: FileId) -> LockResult<RwLockWriteGuard<'_, ()>> {
+        self.shard(id).write()
+    }
+}
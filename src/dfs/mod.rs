@@ -0,0 +1,2361 @@
+//! Existing file-system backed implementation of a TBF
+
+mod atomic;
+mod builder;
+mod checksum;
+mod compress;
+mod feed;
+mod format;
+mod index;
+mod journal;
+mod locks;
+mod shard;
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{self, Write as _};
+use std::fs::File;
+use std::io::{BufReader, Read, Seek, SeekFrom, Write};
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::{fs, io};
+
+#[cfg(feature = "mmap")]
+use memmap2::{Mmap, MmapOptions};
+
+pub use builder::{Builder, ConfigError, IndexMode, SyncPolicy};
+pub use checksum::CorruptionReport;
+pub use compress::{Compression, Reader};
+pub use shard::Sharding;
+
+use checksum::{Content, Hash, Sums};
+use feed::Feed;
+use index::Index;
+use journal::Journal;
+use locks::FileLocks;
+
+use super::{check_stored, FileId, FileInfo, FileSystemRead, FileSystemWrite};
+use crate::codec;
+use crate::error::ErrorKind;
+use crate::events::{Change, Event, Subscribers};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::{self, hash_data};
+use crate::provider::{provide_tags, Providers};
+use crate::search::{self, SearchOptions, SortBy, SortKey};
+use crate::trace;
+use crate::tree::{self, ImportOptions, Layout};
+use crate::{
+    FileWriter, Group, InferredTags, Lookup, Metadata, ProvidedTags, QueryPlan, Retention,
+    SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer, TagPattern, TagPredicate, TagProvider,
+};
+
+/// Error for a directory-backed filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// A file wasn't found
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// A file's data or tags don't match their checksums, found while verifying reads
+    Corrupted(FileId),
+    /// The directory holds a store in a newer format version than this version supports
+    UnsupportedFormat(u32),
+    /// The filesystem was opened read-only, so can't be changed
+    ReadOnly,
+    /// The filesystem was configured wrongly for the directory it was opened in
+    Config(ConfigError),
+    /// No snapshot was saved under the given label
+    SnapshotNotFound(String),
+    /// A thread panic poisoned the state
+    Poisoned,
+    /// An I/O error occured
+    IoError(io::Error),
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Error {
+        Error::Poisoned
+    }
+}
+
+impl From<ConfigError> for Error {
+    fn from(err: ConfigError) -> Error {
+        Error::Config(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl crate::error::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Self::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Self::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Self::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Self::IoError(io::ErrorKind::OutOfMemory.into())
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Self::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Self::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Self::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Self::IoError(e) => ErrorKind::Source(e),
+            Self::Corrupted(_) | Self::UnsupportedFormat(_) | Self::Poisoned => ErrorKind::State,
+            Self::ReadOnly => ErrorKind::ReadOnly,
+            Self::Config(_) | Self::SnapshotNotFound(_) => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::Corrupted(id) => write!(f, "file {id} doesn't match its checksums"),
+            Error::UnsupportedFormat(version) => {
+                write!(f, "store format version {version} isn't supported")
+            }
+            Error::ReadOnly => write!(f, "filesystem was opened read-only"),
+            Error::Config(err) => write!(f, "{err}"),
+            Error::SnapshotNotFound(label) => write!(f, "no snapshot saved as `{label}`"),
+            Error::Poisoned => write!(f, "filesystem was poisoned by a thread panic"),
+            Error::IoError(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+struct SavedState {
+    cur_id: u64,
+    /// IDs of removed files, which may be handed out again
+    free: BTreeSet<FileId>,
+}
+
+impl Default for SavedState {
+    fn default() -> SavedState {
+        SavedState {
+            cur_id: 256,
+            free: BTreeSet::new(),
+        }
+    }
+}
+
+impl SavedState {
+    fn from_path(path: &Path) -> Result<SavedState, Error> {
+        match File::open(path) {
+            Ok(file) => match SavedState::read(BufReader::new(file)) {
+                Ok(state) => Ok(state),
+                // A state file cut short by a crash is recovered by scanning the directory
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof => Ok(SavedState::default()),
+                Err(err) => Err(err.into()),
+            },
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(SavedState::default()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn read<R: Read>(mut input: R) -> io::Result<SavedState> {
+        let cur_id = codec::read_u64(&mut input)?;
+
+        // Older state files end after the counter, with no free list
+        let mut free = BTreeSet::new();
+        let mut rest = Vec::new();
+        input.read_to_end(&mut rest)?;
+        if !rest.is_empty() {
+            let mut rest = &rest[..];
+            for _ in 0..codec::read_u64(&mut rest)? {
+                free.insert(codec::read_id(&mut rest)?);
+            }
+        }
+
+        Ok(SavedState { cur_id, free })
+    }
+
+    fn save(&self, path: &Path, sync: bool) -> Result<(), Error> {
+        atomic::write_with(path, sync, |file| {
+            codec::write_u64(file, self.cur_id)?;
+            codec::write_u64(file, self.free.len() as u64)?;
+            for id in &self.free {
+                codec::write_id(file, *id)?;
+            }
+            Ok(())
+        })?;
+        Ok(())
+    }
+}
+
+/// Get the ID of a file stored in the directory from its name, if it's one of a file's
+fn parse_id(file_name: &str) -> Option<FileId> {
+    let (id, _) = file_name.split_once('.')?;
+    if id.len() != 16 {
+        return None;
+    }
+    u64::from_str_radix(id, 16)
+        .ok()
+        .map(FileId::from_u64_unchecked)
+}
+
+/// Hard link a file to a new path, or copy it if the filesystem doesn't support links. Files
+/// which don't exist are skipped.
+fn link_or_copy(from: &Path, to: &Path) -> io::Result<()> {
+    if fs::hard_link(from, to).is_err() {
+        match fs::copy(from, to) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err),
+            _ => (),
+        }
+    }
+    Ok(())
+}
+
+fn tree_error(err: tree::Error<Error>) -> Error {
+    match err {
+        tree::Error::Io(err) => Error::IoError(err),
+        tree::Error::Fs(err) => err,
+    }
+}
+
+/// A directory-backed implementation of a tag-based filesystem. Given a directory on a standard
+/// filesystem, will persist all data there. Opened with [`DirectoryBackedFs::new`], or
+/// [`DirectoryBackedFs::builder`] for more options.
+///
+/// Searches are served from an index of every file's tags, kept in `tbf.idx`. Changes to it are
+/// appended to `tbf.idx.log`, and only saved in full once enough have built up. If the index is
+/// missing or corrupt when the filesystem is opened, it's rebuilt from the individual tag files.
+///
+/// By default, file IDs are never reused. IDs of removed files are still recorded, so reuse can
+/// be turned on later with [`DirectoryBackedFs::reuse_ids`]. The ID counter is saved in
+/// `tbf.dat`; if it's lost or out of date when the filesystem is opened, it's recovered from the
+/// files in the directory, so an ID is never handed out twice.
+///
+/// Each file is locked while it's read or changed, so threads sharing a filesystem never see a
+/// change to a file half done, or lose one made at the same time. Locks are only held within a
+/// single call, and don't extend to other processes using the same directory.
+///
+/// Prior versions of file data aren't kept unless turned on with
+/// [`DirectoryBackedFs::retention`], in which case they're stored beside the file as `ID.vN.dat`.
+///
+/// Data is stored uncompressed unless set otherwise with [`DirectoryBackedFs::compression`].
+/// Compressed data is decompressed transparently when read, so files stored with different
+/// compression can be mixed freely.
+///
+/// Checksums of each file's data and tags are kept in `ID.sum`, and can be checked with
+/// [`DirectoryBackedFs::verify`], or on every read with [`DirectoryBackedFs::verify_reads`].
+///
+/// Files are stored directly in the directory unless sharded into subdirectories with
+/// [`DirectoryBackedFs::reshard`], which is worth doing for large stores as many filesystems slow
+/// down with too many files in one directory.
+///
+/// Snapshots of the whole store can be saved with [`DirectoryBackedFs::snapshot`] and put back
+/// with [`DirectoryBackedFs::restore`]. They're kept in `tbf.snapshots`, one directory each.
+///
+/// Every change made is numbered and recorded in `tbf.changes`, so the changes since any point
+/// can be read back with [`DirectoryBackedFs::changes_since`], even after the store is reopened.
+///
+/// The directory's format version is kept in `tbf.fmt`. Stores written by older versions of this
+/// crate are migrated when opened, and ones written by newer versions are refused.
+pub struct DirectoryBackedFs {
+    dir: PathBuf,
+    sharding: Sharding,
+    read_only: bool,
+    sync: SyncPolicy,
+    reuse_ids: bool,
+    retention: Retention,
+    compression: Compression,
+    verify_reads: bool,
+    state: RwLock<SavedState>,
+    index: RwLock<Index>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    journal: Mutex<Option<Journal>>,
+    locks: FileLocks,
+    feed: Mutex<Feed>,
+    subscribers: Subscribers,
+}
+
+impl DirectoryBackedFs {
+    /// The format version of stores written by this version of the crate
+    pub const FORMAT_VERSION: u32 = format::VERSION;
+
+    /// Create or load a directory-backed filesystem, in the provided directory.
+    pub fn new<P: AsRef<Path>>(dir: P) -> Result<DirectoryBackedFs, Error> {
+        DirectoryBackedFs::builder().open(dir)
+    }
+
+    /// Start configuring how to open a directory-backed filesystem, with options not all of
+    /// which can be changed once it's open
+    pub fn builder() -> Builder {
+        Builder::default()
+    }
+
+    /// Move every file into the subdirectories of a new sharding scheme. The scheme is saved with
+    /// the store, so it's kept whenever the store is opened again. If moving files is
+    /// interrupted, it's finished next time the store is opened.
+    pub fn reshard(&mut self, sharding: Sharding) -> Result<(), Error> {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        if let Sharding::Nested(levels) = sharding {
+            if levels > shard::MAX_LEVELS {
+                return Err(ConfigError::TooManyShardLevels(levels).into());
+            }
+        }
+        let sharding = Sharding::from_levels(sharding.levels());
+        if sharding == self.sharding {
+            return Ok(());
+        }
+
+        self.sharding = sharding;
+        format::save(&self.dir, sharding, true)?;
+        self.gather_files()?;
+        format::save(&self.dir, sharding, false)?;
+        Ok(())
+    }
+
+    /// Set whether IDs of removed files are handed out again to new files, lowest first. When
+    /// off, new files always get a never before used ID.
+    ///
+    /// A reused ID is lower than the ID counter, so [`last_id`](FileSystemRead::last_id) doesn't
+    /// change when one is handed out, and [`ids_after`](FileSystemRead::ids_after) won't report
+    /// the new file to callers which already saw a higher ID. Watch for new files with
+    /// [`subscribe`](FileSystemRead::subscribe) or [`changes_since`](Self::changes_since)
+    /// instead when reuse is on.
+    #[must_use]
+    pub fn reuse_ids(mut self, reuse: bool) -> Self {
+        self.reuse_ids = reuse;
+        self
+    }
+
+    /// Set how many prior versions of file data are kept when files are edited
+    #[must_use]
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Set how the data of files is compressed when it's written. Files already stored keep
+    /// their compression until they're edited or [recompressed](Self::recompress).
+    #[must_use]
+    pub fn compression(mut self, compression: Compression) -> Self {
+        self.compression = compression;
+        self
+    }
+
+    /// Set whether a file's checksums are checked every time its data is read, failing with
+    /// [`Error::Corrupted`] if they don't match. This reads the data twice, and reading only part
+    /// of a file with [`read_range`](FileSystemRead::read_range) still checks all of it.
+    #[must_use]
+    pub fn verify_reads(mut self, verify: bool) -> Self {
+        self.verify_reads = verify;
+        self
+    }
+
+    /// Check a file's data and tag files against their checksums, returning any problems found.
+    /// Files written before checksums were kept are only checked for missing or unreadable files.
+    pub fn verify(&self, id: FileId) -> Result<Vec<CorruptionReport>, Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+
+        let mut out = Vec::new();
+        let sums = Sums::load(&self.file_name(id).with_extension("sum"))?;
+        let data = checksum::hash_file(&self.file_name(id).with_extension("dat"))?;
+        let tags = checksum::hash_file(&self.file_name(id).with_extension("tag"))?;
+
+        if data == checksum::MISSING {
+            out.push(CorruptionReport::DataMissing(id));
+        } else if sums.is_some_and(|sums| sums.data != data) {
+            out.push(CorruptionReport::DataChanged(id));
+        }
+
+        if tags == checksum::MISSING {
+            out.push(CorruptionReport::TagsMissing(id));
+        } else if sums.is_some_and(|sums| sums.tags != tags) {
+            out.push(CorruptionReport::TagsChanged(id));
+        } else if self.read_tags(id).is_err() {
+            out.push(CorruptionReport::TagsUnreadable(id));
+        }
+        Ok(out)
+    }
+
+    /// Check every file against its checksums, returning all problems found. See
+    /// [`DirectoryBackedFs::verify`].
+    pub fn verify_all(&self) -> Result<Vec<CorruptionReport>, Error> {
+        let ids = self
+            .index
+            .read()?
+            .files()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        let mut out = Vec::new();
+        for id in ids {
+            out.extend(self.verify(id)?);
+        }
+        Ok(out)
+    }
+
+    /// Check the directory for files left inconsistent, such as by a crash or by editing it
+    /// directly, returning every problem found. If `repair` is set, problems are also fixed:
+    ///
+    /// - Data without a tag file is kept, with no tags
+    /// - Tags without a data file are removed, as there's nothing left to tag
+    /// - Tag files which can't be decoded are cut down to the tags which can be
+    /// - IDs past the saved counter are marked as used
+    /// - Leftover versions and checksums of removed files are removed
+    ///
+    /// The index is rebuilt once repairs are done. Checksums aren't checked, see
+    /// [`DirectoryBackedFs::verify_all`] for that.
+    pub fn check_and_repair(&self, repair: bool) -> Result<Vec<CorruptionReport>, Error> {
+        self.assert_dir()?;
+        if repair {
+            self.assert_writable()?;
+        }
+        let cur_id = self.state.read()?.cur_id;
+        let mut ids = self.scan_all_ids()?;
+        ids.extend(self.index.read()?.files().keys());
+
+        let mut out = Vec::new();
+        for id in ids {
+            let _lock = self.locks.write(id)?;
+            if id.into_u64_unchecked() >= cur_id {
+                out.push(CorruptionReport::IdNotAllocated(id));
+            }
+
+            let dat = self.file_name(id).with_extension("dat");
+            let tag = self.file_name(id).with_extension("tag");
+            match (dat.exists(), tag.exists()) {
+                (true, true) => {
+                    let (tags, complete) = self.read_tags_lossy(id)?;
+                    if !complete {
+                        out.push(CorruptionReport::TagsUnreadable(id));
+                        if repair {
+                            self.write_tags(id, tags)?;
+                        }
+                    }
+                }
+                (true, false) => {
+                    out.push(CorruptionReport::TagsMissing(id));
+                    if repair {
+                        self.write_tags(id, [])?;
+                    }
+                }
+                (false, true) => {
+                    out.push(CorruptionReport::DataMissing(id));
+                    if repair {
+                        self.remove_leftovers(id)?;
+                    }
+                }
+                (false, false) => {
+                    if self.index.read()?.tags_of(id).is_some() {
+                        out.push(CorruptionReport::DataMissing(id));
+                        out.push(CorruptionReport::TagsMissing(id));
+                    } else {
+                        out.push(CorruptionReport::Leftover(id));
+                    }
+                    if repair {
+                        self.remove_leftovers(id)?;
+                    }
+                }
+            }
+        }
+
+        if repair {
+            self.rebuild_index()?;
+            self.recover_state()?;
+        }
+        Ok(out)
+    }
+
+    /// Import every regular file under a directory, tagging them by the directories they're in
+    /// and their extension. See [`tree::import_tree`] for details.
+    pub fn import_tree<P: AsRef<Path>>(
+        &self,
+        path: P,
+        options: &ImportOptions,
+    ) -> Result<Vec<(PathBuf, FileId)>, Error> {
+        tree::import_tree(self, path, options).map_err(tree_error)
+    }
+
+    /// Export every file matching a pattern into a directory, in the given layout. See
+    /// [`tree::export_tree`] for details.
+    pub fn export_tree<P, D>(&self, pattern: P, dest: D, layout: Layout) -> Result<usize, Error>
+    where
+        P: TagPattern,
+        D: AsRef<Path>,
+    {
+        tree::export_tree(self, pattern, dest, layout).map_err(tree_error)
+    }
+
+    /// Map an existing file's data into memory, rather than copying it out. Data files are only
+    /// ever replaced whole, never changed in place, so the map keeps showing the data as it was
+    /// when mapped even if the file is later edited or removed. Fails if the file's data is
+    /// stored compressed.
+    #[cfg(feature = "mmap")]
+    pub fn get_data_mmap(&self, id: FileId) -> Result<Mmap, Error> {
+        use std::io::Seek;
+
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        let (mut file, compression) = compress::open(&self.file_name(id).with_extension("dat"))?;
+        if compression != Compression::None {
+            return Err(Error::IoError(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "Compressed file data can't be mapped",
+            )));
+        }
+        let offset = file.stream_position()?;
+        // SAFETY: Data files are written to a temporary file and renamed into place, so the
+        //         mapped file is never modified, short of another program changing it directly.
+        Ok(unsafe { MmapOptions::new().offset(offset).map(&file)? })
+    }
+
+    /// Rewrite the data of a file with the given compression, whatever the filesystem is set to
+    /// use. Data which doesn't get any smaller when compressed is stored as-is.
+    pub fn recompress_file(&self, id: FileId, compression: Compression) -> Result<(), Error> {
+        let _lock = self.locks.write(id)?;
+        self.assert_dir()?;
+        self.assert_writable()?;
+        self.assert_file_exists(id)?;
+        let path = self.file_name(id).with_extension("dat");
+        let data = compress::read(&path)?;
+        let stored = compress::encode(&data, compression)?;
+        self.write_file(&path, &stored)?;
+        self.update_sums(id, Some(hash_data(&stored)), Some(&data), None)
+    }
+
+    /// Rewrite the data of every file stored with a different compression to the one the
+    /// filesystem is set to use, such as after changing it. Prior versions are left as they are.
+    pub fn recompress(&self) -> Result<(), Error> {
+        let ids = self
+            .index
+            .read()?
+            .files()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for id in ids {
+            let (_, compression) = compress::open(&self.file_name(id).with_extension("dat"))?;
+            if compression != self.compression {
+                self.recompress_file(id, self.compression)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Rebuild the tag index from scratch, by reading every tag file in the directory. This
+    /// happens automatically if the index is found to be corrupt when opening the filesystem.
+    pub fn rebuild_index(&self) -> Result<(), Error> {
+        self.assert_writable()?;
+        let mut index = self.read_index()?;
+        index.save(&self.index_path(), self.sync_index()?)?;
+        *self.index.write()? = index;
+        Ok(())
+    }
+
+    /// Reclaim disk space left behind by edited and removed files. Prior versions beyond the
+    /// retention policy, such as after lowering it, are removed, as are versions left behind by
+    /// files which no longer exist, and IDs of live files are taken out of the list of IDs free to
+    /// reuse. Versions of live files are left alone when retention is off, as they may have been
+    /// kept by the filesystem when opened with it on. Returns how many bytes of files were
+    /// removed.
+    ///
+    /// Unlike [`DirectoryBackedFs::check_and_repair`], this only removes files which can't be in
+    /// use, so is safe to run while the filesystem is being changed.
+    pub fn compact(&self) -> Result<u64, Error> {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        let mut freed = 0;
+        for id in self.scan_all_ids()? {
+            let _lock = self.locks.write(id)?;
+            let versions = self.scan_versions(id)?;
+            let excess = if self.index.read()?.tags_of(id).is_none() {
+                // Versions are only kept when a file is edited, so never belong to one still
+                // being added
+                versions.len()
+            } else if self.retention.is_enabled() {
+                self.retention.excess(versions.len())
+            } else {
+                0
+            };
+            for version in &versions[..excess] {
+                let path = self.version_name(id, *version);
+                freed += fs::metadata(&path)?.len();
+                fs::remove_file(path)?;
+            }
+        }
+        self.recover_state()?;
+        Ok(freed)
+    }
+
+    /// Save every file, version and special file under a label, to be put back later with
+    /// [`DirectoryBackedFs::restore`]. Stored files are only ever replaced whole, never changed
+    /// in place, so they're hard linked into the snapshot rather than copied where the
+    /// filesystem supports it, and a snapshot takes little space until files change. Saving
+    /// under a label already in use replaces what was saved there.
+    ///
+    /// Labels must be usable as a file name.
+    pub fn snapshot(&self, label: &str) -> Result<(), Error> {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        let dest = self.snapshot_path(label)?;
+        match fs::remove_dir_all(&dest) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+        fs::create_dir_all(&dest)?;
+
+        let ids = self
+            .index
+            .read()?
+            .files()
+            .keys()
+            .copied()
+            .collect::<Vec<_>>();
+        for id in ids {
+            let _lock = self.locks.read(id)?;
+            let (file, saved) = (
+                self.file_name(id),
+                dest.join(format!("{:016X}", id.into_u64_unchecked())),
+            );
+            for ext in ["dat", "tag", "sum"] {
+                link_or_copy(&file.with_extension(ext), &saved.with_extension(ext))?;
+            }
+            for version in self.scan_versions(id)? {
+                let ext = format!("v{version}.dat");
+                link_or_copy(&file.with_extension(&ext), &saved.with_extension(&ext))?;
+            }
+        }
+        for path in self.stored_special_paths() {
+            if let Some(name) = path.file_name() {
+                link_or_copy(&path, &dest.join(name))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Put every file, version and special file back as it was when saved under a label. The
+    /// snapshot is kept, so can be restored again. IDs handed out since it was saved still aren't
+    /// handed out again, and subscribers aren't told of the files changed.
+    ///
+    /// Files are only locked one at a time, so the filesystem shouldn't be changed by other
+    /// threads until this is done, or their changes may be mixed in with the snapshot.
+    pub fn restore(&self, label: &str) -> Result<(), Error> {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        let src = self.snapshot_path(label)?;
+        if !src.is_dir() {
+            return Err(Error::SnapshotNotFound(label.to_owned()));
+        }
+
+        for id in self.scan_all_ids()? {
+            let _lock = self.locks.write(id)?;
+            self.remove_leftovers(id)?;
+        }
+        for path in self.stored_special_paths() {
+            match fs::remove_file(path) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+
+        for item in fs::read_dir(&src)? {
+            let item = item?;
+            let name = item.file_name();
+            let dest = match name.to_str().and_then(parse_id) {
+                Some(id) => {
+                    let dir = self.sharding.file_dir(&self.dir, id);
+                    fs::create_dir_all(&dir)?;
+                    dir.join(&name)
+                }
+                None => self.dir.join(&name),
+            };
+            link_or_copy(&item.path(), &dest)?;
+        }
+
+        self.rebuild_index()?;
+        self.recover_state()
+    }
+
+    /// List the labels of every saved snapshot, in order
+    pub fn list_snapshots(&self) -> Result<Vec<String>, Error> {
+        self.assert_dir()?;
+        let items = match fs::read_dir(self.snapshots_path()) {
+            Ok(items) => items,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut out = Vec::new();
+        for item in items {
+            let item = item?;
+            if item.file_type()?.is_dir() {
+                out.extend(item.file_name().to_str().map(str::to_owned));
+            }
+        }
+        out.sort_unstable();
+        Ok(out)
+    }
+
+    /// Remove the snapshot saved under a label, freeing the space of any files only it kept.
+    /// Returns whether there was one.
+    pub fn remove_snapshot(&self, label: &str) -> Result<bool, Error> {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        match fs::remove_dir_all(self.snapshot_path(label)?) {
+            Ok(()) => Ok(true),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(false),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Get the sequence number of the last change made to the store, or 0 if none have been
+    pub fn last_seq(&self) -> Result<u64, Error> {
+        Ok(self.feed.lock()?.last_seq())
+    }
+
+    /// Get every change made to the store after the one with a sequence number, oldest first.
+    /// Changes made in a transaction are recorded once it commits, so ones undone by a failed
+    /// transaction never are, while restoring a snapshot isn't recorded at all.
+    pub fn changes_since(&self, seq: u64) -> Result<Vec<Change>, Error> {
+        self.assert_dir()?;
+        Ok(self.feed.lock()?.since(seq)?)
+    }
+
+    /// Build an index from every tag file in the directory
+    #[cfg(not(feature = "parallel"))]
+    fn read_index(&self) -> Result<Index, Error> {
+        let mut index = Index::new();
+        for id in self.scan_ids()? {
+            index.insert(id, self.read_tags(id)?);
+            if let Some(hash) = self.content_hash(id)? {
+                index.set_hash(id, hash);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Build an index from every tag file in the directory, reading them across threads
+    #[cfg(feature = "parallel")]
+    fn read_index(&self) -> Result<Index, Error> {
+        use rayon::prelude::*;
+
+        let tags = self
+            .scan_ids()?
+            .into_par_iter()
+            .map(|id| Ok((id, self.read_tags(id)?, self.content_hash(id)?)))
+            .collect::<Result<Vec<_>, Error>>()?;
+
+        let mut index = Index::new();
+        for (id, tags, hash) in tags {
+            index.insert(id, tags);
+            if let Some(hash) = hash {
+                index.set_hash(id, hash);
+            }
+        }
+        Ok(index)
+    }
+
+    /// Find every file matching a predicate, checking files across threads. Files are only read
+    /// when tag providers are set, otherwise the index answers the search on its own.
+    #[cfg(feature = "parallel")]
+    fn search_parallel(&self, pred: &TagPredicate) -> Result<Vec<FileId>, Error> {
+        use rayon::prelude::*;
+
+        self.assert_dir()?;
+        let providers = self.providers.read()?;
+        let index = self.index.read()?;
+
+        let ids = if providers.is_empty() {
+            match QueryPlan::new(pred).execute(&*index) {
+                Ok(Lookup::Exact(ids)) => return Ok(ids.into_iter().collect()),
+                Ok(Lookup::Superset(ids)) => ids.into_iter().collect(),
+                Ok(Lookup::Unknown) => index.files().keys().copied().collect(),
+                Err(never) => match never {},
+            }
+        } else {
+            index.files().keys().copied().collect::<Vec<_>>()
+        };
+
+        ids.into_par_iter()
+            .filter_map(|id| {
+                let file_tags = index.tags_of(id)?;
+                let matched = if providers.is_empty() {
+                    Ok(pred.match_tags(file_tags))
+                } else {
+                    compress::read(&self.file_name(id).with_extension("dat")).map(|data| {
+                        pred.match_tags(file_tags.iter().chain(&provide_tags(&providers, &data)))
+                    })
+                };
+                match matched {
+                    Ok(true) => Some(Ok(id)),
+                    Ok(false) => None,
+                    Err(err) => Some(Err(err.into())),
+                }
+            })
+            .collect()
+    }
+
+    /// Find exactly which files match a predicate from the index alone, or `None` if some files
+    /// would have to be checked, such as when tag providers are set
+    fn exact_matches(&self, pred: &TagPredicate) -> Result<Option<BTreeSet<FileId>>, Error> {
+        self.assert_dir()?;
+        if !self.providers.read()?.is_empty() {
+            return Ok(None);
+        }
+        match QueryPlan::new(pred).execute(&*self.index.read()?) {
+            Ok(Lookup::Exact(ids)) => Ok(Some(ids)),
+            Ok(_) => Ok(None),
+            Err(never) => match never {},
+        }
+    }
+
+    fn assert_dir(&self) -> Result<(), Error> {
+        if self.dir.is_dir() {
+            Ok(())
+        } else {
+            Err(Error::IoError(io::Error::other(
+                "Provided path is not a directory",
+            )))
+        }
+    }
+
+    fn assert_writable(&self) -> Result<(), Error> {
+        if self.read_only {
+            Err(Error::ReadOnly)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Atomically write a file, syncing it if the sync policy asks for it
+    fn write_file(&self, path: &Path, data: &[u8]) -> Result<(), Error> {
+        atomic::write(path, self.sync_now(path)?, data)?;
+        Ok(())
+    }
+
+    /// Whether a file about to be written should be synced before the change returns. Under
+    /// [`SyncPolicy::OnBatch`], files written during a transaction are instead synced once it's
+    /// committed. This takes the journal lock, so mustn't be called while holding the index or
+    /// state locks.
+    fn sync_now(&self, path: &Path) -> Result<bool, Error> {
+        Ok(match self.sync {
+            SyncPolicy::Relaxed => false,
+            SyncPolicy::Always => true,
+            SyncPolicy::OnBatch => match &mut *self.journal.lock()? {
+                Some(journal) => {
+                    journal.defer_sync(path);
+                    false
+                }
+                None => true,
+            },
+        })
+    }
+
+    /// Whether a change to the index should be synced before it returns, as for
+    /// [`sync_now`](Self::sync_now). Changes go to either the index or the log beside it.
+    fn sync_index(&self) -> Result<bool, Error> {
+        self.sync_now(&index::log_path(&self.index_path()))?;
+        self.sync_now(&self.index_path())
+    }
+
+    fn alloc_id(&self) -> Result<FileId, Error> {
+        let sync = self.sync_now(&self.state_path())?;
+        let mut state = self.state.write()?;
+        if self.reuse_ids {
+            let index = self.index.read()?;
+            // A rolled back removal can leave a live file's ID in the free list
+            while let Some(id) = state.free.pop_first() {
+                if index.tags_of(id).is_none() {
+                    state.save(&self.state_path(), sync)?;
+                    return Ok(id);
+                }
+            }
+        }
+
+        let id = FileId::from_u64_unchecked(state.cur_id);
+        state.cur_id += 1;
+        state.save(&self.state_path(), sync)?;
+        Ok(id)
+    }
+
+    /// Bring the saved state in line with the files on disk, in case it was lost or is older
+    /// than them. The counter is moved past every ID in the directory, and live files are taken
+    /// out of the free list, so no ID is ever handed out twice.
+    fn recover_state(&self) -> Result<(), Error> {
+        let sync = self.sync_now(&self.state_path())?;
+        let mut state = self.state.write()?;
+        let mut changed = false;
+
+        if let Some(max) = self.scan_all_ids()?.last() {
+            if state.cur_id <= max.into_u64_unchecked() {
+                state.cur_id = max.into_u64_unchecked() + 1;
+                changed = true;
+            }
+        }
+
+        let index = self.index.read()?;
+        let free = state.free.len();
+        state.free.retain(|&id| index.tags_of(id).is_none());
+        changed |= state.free.len() != free;
+
+        if changed {
+            state.save(&self.state_path(), sync)?;
+        }
+        Ok(())
+    }
+
+    /// Bring a store from an older format version up to the current one
+    fn migrate(&self, version: u32) -> Result<(), Error> {
+        // Version 0 stores may predate checksums, and those before version 3 don't record the
+        // content of files alongside them, both of which are filled in from the files as stored
+        if version < 3 {
+            for id in self.scan_ids()? {
+                self.update_sums(id, None, None, None)?;
+            }
+        }
+
+        format::save(&self.dir, self.sharding, false)?;
+        Ok(())
+    }
+
+    /// Move every file found in any shard into the one it belongs in, then remove any shards
+    /// left empty
+    fn gather_files(&self) -> Result<(), Error> {
+        let dirs = shard::all_dirs(&self.dir)?;
+        for dir in &dirs {
+            for item in fs::read_dir(dir)? {
+                let item = item?;
+                let Some(id) = item.file_name().to_str().and_then(parse_id) else {
+                    continue;
+                };
+
+                let dest = self.sharding.file_dir(&self.dir, id);
+                if dest != *dir {
+                    fs::create_dir_all(&dest)?;
+                    fs::rename(item.path(), dest.join(item.file_name()))?;
+                }
+            }
+        }
+
+        for dir in dirs.iter().skip(1).rev() {
+            if fs::read_dir(dir)?.next().is_none() {
+                fs::remove_dir(dir)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn free_id(&self, id: FileId) -> Result<(), Error> {
+        let sync = self.sync_now(&self.state_path())?;
+        let mut state = self.state.write()?;
+        state.free.insert(id);
+        state.save(&self.state_path(), sync)
+    }
+
+    fn special_path(&self, file: SpecialFile) -> PathBuf {
+        match file {
+            SpecialFile::Config => self.dir.join("tbf.cfg"),
+            _ => self.dir.join(format!("tbf.{}", file.name())),
+        }
+    }
+
+    /// The paths of every special file which is stored rather than generated
+    fn stored_special_paths(&self) -> Vec<PathBuf> {
+        SpecialFile::ALL
+            .iter()
+            .filter(|file| !file.is_generated())
+            .map(|&file| self.special_path(file))
+            .collect()
+    }
+
+    fn snapshots_path(&self) -> PathBuf {
+        self.dir.join("tbf.snapshots")
+    }
+
+    /// The directory of the snapshot with a label, failing if the label isn't a file name
+    fn snapshot_path(&self, label: &str) -> Result<PathBuf, Error> {
+        if label.is_empty() || label == "." || label == ".." || label.contains(['/', '\\']) {
+            return Err(Error::IoError(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "Snapshot labels must be usable as a file name",
+            )));
+        }
+        Ok(self.snapshots_path().join(label))
+    }
+
+    fn state_path(&self) -> PathBuf {
+        self.dir.join("tbf.dat")
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("tbf.idx")
+    }
+
+    fn journal_path(&self) -> PathBuf {
+        self.dir.join("tbf.journal")
+    }
+
+    fn feed_path(&self) -> PathBuf {
+        self.dir.join("tbf.changes")
+    }
+
+    /// Record a change in the change feed, then tell subscribers of it. Changes made in a
+    /// transaction are held in its journal until it commits.
+    fn emit(&self, event: Event) -> Result<(), Error> {
+        if let Some(journal) = &mut *self.journal.lock()? {
+            journal.defer_event(event);
+            return Ok(());
+        }
+        self.record_changes(&[event])?;
+        self.subscribers.emit(event);
+        Ok(())
+    }
+
+    /// Record changes in the change feed, without telling subscribers of them
+    fn record_changes(&self, events: &[Event]) -> Result<(), Error> {
+        let sync = self.sync_now(&self.feed_path())?;
+        let mut feed = self.feed.lock()?;
+        for &event in events {
+            feed.append(event, sync)?;
+        }
+        Ok(())
+    }
+
+    /// Record a file in the active transaction, if there is one, before it's changed
+    fn journal(&self, id: FileId, is_new: bool) -> Result<(), Error> {
+        if let Some(journal) = &mut *self.journal.lock()? {
+            journal.record(&self.file_name(id), id, is_new)?;
+        }
+        Ok(())
+    }
+
+    fn assert_file_exists(&self, id: FileId) -> Result<(), Error> {
+        if self.index.read()?.tags_of(id).is_some() {
+            Ok(())
+        } else {
+            Err(Error::FileNotFound(id))
+        }
+    }
+
+    fn file_name(&self, id: FileId) -> PathBuf {
+        self.sharding
+            .file_dir(&self.dir, id)
+            .join(format!("{:016X}", id.into_u64_unchecked()))
+    }
+
+    fn version_name(&self, id: FileId, version: u32) -> PathBuf {
+        self.file_name(id).with_extension(format!("v{version}.dat"))
+    }
+
+    /// Find the numbers of all prior versions of a file kept in the directory, oldest first
+    fn scan_versions(&self, id: FileId) -> Result<Vec<u32>, Error> {
+        let prefix = format!("{:016X}.v", id.into_u64_unchecked());
+        let items = match fs::read_dir(self.sharding.file_dir(&self.dir, id)) {
+            Ok(items) => items,
+            Err(err) if err.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+            Err(err) => return Err(err.into()),
+        };
+        let mut out = Vec::new();
+        for item in items {
+            let item = item?;
+            let Some(file_name) = item.file_name().to_str().map(str::to_owned) else {
+                continue;
+            };
+            let Some(version) = file_name
+                .strip_prefix(&prefix)
+                .and_then(|rest| rest.strip_suffix(".dat"))
+            else {
+                continue;
+            };
+
+            if let Ok(version) = version.parse() {
+                out.push(version);
+            }
+        }
+        out.sort_unstable();
+        Ok(out)
+    }
+
+    /// Keep a file's current data as a new version, then drop the oldest versions beyond the
+    /// retention policy. The data is linked rather than moved, so the file always has data
+    /// until it's atomically replaced.
+    fn keep_version(&self, id: FileId) -> Result<(), Error> {
+        let mut versions = self.scan_versions(id)?;
+        let next = versions.last().map_or(1, |version| version + 1);
+        let (current, version) = (
+            self.file_name(id).with_extension("dat"),
+            self.version_name(id, next),
+        );
+        // Not every filesystem supports hard links
+        if fs::hard_link(&current, &version).is_err() {
+            fs::copy(&current, &version)?;
+        }
+        versions.push(next);
+
+        let excess = self.retention.excess(versions.len());
+        for version in &versions[..excess] {
+            fs::remove_file(self.version_name(id, *version))?;
+        }
+        Ok(())
+    }
+
+    /// Change part of a file's data. Data files are never changed in place, so maps of them stay
+    /// valid, so instead a copy is patched and renamed over the original, and then hashed for its
+    /// checksums. Data which is stored compressed, or which might be mistaken for having a header
+    /// once patched, is read and rewritten in full.
+    fn patch_data<W, P>(&self, id: FileId, first: u64, whole: W, part: P) -> Result<(), Error>
+    where
+        W: FnOnce(&mut Vec<u8>) -> Result<(), Error>,
+        P: FnOnce(&mut File, u64) -> io::Result<()>,
+    {
+        let _lock = self.locks.write(id)?;
+        self.assert_dir()?;
+        self.assert_writable()?;
+        self.assert_file_exists(id)?;
+
+        // Data rewritten in full is patched before anything is changed, so a failed patch leaves
+        // the file as it was
+        let path = self.file_name(id).with_extension("dat");
+        let start = compress::plain_start(&path, first)?;
+        let patched = if start.is_none() {
+            let mut data = compress::read(&path)?;
+            whole(&mut data)?;
+            Some(data)
+        } else {
+            None
+        };
+
+        self.journal(id, false)?;
+        if self.retention.is_enabled() {
+            // Kept as a link to the current data file, which renaming the copy over leaves as is
+            self.keep_version(id)?;
+        }
+        match (start, patched) {
+            (Some(start), _) => {
+                atomic::patch(&path, self.sync_now(&path)?, |file| part(file, start))?;
+                self.update_sums(id, Some(checksum::hash_file(&path)?), None, None)?;
+            }
+            (None, Some(data)) => self.write_data(id, &data)?,
+            (None, None) => unreachable!("data without a plain start is always patched in full"),
+        }
+        self.emit(Event::FileEdited(id))
+    }
+
+    fn write_data(&self, id: FileId, data: &[u8]) -> Result<(), Error> {
+        let stored = compress::encode(data, self.compression)?;
+        fs::create_dir_all(self.sharding.file_dir(&self.dir, id))?;
+        self.write_file(&self.file_name(id).with_extension("dat"), &stored)?;
+        self.update_sums(id, Some(hash_data(&stored)), Some(data), None)
+    }
+
+    /// Record new checksums for a file. Those not given are taken from the files as stored. When
+    /// the data changed, its decoded content is recorded from `plain` if given, or read back from
+    /// the data file otherwise. Files keep the creation time first recorded for them.
+    fn update_sums(
+        &self,
+        id: FileId,
+        data: Option<Hash>,
+        plain: Option<&[u8]>,
+        tags: Option<Hash>,
+    ) -> Result<(), Error> {
+        let path = self.file_name(id).with_extension("sum");
+        let data_path = self.file_name(id).with_extension("dat");
+        let old = Sums::load(&path)?;
+        let old_content = old.and_then(|old| old.content);
+
+        let created = match old_content {
+            Some(old) => old.created,
+            // Only files from before creation times were recorded have unchanged data without one
+            None if data.is_none() => match fs::metadata(&data_path) {
+                Ok(meta) => meta.created().or_else(|_| meta.modified())?,
+                Err(err) if err.kind() == io::ErrorKind::NotFound => metadata::now(),
+                Err(err) => return Err(err.into()),
+            },
+            None => metadata::now(),
+        };
+        let content = match (plain, old_content) {
+            (Some(plain), _) => Some(Content {
+                hash: hash_data(plain),
+                size: plain.len() as u64,
+                created,
+            }),
+            (None, Some(old)) if data.is_none() => Some(old),
+            (None, _) => match Reader::open(&data_path) {
+                Ok(reader) => {
+                    let (hash, size) = checksum::hash_reader(BufReader::new(reader))?;
+                    Some(Content {
+                        hash,
+                        size,
+                        created,
+                    })
+                }
+                Err(err) if err.kind() == io::ErrorKind::NotFound => None,
+                Err(err) => return Err(err.into()),
+            },
+        };
+
+        let data = match (data, old) {
+            (Some(data), _) => data,
+            (None, Some(old)) => old.data,
+            (None, None) => checksum::hash_file(&data_path)?,
+        };
+        let tags = match (tags, old) {
+            (Some(tags), _) => tags,
+            (None, Some(old)) => old.tags,
+            (None, None) => checksum::hash_file(&self.file_name(id).with_extension("tag"))?,
+        };
+        Sums {
+            data,
+            tags,
+            content,
+        }
+        .save(&path, self.sync_now(&path)?)?;
+
+        let Some(content) = content else {
+            return Ok(());
+        };
+        if self.index.read()?.hash_of(id) != Some(&content.hash) {
+            let sync = self.sync_index()?;
+            let mut index = self.index.write()?;
+            index.set_hash(id, content.hash);
+            // Files being added are logged once their tags are written
+            if index.tags_of(id).is_some() {
+                index.log(&self.index_path(), sync, id)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Get what a file's data holds, as recorded in its checksums when it was written. It's only
+    /// read from the data itself if the checksums were lost.
+    fn content(&self, id: FileId) -> Result<Content, Error> {
+        let sums = Sums::load(&self.file_name(id).with_extension("sum"))?;
+        if let Some(content) = sums.and_then(|sums| sums.content) {
+            return Ok(content);
+        }
+        let path = self.file_name(id).with_extension("dat");
+        let (hash, size) = checksum::hash_reader(BufReader::new(Reader::open(&path)?))?;
+        Ok(Content {
+            hash,
+            size,
+            created: fs::metadata(&path)?.modified()?,
+        })
+    }
+
+    /// Get the hash of a file's content for the index, or `None` if its data is missing
+    fn content_hash(&self, id: FileId) -> Result<Option<Hash>, Error> {
+        match self.content(id) {
+            Ok(content) => Ok(Some(content.hash)),
+            Err(Error::IoError(err)) if err.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// Fail if a file's data, and optionally its tags, don't match their checksums
+    fn check_sums(&self, id: FileId, tags: bool) -> Result<(), Error> {
+        let Some(sums) = Sums::load(&self.file_name(id).with_extension("sum"))? else {
+            return Ok(());
+        };
+        let data_ok = checksum::hash_file(&self.file_name(id).with_extension("dat"))? == sums.data;
+        let tags_ok =
+            !tags || checksum::hash_file(&self.file_name(id).with_extension("tag"))? == sums.tags;
+        if data_ok && tags_ok {
+            Ok(())
+        } else {
+            Err(Error::Corrupted(id))
+        }
+    }
+
+    fn write_tags<I>(&self, id: FileId, tags: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        trace::record("tags", tags.len());
+
+        let mut encoded = Vec::new();
+        for tag in &tags {
+            codec::write_tag(&mut encoded, tag)?;
+        }
+        self.write_file(&self.file_name(id).with_extension("tag"), &encoded)?;
+        self.update_sums(id, None, None, Some(hash_data(&encoded)))?;
+
+        let sync = self.sync_index()?;
+        let mut index = self.index.write()?;
+        index.insert(id, tags);
+        index.log(&self.index_path(), sync, id)?;
+        Ok(())
+    }
+
+    /// Find the IDs of all files in the directory, without consulting the index
+    fn scan_ids(&self) -> Result<BTreeSet<FileId>, Error> {
+        self.assert_dir()?;
+        let mut out = BTreeSet::new();
+        for dir in self.sharding.dirs(&self.dir)? {
+            for item in fs::read_dir(dir)? {
+                let item = item?;
+                let Some(file_name) = item.file_name().to_str().map(str::to_owned) else {
+                    continue;
+                };
+                if file_name
+                    .split_once('.')
+                    .is_some_and(|(_, ext)| ext == "tag")
+                {
+                    out.extend(parse_id(&file_name));
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    /// Find the ID of every file with anything left in the directory, including data without
+    /// tags and old versions
+    fn scan_all_ids(&self) -> Result<BTreeSet<FileId>, Error> {
+        let mut out = BTreeSet::new();
+        for dir in self.sharding.dirs(&self.dir)? {
+            for item in fs::read_dir(dir)? {
+                out.extend(item?.file_name().to_str().and_then(parse_id));
+            }
+        }
+        Ok(out)
+    }
+
+    /// Read as many of a file's tags as can be decoded, and whether that was all of them
+    fn read_tags_lossy(&self, id: FileId) -> Result<(BTreeSet<Tag>, bool), Error> {
+        let mut file = BufReader::new(File::open(self.file_name(id).with_extension("tag"))?);
+        let mut tags = BTreeSet::new();
+        loop {
+            match codec::read_tag(&mut file) {
+                Ok(Some(tag)) => {
+                    tags.insert(tag);
+                }
+                Ok(None) => return Ok((tags, true)),
+                Err(err)
+                    if matches!(
+                        err.kind(),
+                        io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof
+                    ) =>
+                {
+                    return Ok((tags, false))
+                }
+                Err(err) => return Err(err.into()),
+            }
+        }
+    }
+
+    /// Remove every file left in the directory for an ID
+    fn remove_leftovers(&self, id: FileId) -> Result<(), Error> {
+        for version in self.scan_versions(id)? {
+            fs::remove_file(self.version_name(id, version))?;
+        }
+        for ext in ["dat", "tag", "sum"] {
+            match fs::remove_file(self.file_name(id).with_extension(ext)) {
+                Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+        Ok(())
+    }
+
+    fn read_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Error> {
+        let mut file = BufReader::new(File::open(self.file_name(id).with_extension("tag"))?);
+        let mut tags = BTreeSet::new();
+        while let Some(tag) = codec::read_tag(&mut file)? {
+            tags.insert(tag);
+        }
+        Ok(tags)
+    }
+}
+
+/// The running transaction of a [`DirectoryBackedFs`]. If it's dropped without being ended, such
+/// as when its closure panics, its changes are rolled back.
+struct Transaction<'a> {
+    fs: &'a DirectoryBackedFs,
+    ended: bool,
+}
+
+impl Transaction<'_> {
+    /// Commit the changes made in the transaction, recording them in the change feed, or roll
+    /// them back
+    fn end(&mut self, commit: bool) -> Result<(), Error> {
+        self.ended = true;
+        let fs = self.fs;
+        let Some(mut journal) = fs.journal.lock()?.take() else {
+            return Ok(());
+        };
+        if commit {
+            // Recorded before committing, so a crash can leave changes in the feed which were
+            // rolled back, but can't lose ones which were kept
+            let events = journal.take_events();
+            fs.record_changes(&events)?;
+            journal.commit()?;
+            for event in events {
+                fs.subscribers.emit(event);
+            }
+        } else {
+            journal::rollback(&fs.journal_path(), |id| fs.file_name(id))?;
+            let sync = fs.sync_index()?;
+            let mut index = fs.index.write()?;
+            *index = journal.index().clone();
+            index.save(&fs.index_path(), sync)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.end(false);
+        }
+    }
+}
+
+impl FileSystemRead for DirectoryBackedFs {
+    type Error = Error;
+    type SearchIter<'a, P: TagPattern + 'a> = SearchIter<'a, P>;
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(found = tracing::field::Empty),
+            err(level = "debug")
+        )
+    )]
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        #[cfg(feature = "parallel")]
+        let ids = self.search_parallel(&tags.to_predicate())?;
+        #[cfg(not(feature = "parallel"))]
+        let ids = self.search_tags_iter(tags).collect::<Result<Vec<_>, _>>()?;
+        trace::record("found", ids.len());
+        Ok(ids)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+    )]
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        if options.is_lazy() {
+            return search::page_lazy(self.search_tags_iter(tags), options);
+        }
+
+        let ids = self.search_tags(tags)?;
+        let providers = self.providers.read()?;
+        let index = self.index.read()?;
+
+        search::sort_results(ids, options, |id| {
+            let dat = self.file_name(id).with_extension("dat");
+            match options.sort() {
+                SortBy::Id => Ok(SortKey::Id),
+                SortBy::Created => {
+                    let meta = fs::metadata(dat)?;
+                    Ok(SortKey::Time(meta.created().or_else(|_| meta.modified())?))
+                }
+                SortBy::Size => Ok(SortKey::Size(compress::size(&dat)?)),
+                SortBy::Value { group, name } => {
+                    let file_tags = index.tags_of(id).ok_or(Error::FileNotFound(id))?;
+                    let provided = if providers.is_empty() {
+                        Vec::new()
+                    } else {
+                        provide_tags(&providers, &compress::read(&dat)?)
+                    };
+                    Ok(search::value_key(
+                        file_tags.iter().chain(&provided),
+                        group,
+                        name,
+                    ))
+                }
+            }
+        })
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let ids = self.search_tags(pattern)?;
+        let index = self.index.read()?;
+        let providers = self.providers.read()?;
+
+        let mut out = BTreeMap::new();
+        for id in ids {
+            let Some(tags) = index.tags_of(id) else {
+                continue;
+            };
+            if providers.is_empty() {
+                for tag in tags {
+                    *out.entry(tag.clone()).or_insert(0) += 1;
+                }
+            } else {
+                let mut tags = tags.clone();
+                let data = compress::read(&self.file_name(id).with_extension("dat"))?;
+                tags.extend(provide_tags(&providers, &data));
+                for tag in tags {
+                    *out.entry(tag).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        // Provided tags aren't in the index, so need every file's data
+        if !self.providers.read()?.is_empty() {
+            let counts = self.tag_counts(TagPredicate::And(Vec::new()))?;
+            return Ok(counts.into_keys().map(|tag| tag.group().clone()).collect());
+        }
+        Ok(self
+            .index
+            .read()?
+            .tags()
+            .keys()
+            .map(|tag| tag.group().clone())
+            .collect())
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        if !self.providers.read()?.is_empty() {
+            let counts = self.tag_counts(TagPredicate::group(group.clone()))?;
+            return Ok(counts
+                .into_keys()
+                .filter(|tag| tag.group() == group)
+                .collect());
+        }
+        Ok(self
+            .index
+            .read()?
+            .tags()
+            .keys()
+            .filter(|tag| tag.group() == group)
+            .cloned()
+            .collect())
+    }
+
+    fn count_tags<P>(&self, tags: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        match self.exact_matches(&tags.to_predicate())? {
+            Some(ids) => Ok(ids.len() as u64),
+            None => self
+                .search_tags_iter(tags)
+                .try_fold(0, |count, id| id.map(|_| count + 1)),
+        }
+    }
+
+    fn any_match<P>(&self, tags: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        match self.exact_matches(&tags.to_predicate())? {
+            Some(ids) => Ok(!ids.is_empty()),
+            None => Ok(self.search_tags_iter(tags).next().transpose()?.is_some()),
+        }
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags,
+            scan: Scan::Pending,
+            cursor: Bound::Unbounded,
+            done: false,
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        let mut tags = self
+            .index
+            .read()?
+            .tags_of(id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))?;
+        if self.verify_reads {
+            self.check_sums(id, true)?;
+        }
+        let data = compress::read(&self.file_name(id).with_extension("dat"))?.into_boxed_slice();
+        tags.extend(provide_tags(&*self.providers.read()?, &data));
+        Ok(FileInfo { id, tags, data })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        let mut tags = self
+            .index
+            .read()?
+            .tags_of(id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))?;
+        // Only read the data if a provider needs it
+        let providers = self.providers.read()?;
+        if !providers.is_empty() {
+            let data = compress::read(&self.file_name(id).with_extension("dat"))?;
+            tags.extend(provide_tags(&providers, &data));
+        }
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.index
+            .read()?
+            .tags_of(id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        if self.verify_reads {
+            self.check_sums(id, false)?;
+        }
+        Ok(compress::read(&self.file_name(id).with_extension("dat"))?)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        if self.verify_reads {
+            self.check_sums(id, false)?;
+        }
+        Ok(compress::read_range(
+            &self.file_name(id).with_extension("dat"),
+            range,
+        )?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        let modified = fs::metadata(self.file_name(id).with_extension("dat"))?.modified()?;
+        let content = self.content(id)?;
+        Ok(Metadata {
+            created: content.created,
+            modified,
+            size: content.size,
+            hash: content.hash,
+        })
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.assert_dir()?;
+        Ok(self.index.read()?.duplicates())
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.assert_dir()?;
+        Ok(self.index.read()?.with_hash(hash).next())
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let cur_id = self.state.read()?.cur_id;
+        if cur_id > 256 {
+            Ok(Some(FileId::from_u64_unchecked(cur_id - 1)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self
+            .index
+            .read()?
+            .files()
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        self.scan_versions(id)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        match compress::read(&self.version_name(id, version)) {
+            Ok(data) => Ok(data.into_boxed_slice()),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                Err(Error::VersionNotFound(id, version))
+            }
+            Err(err) => Err(Error::IoError(err)),
+        }
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        let mut out = String::new();
+        match file {
+            SpecialFile::Manifest => {
+                for id in self.index.read()?.files().keys() {
+                    let _ = writeln!(out, "{:016X}", id.into_u64_unchecked());
+                }
+            }
+            SpecialFile::TagRegistry => {
+                let index = self.index.read()?;
+                let providers = self.providers.read()?;
+                let mut tags = index.tags().keys().cloned().collect::<BTreeSet<_>>();
+                if !providers.is_empty() {
+                    for id in index.files().keys() {
+                        let data = compress::read(&self.file_name(*id).with_extension("dat"))?;
+                        tags.extend(provide_tags(&providers, &data));
+                    }
+                }
+                for tag in tags {
+                    let _ = writeln!(out, "{tag}");
+                }
+            }
+            // Removed IDs are recorded so they can be reused, so the trash is every one which
+            // hasn't been yet
+            SpecialFile::Trash => {
+                for id in &self.state.read()?.free {
+                    let _ = writeln!(out, "{:016X}", id.into_u64_unchecked());
+                }
+            }
+            _ => {
+                return Ok(FileInfo {
+                    id: file.id(),
+                    tags: BTreeSet::new(),
+                    data: self.special_data(file)?.into_boxed_slice(),
+                })
+            }
+        }
+
+        Ok(FileInfo {
+            id: file.id(),
+            tags: BTreeSet::new(),
+            data: out.into_bytes().into_boxed_slice(),
+        })
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.assert_dir()?;
+        if file.is_generated() {
+            return Ok(Vec::new());
+        }
+        match fs::read(self.special_path(file)) {
+            Ok(data) => Ok(data),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(Vec::new()),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl FileSystemWrite for DirectoryBackedFs {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(bytes = data.len(), tags = tracing::field::Empty),
+            ret(Display),
+            err
+        )
+    )]
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        let id = self.alloc_id()?;
+        self.journal(id, true)?;
+        self.write_data(id, data)?;
+        let inferred = infer_tags(&*self.inferrers.read()?, data);
+        self.write_tags(id, tags.into_iter().chain(inferred))?;
+        self.emit(Event::FileAdded(id))?;
+        Ok(id)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(id = %id, bytes = data.len(), tags = tracing::field::Empty),
+            err
+        )
+    )]
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let _lock = self.locks.write(id)?;
+        self.assert_dir()?;
+        self.assert_writable()?;
+        if id.into_u64_unchecked() < 256 || self.index.read()?.tags_of(id).is_some() {
+            return Err(Error::AlreadyExists(id));
+        }
+        // The counter can't move past the highest ID
+        let next = id
+            .into_u64_unchecked()
+            .checked_add(1)
+            .ok_or(Error::AlreadyExists(id))?;
+
+        {
+            let sync = self.sync_now(&self.state_path())?;
+            let mut state = self.state.write()?;
+            state.free.remove(&id);
+            state.cur_id = state.cur_id.max(next);
+            state.save(&self.state_path(), sync)?;
+        }
+
+        self.journal(id, true)?;
+        self.write_data(id, data)?;
+        let inferred = infer_tags(&*self.inferrers.read()?, data);
+        self.write_tags(id, tags.into_iter().chain(inferred))?;
+        self.emit(Event::FileAdded(id))?;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(id = %id, bytes = data.map(<[u8]>::len), tags = tracing::field::Empty),
+            err
+        )
+    )]
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let _lock = self.locks.write(id)?;
+        self.assert_dir()?;
+        self.assert_writable()?;
+        self.assert_file_exists(id)?;
+        self.journal(id, false)?;
+        if let Some(data) = data {
+            if self.retention.is_enabled() {
+                self.keep_version(id)?;
+            }
+            self.write_data(id, data)?;
+            self.emit(Event::FileEdited(id))?;
+        }
+        if let Some(tags) = tags {
+            self.write_tags(id, tags)?;
+            self.emit(Event::TagsChanged(id))?;
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let _lock = self.locks.write(id)?;
+        self.assert_writable()?;
+        let mut new = self
+            .index
+            .read()?
+            .tags_of(id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))?;
+        new.extend(tags);
+        self.journal(id, false)?;
+        self.write_tags(id, new)?;
+        self.emit(Event::TagsChanged(id))?;
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let _lock = self.locks.write(id)?;
+        self.assert_writable()?;
+        let mut new = self
+            .index
+            .read()?
+            .tags_of(id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))?;
+        for tag in tags {
+            new.remove(&tag);
+        }
+        self.journal(id, false)?;
+        self.write_tags(id, new)?;
+        self.emit(Event::TagsChanged(id))?;
+        Ok(())
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        let ids = self
+            .index
+            .read()?
+            .tags()
+            .get(old)
+            .cloned()
+            .unwrap_or_default();
+        self.transaction(|fs| {
+            for id in ids {
+                let _lock = fs.locks.write(id)?;
+                let mut tags = fs.index.read()?.tags_of(id).cloned().unwrap_or_default();
+                tags.remove(old);
+                tags.insert(new.clone());
+                fs.journal(id, false)?;
+                fs.write_tags(id, tags)?;
+                fs.emit(Event::TagsChanged(id))?;
+            }
+            Ok(())
+        })
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        let ids = self
+            .index
+            .read()?
+            .tags()
+            .iter()
+            .filter(|(tag, _)| tag.group() == old)
+            .flat_map(|(_, ids)| ids.iter().copied())
+            .collect::<BTreeSet<_>>();
+        self.transaction(|fs| {
+            for id in ids {
+                let _lock = fs.locks.write(id)?;
+                let tags = fs.index.read()?.tags_of(id).cloned().unwrap_or_default();
+                fs.journal(id, false)?;
+                fs.write_tags(
+                    id,
+                    tags.into_iter()
+                        .map(|tag| crate::rename_group(tag, old, &new)),
+                )?;
+                fs.emit(Event::TagsChanged(id))?;
+            }
+            Ok(())
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        if data.is_empty() {
+            return self.assert_file_exists(id);
+        }
+        self.patch_data(
+            id,
+            offset,
+            |buf| crate::write_into(buf, id, offset, data),
+            |file, start| {
+                let at = start
+                    .checked_add(offset)
+                    .ok_or(io::ErrorKind::FileTooLarge)?;
+                file.seek(SeekFrom::Start(at))?;
+                file.write_all(data)
+            },
+        )
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn truncate(&self, id: FileId, len: u64) -> Result<(), Self::Error> {
+        // Cutting data short or adding zeroes can't give it the magic bytes of a header
+        self.patch_data(
+            id,
+            u64::MAX,
+            |buf| crate::resize_data(buf, id, len),
+            |file, start| file.set_len(start.checked_add(len).ok_or(io::ErrorKind::FileTooLarge)?),
+        )
+    }
+
+    fn supports_partial_writes(&self) -> bool {
+        // Data files are never changed in place, as maps, snapshots and kept versions rely on
+        // them staying as they are, so a partial write copies the whole file and hashes it again.
+        // That's cheaper than the default of decoding and rewriting it, but still costs as much
+        // as the size of the file rather than of the change.
+        false
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let _lock = self.locks.write(id)?;
+        self.assert_dir()?;
+        self.assert_writable()?;
+        self.assert_file_exists(id)?;
+        self.journal(id, false)?;
+        {
+            let sync = self.sync_index()?;
+            let mut index = self.index.write()?;
+            index.remove(id);
+            index.log(&self.index_path(), sync, id)?;
+        }
+
+        for version in self.scan_versions(id)? {
+            fs::remove_file(self.version_name(id, version))?;
+        }
+
+        // Files written before checksums were kept have none to remove
+        match fs::remove_file(self.file_name(id).with_extension("sum")) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+        let dat = fs::remove_file(self.file_name(id).with_extension("dat"));
+        let tag = fs::remove_file(self.file_name(id).with_extension("tag"));
+
+        match (dat, tag) {
+            (Err(e), _) | (_, Err(e)) => Err(Error::IoError(e)),
+            (_, _) => {
+                self.emit(Event::FileRemoved(id))?;
+                self.free_id(id)
+            }
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        {
+            let mut journal = self.journal.lock()?;
+            if journal.is_some() {
+                drop(journal);
+                return f(self);
+            }
+            let index = self.index.read()?.clone();
+            *journal = Some(Journal::create(self.journal_path(), index)?);
+        }
+
+        let mut transaction = Transaction {
+            fs: self,
+            ended: false,
+        };
+        let out = f(self);
+        transaction.end(out.is_ok())?;
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        check_stored::<Self::Error>(file)?;
+        self.write_file(&self.special_path(file), data)?;
+        Ok(())
+    }
+}
+
+impl ProvidedTags for DirectoryBackedFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers.write()?.insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl InferredTags for DirectoryBackedFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers.write()?.push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl StreamRead for DirectoryBackedFs {
+    type Reader<'a> = Reader;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        let _lock = self.locks.read(id)?;
+        self.assert_dir()?;
+        self.assert_file_exists(id)?;
+        if self.verify_reads {
+            self.check_sums(id, false)?;
+        }
+        Ok(Reader::open(&self.file_name(id).with_extension("dat"))?)
+    }
+}
+
+impl StreamWrite for DirectoryBackedFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.assert_dir()?;
+        self.assert_writable()?;
+        let id = self.alloc_id()?;
+        self.journal(id, true)?;
+        // Written through a temporary file, moved into place once committed. Streamed data is
+        // given a header, so it can't be mistaken for one, and compressed once committed.
+        fs::create_dir_all(self.sharding.file_dir(&self.dir, id))?;
+        let mut file = File::create(atomic::temp_path(&self.file_name(id).with_extension("dat")))?;
+        file.write_all(&compress::header(Compression::None))?;
+        Ok(Writer {
+            fs: self,
+            id,
+            file,
+            tags: Some(tags.into_iter().collect()),
+        })
+    }
+}
+
+/// Which files a search checks
+enum Scan {
+    /// The index hasn't been planned against yet
+    Pending,
+    /// Every file
+    All,
+    /// Only the files found from the index
+    Only(BTreeSet<FileId>),
+}
+
+/// A lazy search over a [`DirectoryBackedFs`]. Matches are found from the index, which isn't
+/// locked between calls to `next`. Unless tag providers are set, the first call narrows the search
+/// down with a [`QueryPlan`](crate::QueryPlan), so only files which could match are checked.
+pub struct SearchIter<'a, P> {
+    fs: &'a DirectoryBackedFs,
+    pattern: P,
+    scan: Scan,
+    cursor: Bound<FileId>,
+    done: bool,
+}
+
+impl<P: TagPattern> SearchIter<'_, P> {
+    fn advance(&mut self) -> Result<Option<FileId>, Error> {
+        self.fs.assert_dir()?;
+        let providers = self.fs.providers.read()?;
+        let index = self.fs.index.read()?;
+
+        if let Scan::Pending = self.scan {
+            // Provided tags aren't in the index, so could match anything
+            let found = if providers.is_empty() {
+                match QueryPlan::new(&self.pattern.to_predicate()).execute(&*index) {
+                    Ok(found) => found,
+                    Err(never) => match never {},
+                }
+            } else {
+                Lookup::Unknown
+            };
+            self.scan = match found {
+                Lookup::Exact(ids) | Lookup::Superset(ids) => Scan::Only(ids),
+                Lookup::Unknown => Scan::All,
+            };
+        }
+
+        let ids: Box<dyn Iterator<Item = FileId>> = if let Scan::Only(ids) = &self.scan {
+            Box::new(ids.range((self.cursor, Bound::Unbounded)).copied())
+        } else {
+            Box::new(
+                index
+                    .files()
+                    .range((self.cursor, Bound::Unbounded))
+                    .map(|(&id, _)| id),
+            )
+        };
+        for id in ids {
+            self.cursor = Bound::Excluded(id);
+            // Files removed since the plan ran are skipped
+            let Some(file_tags) = index.tags_of(id) else {
+                continue;
+            };
+
+            let matched = if providers.is_empty() {
+                self.pattern.match_tags(file_tags)
+            } else {
+                let data = compress::read(&self.fs.file_name(id).with_extension("dat"))?;
+                self.pattern
+                    .match_tags(file_tags.iter().chain(&provide_tags(&providers, &data)))
+            };
+
+            if matched {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<P: TagPattern> Iterator for SearchIter<'_, P> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let out = self.advance().transpose();
+        self.done = !matches!(out, Some(Ok(_)));
+        out
+    }
+}
+
+/// A handle streaming data into a new file of a [`DirectoryBackedFs`]. Data is written straight
+/// to disk, and the file's tags are written once it's committed.
+pub struct Writer<'a> {
+    fs: &'a DirectoryBackedFs,
+    id: FileId,
+    file: File,
+    tags: Option<Vec<Tag>>,
+}
+
+impl Writer<'_> {
+    fn commit_tags(&mut self) -> Result<(), Error> {
+        self.file.flush()?;
+        let path = self.fs.file_name(self.id).with_extension("dat");
+        let sync = self.fs.sync_now(&path)?;
+        if sync {
+            self.file.sync_all()?;
+        }
+        if let Some(mut tags) = self.tags.take() {
+            // The handle stays open, so any data written after this goes to the moved file
+            fs::rename(atomic::temp_path(&path), &path)?;
+            if sync {
+                atomic::sync_dir(&path)?;
+            }
+            self.fs
+                .update_sums(self.id, Some(checksum::hash_file(&path)?), None, None)?;
+
+            let inferrers = self.fs.inferrers.read()?;
+            if !inferrers.is_empty() {
+                let data = compress::read(&self.fs.file_name(self.id).with_extension("dat"))?;
+                tags.extend(infer_tags(&inferrers, &data));
+            }
+            self.fs.write_tags(self.id, tags)?;
+            self.fs.emit(Event::FileAdded(self.id))?;
+        } else {
+            // Data written since the last flush changes the checksum
+            self.fs
+                .update_sums(self.id, Some(checksum::hash_file(&path)?), None, None)?;
+        }
+        Ok(())
+    }
+}
+
+impl Write for Writer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.file.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit_tags().map_err(|err| match err {
+            Error::IoError(err) => err,
+            err => io::Error::other(err.to_string()),
+        })
+    }
+}
+
+impl FileWriter for Writer<'_> {
+    type Error = Error;
+
+    fn commit(mut self) -> Result<FileId, Self::Error> {
+        self.commit_tags()?;
+        if self.fs.compression != Compression::None {
+            self.fs.recompress_file(self.id, self.fs.compression)?;
+        }
+        Ok(self.id)
+    }
+}
+
+impl Drop for Writer<'_> {
+    fn drop(&mut self) {
+        // While unwinding, a file which was never committed could be only partly written, so it's
+        // left as a temporary file, removed when the store is next opened. Data written after a
+        // commit is already in place, so its checksums are brought up to date either way.
+        if self.tags.is_none() || !std::thread::panicking() {
+            let _ = self.commit_tags();
+        }
+    }
+}
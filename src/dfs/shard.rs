@@ -0,0 +1,106 @@
+//! Spreading files across subdirectories, so no one directory holds too many files
+
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::FileId;
+
+/// The most levels of subdirectories, one for each byte of an ID
+pub(super) const MAX_LEVELS: u8 = 8;
+
+/// How a [`DirectoryBackedFs`](super::DirectoryBackedFs) spreads files across subdirectories
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Sharding {
+    /// Every file is stored directly in the store directory
+    #[default]
+    Flat,
+    /// Files are stored in nested subdirectories, one level for each byte of their ID starting
+    /// from the lowest, named by that byte in hex. Each level splits files across up to 256
+    /// subdirectories, so with IDs handed out in order they're spread evenly. At most 8 levels
+    /// are supported, and 0 levels is the same as [`Sharding::Flat`].
+    Nested(u8),
+}
+
+/// Formats how files are laid out, like `flat` or `nested 2 levels deep`
+impl fmt::Display for Sharding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Sharding::Flat => write!(f, "flat"),
+            Sharding::Nested(levels) => write!(f, "nested {levels} levels deep"),
+        }
+    }
+}
+
+impl Sharding {
+    /// How many levels of subdirectories files are stored under
+    pub(super) fn levels(self) -> u8 {
+        match self {
+            Sharding::Flat => 0,
+            Sharding::Nested(levels) => levels.min(MAX_LEVELS),
+        }
+    }
+
+    pub(super) fn from_levels(levels: u8) -> Sharding {
+        match levels {
+            0 => Sharding::Flat,
+            levels => Sharding::Nested(levels.min(MAX_LEVELS)),
+        }
+    }
+
+    /// The directory a file is stored in
+    pub(super) fn file_dir(self, base: &Path, id: FileId) -> PathBuf {
+        let mut out = base.to_owned();
+        let id = id.into_u64_unchecked();
+        for level in 0..self.levels() {
+            out.push(format!("{:02X}", (id >> (level * 8)) & 0xFF));
+        }
+        out
+    }
+
+    /// Every directory files are stored in
+    pub(super) fn dirs(self, base: &Path) -> io::Result<Vec<PathBuf>> {
+        let mut out = vec![base.to_owned()];
+        for _ in 0..self.levels() {
+            let mut next = Vec::new();
+            for dir in out {
+                next.extend(subdirs(&dir)?);
+            }
+            out = next;
+        }
+        Ok(out)
+    }
+}
+
+/// The base directory and every shard subdirectory under it, however deeply nested, with
+/// parents before their children
+pub(super) fn all_dirs(base: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut out = vec![base.to_owned()];
+    let mut level = out.clone();
+    for _ in 0..MAX_LEVELS {
+        let mut next = Vec::new();
+        for dir in &level {
+            next.extend(subdirs(dir)?);
+        }
+        out.extend(next.iter().cloned());
+        level = next;
+    }
+    Ok(out)
+}
+
+/// The shard subdirectories directly inside a directory
+fn subdirs(dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let mut out = Vec::new();
+    for item in fs::read_dir(dir)? {
+        let item = item?;
+        let is_shard = item
+            .file_name()
+            .to_str()
+            .is_some_and(|name| name.len() == 2 && name.bytes().all(|b| b.is_ascii_hexdigit()));
+        if is_shard && item.file_type()?.is_dir() {
+            out.push(item.path());
+        }
+    }
+    Ok(out)
+}
@@ -0,0 +1,57 @@
+//! Finding and merging files with the same data
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use crate::{FileId, Tag};
+
+/// Which file out of a group of duplicates [`dedupe`](crate::FileSystemWrite::dedupe) keeps.
+/// Whichever it is, it gains the tags of every file merged into it.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum DedupePolicy {
+    /// Keep the file with the lowest ID, which is the first one added for backends allocating IDs
+    /// from a counter
+    #[default]
+    KeepOldest,
+    /// Keep the file with the highest ID
+    KeepNewest,
+    /// Keep the file with the most tags of its own, preferring the lowest ID out of any ties
+    KeepMostTags,
+}
+
+impl DedupePolicy {
+    /// Pick the file to keep out of a non-empty group sorted by ID, returning its index
+    pub(crate) fn choose(self, group: &[(FileId, BTreeSet<Tag>)]) -> usize {
+        match self {
+            DedupePolicy::KeepOldest => 0,
+            DedupePolicy::KeepNewest => group.len() - 1,
+            // The last of several maximums is picked, so search in reverse to get the first
+            DedupePolicy::KeepMostTags => group
+                .iter()
+                .enumerate()
+                .rev()
+                .max_by_key(|(_, (_, tags))| tags.len())
+                .map_or(0, |(idx, _)| idx),
+        }
+    }
+}
+
+/// A group of files with the same data, merged into one by
+/// [`dedupe`](crate::FileSystemWrite::dedupe)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Merge {
+    pub(crate) kept: FileId,
+    pub(crate) removed: Vec<FileId>,
+}
+
+impl Merge {
+    /// The file which was kept, now with the tags of every file in the group
+    pub fn kept(&self) -> FileId {
+        self.kept
+    }
+
+    /// The files which were removed, sorted by ID
+    pub fn removed(&self) -> &[FileId] {
+        &self.removed
+    }
+}
@@ -0,0 +1,1069 @@
+//! An object-safe version of the filesystem traits, so backends can be picked at runtime
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+#[cfg(feature = "std")]
+use std::io::{Read, Write};
+
+use crate::error::{Error as _, ErrorKind};
+
+use crate::search::{SearchHit, SearchOptions};
+use crate::{
+    BatchResult, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, Group,
+    InferredTags, ProvidedTags, SpecialFile, Tag, TagInferrer, TagPattern, TagPredicate,
+    TagProvider, UsageReport,
+};
+#[cfg(feature = "std")]
+use crate::{DedupePolicy, Event, FileWriter, Merge, Metadata, StreamRead, StreamWrite};
+
+/// The errors a filesystem can return to be a [`DynFileSystem`]. With the std feature, they have
+/// to be standard errors, otherwise they only need to be printable.
+#[cfg(feature = "std")]
+pub trait DynFsError: crate::Error + std::error::Error + Send + Sync {
+    /// Get this as a standard error, such as to be the source of a [`DynError`]
+    fn as_std(&self) -> &(dyn std::error::Error + 'static);
+}
+
+#[cfg(feature = "std")]
+impl<E: crate::Error + std::error::Error + Send + Sync + 'static> DynFsError for E {
+    fn as_std(&self) -> &(dyn std::error::Error + 'static) {
+        self
+    }
+}
+
+/// The errors a filesystem can return to be a [`DynFileSystem`]. With the std feature, they have
+/// to be standard errors, otherwise they only need to be printable.
+#[cfg(not(feature = "std"))]
+pub trait DynFsError: crate::Error + fmt::Debug + fmt::Display + Send + Sync {}
+
+#[cfg(not(feature = "std"))]
+impl<E: crate::Error + fmt::Debug + fmt::Display + Send + Sync + ?Sized> DynFsError for E {}
+
+/// Error for a [`DynFileSystem`], which is the error of whichever filesystem is behind it
+#[derive(Debug)]
+pub enum DynError {
+    /// The filesystem returned an error
+    Fs(Box<dyn DynFsError>),
+    /// A file wasn't found
+    FileNotFound(FileId),
+    /// A file ID was already in use
+    AlreadyExists(FileId),
+    /// A prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+}
+
+impl DynError {
+    /// Wrap the error of a filesystem
+    pub fn new<E: DynFsError + 'static>(err: E) -> DynError {
+        DynError::Fs(Box::new(err))
+    }
+}
+
+impl crate::Error for DynError {
+    fn file_not_found(id: FileId) -> Self {
+        DynError::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        DynError::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        DynError::VersionNotFound(id, version)
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            DynError::Fs(err) => err.generic_kind(),
+            DynError::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            DynError::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            DynError::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+        }
+    }
+}
+
+impl fmt::Display for DynError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DynError::Fs(err) => write!(f, "{err}"),
+            DynError::FileNotFound(id) => write!(f, "file {id} not found"),
+            DynError::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            DynError::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for DynError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            DynError::Fs(err) => Some(err.as_std()),
+            _ => None,
+        }
+    }
+}
+
+/// The streaming a filesystem needs to be a [`DynFileSystem`]. With the std feature, that's
+/// [`StreamRead`] and [`StreamWrite`], otherwise nothing.
+#[cfg(feature = "std")]
+pub trait DynStreams: StreamRead + StreamWrite {}
+
+#[cfg(feature = "std")]
+impl<F: StreamRead + StreamWrite + ?Sized> DynStreams for F {}
+
+/// The streaming a filesystem needs to be a [`DynFileSystem`]. With the std feature, that's
+/// `StreamRead` and `StreamWrite`, otherwise nothing.
+#[cfg(not(feature = "std"))]
+pub trait DynStreams {}
+
+#[cfg(not(feature = "std"))]
+impl<F: ?Sized> DynStreams for F {}
+
+/// A lazy search over a [`DynFileSystem`]
+pub type DynSearchIter<'a> = Box<dyn Iterator<Item = Result<FileId, DynError>> + 'a>;
+
+/// A version of [`FileSystem`] which can be used as a trait object, so the backend can be picked
+/// at runtime, such as `Box<dyn DynFileSystem>`. Every filesystem whose errors are
+/// [`DynFsError`]s implements it, with errors wrapped in a [`DynError`], as long as it
+/// implements [`ProvidedTags`], [`InferredTags`] and [`DynStreams`] too.
+///
+/// Tags are passed as slices and patterns as [`TagPredicate`]s, instead of generically. To use
+/// a trait object where a [`FileSystem`] is needed, wrap it in a [`BoxedFs`]. Methods are named
+/// after the ones they stand in for with a `dyn_` prefix, so calls on a filesystem which
+/// implements both this and [`FileSystemRead`] aren't ambiguous.
+pub trait DynFileSystem {
+    // Lookup files
+
+    /// See [`FileSystemRead::search_tags`]
+    fn dyn_search_tags(&self, tags: &TagPredicate) -> Result<Vec<FileId>, DynError>;
+
+    /// See [`FileSystemRead::search_tags_with`]
+    fn dyn_search_tags_with(
+        &self,
+        tags: &TagPredicate,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, DynError>;
+
+    /// See [`FileSystemRead::search_tags_iter`]
+    fn dyn_search_tags_iter(&self, tags: &TagPredicate) -> DynSearchIter<'_>;
+
+    /// See [`FileSystemRead::search_detailed`]
+    fn dyn_search_detailed(&self, tags: &TagPredicate) -> Result<Vec<SearchHit>, DynError>;
+
+    /// See [`FileSystemRead::get_info`]
+    fn dyn_get_info(&self, id: FileId) -> Result<FileInfo, DynError>;
+
+    /// See [`FileSystemRead::get_tags`]
+    fn dyn_get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, DynError>;
+
+    /// See [`FileSystemRead::get_stored_tags`]
+    fn dyn_get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, DynError>;
+
+    /// See [`FileSystemRead::get_info_many`]
+    fn dyn_get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, DynError>;
+
+    /// See [`FileSystemRead::get_tags_many`]
+    fn dyn_get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, DynError>;
+
+    /// See [`FileSystemRead::get_data`]
+    fn dyn_get_data(&self, id: FileId) -> Result<Vec<u8>, DynError>;
+
+    /// See [`FileSystemRead::read_range`]
+    fn dyn_read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, DynError>;
+
+    /// See [`FileSystemRead::get_metadata`]
+    #[cfg(feature = "std")]
+    fn dyn_get_metadata(&self, id: FileId) -> Result<Metadata, DynError>;
+
+    /// See [`StreamRead::read_file`]
+    #[cfg(feature = "std")]
+    fn dyn_read_file(&self, id: FileId) -> Result<Box<dyn Read + '_>, DynError>;
+
+    /// See [`FileSystemRead::last_id`]
+    fn dyn_last_id(&self) -> Result<Option<FileId>, DynError>;
+
+    /// See [`FileSystemRead::ids_after`]
+    fn dyn_ids_after(&self, after: FileId) -> Result<Vec<FileId>, DynError>;
+
+    // Tag statistics
+
+    /// See [`FileSystemRead::tag_counts`]
+    fn dyn_tag_counts(&self, pattern: &TagPredicate) -> Result<BTreeMap<Tag, usize>, DynError>;
+
+    /// See [`FileSystemRead::count_tags`]
+    fn dyn_count_tags(&self, pattern: &TagPredicate) -> Result<u64, DynError>;
+
+    /// See [`FileSystemRead::any_match`]
+    fn dyn_any_match(&self, pattern: &TagPredicate) -> Result<bool, DynError>;
+
+    /// See [`FileSystemRead::list_groups`]
+    fn dyn_list_groups(&self) -> Result<BTreeSet<Group>, DynError>;
+
+    /// See [`FileSystemRead::list_tags`]
+    fn dyn_list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, DynError>;
+
+    /// See [`FileSystemRead::usage`]
+    fn dyn_usage(&self) -> Result<UsageReport, DynError>;
+
+    /// See [`FileSystemRead::find_duplicates`]
+    #[cfg(feature = "std")]
+    fn dyn_find_duplicates(&self) -> Result<Vec<Vec<FileId>>, DynError>;
+
+    /// See [`FileSystemRead::find_by_hash`]
+    #[cfg(feature = "std")]
+    fn dyn_find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, DynError>;
+
+    // Add/Remove/Edit files
+
+    /// See [`FileSystemWrite::add_file`]
+    fn dyn_add_file(&self, data: &[u8], tags: &[Tag]) -> Result<FileId, DynError>;
+
+    /// See [`FileSystemWrite::add_file_with_id`]
+    fn dyn_add_file_with_id(&self, id: FileId, data: &[u8], tags: &[Tag]) -> Result<(), DynError>;
+
+    /// See [`StreamWrite::create_file`]
+    #[cfg(feature = "std")]
+    fn dyn_create_file(&self, tags: &[Tag]) -> Result<Box<dyn DynFileWriter + '_>, DynError>;
+
+    /// See [`FileSystemWrite::edit_file`]
+    fn dyn_edit_file(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<&[Tag]>,
+    ) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::write_at`]
+    fn dyn_write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::truncate`]
+    fn dyn_truncate(&self, id: FileId, len: u64) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::supports_partial_writes`]
+    fn dyn_supports_partial_writes(&self) -> bool;
+
+    /// See [`FileSystemWrite::remove_file`]
+    fn dyn_remove_file(&self, id: FileId) -> Result<(), DynError>;
+
+    /// Run a closure as a single transaction, like [`FileSystemWrite::transaction`]. The closure
+    /// makes its changes through this filesystem, which it has to capture itself.
+    fn dyn_transaction(&self, f: &mut dyn FnMut() -> Result<(), DynError>) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::add_tags`]
+    fn dyn_add_tags(&self, id: FileId, tags: &[Tag]) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::remove_tags`]
+    fn dyn_remove_tags(&self, id: FileId, tags: &[Tag]) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::add_tags_matching`]
+    fn dyn_add_tags_matching(
+        &self,
+        pattern: &TagPredicate,
+        tags: &[Tag],
+    ) -> Result<usize, DynError>;
+
+    /// See [`FileSystemWrite::remove_tags_matching`]
+    fn dyn_remove_tags_matching(
+        &self,
+        pattern: &TagPredicate,
+        tags: &[Tag],
+    ) -> Result<usize, DynError>;
+
+    /// See [`FileSystemWrite::rename_tag`]
+    fn dyn_rename_tag(&self, old: &Tag, new: Tag) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::rename_group`]
+    fn dyn_rename_group(&self, old: &Group, new: Group) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::dedupe`]
+    #[cfg(feature = "std")]
+    fn dyn_dedupe(&self, policy: DedupePolicy) -> Result<Vec<Merge>, DynError>;
+
+    // Versions
+
+    /// See [`FileSystemRead::list_versions`]
+    fn dyn_list_versions(&self, id: FileId) -> Result<Vec<u32>, DynError>;
+
+    /// See [`FileSystemRead::get_version`]
+    fn dyn_get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, DynError>;
+
+    /// See [`FileSystemWrite::revert`]
+    fn dyn_revert(&self, id: FileId, version: u32) -> Result<(), DynError>;
+
+    // Special files
+
+    /// See [`FileSystemRead::special`]
+    fn dyn_special(&self, file: SpecialFile) -> Result<FileInfo, DynError>;
+
+    /// See [`FileSystemRead::special_data`]
+    fn dyn_special_data(&self, file: SpecialFile) -> Result<Vec<u8>, DynError>;
+
+    /// See [`FileSystemRead::config`]
+    fn dyn_config(&self) -> Result<Vec<u8>, DynError>;
+
+    /// See [`FileSystemWrite::set_special_data`]
+    fn dyn_set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), DynError>;
+
+    /// See [`FileSystemWrite::set_config`]
+    fn dyn_set_config(&self, data: &[u8]) -> Result<(), DynError>;
+
+    // Events
+
+    /// See [`FileSystemRead::subscribe`]
+    #[cfg(feature = "std")]
+    fn dyn_subscribe(&self) -> Result<std::sync::mpsc::Receiver<Event>, DynError>;
+
+    // Derived tags
+
+    /// See [`ProvidedTags::register_provider`]
+    fn dyn_register_provider(
+        &self,
+        group: Group,
+        provider: Box<dyn TagProvider>,
+    ) -> Result<(), DynError>;
+
+    /// See [`InferredTags::register_inferrer`]
+    fn dyn_register_inferrer(&self, inferrer: Box<dyn TagInferrer>) -> Result<(), DynError>;
+}
+
+impl<F> DynFileSystem for F
+where
+    F: FileSystem + ProvidedTags + InferredTags + DynStreams,
+    F::Error: DynFsError + 'static,
+{
+    fn dyn_search_tags(&self, tags: &TagPredicate) -> Result<Vec<FileId>, DynError> {
+        FileSystemRead::search_tags(self, tags.clone()).map_err(DynError::new)
+    }
+
+    fn dyn_search_tags_with(
+        &self,
+        tags: &TagPredicate,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, DynError> {
+        FileSystemRead::search_tags_with(self, tags.clone(), options).map_err(DynError::new)
+    }
+
+    fn dyn_search_tags_iter(&self, tags: &TagPredicate) -> DynSearchIter<'_> {
+        Box::new(
+            FileSystemRead::search_tags_iter(self, tags.clone())
+                .map(|id| id.map_err(DynError::new)),
+        )
+    }
+
+    fn dyn_search_detailed(&self, tags: &TagPredicate) -> Result<Vec<SearchHit>, DynError> {
+        FileSystemRead::search_detailed(self, tags.clone()).map_err(DynError::new)
+    }
+
+    fn dyn_get_info(&self, id: FileId) -> Result<FileInfo, DynError> {
+        FileSystemRead::get_info(self, id).map_err(DynError::new)
+    }
+
+    fn dyn_get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, DynError> {
+        FileSystemRead::get_tags(self, id).map_err(DynError::new)
+    }
+
+    fn dyn_get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, DynError> {
+        FileSystemRead::get_stored_tags(self, id).map_err(DynError::new)
+    }
+
+    fn dyn_get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, DynError> {
+        let found = FileSystemRead::get_info_many(self, ids).map_err(DynError::new)?;
+        Ok(found
+            .into_iter()
+            .map(|info| info.map_err(DynError::new))
+            .collect())
+    }
+
+    fn dyn_get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, DynError> {
+        let found = FileSystemRead::get_tags_many(self, ids).map_err(DynError::new)?;
+        Ok(found
+            .into_iter()
+            .map(|tags| tags.map_err(DynError::new))
+            .collect())
+    }
+
+    fn dyn_get_data(&self, id: FileId) -> Result<Vec<u8>, DynError> {
+        FileSystemRead::get_data(self, id).map_err(DynError::new)
+    }
+
+    fn dyn_read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, DynError> {
+        FileSystemRead::read_range(self, id, range).map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_get_metadata(&self, id: FileId) -> Result<Metadata, DynError> {
+        FileSystemRead::get_metadata(self, id).map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_read_file(&self, id: FileId) -> Result<Box<dyn Read + '_>, DynError> {
+        match StreamRead::read_file(self, id) {
+            Ok(reader) => Ok(Box::new(reader)),
+            Err(err) => Err(DynError::new(err)),
+        }
+    }
+
+    fn dyn_last_id(&self) -> Result<Option<FileId>, DynError> {
+        FileSystemRead::last_id(self).map_err(DynError::new)
+    }
+
+    fn dyn_ids_after(&self, after: FileId) -> Result<Vec<FileId>, DynError> {
+        FileSystemRead::ids_after(self, after).map_err(DynError::new)
+    }
+
+    fn dyn_tag_counts(&self, pattern: &TagPredicate) -> Result<BTreeMap<Tag, usize>, DynError> {
+        FileSystemRead::tag_counts(self, pattern.clone()).map_err(DynError::new)
+    }
+
+    fn dyn_count_tags(&self, pattern: &TagPredicate) -> Result<u64, DynError> {
+        FileSystemRead::count_tags(self, pattern.clone()).map_err(DynError::new)
+    }
+
+    fn dyn_any_match(&self, pattern: &TagPredicate) -> Result<bool, DynError> {
+        FileSystemRead::any_match(self, pattern.clone()).map_err(DynError::new)
+    }
+
+    fn dyn_list_groups(&self) -> Result<BTreeSet<Group>, DynError> {
+        FileSystemRead::list_groups(self).map_err(DynError::new)
+    }
+
+    fn dyn_list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, DynError> {
+        FileSystemRead::list_tags(self, group).map_err(DynError::new)
+    }
+
+    fn dyn_usage(&self) -> Result<UsageReport, DynError> {
+        FileSystemRead::usage(self).map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_find_duplicates(&self) -> Result<Vec<Vec<FileId>>, DynError> {
+        FileSystemRead::find_duplicates(self).map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, DynError> {
+        FileSystemRead::find_by_hash(self, hash).map_err(DynError::new)
+    }
+
+    fn dyn_add_file(&self, data: &[u8], tags: &[Tag]) -> Result<FileId, DynError> {
+        FileSystemWrite::add_file(self, data, tags.iter().cloned()).map_err(DynError::new)
+    }
+
+    fn dyn_add_file_with_id(&self, id: FileId, data: &[u8], tags: &[Tag]) -> Result<(), DynError> {
+        FileSystemWrite::add_file_with_id(self, id, data, tags.iter().cloned())
+            .map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_create_file(&self, tags: &[Tag]) -> Result<Box<dyn DynFileWriter + '_>, DynError> {
+        match StreamWrite::create_file(self, tags.iter().cloned()) {
+            Ok(writer) => Ok(Box::new(writer)),
+            Err(err) => Err(DynError::new(err)),
+        }
+    }
+
+    fn dyn_edit_file(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<&[Tag]>,
+    ) -> Result<(), DynError> {
+        FileSystemWrite::edit_file(self, id, data, tags.map(|tags| tags.iter().cloned()))
+            .map_err(DynError::new)
+    }
+
+    fn dyn_write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), DynError> {
+        FileSystemWrite::write_at(self, id, offset, data).map_err(DynError::new)
+    }
+
+    fn dyn_truncate(&self, id: FileId, len: u64) -> Result<(), DynError> {
+        FileSystemWrite::truncate(self, id, len).map_err(DynError::new)
+    }
+
+    fn dyn_supports_partial_writes(&self) -> bool {
+        FileSystemWrite::supports_partial_writes(self)
+    }
+
+    fn dyn_remove_file(&self, id: FileId) -> Result<(), DynError> {
+        FileSystemWrite::remove_file(self, id).map_err(DynError::new)
+    }
+
+    fn dyn_transaction(&self, f: &mut dyn FnMut() -> Result<(), DynError>) -> Result<(), DynError> {
+        // The closure's error can't be turned into ours, so any error will do to roll back, and
+        // the real one is returned once it has
+        let mut failed = None;
+        let out = FileSystemWrite::transaction(self, |_| {
+            f().map_err(|err| {
+                failed = Some(err);
+                F::Error::file_not_found(FileId::from_u64_unchecked(0))
+            })
+        });
+        match failed {
+            Some(err) => Err(err),
+            None => out.map_err(DynError::new),
+        }
+    }
+
+    fn dyn_add_tags(&self, id: FileId, tags: &[Tag]) -> Result<(), DynError> {
+        FileSystemWrite::add_tags(self, id, tags.iter().cloned()).map_err(DynError::new)
+    }
+
+    fn dyn_remove_tags(&self, id: FileId, tags: &[Tag]) -> Result<(), DynError> {
+        FileSystemWrite::remove_tags(self, id, tags.iter().cloned()).map_err(DynError::new)
+    }
+
+    fn dyn_add_tags_matching(
+        &self,
+        pattern: &TagPredicate,
+        tags: &[Tag],
+    ) -> Result<usize, DynError> {
+        FileSystemWrite::add_tags_matching(self, pattern.clone(), tags.iter().cloned())
+            .map_err(DynError::new)
+    }
+
+    fn dyn_remove_tags_matching(
+        &self,
+        pattern: &TagPredicate,
+        tags: &[Tag],
+    ) -> Result<usize, DynError> {
+        FileSystemWrite::remove_tags_matching(self, pattern.clone(), tags.iter().cloned())
+            .map_err(DynError::new)
+    }
+
+    fn dyn_rename_tag(&self, old: &Tag, new: Tag) -> Result<(), DynError> {
+        FileSystemWrite::rename_tag(self, old, new).map_err(DynError::new)
+    }
+
+    fn dyn_rename_group(&self, old: &Group, new: Group) -> Result<(), DynError> {
+        FileSystemWrite::rename_group(self, old, new).map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_dedupe(&self, policy: DedupePolicy) -> Result<Vec<Merge>, DynError> {
+        FileSystemWrite::dedupe(self, policy).map_err(DynError::new)
+    }
+
+    fn dyn_list_versions(&self, id: FileId) -> Result<Vec<u32>, DynError> {
+        FileSystemRead::list_versions(self, id).map_err(DynError::new)
+    }
+
+    fn dyn_get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, DynError> {
+        FileSystemRead::get_version(self, id, version).map_err(DynError::new)
+    }
+
+    fn dyn_revert(&self, id: FileId, version: u32) -> Result<(), DynError> {
+        FileSystemWrite::revert(self, id, version).map_err(DynError::new)
+    }
+
+    fn dyn_special(&self, file: SpecialFile) -> Result<FileInfo, DynError> {
+        FileSystemRead::special(self, file).map_err(DynError::new)
+    }
+
+    fn dyn_special_data(&self, file: SpecialFile) -> Result<Vec<u8>, DynError> {
+        FileSystemRead::special_data(self, file).map_err(DynError::new)
+    }
+
+    fn dyn_config(&self) -> Result<Vec<u8>, DynError> {
+        FileSystemRead::config(self).map_err(DynError::new)
+    }
+
+    fn dyn_set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), DynError> {
+        FileSystemWrite::set_special_data(self, file, data).map_err(DynError::new)
+    }
+
+    fn dyn_set_config(&self, data: &[u8]) -> Result<(), DynError> {
+        FileSystemWrite::set_config(self, data).map_err(DynError::new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dyn_subscribe(&self) -> Result<std::sync::mpsc::Receiver<Event>, DynError> {
+        FileSystemRead::subscribe(self).map_err(DynError::new)
+    }
+
+    fn dyn_register_provider(
+        &self,
+        group: Group,
+        provider: Box<dyn TagProvider>,
+    ) -> Result<(), DynError> {
+        ProvidedTags::register_provider(self, group, move |data: &[u8]| provider.provide(data))
+            .map_err(DynError::new)
+    }
+
+    fn dyn_register_inferrer(&self, inferrer: Box<dyn TagInferrer>) -> Result<(), DynError> {
+        InferredTags::register_inferrer(self, move |data: &[u8]| inferrer.infer(data))
+            .map_err(DynError::new)
+    }
+}
+
+/// A version of [`FileWriter`] which can be used as a trait object, returned by
+/// [`DynFileSystem::dyn_create_file`]
+#[cfg(feature = "std")]
+pub trait DynFileWriter: Write {
+    /// See [`FileWriter::commit`]
+    fn dyn_commit(self: Box<Self>) -> Result<FileId, DynError>;
+}
+
+#[cfg(feature = "std")]
+impl<W> DynFileWriter for W
+where
+    W: FileWriter,
+    W::Error: DynFsError + 'static,
+{
+    fn dyn_commit(self: Box<Self>) -> Result<FileId, DynError> {
+        FileWriter::commit(*self).map_err(DynError::new)
+    }
+}
+
+#[cfg(feature = "std")]
+impl FileWriter for Box<dyn DynFileWriter + '_> {
+    type Error = DynError;
+
+    fn commit(self) -> Result<FileId, Self::Error> {
+        DynFileWriter::dyn_commit(self)
+    }
+}
+
+/// An adapter which implements [`FileSystem`] for a boxed [`DynFileSystem`], so one picked at
+/// runtime can be used anywhere a filesystem is expected
+pub struct BoxedFs {
+    inner: Box<dyn DynFileSystem>,
+}
+
+impl BoxedFs {
+    /// Box a filesystem
+    pub fn new<F: DynFileSystem + 'static>(inner: F) -> BoxedFs {
+        BoxedFs {
+            inner: Box::new(inner),
+        }
+    }
+
+    /// Get the boxed filesystem
+    pub fn inner(&self) -> &dyn DynFileSystem {
+        &*self.inner
+    }
+
+    /// Unwrap the boxed filesystem
+    pub fn into_inner(self) -> Box<dyn DynFileSystem> {
+        self.inner
+    }
+}
+
+impl From<Box<dyn DynFileSystem>> for BoxedFs {
+    fn from(inner: Box<dyn DynFileSystem>) -> BoxedFs {
+        BoxedFs { inner }
+    }
+}
+
+impl FileSystemRead for BoxedFs {
+    type Error = DynError;
+    type SearchIter<'a, P>
+        = DynSearchIter<'a>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.dyn_search_tags(&tags.to_predicate())
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner
+            .dyn_search_tags_with(&tags.to_predicate(), options)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        self.inner.dyn_search_tags_iter(&tags.to_predicate())
+    }
+
+    fn search_detailed<P>(&self, tags: P) -> Result<Vec<SearchHit>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.dyn_search_detailed(&tags.to_predicate())
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        self.inner.dyn_get_info(id)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.dyn_get_tags(id)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.dyn_get_stored_tags(id)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        self.inner.dyn_get_info_many(ids)
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        self.inner.dyn_get_tags_many(ids)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.inner.dyn_get_data(id)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.inner.dyn_read_range(id, range)
+    }
+
+    #[cfg(feature = "std")]
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.inner.dyn_get_metadata(id)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        self.inner.dyn_last_id()
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.inner.dyn_ids_after(after)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.dyn_tag_counts(&pattern.to_predicate())
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.dyn_count_tags(&pattern.to_predicate())
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.dyn_any_match(&pattern.to_predicate())
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        self.inner.dyn_list_groups()
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.dyn_list_tags(group)
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        self.inner.dyn_usage()
+    }
+
+    #[cfg(feature = "std")]
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.inner.dyn_find_duplicates()
+    }
+
+    #[cfg(feature = "std")]
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.inner.dyn_find_by_hash(hash)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.inner.dyn_list_versions(id)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.inner.dyn_get_version(id, version)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        self.inner.dyn_special(file)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.inner.dyn_special_data(file)
+    }
+
+    fn config(&self) -> Result<Vec<u8>, Self::Error> {
+        self.inner.dyn_config()
+    }
+
+    #[cfg(feature = "std")]
+    fn subscribe(&self) -> Result<std::sync::mpsc::Receiver<Event>, Self::Error> {
+        self.inner.dyn_subscribe()
+    }
+}
+
+impl FileSystemWrite for BoxedFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner
+            .dyn_add_file(data, &tags.into_iter().collect::<Vec<_>>())
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner
+            .dyn_add_file_with_id(id, data, &tags.into_iter().collect::<Vec<_>>())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| tags.into_iter().collect::<Vec<_>>());
+        self.inner.dyn_edit_file(id, data, tags.as_deref())
+    }
+
+    fn write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.dyn_write_at(id, offset, data)
+    }
+
+    fn truncate(&self, id: FileId, len: u64) -> Result<(), Self::Error> {
+        self.inner.dyn_truncate(id, len)
+    }
+
+    fn supports_partial_writes(&self) -> bool {
+        self.inner.dyn_supports_partial_writes()
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.inner.dyn_remove_file(id)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // Changes made through `self` go to the inner filesystem, so are part of the transaction
+        let mut f = Some(f);
+        let mut out = None;
+        self.inner.dyn_transaction(&mut || {
+            if let Some(f) = f.take() {
+                out = Some(f(self)?);
+            }
+            Ok(())
+        })?;
+        Ok(out.expect("Transaction closure should have run"))
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner
+            .dyn_add_tags(id, &tags.into_iter().collect::<Vec<_>>())
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner
+            .dyn_remove_tags(id, &tags.into_iter().collect::<Vec<_>>())
+    }
+
+    fn add_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner.dyn_add_tags_matching(
+            &pattern.to_predicate(),
+            &tags.into_iter().collect::<Vec<_>>(),
+        )
+    }
+
+    fn remove_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner.dyn_remove_tags_matching(
+            &pattern.to_predicate(),
+            &tags.into_iter().collect::<Vec<_>>(),
+        )
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        self.inner.dyn_rename_tag(old, new)
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        self.inner.dyn_rename_group(old, new)
+    }
+
+    #[cfg(feature = "std")]
+    fn dedupe(&self, policy: DedupePolicy) -> Result<Vec<Merge>, Self::Error> {
+        self.inner.dyn_dedupe(policy)
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        self.inner.dyn_revert(id, version)
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.dyn_set_special_data(file, data)
+    }
+
+    fn set_config(&self, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.dyn_set_config(data)
+    }
+}
+
+impl ProvidedTags for BoxedFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.inner.dyn_register_provider(group, Box::new(provider))
+    }
+}
+
+impl InferredTags for BoxedFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inner.dyn_register_inferrer(Box::new(inferrer))
+    }
+}
+
+#[cfg(feature = "std")]
+impl StreamRead for BoxedFs {
+    type Reader<'a>
+        = Box<dyn Read + 'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.inner.dyn_read_file(id)
+    }
+}
+
+#[cfg(feature = "std")]
+impl StreamWrite for BoxedFs {
+    type Writer<'a>
+        = Box<dyn DynFileWriter + 'a>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner
+            .dyn_create_file(&tags.into_iter().collect::<Vec<_>>())
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::{BoxedFs, DynError};
+    use crate::{migrate, Error as _, ErrorKind, FileId, InMemoryFs, Tag};
+
+    #[test]
+    fn test_dyn() {
+        use super::DynFileSystem;
+        use crate::{FileSystemRead, FileSystemWrite};
+
+        let backends: Vec<Box<dyn DynFileSystem>> =
+            vec![Box::new(InMemoryFs::new()), Box::new(InMemoryFs::new())];
+        for fs in &backends {
+            let id = fs.dyn_add_file(&[1, 2], &[Tag::named("a")]).unwrap();
+            assert_eq!(fs.dyn_search_tags(&Tag::named("a").into()).unwrap(), [id]);
+            assert_eq!(fs.dyn_get_data(id).unwrap(), [1, 2]);
+
+            fs.dyn_edit_file(id, None, Some(&[Tag::named("b")]))
+                .unwrap();
+            assert_eq!(fs.dyn_get_tags(id).unwrap(), [Tag::named("b")].into());
+
+            let err = fs
+                .dyn_remove_file(FileId::from_u64_unchecked(1000))
+                .unwrap_err();
+            assert!(matches!(err.generic_kind(), ErrorKind::FileNotFound(_)));
+        }
+
+        // A failed transaction returns the closure's error, and undoes its changes
+        let fs = &backends[0];
+        let err = fs
+            .dyn_transaction(&mut || {
+                fs.dyn_add_file(&[3], &[Tag::named("c")])?;
+                Err(DynError::AlreadyExists(FileId::from_u64_unchecked(1)))
+            })
+            .unwrap_err();
+        assert!(matches!(err, DynError::AlreadyExists(_)));
+        assert!(fs
+            .dyn_search_tags(&Tag::named("c").into())
+            .unwrap()
+            .is_empty());
+
+        // With every trait in scope, calls on a filesystem aren't ambiguous
+        let fs = InMemoryFs::new();
+        let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        assert_eq!(fs.get_data(id).unwrap(), fs.dyn_get_data(id).unwrap());
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn test_dyn_error() {
+        use super::DynFileSystem;
+        use std::error::Error;
+
+        // The error of the filesystem behind is shown, and is the source
+        let fs: Box<dyn DynFileSystem> = Box::new(InMemoryFs::new());
+        let missing = FileId::from_u64_unchecked(1000);
+        let err = fs.dyn_get_data(missing).unwrap_err();
+        let expected = crate::imfs::Error::FileNotFound(missing).to_string();
+        assert_eq!(err.to_string(), expected);
+        assert_eq!(err.source().unwrap().to_string(), expected);
+        assert!(format!("{err:?}").contains("FileNotFound"));
+    }
+
+    #[test]
+    fn test_boxed() {
+        use crate::{FileSystemRead, FileSystemWrite};
+
+        let fs = BoxedFs::new(InMemoryFs::new());
+        let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        assert_eq!(fs.search_tags([Tag::named("a")]).unwrap(), [id]);
+        assert_eq!(fs.query().with_tag(Tag::named("a")).run().unwrap(), [id]);
+
+        assert!(fs
+            .transaction(|fs| {
+                fs.add_tags(id, [Tag::named("b")])?;
+                fs.remove_file(FileId::from_u64_unchecked(1000))
+            })
+            .is_err());
+        assert_eq!(fs.get_tags(id).unwrap(), [Tag::named("a")].into());
+
+        let dst = InMemoryFs::new();
+        migrate(&fs, &dst, Tag::named("a")).unwrap();
+        assert_eq!(dst.search_tags(Tag::named("a")).unwrap().len(), 1);
+    }
+}
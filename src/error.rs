@@ -1,8 +1,15 @@
 //! Common error trait and kind for all implementations of the main trait
+//!
+//! Every error type in this crate implements [`Display`](fmt::Display), and
+//! [`std::error::Error`] with the std feature. Errors wrapping another include its message in
+//! their own, and give its source as theirs, so each message in a chain is only shown once.
 
+use core::fmt;
 use core::marker::PhantomData;
 
 use crate::FileId;
+#[cfg(feature = "std")]
+use crate::Group;
 
 /// The generic kind of a TBF error. This abstracts the most common error possibilities for
 /// implementations. Some implementations may never produce errors with a specific kind, so if
@@ -11,18 +18,55 @@ use crate::FileId;
 pub enum ErrorKind<'a> {
     /// Error was for a file ID that doesn't exist
     FileNotFound(FileId),
+    /// Error was for a file ID that's already in use
+    AlreadyExists(FileId),
+    /// Error was for a prior version of a file that isn't kept
+    VersionNotFound(FileId, u32),
     /// Error was caused by another error being returned in the implementation. Only present
     /// with the std feature for now
     #[cfg(feature = "std")]
     Source(&'a (dyn std::error::Error + Send + Sync)),
     /// Error was due to an invalid state in the filesystem
     State,
+    /// Error was from trying to change a filesystem which is read-only
+    ReadOnly,
+    /// Error was from a change which would have gone over a limit on how much can be stored
+    QuotaExceeded,
+    /// Error was from a file being given tags which break a rule of the filesystem, such as more
+    /// than one tag in an exclusive group
+    InvalidTags,
+    /// Error was from a caller without permission to see or change what it tried to
+    PermissionDenied,
+    /// Error was from the store running out of room, such as a bounded store reaching its
+    /// capacity or an allocation failing
+    StorageFull,
     /// Error was caused by something else
     Other,
     /// Variant to ensure `'a` is always used, shouldn't be matched on directly
     __Phantom(PhantomData<&'a ()>),
 }
 
+impl fmt::Display for ErrorKind<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ErrorKind::FileNotFound(id) => write!(f, "file {id} not found"),
+            ErrorKind::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            ErrorKind::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            #[cfg(feature = "std")]
+            ErrorKind::Source(err) => write!(f, "{err}"),
+            ErrorKind::State => write!(f, "filesystem is in an invalid state"),
+            ErrorKind::ReadOnly => write!(f, "filesystem is read-only"),
+            ErrorKind::QuotaExceeded => write!(f, "change would go over a quota"),
+            ErrorKind::InvalidTags => write!(f, "tags break a rule of the filesystem"),
+            ErrorKind::PermissionDenied => write!(f, "permission denied"),
+            ErrorKind::StorageFull => write!(f, "filesystem is out of room"),
+            ErrorKind::Other | ErrorKind::__Phantom(_) => write!(f, "filesystem error"),
+        }
+    }
+}
+
 /// A common trait for all tag-based filesystem errors
 pub trait Error {
     /// Create an instance of this error for a file that wasn't found
@@ -30,10 +74,55 @@ pub trait Error {
     where
         Self: Sized;
 
+    /// Create an instance of this error for a file ID that's already in use
+    fn already_exists(id: FileId) -> Self
+    where
+        Self: Sized;
+
+    /// Create an instance of this error for a prior version of a file that isn't kept. By
+    /// default, this is the same as the file not being found.
+    fn version_not_found(id: FileId, version: u32) -> Self
+    where
+        Self: Sized,
+    {
+        let _ = version;
+        Self::file_not_found(id)
+    }
+
+    /// Create an instance of this error for a change to a file which needs more room than there
+    /// is, such as data too large to hold in memory. By default, this is the same as the file not
+    /// being found, as there's nothing closer to fall back on.
+    fn storage_full(id: FileId) -> Self
+    where
+        Self: Sized,
+    {
+        Self::file_not_found(id)
+    }
+
     /// Get the generic kind of this error
     fn generic_kind(&self) -> ErrorKind<'_>;
 }
 
+/// Formats a group for an error message, naming the default group as such
+#[cfg(feature = "std")]
+pub(crate) struct GroupName<'a>(pub(crate) &'a Group);
+
+#[cfg(feature = "std")]
+impl fmt::Display for GroupName<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0 {
+            Group::Default => write!(f, "the default group"),
+            Group::Custom(name) => write!(f, "group `{name}`"),
+        }
+    }
+}
+
+/// Check whether an error is for a file that doesn't exist
+#[cfg(feature = "std")]
+pub(crate) fn is_not_found<E: Error>(err: &E) -> bool {
+    matches!(err.generic_kind(), ErrorKind::FileNotFound(_))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -0,0 +1,98 @@
+//! Notifications of changes made to a filesystem
+//!
+//! With the std feature, callers can [`subscribe`](crate::FileSystemRead::subscribe) to a
+//! filesystem, receiving an [`Event`] for every change made through it from then on. Events are
+//! sent as each change is made, so with some backends, changes later undone by a failed
+//! transaction are still reported. Those which keep a change feed hold a transaction's events
+//! until it commits, so neither the feed nor subscribers see changes which were undone.
+//!
+//! The backends also keep a feed of every event as a [`Change`], numbered in the order they were
+//! made, so a caller which isn't always listening can catch up on the changes after the last one
+//! it saw with their `changes_since` method.
+
+#[cfg(feature = "std")]
+use std::sync::mpsc::{self, Receiver, Sender};
+#[cfg(feature = "std")]
+use std::sync::{Mutex, PoisonError};
+
+use crate::FileId;
+
+/// A change made to a filesystem
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum Event {
+    /// A new file was added
+    FileAdded(FileId),
+    /// The data of a file was changed
+    FileEdited(FileId),
+    /// A file was removed
+    FileRemoved(FileId),
+    /// The tags of a file were changed
+    TagsChanged(FileId),
+}
+
+impl Event {
+    /// Get the ID of the file this event is for
+    pub fn id(&self) -> FileId {
+        match self {
+            Event::FileAdded(id)
+            | Event::FileEdited(id)
+            | Event::FileRemoved(id)
+            | Event::TagsChanged(id) => *id,
+        }
+    }
+}
+
+/// A change recorded in a filesystem's change feed
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Change {
+    seq: u64,
+    event: Event,
+}
+
+impl Change {
+    #[cfg_attr(not(feature = "imfs"), allow(dead_code))]
+    pub(crate) fn new(seq: u64, event: Event) -> Change {
+        Change { seq, event }
+    }
+
+    /// Get the sequence number of this change. The first change made to a filesystem is 1, and
+    /// each one after is one more than the last.
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Get the event describing this change
+    pub fn event(&self) -> Event {
+        self.event
+    }
+}
+
+/// The senders for everyone subscribed to a filesystem
+#[cfg(feature = "std")]
+#[derive(Default)]
+pub(crate) struct Subscribers(Mutex<Vec<Sender<Event>>>);
+
+#[cfg(feature = "std")]
+impl Subscribers {
+    pub(crate) fn new() -> Subscribers {
+        Subscribers::default()
+    }
+
+    pub(crate) fn subscribe(&self) -> Receiver<Event> {
+        let (send, recv) = mpsc::channel();
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(send);
+        recv
+    }
+
+    /// Send an event to every subscriber, forgetting any whose receiver was dropped
+    pub(crate) fn emit(&self, event: Event) {
+        self.0
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .retain(|send| send.send(event).is_ok());
+    }
+}
@@ -0,0 +1,206 @@
+//! Tags and searchable text extracted from a file's contents
+
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use crate::{Group, Tag};
+
+/// What an [`Extractor`] found in a file's data
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Extraction {
+    tags: Vec<Tag>,
+    text: String,
+}
+
+impl Extraction {
+    /// Create an extraction which found nothing
+    pub fn new() -> Extraction {
+        Extraction::default()
+    }
+
+    /// Add tags derived from the data
+    #[must_use]
+    pub fn with_tags<I: IntoIterator<Item = Tag>>(mut self, tags: I) -> Self {
+        self.tags.extend(tags);
+        self
+    }
+
+    /// Add text the file should be found by when searching its contents
+    #[must_use]
+    pub fn with_text(mut self, text: &str) -> Self {
+        if text.is_empty() {
+            return self;
+        }
+        if !self.text.is_empty() {
+            self.text.push('\n');
+        }
+        self.text.push_str(text);
+        self
+    }
+
+    /// The tags derived from the data
+    pub fn tags(&self) -> &[Tag] {
+        &self.tags
+    }
+
+    /// The text the file should be found by
+    pub fn text(&self) -> &str {
+        &self.text
+    }
+
+    /// Add everything found by another extraction to this one
+    fn merge(self, other: Extraction) -> Extraction {
+        self.with_tags(other.tags).with_text(&other.text)
+    }
+}
+
+/// A source of tags and searchable text found in a file's data, such as the metadata and words
+/// of a document. Extractors are run by a [`TextIndexFs`](crate::TextIndexFs) whenever a file's
+/// data is added or edited. Their tags are stored with the file, replacing those extracted from
+/// its old data, and their text is indexed for searching.
+///
+/// Any function or closure of the form `Fn(&[u8]) -> Extraction` is an extractor.
+pub trait Extractor: Send + Sync {
+    /// Extract tags and text from a file's data
+    fn extract(&self, data: &[u8]) -> Extraction;
+}
+
+impl<F> Extractor for F
+where
+    F: Fn(&[u8]) -> Extraction + Send + Sync,
+{
+    fn extract(&self, data: &[u8]) -> Extraction {
+        self(data)
+    }
+}
+
+/// Extracts the whole of data which is UTF-8 as its text. Other data has no text.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct PlainTextExtractor;
+
+impl Extractor for PlainTextExtractor {
+    fn extract(&self, data: &[u8]) -> Extraction {
+        match core::str::from_utf8(data) {
+            Ok(text) => Extraction::new().with_text(text),
+            Err(_) => Extraction::new(),
+        }
+    }
+}
+
+/// Extracts the `ID3v1` tag at the end of an audio file, such as an MP3. Its title, artist and
+/// album become tags with their value in the `id3` group, along with its year if it has one, and
+/// they and its comment become text.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct Id3Extractor;
+
+impl Id3Extractor {
+    /// The length of an `ID3v1` tag, which is always the last bytes of the file
+    const LEN: usize = 128;
+}
+
+impl Extractor for Id3Extractor {
+    fn extract(&self, data: &[u8]) -> Extraction {
+        let Some(tag) = data
+            .len()
+            .checked_sub(Id3Extractor::LEN)
+            .map(|at| &data[at..])
+        else {
+            return Extraction::new();
+        };
+        if &tag[..3] != b"TAG" {
+            return Extraction::new();
+        }
+
+        // Fields are Latin-1, padded with nulls or spaces
+        let field = |range: core::ops::Range<usize>| {
+            let text = tag[range]
+                .iter()
+                .map(|&b| char::from(b))
+                .collect::<String>();
+            String::from(text.trim_end_matches(['\0', ' ']))
+        };
+        let group = Group::custom("id3");
+        let mut out = Extraction::new();
+        for (name, range) in [("title", 3..33), ("artist", 33..63), ("album", 63..93)] {
+            let value = field(range);
+            if !value.is_empty() {
+                out = out.with_text(&value);
+                out.tags
+                    .push(Tag::new(group.clone(), name).with_value(value));
+            }
+        }
+        if let Ok(year) = field(93..97).parse::<i64>() {
+            out.tags.push(Tag::new(group, "year").with_value(year));
+        }
+        out.with_text(&field(97..127))
+    }
+}
+
+/// Registered extractors, in the order they were added
+pub(crate) type Extractors = Vec<Box<dyn Extractor>>;
+
+/// Run every registered extractor over some data, combining what they found
+pub(crate) fn extract_all(extractors: &Extractors, data: &[u8]) -> Extraction {
+    extractors
+        .iter()
+        .map(|extractor| extractor.extract(data))
+        .fold(Extraction::new(), Extraction::merge)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_plain_text() {
+        assert_eq!(PlainTextExtractor.extract(b"some text").text(), "some text");
+        assert_eq!(PlainTextExtractor.extract(&[0xFF, 0xFE]), Extraction::new());
+    }
+
+    #[test]
+    fn test_id3() {
+        let mut data = vec![0; 16];
+        data.extend_from_slice(b"TAG");
+        for (field, len) in [
+            (&b"Song"[..], 30),
+            (b"Band", 30),
+            (b"", 30),
+            (b"1999", 4),
+            (b"Live", 30),
+        ] {
+            let mut field = field.to_vec();
+            field.resize(len, 0);
+            data.extend(field);
+        }
+        data.push(0);
+
+        let id3 = Group::custom("id3");
+        let out = Id3Extractor.extract(&data);
+        assert_eq!(
+            out.tags(),
+            [
+                Tag::new(id3.clone(), "title").with_value("Song"),
+                Tag::new(id3.clone(), "artist").with_value("Band"),
+                Tag::new(id3, "year").with_value(1999),
+            ]
+        );
+        assert_eq!(out.text(), "Song\nBand\nLive");
+
+        assert_eq!(Id3Extractor.extract(&[0; 200]), Extraction::new());
+        assert_eq!(Id3Extractor.extract(b"TAG"), Extraction::new());
+    }
+
+    #[test]
+    fn test_extract_all() {
+        let extractors: Extractors = vec![
+            Box::new(PlainTextExtractor),
+            Box::new(|data: &[u8]| {
+                Extraction::new().with_tags([Tag::named(data.len().to_string())])
+            }),
+        ];
+        let out = extract_all(&extractors, b"abc");
+        assert_eq!(out.text(), "abc");
+        assert_eq!(out.tags(), [Tag::named("3")]);
+    }
+}
@@ -1,5 +1,9 @@
 use alloc::borrow::Cow;
+use alloc::string::String;
+use core::cmp::Ordering;
 use core::convert::TryFrom;
+use core::fmt;
+use core::hash::{Hash, Hasher};
 
 /// Represents the ID of a file. Most numbers simply represent a unique file, however,
 /// the values 0-255 are reserved for special usage.
@@ -30,6 +34,13 @@ impl FileId {
     }
 }
 
+/// Formats the ID as a plain number
+impl fmt::Display for FileId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
 impl TryFrom<u64> for FileId {
     type Error = ();
 
@@ -42,6 +53,117 @@ impl TryFrom<u64> for FileId {
     }
 }
 
+/// The files with reserved IDs, whose contents have a fixed meaning. Most special files are
+/// generated by the filesystem as plain text, one entry per line. The rest are stored as given,
+/// see [`SpecialFile::is_generated`].
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[non_exhaustive]
+pub enum SpecialFile {
+    /// The ID of every file, in hex as used for file names
+    Manifest,
+    /// Every distinct tag in use, like `group:name=value`
+    TagRegistry,
+    /// The ID of every file which was removed, and whose ID hasn't been reused. Filesystems which
+    /// don't record removed files list the unused IDs below the last one allocated instead, up to
+    /// [`SpecialFile::TRASH_LIMIT`] of them.
+    Trash,
+    /// Arbitrary configuration data, which can be set by the user
+    Config,
+    /// Named predicates, as kept by [`SavedSearches`](crate::SavedSearches)
+    Searches,
+    /// Descriptions and rules of groups, as kept by [`TagRegistry`](crate::TagRegistry)
+    Groups,
+    /// The parent of each tag with one, as kept by [`TagHierarchy`](crate::TagHierarchy)
+    Hierarchy,
+    /// The tag each alias stands for, as kept by [`TagAliases`](crate::TagAliases)
+    Aliases,
+    /// A record of every change made through an [`AuditedFs`](crate::AuditedFs)
+    AuditLog,
+}
+
+impl SpecialFile {
+    /// Every special file, in order of ID
+    pub const ALL: &'static [SpecialFile] = &[
+        SpecialFile::Manifest,
+        SpecialFile::TagRegistry,
+        SpecialFile::Trash,
+        SpecialFile::Config,
+        SpecialFile::Searches,
+        SpecialFile::Groups,
+        SpecialFile::Hierarchy,
+        SpecialFile::Aliases,
+        SpecialFile::AuditLog,
+    ];
+
+    /// The most IDs listed in the trash by filesystems which don't record removed files. Files
+    /// added with a chosen ID can leave gaps of unused IDs far too big to list in full.
+    pub const TRASH_LIMIT: usize = 4096;
+
+    /// Get the reserved ID of this special file
+    pub fn id(self) -> FileId {
+        FileId(match self {
+            SpecialFile::Manifest => 0,
+            SpecialFile::TagRegistry => 1,
+            SpecialFile::Trash => 2,
+            SpecialFile::Config => 3,
+            SpecialFile::Searches => 4,
+            SpecialFile::Groups => 5,
+            SpecialFile::Hierarchy => 6,
+            SpecialFile::Aliases => 7,
+            SpecialFile::AuditLog => 8,
+        })
+    }
+
+    /// Get the special file with a reserved ID, if there is one
+    pub fn from_id(id: FileId) -> Option<SpecialFile> {
+        match id.0 {
+            0 => Some(SpecialFile::Manifest),
+            1 => Some(SpecialFile::TagRegistry),
+            2 => Some(SpecialFile::Trash),
+            3 => Some(SpecialFile::Config),
+            4 => Some(SpecialFile::Searches),
+            5 => Some(SpecialFile::Groups),
+            6 => Some(SpecialFile::Hierarchy),
+            7 => Some(SpecialFile::Aliases),
+            8 => Some(SpecialFile::AuditLog),
+            _ => None,
+        }
+    }
+
+    /// Get the name of this special file, as used for it in paths and keys by some filesystems
+    pub fn name(self) -> &'static str {
+        match self {
+            SpecialFile::Manifest => "manifest",
+            SpecialFile::TagRegistry => "tag_registry",
+            SpecialFile::Trash => "trash",
+            SpecialFile::Config => "config",
+            SpecialFile::Searches => "searches",
+            SpecialFile::Groups => "groups",
+            SpecialFile::Hierarchy => "hierarchy",
+            SpecialFile::Aliases => "aliases",
+            SpecialFile::AuditLog => "audit_log",
+        }
+    }
+
+    /// Get the special file with a name, if there is one
+    pub fn from_name(name: &str) -> Option<SpecialFile> {
+        SpecialFile::ALL
+            .iter()
+            .copied()
+            .find(|file| file.name() == name)
+    }
+
+    /// Whether the contents of this special file are generated from the state of the
+    /// filesystem. Those which aren't hold whatever data was last stored in them, and are empty
+    /// until then.
+    pub fn is_generated(self) -> bool {
+        matches!(
+            self,
+            SpecialFile::Manifest | SpecialFile::TagRegistry | SpecialFile::Trash
+        )
+    }
+}
+
 impl TryFrom<FileId> for u64 {
     type Error = ();
 
@@ -56,10 +178,11 @@ impl TryFrom<FileId> for u64 {
 
 /// The group associated with a tag. Many tags will be part of the 'default'
 /// group, but there can be any number of custom groups.
-#[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
+#[derive(Debug, Default, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub enum Group {
     /// The default group
+    #[default]
     Default,
     /// A group with a custom name
     Custom(Cow<'static, str>),
@@ -85,7 +208,7 @@ impl<I: Into<Cow<'static, str>>> From<I> for Group {
 
 impl PartialEq<str> for Group {
     fn eq(&self, other: &str) -> bool {
-        if other == "" {
+        if other.is_empty() {
             *self == Group::Default
         } else {
             matches!(self, Group::Custom(name) if name == other)
@@ -99,18 +222,151 @@ impl PartialEq<&str> for Group {
     }
 }
 
-impl Default for Group {
-    fn default() -> Self {
-        Group::Default
+/// Formats the name of a custom group, or nothing for the default group
+impl fmt::Display for Group {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Group::Default => Ok(()),
+            Group::Custom(name) => write!(f, "{name}"),
+        }
+    }
+}
+
+/// A typed value attached to a tag, turning it into a key-value pair
+///
+/// Values are totally ordered so tags can be stored in sets: different kinds of value are ordered
+/// by kind, and floats by [`f64::total_cmp`]. Predicates comparing values use
+/// [`TagValue::compare`] instead, which only orders values of compatible kinds.
+#[derive(Debug, Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum TagValue {
+    /// A string value
+    String(Cow<'static, str>),
+    /// An integer value
+    Int(i64),
+    /// A floating point value
+    Float(f64),
+    /// A boolean value
+    Bool(bool),
+    /// A point in time, as seconds since the Unix epoch
+    Timestamp(i64),
+}
+
+impl TagValue {
+    pub(crate) fn kind(&self) -> u8 {
+        match self {
+            TagValue::String(_) => 0,
+            TagValue::Int(_) => 1,
+            TagValue::Float(_) => 2,
+            TagValue::Bool(_) => 3,
+            TagValue::Timestamp(_) => 4,
+        }
+    }
+
+    /// Compare two values of compatible kinds, or `None` if they can't be compared. Ints and
+    /// floats can be compared with each other, while every other kind only compares with itself.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn compare(&self, other: &TagValue) -> Option<Ordering> {
+        match (self, other) {
+            (TagValue::String(a), TagValue::String(b)) => Some(a.cmp(b)),
+            (TagValue::Int(a), TagValue::Int(b))
+            | (TagValue::Timestamp(a), TagValue::Timestamp(b)) => Some(a.cmp(b)),
+            (TagValue::Float(a), TagValue::Float(b)) => a.partial_cmp(b),
+            (TagValue::Int(a), TagValue::Float(b)) => (*a as f64).partial_cmp(b),
+            (TagValue::Float(a), TagValue::Int(b)) => a.partial_cmp(&(*b as f64)),
+            (TagValue::Bool(a), TagValue::Bool(b)) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+impl PartialEq for TagValue {
+    fn eq(&self, other: &TagValue) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TagValue {}
+
+impl PartialOrd for TagValue {
+    fn partial_cmp(&self, other: &TagValue) -> Option<Ordering> {
+        Some(self.cmp(other))
     }
 }
 
-/// A file tag, with a name and optionally a tag group
+impl Ord for TagValue {
+    fn cmp(&self, other: &TagValue) -> Ordering {
+        match (self, other) {
+            (TagValue::String(a), TagValue::String(b)) => a.cmp(b),
+            (TagValue::Int(a), TagValue::Int(b))
+            | (TagValue::Timestamp(a), TagValue::Timestamp(b)) => a.cmp(b),
+            (TagValue::Float(a), TagValue::Float(b)) => a.total_cmp(b),
+            (TagValue::Bool(a), TagValue::Bool(b)) => a.cmp(b),
+            _ => self.kind().cmp(&other.kind()),
+        }
+    }
+}
+
+impl Hash for TagValue {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.kind().hash(state);
+        match self {
+            TagValue::String(val) => val.hash(state),
+            TagValue::Int(val) | TagValue::Timestamp(val) => val.hash(state),
+            TagValue::Float(val) => val.to_bits().hash(state),
+            TagValue::Bool(val) => val.hash(state),
+        }
+    }
+}
+
+impl fmt::Display for TagValue {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagValue::String(val) => write!(f, "{val}"),
+            TagValue::Int(val) | TagValue::Timestamp(val) => write!(f, "{val}"),
+            TagValue::Float(val) => write!(f, "{val}"),
+            TagValue::Bool(val) => write!(f, "{val}"),
+        }
+    }
+}
+
+impl From<&'static str> for TagValue {
+    fn from(val: &'static str) -> TagValue {
+        TagValue::String(Cow::Borrowed(val))
+    }
+}
+
+impl From<String> for TagValue {
+    fn from(val: String) -> TagValue {
+        TagValue::String(Cow::Owned(val))
+    }
+}
+
+impl From<i64> for TagValue {
+    fn from(val: i64) -> TagValue {
+        TagValue::Int(val)
+    }
+}
+
+impl From<f64> for TagValue {
+    fn from(val: f64) -> TagValue {
+        TagValue::Float(val)
+    }
+}
+
+impl From<bool> for TagValue {
+    fn from(val: bool) -> TagValue {
+        TagValue::Bool(val)
+    }
+}
+
+/// A file tag, with a name and optionally a tag group and a value
 #[derive(Debug, Clone, Eq, PartialEq, Hash, Ord, PartialOrd)]
 #[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct Tag {
     group: Group,
     name: Cow<'static, str>,
+    value: Option<TagValue>,
 }
 
 impl Tag {
@@ -119,6 +375,7 @@ impl Tag {
         Tag {
             group: group.into(),
             name: name.into(),
+            value: None,
         }
     }
 
@@ -127,9 +384,18 @@ impl Tag {
         Tag {
             group: Group::Default,
             name: name.into(),
+            value: None,
         }
     }
 
+    /// Set the value of this tag. Tags with different values are different tags, so a file can
+    /// have several values for the same group and name.
+    #[must_use]
+    pub fn with_value<V: Into<TagValue>>(mut self, value: V) -> Tag {
+        self.value = Some(value.into());
+        self
+    }
+
     /// Get the group for this tag
     pub fn group(&self) -> &Group {
         &self.group
@@ -139,4 +405,30 @@ impl Tag {
     pub fn name(&self) -> &str {
         &self.name
     }
+
+    /// Get the value of this tag, if it has one
+    pub fn value(&self) -> Option<&TagValue> {
+        self.value.as_ref()
+    }
+
+    /// Move this tag into a different group, keeping its name and value
+    pub(crate) fn in_group(mut self, group: Group) -> Tag {
+        self.group = group;
+        self
+    }
+}
+
+/// Formats a tag as `group:name=value`, leaving out the group if it's the default, and the value
+/// if there isn't one
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let Group::Custom(group) = &self.group {
+            write!(f, "{group}:")?;
+        }
+        write!(f, "{}", self.name)?;
+        if let Some(value) = &self.value {
+            write!(f, "={value}")?;
+        }
+        Ok(())
+    }
 }
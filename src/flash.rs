@@ -0,0 +1,1408 @@
+//! Implementation of a TBF on NOR flash, for microcontrollers
+//!
+//! The flash is split into its erasable sectors, which are filled one at a time as a log of
+//! changes. When the sector being written fills, the next empty one is started, wrapping around at
+//! the end of the flash, so each sector is erased about as often as every other. One sector is
+//! always kept empty: once the last other one is started, the records of the oldest sector which
+//! are still current are copied forward and it's erased.
+//!
+//! Each sector starts with a header of a magic number, the sector's place in the log, and the next
+//! file ID to hand out when it was started. Each record is then its length and a checksum, as
+//! little-endian `u32`s, followed by a kind byte and the change's fields, padded to the flash's
+//! write size. Tags are encoded the same way as in other stores. A record cut short by losing power
+//! fails its checksum, and the rest of its sector is left unused.
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::convert::{TryFrom, TryInto};
+use core::fmt;
+use core::ops::{Bound, Range};
+#[cfg(feature = "std")]
+use std::io::Cursor;
+#[cfg(feature = "std")]
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "std")]
+use std::time::{Duration, UNIX_EPOCH};
+
+use embedded_storage::nor_flash::{NorFlash, NorFlashError};
+use spin::{Mutex, MutexGuard, RwLock};
+
+use crate::error::ErrorKind;
+use crate::events::Event;
+#[cfg(feature = "std")]
+use crate::events::Subscribers;
+use crate::inference::{infer_tags, Inferrers};
+#[cfg(feature = "std")]
+use crate::metadata::{hash_data, now, Metadata};
+use crate::provider::{provide_tags, Providers};
+use crate::{
+    check_stored, FileId, FileInfo, FileSystemRead, FileSystemWrite, Group, InferredTags,
+    ProvidedTags, SpecialFile, Tag, TagInferrer, TagPattern, TagProvider, TagValue,
+};
+#[cfg(feature = "std")]
+use crate::{BufferedWriter, StreamRead, StreamWrite};
+
+const MAGIC: [u8; 4] = *b"TBF1";
+/// Length of a sector header before padding: the magic, the sector's place in the log, and the
+/// next file ID
+const HEADER_LEN: u32 = 16;
+/// Length of a record's length and checksum
+const FRAME_LEN: u32 = 8;
+/// Value of erased flash, which also marks the end of a sector's records
+const ERASED: u8 = 0xFF;
+
+const FILE: u8 = 0;
+const DATA: u8 = 1;
+const TAGS: u8 = 2;
+const REMOVE: u8 = 3;
+const SPECIAL: u8 = 4;
+const BEGIN: u8 = 5;
+const COMMIT: u8 = 6;
+/// Set on the kind of a record made in a transaction, which is only kept once a commit record
+/// follows it
+const IN_TRANSACTION: u8 = 0x80;
+
+/// Error for a flash filesystem
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The flash returned an error
+    Flash(E),
+    /// The requested file did not exist
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The flash has no room left for a change, even once records which aren't current are
+    /// cleared away
+    Full,
+    /// A change is too large to fit in one sector of the flash
+    TooLarge,
+    /// The flash has fewer than two sectors, or sectors too small to hold a record
+    TooSmall,
+}
+
+impl<E> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::AlreadyExists(id)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Error::Full
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Error::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Error::Full => ErrorKind::StorageFull,
+            Error::TooLarge => ErrorKind::QuotaExceeded,
+            Error::Flash(_) | Error::TooSmall => ErrorKind::Other,
+        }
+    }
+}
+
+impl<E: NorFlashError> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Flash(err) => write!(f, "flash error: {}", err.kind()),
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::Full => write!(f, "flash is full"),
+            Error::TooLarge => write!(f, "change is too large to fit in a sector"),
+            Error::TooSmall => write!(f, "flash is too small to hold a store"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: NorFlashError> std::error::Error for Error<E> {}
+
+/// Where some data is on the flash
+#[derive(Clone, Copy, Default)]
+struct Span {
+    at: u32,
+    len: u32,
+}
+
+/// What's kept in memory about a file. The addresses of the records its data and tags were last
+/// written in tell which records are still current when a sector is cleared.
+#[derive(Clone, Default)]
+struct Entry {
+    tags: BTreeSet<Tag>,
+    data: Span,
+    created: u64,
+    modified: u64,
+    data_record: u32,
+    tags_record: u32,
+}
+
+/// The in-memory index of a store, saved at the start of a transaction to undo it
+#[derive(Clone, Default)]
+struct Index {
+    files: BTreeMap<FileId, Entry>,
+    /// The data of each special file, with the address of the record it was written in
+    special: BTreeMap<SpecialFile, (u32, Span)>,
+}
+
+struct Transaction {
+    saved: Index,
+    /// Whether a record marking the start of the transaction was written yet
+    begun: bool,
+}
+
+/// The flash and where the log on it is up to
+struct State<S> {
+    flash: S,
+    sector_len: u32,
+    /// Length of a sector header, padded to the write size
+    header_len: u32,
+    /// The place in the log of each sector which was started, or `None` for empty ones
+    sectors: Vec<Option<u32>>,
+    head: usize,
+    write_at: u32,
+    next_seq: u32,
+    next_id: u64,
+    index: Index,
+    transaction: Option<Transaction>,
+}
+
+/// A tag-based filesystem stored on NOR flash, for use as the tag store of a microcontroller.
+/// Works without std, given an allocator.
+///
+/// Changes are appended to a log spread over every sector of the flash, so that no sector wears
+/// out before the others. Tags are kept in memory for searching, while file data stays on the
+/// flash and is read when it's needed. At least two sectors are needed, and each change has to fit
+/// in one, so a file's data and tags can't be larger than a sector. One sector is kept empty for
+/// clearing space, so the space for files is a sector less than the flash.
+///
+/// File IDs are never reused, and prior versions of file data aren't kept. Without std, there's no
+/// clock, so creation and modification times aren't recorded.
+pub struct FlashFs<S> {
+    state: Mutex<State<S>>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    #[cfg(feature = "std")]
+    subscribers: Subscribers,
+}
+
+impl<S: NorFlash> FlashFs<S> {
+    /// Open the store on a flash, replaying its log. Empty flash, or flash without a store, is
+    /// started on as an empty store, with each sector erased as it's first used.
+    pub fn new(flash: S) -> Result<FlashFs<S>, Error<S::Error>> {
+        Ok(FlashFs {
+            state: Mutex::new(State::load(flash)?),
+            providers: RwLock::new(BTreeMap::new()),
+            inferrers: RwLock::new(Vec::new()),
+            #[cfg(feature = "std")]
+            subscribers: Subscribers::new(),
+        })
+    }
+
+    /// Close the store, handing back the flash
+    pub fn into_inner(self) -> S {
+        self.state.into_inner().flash
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State<S>> {
+        self.state.lock()
+    }
+
+    /// Tell subscribers of a change
+    #[cfg_attr(not(feature = "std"), allow(clippy::unused_self))]
+    fn emit(&self, event: Event) {
+        #[cfg(feature = "std")]
+        self.subscribers.emit(event);
+        #[cfg(not(feature = "std"))]
+        let _ = event;
+    }
+
+    fn add(
+        &self,
+        id: Option<FileId>,
+        data: &[u8],
+        tags: BTreeSet<Tag>,
+    ) -> Result<FileId, Error<S::Error>> {
+        let mut tags = tags;
+        tags.extend(infer_tags(&self.inferrers.read(), data));
+
+        let mut state = self.lock();
+        let id = if let Some(id) = id {
+            if id.into_u64_unchecked() < 256 || state.index.files.contains_key(&id) {
+                return Err(Error::AlreadyExists(id));
+            }
+            // The counter can't move past the highest ID
+            let next = id
+                .into_u64_unchecked()
+                .checked_add(1)
+                .ok_or(Error::AlreadyExists(id))?;
+            state.next_id = state.next_id.max(next);
+            id
+        } else {
+            let id = FileId::from_u64_unchecked(state.next_id);
+            state.next_id += 1;
+            id
+        };
+        let time = timestamp();
+        state.put_file(id, (time, time), data, tags)?;
+        drop(state);
+
+        self.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn set_tags(
+        &self,
+        id: FileId,
+        f: impl FnOnce(&mut BTreeSet<Tag>),
+    ) -> Result<(), Error<S::Error>> {
+        let mut state = self.lock();
+        let mut tags = state.entry(id)?.tags.clone();
+        f(&mut tags);
+        state.put_tags(id, tags)?;
+        drop(state);
+
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+}
+
+impl<S: NorFlash> State<S> {
+    fn load(flash: S) -> Result<State<S>, Error<S::Error>> {
+        let sector_len = u32::try_from(S::ERASE_SIZE).map_err(|_| Error::TooLarge)?;
+        let header_len = align::<S>(HEADER_LEN);
+        let count = flash.capacity() / S::ERASE_SIZE;
+        if count < 2 || header_len + align::<S>(FRAME_LEN + 1) > sector_len {
+            return Err(Error::TooSmall);
+        }
+
+        let mut state = State {
+            flash,
+            sector_len,
+            header_len,
+            sectors: vec![None; count],
+            head: 0,
+            write_at: 0,
+            next_seq: 0,
+            next_id: 256,
+            index: Index::default(),
+            transaction: None,
+        };
+
+        for sector in 0..count {
+            let mut header = [0; HEADER_LEN as usize];
+            state.read(state.sector_start(sector), &mut header)?;
+            let (magic, rest) = header.split_at(4);
+            let (seq, next_id) = rest.split_at(4);
+            if magic == MAGIC {
+                state.sectors[sector] = Some(u32::from_le_bytes(seq.try_into().unwrap()));
+                let next_id = u64::from_le_bytes(next_id.try_into().unwrap());
+                state.next_id = state.next_id.max(next_id);
+            }
+        }
+
+        let mut order = (0..count)
+            .filter_map(|sector| state.sectors[sector].map(|seq| (seq, sector)))
+            .collect::<Vec<_>>();
+        order.sort_unstable();
+
+        let Some(&(last, head)) = order.last() else {
+            state.start(0)?;
+            return Ok(state);
+        };
+        for &(_, sector) in &order {
+            state.replay(sector)?;
+        }
+        state.abort();
+
+        // A record cut short leaves the flash after it partly written, so it can't be written
+        // over until the sector is erased
+        state.head = head;
+        state.next_seq = last + 1;
+        let end = state.sector_start(head) + state.sector_len;
+        if !state.is_erased(state.write_at, end - state.write_at)? {
+            state.write_at = end;
+        }
+        Ok(state)
+    }
+
+    fn sector_start(&self, sector: usize) -> u32 {
+        // Sectors fit in a `u32`, as flash is addressed with one
+        u32::try_from(sector).unwrap_or(u32::MAX) * self.sector_len
+    }
+
+    fn free_sectors(&self) -> usize {
+        self.sectors.iter().filter(|seq| seq.is_none()).count()
+    }
+
+    fn entry(&self, id: FileId) -> Result<&Entry, Error<S::Error>> {
+        self.index.files.get(&id).ok_or(Error::FileNotFound(id))
+    }
+
+    /// Read from the flash at any offset, widening the read to the flash's read size if needed
+    fn read(&mut self, at: u32, buf: &mut [u8]) -> Result<(), Error<S::Error>> {
+        let size = S::READ_SIZE;
+        let start = at as usize / size * size;
+        let end = (at as usize + buf.len()).div_ceil(size) * size;
+        if start == at as usize && end == at as usize + buf.len() {
+            return self.flash.read(at, buf).map_err(Error::Flash);
+        }
+
+        let mut wide = vec![0; end - start];
+        let offset = at as usize - start;
+        self.flash
+            .read(at - to_u32(offset), &mut wide)
+            .map_err(Error::Flash)?;
+        buf.copy_from_slice(&wide[offset..offset + buf.len()]);
+        Ok(())
+    }
+
+    fn read_span(&mut self, span: Span) -> Result<Vec<u8>, Error<S::Error>> {
+        let mut data = vec![0; span.len as usize];
+        self.read(span.at, &mut data)?;
+        Ok(data)
+    }
+
+    fn is_erased(&mut self, at: u32, len: u32) -> Result<bool, Error<S::Error>> {
+        let mut chunk = [0; 64];
+        let mut done = 0;
+        while done < len {
+            let step = (len - done).min(64);
+            let chunk = &mut chunk[..step as usize];
+            self.read(at + done, chunk)?;
+            if chunk.iter().any(|&byte| byte != ERASED) {
+                return Ok(false);
+            }
+            done += step;
+        }
+        Ok(true)
+    }
+
+    /// Read the record at an address, or `None` at the end of the sector's records
+    fn read_record(&mut self, at: u32, end: u32) -> Result<Option<Vec<u8>>, Error<S::Error>> {
+        if at + FRAME_LEN > end {
+            return Ok(None);
+        }
+        let mut frame = [0; FRAME_LEN as usize];
+        self.read(at, &mut frame)?;
+        let (len, sum) = frame.split_at(4);
+        let len = u32::from_le_bytes(len.try_into().unwrap());
+        if len == u32::MAX || len > end - at - FRAME_LEN {
+            return Ok(None);
+        }
+
+        let body = self.read_span(Span {
+            at: at + FRAME_LEN,
+            len,
+        })?;
+        if body.is_empty() || checksum(&body) != u32::from_le_bytes(sum.try_into().unwrap()) {
+            return Ok(None);
+        }
+        Ok(Some(body))
+    }
+
+    /// Replay the records of a sector, leaving the write position at their end
+    fn replay(&mut self, sector: usize) -> Result<(), Error<S::Error>> {
+        let mut at = self.sector_start(sector) + self.header_len;
+        let end = self.sector_start(sector) + self.sector_len;
+        while let Some(body) = self.read_record(at, end)? {
+            match body[0] {
+                BEGIN => {
+                    self.abort();
+                    self.begin();
+                }
+                COMMIT => self.transaction = None,
+                kind => {
+                    // The start of a transaction may have been in a sector which was cleared
+                    if kind & IN_TRANSACTION == 0 {
+                        self.abort();
+                    } else if self.transaction.is_none() {
+                        self.begin();
+                    }
+                    self.apply(at, &body);
+                }
+            }
+            at += align::<S>(FRAME_LEN + to_u32(body.len()));
+        }
+        self.write_at = at;
+        Ok(())
+    }
+
+    fn begin(&mut self) {
+        self.transaction = Some(Transaction {
+            saved: self.index.clone(),
+            begun: false,
+        });
+    }
+
+    /// Undo the changes of a running transaction
+    fn abort(&mut self) {
+        if let Some(transaction) = self.transaction.take() {
+            self.index = transaction.saved;
+        }
+    }
+
+    /// Make the change a record describes to the index of a store being loaded. Records which
+    /// can't be read are skipped. A file's data and tags may have been copied forward separately,
+    /// so either can be read before the other.
+    fn apply(&mut self, at: u32, body: &[u8]) -> Option<()> {
+        let mut input = &body[1..];
+        let input = &mut input;
+        let data_at =
+            |input: &[u8], len: usize| at + FRAME_LEN + to_u32(body.len() - input.len() - len);
+        match body[0] & !IN_TRANSACTION {
+            FILE => {
+                let id = read_id(input)?;
+                let created = read_u64(input)?;
+                let modified = read_u64(input)?;
+                let data = read_bytes(input)?;
+                let data = Span {
+                    at: data_at(input, data.len()),
+                    len: to_u32(data.len()),
+                };
+                let tags = read_tags(input)?;
+                self.next_id = self.next_id.max(id.into_u64_unchecked() + 1);
+                self.index.files.insert(
+                    id,
+                    Entry {
+                        tags,
+                        data,
+                        created,
+                        modified,
+                        data_record: at,
+                        tags_record: at,
+                    },
+                );
+            }
+            DATA => {
+                let id = read_id(input)?;
+                let created = read_u64(input)?;
+                let modified = read_u64(input)?;
+                let data = read_bytes(input)?;
+                let data = Span {
+                    at: data_at(input, data.len()),
+                    len: to_u32(data.len()),
+                };
+                let entry = self.index.files.entry(id).or_default();
+                entry.data = data;
+                entry.created = created;
+                entry.modified = modified;
+                entry.data_record = at;
+            }
+            TAGS => {
+                let id = read_id(input)?;
+                let tags = read_tags(input)?;
+                let entry = self.index.files.entry(id).or_default();
+                entry.tags = tags;
+                entry.tags_record = at;
+            }
+            REMOVE => {
+                self.index.files.remove(&read_id(input)?);
+            }
+            SPECIAL => {
+                let file = SpecialFile::from_id(read_id(input)?)?;
+                let data = read_bytes(input)?;
+                let data = Span {
+                    at: data_at(input, data.len()),
+                    len: to_u32(data.len()),
+                };
+                self.index.special.insert(file, (at, data));
+            }
+            _ => (),
+        }
+        Some(())
+    }
+
+    /// Start the log in an empty sector, erasing it first if it isn't blank
+    fn start(&mut self, sector: usize) -> Result<(), Error<S::Error>> {
+        let start = self.sector_start(sector);
+        if !self.is_erased(start, self.sector_len)? {
+            self.flash
+                .erase(start, start + self.sector_len)
+                .map_err(Error::Flash)?;
+        }
+
+        let mut header = Vec::new();
+        header.extend_from_slice(&MAGIC);
+        header.extend_from_slice(&self.next_seq.to_le_bytes());
+        header.extend_from_slice(&self.next_id.to_le_bytes());
+        header.resize(self.header_len as usize, ERASED);
+        self.flash.write(start, &header).map_err(Error::Flash)?;
+
+        self.sectors[sector] = Some(self.next_seq);
+        self.next_seq += 1;
+        self.head = sector;
+        self.write_at = start + self.header_len;
+        Ok(())
+    }
+
+    /// Start new sectors until a record of the given length fits. Outside a transaction, the
+    /// oldest sector is cleared whenever the last empty one is started, leaving one for the next
+    /// time. A transaction can't clear sectors, which would save its changes before it commits, so
+    /// it can only use the empty sectors other than the last.
+    fn advance(&mut self, len: u32) -> Result<(), Error<S::Error>> {
+        let count = self.sectors.len();
+        for _ in 0..count {
+            let kept = usize::from(self.transaction.is_some());
+            if self.free_sectors() <= kept {
+                return Err(Error::Full);
+            }
+            let next = (1..count)
+                .map(|step| (self.head + step) % count)
+                .find(|&sector| self.sectors[sector].is_none())
+                .ok_or(Error::Full)?;
+            self.start(next)?;
+
+            if self.transaction.is_none() && self.free_sectors() == 0 {
+                self.collect()?;
+            }
+            if self.write_at + len <= self.sector_start(self.head) + self.sector_len {
+                return Ok(());
+            }
+        }
+        Err(Error::Full)
+    }
+
+    /// Copy the current records of the oldest sector to the head of the log, then erase it. Run
+    /// straight after starting a sector, so what's copied always fits.
+    fn collect(&mut self) -> Result<(), Error<S::Error>> {
+        let oldest = (0..self.sectors.len())
+            .filter(|&sector| sector != self.head)
+            .filter_map(|sector| self.sectors[sector].map(|seq| (seq, sector)))
+            .min();
+        let Some((_, oldest)) = oldest else {
+            return Ok(());
+        };
+
+        let start = self.sector_start(oldest);
+        let end = start + self.sector_len;
+        let mut at = start + self.header_len;
+        while let Some(body) = self.read_record(at, end)? {
+            let len = align::<S>(FRAME_LEN + to_u32(body.len()));
+            self.keep(at, body)?;
+            at += len;
+        }
+
+        self.flash.erase(start, end).map_err(Error::Flash)?;
+        self.sectors[oldest] = None;
+        Ok(())
+    }
+
+    /// Copy a record to the head of the log if any of it is still current. Transactions which
+    /// committed are done with, so their records are copied as plain ones.
+    fn keep(&mut self, at: u32, mut body: Vec<u8>) -> Result<(), Error<S::Error>> {
+        body[0] &= !IN_TRANSACTION;
+        let kind = body[0];
+        let Some(id) = read_id(&mut &body[1..]) else {
+            return Ok(());
+        };
+
+        match kind {
+            FILE | DATA | TAGS => {
+                let Some(entry) = self.index.files.get(&id) else {
+                    return Ok(());
+                };
+                let data_live = kind != TAGS && entry.data_record == at;
+                let tags_live = kind != DATA && entry.tags_record == at;
+                match (data_live, tags_live) {
+                    (false, false) => return Ok(()),
+                    (true, false) if kind == FILE => {
+                        // The data of a file record is where it is in a data record, so the tags
+                        // can just be cut off
+                        body[0] = DATA;
+                        body.truncate((entry.data.at - at - FRAME_LEN + entry.data.len) as usize);
+                    }
+                    (false, true) if kind == FILE => body = encode_tags(id, &entry.tags)?,
+                    _ => (),
+                }
+
+                let new_at = self.write_record(&body)?;
+                let entry = self.index.files.get_mut(&id).unwrap();
+                if data_live {
+                    entry.data.at = entry.data.at - at + new_at;
+                    entry.data_record = new_at;
+                }
+                if tags_live {
+                    entry.tags_record = new_at;
+                }
+            }
+            SPECIAL => {
+                let Some(file) = SpecialFile::from_id(id) else {
+                    return Ok(());
+                };
+                match self.index.special.get(&file) {
+                    Some(&(record, _)) if record == at => (),
+                    _ => return Ok(()),
+                }
+
+                let new_at = self.write_record(&body)?;
+                let (record, data) = self.index.special.get_mut(&file).unwrap();
+                *record = new_at;
+                data.at = data.at - at + new_at;
+            }
+            _ => (),
+        }
+        Ok(())
+    }
+
+    /// Write a record to the head of the log, returning its address
+    fn write_record(&mut self, body: &[u8]) -> Result<u32, Error<S::Error>> {
+        let body_len = u32::try_from(body.len()).map_err(|_| Error::TooLarge)?;
+        let len = align::<S>(FRAME_LEN + body_len);
+        if len > self.sector_len - self.header_len {
+            return Err(Error::TooLarge);
+        }
+        if self.write_at + len > self.sector_start(self.head) + self.sector_len {
+            self.advance(len)?;
+        }
+
+        let mut record = Vec::with_capacity(len as usize);
+        record.extend_from_slice(&body_len.to_le_bytes());
+        record.extend_from_slice(&checksum(body).to_le_bytes());
+        record.extend_from_slice(body);
+        record.resize(len as usize, ERASED);
+        self.flash
+            .write(self.write_at, &record)
+            .map_err(Error::Flash)?;
+
+        let at = self.write_at;
+        self.write_at += len;
+        Ok(at)
+    }
+
+    /// Write a record for a change, marking it as part of the running transaction if there is one
+    fn append(&mut self, mut body: Vec<u8>) -> Result<u32, Error<S::Error>> {
+        if let Some(transaction) = &mut self.transaction {
+            body[0] |= IN_TRANSACTION;
+            if !transaction.begun {
+                transaction.begun = true;
+                self.write_record(&[BEGIN])?;
+            }
+        }
+        self.write_record(&body)
+    }
+
+    /// Write the whole of a file, with data offset from its record as encoded
+    fn put_file(
+        &mut self,
+        id: FileId,
+        (created, modified): (u64, u64),
+        data: &[u8],
+        tags: BTreeSet<Tag>,
+    ) -> Result<(), Error<S::Error>> {
+        let (body, offset) = encode_file(FILE, id, (created, modified), data)?;
+        let mut body = body;
+        write_tags(&mut body, &tags)?;
+        let at = self.append(body)?;
+        self.index.files.insert(
+            id,
+            Entry {
+                tags,
+                data: Span {
+                    at: at + FRAME_LEN + offset,
+                    len: to_u32(data.len()),
+                },
+                created,
+                modified,
+                data_record: at,
+                tags_record: at,
+            },
+        );
+        Ok(())
+    }
+
+    fn put_data(&mut self, id: FileId, data: &[u8]) -> Result<(), Error<S::Error>> {
+        let created = self.entry(id)?.created;
+        let modified = timestamp();
+        let (body, offset) = encode_file(DATA, id, (created, modified), data)?;
+        let at = self.append(body)?;
+        let entry = self
+            .index
+            .files
+            .get_mut(&id)
+            .ok_or(Error::FileNotFound(id))?;
+        entry.data = Span {
+            at: at + FRAME_LEN + offset,
+            len: to_u32(data.len()),
+        };
+        entry.modified = modified;
+        entry.data_record = at;
+        Ok(())
+    }
+
+    fn put_tags(&mut self, id: FileId, tags: BTreeSet<Tag>) -> Result<(), Error<S::Error>> {
+        let at = self.append(encode_tags(id, &tags)?)?;
+        let entry = self
+            .index
+            .files
+            .get_mut(&id)
+            .ok_or(Error::FileNotFound(id))?;
+        entry.tags = tags;
+        entry.tags_record = at;
+        Ok(())
+    }
+
+    /// Finish the running transaction, writing a record to keep its changes if it made any. The
+    /// record is written as part of the transaction, so nothing's copied forward before it.
+    fn commit(&mut self) -> Result<(), Error<S::Error>> {
+        if self
+            .transaction
+            .as_ref()
+            .is_some_and(|transaction| transaction.begun)
+        {
+            self.write_record(&[COMMIT])?;
+        }
+        self.transaction = None;
+        Ok(())
+    }
+}
+
+impl<S: NorFlash> FileSystemRead for FlashFs<S> {
+    type Error = Error<S::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, S, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags,
+            cursor: Bound::Unbounded,
+            done: false,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let providers = self.providers.read();
+        let mut state = self.lock();
+        let entry = state.entry(id)?;
+        let (mut tags, span) = (entry.tags.clone(), entry.data);
+        let data = state.read_span(span)?;
+        tags.extend(provide_tags(&providers, &data));
+
+        Ok(FileInfo {
+            id,
+            tags,
+            data: data.into_boxed_slice(),
+        })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let providers = self.providers.read();
+        let mut state = self.lock();
+        let entry = state.entry(id)?;
+        let (mut tags, span) = (entry.tags.clone(), entry.data);
+        if !providers.is_empty() {
+            tags.extend(provide_tags(&providers, &state.read_span(span)?));
+        }
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.lock().entry(id)?.tags.clone())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        let mut state = self.lock();
+        let span = state.entry(id)?.data;
+        state.read_span(span)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let mut state = self.lock();
+        let span = state.entry(id)?.data;
+        let part = crate::clamp_range(range, span.len as usize);
+        state.read_span(Span {
+            at: span.at + to_u32(part.start),
+            len: to_u32(part.len()),
+        })
+    }
+
+    #[cfg(feature = "std")]
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let mut state = self.lock();
+        let entry = state.entry(id)?;
+        let (created, modified, span) = (entry.created, entry.modified, entry.data);
+        let data = state.read_span(span)?;
+
+        Ok(Metadata {
+            created: UNIX_EPOCH + Duration::from_nanos(created),
+            modified: UNIX_EPOCH + Duration::from_nanos(modified),
+            size: data.len() as u64,
+            hash: hash_data(&data),
+        })
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let next = self.lock().next_id;
+        if next > 256 {
+            Ok(Some(FileId::from_u64_unchecked(next - 1)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self
+            .lock()
+            .index
+            .files
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        let mut state = self.lock();
+        match state.index.special.get(&file) {
+            Some(&(_, span)) => state.read_span(span),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    #[cfg(feature = "std")]
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl<S: NorFlash> FileSystemWrite for FlashFs<S> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.add(None, data, tags.into_iter().collect())
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.add(Some(id), data, tags.into_iter().collect())
+            .map(|_| ())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut state = self.lock();
+        let created = state.entry(id)?.created;
+        match (data, tags) {
+            (Some(data), Some(tags)) => {
+                let tags = tags.into_iter().collect();
+                state.put_file(id, (created, timestamp()), data, tags)?;
+                drop(state);
+                self.emit(Event::FileEdited(id));
+                self.emit(Event::TagsChanged(id));
+            }
+            (Some(data), None) => {
+                state.put_data(id, data)?;
+                drop(state);
+                self.emit(Event::FileEdited(id));
+            }
+            (None, Some(tags)) => {
+                state.put_tags(id, tags.into_iter().collect())?;
+                drop(state);
+                self.emit(Event::TagsChanged(id));
+            }
+            (None, None) => (),
+        }
+        Ok(())
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.set_tags(id, |file_tags| file_tags.extend(tags))
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.set_tags(id, |file_tags| {
+            for tag in tags {
+                file_tags.remove(&tag);
+            }
+        })
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let mut state = self.lock();
+        state.entry(id)?;
+        let mut body = vec![REMOVE];
+        write_u64(&mut body, id.into_u64_unchecked());
+        state.append(body)?;
+        state.index.files.remove(&id);
+        drop(state);
+
+        self.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut state = self.lock();
+            if state.transaction.is_some() {
+                drop(state);
+                return f(self);
+            }
+            state.begin();
+        }
+
+        let out = f(self);
+
+        let mut state = self.lock();
+        let out = out.and_then(|val| state.commit().map(|()| val));
+        state.abort();
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        check_stored::<Self::Error>(file)?;
+        let mut body = vec![SPECIAL];
+        write_u64(&mut body, file.id().into_u64_unchecked());
+        write_len(&mut body, data.len())?;
+        let offset = to_u32(body.len());
+        body.extend_from_slice(data);
+
+        let mut state = self.lock();
+        let at = state.append(body)?;
+        let span = Span {
+            at: at + FRAME_LEN + offset,
+            len: to_u32(data.len()),
+        };
+        state.index.special.insert(file, (at, span));
+        Ok(())
+    }
+}
+
+impl<S: NorFlash> ProvidedTags for FlashFs<S> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers.write().insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl<S: NorFlash> InferredTags for FlashFs<S> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers.write().push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: NorFlash> StreamRead for FlashFs<S> {
+    type Reader<'a>
+        = Cursor<Vec<u8>>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(Cursor::new(self.get_data(id)?))
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: NorFlash> StreamWrite for FlashFs<S> {
+    type Writer<'a>
+        = Writer<'a, S>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+/// Round a length up to a multiple of the flash's write size
+fn align<S: NorFlash>(len: u32) -> u32 {
+    // Write sizes are a handful of bytes
+    let size = u32::try_from(S::WRITE_SIZE).unwrap_or(u32::MAX);
+    len.div_ceil(size) * size
+}
+
+/// Convert a length to a `u32`. Flash is addressed with one, so lengths on it always fit.
+fn to_u32(len: usize) -> u32 {
+    u32::try_from(len).unwrap_or(u32::MAX)
+}
+
+/// FNV-1a, to tell a whole record from one cut short
+fn checksum(data: &[u8]) -> u32 {
+    data.iter().fold(0x811c_9dc5, |hash, &byte| {
+        (hash ^ u32::from(byte)).wrapping_mul(0x0100_0193)
+    })
+}
+
+/// The current time in nanoseconds since the Unix epoch, or zero without a clock
+fn timestamp() -> u64 {
+    #[cfg(feature = "std")]
+    return now()
+        .duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0);
+    #[cfg(not(feature = "std"))]
+    0
+}
+
+fn write_u64(out: &mut Vec<u8>, val: u64) {
+    out.extend_from_slice(&val.to_le_bytes());
+}
+
+fn write_len<E>(out: &mut Vec<u8>, len: usize) -> Result<(), Error<E>> {
+    let len = u32::try_from(len).map_err(|_| Error::TooLarge)?;
+    out.extend_from_slice(&len.to_le_bytes());
+    Ok(())
+}
+
+fn write_str<E>(out: &mut Vec<u8>, val: &str) -> Result<(), Error<E>> {
+    write_len(out, val.len())?;
+    out.extend_from_slice(val.as_bytes());
+    Ok(())
+}
+
+fn write_tags<E>(out: &mut Vec<u8>, tags: &BTreeSet<Tag>) -> Result<(), Error<E>> {
+    write_len(out, tags.len())?;
+    for tag in tags {
+        let mut flags = 0;
+        if let Group::Custom(_) = tag.group() {
+            flags |= 1;
+        }
+        if tag.value().is_some() {
+            flags |= 2;
+        }
+        out.push(flags);
+
+        if let Group::Custom(group) = tag.group() {
+            write_str(out, group)?;
+        }
+        write_str(out, tag.name())?;
+        match tag.value() {
+            None => (),
+            Some(TagValue::String(val)) => {
+                out.push(0);
+                write_str(out, val)?;
+            }
+            Some(TagValue::Int(val)) => {
+                out.push(1);
+                out.extend_from_slice(&val.to_le_bytes());
+            }
+            Some(TagValue::Float(val)) => {
+                out.push(2);
+                write_u64(out, val.to_bits());
+            }
+            Some(TagValue::Bool(val)) => out.extend_from_slice(&[3, u8::from(*val)]),
+            Some(TagValue::Timestamp(val)) => {
+                out.push(4);
+                out.extend_from_slice(&val.to_le_bytes());
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Encode a file or data record up to the end of its data, returning it with the offset of the
+/// data in it
+fn encode_file<E>(
+    kind: u8,
+    id: FileId,
+    (created, modified): (u64, u64),
+    data: &[u8],
+) -> Result<(Vec<u8>, u32), Error<E>> {
+    let mut out = vec![kind];
+    write_u64(&mut out, id.into_u64_unchecked());
+    write_u64(&mut out, created);
+    write_u64(&mut out, modified);
+    write_len(&mut out, data.len())?;
+    let offset = to_u32(out.len());
+    out.extend_from_slice(data);
+    Ok((out, offset))
+}
+
+fn encode_tags<E>(id: FileId, tags: &BTreeSet<Tag>) -> Result<Vec<u8>, Error<E>> {
+    let mut out = vec![TAGS];
+    write_u64(&mut out, id.into_u64_unchecked());
+    write_tags(&mut out, tags)?;
+    Ok(out)
+}
+
+fn take<'a>(input: &mut &'a [u8], len: usize) -> Option<&'a [u8]> {
+    if input.len() < len {
+        return None;
+    }
+    let (out, rest) = input.split_at(len);
+    *input = rest;
+    Some(out)
+}
+
+fn read_u32(input: &mut &[u8]) -> Option<u32> {
+    Some(u32::from_le_bytes(take(input, 4)?.try_into().ok()?))
+}
+
+fn read_u64(input: &mut &[u8]) -> Option<u64> {
+    Some(u64::from_le_bytes(take(input, 8)?.try_into().ok()?))
+}
+
+fn read_id(input: &mut &[u8]) -> Option<FileId> {
+    read_u64(input).map(FileId::from_u64_unchecked)
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let len = read_u32(input)?;
+    take(input, len as usize)
+}
+
+fn read_str(input: &mut &[u8]) -> Option<String> {
+    String::from_utf8(read_bytes(input)?.to_vec()).ok()
+}
+
+fn read_tags(input: &mut &[u8]) -> Option<BTreeSet<Tag>> {
+    let len = read_u32(input)?;
+    (0..len)
+        .map(|_| {
+            let flags = take(input, 1)?[0];
+            let group = if flags & 1 == 0 {
+                Group::Default
+            } else {
+                Group::Custom(Cow::Owned(read_str(input)?))
+            };
+            let tag = Tag::new(group, read_str(input)?);
+            if flags & 2 == 0 {
+                return Some(tag);
+            }
+            let value = match take(input, 1)?[0] {
+                0 => TagValue::from(read_str(input)?),
+                1 => TagValue::Int(read_u64(input)?.cast_signed()),
+                2 => TagValue::Float(f64::from_bits(read_u64(input)?)),
+                3 => TagValue::Bool(take(input, 1)?[0] != 0),
+                4 => TagValue::Timestamp(read_u64(input)?.cast_signed()),
+                _ => return None,
+            };
+            Some(tag.with_value(value))
+        })
+        .collect()
+}
+
+/// A lazy search over a [`FlashFs`]. The store is only locked while finding each match, so files
+/// added or removed during iteration may or may not be seen.
+pub struct SearchIter<'a, S, P> {
+    fs: &'a FlashFs<S>,
+    pattern: P,
+    cursor: Bound<FileId>,
+    done: bool,
+}
+
+impl<S: NorFlash, P: TagPattern> SearchIter<'_, S, P> {
+    fn advance(&mut self) -> Result<Option<FileId>, Error<S::Error>> {
+        let providers = self.fs.providers.read();
+        let mut state = self.fs.lock();
+        loop {
+            let Some((&id, entry)) = state
+                .index
+                .files
+                .range((self.cursor, Bound::Unbounded))
+                .next()
+            else {
+                return Ok(None);
+            };
+            self.cursor = Bound::Excluded(id);
+
+            let matched = if providers.is_empty() {
+                self.pattern.match_tags(&entry.tags)
+            } else {
+                let (tags, span) = (entry.tags.clone(), entry.data);
+                let provided = provide_tags(&providers, &state.read_span(span)?);
+                self.pattern.match_tags(tags.iter().chain(&provided))
+            };
+
+            if matched {
+                return Ok(Some(id));
+            }
+        }
+    }
+}
+
+impl<S: NorFlash, P: TagPattern> Iterator for SearchIter<'_, S, P> {
+    type Item = Result<FileId, Error<S::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let out = self.advance().transpose();
+        self.done = !matches!(out, Some(Ok(_)));
+        out
+    }
+}
+
+/// A handle streaming data into a new file of a [`FlashFs`]. Data is buffered until the handle is
+/// flushed, which adds the file or updates its data if it already exists.
+#[cfg(feature = "std")]
+pub type Writer<'a, S> = BufferedWriter<'a, FlashFs<S>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use embedded_storage::nor_flash::{ErrorType, NorFlashErrorKind, ReadNorFlash};
+
+    /// Flash kept in memory, which counts how often each sector is erased and checks that only
+    /// erased bytes are written
+    #[derive(Clone)]
+    struct MockFlash {
+        data: Vec<u8>,
+        erases: Vec<u32>,
+    }
+
+    impl MockFlash {
+        fn new(sectors: usize) -> MockFlash {
+            MockFlash {
+                data: vec![ERASED; sectors * Self::ERASE_SIZE],
+                erases: vec![0; sectors],
+            }
+        }
+    }
+
+    impl ErrorType for MockFlash {
+        type Error = NorFlashErrorKind;
+    }
+
+    impl ReadNorFlash for MockFlash {
+        const READ_SIZE: usize = 1;
+
+        fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            bytes.copy_from_slice(&self.data[offset..offset + bytes.len()]);
+            Ok(())
+        }
+
+        fn capacity(&self) -> usize {
+            self.data.len()
+        }
+    }
+
+    impl NorFlash for MockFlash {
+        const WRITE_SIZE: usize = 4;
+        const ERASE_SIZE: usize = 256;
+
+        fn erase(&mut self, from: u32, to: u32) -> Result<(), Self::Error> {
+            let (from, to) = (from as usize, to as usize);
+            self.data[from..to].fill(ERASED);
+            self.erases[from / Self::ERASE_SIZE] += 1;
+            Ok(())
+        }
+
+        fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Self::Error> {
+            let offset = offset as usize;
+            assert_eq!(offset % Self::WRITE_SIZE, 0);
+            assert_eq!(bytes.len() % Self::WRITE_SIZE, 0);
+            let target = &mut self.data[offset..offset + bytes.len()];
+            assert!(target.iter().all(|&byte| byte == ERASED));
+            target.copy_from_slice(bytes);
+            Ok(())
+        }
+    }
+
+    fn assert_same<S: NorFlash, T: NorFlash>(a: &FlashFs<S>, b: &FlashFs<T>) {
+        let all = crate::TagPredicate::And(Vec::new());
+        let ids = a.search_tags(all.clone()).unwrap();
+        assert_eq!(ids, b.search_tags(all).unwrap());
+        for id in ids {
+            assert_eq!(a.get_data(id).unwrap(), b.get_data(id).unwrap());
+            assert_eq!(a.get_tags(id).unwrap(), b.get_tags(id).unwrap());
+            assert_eq!(a.get_metadata(id).unwrap(), b.get_metadata(id).unwrap());
+        }
+        assert_eq!(a.last_id().unwrap(), b.last_id().unwrap());
+        assert_eq!(a.config().unwrap(), b.config().unwrap());
+    }
+
+    #[test]
+    fn test_flash_fs() {
+        let fs = FlashFs::new(MockFlash::new(4)).unwrap();
+        let a = fs.add_file(&[1], [Tag::named("a").with_value(1)]).unwrap();
+        let b = fs.add_file(&[2], [Tag::named("b")]).unwrap();
+        fs.edit_file(a, Some(&[3]), Some([Tag::new("group", "c")]))
+            .unwrap();
+        fs.add_tags(b, [Tag::named("d")]).unwrap();
+        fs.set_config(b"config").unwrap();
+        let c = fs.add_file(&[4], []).unwrap();
+        fs.remove_file(c).unwrap();
+        // Changes in a failed transaction aren't kept
+        fs.transaction(|fs| {
+            fs.remove_file(a)?;
+            fs.remove_file(c)
+        })
+        .unwrap_err();
+        assert_eq!(fs.get_data(a).unwrap(), [3]);
+        assert!(matches!(
+            fs.add_file_with_id(FileId::from_u64_unchecked(u64::MAX), &[], []),
+            Err(Error::AlreadyExists(_))
+        ));
+
+        let flash = fs.into_inner();
+        let fs = FlashFs::new(flash.clone()).unwrap();
+        assert_same(&fs, &FlashFs::new(flash).unwrap());
+        assert!(fs.get_info(c).is_err());
+        assert_eq!(
+            fs.get_tags(a).unwrap(),
+            BTreeSet::from([Tag::new("group", "c")])
+        );
+
+        // Edits cycle through every sector, clearing old records and spreading out erases
+        for i in 0..200u8 {
+            fs.edit_file(b, Some(&[i; 40]), None::<[Tag; 0]>).unwrap();
+        }
+        let flash = fs.into_inner();
+        let erases = &flash.erases;
+        assert!(erases.iter().max().unwrap() - erases.iter().min().unwrap() <= 1);
+        assert!(erases.iter().all(|&count| count > 0));
+
+        let fs = FlashFs::new(flash).unwrap();
+        assert_eq!(fs.get_data(a).unwrap(), [3]);
+        assert_eq!(fs.get_data(b).unwrap(), [199; 40]);
+        assert_eq!(fs.config().unwrap(), b"config");
+        assert!(fs.add_file(&[], []).unwrap() > c);
+
+        // Running out of space fails the change, but keeps what's already stored
+        let big = [0; 150];
+        let err = loop {
+            if let Err(err) = fs.add_file(&big, []) {
+                break err;
+            }
+        };
+        assert!(matches!(err, Error::Full));
+        assert!(matches!(fs.add_file(&[0; 300], []), Err(Error::TooLarge)));
+        let fs = FlashFs::new(fs.into_inner()).unwrap();
+        assert_eq!(fs.get_data(b).unwrap(), [199; 40]);
+    }
+}
@@ -0,0 +1,250 @@
+//! A filesystem on another machine, used through a [`GrpcService`](super::GrpcService)
+
+use std::collections::BTreeSet;
+
+use prost::Message;
+use tonic::client::Grpc;
+use tonic::codec::{ProstCodec, Streaming};
+use tonic::codegen::http::uri::PathAndQuery;
+use tonic::transport::{Channel, Endpoint};
+use tonic::Request;
+
+use super::{proto, search_to_proto, tags_from_proto, tags_to_proto, Error};
+use crate::{AsyncFileSystem, FileId, FileInfo, SearchOptions, Tag, TagPattern};
+
+/// A tag-based filesystem served by another process, likely on another machine, through a
+/// [`GrpcService`](super::GrpcService). Every operation is a request to the server.
+///
+/// Besides the operations of [`AsyncFileSystem`], results of searches and the data of files can
+/// be streamed, with [`GrpcFs::search_stream`] and [`GrpcFs::read_file`].
+#[derive(Clone)]
+pub struct GrpcFs {
+    client: Grpc<Channel>,
+}
+
+impl GrpcFs {
+    /// Connect to the server at a URL, like `http://localhost:50051`
+    pub async fn connect(url: impl Into<String>) -> Result<GrpcFs, Error> {
+        let channel = Endpoint::from_shared(url.into())?.connect().await?;
+        Ok(GrpcFs::from_channel(channel))
+    }
+
+    /// Create a filesystem using an existing channel, such as one with TLS or timeouts
+    /// configured
+    pub fn from_channel(channel: Channel) -> GrpcFs {
+        GrpcFs {
+            client: Grpc::new(channel),
+        }
+    }
+
+    async fn unary<Req, Res>(&self, path: &'static str, request: Req) -> Result<Res, Error>
+    where
+        Req: Message + Send + Sync + 'static,
+        Res: Message + Default + Send + Sync + 'static,
+    {
+        let mut client = self.client.clone();
+        client.ready().await?;
+        let response = client
+            .unary(
+                Request::new(request),
+                PathAndQuery::from_static(path),
+                ProstCodec::default(),
+            )
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    async fn streaming<Req, Res>(
+        &self,
+        path: &'static str,
+        request: Req,
+    ) -> Result<Streaming<Res>, Error>
+    where
+        Req: Message + Send + Sync + 'static,
+        Res: Message + Default + Send + Sync + 'static,
+    {
+        let mut client = self.client.clone();
+        client.ready().await?;
+        let response = client
+            .server_streaming(
+                Request::new(request),
+                PathAndQuery::from_static(path),
+                ProstCodec::default(),
+            )
+            .await?;
+        Ok(response.into_inner())
+    }
+
+    /// Add a new file with the given ID, data, and tags
+    pub async fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let request = proto::AddFileRequest {
+            id: Some(id.into_u64_unchecked()),
+            data: data.to_vec(),
+            tags: tags_to_proto(&tags.into_iter().collect::<Vec<_>>()),
+        };
+        self.unary::<_, proto::FileRef>("/tbf.Tbf/AddFile", request)
+            .await?;
+        Ok(())
+    }
+
+    /// Get the tags of an existing file, without its data
+    pub async fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Error> {
+        let request = proto::FileRef {
+            id: id.into_u64_unchecked(),
+        };
+        let tags: proto::TagList = self.unary("/tbf.Tbf/GetTags", request).await?;
+        tags_from_proto(tags.tags)
+            .map(|tags| tags.into_iter().collect())
+            .ok_or(Error::InvalidResponse)
+    }
+
+    /// Stream the data of an existing file, rather than waiting for all of it at once
+    pub async fn read_file(&self, id: FileId) -> Result<DataStream, Error> {
+        let request = proto::FileRef {
+            id: id.into_u64_unchecked(),
+        };
+        Ok(DataStream {
+            inner: self.streaming("/tbf.Tbf/GetData", request).await?,
+        })
+    }
+
+    /// Search for files matching a pattern, with options controlling the order and which
+    /// results are returned
+    pub async fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Error>
+    where
+        P: TagPattern,
+    {
+        let mut stream = self.search_stream(tags, options).await?;
+        let mut out = Vec::new();
+        while let Some(id) = stream.next_id().await {
+            out.push(id?);
+        }
+        Ok(out)
+    }
+
+    /// Search for files matching a pattern, getting results as the server finds them
+    pub async fn search_stream<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<SearchStream, Error>
+    where
+        P: TagPattern,
+    {
+        let request = search_to_proto(&tags.to_predicate(), options).ok_or(Error::Unsupported)?;
+        Ok(SearchStream {
+            inner: self.streaming("/tbf.Tbf/Search", request).await?,
+        })
+    }
+}
+
+impl AsyncFileSystem for GrpcFs {
+    type Error = Error;
+
+    async fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag> + Send,
+    {
+        let request = proto::AddFileRequest {
+            id: None,
+            data: data.to_vec(),
+            tags: tags_to_proto(&tags.into_iter().collect::<Vec<_>>()),
+        };
+        let file: proto::FileRef = self.unary("/tbf.Tbf/AddFile", request).await?;
+        Ok(FileId::from_u64_unchecked(file.id))
+    }
+
+    async fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag> + Send,
+    {
+        let request = proto::EditFileRequest {
+            id: id.into_u64_unchecked(),
+            data: data.map(<[u8]>::to_vec),
+            tags: tags.map(|tags| proto::TagList {
+                tags: tags_to_proto(&tags.into_iter().collect::<Vec<_>>()),
+            }),
+        };
+        self.unary::<_, proto::Empty>("/tbf.Tbf/EditFile", request)
+            .await?;
+        Ok(())
+    }
+
+    async fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let request = proto::FileRef {
+            id: id.into_u64_unchecked(),
+        };
+        self.unary::<_, proto::Empty>("/tbf.Tbf/RemoveFile", request)
+            .await?;
+        Ok(())
+    }
+
+    async fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern + Send + 'static,
+    {
+        self.search_tags_with(tags, &SearchOptions::new()).await
+    }
+
+    async fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let request = proto::FileRef {
+            id: id.into_u64_unchecked(),
+        };
+        let file: proto::File = self.unary("/tbf.Tbf/GetInfo", request).await?;
+        let tags = tags_from_proto(file.tags).ok_or(Error::InvalidResponse)?;
+        Ok(FileInfo {
+            id,
+            tags: tags.into_iter().collect(),
+            data: file.data.into_boxed_slice(),
+        })
+    }
+}
+
+/// The results of a search of a [`GrpcFs`], received as the server finds them
+pub struct SearchStream {
+    inner: Streaming<proto::FileRef>,
+}
+
+impl SearchStream {
+    /// Wait for the next matching file, returning `None` once there are no more
+    pub async fn next_id(&mut self) -> Option<Result<FileId, Error>> {
+        match self.inner.message().await {
+            Ok(Some(file)) => Some(Ok(FileId::from_u64_unchecked(file.id))),
+            Ok(None) => None,
+            Err(status) => Some(Err(Error::from(status))),
+        }
+    }
+}
+
+/// The data of a file of a [`GrpcFs`], received in chunks as the server reads it
+pub struct DataStream {
+    inner: Streaming<proto::Chunk>,
+}
+
+impl DataStream {
+    /// Wait for the next chunk of data, returning `None` once there is no more
+    pub async fn next_chunk(&mut self) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.inner.message().await?.map(|chunk| chunk.data))
+    }
+
+    /// Wait for the rest of the data
+    pub async fn read_to_end(mut self) -> Result<Vec<u8>, Error> {
+        let mut out = Vec::new();
+        while let Some(chunk) = self.next_chunk().await? {
+            out.extend(chunk);
+        }
+        Ok(out)
+    }
+}
@@ -0,0 +1,437 @@
+//! Serving and using filesystems over gRPC, for typed and streaming access from other languages
+//! and machines
+//!
+//! The protocol is defined in `src/grpc/tbf.proto`, which other languages can generate clients
+//! from. [`GrpcService`] serves any filesystem as a [`tonic`] service, and [`GrpcFs`] is an
+//! [`AsyncFileSystem`](crate::AsyncFileSystem) using one. Searches and file data are streamed,
+//! so neither side has to hold every result at once.
+//!
+//! Errors are sent as a status with a matching code, such as `NOT_FOUND`, whose details are an
+//! encoded [`proto::ErrorDetails`], so clients get back the same [`ErrorKind`].
+
+use alloc::borrow::Cow;
+use core::convert::TryFrom;
+use core::fmt;
+
+use prost::Message;
+use tonic::{Code, Status};
+
+#[cfg(feature = "regex")]
+use crate::TagRegex;
+use crate::{ErrorKind, FileId, Group, SearchOptions, SortBy, Tag, TagPredicate, TagValue};
+
+mod client;
+pub mod proto;
+mod server;
+
+pub use client::{DataStream, GrpcFs, SearchStream};
+pub use server::GrpcService;
+
+/// Error for a filesystem accessed over gRPC
+#[derive(Debug)]
+pub enum Error {
+    /// A file wasn't found
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// The remote filesystem is read-only
+    ReadOnly,
+    /// The remote filesystem is full
+    QuotaExceeded,
+    /// The remote filesystem rejected the tags given to a file
+    InvalidTags,
+    /// The remote filesystem denied the caller permission
+    PermissionDenied,
+    /// The remote filesystem ran out of room
+    StorageFull,
+    /// The remote filesystem is in an invalid state
+    State,
+    /// The remote filesystem failed in some other way, or rejected a request
+    Status(Status),
+    /// The server sent a message which couldn't be understood
+    InvalidResponse,
+    /// The request can't be sent to the server, such as a search with a
+    /// [`TagPredicate::Custom`] predicate
+    Unsupported,
+    /// The server couldn't be connected to
+    Transport(tonic::transport::Error),
+}
+
+impl Error {
+    /// Get the error described by a status, using its details if it has them
+    fn from_status(status: Status) -> Error {
+        let Ok(details) = proto::ErrorDetails::decode(status.details()) else {
+            return Error::Status(status);
+        };
+        let id = FileId::from_u64_unchecked(details.id);
+        match details.kind() {
+            proto::ErrorKind::FileNotFound => Error::FileNotFound(id),
+            proto::ErrorKind::AlreadyExists => Error::AlreadyExists(id),
+            proto::ErrorKind::VersionNotFound => Error::VersionNotFound(id, details.version),
+            proto::ErrorKind::ReadOnly => Error::ReadOnly,
+            proto::ErrorKind::QuotaExceeded => Error::QuotaExceeded,
+            proto::ErrorKind::InvalidTags => Error::InvalidTags,
+            proto::ErrorKind::PermissionDenied => Error::PermissionDenied,
+            proto::ErrorKind::StorageFull => Error::StorageFull,
+            proto::ErrorKind::State => Error::State,
+            proto::ErrorKind::Other => Error::Status(status),
+        }
+    }
+}
+
+impl From<Status> for Error {
+    fn from(status: Status) -> Error {
+        Error::from_status(status)
+    }
+}
+
+impl From<tonic::transport::Error> for Error {
+    fn from(err: tonic::transport::Error) -> Error {
+        Error::Transport(err)
+    }
+}
+
+impl crate::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Error::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Error::StorageFull
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Error::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Error::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Error::ReadOnly => ErrorKind::ReadOnly,
+            Error::QuotaExceeded => ErrorKind::QuotaExceeded,
+            Error::InvalidTags => ErrorKind::InvalidTags,
+            Error::PermissionDenied => ErrorKind::PermissionDenied,
+            Error::StorageFull => ErrorKind::StorageFull,
+            Error::State => ErrorKind::State,
+            Error::Status(status) => ErrorKind::Source(status),
+            Error::Transport(err) => ErrorKind::Source(err),
+            Error::InvalidResponse | Error::Unsupported => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::ReadOnly => write!(f, "remote filesystem is read-only"),
+            Error::QuotaExceeded => {
+                write!(f, "change would go over a quota of the remote filesystem")
+            }
+            Error::InvalidTags => write!(f, "remote filesystem rejected the tags"),
+            Error::PermissionDenied => write!(f, "remote filesystem denied permission"),
+            Error::StorageFull => write!(f, "remote filesystem is out of room"),
+            Error::State => write!(f, "remote filesystem is in an invalid state"),
+            Error::Status(status) => write!(f, "remote filesystem failed: {status}"),
+            Error::InvalidResponse => {
+                write!(f, "server sent a message which couldn't be understood")
+            }
+            Error::Unsupported => write!(f, "request can't be sent to the server"),
+            Error::Transport(err) => write!(f, "couldn't connect to the server: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Status(status) => std::error::Error::source(status),
+            Error::Transport(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+/// Get the status to send for an error
+fn error_to_status(kind: &ErrorKind<'_>) -> Status {
+    let (code, kind, id, version) = match kind {
+        ErrorKind::FileNotFound(id) => (Code::NotFound, proto::ErrorKind::FileNotFound, *id, 0),
+        ErrorKind::AlreadyExists(id) => {
+            (Code::AlreadyExists, proto::ErrorKind::AlreadyExists, *id, 0)
+        }
+        ErrorKind::VersionNotFound(id, version) => (
+            Code::NotFound,
+            proto::ErrorKind::VersionNotFound,
+            *id,
+            *version,
+        ),
+        ErrorKind::ReadOnly => (
+            Code::PermissionDenied,
+            proto::ErrorKind::ReadOnly,
+            FileId::from_u64_unchecked(0),
+            0,
+        ),
+        ErrorKind::QuotaExceeded => (
+            Code::ResourceExhausted,
+            proto::ErrorKind::QuotaExceeded,
+            FileId::from_u64_unchecked(0),
+            0,
+        ),
+        ErrorKind::InvalidTags => (
+            Code::InvalidArgument,
+            proto::ErrorKind::InvalidTags,
+            FileId::from_u64_unchecked(0),
+            0,
+        ),
+        ErrorKind::PermissionDenied => (
+            Code::PermissionDenied,
+            proto::ErrorKind::PermissionDenied,
+            FileId::from_u64_unchecked(0),
+            0,
+        ),
+        ErrorKind::StorageFull => (
+            Code::ResourceExhausted,
+            proto::ErrorKind::StorageFull,
+            FileId::from_u64_unchecked(0),
+            0,
+        ),
+        ErrorKind::State => (
+            Code::FailedPrecondition,
+            proto::ErrorKind::State,
+            FileId::from_u64_unchecked(0),
+            0,
+        ),
+        _ => return Status::internal("Filesystem error"),
+    };
+    let details = proto::ErrorDetails {
+        kind: kind.into(),
+        id: id.into_u64_unchecked(),
+        version,
+    };
+    Status::with_details(code, "Filesystem error", details.encode_to_vec().into())
+}
+
+fn group_to_proto(group: &Group) -> Option<String> {
+    match group {
+        Group::Default => None,
+        Group::Custom(group) => Some(group.to_string()),
+    }
+}
+
+fn group_from_proto(group: Option<String>) -> Group {
+    group.map_or(Group::Default, Group::from)
+}
+
+fn value_to_proto(value: &TagValue) -> proto::TagValue {
+    use proto::tag_value::Value;
+
+    let value = match value {
+        TagValue::String(str) => Value::String(str.to_string()),
+        TagValue::Int(int) => Value::Int(*int),
+        TagValue::Float(float) => Value::Float(*float),
+        TagValue::Bool(bool) => Value::Bool(*bool),
+        TagValue::Timestamp(secs) => Value::Timestamp(*secs),
+    };
+    proto::TagValue { value: Some(value) }
+}
+
+fn value_from_proto(value: proto::TagValue) -> Option<TagValue> {
+    use proto::tag_value::Value;
+
+    Some(match value.value? {
+        Value::String(str) => TagValue::String(Cow::Owned(str)),
+        Value::Int(int) => TagValue::Int(int),
+        Value::Float(float) => TagValue::Float(float),
+        Value::Bool(bool) => TagValue::Bool(bool),
+        Value::Timestamp(secs) => TagValue::Timestamp(secs),
+    })
+}
+
+fn tag_to_proto(tag: &Tag) -> proto::Tag {
+    proto::Tag {
+        group: group_to_proto(tag.group()),
+        name: tag.name().to_owned(),
+        value: tag.value().map(value_to_proto),
+    }
+}
+
+fn tag_from_proto(tag: proto::Tag) -> Option<Tag> {
+    let out = Tag::new(group_from_proto(tag.group), tag.name);
+    match tag.value {
+        None => Some(out),
+        Some(value) => Some(out.with_value(value_from_proto(value)?)),
+    }
+}
+
+fn tags_to_proto<'a, I: IntoIterator<Item = &'a Tag>>(tags: I) -> Vec<proto::Tag> {
+    tags.into_iter().map(tag_to_proto).collect()
+}
+
+fn tags_from_proto(tags: Vec<proto::Tag>) -> Option<Vec<Tag>> {
+    tags.into_iter().map(tag_from_proto).collect()
+}
+
+/// Get the message for a predicate, if it can be sent. Custom predicates can't be.
+fn predicate_to_proto(pred: &TagPredicate) -> Option<proto::Predicate> {
+    use proto::predicate::Kind;
+
+    let list = |preds: &[TagPredicate]| -> Option<proto::PredicateList> {
+        Some(proto::PredicateList {
+            predicates: preds
+                .iter()
+                .map(predicate_to_proto)
+                .collect::<Option<_>>()?,
+        })
+    };
+    let bound = |group: &Group, name: &str, value: &TagValue| proto::ValueBound {
+        group: group_to_proto(group),
+        name: name.to_owned(),
+        value: Some(value_to_proto(value)),
+    };
+    let kind = match pred {
+        TagPredicate::And(preds) => Kind::And(list(preds)?),
+        TagPredicate::Or(preds) => Kind::Or(list(preds)?),
+        TagPredicate::Not(pred) => Kind::Not(Box::new(predicate_to_proto(pred)?)),
+        TagPredicate::Group(group) => Kind::Group(group.to_string()),
+        TagPredicate::Name(name) => Kind::Name(name.clone()),
+        TagPredicate::NameContains(substr) => Kind::NameContains(substr.clone()),
+        TagPredicate::NameGlob(glob) => Kind::NameGlob(glob.clone()),
+        TagPredicate::GroupGlob(glob) => Kind::GroupGlob(glob.clone()),
+        #[cfg(feature = "regex")]
+        TagPredicate::NameRegex(regex) => Kind::NameRegex(regex.as_str().to_owned()),
+        #[cfg(feature = "regex")]
+        TagPredicate::GroupRegex(regex) => Kind::GroupRegex(regex.as_str().to_owned()),
+        TagPredicate::Tag(tag) => Kind::Tag(tag_to_proto(tag)),
+        TagPredicate::ValueGt { group, name, value } => Kind::ValueGt(bound(group, name, value)),
+        TagPredicate::ValueLt { group, name, value } => Kind::ValueLt(bound(group, name, value)),
+        TagPredicate::ValueRange {
+            group,
+            name,
+            min,
+            max,
+        } => Kind::ValueRange(proto::ValueRange {
+            group: group_to_proto(group),
+            name: name.clone(),
+            min: Some(value_to_proto(min)),
+            max: Some(value_to_proto(max)),
+        }),
+        TagPredicate::Custom(_) => return None,
+    };
+    Some(proto::Predicate { kind: Some(kind) })
+}
+
+/// Get the predicate sent in a message, if it's valid. Regex predicates are only valid if the
+/// `regex` feature is enabled.
+fn predicate_from_proto(pred: proto::Predicate) -> Option<TagPredicate> {
+    use proto::predicate::Kind;
+
+    let list = |list: proto::PredicateList| -> Option<Vec<TagPredicate>> {
+        list.predicates
+            .into_iter()
+            .map(predicate_from_proto)
+            .collect()
+    };
+    Some(match pred.kind? {
+        Kind::And(preds) => TagPredicate::And(list(preds)?),
+        Kind::Or(preds) => TagPredicate::Or(list(preds)?),
+        Kind::Not(pred) => TagPredicate::Not(Box::new(predicate_from_proto(*pred)?)),
+        Kind::Group(group) => TagPredicate::Group(Group::from(group)),
+        Kind::Name(name) => TagPredicate::Name(name),
+        Kind::NameContains(substr) => TagPredicate::NameContains(substr),
+        Kind::NameGlob(glob) => TagPredicate::NameGlob(glob),
+        Kind::GroupGlob(glob) => TagPredicate::GroupGlob(glob),
+        #[cfg(feature = "regex")]
+        Kind::NameRegex(regex) => TagPredicate::NameRegex(TagRegex::new(&regex).ok()?),
+        #[cfg(feature = "regex")]
+        Kind::GroupRegex(regex) => TagPredicate::GroupRegex(TagRegex::new(&regex).ok()?),
+        #[cfg(not(feature = "regex"))]
+        Kind::NameRegex(_) | Kind::GroupRegex(_) => return None,
+        Kind::Tag(tag) => TagPredicate::Tag(tag_from_proto(tag)?),
+        Kind::ValueGt(bound) => TagPredicate::ValueGt {
+            group: group_from_proto(bound.group),
+            name: bound.name,
+            value: value_from_proto(bound.value?)?,
+        },
+        Kind::ValueLt(bound) => TagPredicate::ValueLt {
+            group: group_from_proto(bound.group),
+            name: bound.name,
+            value: value_from_proto(bound.value?)?,
+        },
+        Kind::ValueRange(range) => TagPredicate::ValueRange {
+            group: group_from_proto(range.group),
+            name: range.name,
+            min: value_from_proto(range.min?)?,
+            max: value_from_proto(range.max?)?,
+        },
+    })
+}
+
+fn search_to_proto(
+    pattern: &TagPredicate,
+    options: &SearchOptions,
+) -> Option<proto::SearchRequest> {
+    use proto::sort::By;
+
+    let sort = match options.sort() {
+        SortBy::Id => None,
+        SortBy::Created => Some(By::Created(proto::Empty {})),
+        SortBy::Size => Some(By::Size(proto::Empty {})),
+        SortBy::Value { group, name } => Some(By::Value(proto::TagName {
+            group: group_to_proto(group),
+            name: name.clone(),
+        })),
+    };
+    Some(proto::SearchRequest {
+        pattern: Some(predicate_to_proto(pattern)?),
+        sort: sort.map(|by| proto::Sort { by: Some(by) }),
+        descending: options.is_descending(),
+        after: options.cursor().map(FileId::into_u64_unchecked),
+        offset: options.skipped() as u64,
+        limit: options.max_len().map(|limit| limit as u64),
+    })
+}
+
+/// Get the search sent in a message, if it's valid
+fn search_from_proto(search: proto::SearchRequest) -> Option<(TagPredicate, SearchOptions)> {
+    use proto::sort::By;
+
+    let pattern = predicate_from_proto(search.pattern?)?;
+    let mut options = SearchOptions::new();
+    match search.sort.map(|sort| sort.by) {
+        None | Some(Some(By::Id(_))) => (),
+        Some(Some(By::Created(_))) => options = options.sort_by(SortBy::Created),
+        Some(Some(By::Size(_))) => options = options.sort_by(SortBy::Size),
+        Some(Some(By::Value(tag))) => {
+            options = options.sort_by(SortBy::Value {
+                group: group_from_proto(tag.group),
+                name: tag.name,
+            });
+        }
+        Some(None) => return None,
+    }
+    if search.descending {
+        options = options.descending();
+    }
+    if let Some(after) = search.after {
+        options = options.after(FileId::from_u64_unchecked(after));
+    }
+    if search.offset != 0 {
+        options = options.offset(usize::try_from(search.offset).ok()?);
+    }
+    if let Some(limit) = search.limit {
+        options = options.limit(usize::try_from(limit).ok()?);
+    }
+    Some((pattern, options))
+}
@@ -0,0 +1,329 @@
+//! The messages of the gRPC protocol, matching those in `tbf.proto`
+//!
+//! These are kept by hand, rather than generated at build time, so building doesn't need
+//! `protoc`. Any change here has to be made to the schema as well.
+
+use prost::{Enumeration, Message};
+
+/// A message with no fields
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct Empty {}
+
+/// The ID of a file
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct FileRef {
+    /// The raw ID
+    #[prost(fixed64, tag = "1")]
+    pub id: u64,
+}
+
+/// The value of a tag
+#[derive(Clone, PartialEq, Message)]
+pub struct TagValue {
+    /// The value, which is always set by valid messages
+    #[prost(oneof = "tag_value::Value", tags = "1, 2, 3, 4, 5")]
+    pub value: Option<tag_value::Value>,
+}
+
+/// Types for [`TagValue`]
+pub mod tag_value {
+    use prost::Oneof;
+
+    /// The possible types of a value
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum Value {
+        /// A string value
+        #[prost(string, tag = "1")]
+        String(String),
+        /// An integer value
+        #[prost(sint64, tag = "2")]
+        Int(i64),
+        /// A floating point value
+        #[prost(double, tag = "3")]
+        Float(f64),
+        /// A boolean value
+        #[prost(bool, tag = "4")]
+        Bool(bool),
+        /// A point in time, as seconds since the Unix epoch
+        #[prost(sint64, tag = "5")]
+        Timestamp(i64),
+    }
+}
+
+/// A tag
+#[derive(Clone, PartialEq, Message)]
+pub struct Tag {
+    /// The group of the tag, left out for the default group
+    #[prost(string, optional, tag = "1")]
+    pub group: Option<String>,
+    /// The name of the tag
+    #[prost(string, tag = "2")]
+    pub name: String,
+    /// The value of the tag, if it has one
+    #[prost(message, optional, tag = "3")]
+    pub value: Option<TagValue>,
+}
+
+/// A list of tags
+#[derive(Clone, PartialEq, Message)]
+pub struct TagList {
+    /// The tags
+    #[prost(message, repeated, tag = "1")]
+    pub tags: Vec<Tag>,
+}
+
+/// A file with its tags and data
+#[derive(Clone, PartialEq, Message)]
+pub struct File {
+    /// The ID of the file
+    #[prost(fixed64, tag = "1")]
+    pub id: u64,
+    /// The tags of the file
+    #[prost(message, repeated, tag = "2")]
+    pub tags: Vec<Tag>,
+    /// The data of the file
+    #[prost(bytes = "vec", tag = "3")]
+    pub data: Vec<u8>,
+}
+
+/// Part of the data of a file
+#[derive(Clone, PartialEq, Message)]
+pub struct Chunk {
+    /// The data in this part
+    #[prost(bytes = "vec", tag = "1")]
+    pub data: Vec<u8>,
+}
+
+/// A request to add a file
+#[derive(Clone, PartialEq, Message)]
+pub struct AddFileRequest {
+    /// The ID to add the file with, or none to pick a new one
+    #[prost(fixed64, optional, tag = "1")]
+    pub id: Option<u64>,
+    /// The data of the file
+    #[prost(bytes = "vec", tag = "2")]
+    pub data: Vec<u8>,
+    /// The tags of the file
+    #[prost(message, repeated, tag = "3")]
+    pub tags: Vec<Tag>,
+}
+
+/// A request to replace the data and/or tags of a file
+#[derive(Clone, PartialEq, Message)]
+pub struct EditFileRequest {
+    /// The ID of the file
+    #[prost(fixed64, tag = "1")]
+    pub id: u64,
+    /// The new data of the file, if it's changed
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub data: Option<Vec<u8>>,
+    /// The new tags of the file, if they're changed
+    #[prost(message, optional, tag = "3")]
+    pub tags: Option<TagList>,
+}
+
+/// The group and name of a tag
+#[derive(Clone, PartialEq, Message)]
+pub struct TagName {
+    /// The group, left out for the default group
+    #[prost(string, optional, tag = "1")]
+    pub group: Option<String>,
+    /// The name
+    #[prost(string, tag = "2")]
+    pub name: String,
+}
+
+/// A tag and a value to compare its values against
+#[derive(Clone, PartialEq, Message)]
+pub struct ValueBound {
+    /// The group of the tag, left out for the default group
+    #[prost(string, optional, tag = "1")]
+    pub group: Option<String>,
+    /// The name of the tag
+    #[prost(string, tag = "2")]
+    pub name: String,
+    /// The value to compare against
+    #[prost(message, optional, tag = "3")]
+    pub value: Option<TagValue>,
+}
+
+/// A tag and an inclusive range its values can be in
+#[derive(Clone, PartialEq, Message)]
+pub struct ValueRange {
+    /// The group of the tag, left out for the default group
+    #[prost(string, optional, tag = "1")]
+    pub group: Option<String>,
+    /// The name of the tag
+    #[prost(string, tag = "2")]
+    pub name: String,
+    /// The lowest value in the range
+    #[prost(message, optional, tag = "3")]
+    pub min: Option<TagValue>,
+    /// The highest value in the range
+    #[prost(message, optional, tag = "4")]
+    pub max: Option<TagValue>,
+}
+
+/// A list of predicates
+#[derive(Clone, PartialEq, Message)]
+pub struct PredicateList {
+    /// The predicates
+    #[prost(message, repeated, tag = "1")]
+    pub predicates: Vec<Predicate>,
+}
+
+/// A predicate on the tags of files
+#[derive(Clone, PartialEq, Message)]
+pub struct Predicate {
+    /// The kind of predicate, which is always set by valid messages
+    #[prost(
+        oneof = "predicate::Kind",
+        tags = "1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14"
+    )]
+    pub kind: Option<predicate::Kind>,
+}
+
+/// Types for [`Predicate`]
+pub mod predicate {
+    use prost::Oneof;
+
+    /// The kinds of predicate, as in [`TagPredicate`](crate::TagPredicate)
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum Kind {
+        /// Matches if every inner predicate does
+        #[prost(message, tag = "1")]
+        And(super::PredicateList),
+        /// Matches if any inner predicate does
+        #[prost(message, tag = "2")]
+        Or(super::PredicateList),
+        /// Matches if the inner predicate doesn't
+        #[prost(message, tag = "3")]
+        Not(Box<super::Predicate>),
+        /// Matches files with a tag in a group
+        #[prost(string, tag = "4")]
+        Group(String),
+        /// Matches files with a tag with a name
+        #[prost(string, tag = "5")]
+        Name(String),
+        /// Matches files with a tag whose name contains a string
+        #[prost(string, tag = "6")]
+        NameContains(String),
+        /// Matches files with a tag whose name matches a glob
+        #[prost(string, tag = "7")]
+        NameGlob(String),
+        /// Matches files with a tag whose group matches a glob
+        #[prost(string, tag = "8")]
+        GroupGlob(String),
+        /// Matches files with a tag whose name matches a regex
+        #[prost(string, tag = "9")]
+        NameRegex(String),
+        /// Matches files with a tag whose group matches a regex
+        #[prost(string, tag = "10")]
+        GroupRegex(String),
+        /// Matches files with a tag
+        #[prost(message, tag = "11")]
+        Tag(super::Tag),
+        /// Matches files with a value of a tag greater than a value
+        #[prost(message, tag = "12")]
+        ValueGt(super::ValueBound),
+        /// Matches files with a value of a tag less than a value
+        #[prost(message, tag = "13")]
+        ValueLt(super::ValueBound),
+        /// Matches files with a value of a tag in a range
+        #[prost(message, tag = "14")]
+        ValueRange(super::ValueRange),
+    }
+}
+
+/// What to sort search results by
+#[derive(Clone, PartialEq, Message)]
+pub struct Sort {
+    /// What to sort by, which is always set by valid messages
+    #[prost(oneof = "sort::By", tags = "1, 2, 3, 4")]
+    pub by: Option<sort::By>,
+}
+
+/// Types for [`Sort`]
+pub mod sort {
+    use prost::Oneof;
+
+    /// The ways to sort search results, as in [`SortBy`](crate::SortBy)
+    #[derive(Clone, PartialEq, Oneof)]
+    pub enum By {
+        /// Sort by file ID
+        #[prost(message, tag = "1")]
+        Id(super::Empty),
+        /// Sort by when files were added
+        #[prost(message, tag = "2")]
+        Created(super::Empty),
+        /// Sort by the size of files' data
+        #[prost(message, tag = "3")]
+        Size(super::Empty),
+        /// Sort by the value of a tag
+        #[prost(message, tag = "4")]
+        Value(super::TagName),
+    }
+}
+
+/// A search for files, as in [`SearchOptions`](crate::SearchOptions)
+#[derive(Clone, PartialEq, Message)]
+pub struct SearchRequest {
+    /// The predicate files must match
+    #[prost(message, optional, tag = "1")]
+    pub pattern: Option<Predicate>,
+    /// What to sort results by, left out to sort by ID
+    #[prost(message, optional, tag = "2")]
+    pub sort: Option<Sort>,
+    /// Whether to return the largest results first
+    #[prost(bool, tag = "3")]
+    pub descending: bool,
+    /// The file results must come after, if any
+    #[prost(fixed64, optional, tag = "4")]
+    pub after: Option<u64>,
+    /// How many results to skip
+    #[prost(uint64, tag = "5")]
+    pub offset: u64,
+    /// The most results to return, if limited
+    #[prost(uint64, optional, tag = "6")]
+    pub limit: Option<u64>,
+}
+
+/// The kind of an error, as in [`ErrorKind`](crate::ErrorKind)
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord, Enumeration)]
+#[repr(i32)]
+pub enum ErrorKind {
+    /// Any other error
+    Other = 0,
+    /// A file wasn't found
+    FileNotFound = 1,
+    /// A file already exists
+    AlreadyExists = 2,
+    /// A version of a file wasn't found
+    VersionNotFound = 3,
+    /// The filesystem is read-only
+    ReadOnly = 4,
+    /// The filesystem is in an invalid state
+    State = 5,
+    /// A change would have gone over a limit
+    QuotaExceeded = 6,
+    /// A file was given tags which break a rule of the filesystem
+    InvalidTags = 7,
+    /// The caller isn't permitted to do something
+    PermissionDenied = 8,
+    /// The filesystem ran out of room
+    StorageFull = 9,
+}
+
+/// The details of an error, sent in the details of a status
+#[derive(Clone, Copy, PartialEq, Message)]
+pub struct ErrorDetails {
+    /// The kind of error
+    #[prost(enumeration = "ErrorKind", tag = "1")]
+    pub kind: i32,
+    /// The file the error was for, if any
+    #[prost(fixed64, tag = "2")]
+    pub id: u64,
+    /// The version the error was for, if any
+    #[prost(uint32, tag = "3")]
+    pub version: u32,
+}
@@ -0,0 +1,358 @@
+//! Serving a filesystem as a [`tonic`] service
+
+// Handlers fail with tonic's `Status`, as every tonic service does
+#![allow(clippy::result_large_err)]
+
+use std::convert::Infallible;
+use std::io::Read;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+use prost::Message;
+use tokio::sync::mpsc;
+use tokio::task::spawn_blocking;
+use tonic::body::BoxBody;
+use tonic::codec::ProstCodec;
+use tonic::codegen::tokio_stream::Stream;
+use tonic::codegen::{http, Body, BoxFuture, Service, StdError};
+use tonic::server::{Grpc, NamedService, ServerStreamingService, UnaryService};
+use tonic::{Request, Response, Status};
+
+use super::{error_to_status, proto, search_from_proto, tags_from_proto, tags_to_proto};
+use crate::{FileId, FileSystem, StreamRead};
+
+/// How much file data is sent in each chunk
+const CHUNK_LEN: usize = 64 * 1024;
+
+/// How many messages of a stream can be waiting to be sent before the filesystem stops producing
+/// more
+const STREAM_BUFFER: usize = 16;
+
+type Handler<F, Req, Res> = fn(&F, Req) -> Result<Res, Status>;
+type StreamHandler<F, Req, Res> = fn(&F, Req, &mut dyn FnMut(Res) -> bool) -> Result<(), Status>;
+
+/// A gRPC service for a filesystem, implementing the `tbf.Tbf` service of the protocol. It's
+/// served by adding it to a [`tonic::transport::Server`].
+///
+/// Filesystem operations are run on Tokio's blocking threads, so they don't hold up other
+/// requests. Searches and file data are streamed as they're found or read.
+pub struct GrpcService<F> {
+    fs: Arc<F>,
+}
+
+impl<F> GrpcService<F> {
+    /// Create a service for a filesystem
+    pub fn new(fs: F) -> GrpcService<F> {
+        GrpcService { fs: Arc::new(fs) }
+    }
+
+    /// Create a service for a filesystem which is shared with other code
+    pub fn from_arc(fs: Arc<F>) -> GrpcService<F> {
+        GrpcService { fs }
+    }
+
+    /// Get the served filesystem
+    pub fn fs(&self) -> &F {
+        &self.fs
+    }
+}
+
+impl<F> Clone for GrpcService<F> {
+    fn clone(&self) -> Self {
+        GrpcService {
+            fs: Arc::clone(&self.fs),
+        }
+    }
+}
+
+impl<F> NamedService for GrpcService<F> {
+    const NAME: &'static str = "tbf.Tbf";
+}
+
+impl<F, B> Service<http::Request<B>> for GrpcService<F>
+where
+    F: FileSystem + StreamRead + Send + Sync + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+{
+    type Response = http::Response<BoxBody>;
+    type Error = Infallible;
+    type Future = BoxFuture<Self::Response, Self::Error>;
+
+    fn poll_ready(&mut self, _: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, request: http::Request<B>) -> Self::Future {
+        let fs = Arc::clone(&self.fs);
+        match request.uri().path() {
+            "/tbf.Tbf/AddFile" => unary(fs, request, add_file),
+            "/tbf.Tbf/EditFile" => unary(fs, request, edit_file),
+            "/tbf.Tbf/RemoveFile" => unary(fs, request, remove_file),
+            "/tbf.Tbf/GetInfo" => unary(fs, request, get_info),
+            "/tbf.Tbf/GetTags" => unary(fs, request, get_tags),
+            "/tbf.Tbf/GetData" => streaming(fs, request, get_data),
+            "/tbf.Tbf/Search" => streaming(fs, request, search),
+            _ => Box::pin(async { Ok(Status::unimplemented("No such method").into_http()) }),
+        }
+    }
+}
+
+fn unary<F, B, Req, Res>(
+    fs: Arc<F>,
+    request: http::Request<B>,
+    handler: Handler<F, Req, Res>,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    F: Send + Sync + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: Message + Default + Send + 'static,
+    Res: Message + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::default());
+        Ok(grpc.unary(Unary { fs, handler }, request).await)
+    })
+}
+
+fn streaming<F, B, Req, Res>(
+    fs: Arc<F>,
+    request: http::Request<B>,
+    handler: StreamHandler<F, Req, Res>,
+) -> BoxFuture<http::Response<BoxBody>, Infallible>
+where
+    F: Send + Sync + 'static,
+    B: Body + Send + 'static,
+    B::Error: Into<StdError> + Send + 'static,
+    Req: Message + Default + Send + 'static,
+    Res: Message + Send + 'static,
+{
+    Box::pin(async move {
+        let mut grpc = Grpc::new(ProstCodec::default());
+        Ok(grpc
+            .server_streaming(Streaming { fs, handler }, request)
+            .await)
+    })
+}
+
+/// A unary method, running its handler on a blocking thread
+struct Unary<F, Req, Res> {
+    fs: Arc<F>,
+    handler: Handler<F, Req, Res>,
+}
+
+impl<F, Req, Res> UnaryService<Req> for Unary<F, Req, Res>
+where
+    F: Send + Sync + 'static,
+    Req: Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type Future = BoxFuture<Response<Res>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let fs = Arc::clone(&self.fs);
+        let handler = self.handler;
+        Box::pin(async move {
+            let res = spawn_blocking(move || handler(&fs, request.into_inner()))
+                .await
+                .map_err(|_| Status::internal("Request handler panicked"))??;
+            Ok(Response::new(res))
+        })
+    }
+}
+
+/// A server streaming method, running its handler on a blocking thread which sends messages as
+/// they're produced
+struct Streaming<F, Req, Res> {
+    fs: Arc<F>,
+    handler: StreamHandler<F, Req, Res>,
+}
+
+impl<F, Req, Res> ServerStreamingService<Req> for Streaming<F, Req, Res>
+where
+    F: Send + Sync + 'static,
+    Req: Send + 'static,
+    Res: Send + 'static,
+{
+    type Response = Res;
+    type ResponseStream = Receiver<Res>;
+    type Future = BoxFuture<Response<Receiver<Res>>, Status>;
+
+    fn call(&mut self, request: Request<Req>) -> Self::Future {
+        let fs = Arc::clone(&self.fs);
+        let handler = self.handler;
+        Box::pin(async move {
+            let (send, mut recv) = mpsc::channel(STREAM_BUFFER);
+            spawn_blocking(move || {
+                // Stop once the client has gone away
+                let mut emit = |msg| send.blocking_send(Ok(msg)).is_ok();
+                if let Err(status) = handler(&fs, request.into_inner(), &mut emit) {
+                    let _ = send.blocking_send(Err(status));
+                }
+            });
+            // Wait for the first message, so errors found before anything is sent, such as for
+            // missing files, fail the call rather than the stream
+            let first = match recv.recv().await {
+                Some(Err(status)) => return Err(status),
+                first => first,
+            };
+            Ok(Response::new(Receiver { first, recv }))
+        })
+    }
+}
+
+/// The messages of a stream, as they're sent by its handler
+struct Receiver<T> {
+    first: Option<Result<T, Status>>,
+    recv: mpsc::Receiver<Result<T, Status>>,
+}
+
+// Messages are never pinned, only moved out
+impl<T> Unpin for Receiver<T> {}
+
+impl<T> Stream for Receiver<T> {
+    type Item = Result<T, Status>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        match self.first.take() {
+            Some(first) => Poll::Ready(Some(first)),
+            None => self.recv.poll_recv(cx),
+        }
+    }
+}
+
+fn status<E: crate::Error>(err: &E) -> Status {
+    error_to_status(&err.generic_kind())
+}
+
+fn add_file<F: FileSystem>(
+    fs: &F,
+    request: proto::AddFileRequest,
+) -> Result<proto::FileRef, Status> {
+    let tags =
+        tags_from_proto(request.tags).ok_or_else(|| Status::invalid_argument("Invalid tags"))?;
+    let id = match request.id {
+        Some(id) => {
+            let id = FileId::from_u64_unchecked(id);
+            fs.add_file_with_id(id, &request.data, tags)
+                .map_err(|err| status(&err))?;
+            id
+        }
+        None => fs
+            .add_file(&request.data, tags)
+            .map_err(|err| status(&err))?,
+    };
+    Ok(proto::FileRef {
+        id: id.into_u64_unchecked(),
+    })
+}
+
+fn edit_file<F: FileSystem>(
+    fs: &F,
+    request: proto::EditFileRequest,
+) -> Result<proto::Empty, Status> {
+    let tags = match request.tags {
+        Some(list) => Some(
+            tags_from_proto(list.tags).ok_or_else(|| Status::invalid_argument("Invalid tags"))?,
+        ),
+        None => None,
+    };
+    fs.edit_file(
+        FileId::from_u64_unchecked(request.id),
+        request.data.as_deref(),
+        tags,
+    )
+    .map_err(|err| status(&err))?;
+    Ok(proto::Empty {})
+}
+
+fn remove_file<F: FileSystem>(fs: &F, request: proto::FileRef) -> Result<proto::Empty, Status> {
+    fs.remove_file(FileId::from_u64_unchecked(request.id))
+        .map_err(|err| status(&err))?;
+    Ok(proto::Empty {})
+}
+
+fn get_info<F: FileSystem>(fs: &F, request: proto::FileRef) -> Result<proto::File, Status> {
+    let info = fs
+        .get_info(FileId::from_u64_unchecked(request.id))
+        .map_err(|err| status(&err))?;
+    Ok(proto::File {
+        id: request.id,
+        tags: tags_to_proto(info.tags()),
+        data: info.data.into_vec(),
+    })
+}
+
+fn get_tags<F: FileSystem>(fs: &F, request: proto::FileRef) -> Result<proto::TagList, Status> {
+    let tags = fs
+        .get_tags(FileId::from_u64_unchecked(request.id))
+        .map_err(|err| status(&err))?;
+    Ok(proto::TagList {
+        tags: tags_to_proto(&tags),
+    })
+}
+
+fn get_data<F: StreamRead>(
+    fs: &F,
+    request: proto::FileRef,
+    emit: &mut dyn FnMut(proto::Chunk) -> bool,
+) -> Result<(), Status> {
+    let mut reader = fs
+        .read_file(FileId::from_u64_unchecked(request.id))
+        .map_err(|err| status(&err))?;
+    loop {
+        let mut data = vec![0; CHUNK_LEN];
+        let len = reader
+            .read(&mut data)
+            .map_err(|_| Status::internal("Failed to read file data"))?;
+        if len == 0 {
+            return Ok(());
+        }
+        data.truncate(len);
+        if !emit(proto::Chunk { data }) {
+            return Ok(());
+        }
+    }
+}
+
+fn search<F: FileSystem>(
+    fs: &F,
+    request: proto::SearchRequest,
+    emit: &mut dyn FnMut(proto::FileRef) -> bool,
+) -> Result<(), Status> {
+    let (pattern, options) =
+        search_from_proto(request).ok_or_else(|| Status::invalid_argument("Invalid search"))?;
+    let mut send = |id: FileId| {
+        emit(proto::FileRef {
+            id: id.into_u64_unchecked(),
+        })
+    };
+
+    if options.is_lazy() {
+        // Results are wanted in the order they're found, so they can be sent as they are
+        let after = options.cursor();
+        let ids = fs
+            .search_tags_iter(pattern)
+            .filter(|id| !matches!((id, after), (Ok(id), Some(after)) if *id <= after))
+            .skip(options.skipped())
+            .take(options.max_len().unwrap_or(usize::MAX));
+        for id in ids {
+            if !send(id.map_err(|err| status(&err))?) {
+                break;
+            }
+        }
+    } else {
+        let ids = fs
+            .search_tags_with(pattern, &options)
+            .map_err(|err| status(&err))?;
+        for id in ids {
+            if !send(id) {
+                break;
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,291 @@
+//! Implications between tags, kept inside a filesystem, and searches which respect them
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use std::io;
+
+use crate::error::ErrorKind;
+use crate::{
+    codec, FileId, FileSystemRead, FileSystemWrite, SpecialFile, Tag, TagPattern, TagPredicate,
+};
+
+/// Error while reading or changing the hierarchy of a filesystem
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The filesystem returned an error
+    Fs(E),
+    /// The stored hierarchy couldn't be read, as it was malformed
+    Corrupt(io::Error),
+    /// Giving this tag the requested parent would make it its own ancestor
+    Cycle(Tag),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+            Error::Cycle(_) => ErrorKind::InvalidTags,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored hierarchy is malformed: {err}"),
+            Error::Cycle(tag) => write!(f, "tag `{tag}` would be its own ancestor"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+            Error::Cycle(_) => None,
+        }
+    }
+}
+
+/// The parents of tags in a filesystem, stored in its [`SpecialFile::Hierarchy`] file. A tag
+/// implies its parent, and so every ancestor above that, so a file tagged `animal/cat` with the
+/// parent `animal` is found by searches for `animal` as well.
+///
+/// Implications only affect searches made and tags read through this handle. Files keep the tags
+/// they were given, so changing the hierarchy changes what's implied by files already stored.
+/// Tags are matched exactly, including their value, when looking up their parent.
+///
+/// Changes read every rule and write them all back, so two made at once from different handles
+/// can lose one of them.
+pub struct TagHierarchy<'a, F: ?Sized> {
+    fs: &'a F,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> TagHierarchy<'a, F> {
+    /// Get the hierarchy of a filesystem
+    pub fn new(fs: &'a F) -> TagHierarchy<'a, F> {
+        TagHierarchy { fs }
+    }
+
+    /// Get every tag with a parent, mapped to that parent
+    pub fn rules(&self) -> Result<BTreeMap<Tag, Tag>, Error<F::Error>> {
+        let data = self.fs.special_data(SpecialFile::Hierarchy)?;
+        let mut input = &*data;
+        let mut rules = BTreeMap::new();
+        while let Some(tag) = codec::read_tag(&mut input).map_err(Error::Corrupt)? {
+            let parent = codec::read_tag(&mut input)
+                .map_err(Error::Corrupt)?
+                .ok_or_else(|| Error::Corrupt(io::ErrorKind::UnexpectedEof.into()))?;
+            rules.insert(tag, parent);
+        }
+        Ok(rules)
+    }
+
+    /// Get the parent of a tag, if it has one
+    pub fn parent(&self, tag: &Tag) -> Result<Option<Tag>, Error<F::Error>> {
+        Ok(self.rules()?.remove(tag))
+    }
+
+    /// Get every tag implied by a set of tags, which is the tags themselves and all their
+    /// ancestors
+    pub fn implied_tags(&self, tags: &BTreeSet<Tag>) -> Result<BTreeSet<Tag>, Error<F::Error>> {
+        Ok(implied(&self.rules()?, tags))
+    }
+
+    /// Get the tags of a file along with every tag they imply
+    pub fn effective_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Error<F::Error>> {
+        let tags = self.fs.get_tags(id)?;
+        self.implied_tags(&tags)
+    }
+
+    /// Rewrite a predicate so that, evaluated against the tags of a file, it matches as the
+    /// original would against the file's effective tags
+    pub fn expand(&self, pred: &TagPredicate) -> Result<TagPredicate, Error<F::Error>> {
+        Ok(expand(&self.rules()?, pred))
+    }
+
+    /// Search for files whose effective tags match a pattern
+    pub fn search_tags<P>(&self, tags: &P) -> Result<Vec<FileId>, Error<F::Error>>
+    where
+        P: TagPattern + ?Sized,
+    {
+        let pred = self.expand(&tags.to_predicate())?;
+        Ok(self.fs.search_tags(pred)?)
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> TagHierarchy<'_, F> {
+    fn store(&self, rules: &BTreeMap<Tag, Tag>) -> Result<(), Error<F::Error>> {
+        let mut data = Vec::new();
+        for (tag, parent) in rules {
+            codec::write_tag(&mut data, tag).map_err(Error::Corrupt)?;
+            codec::write_tag(&mut data, parent).map_err(Error::Corrupt)?;
+        }
+        self.fs.set_special_data(SpecialFile::Hierarchy, &data)?;
+        Ok(())
+    }
+
+    /// Set the parent of a tag, replacing any it already had. Fails if the parent is the tag or
+    /// one of its descendants.
+    pub fn set_parent(&self, tag: Tag, parent: Tag) -> Result<(), Error<F::Error>> {
+        let mut rules = self.rules()?;
+        if ancestors(&rules, &parent).any(|ancestor| *ancestor == tag) {
+            return Err(Error::Cycle(tag));
+        }
+        rules.insert(tag, parent);
+        self.store(&rules)
+    }
+
+    /// Remove the parent of a tag, so it no longer implies anything. Returns whether it had one.
+    pub fn remove_parent(&self, tag: &Tag) -> Result<bool, Error<F::Error>> {
+        let mut rules = self.rules()?;
+        if rules.remove(tag).is_none() {
+            return Ok(false);
+        }
+        self.store(&rules)?;
+        Ok(true)
+    }
+}
+
+/// Walk from a tag up through its ancestors, starting with the tag itself. Stops if a tag is
+/// seen again, in case the stored rules were written with a cycle.
+fn ancestors<'a>(rules: &'a BTreeMap<Tag, Tag>, tag: &'a Tag) -> impl Iterator<Item = &'a Tag> {
+    let mut seen = BTreeSet::new();
+    core::iter::successors(Some(tag), move |tag| rules.get(tag))
+        .take_while(move |tag| seen.insert(*tag))
+}
+
+fn implied(rules: &BTreeMap<Tag, Tag>, tags: &BTreeSet<Tag>) -> BTreeSet<Tag> {
+    tags.iter()
+        .flat_map(|tag| ancestors(rules, tag))
+        .cloned()
+        .collect()
+}
+
+fn expand(rules: &BTreeMap<Tag, Tag>, pred: &TagPredicate) -> TagPredicate {
+    match pred {
+        TagPredicate::And(preds) => {
+            TagPredicate::And(preds.iter().map(|pred| expand(rules, pred)).collect())
+        }
+        TagPredicate::Or(preds) => {
+            TagPredicate::Or(preds.iter().map(|pred| expand(rules, pred)).collect())
+        }
+        TagPredicate::Not(pred) => TagPredicate::not(expand(rules, pred)),
+        // Every other predicate matches a file if any one tag matches, so it also matches
+        // through any tag with an ancestor it matches
+        leaf => {
+            let implying = rules
+                .keys()
+                .filter(|tag| ancestors(rules, tag).skip(1).any(|a| leaf.match_tags([a])))
+                .cloned()
+                .map(TagPredicate::Tag)
+                .collect::<Vec<_>>();
+            if implying.is_empty() {
+                leaf.clone()
+            } else {
+                TagPredicate::Or(core::iter::once(leaf.clone()).chain(implying).collect())
+            }
+        }
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{Group, InMemoryFs};
+
+    #[test]
+    fn test_hierarchy() {
+        let ifs = InMemoryFs::new();
+        let animal = Tag::named("animal");
+        let cat = Tag::named("animal/cat");
+        let kitten = Tag::named("animal/cat/kitten");
+        let dog = Tag::named("animal/dog");
+
+        let hierarchy = TagHierarchy::new(&ifs);
+        hierarchy.set_parent(cat.clone(), animal.clone()).unwrap();
+        hierarchy.set_parent(kitten.clone(), cat.clone()).unwrap();
+        hierarchy.set_parent(dog.clone(), animal.clone()).unwrap();
+        assert_eq!(hierarchy.parent(&kitten).unwrap(), Some(cat.clone()));
+        assert_eq!(hierarchy.parent(&animal).unwrap(), None);
+
+        let small = ifs.add_file(&[1], [kitten.clone()]).unwrap();
+        let big = ifs.add_file(&[2], [dog.clone()]).unwrap();
+        let other = ifs.add_file(&[3], [Tag::named("plant")]).unwrap();
+
+        assert_eq!(
+            hierarchy.effective_tags(small).unwrap(),
+            BTreeSet::from([animal.clone(), cat.clone(), kitten.clone()])
+        );
+        assert_eq!(hierarchy.search_tags(&animal).unwrap(), vec![small, big]);
+        assert_eq!(hierarchy.search_tags(&cat).unwrap(), vec![small]);
+        assert_eq!(
+            hierarchy
+                .search_tags(&TagPredicate::not(animal.clone()))
+                .unwrap(),
+            vec![other]
+        );
+        assert_eq!(
+            hierarchy
+                .search_tags(&TagPredicate::and([animal.clone(), dog.clone()]))
+                .unwrap(),
+            vec![big]
+        );
+        assert_eq!(
+            hierarchy
+                .search_tags(&TagPredicate::name_glob("ani*l"))
+                .unwrap(),
+            vec![small, big]
+        );
+        // Without the hierarchy, only the tags themselves are matched
+        assert!(ifs.search_tags(animal.clone()).unwrap().is_empty());
+
+        // Tags can't become their own ancestor
+        assert!(matches!(
+            hierarchy.set_parent(animal.clone(), kitten.clone()),
+            Err(Error::Cycle(_))
+        ));
+        assert!(matches!(
+            hierarchy.set_parent(cat.clone(), cat.clone()),
+            Err(Error::Cycle(_))
+        ));
+
+        // Moving a tag moves what it implies
+        let pets = Tag::new(Group::custom("pets"), "cat");
+        hierarchy.set_parent(cat.clone(), pets.clone()).unwrap();
+        assert_eq!(hierarchy.search_tags(&pets).unwrap(), vec![small]);
+        assert_eq!(hierarchy.search_tags(&animal).unwrap(), vec![big]);
+
+        assert!(hierarchy.remove_parent(&kitten).unwrap());
+        assert!(!hierarchy.remove_parent(&kitten).unwrap());
+        assert_eq!(
+            TagHierarchy::new(&ifs).effective_tags(small).unwrap(),
+            BTreeSet::from([kitten])
+        );
+    }
+}
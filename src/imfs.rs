@@ -3,26 +3,133 @@
 #[cfg(not(feature = "std"))]
 use spin::{RwLock, RwLockReadGuard as ReadGuard, RwLockWriteGuard as WriteGuard};
 #[cfg(feature = "std")]
+use std::io::Cursor;
+#[cfg(feature = "std")]
+use std::sync::mpsc::Receiver;
+#[cfg(feature = "std")]
 use std::sync::{
     PoisonError, RwLock, RwLockReadGuard as ReadGuard, RwLockWriteGuard as WriteGuard,
 };
+#[cfg(feature = "std")]
+use std::time::SystemTime;
 
 use alloc::borrow::ToOwned;
 use alloc::boxed::Box;
 use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
+use alloc::sync::Arc;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::{self, Write as _};
+use core::iter;
+use core::mem;
+use core::ops::{Bound, Range};
+use core::sync::atomic::{AtomicUsize, Ordering};
 
+use super::{
+    check_stored, generate_special, BatchResult, FileId, FileInfo, FileSystemRead, FileSystemWrite,
+    Group, InferredTags, ProvidedTags, Retention, SpecialFile, Tag, TagInferrer, TagPattern,
+    TagProvider,
+};
+#[cfg(feature = "std")]
+use super::{BufferedWriter, StreamRead, StreamWrite};
 use crate::error::ErrorKind;
-use super::{FileId, FileInfo, FileSystem, Tag, TagPattern};
+#[cfg(feature = "std")]
+use crate::events::Subscribers;
+use crate::events::{Change, Event};
+use crate::inference::{infer_tags, Inferrers};
+#[cfg(feature = "std")]
+use crate::metadata::{hash_data, now, Metadata};
+use crate::provider::{provide_tags, Providers};
+use crate::search::{self, SearchHit, SearchOptions, SortBy, SortKey};
+use crate::trace;
 
-type FileData = Vec<Box<[u8]>>;
+type FileData = BTreeMap<FileId, Arc<[u8]>>;
 type TagData = BTreeMap<FileId, BTreeSet<Tag>>;
+/// Prior versions of each file's data, oldest first
+type VersionData = BTreeMap<FileId, Vec<(u32, Arc<[u8]>)>>;
+/// Creation and modification times of each file
+#[cfg(feature = "std")]
+type TimeData = BTreeMap<FileId, (SystemTime, SystemTime)>;
+/// Hash of each file's data, kept up to date so metadata and hash lookups don't read the data
+#[cfg(feature = "std")]
+type HashData = BTreeMap<FileId, [u8; 32]>;
+/// The data of special files which aren't generated
+type SpecialData = BTreeMap<SpecialFile, Arc<[u8]>>;
+
+/// The state saved at the start of a transaction, or by [`InMemoryFs::snapshot`]. The change feed
+/// isn't part of it, as a transaction's changes are only recorded once it commits, and restoring
+/// a snapshot isn't recorded at all.
+#[derive(Clone)]
+struct Snapshot {
+    files: FileData,
+    tags: TagData,
+    versions: VersionData,
+    #[cfg(feature = "std")]
+    times: TimeData,
+    #[cfg(feature = "std")]
+    hashes: HashData,
+    special: SpecialData,
+    bytes: usize,
+}
+
+/// Hands out file IDs from a counter. Not part of transaction snapshots, so an ID handed out in
+/// a rolled back transaction is never handed out again.
+struct Ids {
+    next: u64,
+    /// IDs of removed files, which may be handed out again
+    free: BTreeSet<FileId>,
+}
+
+impl Ids {
+    fn new() -> Ids {
+        Ids {
+            next: 256,
+            free: BTreeSet::new(),
+        }
+    }
+
+    fn alloc(&mut self, reuse: bool, files: &FileData) -> FileId {
+        if reuse {
+            // A rolled back removal can leave a live file's ID in the free list
+            while let Some(id) = self.free.pop_first() {
+                if !files.contains_key(&id) {
+                    return id;
+                }
+            }
+        }
+
+        let id = FileId::from_u64_unchecked(self.next);
+        self.next += 1;
+        id
+    }
+
+    /// Record an ID chosen by the caller as used. Fails for the highest ID, which the counter
+    /// can't move past.
+    fn claim(&mut self, id: FileId) -> Result<(), Error> {
+        let next = id
+            .into_u64_unchecked()
+            .checked_add(1)
+            .ok_or(Error::AlreadyExists(id))?;
+        self.free.remove(&id);
+        self.next = self.next.max(next);
+        Ok(())
+    }
+}
 
 /// Error for an in-memory filesystem
 #[derive(Debug)]
 pub enum Error {
     /// The requested file did not exist
     FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// No snapshot was saved under the given label
+    SnapshotNotFound(String),
+    /// The filesystem is at its capacity, or memory for a file's data couldn't be allocated
+    StorageFull,
     /// The filesystem was poisoned by a thread panic
     Poisoned,
 }
@@ -39,32 +146,317 @@ impl crate::error::Error for Error {
         Self::FileNotFound(id)
     }
 
+    fn already_exists(id: FileId) -> Self {
+        Self::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Self::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Self::StorageFull
+    }
+
     fn generic_kind(&self) -> ErrorKind<'_> {
         match self {
             Self::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Self::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Self::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
             Self::Poisoned => ErrorKind::State,
+            Self::SnapshotNotFound(_) => ErrorKind::Other,
+            Self::StorageFull => ErrorKind::StorageFull,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::SnapshotNotFound(label) => write!(f, "no snapshot saved as `{label}`"),
+            Error::StorageFull => write!(f, "filesystem is out of room"),
+            Error::Poisoned => write!(f, "filesystem was poisoned by a thread panic"),
         }
     }
 }
 
+#[cfg(feature = "std")]
+impl std::error::Error for Error {}
+
 /// An in-memory implementation of a tag-based filesystem. This implementation
 /// will store all data in program memory, only persisting it for the duration of the
 /// program runtime.
 ///
 /// This is most useful for tests / mocking of a filesystem, and probably not what you want
 /// for long term usage.
+///
+/// By default, file IDs are never reused, and prior versions of file data aren't kept. These can
+/// be turned on with [`InMemoryFs::reuse_ids`] and [`InMemoryFs::retention`].
+///
+/// Every change made is numbered and kept, so the changes since any point can be read back with
+/// [`InMemoryFs::changes_since`].
+///
+/// File data is kept in shared buffers, so reading it with [`InMemoryFs::get_data_shared`] or
+/// [`read_file`](crate::StreamRead::read_file), and saving snapshots, doesn't copy it. A file's
+/// data is only copied when it's changed while something else still holds it.
+///
+/// For targets with little memory, the store can be bounded with [`InMemoryFs::with_capacity`].
+/// Shared buffers can't be allocated fallibly, so before file data is copied a buffer of the same
+/// size is allocated and freed. Running out of memory then usually fails the change with
+/// [`Error::StorageFull`], but can still abort if other allocations take the memory in between.
+/// The maps indexing files allocate as usual, so a bound on the number of files should be set too.
 pub struct InMemoryFs {
+    reuse_ids: bool,
+    retention: Retention,
+    max_files: Option<usize>,
+    max_bytes: Option<usize>,
+    /// Bytes of file data stored, including prior versions, changed along with the data
+    bytes: AtomicUsize,
+    ids: RwLock<Ids>,
     files: RwLock<FileData>,
     tags: RwLock<TagData>,
+    versions: RwLock<VersionData>,
+    #[cfg(feature = "std")]
+    times: RwLock<TimeData>,
+    #[cfg(feature = "std")]
+    hashes: RwLock<HashData>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    special: RwLock<SpecialData>,
+    snapshot: RwLock<Option<Snapshot>>,
+    snapshots: RwLock<BTreeMap<String, Snapshot>>,
+    /// Every change made, in order, for the change feed
+    feed: RwLock<Vec<Event>>,
+    /// Changes made in the running transaction, recorded in the feed once it commits
+    pending: RwLock<Option<Vec<Event>>>,
+    #[cfg(feature = "std")]
+    subscribers: Subscribers,
 }
 
 impl InMemoryFs {
     /// Create a new instance of an in-memory filesystem
     pub fn new() -> InMemoryFs {
         InMemoryFs {
-            files: RwLock::new(Vec::new()),
+            reuse_ids: false,
+            retention: Retention::Off,
+            max_files: None,
+            max_bytes: None,
+            bytes: AtomicUsize::new(0),
+            ids: RwLock::new(Ids::new()),
+            files: RwLock::new(BTreeMap::new()),
             tags: RwLock::new(BTreeMap::new()),
+            versions: RwLock::new(BTreeMap::new()),
+            #[cfg(feature = "std")]
+            times: RwLock::new(BTreeMap::new()),
+            #[cfg(feature = "std")]
+            hashes: RwLock::new(BTreeMap::new()),
+            providers: RwLock::new(BTreeMap::new()),
+            inferrers: RwLock::new(Vec::new()),
+            special: RwLock::new(BTreeMap::new()),
+            snapshot: RwLock::new(None),
+            snapshots: RwLock::new(BTreeMap::new()),
+            feed: RwLock::new(Vec::new()),
+            pending: RwLock::new(None),
+            #[cfg(feature = "std")]
+            subscribers: Subscribers::new(),
+        }
+    }
+
+    /// Set whether IDs of removed files are handed out again to new files, lowest first. When
+    /// off, new files always get a never before used ID.
+    #[must_use]
+    pub fn reuse_ids(mut self, reuse: bool) -> Self {
+        self.reuse_ids = reuse;
+        self
+    }
+
+    /// Create an in-memory filesystem which holds at most `files` files and `bytes` bytes of
+    /// file data. Shorthand for [`InMemoryFs::max_files`] and [`InMemoryFs::max_bytes`].
+    pub fn with_capacity(files: usize, bytes: usize) -> InMemoryFs {
+        InMemoryFs::new().max_files(files).max_bytes(bytes)
+    }
+
+    /// Set how many prior versions of file data are kept when files are edited
+    #[must_use]
+    pub fn retention(mut self, retention: Retention) -> Self {
+        self.retention = retention;
+        self
+    }
+
+    /// Limit how many files can be stored at once. Adding a file past the limit fails with
+    /// [`Error::StorageFull`].
+    #[must_use]
+    pub fn max_files(mut self, files: usize) -> Self {
+        self.max_files = Some(files);
+        self
+    }
+
+    /// Limit how many bytes of file data can be stored at once, including prior versions which
+    /// are kept. A change which would go past the limit fails with [`Error::StorageFull`]. When
+    /// versions are kept, an edit needs room for its data before the oldest version is dropped.
+    #[must_use]
+    pub fn max_bytes(mut self, bytes: usize) -> Self {
+        self.max_bytes = Some(bytes);
+        self
+    }
+
+    /// Reclaim memory left behind by edited and removed files. Prior versions beyond the
+    /// retention policy, such as after lowering it, are dropped, and IDs of live files are taken
+    /// out of the list of IDs free to reuse. Returns how many bytes of file data were freed.
+    pub fn compact(&self) -> Result<u64, Error> {
+        let mut freed = 0;
+        if self.retention.is_enabled() {
+            let mut versions = self.write_versions()?;
+            versions.retain(|_, file_versions| {
+                let excess = self.retention.excess(file_versions.len());
+                for (_, data) in file_versions.drain(..excess) {
+                    self.bytes.fetch_sub(data.len(), Ordering::Relaxed);
+                    freed += data.len() as u64;
+                }
+                file_versions.shrink_to_fit();
+                !file_versions.is_empty()
+            });
+        }
+
+        let files = self.read_files()?;
+        self.write_ids()?.free.retain(|id| !files.contains_key(id));
+        Ok(freed)
+    }
+
+    /// Save a copy of every file, version and special file under a label, to be put back later
+    /// with [`InMemoryFs::restore`]. Saving under a label already in use replaces what was saved
+    /// there. File data is shared with the filesystem until one of them changes it, so the copy
+    /// costs little memory at first.
+    pub fn snapshot(&self, label: &str) -> Result<(), Error> {
+        let saved = self.save_state()?;
+        self.write_snapshots()?.insert(label.to_owned(), saved);
+        Ok(())
+    }
+
+    /// Put every file, version and special file back as it was when saved under a label. The
+    /// snapshot is kept, so can be restored again. IDs handed out since it was saved still aren't
+    /// handed out again, and subscribers aren't told of the files changed.
+    pub fn restore(&self, label: &str) -> Result<(), Error> {
+        let saved = self
+            .read_snapshots()?
+            .get(label)
+            .cloned()
+            .ok_or_else(|| Error::SnapshotNotFound(label.to_owned()))?;
+        self.load_state(saved)
+    }
+
+    /// List the labels of every saved snapshot, in order
+    pub fn list_snapshots(&self) -> Result<Vec<String>, Error> {
+        Ok(self.read_snapshots()?.keys().cloned().collect())
+    }
+
+    /// Drop the snapshot saved under a label, freeing its memory. Returns whether there was one.
+    pub fn remove_snapshot(&self, label: &str) -> Result<bool, Error> {
+        Ok(self.write_snapshots()?.remove(label).is_some())
+    }
+
+    /// Get the sequence number of the last change made to the filesystem, or 0 if none have been
+    pub fn last_seq(&self) -> Result<u64, Error> {
+        Ok(self.read_feed()?.len() as u64)
+    }
+
+    /// Get every change made to the filesystem after the one with a sequence number, oldest
+    /// first. Changes made in a transaction are recorded once it commits, so ones undone by a
+    /// failed transaction never are, while restoring a snapshot isn't recorded at all.
+    pub fn changes_since(&self, seq: u64) -> Result<Vec<Change>, Error> {
+        let feed = self.read_feed()?;
+        let start = match usize::try_from(seq) {
+            Ok(start) if start < feed.len() => start,
+            _ => return Ok(Vec::new()),
+        };
+        Ok(feed[start..]
+            .iter()
+            .zip(seq + 1..)
+            .map(|(&event, seq)| Change::new(seq, event))
+            .collect())
+    }
+
+    /// Get the data of a file without copying it, unlike [`get_data`](FileSystemRead::get_data).
+    /// The buffer is shared with the filesystem, and keeps the data it was given even if the file
+    /// is changed or removed afterwards.
+    pub fn get_data_shared(&self, id: FileId) -> Result<Arc<[u8]>, Error> {
+        self.read_files()?
+            .get(&id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    /// Get when a file was added and last modified
+    #[cfg(feature = "wasm")]
+    pub(crate) fn times(&self, id: FileId) -> Result<(SystemTime, SystemTime), Error> {
+        self.times
+            .read()?
+            .get(&id)
+            .copied()
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    /// Set when a file was added and last modified, for backends keeping files in an
+    /// [`InMemoryFs`] which load them from elsewhere
+    #[cfg(feature = "wasm")]
+    pub(crate) fn set_times(
+        &self,
+        id: FileId,
+        created: SystemTime,
+        modified: SystemTime,
+    ) -> Result<(), Error> {
+        self.assert_file_exists(id)?;
+        self.times.write()?.insert(id, (created, modified));
+        Ok(())
+    }
+
+    fn save_state(&self) -> Result<Snapshot, Error> {
+        Ok(Snapshot {
+            files: self.read_files()?.clone(),
+            tags: self.read_tags()?.clone(),
+            versions: self.read_versions()?.clone(),
+            #[cfg(feature = "std")]
+            times: self.times.read()?.clone(),
+            #[cfg(feature = "std")]
+            hashes: self.hashes.read()?.clone(),
+            special: self.read_special()?.clone(),
+            bytes: self.bytes.load(Ordering::Relaxed),
+        })
+    }
+
+    fn load_state(&self, state: Snapshot) -> Result<(), Error> {
+        *self.write_files()? = state.files;
+        *self.write_tags()? = state.tags;
+        *self.write_versions()? = state.versions;
+        #[cfg(feature = "std")]
+        {
+            *self.times.write()? = state.times;
+            *self.hashes.write()? = state.hashes;
         }
+        *self.write_special()? = state.special;
+        self.bytes.store(state.bytes, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn read_ids(&self) -> Result<ReadGuard<'_, Ids>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.ids.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.ids.read();
+        Ok(out)
+    }
+
+    fn write_ids(&self) -> Result<WriteGuard<'_, Ids>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.ids.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.ids.write();
+        Ok(out)
     }
 
     fn read_files(&self) -> Result<ReadGuard<'_, FileData>, Error> {
@@ -99,111 +491,1169 @@ impl InMemoryFs {
         Ok(out)
     }
 
+    fn read_versions(&self) -> Result<ReadGuard<'_, VersionData>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.versions.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.versions.read();
+        Ok(out)
+    }
+
+    fn write_versions(&self) -> Result<WriteGuard<'_, VersionData>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.versions.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.versions.write();
+        Ok(out)
+    }
+
+    fn read_providers(&self) -> Result<ReadGuard<'_, Providers>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.providers.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.providers.read();
+        Ok(out)
+    }
+
+    fn write_providers(&self) -> Result<WriteGuard<'_, Providers>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.providers.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.providers.write();
+        Ok(out)
+    }
+
+    fn read_inferrers(&self) -> Result<ReadGuard<'_, Inferrers>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.inferrers.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.inferrers.read();
+        Ok(out)
+    }
+
+    fn write_inferrers(&self) -> Result<WriteGuard<'_, Inferrers>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.inferrers.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.inferrers.write();
+        Ok(out)
+    }
+
+    fn read_special(&self) -> Result<ReadGuard<'_, SpecialData>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.special.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.special.read();
+        Ok(out)
+    }
+
+    fn write_special(&self) -> Result<WriteGuard<'_, SpecialData>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.special.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.special.write();
+        Ok(out)
+    }
+
+    fn read_feed(&self) -> Result<ReadGuard<'_, Vec<Event>>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.feed.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.feed.read();
+        Ok(out)
+    }
+
+    fn write_pending(&self) -> Result<WriteGuard<'_, Option<Vec<Event>>>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.pending.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.pending.write();
+        Ok(out)
+    }
+
+    fn read_snapshots(&self) -> Result<ReadGuard<'_, BTreeMap<String, Snapshot>>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.snapshots.read()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.snapshots.read();
+        Ok(out)
+    }
+
+    fn write_snapshots(&self) -> Result<WriteGuard<'_, BTreeMap<String, Snapshot>>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.snapshots.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.snapshots.write();
+        Ok(out)
+    }
+
+    fn write_snapshot(&self) -> Result<WriteGuard<'_, Option<Snapshot>>, Error> {
+        #[cfg(feature = "std")]
+        let out = self.snapshot.write()?;
+        #[cfg(not(feature = "std"))]
+        let out = self.snapshot.write();
+        Ok(out)
+    }
+
     fn assert_file_exists(&self, id: FileId) -> Result<(), Error> {
         self.read_tags()?
             .get(&id)
             .map(|_| ())
             .ok_or(Error::FileNotFound(id))
     }
-}
 
-impl Default for InMemoryFs {
-    fn default() -> Self {
-        InMemoryFs::new()
+    /// Record a change in the change feed, then tell subscribers of it. Changes made in a
+    /// transaction are held until it commits.
+    fn emit(&self, event: Event) {
+        #[cfg(feature = "std")]
+        let mut pending = self.pending.write().unwrap_or_else(PoisonError::into_inner);
+        #[cfg(not(feature = "std"))]
+        let mut pending = self.pending.write();
+        if let Some(pending) = &mut *pending {
+            pending.push(event);
+        } else {
+            drop(pending);
+            self.publish(event);
+        }
     }
-}
 
-impl FileSystem for InMemoryFs {
-    type Error = Error;
+    /// Record a change in the change feed straight away, then tell subscribers of it
+    fn publish(&self, event: Event) {
+        #[cfg(feature = "std")]
+        {
+            self.feed
+                .write()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(event);
+            self.subscribers.emit(event);
+        }
+        #[cfg(not(feature = "std"))]
+        self.feed.write().push(event);
+    }
 
-    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
-    where
-        I: IntoIterator<Item = Tag>,
-    {
-        let new_id = {
-            let mut files = self.write_files()?;
-            files.push(data.to_owned().into_boxed_slice());
+    /// Check there's room for a file to be added, with data of the given length
+    fn check_room(&self, files: &FileData, len: usize) -> Result<(), Error> {
+        if self.max_files.is_some_and(|max| files.len() >= max) {
+            return Err(Error::StorageFull);
+        }
+        self.check_bytes(len, 0)
+    }
 
-            FileId::from_u64_unchecked(files.len() as u64 + 255)
+    /// Check the file data stored stays within the limit, once data of one length is added and
+    /// data of another is dropped
+    fn check_bytes(&self, added: usize, dropped: usize) -> Result<(), Error> {
+        let Some(max) = self.max_bytes else {
+            return Ok(());
         };
+        let used = self.bytes.load(Ordering::Relaxed);
+        if (used - dropped).saturating_add(added) > max {
+            return Err(Error::StorageFull);
+        }
+        Ok(())
+    }
 
-        let mut tags_map = self.write_tags()?;
-        tags_map.insert(new_id, tags.into_iter().collect());
+    /// Replace the data of a file, keeping the old data as a new version if `versioned` is set
+    /// and the retention policy allows it
+    fn replace_data(&self, id: FileId, data: &[u8], versioned: bool) -> Result<(), Error> {
+        self.assert_file_exists(id)?;
+        let data = copy_data(data)?;
 
-        Ok(new_id)
+        let mut files = self.write_files()?;
+        let versioned = versioned && self.retention.is_enabled();
+        let old_len = files.get(&id).map_or(0, |old| old.len());
+        self.check_bytes(data.len(), if versioned { 0 } else { old_len })?;
+        #[cfg(feature = "std")]
+        self.rehash(id, &data)?;
+        self.resized(old_len, data.len(), versioned);
+        let old = files.insert(id, data).unwrap_or_default();
+        self.edited(id, versioned.then_some(old))
     }
 
-    fn edit_file<I>(
-        &self,
-        id: FileId,
-        data: Option<&[u8]>,
-        tags: Option<I>,
-    ) -> Result<(), Self::Error>
+    /// Change part of a file's data in place, keeping the old data as a new version if the
+    /// retention policy allows it. The data is resized to the length given by `len` from its old
+    /// length, and then patched.
+    fn patch_data<L, P>(&self, id: FileId, len: L, patch: P) -> Result<(), Error>
     where
-        I: IntoIterator<Item = Tag>,
+        L: FnOnce(usize) -> usize,
+        P: FnOnce(&mut [u8]),
     {
-        self.assert_file_exists(id)?;
+        let mut files = self.write_files()?;
+        let versioned = self.retention.is_enabled();
+        let old_len = files.get(&id).ok_or(Error::FileNotFound(id))?.len();
+        let new_len = len(old_len);
+        self.check_bytes(new_len, if versioned { 0 } else { old_len })?;
 
-        if let Some(data) = data {
-            let mut files = self.write_files()?;
-            files[(id.into_u64_unchecked() - 255) as usize] = data.to_owned().into_boxed_slice();
+        let Some(file) = files.get_mut(&id) else {
+            return Err(Error::FileNotFound(id));
+        };
+        // Data still held elsewhere, such as by a reader or a snapshot, has to be copied instead
+        if let Some(data) = Arc::get_mut(file).filter(|_| !versioned && new_len == old_len) {
+            patch(data);
+            #[cfg(feature = "std")]
+            self.rehash(id, data)?;
+            return self.edited(id, None);
         }
-        if let Some(tags) = tags {
-            let mut tags_map = self.write_tags()?;
-            tags_map.insert(id, tags.into_iter().collect());
+        check_alloc(new_len)?;
+        let kept = old_len.min(new_len);
+        // Collected straight into the shared buffer, so the data is only copied once
+        let mut data = file[..kept]
+            .iter()
+            .copied()
+            .chain(iter::repeat_n(0, new_len - kept))
+            .collect::<Arc<[u8]>>();
+        if let Some(buf) = Arc::get_mut(&mut data) {
+            patch(buf);
         }
-
-        Ok(())
+        #[cfg(feature = "std")]
+        self.rehash(id, &data)?;
+        self.resized(old_len, new_len, versioned);
+        let old = mem::replace(file, data);
+        self.edited(id, versioned.then_some(old))
     }
 
-    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
-        self.assert_file_exists(id)?;
-
-        let mut files = self.write_files()?;
-        files[(id.into_u64_unchecked() - 255) as usize] = Box::new([]) as Box<[u8]>;
-        let mut tags_map = self.write_tags()?;
-        tags_map.remove(&id);
+    /// Record the hash of a file's new data
+    #[cfg(feature = "std")]
+    fn rehash(&self, id: FileId, data: &[u8]) -> Result<(), Error> {
+        self.hashes.write()?.insert(id, hash_data(data));
         Ok(())
     }
 
-    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
-    where
-        P: TagPattern,
-    {
-        let mut out = Vec::new();
-        for (id, file_tags) in self.read_tags()?.iter() {
-            if tags.match_tags(file_tags) {
-                out.push(*id);
-            }
+    /// Count the bytes stored once a file's data is replaced, with the old data kept as a
+    /// version if `kept` is set
+    fn resized(&self, old_len: usize, new_len: usize, kept: bool) {
+        self.bytes.fetch_add(new_len, Ordering::Relaxed);
+        if !kept {
+            self.bytes.fetch_sub(old_len, Ordering::Relaxed);
         }
-        Ok(out)
     }
 
-    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
-        self.assert_file_exists(id)?;
+    /// Record an edit of a file's data, keeping the old data as a new version if given
+    fn edited(&self, id: FileId, old: Option<Arc<[u8]>>) -> Result<(), Error> {
+        if let Some(old) = old {
+            let mut versions = self.write_versions()?;
+            let file_versions = versions.entry(id).or_default();
+            let next = file_versions.last().map_or(1, |(version, _)| version + 1);
+            file_versions.push((next, old));
+            let excess = self.retention.excess(file_versions.len());
+            for (_, data) in file_versions.drain(..excess) {
+                self.bytes.fetch_sub(data.len(), Ordering::Relaxed);
+            }
+        }
 
-        Ok(FileInfo {
-            id,
-            data: self.read_files()?[(id.into_u64_unchecked() - 255) as usize].clone(),
-            tags: self.read_tags()?.get(&id).unwrap().clone(),
-        })
+        #[cfg(feature = "std")]
+        if let Some((_, modified)) = self.times.write()?.get_mut(&id) {
+            *modified = now();
+        }
+        self.emit(Event::FileEdited(id));
+        Ok(())
     }
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-
-    #[test]
-    pub fn test_add_file() {
-        let ifs = InMemoryFs::new();
+impl Default for InMemoryFs {
+    fn default() -> Self {
+        InMemoryFs::new()
+    }
+}
 
-        let id = ifs.add_file(&[0, 1, 2], []).unwrap();
+/// The running transaction of an [`InMemoryFs`]. If it's dropped without being ended, such as
+/// when its closure panics, its changes are undone.
+struct Transaction<'a> {
+    fs: &'a InMemoryFs,
+    ended: bool,
+}
 
-        assert_eq!(id, FileId::from_u64_unchecked(256));
+impl Transaction<'_> {
+    /// Keep the changes made in the transaction, recording them in the change feed, or undo them
+    fn end(&mut self, commit: bool) -> Result<(), Error> {
+        self.ended = true;
+        let snapshot = self.fs.write_snapshot()?.take();
+        let events = self.fs.write_pending()?.take().unwrap_or_default();
+        match snapshot {
+            Some(snapshot) if !commit => self.fs.load_state(snapshot)?,
+            _ => {
+                for event in events {
+                    self.fs.publish(event);
+                }
+            }
+        }
+        Ok(())
     }
+}
 
-    #[test]
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.end(false);
+        }
+    }
+}
+
+impl FileSystemRead for InMemoryFs {
+    type Error = Error;
+    type SearchIter<'a, P: TagPattern + 'a> = SearchIter<'a, P>;
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(found = tracing::field::Empty),
+            err(level = "debug")
+        )
+    )]
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let ids = self.search_tags_iter(tags).collect::<Result<Vec<_>, _>>()?;
+        trace::record("found", ids.len());
+        Ok(ids)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err(level = "debug"))
+    )]
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        if options.is_lazy() {
+            return search::page_lazy(self.search_tags_iter(tags), options);
+        }
+
+        let ids = self.search_tags(tags)?;
+        let providers = self.read_providers()?;
+        let files = self.read_files()?;
+        let tags_map = self.read_tags()?;
+        #[cfg(feature = "std")]
+        let times = self.times.read()?;
+
+        search::sort_results(ids, options, |id| match options.sort() {
+            SortBy::Id => Ok(SortKey::Id),
+            #[cfg(feature = "std")]
+            SortBy::Created => Ok(SortKey::Time(
+                times.get(&id).ok_or(Error::FileNotFound(id))?.0,
+            )),
+            SortBy::Size => Ok(SortKey::Size(
+                files.get(&id).ok_or(Error::FileNotFound(id))?.len() as u64,
+            )),
+            SortBy::Value { group, name } => {
+                let file_tags = tags_map.get(&id).ok_or(Error::FileNotFound(id))?;
+                let data = files.get(&id).ok_or(Error::FileNotFound(id))?;
+                let provided = provide_tags(&providers, data);
+                Ok(search::value_key(
+                    file_tags.iter().chain(&provided),
+                    group,
+                    name,
+                ))
+            }
+        })
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let ids = self.search_tags(pattern)?;
+        let providers = self.read_providers()?;
+        let files = self.read_files()?;
+        let tags_map = self.read_tags()?;
+
+        let mut out = BTreeMap::new();
+        for id in ids {
+            let (Some(file_tags), Some(data)) = (tags_map.get(&id), files.get(&id)) else {
+                continue;
+            };
+            if providers.is_empty() {
+                for tag in file_tags {
+                    *out.entry(tag.clone()).or_insert(0) += 1;
+                }
+            } else {
+                let mut file_tags = file_tags.clone();
+                file_tags.extend(provide_tags(&providers, data));
+                for tag in file_tags {
+                    *out.entry(tag).or_insert(0) += 1;
+                }
+            }
+        }
+        Ok(out)
+    }
+
+    fn search_detailed<P>(&self, tags: P) -> Result<Vec<SearchHit>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = tags.to_predicate();
+        let ids = self.search_tags(&pattern)?;
+        let providers = self.read_providers()?;
+        let files = self.read_files()?;
+        let tags_map = self.read_tags()?;
+
+        let mut out = Vec::with_capacity(ids.len());
+        for id in ids {
+            let (Some(file_tags), Some(data)) = (tags_map.get(&id), files.get(&id)) else {
+                continue;
+            };
+            let mut file_tags = file_tags.clone();
+            file_tags.extend(provide_tags(&providers, data));
+            out.push(SearchHit::new(id, file_tags, &pattern));
+        }
+        Ok(out)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags,
+            cursor: Bound::Unbounded,
+            done: false,
+        }
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        self.assert_file_exists(id)?;
+
+        let data = self.get_data_shared(id)?;
+        let mut tags = self.read_tags()?.get(&id).unwrap().clone();
+        tags.extend(provide_tags(&*self.read_providers()?, &data));
+
+        Ok(FileInfo {
+            id,
+            tags,
+            data: Box::from(&*data),
+        })
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let mut tags = self
+            .read_tags()?
+            .get(&id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))?;
+        let providers = self.read_providers()?;
+        if !providers.is_empty() {
+            if let Some(data) = self.read_files()?.get(&id) {
+                tags.extend(provide_tags(&providers, data));
+            }
+        }
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.read_tags()?
+            .get(&id)
+            .cloned()
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        let providers = self.read_providers()?;
+        let files = self.read_files()?;
+        let tags_map = self.read_tags()?;
+
+        Ok(ids
+            .iter()
+            .map(|&id| {
+                let (Some(tags), Some(data)) = (tags_map.get(&id), files.get(&id)) else {
+                    return Err(Error::FileNotFound(id));
+                };
+                let mut tags = tags.clone();
+                tags.extend(provide_tags(&providers, data));
+                Ok(FileInfo {
+                    id,
+                    tags,
+                    data: Box::from(&**data),
+                })
+            })
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        let providers = self.read_providers()?;
+        let files = self.read_files()?;
+        let tags_map = self.read_tags()?;
+
+        Ok(ids
+            .iter()
+            .map(|&id| {
+                let mut tags = tags_map.get(&id).cloned().ok_or(Error::FileNotFound(id))?;
+                if let Some(data) = files.get(&id).filter(|_| !providers.is_empty()) {
+                    tags.extend(provide_tags(&providers, data));
+                }
+                Ok(tags)
+            })
+            .collect())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err(level = "debug"))
+    )]
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.read_files()?
+            .get(&id)
+            .map(|data| data.to_vec())
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let files = self.read_files()?;
+        let data = files.get(&id).ok_or(Error::FileNotFound(id))?;
+        Ok(data[crate::clamp_range(range, data.len())].to_vec())
+    }
+
+    #[cfg(feature = "std")]
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let (created, modified) = *self.times.read()?.get(&id).ok_or(Error::FileNotFound(id))?;
+        let files = self.read_files()?;
+        let data = files.get(&id).ok_or(Error::FileNotFound(id))?;
+        let hash = *self
+            .hashes
+            .read()?
+            .get(&id)
+            .ok_or(Error::FileNotFound(id))?;
+
+        Ok(Metadata {
+            created,
+            modified,
+            size: data.len() as u64,
+            hash,
+        })
+    }
+
+    #[cfg(feature = "std")]
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        let mut by_hash = BTreeMap::<[u8; 32], Vec<FileId>>::new();
+        for (&id, &hash) in &*self.hashes.read()? {
+            by_hash.entry(hash).or_default().push(id);
+        }
+        let mut groups = by_hash
+            .into_values()
+            .filter(|ids| ids.len() > 1)
+            .collect::<Vec<_>>();
+        groups.sort_unstable();
+        Ok(groups)
+    }
+
+    #[cfg(feature = "std")]
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self
+            .hashes
+            .read()?
+            .iter()
+            .find(|(_, file_hash)| *file_hash == hash)
+            .map(|(&id, _)| id))
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let next = self.read_ids()?.next;
+        if next > 256 {
+            Ok(Some(FileId::from_u64_unchecked(next - 1)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self
+            .read_tags()?
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .map(|(id, _)| *id)
+            .collect())
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.assert_file_exists(id)?;
+        Ok(self
+            .read_versions()?
+            .get(&id)
+            .map(|versions| versions.iter().map(|(version, _)| *version).collect())
+            .unwrap_or_default())
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.assert_file_exists(id)?;
+        self.read_versions()?
+            .get(&id)
+            .and_then(|versions| versions.iter().find(|(num, _)| *num == version))
+            .map(|(_, data)| Box::from(&**data))
+            .ok_or(Error::VersionNotFound(id, version))
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        // Removed IDs are recorded so they can be reused, so the trash is every one which hasn't
+        // been yet
+        let SpecialFile::Trash = file else {
+            return generate_special(self, file);
+        };
+        let files = self.read_files()?;
+        let mut out = String::new();
+        for id in &self.read_ids()?.free {
+            // A rolled back removal can leave a live file's ID in the free list
+            if !files.contains_key(id) {
+                let _ = writeln!(out, "{:016X}", id.into_u64_unchecked());
+            }
+        }
+        Ok(FileInfo::new(file.id(), [], out.into_bytes()))
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self
+            .read_special()?
+            .get(&file)
+            .map(|data| data.to_vec())
+            .unwrap_or_default())
+    }
+
+    #[cfg(feature = "std")]
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl FileSystemWrite for InMemoryFs {
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(bytes = data.len(), tags = tracing::field::Empty),
+            ret(Display),
+            err
+        )
+    )]
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let new_id = {
+            let copy = copy_data(data)?;
+            let mut files = self.write_files()?;
+            self.check_room(&files, data.len())?;
+            let id = self.write_ids()?.alloc(self.reuse_ids, &files);
+            files.insert(id, copy);
+            self.bytes.fetch_add(data.len(), Ordering::Relaxed);
+            id
+        };
+
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.read_inferrers()?, data));
+        trace::record("tags", tags.len());
+
+        let mut tags_map = self.write_tags()?;
+        tags_map.insert(new_id, tags);
+
+        #[cfg(feature = "std")]
+        {
+            let now = now();
+            self.times.write()?.insert(new_id, (now, now));
+            self.rehash(new_id, data)?;
+        }
+
+        self.emit(Event::FileAdded(new_id));
+        Ok(new_id)
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(id = %id, bytes = data.len(), tags = tracing::field::Empty),
+            err
+        )
+    )]
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if id.into_u64_unchecked() < 256 {
+            return Err(Error::AlreadyExists(id));
+        }
+
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.read_inferrers()?, data));
+        trace::record("tags", tags.len());
+
+        {
+            let copy = copy_data(data)?;
+            let mut files = self.write_files()?;
+            let mut tags_map = self.write_tags()?;
+            if tags_map.contains_key(&id) {
+                return Err(Error::AlreadyExists(id));
+            }
+            self.check_room(&files, data.len())?;
+
+            self.write_ids()?.claim(id)?;
+            files.insert(id, copy);
+            self.bytes.fetch_add(data.len(), Ordering::Relaxed);
+            tags_map.insert(id, tags);
+        }
+
+        #[cfg(feature = "std")]
+        {
+            let now = now();
+            self.times.write()?.insert(id, (now, now));
+            self.rehash(id, data)?;
+        }
+
+        self.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(
+            level = "debug",
+            skip_all,
+            fields(id = %id, bytes = data.map(<[u8]>::len), tags = tracing::field::Empty),
+            err
+        )
+    )]
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.assert_file_exists(id)?;
+
+        if let Some(data) = data {
+            self.replace_data(id, data, true)?;
+        }
+        if let Some(tags) = tags {
+            let tags = tags.into_iter().collect::<BTreeSet<_>>();
+            trace::record("tags", tags.len());
+            let mut tags_map = self.write_tags()?;
+            tags_map.insert(id, tags);
+            self.emit(Event::TagsChanged(id));
+        }
+
+        Ok(())
+    }
+
+    fn write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        if data.is_empty() {
+            return self.assert_file_exists(id);
+        }
+        let start = usize::try_from(offset).map_err(|_| Error::StorageFull)?;
+        let end = start.checked_add(data.len()).ok_or(Error::StorageFull)?;
+        self.patch_data(
+            id,
+            |len| len.max(end),
+            |buf| {
+                buf[start..end].copy_from_slice(data);
+            },
+        )
+    }
+
+    fn truncate(&self, id: FileId, len: u64) -> Result<(), Self::Error> {
+        let len = usize::try_from(len).map_err(|_| Error::StorageFull)?;
+        self.patch_data(id, |_| len, |_| ())
+    }
+
+    fn supports_partial_writes(&self) -> bool {
+        true
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.write_tags()?
+            .get_mut(&id)
+            .ok_or(Error::FileNotFound(id))?
+            .extend(tags);
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags_map = self.write_tags()?;
+        let file_tags = tags_map.get_mut(&id).ok_or(Error::FileNotFound(id))?;
+        for tag in tags {
+            file_tags.remove(&tag);
+        }
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        for (&id, tags) in self.write_tags()?.iter_mut() {
+            if tags.remove(old) {
+                tags.insert(new.clone());
+                self.emit(Event::TagsChanged(id));
+            }
+        }
+        Ok(())
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        for (&id, tags) in self.write_tags()?.iter_mut() {
+            if tags.iter().any(|tag| tag.group() == old) {
+                *tags = mem::take(tags)
+                    .into_iter()
+                    .map(|tag| crate::rename_group(tag, old, &new))
+                    .collect();
+                self.emit(Event::TagsChanged(id));
+            }
+        }
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, fields(id = %id), err)
+    )]
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.assert_file_exists(id)?;
+
+        let mut files = self.write_files()?;
+        let removed = files.remove(&id).into_iter();
+        self.write_ids()?.free.insert(id);
+        let mut tags_map = self.write_tags()?;
+        tags_map.remove(&id);
+        let versions = self.write_versions()?.remove(&id).unwrap_or_default();
+        let dropped = removed
+            .chain(versions.into_iter().map(|(_, data)| data))
+            .map(|data| data.len())
+            .sum::<usize>();
+        self.bytes.fetch_sub(dropped, Ordering::Relaxed);
+        #[cfg(feature = "std")]
+        {
+            self.times.write()?.remove(&id);
+            self.hashes.write()?.remove(&id);
+        }
+        self.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    #[cfg_attr(
+        feature = "tracing",
+        tracing::instrument(level = "debug", skip_all, err)
+    )]
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut snapshot = self.write_snapshot()?;
+            if snapshot.is_some() {
+                drop(snapshot);
+                return f(self);
+            }
+            *snapshot = Some(self.save_state()?);
+            *self.write_pending()? = Some(Vec::new());
+        }
+
+        let mut transaction = Transaction {
+            fs: self,
+            ended: false,
+        };
+        let out = f(self);
+        transaction.end(out.is_ok())?;
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        check_stored::<Self::Error>(file)?;
+        self.write_special()?.insert(file, copy_data(data)?);
+        Ok(())
+    }
+}
+
+impl ProvidedTags for InMemoryFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.write_providers()?.insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl InferredTags for InMemoryFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.write_inferrers()?.push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl StreamRead for InMemoryFs {
+    type Reader<'a> = Cursor<Arc<[u8]>>;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.assert_file_exists(id)?;
+        Ok(Cursor::new(self.get_data_shared(id)?))
+    }
+}
+
+#[cfg(feature = "std")]
+impl StreamWrite for InMemoryFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        // Flushing again only adds more of the new file's data, so isn't kept as a version
+        Ok(BufferedWriter::new(self, tags)
+            .with_replace(|fs, id, data| fs.replace_data(id, data, false)))
+    }
+}
+
+/// Copy file data into a shared buffer, first checking there's likely the memory for it
+fn copy_data(data: &[u8]) -> Result<Arc<[u8]>, Error> {
+    check_alloc(data.len())?;
+    Ok(Arc::from(data))
+}
+
+/// Check for the memory to allocate a buffer of the given size, by allocating and freeing one.
+/// Shared buffers can't be allocated fallibly, so this is the best that can be done before making
+/// one, though other allocations may still take the memory in between.
+fn check_alloc(len: usize) -> Result<(), Error> {
+    Vec::<u8>::new()
+        .try_reserve_exact(len)
+        .map_err(|_| Error::StorageFull)
+}
+
+/// A lazy search over an [`InMemoryFs`]. No locks are held between calls to `next`, so files
+/// added or removed during iteration may or may not be seen.
+pub struct SearchIter<'a, P> {
+    fs: &'a InMemoryFs,
+    pattern: P,
+    cursor: Bound<FileId>,
+    done: bool,
+}
+
+impl<P: TagPattern> SearchIter<'_, P> {
+    fn advance(&mut self) -> Result<Option<FileId>, Error> {
+        let providers = self.fs.read_providers()?;
+        let files = self.fs.read_files()?;
+        for (&id, file_tags) in self.fs.read_tags()?.range((self.cursor, Bound::Unbounded)) {
+            self.cursor = Bound::Excluded(id);
+
+            let matched = if providers.is_empty() {
+                self.pattern.match_tags(file_tags)
+            } else {
+                let Some(data) = files.get(&id) else {
+                    continue;
+                };
+                let provided = provide_tags(&providers, data);
+                self.pattern.match_tags(file_tags.iter().chain(&provided))
+            };
+
+            if matched {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<P: TagPattern> Iterator for SearchIter<'_, P> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let out = self.advance().transpose();
+        self.done = !matches!(out, Some(Ok(_)));
+        out
+    }
+}
+
+/// A handle streaming data into a new file of an [`InMemoryFs`]. Data is buffered until the
+/// handle is flushed, which adds the file or updates its data if it already exists.
+#[cfg(feature = "std")]
+pub type Writer<'a> = BufferedWriter<'a, InMemoryFs>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::Error as _;
+    use crate::{FileWriter, SpecialFile, TagPredicate};
+
+    #[cfg(feature = "testing")]
+    #[test]
+    pub fn test_conformance() {
+        crate::testkit::assert_filesystem_conformance(InMemoryFs::new);
+    }
+
+    #[test]
+    pub fn test_add_file() {
+        let ifs = InMemoryFs::new();
+
+        let id = ifs.add_file(&[0, 1, 2], []).unwrap();
+
+        assert_eq!(id, FileId::from_u64_unchecked(256));
+    }
+
+    #[test]
+    pub fn test_add_file_with_id() {
+        let ifs = InMemoryFs::new();
+
+        let chosen = FileId::from_u64_unchecked(260);
+        ifs.add_file_with_id(chosen, &[1], [Tag::named("a")])
+            .unwrap();
+        assert_eq!(ifs.get_info(chosen).unwrap().data(), &[1]);
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [chosen]);
+
+        assert!(matches!(
+            ifs.add_file_with_id(chosen, &[], []),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(
+            ifs.add_file_with_id(FileId::from_u64_unchecked(3), &[], []),
+            Err(Error::AlreadyExists(_))
+        ));
+        // The ID counter can't move past the highest ID
+        let highest = FileId::from_u64_unchecked(u64::MAX);
+        assert!(matches!(
+            ifs.add_file_with_id(highest, &[], []),
+            Err(Error::AlreadyExists(_))
+        ));
+        assert!(matches!(ifs.get_info(highest), Err(Error::FileNotFound(_))));
+
+        let id = ifs.add_file(&[], []).unwrap();
+        assert_eq!(id, FileId::from_u64_unchecked(261));
+    }
+
+    #[test]
+    pub fn test_remove_file() {
+        let ifs = InMemoryFs::new();
+
+        let first = ifs.add_file(&[0], [Tag::named("a")]).unwrap();
+        let second = ifs.add_file(&[1], [Tag::named("a")]).unwrap();
+        ifs.remove_file(first).unwrap();
+
+        assert!(matches!(ifs.get_info(first), Err(Error::FileNotFound(_))));
+        assert!(matches!(
+            ifs.remove_file(first),
+            Err(Error::FileNotFound(_))
+        ));
+        assert_eq!(ifs.get_info(second).unwrap().data(), &[1]);
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [second]);
+
+        let third = ifs.add_file(&[2], []).unwrap();
+        assert!(third > second);
+        assert_eq!(ifs.get_info(third).unwrap().data(), &[2]);
+        assert_eq!(ifs.get_info(second).unwrap().data(), &[1]);
+    }
+
+    #[test]
+    pub fn test_reuse_ids() {
+        let ifs = InMemoryFs::new().reuse_ids(true);
+
+        let first = ifs.add_file(&[0], []).unwrap();
+        let second = ifs.add_file(&[1], []).unwrap();
+        ifs.remove_file(first).unwrap();
+
+        assert_eq!(ifs.add_file(&[2], []).unwrap(), first);
+        assert_eq!(ifs.get_info(first).unwrap().data(), &[2]);
+        assert_eq!(ifs.get_info(second).unwrap().data(), &[1]);
+        assert!(ifs.add_file(&[3], []).unwrap() > second);
+    }
+
+    #[test]
+    pub fn test_capacity() {
+        let ifs = InMemoryFs::with_capacity(2, 10);
+        let a = ifs.add_file(&[0; 4], []).unwrap();
+        let b = ifs.add_file(&[0; 4], []).unwrap();
+        let err = ifs.add_file(&[], []).unwrap_err();
+        assert!(matches!(err, Error::StorageFull));
+        assert!(matches!(err.generic_kind(), ErrorKind::StorageFull));
+
+        // Edits can't go over the byte limit, but can use what's freed by the data they replace
+        assert!(matches!(
+            ifs.edit_file(a, Some(&[0; 7]), None::<[Tag; 0]>),
+            Err(Error::StorageFull)
+        ));
+        ifs.edit_file(a, Some(&[0; 6]), None::<[Tag; 0]>).unwrap();
+        ifs.remove_file(b).unwrap();
+        ifs.add_file(&[0; 4], []).unwrap();
+
+        // Kept versions count towards the limit
+        let ifs = InMemoryFs::new().max_bytes(10).retention(Retention::All);
+        let a = ifs.add_file(&[0; 4], []).unwrap();
+        ifs.edit_file(a, Some(&[0; 4]), None::<[Tag; 0]>).unwrap();
+        assert!(matches!(
+            ifs.edit_file(a, Some(&[0; 4]), None::<[Tag; 0]>),
+            Err(Error::StorageFull)
+        ));
+        assert_eq!(ifs.list_versions(a).unwrap(), [1]);
+
+        // Bytes freed in a transaction which is undone are taken up again, while removing a file
+        // frees its versions too
+        let res = ifs.transaction(|fs| {
+            fs.remove_file(a)?;
+            fs.add_file(&[0; 10], [])?;
+            fs.remove_file(FileId::from_u64_unchecked(1000))
+        });
+        assert!(res.is_err());
+        assert!(matches!(ifs.add_file(&[0; 3], []), Err(Error::StorageFull)));
+        ifs.remove_file(a).unwrap();
+        ifs.add_file(&[0; 10], []).unwrap();
+    }
+
+    #[test]
+    pub fn test_get_tags_data() {
+        let ifs = InMemoryFs::new();
+
+        let id = ifs.add_file(&[0, 1], [Tag::named("a")]).unwrap();
+        assert_eq!(ifs.get_tags(id).unwrap(), BTreeSet::from([Tag::named("a")]));
+        assert_eq!(ifs.get_data(id).unwrap(), [0, 1]);
+
+        ifs.register_provider(Group::custom("len"), |data: &[u8]| {
+            vec![Tag::named(if data.len() == 2 { "two" } else { "other" })]
+        })
+        .unwrap();
+        assert!(ifs
+            .get_tags(id)
+            .unwrap()
+            .contains(&Tag::new(Group::custom("len"), "two")));
+
+        ifs.remove_file(id).unwrap();
+        assert!(matches!(ifs.get_tags(id), Err(Error::FileNotFound(_))));
+        assert!(matches!(ifs.get_data(id), Err(Error::FileNotFound(_))));
+    }
+
+    #[test]
     pub fn test_search_files() {
         let ifs = InMemoryFs::new();
 
@@ -226,4 +1676,694 @@ mod tests {
         assert!(items.contains(&first) && items.contains(&third));
         assert!(!items.contains(&second) && !items.contains(&fourth));
     }
+
+    #[test]
+    pub fn test_bulk_tags() {
+        let ifs = InMemoryFs::new();
+
+        let first = ifs.add_file(&[0], [Tag::named("a")]).unwrap();
+        let second = ifs
+            .add_file(&[1], [Tag::named("a"), Tag::named("b")])
+            .unwrap();
+        let third = ifs.add_file(&[2], [Tag::named("c")]).unwrap();
+
+        ifs.add_tags(third, [Tag::named("d")]).unwrap();
+        ifs.remove_tags(second, [Tag::named("b")]).unwrap();
+        assert_eq!(
+            ifs.get_info(third).unwrap().tags(),
+            &BTreeSet::from([Tag::named("c"), Tag::named("d")])
+        );
+
+        let changed = ifs
+            .add_tags_matching(Tag::named("a"), [Tag::named("e")])
+            .unwrap();
+        assert_eq!(changed, 2);
+        assert_eq!(ifs.search_tags(Tag::named("e")).unwrap(), [first, second]);
+
+        ifs.remove_tags_matching(Tag::named("e"), [Tag::named("a")])
+            .unwrap();
+        assert!(ifs.search_tags(Tag::named("a")).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_metadata() {
+        let ifs = InMemoryFs::new();
+
+        let id = ifs.add_file(&[0, 1, 2], []).unwrap();
+        let before = ifs.get_metadata(id).unwrap();
+        assert_eq!(before.size(), 3);
+        assert_eq!(before.created(), before.modified());
+
+        ifs.edit_file(id, Some(&[3, 4]), None::<[Tag; 0]>).unwrap();
+        let after = ifs.get_metadata(id).unwrap();
+        assert_eq!(after.size(), 2);
+        assert_eq!(after.created(), before.created());
+        assert!(after.modified() >= before.modified());
+        assert_ne!(after.hash(), before.hash());
+
+        // The kept hash follows every way of changing the data
+        ifs.write_at(id, 1, &[5]).unwrap();
+        assert_eq!(ifs.get_metadata(id).unwrap().hash(), &hash_data(&[3, 5]));
+        ifs.truncate(id, 3).unwrap();
+        assert_eq!(ifs.get_metadata(id).unwrap().hash(), &hash_data(&[3, 5, 0]));
+        let res = ifs.transaction(|fs| {
+            fs.edit_file(id, Some(&[6]), None::<[Tag; 0]>)?;
+            fs.remove_file(FileId::from_u64_unchecked(1000))
+        });
+        assert!(res.is_err());
+        assert_eq!(ifs.get_metadata(id).unwrap().hash(), &hash_data(&[3, 5, 0]));
+    }
+
+    #[test]
+    pub fn test_inferrer() {
+        let ifs = InMemoryFs::new();
+        ifs.register_inferrer(crate::inference::SizeInferrer)
+            .unwrap();
+
+        let id = ifs.add_file(&[], [Tag::named("a")]).unwrap();
+        assert_eq!(
+            ifs.get_info(id).unwrap().tags(),
+            &BTreeSet::from([Tag::named("a"), Tag::new(Group::custom("size"), "empty")])
+        );
+
+        // Inferred tags are stored, so they stay after an edit
+        ifs.edit_file(id, Some(&[0]), None::<[Tag; 0]>).unwrap();
+        assert_eq!(
+            ifs.search_tags(Tag::new(Group::custom("size"), "empty"))
+                .unwrap(),
+            [id]
+        );
+    }
+
+    #[test]
+    pub fn test_search_sorted() {
+        let ifs = InMemoryFs::new();
+
+        let first = ifs
+            .add_file(&[0, 0, 0], [Tag::named("a").with_value(2)])
+            .unwrap();
+        let second = ifs.add_file(&[0], [Tag::named("a").with_value(1)]).unwrap();
+        let third = ifs.add_file(&[0, 0], [Tag::named("b")]).unwrap();
+        let all = TagPredicate::And(Vec::new());
+
+        let options = SearchOptions::new().descending();
+        assert_eq!(
+            ifs.search_tags_with(all.clone(), &options).unwrap(),
+            [third, second, first]
+        );
+
+        let options = SearchOptions::new().sort_by(SortBy::Size);
+        assert_eq!(
+            ifs.search_tags_with(all.clone(), &options).unwrap(),
+            [second, third, first]
+        );
+
+        let options = SearchOptions::new().sort_by(SortBy::value(Group::Default, "a"));
+        assert_eq!(
+            ifs.search_tags_with(all.clone(), &options).unwrap(),
+            [second, first, third]
+        );
+
+        let options = options.descending();
+        assert_eq!(
+            ifs.search_tags_with(all, &options).unwrap(),
+            [first, second, third]
+        );
+    }
+
+    #[test]
+    pub fn test_search_paged() {
+        let ifs = InMemoryFs::new();
+
+        let ids = (0..5)
+            .map(|i| ifs.add_file(&[i], [Tag::named("a")]).unwrap())
+            .collect::<Vec<_>>();
+
+        let options = SearchOptions::new().offset(1).limit(2);
+        assert_eq!(
+            ifs.search_tags_with(Tag::named("a"), &options).unwrap(),
+            ids[1..3]
+        );
+
+        let options = SearchOptions::new().after(ids[2]).limit(5);
+        assert_eq!(
+            ifs.search_tags_with(Tag::named("a"), &options).unwrap(),
+            ids[3..]
+        );
+
+        let options = SearchOptions::new().descending().after(ids[3]).limit(2);
+        assert_eq!(
+            ifs.search_tags_with(Tag::named("a"), &options).unwrap(),
+            [ids[2], ids[1]]
+        );
+
+        let options = SearchOptions::new().sort_by(SortBy::Size).after(ids[4]);
+        assert!(ifs
+            .search_tags_with(Tag::named("a"), &options)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    pub fn test_search_detailed() {
+        use crate::predicate::{group, tag};
+
+        let ifs = InMemoryFs::new();
+        let both = ifs
+            .add_file(&[], [Tag::named("a"), Tag::new("g", "b")])
+            .unwrap();
+        let only_a = ifs
+            .add_file(&[], [Tag::named("a"), Tag::named("c")])
+            .unwrap();
+        ifs.add_file(&[], [Tag::named("c")]).unwrap();
+
+        let hits = ifs
+            .search_detailed(tag("a") & (group("g") | !tag("c")))
+            .unwrap();
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].id(), both);
+        assert_eq!(hits[0].tags(), &ifs.get_tags(both).unwrap());
+        assert_eq!(
+            hits[0].matched(),
+            [vec![], vec![0], vec![1], vec![1, 0], vec![1, 1]]
+        );
+        assert_eq!(
+            hits[0].matching_tags(),
+            &BTreeSet::from([Tag::named("a"), Tag::new("g", "b")])
+        );
+
+        let hits = ifs.search_detailed(tag("a") | tag("b")).unwrap();
+        assert_eq!(
+            hits.iter().map(SearchHit::id).collect::<Vec<_>>(),
+            [both, only_a]
+        );
+        assert!(hits[1].is_matched(&[0]));
+        assert!(!hits[1].is_matched(&[1]));
+        // A not which fails still has its inner part matched, but its tags aren't highlighted
+        let hits = ifs.search_detailed(tag("a") & !tag("c")).unwrap();
+        assert_eq!(hits[0].matched(), [vec![], vec![0], vec![1]]);
+        let hit = SearchHit::new(only_a, ifs.get_tags(only_a).unwrap(), &!tag("c"));
+        assert_eq!(hit.matched(), [vec![0]]);
+        assert!(hit.matching_tags().is_empty());
+    }
+
+    #[test]
+    pub fn test_tag_counts() {
+        let ifs = InMemoryFs::new();
+        let group = Group::custom("g");
+
+        ifs.add_file(&[], [Tag::named("a"), Tag::new(group.clone(), "b")])
+            .unwrap();
+        ifs.add_file(&[], [Tag::named("a"), Tag::new(group.clone(), "c")])
+            .unwrap();
+        ifs.add_file(&[], [Tag::named("d")]).unwrap();
+
+        let counts = ifs.tag_counts(Tag::named("a")).unwrap();
+        assert_eq!(
+            counts,
+            BTreeMap::from([
+                (Tag::named("a"), 2),
+                (Tag::new(group.clone(), "b"), 1),
+                (Tag::new(group.clone(), "c"), 1),
+            ])
+        );
+
+        assert_eq!(
+            ifs.list_groups().unwrap(),
+            BTreeSet::from([Group::Default, group.clone()])
+        );
+        assert_eq!(
+            ifs.list_tags(&group).unwrap(),
+            BTreeSet::from([Tag::new(group.clone(), "b"), Tag::new(group, "c")])
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn test_find_duplicates() {
+        let ifs = InMemoryFs::new();
+        let first = ifs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+        ifs.add_file(&[3], []).unwrap();
+        let second = ifs.add_file(&[1], []).unwrap();
+        assert!(ifs.find_duplicates().unwrap().is_empty());
+
+        ifs.write_at(second, 1, &[2]).unwrap();
+        assert_eq!(ifs.find_duplicates().unwrap(), [vec![first, second]]);
+        ifs.remove_file(first).unwrap();
+        assert!(ifs.find_duplicates().unwrap().is_empty());
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn test_find_by_hash() {
+        let ifs = InMemoryFs::new();
+        let first = ifs.add_file(&[1, 2], []).unwrap();
+        let second = ifs.add_file(&[1, 2], []).unwrap();
+        let hash = *ifs.get_metadata(first).unwrap().hash();
+        assert_eq!(ifs.find_by_hash(&hash).unwrap(), Some(first));
+
+        ifs.remove_file(first).unwrap();
+        assert_eq!(ifs.find_by_hash(&hash).unwrap(), Some(second));
+        ifs.truncate(second, 1).unwrap();
+        assert_eq!(ifs.find_by_hash(&hash).unwrap(), None);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    pub fn test_dedupe() {
+        use crate::DedupePolicy;
+
+        let ifs = InMemoryFs::new();
+        let old = ifs.add_file(&[1], [Tag::named("a")]).unwrap();
+        let new = ifs.add_file(&[1], [Tag::named("b")]).unwrap();
+
+        let failed = ifs.transaction(|fs| {
+            fs.dedupe(DedupePolicy::KeepNewest)?;
+            Err::<(), _>(Error::file_not_found(old))
+        });
+        assert!(failed.is_err());
+        assert_eq!(ifs.find_duplicates().unwrap(), [vec![old, new]]);
+
+        let merges = ifs.dedupe(DedupePolicy::KeepNewest).unwrap();
+        assert_eq!(merges[0].kept(), new);
+        assert_eq!(merges[0].removed(), [old]);
+        assert_eq!(
+            ifs.get_tags(new).unwrap(),
+            BTreeSet::from([Tag::named("a"), Tag::named("b")])
+        );
+        assert!(ifs.get_info(old).is_err());
+    }
+
+    #[test]
+    pub fn test_events() {
+        use crate::Event;
+
+        let ifs = InMemoryFs::new();
+        let events = ifs.subscribe().unwrap();
+
+        let id = ifs.add_file(&[], [Tag::named("a")]).unwrap();
+        ifs.edit_file(id, Some(&[1]), Some([Tag::named("b")]))
+            .unwrap();
+        ifs.rename_tag(&Tag::named("b"), Tag::named("c")).unwrap();
+        ifs.remove_file(id).unwrap();
+
+        assert_eq!(
+            events.try_iter().collect::<Vec<_>>(),
+            [
+                Event::FileAdded(id),
+                Event::FileEdited(id),
+                Event::TagsChanged(id),
+                Event::TagsChanged(id),
+                Event::FileRemoved(id),
+            ]
+        );
+
+        drop(events);
+        ifs.add_file(&[], []).unwrap();
+    }
+
+    #[test]
+    pub fn test_change_feed() {
+        use crate::{Change, Event};
+
+        let ifs = InMemoryFs::new();
+        assert_eq!(ifs.last_seq().unwrap(), 0);
+        assert!(ifs.changes_since(0).unwrap().is_empty());
+
+        let id = ifs.add_file(&[], []).unwrap();
+        ifs.add_tags(id, [Tag::named("a")]).unwrap();
+        ifs.remove_file(id).unwrap();
+
+        assert_eq!(ifs.last_seq().unwrap(), 3);
+        assert_eq!(
+            ifs.changes_since(1).unwrap(),
+            [
+                Change::new(2, Event::TagsChanged(id)),
+                Change::new(3, Event::FileRemoved(id)),
+            ]
+        );
+        assert_eq!(ifs.changes_since(0).unwrap().len(), 3);
+        assert!(ifs.changes_since(3).unwrap().is_empty());
+        assert!(ifs.changes_since(10).unwrap().is_empty());
+        assert!(ifs.changes_since(u64::MAX).unwrap().is_empty());
+
+        // Changes in a transaction are only recorded once it commits
+        let _ = ifs.transaction(|ifs| {
+            ifs.add_file(&[], [])?;
+            Err::<(), _>(Error::FileNotFound(id))
+        });
+        assert_eq!(ifs.last_seq().unwrap(), 3);
+        let added = ifs
+            .transaction(|ifs| {
+                let added = ifs.add_file(&[], [])?;
+                assert_eq!(ifs.last_seq()?, 3);
+                Ok(added)
+            })
+            .unwrap();
+        assert_eq!(
+            ifs.changes_since(3).unwrap(),
+            [Change::new(4, Event::FileAdded(added))]
+        );
+    }
+
+    #[test]
+    pub fn test_special() {
+        let ifs = InMemoryFs::new();
+
+        ifs.add_file(&[], [Tag::named("a"), Tag::new(Group::custom("g"), "b")])
+            .unwrap();
+        let second = ifs.add_file(&[], [Tag::named("a").with_value(1)]).unwrap();
+        ifs.add_file(&[], []).unwrap();
+        ifs.remove_file(second).unwrap();
+
+        let manifest = ifs.special(SpecialFile::Manifest).unwrap();
+        assert_eq!(manifest.id(), FileId::from_u64_unchecked(0));
+        assert_eq!(manifest.data(), b"0000000000000100\n0000000000000102\n");
+
+        let registry = ifs.special(SpecialFile::TagRegistry).unwrap();
+        assert_eq!(registry.data(), b"a\ng:b\n");
+
+        let trash = ifs.special(SpecialFile::Trash).unwrap();
+        assert_eq!(trash.data(), b"0000000000000101\n");
+        // IDs skipped over by a chosen ID were never used, so aren't in the trash
+        ifs.add_file_with_id(FileId::from_u64_unchecked(1 << 40), &[], [])
+            .unwrap();
+        let trash = ifs.special(SpecialFile::Trash).unwrap();
+        assert_eq!(trash.data(), b"0000000000000101\n");
+
+        assert!(ifs.special(SpecialFile::Config).unwrap().data().is_empty());
+        ifs.set_config(b"x = 1").unwrap();
+        assert_eq!(ifs.special(SpecialFile::Config).unwrap().data(), b"x = 1");
+    }
+
+    #[test]
+    pub fn test_versions() {
+        let ifs = InMemoryFs::new().retention(Retention::Last(2));
+
+        let id = ifs.add_file(&[0], []).unwrap();
+        assert!(ifs.list_versions(id).unwrap().is_empty());
+
+        for data in [[1], [2], [3]] {
+            ifs.edit_file(id, Some(&data), None::<[Tag; 0]>).unwrap();
+        }
+        ifs.edit_file(id, None, Some([Tag::named("a")])).unwrap();
+
+        assert_eq!(ifs.list_versions(id).unwrap(), [2, 3]);
+        assert_eq!(&*ifs.get_version(id, 2).unwrap(), &[1]);
+        assert!(matches!(
+            ifs.get_version(id, 1),
+            Err(Error::VersionNotFound(_, 1))
+        ));
+
+        ifs.revert(id, 2).unwrap();
+        assert_eq!(ifs.get_info(id).unwrap().data(), &[1]);
+        assert_eq!(ifs.list_versions(id).unwrap(), [3, 4]);
+        assert_eq!(&*ifs.get_version(id, 4).unwrap(), &[3]);
+
+        let unversioned = InMemoryFs::new();
+        let id = unversioned.add_file(&[0], []).unwrap();
+        unversioned
+            .edit_file(id, Some(&[1]), None::<[Tag; 0]>)
+            .unwrap();
+        assert!(unversioned.list_versions(id).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_partial_writes() {
+        let ifs = InMemoryFs::new().retention(Retention::All);
+        assert!(ifs.supports_partial_writes());
+
+        let id = ifs.add_file(&[1, 2, 3], []).unwrap();
+        ifs.write_at(id, 1, &[4]).unwrap();
+        ifs.truncate(id, 1).unwrap();
+        assert_eq!(ifs.get_data(id).unwrap(), [1]);
+        assert_eq!(ifs.list_versions(id).unwrap(), [1, 2]);
+        assert_eq!(&*ifs.get_version(id, 1).unwrap(), &[1, 2, 3]);
+        assert_eq!(&*ifs.get_version(id, 2).unwrap(), &[1, 4, 3]);
+
+        let limited = InMemoryFs::new().max_bytes(4);
+        let id = limited.add_file(&[1, 2], []).unwrap();
+        limited.write_at(id, 2, &[3, 4]).unwrap();
+        assert!(matches!(
+            limited.write_at(id, 4, &[5]),
+            Err(Error::StorageFull)
+        ));
+        assert_eq!(limited.get_data(id).unwrap(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    pub fn test_shared_data() {
+        let ifs = InMemoryFs::new();
+        let id = ifs.add_file(&[1, 2, 3], []).unwrap();
+
+        let shared = ifs.get_data_shared(id).unwrap();
+        assert_eq!(&*shared, &[1, 2, 3]);
+        assert!(Arc::ptr_eq(&shared, &ifs.get_data_shared(id).unwrap()));
+
+        // Data still held isn't changed under the holder
+        ifs.write_at(id, 0, &[4]).unwrap();
+        assert_eq!(&*shared, &[1, 2, 3]);
+        assert_eq!(&*ifs.get_data_shared(id).unwrap(), &[4, 2, 3]);
+        ifs.remove_file(id).unwrap();
+        assert_eq!(&*shared, &[1, 2, 3]);
+        assert!(matches!(
+            ifs.get_data_shared(id),
+            Err(Error::FileNotFound(_))
+        ));
+    }
+
+    #[test]
+    pub fn test_compact() {
+        let ifs = InMemoryFs::new().retention(Retention::All);
+        let id = ifs.add_file(&[0; 4], []).unwrap();
+        for i in 1..4 {
+            ifs.edit_file(id, Some(&[i; 4]), None::<[Tag; 0]>).unwrap();
+        }
+        assert_eq!(ifs.compact().unwrap(), 0);
+
+        let ifs = ifs.retention(Retention::Last(1));
+        assert_eq!(ifs.compact().unwrap(), 8);
+        assert_eq!(ifs.list_versions(id).unwrap(), [3]);
+        assert_eq!(ifs.get_info(id).unwrap().data(), &[3; 4]);
+
+        // A rolled back removal leaves a live ID free to reuse until compacted
+        let _ = ifs.transaction(|fs| {
+            fs.remove_file(id)?;
+            Err::<(), _>(Error::Poisoned)
+        });
+        assert!(ifs.read_ids().unwrap().free.contains(&id));
+        ifs.compact().unwrap();
+        assert!(ifs.read_ids().unwrap().free.is_empty());
+    }
+
+    #[test]
+    pub fn test_rename() {
+        let ifs = InMemoryFs::new();
+        let group = Group::custom("g");
+
+        let first = ifs
+            .add_file(&[0], [Tag::named("a"), Tag::new(group.clone(), "b")])
+            .unwrap();
+        let second = ifs.add_file(&[1], [Tag::named("c")]).unwrap();
+
+        ifs.rename_tag(&Tag::named("a"), Tag::named("z")).unwrap();
+        ifs.rename_group(&group, Group::custom("h")).unwrap();
+
+        assert_eq!(
+            ifs.get_info(first).unwrap().tags(),
+            &BTreeSet::from([Tag::named("z"), Tag::new(Group::custom("h"), "b")])
+        );
+        assert_eq!(
+            ifs.get_info(second).unwrap().tags(),
+            &BTreeSet::from([Tag::named("c")])
+        );
+    }
+
+    #[test]
+    pub fn test_transaction() {
+        let ifs = InMemoryFs::new();
+        let kept = ifs.add_file(&[0], [Tag::named("a")]).unwrap();
+
+        let res = ifs.transaction(|fs| {
+            fs.add_file(&[1], [Tag::named("a")])?;
+            fs.edit_file(kept, Some(&[2]), None::<[Tag; 0]>)?;
+            fs.remove_file(FileId::from_u64_unchecked(1000))
+        });
+        assert!(res.is_err());
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [kept]);
+        assert_eq!(ifs.get_info(kept).unwrap().data(), &[0]);
+
+        let added = ifs
+            .transaction(|fs| fs.add_file(&[1], [Tag::named("a")]))
+            .unwrap();
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [kept, added]);
+
+        // Special files are undone along with the rest
+        ifs.set_config(b"before").unwrap();
+        let res = ifs.transaction(|fs| {
+            fs.set_config(b"after")?;
+            fs.remove_file(FileId::from_u64_unchecked(1000))
+        });
+        assert!(res.is_err());
+        assert_eq!(ifs.config().unwrap(), b"before");
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    pub fn test_transaction_panic() {
+        use std::panic::{self, AssertUnwindSafe};
+
+        let ifs = InMemoryFs::new();
+        let kept = ifs.add_file(&[0], [Tag::named("a")]).unwrap();
+
+        let res = panic::catch_unwind(AssertUnwindSafe(|| {
+            ifs.transaction(|fs| -> Result<(), Error> {
+                fs.remove_file(kept)?;
+                panic!("failed part way through")
+            })
+        }));
+        assert!(res.is_err());
+        assert_eq!(ifs.get_info(kept).unwrap().data(), &[0]);
+
+        // Later transactions aren't joined to the one which panicked
+        let res = ifs.transaction(|fs| {
+            fs.remove_file(kept)?;
+            fs.remove_file(kept)
+        });
+        assert!(res.is_err());
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [kept]);
+    }
+
+    #[test]
+    pub fn test_snapshots() {
+        let ifs = InMemoryFs::new().retention(Retention::Last(1));
+        let kept = ifs.add_file(&[0], [Tag::named("a")]).unwrap();
+        ifs.set_config(b"old").unwrap();
+        ifs.snapshot("first").unwrap();
+
+        ifs.edit_file(kept, Some(&[1]), Some([Tag::named("b")]))
+            .unwrap();
+        let added = ifs.add_file(&[2], []).unwrap();
+        ifs.set_config(b"new").unwrap();
+        ifs.snapshot("second").unwrap();
+        assert_eq!(ifs.list_snapshots().unwrap(), ["first", "second"]);
+
+        ifs.restore("first").unwrap();
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [kept]);
+        assert_eq!(ifs.get_info(kept).unwrap().data(), &[0]);
+        assert!(ifs.list_versions(kept).unwrap().is_empty());
+        assert!(ifs.get_info(added).is_err());
+        assert_eq!(ifs.config().unwrap(), b"old");
+        // IDs handed out since aren't reused
+        assert!(ifs.add_file(&[], []).unwrap() > added);
+
+        ifs.restore("second").unwrap();
+        assert_eq!(ifs.get_info(added).unwrap().data(), &[2]);
+        assert_eq!(ifs.list_versions(kept).unwrap(), [1]);
+
+        assert!(ifs.remove_snapshot("first").unwrap());
+        assert!(matches!(
+            ifs.restore("first"),
+            Err(Error::SnapshotNotFound(label)) if label == "first"
+        ));
+    }
+
+    #[test]
+    pub fn test_search_iter() {
+        let ifs = InMemoryFs::new();
+
+        let first = ifs.add_file(&[0], [Tag::named("a")]).unwrap();
+        ifs.add_file(&[1], [Tag::named("b")]).unwrap();
+        let third = ifs.add_file(&[2], [Tag::named("a")]).unwrap();
+        ifs.add_file(&[3], [Tag::named("a")]).unwrap();
+
+        let mut iter = ifs.search_tags_iter(Tag::named("a"));
+        assert_eq!(iter.next().unwrap().unwrap(), first);
+        assert_eq!(iter.next().unwrap().unwrap(), third);
+
+        let all = ifs.search_tags_iter(Tag::named("a")).count();
+        assert_eq!(all, 3);
+    }
+
+    #[test]
+    pub fn test_stream_file() {
+        use std::io::{Read, Write};
+
+        let ifs = InMemoryFs::new();
+
+        let mut writer = ifs.create_file([Tag::named("a")]).unwrap();
+        writer.write_all(&[0, 1, 2]).unwrap();
+        writer.write_all(&[3, 4]).unwrap();
+        let id = writer.commit().unwrap();
+
+        let mut data = Vec::new();
+        ifs.read_file(id).unwrap().read_to_end(&mut data).unwrap();
+        assert_eq!(data, [0, 1, 2, 3, 4]);
+        assert_eq!(ifs.search_tags(Tag::named("a")).unwrap(), [id]);
+
+        // Data written after a flush is committed when the handle is dropped
+        let mut writer = ifs.create_file([Tag::named("b")]).unwrap();
+        writer.write_all(&[5]).unwrap();
+        writer.flush().unwrap();
+        writer.write_all(&[6]).unwrap();
+        drop(writer);
+        let flushed = ifs.search_tags(Tag::named("b")).unwrap();
+        assert_eq!(ifs.get_data(flushed[0]).unwrap(), [5, 6]);
+
+        // But a handle dropped while panicking adds nothing
+        let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let mut writer = ifs.create_file([Tag::named("c")]).unwrap();
+            writer.write_all(&[7]).unwrap();
+            panic!("failed part way through");
+        }));
+        assert!(res.is_err());
+        assert!(ifs.search_tags(Tag::named("c")).unwrap().is_empty());
+    }
+
+    #[test]
+    pub fn test_last_id() {
+        let ifs = InMemoryFs::new();
+        assert_eq!(ifs.last_id().unwrap(), None);
+
+        ifs.add_file(&[0], []).unwrap();
+        ifs.add_file(&[1], []).unwrap();
+        let third = ifs.add_file(&[2], []).unwrap();
+        ifs.remove_file(third).unwrap();
+
+        assert_eq!(ifs.last_id().unwrap(), Some(third));
+    }
+
+    #[test]
+    pub fn test_ids_after() {
+        let ifs = InMemoryFs::new();
+
+        ifs.add_file(&[0], []).unwrap();
+        let cursor = ifs.add_file(&[1], []).unwrap();
+        let third = ifs.add_file(&[2], []).unwrap();
+        let fourth = ifs.add_file(&[3], []).unwrap();
+
+        assert_eq!(ifs.ids_after(cursor).unwrap(), [third, fourth]);
+    }
+
+    #[test]
+    pub fn test_provider() {
+        use alloc::string::ToString;
+
+        let ifs = InMemoryFs::new();
+        ifs.register_provider(Group::custom("len"), |data: &[u8]| {
+            vec![Tag::named(data.len().to_string())]
+        })
+        .unwrap();
+
+        let short = ifs.add_file(&[0, 1], [Tag::named("a")]).unwrap();
+        let long = ifs.add_file(&[0, 1, 2, 3], [Tag::named("a")]).unwrap();
+
+        let items = ifs
+            .search_tags(Tag::new(Group::custom("len"), "4"))
+            .unwrap();
+        assert_eq!(items, [long]);
+
+        let info = ifs.get_info(short).unwrap();
+        assert!(info.tags().contains(&Tag::new(Group::custom("len"), "2")));
+    }
 }
@@ -0,0 +1,112 @@
+//! Tags inferred from a file's data once, when it's added
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+
+use crate::{FileSystemWrite, Group, Tag};
+
+/// A source of tags which are computed from a file's data when it's added, and then stored with
+/// it like any other tag. Unlike a [`TagProvider`](crate::TagProvider), inferred tags aren't
+/// updated when the data is edited, and can be freely removed by the user.
+///
+/// Any function or closure of the form `Fn(&[u8]) -> Vec<Tag>` is an inferrer.
+pub trait TagInferrer: Send + Sync {
+    /// Infer tags for a new file with the given data
+    fn infer(&self, data: &[u8]) -> Vec<Tag>;
+}
+
+impl<F> TagInferrer for F
+where
+    F: Fn(&[u8]) -> Vec<Tag> + Send + Sync,
+{
+    fn infer(&self, data: &[u8]) -> Vec<Tag> {
+        self(data)
+    }
+}
+
+/// Filesystems which can have [`TagInferrer`]s registered with them
+pub trait InferredTags: FileSystemWrite {
+    /// Register an inferrer, which is run over the data of every file added from then on, whether
+    /// through `add_file` or `create_file`. Inferred tags are stored alongside the file's own.
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static;
+}
+
+/// Tags files with a rough size bucket in the `size` group: `empty`, `small` (under 64 KiB),
+/// `medium` (under 16 MiB) or `large`.
+#[derive(Debug, Default, Copy, Clone)]
+pub struct SizeInferrer;
+
+impl TagInferrer for SizeInferrer {
+    fn infer(&self, data: &[u8]) -> Vec<Tag> {
+        let bucket = match data.len() {
+            0 => "empty",
+            len if len < 64 * 1024 => "small",
+            len if len < 16 * 1024 * 1024 => "medium",
+            _ => "large",
+        };
+        alloc::vec![Tag::new(Group::custom("size"), bucket)]
+    }
+}
+
+/// Tags files with their MIME type and usual extension, in the `mime` and `ext` groups, based on
+/// the magic bytes at the start of their data. Files of an unrecognized type get no tags.
+#[cfg(feature = "magic")]
+#[derive(Debug, Default, Copy, Clone)]
+pub struct MagicInferrer;
+
+#[cfg(feature = "magic")]
+impl TagInferrer for MagicInferrer {
+    fn infer(&self, data: &[u8]) -> Vec<Tag> {
+        match ::infer::get(data) {
+            Some(kind) => alloc::vec![
+                Tag::new(Group::custom("mime"), kind.mime_type()),
+                Tag::new(Group::custom("ext"), kind.extension()),
+            ],
+            None => Vec::new(),
+        }
+    }
+}
+
+/// Registered inferrers, in the order they were added
+pub(crate) type Inferrers = Vec<Box<dyn TagInferrer>>;
+
+/// Run every registered inferrer over some data
+pub(crate) fn infer_tags(inferrers: &Inferrers, data: &[u8]) -> Vec<Tag> {
+    inferrers
+        .iter()
+        .flat_map(|inferrer| inferrer.infer(data))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_size() {
+        assert_eq!(
+            SizeInferrer.infer(&[]),
+            [Tag::new(Group::custom("size"), "empty")]
+        );
+        assert_eq!(
+            SizeInferrer.infer(&[0; 10]),
+            [Tag::new(Group::custom("size"), "small")]
+        );
+    }
+
+    #[cfg(feature = "magic")]
+    #[test]
+    fn test_magic() {
+        let png = [0x89, b'P', b'N', b'G', 0x0D, 0x0A, 0x1A, 0x0A, 0, 0, 0, 0];
+        assert_eq!(
+            MagicInferrer.infer(&png),
+            [
+                Tag::new(Group::custom("mime"), "image/png"),
+                Tag::new(Group::custom("ext"), "png"),
+            ]
+        );
+        assert!(MagicInferrer.infer(&[0, 1, 2]).is_empty());
+    }
+}
@@ -0,0 +1,401 @@
+//! The JSON representation of IDs, tags, and errors used over HTTP
+//!
+//! IDs are strings of hex digits, as used for file names, since not every language can hold a
+//! 64-bit integer exactly. Tags are objects with a `name`, a `group` unless it's the default,
+//! and a `value` if they have one. Values are objects with a single key naming their type, one
+//! of `string`, `int`, `float`, `bool`, or `timestamp`, so every type survives the round trip.
+//! Errors are objects with an `error` naming their kind, and the `id` and `version` they were
+//! for, if any. Predicates are objects with a single key naming their kind, see
+//! [`predicate_to_json`].
+
+use alloc::borrow::Cow;
+#[cfg(feature = "server")]
+use alloc::collections::BTreeSet;
+use core::convert::TryFrom;
+use core::fmt::Write as _;
+
+use serde_json::{json, Map, Value};
+
+#[cfg(feature = "regex")]
+use crate::TagRegex;
+use crate::{ErrorKind, FileId, Group, SearchOptions, SortBy, Tag, TagPredicate, TagValue};
+
+/// The header holding the tags of a file sent as raw data
+pub(crate) const TAGS_HEADER: &str = "Tbf-Tags";
+
+/// Format an ID as a string of 16 hex digits
+pub(crate) fn id_to_string(id: FileId) -> String {
+    format!("{:016X}", id.into_u64_unchecked())
+}
+
+/// Parse an ID from a string of hex digits
+pub(crate) fn parse_id(id: &str) -> Option<FileId> {
+    if id.is_empty() || id.len() > 16 {
+        return None;
+    }
+    u64::from_str_radix(id, 16)
+        .ok()
+        .map(FileId::from_u64_unchecked)
+}
+
+/// Format a hash as 64 lower-case hex digits
+pub(crate) fn hash_to_string(hash: &[u8; 32]) -> String {
+    let mut out = String::with_capacity(64);
+    for b in hash {
+        let _ = write!(out, "{b:02x}");
+    }
+    out
+}
+
+/// Parse a hash from 64 hex digits
+pub(crate) fn parse_hash(hash: &str) -> Option<[u8; 32]> {
+    if hash.len() != 64 || !hash.is_ascii() {
+        return None;
+    }
+    let mut out = [0; 32];
+    for (i, b) in out.iter_mut().enumerate() {
+        *b = u8::from_str_radix(&hash[i * 2..i * 2 + 2], 16).ok()?;
+    }
+    Some(out)
+}
+
+pub(crate) fn ids_to_json(ids: &[FileId]) -> Value {
+    Value::Array(
+        ids.iter()
+            .map(|&id| Value::String(id_to_string(id)))
+            .collect(),
+    )
+}
+
+pub(crate) fn ids_from_json(value: &Value) -> Option<Vec<FileId>> {
+    value
+        .as_array()?
+        .iter()
+        .map(|id| parse_id(id.as_str()?))
+        .collect()
+}
+
+fn value_to_json(value: &TagValue) -> Value {
+    match value {
+        TagValue::String(val) => json!({ "string": val }),
+        TagValue::Int(val) => json!({ "int": val }),
+        TagValue::Float(val) => json!({ "float": val }),
+        TagValue::Bool(val) => json!({ "bool": val }),
+        TagValue::Timestamp(val) => json!({ "timestamp": val }),
+    }
+}
+
+fn value_from_json(value: &Value) -> Option<TagValue> {
+    let value = value.as_object()?;
+    let (kind, value) = value.iter().next().filter(|_| value.len() == 1)?;
+    Some(match kind.as_str() {
+        "string" => TagValue::String(Cow::Owned(value.as_str()?.to_owned())),
+        "int" => TagValue::Int(value.as_i64()?),
+        "float" => TagValue::Float(value.as_f64()?),
+        "bool" => TagValue::Bool(value.as_bool()?),
+        "timestamp" => TagValue::Timestamp(value.as_i64()?),
+        _ => return None,
+    })
+}
+
+/// Write a group and name into an object, leaving out the group if it's the default
+fn named_to_json(group: &Group, name: &str) -> Map<String, Value> {
+    let mut out = Map::new();
+    if let Group::Custom(group) = group {
+        out.insert("group".to_owned(), Value::String(group.to_string()));
+    }
+    out.insert("name".to_owned(), Value::String(name.to_owned()));
+    out
+}
+
+fn named_from_json(value: &Value) -> Option<(Group, String)> {
+    let group = match value.get("group") {
+        None | Some(Value::Null) => Group::Default,
+        Some(group) => Group::from(group.as_str()?.to_owned()),
+    };
+    let name = value.get("name")?.as_str()?.to_owned();
+    Some((group, name))
+}
+
+pub(crate) fn tag_to_json(tag: &Tag) -> Value {
+    let mut out = named_to_json(tag.group(), tag.name());
+    if let Some(value) = tag.value() {
+        out.insert("value".to_owned(), value_to_json(value));
+    }
+    Value::Object(out)
+}
+
+pub(crate) fn tag_from_json(value: &Value) -> Option<Tag> {
+    let (group, name) = named_from_json(value)?;
+    let tag = Tag::new(group, name);
+    match value.get("value") {
+        None | Some(Value::Null) => Some(tag),
+        Some(value) => Some(tag.with_value(value_from_json(value)?)),
+    }
+}
+
+pub(crate) fn tags_to_json<'a, I: IntoIterator<Item = &'a Tag>>(tags: I) -> Value {
+    Value::Array(tags.into_iter().map(tag_to_json).collect())
+}
+
+pub(crate) fn tags_from_json(value: &Value) -> Option<Vec<Tag>> {
+    value.as_array()?.iter().map(tag_from_json).collect()
+}
+
+/// Write a predicate as an object with a single key naming its kind, like `{"and": [...]}`,
+/// `{"name_glob": "*.png"}`, or `{"value_gt": {"name": "rating", "value": {"int": 3}}}`. Custom
+/// predicates have no representation, so `None` is returned if there are any.
+pub(crate) fn predicate_to_json(pred: &TagPredicate) -> Option<Value> {
+    let valued = |group: &Group, name: &str, values: &[(&str, &TagValue)]| {
+        let mut out = named_to_json(group, name);
+        for (key, value) in values {
+            out.insert((*key).to_owned(), value_to_json(value));
+        }
+        Value::Object(out)
+    };
+    let list = |preds: &[TagPredicate]| -> Option<Vec<Value>> {
+        preds.iter().map(predicate_to_json).collect()
+    };
+    Some(match pred {
+        TagPredicate::And(preds) => json!({ "and": list(preds)? }),
+        TagPredicate::Or(preds) => json!({ "or": list(preds)? }),
+        TagPredicate::Not(pred) => json!({ "not": predicate_to_json(pred)? }),
+        TagPredicate::Group(group) => json!({ "group": group.to_string() }),
+        TagPredicate::Name(name) => json!({ "name": name }),
+        TagPredicate::NameContains(substr) => json!({ "name_contains": substr }),
+        TagPredicate::NameGlob(glob) => json!({ "name_glob": glob }),
+        TagPredicate::GroupGlob(glob) => json!({ "group_glob": glob }),
+        #[cfg(feature = "regex")]
+        TagPredicate::NameRegex(regex) => json!({ "name_regex": regex.as_str() }),
+        #[cfg(feature = "regex")]
+        TagPredicate::GroupRegex(regex) => json!({ "group_regex": regex.as_str() }),
+        TagPredicate::Tag(tag) => json!({ "tag": tag_to_json(tag) }),
+        TagPredicate::ValueGt { group, name, value } => {
+            json!({ "value_gt": valued(group, name, &[("value", value)]) })
+        }
+        TagPredicate::ValueLt { group, name, value } => {
+            json!({ "value_lt": valued(group, name, &[("value", value)]) })
+        }
+        TagPredicate::ValueRange {
+            group,
+            name,
+            min,
+            max,
+        } => json!({ "value_range": valued(group, name, &[("min", min), ("max", max)]) }),
+        TagPredicate::Custom(_) => return None,
+    })
+}
+
+pub(crate) fn predicate_from_json(value: &Value) -> Option<TagPredicate> {
+    let value = value.as_object()?;
+    let (kind, value) = value.iter().next().filter(|_| value.len() == 1)?;
+    let preds = |value: &Value| -> Option<Vec<TagPredicate>> {
+        value.as_array()?.iter().map(predicate_from_json).collect()
+    };
+    let string = |value: &Value| value.as_str().map(ToOwned::to_owned);
+    let valued = |key: &str| -> Option<(Group, String, TagValue)> {
+        let (group, name) = named_from_json(value)?;
+        Some((group, name, value_from_json(value.get(key)?)?))
+    };
+    Some(match kind.as_str() {
+        "and" => TagPredicate::And(preds(value)?),
+        "or" => TagPredicate::Or(preds(value)?),
+        "not" => TagPredicate::Not(Box::new(predicate_from_json(value)?)),
+        "group" => TagPredicate::Group(Group::from(string(value)?)),
+        "name" => TagPredicate::Name(string(value)?),
+        "name_contains" => TagPredicate::NameContains(string(value)?),
+        "name_glob" => TagPredicate::NameGlob(string(value)?),
+        "group_glob" => TagPredicate::GroupGlob(string(value)?),
+        #[cfg(feature = "regex")]
+        "name_regex" => TagPredicate::NameRegex(TagRegex::new(value.as_str()?).ok()?),
+        #[cfg(feature = "regex")]
+        "group_regex" => TagPredicate::GroupRegex(TagRegex::new(value.as_str()?).ok()?),
+        "tag" => TagPredicate::Tag(tag_from_json(value)?),
+        "value_gt" => {
+            let (group, name, value) = valued("value")?;
+            TagPredicate::ValueGt { group, name, value }
+        }
+        "value_lt" => {
+            let (group, name, value) = valued("value")?;
+            TagPredicate::ValueLt { group, name, value }
+        }
+        "value_range" => {
+            let (group, name, min) = valued("min")?;
+            let (_, _, max) = valued("max")?;
+            TagPredicate::ValueRange {
+                group,
+                name,
+                min,
+                max,
+            }
+        }
+        _ => return None,
+    })
+}
+
+/// Write a search as an object holding its `pattern`, and any options which aren't the default:
+/// `sort`, one of `"id"`, `"created"`, `"size"`, or `{"value": {"group": ..., "name": ...}}`, and
+/// `descending`, `after`, `offset`, and `limit`. Returns `None` if the pattern can't be written.
+#[cfg(feature = "remote")]
+pub(crate) fn search_to_json(pattern: &TagPredicate, options: &SearchOptions) -> Option<Value> {
+    let mut out = Map::new();
+    out.insert("pattern".to_owned(), predicate_to_json(pattern)?);
+    let sort = match options.sort() {
+        SortBy::Id => None,
+        SortBy::Created => Some(json!("created")),
+        SortBy::Size => Some(json!("size")),
+        SortBy::Value { group, name } => {
+            Some(json!({ "value": Value::Object(named_to_json(group, name)) }))
+        }
+    };
+    if let Some(sort) = sort {
+        out.insert("sort".to_owned(), sort);
+    }
+    if options.is_descending() {
+        out.insert("descending".to_owned(), Value::Bool(true));
+    }
+    if let Some(after) = options.cursor() {
+        out.insert("after".to_owned(), Value::String(id_to_string(after)));
+    }
+    if options.skipped() != 0 {
+        out.insert("offset".to_owned(), json!(options.skipped()));
+    }
+    if let Some(limit) = options.max_len() {
+        out.insert("limit".to_owned(), json!(limit));
+    }
+    Some(Value::Object(out))
+}
+
+pub(crate) fn search_from_json(value: &Value) -> Option<(TagPredicate, SearchOptions)> {
+    let pattern = predicate_from_json(value.get("pattern")?)?;
+    let mut options = SearchOptions::new();
+    match value.get("sort") {
+        None | Some(Value::Null) => (),
+        Some(Value::String(sort)) if sort == "id" => (),
+        Some(Value::String(sort)) if sort == "created" => {
+            options = options.sort_by(SortBy::Created);
+        }
+        Some(Value::String(sort)) if sort == "size" => options = options.sort_by(SortBy::Size),
+        Some(sort) => {
+            let (group, name) = named_from_json(sort.get("value")?)?;
+            options = options.sort_by(SortBy::Value { group, name });
+        }
+    }
+    if value.get("descending").and_then(Value::as_bool) == Some(true) {
+        options = options.descending();
+    }
+    if let Some(after) = value.get("after") {
+        options = options.after(parse_id(after.as_str()?)?);
+    }
+    if let Some(offset) = value.get("offset") {
+        options = options.offset(usize::try_from(offset.as_u64()?).ok()?);
+    }
+    if let Some(limit) = value.get("limit") {
+        options = options.limit(usize::try_from(limit.as_u64()?).ok()?);
+    }
+    Some((pattern, options))
+}
+
+/// Write a value as JSON made only of ASCII, escaping anything else, so it can be sent in a
+/// header
+#[cfg(feature = "remote")]
+pub(crate) fn to_ascii(value: &Value) -> String {
+    let mut out = String::new();
+    for c in value.to_string().chars() {
+        if c.is_ascii() {
+            out.push(c);
+        } else {
+            let mut buf = [0; 2];
+            for unit in c.encode_utf16(&mut buf) {
+                let _ = write!(out, "\\u{unit:04x}");
+            }
+        }
+    }
+    out
+}
+
+/// The HTTP status code for an error of some kind
+pub(crate) fn error_status(kind: &ErrorKind<'_>) -> u16 {
+    match kind {
+        ErrorKind::FileNotFound(_) | ErrorKind::VersionNotFound(..) => 404,
+        ErrorKind::AlreadyExists(_) => 409,
+        ErrorKind::ReadOnly | ErrorKind::PermissionDenied => 403,
+        ErrorKind::QuotaExceeded | ErrorKind::StorageFull => 507,
+        ErrorKind::InvalidTags => 422,
+        _ => 500,
+    }
+}
+
+pub(crate) fn error_to_json(kind: &ErrorKind<'_>) -> Value {
+    match kind {
+        ErrorKind::FileNotFound(id) => {
+            json!({ "error": "file_not_found", "id": id_to_string(*id) })
+        }
+        ErrorKind::AlreadyExists(id) => {
+            json!({ "error": "already_exists", "id": id_to_string(*id) })
+        }
+        ErrorKind::VersionNotFound(id, version) => json!({
+            "error": "version_not_found",
+            "id": id_to_string(*id),
+            "version": version,
+        }),
+        ErrorKind::ReadOnly => json!({ "error": "read_only" }),
+        ErrorKind::QuotaExceeded => json!({ "error": "quota_exceeded" }),
+        ErrorKind::InvalidTags => json!({ "error": "invalid_tags" }),
+        ErrorKind::PermissionDenied => json!({ "error": "permission_denied" }),
+        ErrorKind::StorageFull => json!({ "error": "storage_full" }),
+        ErrorKind::State => json!({ "error": "state" }),
+        _ => json!({ "error": "other" }),
+    }
+}
+
+/// Write the tags of many files, each either an object like `{"tags": [...]}`, or an error
+/// object holding the `status` it would have been sent with
+#[cfg(feature = "server")]
+pub(crate) fn tags_many_to_json<E: crate::Error>(found: Vec<Result<BTreeSet<Tag>, E>>) -> Value {
+    Value::Array(
+        found
+            .into_iter()
+            .map(|tags| match tags {
+                Ok(tags) => json!({ "tags": tags_to_json(&tags) }),
+                Err(err) => {
+                    let kind = err.generic_kind();
+                    let mut out = error_to_json(&kind);
+                    out["status"] = json!(error_status(&kind));
+                    out
+                }
+            })
+            .collect(),
+    )
+}
+
+/// An error which isn't from the filesystem, such as a malformed request
+pub(crate) fn bad_request(message: &str) -> Value {
+    json!({ "error": "bad_request", "message": message })
+}
+
+#[cfg(all(test, feature = "serde"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_predicate_serde() {
+        let pred = TagPredicate::and([
+            TagPredicate::not(TagPredicate::Tag(Tag::new("g", "a").with_value("x"))),
+            TagPredicate::or([
+                TagPredicate::group(Group::Default),
+                TagPredicate::name_contains("b"),
+            ]),
+            TagPredicate::value_lt("g", "c", TagValue::Bool(true)),
+        ]);
+
+        // The serde shape is the same as the one used over HTTP
+        let value = serde_json::to_value(&pred).unwrap();
+        assert_eq!(value, predicate_to_json(&pred).unwrap());
+        assert_eq!(predicate_from_json(&value).unwrap(), pred);
+
+        let custom = TagPredicate::not(TagPredicate::custom(|_| true));
+        assert!(serde_json::to_value(&custom).is_err());
+        assert!(predicate_to_json(&custom).is_none());
+    }
+}
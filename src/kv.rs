@@ -0,0 +1,967 @@
+//! Implementation of a TBF stored in a single embedded database file, using [`redb`]
+
+use core::convert::TryFrom;
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Cursor};
+use std::ops::Bound;
+use std::path::Path;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use redb::{
+    Database, MultimapTable, MultimapTableDefinition, ReadOnlyMultimapTable, ReadOnlyTable,
+    ReadTransaction, ReadableMultimapTable, ReadableTable, Table, TableDefinition,
+    WriteTransaction,
+};
+
+use crate::codec::{read_tag, read_u64, write_string, write_tag, write_value};
+use crate::error::ErrorKind;
+use crate::events::{Event, Subscribers};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::{hash_data, Metadata};
+use crate::provider::{provide_tags, Providers};
+use crate::{
+    check_stored, BatchResult, BufferedWriter, FileId, FileInfo, FileSystemRead, FileSystemWrite,
+    Group, InferredTags, Lookup, ProvidedTags, QueryPlan, SpecialFile, StreamRead, StreamWrite,
+    Tag, TagIndex, TagInferrer, TagPattern, TagPredicate, TagProvider,
+};
+
+/// The tags of each file
+const FILES: TableDefinition<'static, u64, &[u8]> = TableDefinition::new("files");
+/// The data of each file
+const DATA: TableDefinition<'static, u64, &[u8]> = TableDefinition::new("data");
+/// The creation and modification times of each file, in nanoseconds since the Unix epoch
+const TIMES: TableDefinition<'static, u64, (u64, u64)> = TableDefinition::new("times");
+/// The files with each tag, keyed by [`tag_key`]
+const INDEX: MultimapTableDefinition<'static, &[u8], u64> = MultimapTableDefinition::new("index");
+/// Values kept about the filesystem as a whole
+const SETTINGS: TableDefinition<'static, &str, &[u8]> = TableDefinition::new("settings");
+
+const NEXT_ID: &str = "next_id";
+
+/// Error for an embedded database filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// The requested file did not exist
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// The database returned an error
+    Db(Box<redb::Error>),
+    /// A value in the database couldn't be decoded
+    IoError(io::Error),
+    /// A thread panic poisoned the state
+    Poisoned,
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Error {
+        Error::Poisoned
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+macro_rules! from_db_error {
+    ($($ty:ident),*) => {
+        $(
+            impl From<redb::$ty> for Error {
+                fn from(err: redb::$ty) -> Error {
+                    Error::Db(Box::new(err.into()))
+                }
+            }
+        )*
+    };
+}
+
+from_db_error!(
+    Error,
+    DatabaseError,
+    TransactionError,
+    TableError,
+    StorageError,
+    CommitError
+);
+
+impl crate::error::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Self::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Self::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Self::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Self::IoError(io::ErrorKind::OutOfMemory.into())
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Self::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Self::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Self::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Self::Db(e) => ErrorKind::Source(&**e),
+            Self::IoError(e) => ErrorKind::Source(e),
+            Self::Poisoned => ErrorKind::State,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::Db(err) => write!(f, "database error: {err}"),
+            Error::IoError(err) => write!(f, "value in the database is malformed: {err}"),
+            Error::Poisoned => write!(f, "filesystem was poisoned by a thread panic"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Db(err) => std::error::Error::source(err),
+            Error::IoError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+/// A tag-based filesystem stored in a single [`redb`] database file. Every change is made in a
+/// database transaction, so the file is always left consistent, even after a crash.
+///
+/// Alongside each file's tags, the database keeps an inverted index from tags to files. Searches
+/// for exact tags, groups, or values of a tag are narrowed down with it, rather than checking
+/// every file, as long as no [`TagProvider`] is registered.
+///
+/// File IDs are never reused, and prior versions of file data aren't kept.
+pub struct KvFs {
+    db: Database,
+    next_id: Mutex<u64>,
+    /// The write transaction of a running call to `transaction`, which every change joins
+    txn: Mutex<Option<WriteTransaction>>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    subscribers: Subscribers,
+}
+
+impl KvFs {
+    /// Open the filesystem stored in a database file, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<KvFs, Error> {
+        let db = Database::create(path)?;
+
+        // Read transactions can't open tables which don't exist yet, so create them all up-front
+        let txn = db.begin_write()?;
+        let next_id = match Tables::open_write(&txn)?.setting(NEXT_ID)? {
+            Some(next) => read_u64(&mut &*next)?,
+            None => 256,
+        };
+        txn.commit()?;
+
+        Ok(KvFs {
+            db,
+            next_id: Mutex::new(next_id),
+            txn: Mutex::new(None),
+            providers: RwLock::new(BTreeMap::new()),
+            inferrers: RwLock::new(Vec::new()),
+            subscribers: Subscribers::new(),
+        })
+    }
+
+    /// Run a closure over the tables, as of the running transaction if there is one
+    fn with_read<T>(&self, f: impl FnOnce(&dyn View) -> Result<T, Error>) -> Result<T, Error> {
+        let txn = self.txn.lock()?;
+        if let Some(txn) = &*txn {
+            return f(&Tables::open_write(txn)?);
+        }
+        drop(txn);
+
+        let txn = self.db.begin_read()?;
+        f(&Tables::open_read(&txn)?)
+    }
+
+    /// Run a closure changing the tables. It joins the running transaction if there is one,
+    /// otherwise its changes are committed once it succeeds.
+    fn with_write<T>(
+        &self,
+        f: impl FnOnce(&mut WriteTables<'_>) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let txn = self.txn.lock()?;
+        if let Some(txn) = &*txn {
+            return f(&mut Tables::open_write(txn)?);
+        }
+        drop(txn);
+
+        let txn = self.db.begin_write()?;
+        let out = f(&mut Tables::open_write(&txn)?);
+        match out {
+            Ok(_) => txn.commit()?,
+            Err(_) => txn.abort()?,
+        }
+        out
+    }
+
+    /// Hand out a new file ID. The counter isn't rolled back with transactions, so an ID handed
+    /// out in a rolled back transaction is never handed out again.
+    fn alloc_id(&self, tables: &mut WriteTables<'_>) -> Result<FileId, Error> {
+        let mut next = self.next_id.lock()?;
+        let id = FileId::from_u64_unchecked(*next);
+        *next += 1;
+        tables.set_setting(NEXT_ID, &next.to_le_bytes())?;
+        Ok(id)
+    }
+
+    /// Find exactly which files match a predicate from the index alone, or `None` if some files
+    /// would have to be checked, such as when tag providers are set
+    fn exact_matches(&self, pred: &TagPredicate) -> Result<Option<BTreeSet<FileId>>, Error> {
+        if !self.providers.read()?.is_empty() {
+            return Ok(None);
+        }
+        self.with_read(|view| match QueryPlan::new(pred).execute(view)? {
+            Lookup::Exact(ids) => Ok(Some(ids)),
+            _ => Ok(None),
+        })
+    }
+
+    /// Record an ID chosen by the caller as used
+    fn claim_id(&self, tables: &mut WriteTables<'_>, id: FileId) -> Result<(), Error> {
+        let claimed = id
+            .into_u64_unchecked()
+            .checked_add(1)
+            .ok_or(Error::AlreadyExists(id))?;
+        let mut next = self.next_id.lock()?;
+        *next = (*next).max(claimed);
+        tables.set_setting(NEXT_ID, &next.to_le_bytes())
+    }
+
+    fn emit(&self, event: Event) {
+        self.subscribers.emit(event);
+    }
+}
+
+impl FileSystemRead for KvFs {
+    type Error = Error;
+    type SearchIter<'a, P: TagPattern + 'a> = SearchIter<'a, P>;
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let mut out = BTreeMap::new();
+        for id in self.search_tags(pattern)? {
+            for tag in self.get_tags(id)? {
+                *out.entry(tag).or_insert(0) += 1;
+            }
+        }
+        Ok(out)
+    }
+
+    fn count_tags<P>(&self, tags: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        match self.exact_matches(&tags.to_predicate())? {
+            Some(ids) => Ok(ids.len() as u64),
+            None => self
+                .search_tags_iter(tags)
+                .try_fold(0, |count, id| id.map(|_| count + 1)),
+        }
+    }
+
+    fn any_match<P>(&self, tags: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        match self.exact_matches(&tags.to_predicate())? {
+            Some(ids) => Ok(!ids.is_empty()),
+            None => Ok(self.search_tags_iter(tags).next().transpose()?.is_some()),
+        }
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags,
+            scan: Scan::Pending,
+            cursor: Bound::Unbounded,
+            done: false,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let (mut tags, data) = self.with_read(|view| {
+            let tags = view.tags(id)?.ok_or(Error::FileNotFound(id))?;
+            Ok((tags, view.data(id)?.unwrap_or_default()))
+        })?;
+        tags.extend(provide_tags(&*self.providers.read()?, &data));
+
+        Ok(FileInfo {
+            id,
+            tags,
+            data: data.into_boxed_slice(),
+        })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let providers = self.providers.read()?;
+        if !providers.is_empty() {
+            return Ok(self.get_info(id)?.tags);
+        }
+        self.with_read(|view| view.tags(id)?.ok_or(Error::FileNotFound(id)))
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.with_read(|view| view.tags(id)?.ok_or(Error::FileNotFound(id)))
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        let providers = self.providers.read()?;
+        // Every file is read from the same snapshot
+        self.with_read(|view| {
+            ids.iter()
+                .map(|&id| {
+                    let Some(mut tags) = view.tags(id)? else {
+                        return Ok(Err(Error::FileNotFound(id)));
+                    };
+                    let data = view.data(id)?.unwrap_or_default();
+                    tags.extend(provide_tags(&providers, &data));
+                    Ok(Ok(FileInfo {
+                        id,
+                        tags,
+                        data: data.into_boxed_slice(),
+                    }))
+                })
+                .collect()
+        })
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        if !self.providers.read()?.is_empty() {
+            return Ok(self
+                .get_info_many(ids)?
+                .into_iter()
+                .map(|info| info.map(|info| info.tags))
+                .collect());
+        }
+        self.with_read(|view| {
+            ids.iter()
+                .map(|&id| Ok(view.tags(id)?.ok_or(Error::FileNotFound(id))))
+                .collect()
+        })
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.with_read(|view| {
+            view.tags(id)?.ok_or(Error::FileNotFound(id))?;
+            Ok(view.data(id)?.unwrap_or_default())
+        })
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let ((created, modified), data) = self.with_read(|view| {
+            let times = view.times(id)?.ok_or(Error::FileNotFound(id))?;
+            Ok((times, view.data(id)?.unwrap_or_default()))
+        })?;
+
+        Ok(Metadata {
+            created,
+            modified,
+            size: data.len() as u64,
+            hash: hash_data(&data),
+        })
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let next = *self.next_id.lock()?;
+        if next > 256 {
+            Ok(Some(FileId::from_u64_unchecked(next - 1)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.with_read(|view| view.ids_after(after))
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.with_read(|view| Ok(view.setting(file.name())?.unwrap_or_default()))
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl FileSystemWrite for KvFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.inferrers.read()?, data));
+
+        let id = self.with_write(|tables| {
+            let id = self.alloc_id(tables)?;
+            tables.insert(id, data, &tags)?;
+            Ok(id)
+        })?;
+        self.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if id.into_u64_unchecked() < 256 {
+            return Err(Error::AlreadyExists(id));
+        }
+
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.inferrers.read()?, data));
+
+        self.with_write(|tables| {
+            if tables.tags(id)?.is_some() {
+                return Err(Error::AlreadyExists(id));
+            }
+            self.claim_id(tables, id)?;
+            tables.insert(id, data, &tags)
+        })?;
+        self.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| tags.into_iter().collect::<BTreeSet<_>>());
+        self.with_write(|tables| {
+            let (created, _) = tables.times(id)?.ok_or(Error::FileNotFound(id))?;
+            if let Some(data) = data {
+                tables.set_data(id, data)?;
+                tables.set_times(id, created, SystemTime::now())?;
+            }
+            if let Some(tags) = &tags {
+                tables.set_tags(id, tags)?;
+            }
+            Ok(())
+        })?;
+
+        if data.is_some() {
+            self.emit(Event::FileEdited(id));
+        }
+        if tags.is_some() {
+            self.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.with_write(|tables| {
+            let mut file_tags = tables.tags(id)?.ok_or(Error::FileNotFound(id))?;
+            file_tags.extend(tags);
+            tables.set_tags(id, &file_tags)
+        })?;
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.with_write(|tables| {
+            let mut file_tags = tables.tags(id)?.ok_or(Error::FileNotFound(id))?;
+            for tag in tags {
+                file_tags.remove(&tag);
+            }
+            tables.set_tags(id, &file_tags)
+        })?;
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.with_write(|tables| tables.remove(id))?;
+        self.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut txn = self.txn.lock()?;
+            if txn.is_some() {
+                drop(txn);
+                return f(self);
+            }
+            *txn = Some(self.db.begin_write()?);
+        }
+
+        let out = f(self);
+
+        let txn = self.txn.lock()?.take();
+        if let Some(txn) = txn {
+            match out {
+                Ok(_) => txn.commit()?,
+                Err(_) => txn.abort()?,
+            }
+        }
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        check_stored::<Self::Error>(file)?;
+        self.with_write(|tables| tables.set_setting(file.name(), data))
+    }
+}
+
+impl ProvidedTags for KvFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers.write()?.insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl InferredTags for KvFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers.write()?.push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl StreamRead for KvFs {
+    type Reader<'a> = Cursor<Vec<u8>>;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.get_data(id).map(Cursor::new)
+    }
+}
+
+impl StreamWrite for KvFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+/// Every table of the database, opened in one transaction
+struct Tables<F, D, T, I, S> {
+    files: F,
+    data: D,
+    times: T,
+    index: I,
+    settings: S,
+}
+
+type ReadTables = Tables<
+    ReadOnlyTable<u64, &'static [u8]>,
+    ReadOnlyTable<u64, &'static [u8]>,
+    ReadOnlyTable<u64, (u64, u64)>,
+    ReadOnlyMultimapTable<&'static [u8], u64>,
+    ReadOnlyTable<&'static str, &'static [u8]>,
+>;
+
+type WriteTables<'t> = Tables<
+    Table<'t, u64, &'static [u8]>,
+    Table<'t, u64, &'static [u8]>,
+    Table<'t, u64, (u64, u64)>,
+    MultimapTable<'t, &'static [u8], u64>,
+    Table<'t, &'static str, &'static [u8]>,
+>;
+
+impl ReadTables {
+    fn open_read(txn: &ReadTransaction) -> Result<ReadTables, Error> {
+        Ok(Tables {
+            files: txn.open_table(FILES)?,
+            data: txn.open_table(DATA)?,
+            times: txn.open_table(TIMES)?,
+            index: txn.open_multimap_table(INDEX)?,
+            settings: txn.open_table(SETTINGS)?,
+        })
+    }
+}
+
+impl<'t> WriteTables<'t> {
+    fn open_write(txn: &'t WriteTransaction) -> Result<WriteTables<'t>, Error> {
+        Ok(Tables {
+            files: txn.open_table(FILES)?,
+            data: txn.open_table(DATA)?,
+            times: txn.open_table(TIMES)?,
+            index: txn.open_multimap_table(INDEX)?,
+            settings: txn.open_table(SETTINGS)?,
+        })
+    }
+
+    fn insert(&mut self, id: FileId, data: &[u8], tags: &BTreeSet<Tag>) -> Result<(), Error> {
+        let now = SystemTime::now();
+        self.set_data(id, data)?;
+        self.set_times(id, now, now)?;
+        self.set_tags(id, tags)
+    }
+
+    fn remove(&mut self, id: FileId) -> Result<(), Error> {
+        let old = self.tags(id)?.ok_or(Error::FileNotFound(id))?;
+        self.unindex(id, &old)?;
+        let id = id.into_u64_unchecked();
+        self.files.remove(id)?;
+        self.data.remove(id)?;
+        self.times.remove(id)?;
+        Ok(())
+    }
+
+    /// Set the tags of a file, updating the index to match
+    fn set_tags(&mut self, id: FileId, tags: &BTreeSet<Tag>) -> Result<(), Error> {
+        if let Some(old) = self.tags(id)? {
+            self.unindex(id, &old)?;
+        }
+        for tag in tags {
+            self.index
+                .insert(tag_key(tag)?.as_slice(), id.into_u64_unchecked())?;
+        }
+
+        let mut encoded = Vec::new();
+        for tag in tags {
+            write_tag(&mut encoded, tag)?;
+        }
+        self.files
+            .insert(id.into_u64_unchecked(), encoded.as_slice())?;
+        Ok(())
+    }
+
+    fn unindex(&mut self, id: FileId, tags: &BTreeSet<Tag>) -> Result<(), Error> {
+        for tag in tags {
+            self.index
+                .remove(tag_key(tag)?.as_slice(), id.into_u64_unchecked())?;
+        }
+        Ok(())
+    }
+
+    fn set_data(&mut self, id: FileId, data: &[u8]) -> Result<(), Error> {
+        self.data.insert(id.into_u64_unchecked(), data)?;
+        Ok(())
+    }
+
+    fn set_times(
+        &mut self,
+        id: FileId,
+        created: SystemTime,
+        modified: SystemTime,
+    ) -> Result<(), Error> {
+        self.times.insert(
+            id.into_u64_unchecked(),
+            (to_nanos(created), to_nanos(modified)),
+        )?;
+        Ok(())
+    }
+
+    fn set_setting(&mut self, name: &str, value: &[u8]) -> Result<(), Error> {
+        self.settings.insert(name, value)?;
+        Ok(())
+    }
+}
+
+/// Reading the tables, whether they were opened for reading or writing
+trait View {
+    fn tags(&self, id: FileId) -> Result<Option<BTreeSet<Tag>>, Error>;
+
+    fn data(&self, id: FileId) -> Result<Option<Vec<u8>>, Error>;
+
+    fn times(&self, id: FileId) -> Result<Option<(SystemTime, SystemTime)>, Error>;
+
+    /// Get the first file past a cursor, along with its tags
+    fn next_file(&self, cursor: Bound<FileId>) -> Result<Option<(FileId, BTreeSet<Tag>)>, Error>;
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Error>;
+
+    /// Get every file with a tag whose index key starts with a prefix
+    fn indexed(&self, prefix: &[u8]) -> Result<BTreeSet<FileId>, Error>;
+
+    fn setting(&self, name: &str) -> Result<Option<Vec<u8>>, Error>;
+}
+
+impl<F, D, T, I, S> View for Tables<F, D, T, I, S>
+where
+    F: ReadableTable<u64, &'static [u8]>,
+    D: ReadableTable<u64, &'static [u8]>,
+    T: ReadableTable<u64, (u64, u64)>,
+    I: ReadableMultimapTable<&'static [u8], u64>,
+    S: ReadableTable<&'static str, &'static [u8]>,
+{
+    fn tags(&self, id: FileId) -> Result<Option<BTreeSet<Tag>>, Error> {
+        match self.files.get(id.into_u64_unchecked())? {
+            Some(tags) => Ok(Some(decode_tags(tags.value())?)),
+            None => Ok(None),
+        }
+    }
+
+    fn data(&self, id: FileId) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .data
+            .get(id.into_u64_unchecked())?
+            .map(|data| data.value().to_vec()))
+    }
+
+    fn times(&self, id: FileId) -> Result<Option<(SystemTime, SystemTime)>, Error> {
+        Ok(self.times.get(id.into_u64_unchecked())?.map(|times| {
+            let (created, modified) = times.value();
+            (from_nanos(created), from_nanos(modified))
+        }))
+    }
+
+    fn next_file(&self, cursor: Bound<FileId>) -> Result<Option<(FileId, BTreeSet<Tag>)>, Error> {
+        let range = (
+            cursor.map(FileId::into_u64_unchecked),
+            Bound::<u64>::Unbounded,
+        );
+        match self.files.range(range)?.next() {
+            Some(entry) => {
+                let (id, tags) = entry?;
+                Ok(Some((
+                    FileId::from_u64_unchecked(id.value()),
+                    decode_tags(tags.value())?,
+                )))
+            }
+            None => Ok(None),
+        }
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Error> {
+        let mut out = Vec::new();
+        let range = (
+            Bound::Excluded(after.into_u64_unchecked()),
+            Bound::Unbounded,
+        );
+        for entry in self.files.range(range)? {
+            out.push(FileId::from_u64_unchecked(entry?.0.value()));
+        }
+        Ok(out)
+    }
+
+    fn indexed(&self, prefix: &[u8]) -> Result<BTreeSet<FileId>, Error> {
+        let mut out = BTreeSet::new();
+        for entry in self.index.range(prefix..)? {
+            let (key, ids) = entry?;
+            if !key.value().starts_with(prefix) {
+                break;
+            }
+            for id in ids {
+                out.insert(FileId::from_u64_unchecked(id?.value()));
+            }
+        }
+        Ok(out)
+    }
+
+    fn setting(&self, name: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.settings.get(name)?.map(|value| value.value().to_vec()))
+    }
+}
+
+fn decode_tags(mut encoded: &[u8]) -> io::Result<BTreeSet<Tag>> {
+    let mut tags = BTreeSet::new();
+    while let Some(tag) = read_tag(&mut encoded)? {
+        tags.insert(tag);
+    }
+    Ok(tags)
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0)
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+/// The start of the index key of every tag in a group
+fn group_key(group: &Group) -> io::Result<Vec<u8>> {
+    let mut key = Vec::new();
+    match group {
+        Group::Default => key.push(0),
+        Group::Custom(group) => {
+            key.push(1);
+            write_string(&mut key, group)?;
+        }
+    }
+    Ok(key)
+}
+
+/// The start of the index key of every tag with a group and name, whatever its value
+fn name_key(group: &Group, name: &str) -> io::Result<Vec<u8>> {
+    let mut key = group_key(group)?;
+    write_string(&mut key, name)?;
+    Ok(key)
+}
+
+/// The index key of a tag. Strings are length-prefixed, so the keys of every tag in a group, or
+/// with a group and name, share a prefix no other key starts with.
+fn tag_key(tag: &Tag) -> io::Result<Vec<u8>> {
+    let mut key = name_key(tag.group(), tag.name())?;
+    match tag.value() {
+        Some(value) => {
+            key.push(1);
+            write_value(&mut key, value)?;
+        }
+        None => key.push(0),
+    }
+    Ok(key)
+}
+
+/// The index holds every tag, keyed so all the tags of a group, or of a group and name, can be
+/// found by prefix
+impl TagIndex for dyn View + '_ {
+    type Error = Error;
+
+    fn lookup(&self, atom: &TagPredicate) -> Result<Lookup, Error> {
+        Ok(match atom {
+            TagPredicate::Tag(tag) => Lookup::Exact(self.indexed(&tag_key(tag)?)?),
+            TagPredicate::Group(group) => Lookup::Exact(self.indexed(&group_key(group)?)?),
+            TagPredicate::ValueGt { group, name, .. }
+            | TagPredicate::ValueLt { group, name, .. }
+            | TagPredicate::ValueRange { group, name, .. } => {
+                Lookup::Superset(self.indexed(&name_key(group, name)?)?)
+            }
+            _ => Lookup::Unknown,
+        })
+    }
+
+    fn all_files(&self) -> Result<Option<BTreeSet<FileId>>, Error> {
+        // Listing every file is as slow as just scanning them
+        Ok(None)
+    }
+}
+
+/// Which files a search checks
+enum Scan {
+    /// The index hasn't been used yet
+    Pending,
+    /// Every file
+    All,
+    /// Only the files found from the index
+    Only(BTreeSet<FileId>),
+}
+
+/// A lazy search over a [`KvFs`]. The index is used to find which files to check on the first call
+/// to `next`, and each call after reads from a new snapshot of the database, so files added or
+/// removed during iteration may or may not be seen.
+pub struct SearchIter<'a, P> {
+    fs: &'a KvFs,
+    pattern: P,
+    scan: Scan,
+    cursor: Bound<FileId>,
+    done: bool,
+}
+
+impl<P: TagPattern> SearchIter<'_, P> {
+    fn advance(&mut self) -> Result<Option<FileId>, Error> {
+        let fs = self.fs;
+        let providers = fs.providers.read()?;
+        fs.with_read(|view| {
+            if let Scan::Pending = self.scan {
+                // Provided tags aren't in the index, so could match anything
+                let found = if providers.is_empty() {
+                    QueryPlan::new(&self.pattern.to_predicate()).execute(view)?
+                } else {
+                    Lookup::Unknown
+                };
+                self.scan = match found {
+                    Lookup::Exact(ids) | Lookup::Superset(ids) => Scan::Only(ids),
+                    Lookup::Unknown => Scan::All,
+                };
+            }
+
+            loop {
+                let (id, tags) = if let Scan::Only(ids) = &self.scan {
+                    let Some(&id) = ids.range((self.cursor, Bound::Unbounded)).next() else {
+                        return Ok(None);
+                    };
+                    self.cursor = Bound::Excluded(id);
+                    match view.tags(id)? {
+                        Some(tags) => (id, tags),
+                        None => continue,
+                    }
+                } else {
+                    let Some((id, tags)) = view.next_file(self.cursor)? else {
+                        return Ok(None);
+                    };
+                    self.cursor = Bound::Excluded(id);
+                    (id, tags)
+                };
+
+                let matched = if providers.is_empty() {
+                    self.pattern.match_tags(&tags)
+                } else {
+                    let data = view.data(id)?.unwrap_or_default();
+                    self.pattern
+                        .match_tags(tags.iter().chain(&provide_tags(&providers, &data)))
+                };
+                if matched {
+                    return Ok(Some(id));
+                }
+            }
+        })
+    }
+}
+
+impl<P: TagPattern> Iterator for SearchIter<'_, P> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let out = self.advance().transpose();
+        self.done = !matches!(out, Some(Ok(_)));
+        out
+    }
+}
+
+/// A handle streaming data into a new file of a [`KvFs`]. Data is buffered until the handle is
+/// flushed, which adds the file or updates its data if it already exists.
+pub type Writer<'a> = BufferedWriter<'a, KvFs>;
@@ -14,13 +14,13 @@
 //! in-memory maps.
 
 #![warn(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc, clippy::must_use_candidate)]
 #![warn(
     missing_docs,
     elided_lifetimes_in_paths,
     explicit_outlives_requirements,
     missing_abi,
     noop_method_call,
-    pointer_structural_match,
     semicolon_in_expressions_from_macros,
     unused_import_braces,
     unused_lifetimes,
@@ -35,39 +35,628 @@
 
 extern crate alloc;
 
+#[cfg(feature = "std")]
+mod acl;
+#[cfg(feature = "std")]
+mod alias;
+#[cfg(feature = "async")]
+mod async_fs;
+#[cfg(feature = "std")]
+mod audit;
+#[cfg(feature = "backup")]
+pub mod backup;
+#[cfg(feature = "std")]
+mod cache;
+#[cfg(feature = "std")]
+#[cfg_attr(not(any(feature = "dfs", feature = "packed")), allow(dead_code))]
+mod codec;
+#[cfg(feature = "std")]
+mod collection;
+#[cfg(feature = "crypto")]
+mod crypt;
+#[cfg(feature = "std")]
+mod dedup;
+#[cfg(feature = "std")]
+mod derived;
 #[cfg(feature = "dfs")]
 mod dfs;
+#[cfg(feature = "std")]
+mod duplicates;
+mod dyn_fs;
+pub mod error;
+pub mod events;
+#[cfg(feature = "search")]
+pub mod extract;
+mod file;
+#[cfg(feature = "embedded")]
+mod flash;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+#[cfg(feature = "std")]
+mod hierarchy;
 #[cfg(feature = "imfs")]
 mod imfs;
+pub mod inference;
+#[cfg(any(feature = "server", feature = "remote"))]
+#[cfg_attr(not(all(feature = "server", feature = "remote")), allow(dead_code))]
+mod json;
+#[cfg(feature = "kv")]
+mod kv;
+#[cfg(feature = "std")]
+mod links;
+#[cfg(feature = "std")]
+mod metadata;
+#[cfg(feature = "std")]
+mod metered;
+mod migrate;
+#[cfg(feature = "std")]
+mod mirror;
+#[cfg(feature = "std")]
+mod multi;
+#[cfg(feature = "std")]
+mod overlay;
+#[cfg(feature = "packed")]
+mod packed;
 mod pattern;
-mod file;
-pub mod error;
+#[cfg(feature = "postgres")]
+mod pg;
+pub mod provider;
+mod query;
+#[cfg(feature = "std")]
+mod quota;
+mod read_only;
+#[cfg(feature = "std")]
+mod registry;
+#[cfg(feature = "remote")]
+mod remote;
+#[cfg(feature = "std")]
+mod saved;
+pub mod search;
+#[cfg(feature = "server")]
+pub mod server;
+#[cfg(feature = "std")]
+mod stream;
+#[cfg(feature = "std")]
+pub mod sync;
+#[cfg(feature = "testing")]
+pub mod testing;
+#[cfg(feature = "testing")]
+pub mod testkit;
+#[cfg(feature = "search")]
+mod text;
+#[cfg(any(feature = "imfs", feature = "dfs"))]
+mod trace;
+#[cfg(feature = "std")]
+pub mod tree;
+mod usage;
+mod version;
+#[cfg(feature = "std")]
+pub mod vfs;
+#[cfg(feature = "wasm")]
+mod web;
 
+#[cfg(feature = "std")]
+pub use acl::{
+    Access, AccessRules, Error as PermissionError, Identity, PermissionedFs,
+    SearchIter as PermissionedSearchIter, Writer as PermissionedWriter,
+};
+#[cfg(feature = "std")]
+pub use alias::{
+    AliasFs, Error as AliasError, SearchIter as AliasSearchIter, TagAliases, Writer as AliasWriter,
+};
+#[cfg(feature = "std")]
+pub use audit::{
+    AuditAction, AuditEntry, AuditedFs, Error as AuditError, SearchIter as AuditedSearchIter,
+    Writer as AuditedWriter,
+};
+#[cfg(feature = "std")]
+pub use cache::{CachedFs, Writer as CachedWriter};
+#[cfg(feature = "std")]
+pub use collection::{Collection, Collections, Error as CollectionError};
+#[cfg(feature = "crypto")]
+pub use crypt::{
+    EncryptedFs, Error as CryptError, SearchIter as CryptSearchIter, Writer as CryptWriter,
+};
+#[cfg(feature = "std")]
+pub use dedup::{DedupFs, SearchIter as DedupSearchIter, Writer as DedupWriter};
+#[cfg(feature = "std")]
+pub use derived::{DerivedData, Deriver, Error as DerivedError};
 #[cfg(feature = "dfs")]
-pub use dfs::{DirectoryBackedFs, Error as DfsError};
+pub use dfs::{
+    Builder as DfsBuilder, Compression, ConfigError as DfsConfigError, CorruptionReport,
+    DirectoryBackedFs, Error as DfsError, IndexMode, Reader as DfsReader,
+    SearchIter as DfsSearchIter, Sharding, SyncPolicy, Writer as DfsWriter,
+};
+#[cfg(feature = "std")]
+pub use duplicates::{DedupePolicy, Merge};
+#[cfg(all(feature = "embedded", feature = "std"))]
+pub use flash::Writer as FlashWriter;
+#[cfg(feature = "embedded")]
+pub use flash::{Error as FlashError, FlashFs, SearchIter as FlashSearchIter};
+#[cfg(all(feature = "imfs", feature = "std"))]
+pub use imfs::Writer as ImfsWriter;
 #[cfg(feature = "imfs")]
-pub use imfs::{Error as ImfsError, InMemoryFs};
+pub use imfs::{Error as ImfsError, InMemoryFs, SearchIter as ImfsSearchIter};
+#[cfg(feature = "kv")]
+pub use kv::{Error as KvError, KvFs, SearchIter as KvSearchIter, Writer as KvWriter};
+#[cfg(feature = "std")]
+pub use links::{Link, Links};
+#[cfg(feature = "wasm")]
+pub use web::{Error as WebError, SearchIter as WebSearchIter, WebFs, Writer as WebWriter};
+
+#[cfg(all(feature = "async", feature = "dfs"))]
+pub use async_fs::AsyncDirectoryBackedFs;
+#[cfg(feature = "async")]
+pub use async_fs::AsyncFileSystem;
 
-pub use pattern::{TagPattern, TagPredicate};
-pub use file::{FileId, Tag, Group};
+#[cfg(feature = "std")]
+pub use dyn_fs::DynFileWriter;
+pub use dyn_fs::{BoxedFs, DynError, DynFileSystem, DynFsError, DynSearchIter, DynStreams};
 pub use error::{Error, ErrorKind};
+pub use events::{Change, Event};
+#[cfg(feature = "search")]
+pub use extract::{Extraction, Extractor};
+pub use file::{FileId, Group, SpecialFile, Tag, TagValue};
+#[cfg(feature = "std")]
+pub use hierarchy::{Error as HierarchyError, TagHierarchy};
+pub use inference::{InferredTags, TagInferrer};
+#[cfg(feature = "mmap")]
+pub use memmap2::Mmap;
+#[cfg(feature = "std")]
+pub use metadata::Metadata;
+#[cfg(feature = "metrics")]
+pub use metered::MetricsRecorder;
+#[cfg(feature = "std")]
+pub use metered::{
+    MeteredFs, Metrics, Operation, SearchIter as MeteredSearchIter, Writer as MeteredWriter,
+};
+pub use migrate::{migrate, migrate_with_ids, Error as MigrateError};
+#[cfg(feature = "std")]
+pub use mirror::{
+    Error as MirrorError, MirrorPolicy, MirroredFs, SearchIter as MirroredSearchIter,
+    Writer as MirroredWriter,
+};
+#[cfg(feature = "std")]
+pub use multi::{
+    Error as MultiError, MultiFs, SearchIter as MultiSearchIter, Writer as MultiWriter,
+};
+#[cfg(feature = "std")]
+pub use overlay::{
+    Error as OverlayError, OverlayFs, Reader as OverlayReader, SearchIter as OverlaySearchIter,
+    Writer as OverlayWriter,
+};
+#[cfg(feature = "packed")]
+pub use packed::{
+    Error as PackedError, PackedFs, SearchIter as PackedSearchIter, Writer as PackedWriter,
+};
+pub use pattern::predicate;
+#[cfg(feature = "regex")]
+pub use pattern::TagRegex;
+pub use pattern::{
+    Lookup, MatchFn, NormalForm, ParseError, ParseErrorKind, QueryPlan, TagIndex, TagPattern,
+    TagPredicate,
+};
+#[cfg(feature = "postgres")]
+pub use pg::{
+    Error as PostgresError, PostgresFs, SearchIter as PostgresSearchIter, Writer as PostgresWriter,
+};
+pub use provider::{ProvidedTags, TagProvider};
+pub use query::Query;
+#[cfg(feature = "std")]
+pub use quota::{
+    Error as QuotaError, Quota, QuotaFs, QuotaLimits, SearchIter as QuotaSearchIter,
+    Writer as QuotaWriter,
+};
+#[cfg(feature = "std")]
+pub use read_only::Writer as ReadOnlyWriter;
+pub use read_only::{Error as ReadOnlyError, ReadOnly, SearchIter as ReadOnlySearchIter};
+#[cfg(feature = "std")]
+pub use registry::{
+    Error as RegistryError, GroupInfo, RegistryFs, SearchIter as RegistrySearchIter, TagRegistry,
+    Writer as RegistryWriter,
+};
+#[cfg(feature = "remote")]
+pub use remote::{
+    Error as RemoteError, Reader as RemoteReader, RemoteFs, SearchIter as RemoteSearchIter,
+    Writer as RemoteWriter,
+};
+#[cfg(feature = "std")]
+pub use saved::{Error as SavedSearchError, SavedSearch, SavedSearches};
+pub use search::{SearchHit, SearchOptions, SortBy};
+#[cfg(feature = "std")]
+pub use stream::{BufferedWriter, FileWriter, StreamRead, StreamWrite, WrappedWriter};
+#[cfg(feature = "search")]
+pub use text::{TextIndexFs, Writer as TextIndexWriter};
+pub use usage::{GroupUsage, UsageReport};
+pub use version::Retention;
 
 use alloc::boxed::Box;
-use alloc::collections::BTreeSet;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::string::String;
 use alloc::vec::Vec;
+use core::convert::TryFrom;
+use core::fmt::Write as _;
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use search::SortKey;
+
+/// A trait representing an implementation of a tag-based filesystem. Implemented for anything
+/// implementing both halves of the interface, [`FileSystemRead`] for looking files up and
+/// [`FileSystemWrite`] for changing them, so code which only needs one half should ask for just
+/// that.
+pub trait FileSystem: FileSystemRead + FileSystemWrite {}
+
+impl<T: FileSystemRead + FileSystemWrite + ?Sized> FileSystem for T {}
+
+/// The results of looking up several files at once, as from
+/// [`FileSystemRead::get_info_many`]. Each file has its own result, while the outer error is for
+/// failures of the whole batch.
+pub type BatchResult<T, E> = Result<Vec<Result<T, E>>, E>;
+
+/// A lazy search for filesystems wrapping another, which is a search of the inner filesystem
+/// whose errors are converted as they're returned
+pub struct WrappedSearchIter<I, E> {
+    inner: I,
+    _error: PhantomData<fn() -> E>,
+}
 
-/// A trait representing an implementation of a tag-based filesystem.
-pub trait FileSystem {
+impl<I, E> WrappedSearchIter<I, E> {
+    /// Wrap a search of the inner filesystem
+    pub fn new(inner: I) -> WrappedSearchIter<I, E> {
+        WrappedSearchIter {
+            inner,
+            _error: PhantomData,
+        }
+    }
+}
+
+impl<I, T, E> Iterator for WrappedSearchIter<I, E>
+where
+    I: Iterator<Item = Result<FileId, T>>,
+    E: From<T>,
+{
+    type Item = Result<FileId, E>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|id| id.map_err(E::from))
+    }
+}
+
+/// The lookup half of a tag-based filesystem, for searching for files and reading them
+pub trait FileSystemRead {
     /// The error type to use with this filesystem.
     type Error: Error;
 
+    /// The iterator returned by a lazy search
+    type SearchIter<'a, P>: Iterator<Item = Result<FileId, Self::Error>>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    // Lookup files
+
+    /// Search for files matching a given tag pattern
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.search_tags_iter(tags).collect()
+    }
+
+    /// Search for files matching a given tag pattern, returning a page of them in the order set
+    /// by the options
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        if options.is_lazy() {
+            return search::page_lazy(self.search_tags_iter(tags), options);
+        }
+
+        let ids = self.search_tags(tags)?;
+        search::sort_results(ids, options, |id| match options.sort() {
+            SortBy::Id => Ok(SortKey::Id),
+            #[cfg(feature = "std")]
+            SortBy::Created => Ok(SortKey::Time(self.get_metadata(id)?.created())),
+            SortBy::Size => Ok(SortKey::Size(self.get_info(id)?.data.len() as u64)),
+            SortBy::Value { group, name } => {
+                Ok(search::value_key(&self.get_info(id)?.tags, group, name))
+            }
+        })
+    }
+
+    /// Search for files matching a given tag pattern, lazily finding each match as the iterator
+    /// is advanced. This allows stopping early or paging through results without finding every
+    /// match up-front.  Matches are returned in ascending ID order.
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a;
+
+    /// Count the files matching a given tag pattern, without collecting their IDs
+    fn count_tags<P>(&self, tags: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.search_tags_iter(tags)
+            .try_fold(0, |count, id| id.map(|_| count + 1))
+    }
+
+    /// Check whether any file matches a given tag pattern, stopping at the first found
+    fn any_match<P>(&self, tags: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.search_tags_iter(tags).next().transpose()?.is_some())
+    }
+
+    /// Search for files matching a given tag pattern, getting each file's tags along with which
+    /// parts of the pattern it matched, such as to show why a file matched a complex query
+    fn search_detailed<P>(&self, tags: P) -> Result<Vec<SearchHit>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = tags.to_predicate();
+        self.search_tags(&pattern)?
+            .into_iter()
+            .map(|id| Ok(SearchHit::new(id, self.get_tags(id)?, &pattern)))
+            .collect()
+    }
+
+    /// Get info about an existing file
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error>;
+
+    /// Get the tags of an existing file, including those from registered providers.
+    /// Implementations may avoid reading the file's data when no provider needs it.
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.get_info(id)?.tags)
+    }
+
+    /// Get the tags stored with an existing file, leaving out those computed by a
+    /// [`TagProvider`]. These are the tags [`edit_file`](FileSystemWrite::edit_file) replaces, so
+    /// changes to a file's tags start from them. By default, this is the same as
+    /// [`get_tags`](Self::get_tags), which suits filesystems without providers.
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.get_tags(id)
+    }
+
+    /// Get info about several existing files at once, in the same order as their IDs. Each file
+    /// can fail on its own, such as if it doesn't exist, while the outer error is for failures of
+    /// the whole batch. Backends which can look up many files in one query or request do so.
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(ids.iter().map(|&id| self.get_info(id)).collect())
+    }
+
+    /// Get the tags of several existing files at once, like
+    /// [`get_info_many`](Self::get_info_many) without their data
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(ids.iter().map(|&id| self.get_tags(id)).collect())
+    }
+
+    /// Get the data of an existing file, without its tags
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.get_info(id)?.data.into_vec())
+    }
+
+    /// Get part of the data of an existing file, such as to answer an HTTP range request. The
+    /// range is cut short at the end of the data, so a range starting past it gives nothing.
+    /// By default this reads all of the data, but backends which can read only the part asked
+    /// for do so.
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let mut data = self.get_data(id)?;
+        let range = clamp_range(range, data.len());
+        data.truncate(range.end);
+        data.drain(..range.start);
+        Ok(data)
+    }
+
+    /// Get the metadata the filesystem keeps about an existing file
+    ///
+    /// This default reads the whole file to find its size and hash. Nothing records when the file
+    /// was added or changed, so both times are the Unix epoch. Implementations which keep
+    /// metadata should override it.
+    #[cfg(feature = "std")]
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let data = self.get_data(id)?;
+        Ok(Metadata {
+            created: std::time::SystemTime::UNIX_EPOCH,
+            modified: std::time::SystemTime::UNIX_EPOCH,
+            size: data.len() as u64,
+            hash: metadata::hash_data(&data),
+        })
+    }
+
+    /// Get the most recently allocated file ID, if any file was ever added. Implementations which
+    /// allocate IDs from a counter report the counter, so a file which was added and then removed
+    /// is still counted. This default instead reports the highest ID currently in use.
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self
+            .search_tags(TagPredicate::And(Vec::new()))?
+            .into_iter()
+            .max())
+    }
+
+    /// Get all existing file IDs strictly greater than `after`, in ascending order. For
+    /// implementations which allocate IDs in increasing order, this is a cheap way to find files
+    /// added since a previous call. Edits to existing files are not reported, nor are files given
+    /// a reused ID lower than `after`.
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        let mut ids = self.search_tags(TagPredicate::And(Vec::new()))?;
+        ids.retain(|id| *id > after);
+        ids.sort_unstable();
+        Ok(ids)
+    }
+
+    // Tag statistics
+
+    /// Count how many of the files matching a pattern have each tag. Tags no matching file has
+    /// aren't included.
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let mut out = BTreeMap::new();
+        for id in self.search_tags(pattern)? {
+            for tag in self.get_tags(id)? {
+                *out.entry(tag).or_insert(0) += 1;
+            }
+        }
+        Ok(out)
+    }
+
+    /// List every group with a tag on at least one file
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self
+            .tag_counts(TagPredicate::And(Vec::new()))?
+            .into_keys()
+            .map(|tag| tag.group().clone())
+            .collect())
+    }
+
+    /// List every tag in a group which is on at least one file
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .tag_counts(TagPredicate::group(group.clone()))?
+            .into_keys()
+            .filter(|tag| tag.group() == group)
+            .collect())
+    }
+
+    /// Find how much the filesystem stores: the total size of file data, how many files there
+    /// are, and the same for files with tags in each group
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        let mut usage = UsageReport::default();
+        for id in self.search_tags(TagPredicate::And(Vec::new()))? {
+            #[cfg(feature = "std")]
+            let size = self.get_metadata(id)?.size();
+            #[cfg(not(feature = "std"))]
+            let size = self.get_data(id)?.len() as u64;
+            usage.add(size, &self.get_tags(id)?);
+        }
+        Ok(usage)
+    }
+
+    /// Find groups of files with identical data, by comparing hashes of it. Each group is sorted
+    /// by ID, and groups are ordered by their first file. Files whose data is unique aren't in
+    /// any group.
+    #[cfg(feature = "std")]
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        let mut by_hash = BTreeMap::<[u8; 32], Vec<FileId>>::new();
+        for id in self.search_tags(TagPredicate::And(Vec::new()))? {
+            by_hash
+                .entry(*self.get_metadata(id)?.hash())
+                .or_default()
+                .push(id);
+        }
+        let mut groups = by_hash
+            .into_values()
+            .filter(|ids| ids.len() > 1)
+            .collect::<Vec<_>>();
+        groups.sort_unstable();
+        Ok(groups)
+    }
+
+    /// Find a file whose data has the given BLAKE3 hash, as from [`Metadata::hash`], such as to
+    /// skip adding a file which is already stored. If several do, the one with the lowest ID is
+    /// found. Backends which keep the hashes of file data look them up directly, while this
+    /// default hashes every file until one matches.
+    #[cfg(feature = "std")]
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        for id in self.search_tags_iter(TagPredicate::And(Vec::new())) {
+            let id = id?;
+            if self.get_metadata(id)?.hash() == hash {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+
+    // Versions
+
+    /// List the numbers of the prior versions kept of a file's data, oldest first. Versions are
+    /// only kept by implementations which support it, and only once turned on with a
+    /// [`Retention`] policy, so by default this is empty.
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.get_info(id).map(|_| Vec::new())
+    }
+
+    /// Get the data of a prior version of a file
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.get_info(id)?;
+        Err(Self::Error::version_not_found(id, version))
+    }
+
+    // Special files
+
+    /// Get one of the special files with a reserved ID. Their contents are generated from the
+    /// state of the filesystem, except for those which aren't [generated], which hold whatever
+    /// was last passed to `set_special_data`. Special files have no tags.
+    ///
+    /// [generated]: SpecialFile::is_generated
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        generate_special(self, file)
+    }
+
+    /// Get the stored data of a special file, which is empty if it was never set. Generated
+    /// special files store nothing, so this is always empty for them.
+    ///
+    /// This default stores nothing for any special file, so is always empty. Implementations
+    /// which override [`set_special_data`](FileSystemWrite::set_special_data) should override
+    /// this too.
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        let _ = file;
+        Ok(Vec::new())
+    }
+
+    /// Get the data of the config special file, which is empty if it was never set
+    fn config(&self) -> Result<Vec<u8>, Self::Error> {
+        self.special_data(SpecialFile::Config)
+    }
+
+    /// Start building a query, which narrows a search one constraint at a time
+    fn query(&self) -> Query<'_, Self> {
+        Query::new(self)
+    }
+
+    // Events
+
+    /// Subscribe to changes made to the filesystem from now on. The subscription ends when the
+    /// receiver is dropped.
+    ///
+    /// This default doesn't track changes, so the receiver is disconnected from the start and
+    /// never gets an event. Implementations which track changes should override it.
+    #[cfg(feature = "std")]
+    fn subscribe(&self) -> Result<std::sync::mpsc::Receiver<Event>, Self::Error> {
+        let (_, recv) = std::sync::mpsc::channel();
+        Ok(recv)
+    }
+}
+
+/// The changing half of a tag-based filesystem, for adding, editing, and removing files
+pub trait FileSystemWrite: FileSystemRead {
     // Add/Remove/Edit files
 
-    /// Add a new file with the given data and tags
+    /// Add a new file with the given data and tags, plus any tags inferred by registered
+    /// [`TagInferrer`]s
     fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
     where
         I: IntoIterator<Item = Tag>;
 
+    /// Add a new file with a chosen ID, instead of one picked by the filesystem. Otherwise acts
+    /// like `add_file`. Fails with an error of kind [`ErrorKind::AlreadyExists`] if a file with
+    /// the ID exists, or the ID is one of the reserved IDs below 256.
+    ///
+    /// This lets IDs survive copying files between filesystems. Implementations which allocate
+    /// IDs from a counter move it past the chosen ID, so later files never clash with it. They
+    /// reject `u64::MAX` with [`ErrorKind::AlreadyExists`] too, as the counter can't move past it.
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>;
+
     /// Edit an existing file, altering the data or tags
     fn edit_file<I>(
         &self,
@@ -78,18 +667,315 @@ pub trait FileSystem {
     where
         I: IntoIterator<Item = Tag>;
 
+    /// Write data into an existing file at an offset, replacing what's there and extending the
+    /// file if it runs past the end. Any gap between the old end and the offset is filled with
+    /// zeroes. This is an edit of the file's data, so a prior version is kept as usual. Fails with
+    /// [`Error::storage_full`] if the file can't grow to reach the end of the data.
+    ///
+    /// See [`supports_partial_writes`](Self::supports_partial_writes) for whether this changes
+    /// just part of the file, rather than reading and rewriting all of it as this default does.
+    fn write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        let mut new = self.get_data(id)?;
+        write_into(&mut new, id, offset, data)?;
+        self.edit_file(id, Some(&new), None::<[Tag; 0]>)
+    }
+
+    /// Cut an existing file's data to a length, or extend it to the length with zeroes. Like
+    /// [`write_at`](Self::write_at), this reads and rewrites all of the data by default.
+    fn truncate(&self, id: FileId, len: u64) -> Result<(), Self::Error> {
+        let mut new = self.get_data(id)?;
+        resize_data(&mut new, id, len)?;
+        self.edit_file(id, Some(&new), None::<[Tag; 0]>)
+    }
+
+    /// Whether [`write_at`](Self::write_at) and [`truncate`](Self::truncate) change only the part
+    /// of a file they're given, rather than reading and rewriting all of its data. Either way,
+    /// they have the same result.
+    fn supports_partial_writes(&self) -> bool {
+        false
+    }
+
     /// Remove an existing file
     fn remove_file(&self, id: FileId) -> Result<(), Self::Error>;
 
-    // Lookup files
+    /// Run a closure as a single transaction. If it returns an error, every change made to the
+    /// filesystem while it ran is undone, otherwise they're all kept.
+    ///
+    /// Transactions don't isolate concurrent callers: changes made by other threads while one is
+    /// running become part of it, and a transaction started inside another joins the outer one.
+    ///
+    /// This default has no way to undo changes, so it only runs the closure, and changes made
+    /// before an error are kept. Implementations which can undo changes should override it.
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        f(self)
+    }
 
-    /// Search for files matching a given tag pattern
-    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    /// Add tags to an existing file, keeping the ones it already has
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
     where
-        P: TagPattern;
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.get_stored_tags(id)?;
+        new.extend(tags);
+        self.edit_file(id, None, Some(new))
+    }
 
-    /// Get info about an existing file
-    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error>;
+    /// Remove tags from an existing file. Tags the file doesn't have are ignored.
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.get_stored_tags(id)?;
+        for tag in tags {
+            new.remove(&tag);
+        }
+        self.edit_file(id, None, Some(new))
+    }
+
+    /// Add tags to every file matching a pattern, as a single transaction. Returns the number of
+    /// files changed.
+    fn add_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        let ids = self.search_tags(pattern)?;
+        self.transaction(|fs| {
+            for &id in &ids {
+                fs.add_tags(id, tags.iter().cloned())?;
+            }
+            Ok(ids.len())
+        })
+    }
+
+    /// Remove tags from every file matching a pattern, as a single transaction. Returns the
+    /// number of files changed.
+    fn remove_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        let ids = self.search_tags(pattern)?;
+        self.transaction(|fs| {
+            for &id in &ids {
+                fs.remove_tags(id, tags.iter().cloned())?;
+            }
+            Ok(ids.len())
+        })
+    }
+
+    /// Rename a tag on every file which has it. Files which already have the new tag simply lose
+    /// the old one.
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        for id in self.search_tags(old.clone())? {
+            let mut tags = self.get_stored_tags(id)?;
+            tags.remove(old);
+            tags.insert(new.clone());
+            self.edit_file(id, None, Some(tags))?;
+        }
+        Ok(())
+    }
+
+    /// Move every tag in one group into another, keeping their names
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        for id in self.search_tags(TagPredicate::group(old.clone()))? {
+            let tags = self
+                .get_stored_tags(id)?
+                .into_iter()
+                .map(|tag| rename_group(tag, old, &new))
+                .collect::<BTreeSet<_>>();
+            self.edit_file(id, None, Some(tags))?;
+        }
+        Ok(())
+    }
+
+    /// Merge files with identical data, as found by
+    /// [`find_duplicates`](FileSystemRead::find_duplicates). Out of each group, the file picked by
+    /// the policy is kept and gains the tags of the others, which are removed. Runs as a single
+    /// transaction, returning the merges made.
+    #[cfg(feature = "std")]
+    fn dedupe(&self, policy: DedupePolicy) -> Result<Vec<Merge>, Self::Error> {
+        self.transaction(|fs| {
+            let mut merges = Vec::new();
+            for group in fs.find_duplicates()? {
+                let mut files = group
+                    .iter()
+                    .copied()
+                    .zip(fs.get_tags_many(&group)?)
+                    .map(|(id, tags)| Ok((id, tags?)))
+                    .collect::<Result<Vec<_>, Self::Error>>()?;
+                let (kept, tags) = files.remove(policy.choose(&files));
+
+                let new = files
+                    .iter()
+                    .flat_map(|(_, more)| more.difference(&tags).cloned())
+                    .collect::<BTreeSet<_>>();
+                if !new.is_empty() {
+                    fs.add_tags(kept, new)?;
+                }
+                let removed = files.into_iter().map(|(id, _)| id).collect::<Vec<_>>();
+                for &id in &removed {
+                    fs.remove_file(id)?;
+                }
+                merges.push(Merge { kept, removed });
+            }
+            Ok(merges)
+        })
+    }
+
+    // Versions
+
+    /// Replace a file's data with that of a prior version. The data being replaced is kept as a
+    /// new version, as with any other edit, so reverting can itself be undone.
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        let data = self.get_version(id, version)?;
+        self.edit_file(id, Some(&data), None::<[Tag; 0]>)
+    }
+
+    // Special files
+
+    /// Replace the stored data of a special file. Generated special files can't be replaced, as
+    /// their ID is taken by what's generated, so this fails with an already exists error for them.
+    ///
+    /// This default has nowhere to store special files, so treats every one like a generated one
+    /// and always fails. Implementations which can store them should override it, along with
+    /// [`special_data`](FileSystemRead::special_data).
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        let _ = data;
+        Err(Self::Error::already_exists(file.id()))
+    }
+
+    /// Replace the data of the config special file
+    fn set_config(&self, data: &[u8]) -> Result<(), Self::Error> {
+        self.set_special_data(SpecialFile::Config, data)
+    }
+}
+
+/// Generate the contents of a special file from the state of a filesystem, as the default for
+/// [`FileSystem::special`]
+pub(crate) fn generate_special<F: FileSystemRead + ?Sized>(
+    fs: &F,
+    file: SpecialFile,
+) -> Result<FileInfo, F::Error> {
+    let mut out = String::new();
+    match file {
+        SpecialFile::Manifest => {
+            for id in fs.search_tags(TagPredicate::And(Vec::new()))? {
+                let _ = writeln!(out, "{:016X}", id.into_u64_unchecked());
+            }
+        }
+        SpecialFile::TagRegistry => {
+            let mut tags = BTreeSet::new();
+            for id in fs.search_tags(TagPredicate::And(Vec::new()))? {
+                tags.extend(fs.get_info(id)?.tags);
+            }
+            for tag in tags {
+                let _ = writeln!(out, "{tag}");
+            }
+        }
+        // Without a record of removed files, the unused IDs below the last one allocated are
+        // taken as removed
+        SpecialFile::Trash => {
+            let existing = fs
+                .search_tags(TagPredicate::And(Vec::new()))?
+                .into_iter()
+                .collect::<BTreeSet<_>>();
+            if let Some(last) = fs.last_id()? {
+                let unused = (256..=last.into_u64_unchecked())
+                    .map(FileId::from_u64_unchecked)
+                    .filter(|id| !existing.contains(id))
+                    .take(SpecialFile::TRASH_LIMIT);
+                for id in unused {
+                    let _ = writeln!(out, "{:016X}", id.into_u64_unchecked());
+                }
+            }
+        }
+        _ => {
+            return Ok(FileInfo {
+                id: file.id(),
+                tags: BTreeSet::new(),
+                data: fs.special_data(file)?.into_boxed_slice(),
+            })
+        }
+    }
+
+    Ok(FileInfo {
+        id: file.id(),
+        tags: BTreeSet::new(),
+        data: out.into_bytes().into_boxed_slice(),
+    })
+}
+
+/// Check that a special file can be replaced, for implementations of
+/// [`FileSystemWrite::set_special_data`]
+pub(crate) fn check_stored<E: Error>(file: SpecialFile) -> Result<(), E> {
+    if file.is_generated() {
+        Err(E::already_exists(file.id()))
+    } else {
+        Ok(())
+    }
+}
+
+/// Check whether a filesystem has a file, without reading its data
+#[cfg(feature = "std")]
+pub(crate) fn exists<F: FileSystemRead + ?Sized>(fs: &F, id: FileId) -> Result<bool, F::Error> {
+    match fs.get_metadata(id) {
+        Ok(_) => Ok(true),
+        Err(err) if error::is_not_found(&err) => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// Write data into a file's buffer at an offset, growing it with zeroes as needed. Writing nothing
+/// never grows it. Fails if the buffer can't grow to the end of the data.
+fn write_into<E: Error>(buf: &mut Vec<u8>, id: FileId, offset: u64, data: &[u8]) -> Result<(), E> {
+    if data.is_empty() {
+        return Ok(());
+    }
+    let end = usize::try_from(offset)
+        .ok()
+        .and_then(|start| start.checked_add(data.len()))
+        .ok_or_else(|| E::storage_full(id))?;
+    if let Some(extra) = end.checked_sub(buf.len()) {
+        buf.try_reserve_exact(extra)
+            .map_err(|_| E::storage_full(id))?;
+        buf.resize(end, 0);
+    }
+    buf[end - data.len()..end].copy_from_slice(data);
+    Ok(())
+}
+
+/// Resize a file's buffer, filling any new room with zeroes. Fails with
+/// [`Error::storage_full`] instead of aborting if the room can't be allocated.
+fn resize_data<E: Error>(buf: &mut Vec<u8>, id: FileId, len: u64) -> Result<(), E> {
+    let len = usize::try_from(len).map_err(|_| E::storage_full(id))?;
+    if let Some(extra) = len.checked_sub(buf.len()) {
+        buf.try_reserve_exact(extra)
+            .map_err(|_| E::storage_full(id))?;
+    }
+    buf.resize(len, 0);
+    Ok(())
+}
+
+/// Cut a range of data short at its length, making it empty if it starts past the end
+fn clamp_range(range: Range<u64>, len: usize) -> Range<usize> {
+    let clamp = |at| usize::try_from(at).map_or(len, |at: usize| at.min(len));
+    let start = clamp(range.start);
+    start..clamp(range.end).max(start)
+}
+
+/// Move a tag to a new group, if it's in the old one
+fn rename_group(tag: Tag, old: &Group, new: &Group) -> Tag {
+    if tag.group() == old {
+        tag.in_group(new.clone())
+    } else {
+        tag
+    }
 }
 
 /// Combined info about a file
@@ -101,6 +987,20 @@ pub struct FileInfo {
 }
 
 impl FileInfo {
+    /// Create info about a file from its parts, for implementations of [`FileSystem`] outside
+    /// this crate
+    pub fn new<I, D>(id: FileId, tags: I, data: D) -> FileInfo
+    where
+        I: IntoIterator<Item = Tag>,
+        D: Into<Box<[u8]>>,
+    {
+        FileInfo {
+            id,
+            tags: tags.into_iter().collect(),
+            data: data.into(),
+        }
+    }
+
     /// Get the ID of this file
     pub fn id(&self) -> FileId {
         self.id
@@ -113,6 +1013,11 @@ impl FileInfo {
 
     /// Get the raw data associated with this file
     pub fn data(&self) -> &[u8] {
-        &*self.data
+        &self.data
+    }
+
+    /// Take apart this info, into the file's ID, tags, and data
+    pub fn into_parts(self) -> (FileId, BTreeSet<Tag>, Box<[u8]>) {
+        (self.id, self.tags, self.data)
     }
 }
@@ -0,0 +1,199 @@
+//! Typed relations between files, stored as tags on the file they're from
+
+use alloc::string::String;
+use core::convert::TryFrom;
+
+use crate::{FileId, FileSystemRead, FileSystemWrite, Group, Tag, TagPredicate};
+
+/// The start of the group of a link's tag, before the hex ID of the file it's to
+const GROUP_PREFIX: &str = "link.";
+
+/// The group of the tags linking to a file
+pub(crate) fn link_group(to: FileId) -> Group {
+    Group::custom(format!("{}{:x}", GROUP_PREFIX, to.into_u64_unchecked()))
+}
+
+/// The file a link's tag is to, if it's in a link group
+fn link_target(group: &Group) -> Option<FileId> {
+    let Group::Custom(name) = group else {
+        return None;
+    };
+    let id = u64::from_str_radix(name.strip_prefix(GROUP_PREFIX)?, 16).ok()?;
+    FileId::try_from(id).ok()
+}
+
+/// A relation of some kind from one file to another, like an attachment or a thumbnail
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Link {
+    from: FileId,
+    to: FileId,
+    kind: String,
+}
+
+impl Link {
+    /// The file the link is from, which stores it
+    pub fn from(&self) -> FileId {
+        self.from
+    }
+
+    /// The file the link is to
+    pub fn to(&self) -> FileId {
+        self.to
+    }
+
+    /// What the relation between the files is
+    pub fn kind(&self) -> &str {
+        &self.kind
+    }
+}
+
+/// The links between the files of a filesystem. A link is stored as a tag on the file it's
+/// from, named for its kind in a group for the file it's to, so links can be searched for like
+/// any other tag, and [`TagPredicate::linked_to`] finds every file with a link to another.
+///
+/// Links are kept as files change like their other tags, and are removed along with the file
+/// they're from. Removing the file a link is to leaves the link in place.
+pub struct Links<'a, F: ?Sized> {
+    fs: &'a F,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> Links<'a, F> {
+    /// Get the links between the files of a filesystem
+    pub fn new(fs: &'a F) -> Links<'a, F> {
+        Links { fs }
+    }
+
+    /// Get every link from a file, in order of the file they're to and then their kind
+    pub fn links_from(&self, id: FileId) -> Result<Vec<Link>, F::Error> {
+        Ok(self
+            .fs
+            .get_tags(id)?
+            .into_iter()
+            .filter_map(|tag| {
+                Some(Link {
+                    from: id,
+                    to: link_target(tag.group())?,
+                    kind: tag.name().to_owned(),
+                })
+            })
+            .collect())
+    }
+
+    /// Get every link to a file, in order of the file they're from and then their kind
+    pub fn links_to(&self, id: FileId) -> Result<Vec<Link>, F::Error> {
+        let group = link_group(id);
+        let mut links = Vec::new();
+        for from in self.fs.search_tags(TagPredicate::Group(group.clone()))? {
+            links.extend(
+                self.fs
+                    .get_tags(from)?
+                    .into_iter()
+                    .filter(|tag| *tag.group() == group)
+                    .map(|tag| Link {
+                        from,
+                        to: id,
+                        kind: tag.name().to_owned(),
+                    }),
+            );
+        }
+        Ok(links)
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> Links<'_, F> {
+    /// Link one file to another with a kind of relation. Both files must exist, and linking
+    /// files which already are with the same kind does nothing.
+    pub fn relates(&self, from: FileId, to: FileId, kind: &str) -> Result<(), F::Error> {
+        if !crate::exists(self.fs, to)? {
+            return Err(crate::Error::file_not_found(to));
+        }
+        self.fs
+            .add_tags(from, [Tag::new(link_group(to), kind.to_owned())])
+    }
+
+    /// Remove a link of some kind from one file to another. Returns whether there was one.
+    pub fn unrelate(&self, from: FileId, to: FileId, kind: &str) -> Result<bool, F::Error> {
+        let tag = Tag::new(link_group(to), kind.to_owned());
+        if !self.fs.get_tags(from)?.contains(&tag) {
+            return Ok(false);
+        }
+        self.fs.remove_tags(from, [tag])?;
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::error::is_not_found;
+    use crate::InMemoryFs;
+
+    #[test]
+    fn test_links() {
+        let fs = InMemoryFs::new();
+        let links = Links::new(&fs);
+        let doc = fs.add_file(b"doc", [Tag::named("doc")]).unwrap();
+        let draft = fs.add_file(b"draft", [Tag::named("doc")]).unwrap();
+        let image = fs.add_file(b"image", []).unwrap();
+        let thumb = fs.add_file(b"thumb", []).unwrap();
+
+        links.relates(doc, image, "attachment").unwrap();
+        links.relates(draft, image, "attachment").unwrap();
+        links.relates(thumb, image, "thumbnail").unwrap();
+        links.relates(doc, draft, "previous").unwrap();
+        // Linking again changes nothing
+        links.relates(doc, image, "attachment").unwrap();
+
+        let from_doc = links.links_from(doc).unwrap();
+        assert_eq!(
+            from_doc
+                .iter()
+                .map(|link| (link.to(), link.kind()))
+                .collect::<Vec<_>>(),
+            [(draft, "previous"), (image, "attachment")]
+        );
+        assert!(from_doc.iter().all(|link| link.from() == doc));
+
+        let to_image = links.links_to(image).unwrap();
+        assert_eq!(
+            to_image
+                .iter()
+                .map(|link| (link.from(), link.kind()))
+                .collect::<Vec<_>>(),
+            [
+                (doc, "attachment"),
+                (draft, "attachment"),
+                (thumb, "thumbnail")
+            ]
+        );
+
+        assert_eq!(
+            fs.search_tags(TagPredicate::linked_to(image)).unwrap(),
+            [doc, draft, thumb]
+        );
+        assert_eq!(
+            fs.search_tags(TagPredicate::and([
+                TagPredicate::linked_to(image),
+                TagPredicate::not(TagPredicate::linked_to(draft)),
+                TagPredicate::name("doc"),
+            ]))
+            .unwrap(),
+            [draft]
+        );
+
+        assert!(links.unrelate(draft, image, "attachment").unwrap());
+        assert!(!links.unrelate(draft, image, "attachment").unwrap());
+        assert_eq!(links.links_to(image).unwrap().len(), 2);
+
+        let missing = FileId::from_u64_unchecked(0xFFFF);
+        assert!(is_not_found(
+            &links.relates(doc, missing, "attachment").unwrap_err()
+        ));
+
+        // Links go with the file they're from, but stay when the file they're to is removed
+        fs.remove_file(thumb).unwrap();
+        fs.remove_file(draft).unwrap();
+        assert_eq!(links.links_to(image).unwrap().len(), 1);
+        assert_eq!(links.links_from(doc).unwrap().len(), 2);
+    }
+}
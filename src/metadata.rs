@@ -0,0 +1,48 @@
+//! Metadata kept about every file, alongside its tags
+
+use std::time::SystemTime;
+
+/// Information about a file which is maintained by the filesystem, rather than set by the user
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Metadata {
+    pub(crate) created: SystemTime,
+    pub(crate) modified: SystemTime,
+    pub(crate) size: u64,
+    pub(crate) hash: [u8; 32],
+}
+
+impl Metadata {
+    /// When the file was added
+    pub fn created(&self) -> SystemTime {
+        self.created
+    }
+
+    /// When the file's data was last changed. Changing tags doesn't count as a modification.
+    pub fn modified(&self) -> SystemTime {
+        self.modified
+    }
+
+    /// The size of the file's data, in bytes
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    /// The BLAKE3 hash of the file's data
+    pub fn hash(&self) -> &[u8; 32] {
+        &self.hash
+    }
+}
+
+/// The current time. The standard clock panics in the browser, so there it's read from JavaScript.
+pub(crate) fn now() -> SystemTime {
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    return SystemTime::UNIX_EPOCH
+        + std::time::Duration::from_secs_f64(js_sys::Date::now() / 1000.0);
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    SystemTime::now()
+}
+
+/// Hash file data the same way as [`Metadata::hash`]
+pub(crate) fn hash_data(data: &[u8]) -> [u8; 32] {
+    *blake3::hash(data).as_bytes()
+}
@@ -0,0 +1,567 @@
+//! A wrapper reporting how often operations are run and how long they take
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::time::{Duration, Instant};
+
+use crate::metadata::Metadata;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, DedupePolicy, Event, FileId, FileInfo, FileSystem, FileSystemRead,
+    FileSystemWrite, FileWriter, Group, InferredTags, Merge, ProvidedTags, SpecialFile, StreamRead,
+    StreamWrite, Tag, TagInferrer, TagPattern, TagProvider, UsageReport,
+};
+
+/// A kind of operation on a [`MeteredFs`], which measurements are reported for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Operation {
+    /// Adding a file, whether all at once or by streaming
+    Add,
+    /// Changing a file's data or tags, including renaming tags and reverting
+    Edit,
+    /// Removing a file
+    Remove,
+    /// Reading a single file, its metadata, versions, or a special file
+    Get,
+    /// Searching, counting tags, or listing what's stored
+    Search,
+    /// Changing a special file
+    SetSpecial,
+    /// Running a transaction, including every operation in it
+    Transaction,
+}
+
+impl Operation {
+    /// The name of the operation in snake case, such as `set_special`, for labelling
+    /// measurements
+    pub fn name(self) -> &'static str {
+        match self {
+            Operation::Add => "add",
+            Operation::Edit => "edit",
+            Operation::Remove => "remove",
+            Operation::Get => "get",
+            Operation::Search => "search",
+            Operation::SetSpecial => "set_special",
+            Operation::Transaction => "transaction",
+        }
+    }
+}
+
+/// A sink for measurements of the operations run on a [`MeteredFs`], such as an exporter for a
+/// monitoring system
+///
+/// Any function or closure of the form `Fn(Operation, Duration, bool)` is a sink.
+pub trait Metrics: Send + Sync {
+    /// Record that an operation was run, how long it took, and whether it succeeded
+    fn record(&self, op: Operation, elapsed: Duration, ok: bool);
+}
+
+impl<F> Metrics for F
+where
+    F: Fn(Operation, Duration, bool) + Send + Sync,
+{
+    fn record(&self, op: Operation, elapsed: Duration, ok: bool) {
+        self(op, elapsed, ok);
+    }
+}
+
+/// Reports measurements to the recorder installed for the [`metrics`] crate. Each operation
+/// increments the counter `tbf_operations_total`, and its duration in seconds is recorded in the
+/// histogram `tbf_operation_seconds`. Both are labelled with the `operation`'s
+/// [name](Operation::name), and a `result` of `ok` or `error`.
+#[cfg(feature = "metrics")]
+#[derive(Debug, Default, Clone, Copy)]
+pub struct MetricsRecorder;
+
+#[cfg(feature = "metrics")]
+impl Metrics for MetricsRecorder {
+    fn record(&self, op: Operation, elapsed: Duration, ok: bool) {
+        let result = if ok { "ok" } else { "error" };
+        metrics::counter!("tbf_operations_total", "operation" => op.name(), "result" => result)
+            .increment(1);
+        metrics::histogram!("tbf_operation_seconds", "operation" => op.name(), "result" => result)
+            .record(elapsed);
+    }
+}
+
+/// A wrapper around another filesystem, reporting every operation run on it to a [`Metrics`]
+/// sink. Calls are passed on unchanged, so wrapping a filesystem once is enough to measure
+/// everything done with it.
+///
+/// Operations built from others, such as adding tags to every matching file, are reported as one
+/// operation of their own kind. Lazy searches are reported once the search is dropped, timing only
+/// the calls made to it, and streamed files once they're committed, timing from their creation.
+///
+/// ```
+/// # use std::sync::Mutex;
+/// # use std::time::Duration;
+/// # use tbf::{FileSystemWrite, InMemoryFs, MeteredFs, Operation, Tag};
+/// let adds = Mutex::new(Vec::new());
+/// let fs = MeteredFs::new(InMemoryFs::new(), |op, elapsed: Duration, ok| {
+///     if op == Operation::Add {
+///         adds.lock().unwrap().push((elapsed, ok));
+///     }
+/// });
+///
+/// fs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+/// assert_eq!(adds.lock().unwrap().len(), 1);
+/// ```
+pub struct MeteredFs<F, M> {
+    inner: F,
+    metrics: M,
+}
+
+impl<F, M: Metrics> MeteredFs<F, M> {
+    /// Wrap a filesystem, reporting its operations to a sink
+    pub fn new(inner: F, metrics: M) -> MeteredFs<F, M> {
+        MeteredFs { inner, metrics }
+    }
+
+    /// Get the wrapped filesystem, to use it without reporting operations
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Get the sink operations are reported to
+    pub fn metrics(&self) -> &M {
+        &self.metrics
+    }
+
+    /// Unwrap the inner filesystem
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Run an operation, reporting how long it took and whether it succeeded
+    fn measure<T, E>(&self, op: Operation, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+        let start = Instant::now();
+        let out = f();
+        self.metrics.record(op, start.elapsed(), out.is_ok());
+        out
+    }
+}
+
+impl<F: FileSystemRead, M: Metrics> FileSystemRead for MeteredFs<F, M> {
+    type Error = F::Error;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, M, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.measure(Operation::Search, || self.inner.search_tags(tags))
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.measure(Operation::Search, || {
+            self.inner.search_tags_with(tags, options)
+        })
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        let start = Instant::now();
+        let inner = self.inner.search_tags_iter(tags);
+        SearchIter {
+            inner,
+            metrics: &self.metrics,
+            spent: start.elapsed(),
+            ok: true,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_info(id))
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_tags(id))
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_stored_tags(id))
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_info_many(ids))
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_tags_many(ids))
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_data(id))
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.read_range(id, range))
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_metadata(id))
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        self.measure(Operation::Search, || self.inner.last_id())
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.measure(Operation::Search, || self.inner.ids_after(after))
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.measure(Operation::Search, || self.inner.tag_counts(pattern))
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.measure(Operation::Search, || self.inner.count_tags(pattern))
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.measure(Operation::Search, || self.inner.any_match(pattern))
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.measure(Operation::Search, || self.inner.find_duplicates())
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.measure(Operation::Search, || self.inner.find_by_hash(hash))
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        self.measure(Operation::Search, || self.inner.list_groups())
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.measure(Operation::Search, || self.inner.list_tags(group))
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        self.measure(Operation::Search, || self.inner.usage())
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.list_versions(id))
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.get_version(id, version))
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        self.measure(Operation::Get, || self.inner.special(file))
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.special_data(file))
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        self.inner.subscribe()
+    }
+}
+
+impl<F: FileSystem, M: Metrics> FileSystemWrite for MeteredFs<F, M> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Add, || self.inner.add_file(data, tags))
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Add, || {
+            self.inner.add_file_with_id(id, data, tags)
+        })
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Edit, || self.inner.edit_file(id, data, tags))
+    }
+
+    fn write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        self.measure(Operation::Edit, || self.inner.write_at(id, offset, data))
+    }
+
+    fn truncate(&self, id: FileId, len: u64) -> Result<(), Self::Error> {
+        self.measure(Operation::Edit, || self.inner.truncate(id, len))
+    }
+
+    fn supports_partial_writes(&self) -> bool {
+        self.inner.supports_partial_writes()
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.measure(Operation::Remove, || self.inner.remove_file(id))
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        self.measure(Operation::Transaction, || {
+            self.inner.transaction(|_| f(self))
+        })
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Edit, || self.inner.add_tags(id, tags))
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Edit, || self.inner.remove_tags(id, tags))
+    }
+
+    fn add_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Edit, || {
+            self.inner.add_tags_matching(pattern, tags)
+        })
+    }
+
+    fn remove_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        self.measure(Operation::Edit, || {
+            self.inner.remove_tags_matching(pattern, tags)
+        })
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        self.measure(Operation::Edit, || self.inner.rename_tag(old, new))
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        self.measure(Operation::Edit, || self.inner.rename_group(old, new))
+    }
+
+    fn dedupe(&self, policy: DedupePolicy) -> Result<Vec<Merge>, Self::Error> {
+        self.measure(Operation::Edit, || self.inner.dedupe(policy))
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        self.measure(Operation::Edit, || self.inner.revert(id, version))
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.measure(Operation::SetSpecial, || {
+            self.inner.set_special_data(file, data)
+        })
+    }
+}
+
+impl<F: ProvidedTags, M: Metrics> ProvidedTags for MeteredFs<F, M> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.inner.register_provider(group, provider)
+    }
+}
+
+impl<F: InferredTags, M: Metrics> InferredTags for MeteredFs<F, M> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inner.register_inferrer(inferrer)
+    }
+}
+
+impl<F: StreamRead, M: Metrics> StreamRead for MeteredFs<F, M> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.measure(Operation::Get, || self.inner.read_file(id))
+    }
+}
+
+impl<F: StreamWrite, M: Metrics> StreamWrite for MeteredFs<F, M> {
+    type Writer<'a>
+        = Writer<'a, F, M>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let start = Instant::now();
+        match self.inner.create_file(tags) {
+            Ok(inner) => Ok(Writer {
+                inner,
+                metrics: &self.metrics,
+                start,
+            }),
+            Err(err) => {
+                self.metrics.record(Operation::Add, start.elapsed(), false);
+                Err(err)
+            }
+        }
+    }
+}
+
+/// A lazy search over a [`MeteredFs`], which is reported once dropped
+pub struct SearchIter<'a, F: FileSystemRead + 'a, M: Metrics, P: TagPattern + 'a> {
+    inner: F::SearchIter<'a, P>,
+    metrics: &'a M,
+    /// Time spent in the search so far, not counting time between calls
+    spent: Duration,
+    ok: bool,
+}
+
+impl<F: FileSystemRead, M: Metrics, P: TagPattern> Iterator for SearchIter<'_, F, M, P> {
+    type Item = Result<FileId, F::Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let start = Instant::now();
+        let next = self.inner.next();
+        self.spent += start.elapsed();
+        self.ok &= !matches!(next, Some(Err(_)));
+        next
+    }
+}
+
+impl<F: FileSystemRead, M: Metrics, P: TagPattern> Drop for SearchIter<'_, F, M, P> {
+    fn drop(&mut self) {
+        self.metrics.record(Operation::Search, self.spent, self.ok);
+    }
+}
+
+/// A handle streaming data into a new file of a [`MeteredFs`], which is reported once committed
+pub struct Writer<'a, F: StreamWrite + 'a, M> {
+    inner: F::Writer<'a>,
+    metrics: &'a M,
+    start: Instant,
+}
+
+impl<F: StreamWrite, M> io::Write for Writer<'_, F, M> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<F: StreamWrite, M: Metrics> FileWriter for Writer<'_, F, M> {
+    type Error = F::Error;
+
+    fn commit(self) -> Result<FileId, Self::Error> {
+        let out = self.inner.commit();
+        self.metrics
+            .record(Operation::Add, self.start.elapsed(), out.is_ok());
+        out
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Write;
+    use std::sync::Mutex;
+
+    use super::*;
+    use crate::InMemoryFs;
+
+    #[cfg(feature = "testing")]
+    #[test]
+    fn test_conformance() {
+        crate::testkit::assert_filesystem_conformance(|| {
+            MeteredFs::new(InMemoryFs::new(), |_: Operation, _: Duration, _: bool| ())
+        });
+    }
+
+    #[test]
+    fn test_metered() {
+        let seen = Mutex::new(Vec::new());
+        let fs = MeteredFs::new(InMemoryFs::new(), |op, _: Duration, ok| {
+            seen.lock().unwrap().push((op, ok));
+        });
+        let taken = |seen: &Mutex<Vec<_>>| core::mem::take(&mut *seen.lock().unwrap());
+
+        let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        fs.get_data(id).unwrap();
+        fs.get_data(FileId::from_u64_unchecked(1000)).unwrap_err();
+        fs.search_tags(Tag::named("a")).unwrap();
+        assert_eq!(
+            taken(&seen),
+            [
+                (Operation::Add, true),
+                (Operation::Get, true),
+                (Operation::Get, false),
+                (Operation::Search, true),
+            ]
+        );
+
+        // Operations in a transaction are reported as well as the transaction
+        fs.transaction(|fs| fs.remove_file(id)).unwrap();
+        assert_eq!(
+            taken(&seen),
+            [(Operation::Remove, true), (Operation::Transaction, true)]
+        );
+
+        let mut iter = fs.search_tags_iter(Tag::named("a"));
+        assert!(iter.next().is_none());
+        assert!(taken(&seen).is_empty());
+        drop(iter);
+        assert_eq!(taken(&seen), [(Operation::Search, true)]);
+
+        let mut writer = fs.create_file([Tag::named("b")]).unwrap();
+        writer.write_all(&[2]).unwrap();
+        assert!(taken(&seen).is_empty());
+        writer.commit().unwrap();
+        assert_eq!(taken(&seen), [(Operation::Add, true)]);
+    }
+}
@@ -0,0 +1,130 @@
+//! Copying files between implementations of the main trait
+
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+use core::fmt;
+
+use crate::{FileId, FileSystemRead, FileSystemWrite, TagPattern};
+
+/// Error while migrating files between two filesystems
+#[derive(Debug)]
+pub enum Error<S, D> {
+    /// The filesystem being copied from returned an error
+    Source(S),
+    /// The filesystem being copied to returned an error
+    Dest(D),
+}
+
+impl<S: fmt::Display, D: fmt::Display> fmt::Display for Error<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Source(err) => write!(f, "source filesystem: {err}"),
+            Error::Dest(err) => write!(f, "destination filesystem: {err}"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<S: std::error::Error, D: std::error::Error> std::error::Error for Error<S, D> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Source(err) => std::error::Error::source(err),
+            Error::Dest(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// Copy every file matching a pattern from one filesystem to another, with its data and tags.
+/// Files are given new IDs by the destination, so this returns a map from each file's ID in the
+/// source to its new one. The source is left unchanged.
+///
+/// The migration isn't atomic: if it fails part way through, files already copied are kept.
+/// To keep IDs the same instead, use [`migrate_with_ids`].
+pub fn migrate<S, D, P>(
+    src: &S,
+    dst: &D,
+    pattern: P,
+) -> Result<BTreeMap<FileId, FileId>, Error<S::Error, D::Error>>
+where
+    S: FileSystemRead,
+    D: FileSystemWrite,
+    P: TagPattern,
+{
+    let mut out = BTreeMap::new();
+    for id in src.search_tags(pattern).map_err(Error::Source)? {
+        let info = src.get_info(id).map_err(Error::Source)?;
+        let new_id = dst.add_file(&info.data, info.tags).map_err(Error::Dest)?;
+        out.insert(id, new_id);
+    }
+    Ok(out)
+}
+
+/// Copy every file matching a pattern from one filesystem to another, keeping their IDs. Fails
+/// if any of the IDs are already in use in the destination, after copying the files before it.
+/// Returns the IDs of the copied files.
+pub fn migrate_with_ids<S, D, P>(
+    src: &S,
+    dst: &D,
+    pattern: P,
+) -> Result<Vec<FileId>, Error<S::Error, D::Error>>
+where
+    S: FileSystemRead,
+    D: FileSystemWrite,
+    P: TagPattern,
+{
+    let ids = src.search_tags(pattern).map_err(Error::Source)?;
+    for &id in &ids {
+        let info = src.get_info(id).map_err(Error::Source)?;
+        dst.add_file_with_id(id, &info.data, info.tags)
+            .map_err(Error::Dest)?;
+    }
+    Ok(ids)
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{InMemoryFs, Tag};
+
+    #[test]
+    fn test_migrate() {
+        let src = InMemoryFs::new();
+        let first = src.add_file(&[1], [Tag::named("a")]).unwrap();
+        src.add_file(&[2], [Tag::named("b")]).unwrap();
+        let third = src
+            .add_file(&[3], [Tag::named("a"), Tag::named("c")])
+            .unwrap();
+
+        let dst = InMemoryFs::new();
+        dst.add_file(&[0], []).unwrap();
+
+        let ids = migrate(&src, &dst, Tag::named("a")).unwrap();
+        assert_eq!(ids.keys().copied().collect::<Vec<_>>(), [first, third]);
+
+        for (old, new) in ids {
+            let old_info = src.get_info(old).unwrap();
+            let new_info = dst.get_info(new).unwrap();
+            assert_eq!(old_info.tags(), new_info.tags());
+            assert_eq!(old_info.data(), new_info.data());
+        }
+        assert_eq!(dst.search_tags(Tag::named("b")).unwrap(), []);
+    }
+
+    #[test]
+    fn test_migrate_with_ids() {
+        let src = InMemoryFs::new();
+        src.add_file(&[1], [Tag::named("a")]).unwrap();
+        let second = src.add_file(&[2], [Tag::named("b")]).unwrap();
+
+        let dst = InMemoryFs::new();
+        assert_eq!(
+            migrate_with_ids(&src, &dst, Tag::named("b")).unwrap(),
+            [second]
+        );
+        assert_eq!(dst.get_info(second).unwrap().data(), &[2]);
+        assert!(matches!(
+            migrate_with_ids(&src, &dst, Tag::named("b")),
+            Err(Error::Dest(crate::ImfsError::AlreadyExists(_)))
+        ));
+    }
+}
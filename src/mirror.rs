@@ -0,0 +1,670 @@
+//! Mirroring wrapper, applying every change to a second TBF
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::marker::PhantomData;
+use core::ops::Range;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+
+use crate::error::{is_not_found, ErrorKind};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::Metadata;
+use crate::{
+    exists, BatchResult, BufferedWriter, Error as _, Event, FileId, FileInfo, FileSystem,
+    FileSystemRead, FileSystemWrite, Group, InferredTags, ProvidedTags, SearchOptions, SpecialFile,
+    StreamRead, StreamWrite, Tag, TagInferrer, TagPattern, TagPredicate, TagProvider,
+};
+
+/// Error for a mirrored filesystem
+#[derive(Debug)]
+pub enum Error<P, M> {
+    /// The primary filesystem returned an error
+    Primary(P),
+    /// The mirror filesystem returned an error
+    Mirror(M),
+}
+
+impl<P: crate::Error, M: crate::Error> crate::Error for Error<P, M> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Primary(P::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Primary(P::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Primary(P::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Primary(P::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Primary(err) => err.generic_kind(),
+            Error::Mirror(err) => err.generic_kind(),
+        }
+    }
+}
+
+impl<P: fmt::Display, M: fmt::Display> fmt::Display for Error<P, M> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Primary(err) => write!(f, "primary filesystem: {err}"),
+            Error::Mirror(err) => write!(f, "mirror filesystem: {err}"),
+        }
+    }
+}
+
+impl<P: std::error::Error, M: std::error::Error> std::error::Error for Error<P, M> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Primary(err) => std::error::Error::source(err),
+            Error::Mirror(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// How a [`MirroredFs`] handles a change which the primary makes but the mirror fails to
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Default)]
+pub enum MirrorPolicy {
+    /// The change fails, and is undone on the primary, so the two are always the same
+    #[default]
+    Strict,
+    /// The change is kept on the primary, and the files involved are remembered as out of sync
+    /// until [`MirroredFs::resync`] is called. Changes only fail if the primary fails.
+    BestEffort,
+}
+
+/// A wrapper around two filesystems, applying every change to both and reading from the first,
+/// the primary. The second, the mirror, is kept as a copy of the primary, with the same IDs, so
+/// it can serve as a live backup, or be switched to when moving between implementations.
+///
+/// Both filesystems should start out the same, such as both empty. New files are given IDs by
+/// the primary. Inferred tags are found once and stored in both, while providers are registered
+/// with the primary, as it's the one read from.
+pub struct MirroredFs<P, M> {
+    primary: P,
+    mirror: M,
+    policy: MirrorPolicy,
+    /// Files the mirror failed to change. The config special file's ID is used for the config.
+    out_of_sync: Mutex<BTreeSet<FileId>>,
+    /// Groups of provided tags, which aren't copied to the mirror
+    provided: RwLock<BTreeSet<Group>>,
+    inferrers: RwLock<Inferrers>,
+}
+
+impl<P: FileSystem, M: FileSystem> MirroredFs<P, M> {
+    /// Mirror changes to a primary filesystem into another, failing changes the mirror can't make
+    pub fn new(primary: P, mirror: M) -> MirroredFs<P, M> {
+        MirroredFs::with_policy(primary, mirror, MirrorPolicy::Strict)
+    }
+
+    /// Mirror changes to a primary filesystem into another, handling failures of the mirror
+    /// with the given policy
+    pub fn with_policy(primary: P, mirror: M, policy: MirrorPolicy) -> MirroredFs<P, M> {
+        MirroredFs {
+            primary,
+            mirror,
+            policy,
+            out_of_sync: Mutex::new(BTreeSet::new()),
+            provided: RwLock::new(BTreeSet::new()),
+            inferrers: RwLock::new(Inferrers::new()),
+        }
+    }
+
+    /// Get the primary filesystem, which is read from
+    pub fn primary(&self) -> &P {
+        &self.primary
+    }
+
+    /// Get the mirror filesystem
+    pub fn mirror(&self) -> &M {
+        &self.mirror
+    }
+
+    /// Get the policy for failures of the mirror
+    pub fn policy(&self) -> MirrorPolicy {
+        self.policy
+    }
+
+    /// Take apart the wrapper, into its primary and mirror filesystems
+    pub fn into_parts(self) -> (P, M) {
+        (self.primary, self.mirror)
+    }
+
+    /// Get the IDs of files which the mirror failed to change, and so may differ from the
+    /// primary. Only files changed under [`MirrorPolicy::BestEffort`] can be out of sync.
+    pub fn out_of_sync(&self) -> Vec<FileId> {
+        self.lock_out_of_sync().iter().copied().collect()
+    }
+
+    /// Copy every out of sync file from the primary to the mirror again, or remove it from the
+    /// mirror if the primary no longer has it. Files are no longer out of sync once they've been
+    /// copied, so if this fails part way through, it can be called again to carry on.
+    pub fn resync(&self) -> Result<(), Error<P::Error, M::Error>> {
+        for id in self.out_of_sync() {
+            if let Some(file) = SpecialFile::from_id(id) {
+                let data = self.primary.special_data(file).map_err(Error::Primary)?;
+                self.mirror
+                    .set_special_data(file, &data)
+                    .map_err(Error::Mirror)?;
+            } else {
+                self.resync_file(id)?;
+            }
+            self.lock_out_of_sync().remove(&id);
+        }
+        Ok(())
+    }
+
+    fn resync_file(&self, id: FileId) -> Result<(), Error<P::Error, M::Error>> {
+        let (_, tags, data) = match self.primary.get_info(id) {
+            Ok(info) => info.into_parts(),
+            Err(err) if is_not_found(&err) => {
+                return match self.mirror.remove_file(id) {
+                    Err(err) if !is_not_found(&err) => Err(Error::Mirror(err)),
+                    _ => Ok(()),
+                };
+            }
+            Err(err) => return Err(Error::Primary(err)),
+        };
+
+        let provided = self.provided.read().unwrap_or_else(PoisonError::into_inner);
+        let tags = tags
+            .into_iter()
+            .filter(|tag| !provided.contains(tag.group()))
+            .collect::<Vec<_>>();
+        drop(provided);
+
+        if exists(&self.mirror, id).map_err(Error::Mirror)? {
+            self.mirror.edit_file(id, Some(&data), Some(tags))
+        } else {
+            self.mirror.add_file_with_id(id, &data, tags)
+        }
+        .map_err(Error::Mirror)
+    }
+
+    fn lock_out_of_sync(&self) -> MutexGuard<'_, BTreeSet<FileId>> {
+        self.out_of_sync
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn inferred_tags<I>(&self, data: &[u8], tags: I) -> Vec<Tag>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<Vec<_>>();
+        tags.extend(infer_tags(
+            &self
+                .inferrers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        ));
+        tags
+    }
+
+    /// Make a change to the primary, then the mirror, handling a failure of the mirror with the
+    /// policy. `changed` gives the files which are out of sync if it fails.
+    fn apply<T>(
+        &self,
+        primary: impl FnOnce(&P) -> Result<T, P::Error>,
+        mirror: impl FnOnce(&M, &T) -> Result<(), M::Error>,
+        changed: impl FnOnce(&T) -> Vec<FileId>,
+    ) -> Result<T, Error<P::Error, M::Error>> {
+        match self.policy {
+            MirrorPolicy::Strict => {
+                // The primary needs one of its own errors to undo the change, so a stand-in is
+                // returned to it for the mirror's
+                let mut mirror_err = None;
+                let out = self.primary.transaction(|fs| {
+                    let val = primary(fs)?;
+                    match mirror(&self.mirror, &val) {
+                        Ok(()) => Ok(val),
+                        Err(err) => {
+                            mirror_err = Some(err);
+                            Err(P::Error::file_not_found(FileId::from_u64_unchecked(0)))
+                        }
+                    }
+                });
+                match (out, mirror_err) {
+                    (Err(_), Some(err)) => Err(Error::Mirror(err)),
+                    (out, _) => out.map_err(Error::Primary),
+                }
+            }
+            MirrorPolicy::BestEffort => {
+                let val = primary(&self.primary).map_err(Error::Primary)?;
+                if mirror(&self.mirror, &val).is_err() {
+                    self.lock_out_of_sync().extend(changed(&val));
+                }
+                Ok(val)
+            }
+        }
+    }
+}
+
+impl<P: FileSystem, M: FileSystem> FileSystemRead for MirroredFs<P, M> {
+    type Error = Error<P::Error, M::Error>;
+    type SearchIter<'a, Q>
+        = SearchIter<'a, P, M, Q>
+    where
+        Self: 'a,
+        Q: TagPattern + 'a;
+
+    fn search_tags<Q>(&self, tags: Q) -> Result<Vec<FileId>, Self::Error>
+    where
+        Q: TagPattern,
+    {
+        self.primary.search_tags(tags).map_err(Error::Primary)
+    }
+
+    fn search_tags_with<Q>(
+        &self,
+        tags: Q,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        Q: TagPattern,
+    {
+        self.primary
+            .search_tags_with(tags, options)
+            .map_err(Error::Primary)
+    }
+
+    fn search_tags_iter<'a, Q>(&'a self, tags: Q) -> Self::SearchIter<'a, Q>
+    where
+        Q: TagPattern + 'a,
+    {
+        SearchIter {
+            inner: self.primary.search_tags_iter(tags),
+            mirror: PhantomData,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        self.primary.get_info(id).map_err(Error::Primary)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.primary.get_tags(id).map_err(Error::Primary)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.primary.get_stored_tags(id).map_err(Error::Primary)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        let found = self.primary.get_info_many(ids).map_err(Error::Primary)?;
+        Ok(found
+            .into_iter()
+            .map(|info| info.map_err(Error::Primary))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        let found = self.primary.get_tags_many(ids).map_err(Error::Primary)?;
+        Ok(found
+            .into_iter()
+            .map(|tags| tags.map_err(Error::Primary))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.primary.get_data(id).map_err(Error::Primary)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.primary.read_range(id, range).map_err(Error::Primary)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.primary.get_metadata(id).map_err(Error::Primary)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        self.primary.last_id().map_err(Error::Primary)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.primary.ids_after(after).map_err(Error::Primary)
+    }
+
+    fn tag_counts<Q>(&self, pattern: Q) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        Q: TagPattern,
+    {
+        self.primary.tag_counts(pattern).map_err(Error::Primary)
+    }
+
+    fn count_tags<Q>(&self, pattern: Q) -> Result<u64, Self::Error>
+    where
+        Q: TagPattern,
+    {
+        self.primary.count_tags(pattern).map_err(Error::Primary)
+    }
+
+    fn any_match<Q>(&self, pattern: Q) -> Result<bool, Self::Error>
+    where
+        Q: TagPattern,
+    {
+        self.primary.any_match(pattern).map_err(Error::Primary)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.primary.find_duplicates().map_err(Error::Primary)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.primary.find_by_hash(hash).map_err(Error::Primary)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        self.primary.list_groups().map_err(Error::Primary)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.primary.list_tags(group).map_err(Error::Primary)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.primary.list_versions(id).map_err(Error::Primary)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.primary
+            .get_version(id, version)
+            .map_err(Error::Primary)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        self.primary.special(file).map_err(Error::Primary)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.primary.special_data(file).map_err(Error::Primary)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        self.primary.subscribe().map_err(Error::Primary)
+    }
+}
+
+impl<P: FileSystem, M: FileSystem> FileSystemWrite for MirroredFs<P, M> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.inferred_tags(data, tags);
+        self.apply(
+            |fs| fs.add_file(data, tags.iter().cloned()),
+            |fs, &id| fs.add_file_with_id(id, data, tags.iter().cloned()),
+            |&id| vec![id],
+        )
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.inferred_tags(data, tags);
+        self.apply(
+            |fs| fs.add_file_with_id(id, data, tags.iter().cloned()),
+            |fs, ()| fs.add_file_with_id(id, data, tags.iter().cloned()),
+            |()| vec![id],
+        )
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| tags.into_iter().collect::<Vec<_>>());
+        self.apply(
+            |fs| fs.edit_file(id, data, tags.clone()),
+            |fs, ()| fs.edit_file(id, data, tags.clone()),
+            |()| vec![id],
+        )
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.apply(
+            |fs| fs.remove_file(id),
+            |fs, ()| fs.remove_file(id),
+            |()| vec![id],
+        )
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // Each filesystem needs one of its own errors to undo changes, so stand-ins are returned
+        // to them, and the real error returned once they're done
+        let mut err = None;
+        let out = self.primary.transaction(|_| {
+            let out = self.mirror.transaction(|_| {
+                f(self).map_err(|inner| {
+                    err = Some(inner);
+                    M::Error::file_not_found(FileId::from_u64_unchecked(0))
+                })
+            });
+            out.map_err(|inner| {
+                err.get_or_insert(Error::Mirror(inner));
+                P::Error::file_not_found(FileId::from_u64_unchecked(0))
+            })
+        });
+        out.map_err(|inner| err.unwrap_or(Error::Primary(inner)))
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        self.apply(
+            |fs| fs.add_tags(id, tags.iter().cloned()),
+            |fs, ()| fs.add_tags(id, tags.iter().cloned()),
+            |()| vec![id],
+        )
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        self.apply(
+            |fs| fs.remove_tags(id, tags.iter().cloned()),
+            |fs, ()| fs.remove_tags(id, tags.iter().cloned()),
+            |()| vec![id],
+        )
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        let ids = self.search_tags(old.clone())?;
+        self.apply(
+            |fs| fs.rename_tag(old, new.clone()),
+            |fs, ()| fs.rename_tag(old, new.clone()),
+            |()| ids,
+        )
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        let ids = self.search_tags(TagPredicate::group(old.clone()))?;
+        self.apply(
+            |fs| fs.rename_group(old, new.clone()),
+            |fs, ()| fs.rename_group(old, new.clone()),
+            |()| ids,
+        )
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.apply(
+            |fs| fs.set_special_data(file, data),
+            |fs, ()| fs.set_special_data(file, data),
+            |()| vec![file.id()],
+        )
+    }
+}
+
+impl<P: FileSystem + ProvidedTags, M: FileSystem> ProvidedTags for MirroredFs<P, M> {
+    fn register_provider<Q>(&self, group: Group, provider: Q) -> Result<(), Self::Error>
+    where
+        Q: TagProvider + 'static,
+    {
+        self.primary
+            .register_provider(group.clone(), provider)
+            .map_err(Error::Primary)?;
+        self.provided
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(group);
+        Ok(())
+    }
+}
+
+impl<P: FileSystem, M: FileSystem> InferredTags for MirroredFs<P, M> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl<P: FileSystem + StreamRead, M: FileSystem> StreamRead for MirroredFs<P, M> {
+    type Reader<'a>
+        = P::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.primary.read_file(id).map_err(Error::Primary)
+    }
+}
+
+impl<P: FileSystem, M: FileSystem> StreamWrite for MirroredFs<P, M> {
+    type Writer<'a>
+        = Writer<'a, P, M>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+/// A lazy search over a [`MirroredFs`], which is a search of the primary filesystem
+pub struct SearchIter<'a, P: FileSystem + 'a, M: FileSystem, Q: TagPattern + 'a> {
+    inner: P::SearchIter<'a, Q>,
+    mirror: PhantomData<M>,
+}
+
+impl<P: FileSystem, M: FileSystem, Q: TagPattern> Iterator for SearchIter<'_, P, M, Q> {
+    type Item = Result<FileId, Error<P::Error, M::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|id| id.map_err(Error::Primary))
+    }
+}
+
+/// A handle streaming data into a new file of a [`MirroredFs`]. Data is buffered until the handle
+/// is flushed, as it has to be written to both filesystems.
+pub type Writer<'a, P, M> = BufferedWriter<'a, MirroredFs<P, M>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{ImfsError, InMemoryFs, ReadOnly, ReadOnlyError};
+
+    #[test]
+    fn test_mirrored() {
+        let fs = MirroredFs::new(InMemoryFs::new(), InMemoryFs::new());
+        let a = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = fs.add_file(&[2], [Tag::named("b")]).unwrap();
+        fs.edit_file(a, Some(&[3]), None::<[Tag; 0]>).unwrap();
+        fs.add_tags(b, [Tag::named("c")]).unwrap();
+        fs.remove_file(a).unwrap();
+        fs.set_config(b"config").unwrap();
+
+        let res = fs.transaction(|fs| {
+            fs.add_file(&[4], [])?;
+            fs.remove_file(a)
+        });
+        assert!(res.is_err());
+
+        for inner in [fs.primary(), fs.mirror()] {
+            assert_eq!(
+                inner.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+                [b]
+            );
+            assert_eq!(inner.get_tags(b).unwrap().len(), 2);
+            assert_eq!(inner.config().unwrap(), b"config");
+        }
+    }
+
+    #[test]
+    fn test_mirrored_strict() {
+        let fs = MirroredFs::new(InMemoryFs::new(), ReadOnly::new(InMemoryFs::new()));
+        assert!(matches!(
+            fs.add_file(&[1], []),
+            Err(Error::Mirror(ReadOnlyError::ReadOnly))
+        ));
+        assert!(fs
+            .primary()
+            .search_tags(TagPredicate::And(Vec::new()))
+            .unwrap()
+            .is_empty());
+        assert!(fs.out_of_sync().is_empty());
+    }
+
+    #[test]
+    fn test_mirrored_best_effort() {
+        let fs = MirroredFs::with_policy(
+            InMemoryFs::new(),
+            InMemoryFs::new(),
+            MirrorPolicy::BestEffort,
+        );
+        let a = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = fs.add_file(&[2], [Tag::named("b")]).unwrap();
+
+        // Changes the mirror can't make are kept on the primary
+        fs.mirror().remove_file(a).unwrap();
+        fs.edit_file(a, Some(&[3]), None::<[Tag; 0]>).unwrap();
+        fs.mirror().remove_file(b).unwrap();
+        fs.remove_file(b).unwrap();
+        assert!(matches!(
+            fs.mirror().get_info(a),
+            Err(ImfsError::FileNotFound(_))
+        ));
+        assert_eq!(fs.out_of_sync(), [a, b]);
+
+        fs.register_provider(Group::custom("len"), |data: &[u8]| {
+            vec![Tag::named(data.len().to_string())]
+        })
+        .unwrap();
+        fs.resync().unwrap();
+        assert!(fs.out_of_sync().is_empty());
+        assert_eq!(fs.mirror().get_data(a).unwrap(), [3]);
+        assert_eq!(
+            fs.mirror().get_tags(a).unwrap(),
+            BTreeSet::from([Tag::named("a")])
+        );
+    }
+}
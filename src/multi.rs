@@ -0,0 +1,814 @@
+//! Union of several TBFs, searched and changed as one
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::sync::Arc;
+use core::fmt::{self, Write as _};
+use core::ops::Range;
+use std::io::{self, Read};
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, PoisonError};
+
+use crate::error::ErrorKind;
+use crate::events::{Event, Subscribers};
+use crate::metadata::Metadata;
+use crate::Error as _;
+use crate::{
+    DynError, DynFileSystem, DynFileWriter, DynSearchIter, FileId, FileInfo, FileSystemRead,
+    FileSystemWrite, FileWriter, Group, InferredTags, ProvidedTags, SpecialFile, StreamRead,
+    StreamWrite, Tag, TagInferrer, TagPattern, TagPredicate, TagProvider,
+};
+
+/// How many of the low bits of an ID are the file's ID within its store. The rest are the index
+/// of the store.
+const STORE_SHIFT: u32 = 48;
+const LOCAL_MASK: u64 = (1 << STORE_SHIFT) - 1;
+
+/// Error for a multi-store filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// Error was for a file ID that doesn't exist
+    FileNotFound(FileId),
+    /// Error was for a file ID that's already in use
+    AlreadyExists(FileId),
+    /// Error was for a prior version of a file that isn't kept
+    VersionNotFound(FileId, u32),
+    /// A file ID, or the default for new files, refers to a store which doesn't exist
+    UnknownStore(usize),
+    /// A store gave a file an ID too large to be told apart from the files of other stores
+    IdOutOfRange(FileId),
+    /// A store returned an error
+    Store(DynError),
+}
+
+impl crate::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Error::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::VersionNotFound(id, version)
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Error::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Error::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Error::UnknownStore(_) | Error::IdOutOfRange(_) => ErrorKind::State,
+            Error::Store(err) => err.generic_kind(),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::UnknownStore(store) => write!(f, "store {store} doesn't exist"),
+            Error::IdOutOfRange(id) => {
+                write!(f, "file ID {id} is too large to tell which store it's in")
+            }
+            Error::Store(err) => write!(f, "{err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Store(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+/// Translate an error of a store, so any IDs in it are those seen through the [`MultiFs`]
+fn store_err(store: usize, err: DynError) -> Error {
+    let join = |id| join_unchecked(store, id);
+    match err.generic_kind() {
+        ErrorKind::FileNotFound(id) => Error::FileNotFound(join(id)),
+        ErrorKind::AlreadyExists(id) => Error::AlreadyExists(join(id)),
+        ErrorKind::VersionNotFound(id, version) => Error::VersionNotFound(join(id), version),
+        _ => Error::Store(err),
+    }
+}
+
+fn join_unchecked(store: usize, id: FileId) -> FileId {
+    FileId::from_u64_unchecked(((store as u64) << STORE_SHIFT) | id.into_u64_unchecked())
+}
+
+/// A provider or inferrer shared between every store
+struct Shared<T: ?Sized>(Arc<T>);
+
+impl TagProvider for Shared<dyn TagProvider> {
+    fn provide(&self, data: &[u8]) -> Vec<Tag> {
+        self.0.provide(data)
+    }
+}
+
+impl TagInferrer for Shared<dyn TagInferrer> {
+    fn infer(&self, data: &[u8]) -> Vec<Tag> {
+        self.0.infer(data)
+    }
+}
+
+/// Providers and inferrers registered with the [`MultiFs`], so they can also be registered with
+/// stores added later
+#[derive(Default)]
+struct Registered {
+    providers: Vec<(Group, Arc<dyn TagProvider>)>,
+    inferrers: Vec<Arc<dyn TagInferrer>>,
+}
+
+/// A filesystem made of several others, possibly of different implementations, such as a local
+/// directory and a remote server. Searches span every store, and files are changed in whichever
+/// store holds them, so they can be used as if they were one filesystem.
+///
+/// File IDs are namespaced by store: the top 16 bits of an ID are the index of the store, and the
+/// rest the file's ID within it. Files of the first store keep their IDs, and results are in ID
+/// order, so files of earlier stores come first. New files are added to the default store, which
+/// is the first unless changed.
+///
+/// Providers and inferrers are registered with every store. Transactions cover every store, so
+/// all of them are undone if it fails.
+pub struct MultiFs {
+    stores: Vec<Box<dyn DynFileSystem>>,
+    default: usize,
+    registered: Mutex<Registered>,
+    subscribers: Subscribers,
+}
+
+impl MultiFs {
+    /// Create a filesystem with no stores. Files can't be added until one is.
+    pub fn new() -> MultiFs {
+        MultiFs {
+            stores: Vec::new(),
+            default: 0,
+            registered: Mutex::new(Registered::default()),
+            subscribers: Subscribers::new(),
+        }
+    }
+
+    /// Add a store, returning its index. Any providers and inferrers already registered are
+    /// registered with it.
+    pub fn add_store<F: DynFileSystem + 'static>(&mut self, fs: F) -> Result<usize, Error> {
+        let store = self.stores.len();
+        if (store as u64) >> (64 - STORE_SHIFT) != 0 {
+            return Err(Error::UnknownStore(store));
+        }
+
+        let registered = self
+            .registered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        for (group, provider) in &registered.providers {
+            fs.dyn_register_provider(group.clone(), Box::new(Shared(Arc::clone(provider))))
+                .map_err(|err| store_err(store, err))?;
+        }
+        for inferrer in &registered.inferrers {
+            fs.dyn_register_inferrer(Box::new(Shared(Arc::clone(inferrer))))
+                .map_err(|err| store_err(store, err))?;
+        }
+        drop(registered);
+
+        self.stores.push(Box::new(fs));
+        Ok(store)
+    }
+
+    /// Set which store new files are added to
+    pub fn set_default_store(&mut self, store: usize) {
+        self.default = store;
+    }
+
+    /// Get the store with an index
+    pub fn store(&self, store: usize) -> Option<&dyn DynFileSystem> {
+        self.stores.get(store).map(|fs| &**fs)
+    }
+
+    /// Get how many stores there are
+    pub fn store_count(&self) -> usize {
+        self.stores.len()
+    }
+
+    /// Split an ID into the index of its store and the file's ID within that store
+    pub fn split_id(id: FileId) -> (usize, FileId) {
+        let id = id.into_u64_unchecked();
+        // Always fits, as it's at most 16 bits
+        #[allow(clippy::cast_possible_truncation)]
+        let store = (id >> STORE_SHIFT) as usize;
+        (store, FileId::from_u64_unchecked(id & LOCAL_MASK))
+    }
+
+    /// Join the index of a store and a file's ID within it into an ID, if the file's ID is small
+    /// enough
+    pub fn join_id(store: usize, id: FileId) -> Option<FileId> {
+        let fits = id.into_u64_unchecked() <= LOCAL_MASK && (store as u64) >> 16 == 0;
+        fits.then(|| join_unchecked(store, id))
+    }
+
+    fn join(store: usize, id: FileId) -> Result<FileId, Error> {
+        MultiFs::join_id(store, id).ok_or(Error::IdOutOfRange(id))
+    }
+
+    /// Get the store holding a file and its ID within it
+    fn route(&self, id: FileId) -> Result<(usize, &dyn DynFileSystem, FileId), Error> {
+        let (store, local) = MultiFs::split_id(id);
+        match self.store(store) {
+            Some(fs) => Ok((store, fs, local)),
+            None => Err(Error::FileNotFound(id)),
+        }
+    }
+
+    /// Run a lookup against the store holding a file
+    fn with_store<'a, T>(
+        &'a self,
+        id: FileId,
+        f: impl FnOnce(&'a dyn DynFileSystem, FileId) -> Result<T, DynError>,
+    ) -> Result<T, Error> {
+        let (store, fs, local) = self.route(id)?;
+        f(fs, local).map_err(|err| store_err(store, err))
+    }
+
+    /// Run a change against every store in turn
+    fn each_store(
+        &self,
+        mut f: impl FnMut(&dyn DynFileSystem) -> Result<(), DynError>,
+    ) -> Result<(), Error> {
+        for (store, fs) in self.stores.iter().enumerate() {
+            f(&**fs).map_err(|err| store_err(store, err))?;
+        }
+        Ok(())
+    }
+
+    fn default_store(&self) -> Result<&dyn DynFileSystem, Error> {
+        self.store(self.default)
+            .ok_or(Error::UnknownStore(self.default))
+    }
+
+    /// List the trash of every store, with its IDs joined to the store
+    fn trash(&self) -> Result<String, Error> {
+        let mut out = String::new();
+        for (store, fs) in self.stores.iter().enumerate() {
+            let trash = fs
+                .dyn_special(SpecialFile::Trash)
+                .map_err(|err| store_err(store, err))?;
+            let trash = String::from_utf8_lossy(trash.data());
+            for id in trash
+                .lines()
+                .filter_map(|id| u64::from_str_radix(id, 16).ok())
+            {
+                let id = MultiFs::join(store, FileId::from_u64_unchecked(id))?;
+                let _ = writeln!(out, "{:016X}", id.into_u64_unchecked());
+            }
+        }
+        Ok(out)
+    }
+}
+
+impl Default for MultiFs {
+    fn default() -> MultiFs {
+        MultiFs::new()
+    }
+}
+
+/// Run a closure inside a transaction of every store, nested one inside the next
+fn nest(
+    stores: &[Box<dyn DynFileSystem>],
+    f: &mut dyn FnMut() -> Result<(), DynError>,
+) -> Result<(), DynError> {
+    match stores.split_first() {
+        Some((fs, rest)) => fs.dyn_transaction(&mut || nest(rest, f)),
+        None => f(),
+    }
+}
+
+impl FileSystemRead for MultiFs {
+    type Error = Error;
+    type SearchIter<'a, P>
+        = SearchIter<'a>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = tags.to_predicate();
+        let mut out = Vec::new();
+        for (store, fs) in self.stores.iter().enumerate() {
+            for id in fs
+                .dyn_search_tags(&pattern)
+                .map_err(|err| store_err(store, err))?
+            {
+                out.push(MultiFs::join(store, id)?);
+            }
+        }
+        Ok(out)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags.to_predicate(),
+            store: 0,
+            inner: None,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let (_, tags, data) = self
+            .with_store(id, DynFileSystem::dyn_get_info)?
+            .into_parts();
+        Ok(FileInfo { id, tags, data })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_get_tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_get_stored_tags)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_get_data)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.with_store(id, |fs, id| fs.dyn_read_range(id, range))
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_get_metadata)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let mut out = None;
+        for (store, fs) in self.stores.iter().enumerate() {
+            if let Some(id) = fs.dyn_last_id().map_err(|err| store_err(store, err))? {
+                out = Some(MultiFs::join(store, id)?);
+            }
+        }
+        Ok(out)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = pattern.to_predicate();
+        let mut out = BTreeMap::new();
+        for (store, fs) in self.stores.iter().enumerate() {
+            let counts = fs
+                .dyn_tag_counts(&pattern)
+                .map_err(|err| store_err(store, err))?;
+            for (tag, count) in counts {
+                *out.entry(tag).or_insert(0) += count;
+            }
+        }
+        Ok(out)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = pattern.to_predicate();
+        let mut out = 0;
+        for (store, fs) in self.stores.iter().enumerate() {
+            out += fs
+                .dyn_count_tags(&pattern)
+                .map_err(|err| store_err(store, err))?;
+        }
+        Ok(out)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let pattern = pattern.to_predicate();
+        for (store, fs) in self.stores.iter().enumerate() {
+            if fs
+                .dyn_any_match(&pattern)
+                .map_err(|err| store_err(store, err))?
+            {
+                return Ok(true);
+            }
+        }
+        Ok(false)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        // Earlier stores have lower IDs, so the first match is the lowest
+        for (store, fs) in self.stores.iter().enumerate() {
+            if let Some(id) = fs
+                .dyn_find_by_hash(hash)
+                .map_err(|err| store_err(store, err))?
+            {
+                return MultiFs::join(store, id).map(Some);
+            }
+        }
+        Ok(None)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        let mut out = BTreeSet::new();
+        for (store, fs) in self.stores.iter().enumerate() {
+            out.extend(fs.dyn_list_groups().map_err(|err| store_err(store, err))?);
+        }
+        Ok(out)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        let mut out = BTreeSet::new();
+        for (store, fs) in self.stores.iter().enumerate() {
+            out.extend(
+                fs.dyn_list_tags(group)
+                    .map_err(|err| store_err(store, err))?,
+            );
+        }
+        Ok(out)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_list_versions)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.with_store(id, |fs, id| fs.dyn_get_version(id, version))
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        match file {
+            // Each store keeps its own trash, of IDs local to it
+            SpecialFile::Trash => Ok(FileInfo::new(file.id(), [], self.trash()?.into_bytes())),
+            _ => crate::generate_special(self, file),
+        }
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.default_store()?
+            .dyn_special_data(file)
+            .map_err(|err| store_err(self.default, err))
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl FileSystemWrite for MultiFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let fs = self.default_store()?;
+        let local = fs
+            .dyn_add_file(data, &tags.into_iter().collect::<Vec<_>>())
+            .map_err(|err| store_err(self.default, err))?;
+        let id = MultiFs::join(self.default, local).inspect_err(|_| {
+            let _ = fs.dyn_remove_file(local);
+        })?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let (store, local) = MultiFs::split_id(id);
+        let fs = self.store(store).ok_or(Error::UnknownStore(store))?;
+        fs.dyn_add_file_with_id(local, data, &tags.into_iter().collect::<Vec<_>>())
+            .map_err(|err| store_err(store, err))?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| tags.into_iter().collect::<Vec<_>>());
+        self.with_store(id, |fs, id| fs.dyn_edit_file(id, data, tags.as_deref()))?;
+
+        if data.is_some() {
+            self.subscribers.emit(Event::FileEdited(id));
+        }
+        if tags.is_some() {
+            self.subscribers.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_remove_file)?;
+        self.subscribers.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // The stores need one of their own errors to undo changes, so a stand-in is returned to
+        // them, and the real error returned once they're done
+        let mut f = Some(f);
+        let mut out = None;
+        let mut err = None;
+        let res = nest(&self.stores, &mut || {
+            if let Some(f) = f.take() {
+                match f(self) {
+                    Ok(val) => out = Some(val),
+                    Err(inner) => {
+                        err = Some(inner);
+                        return Err(DynError::FileNotFound(FileId::from_u64_unchecked(0)));
+                    }
+                }
+            }
+            Ok(())
+        });
+        match (res, err) {
+            (Err(_), Some(err)) => Err(err),
+            (Err(err), None) => Err(Error::Store(err)),
+            (Ok(()), _) => Ok(out.expect("Transaction closure should have run")),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        self.with_store(id, |fs, id| fs.dyn_add_tags(id, &tags))?;
+        self.subscribers.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        self.with_store(id, |fs, id| fs.dyn_remove_tags(id, &tags))?;
+        self.subscribers.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        let ids = FileSystemRead::search_tags(self, old.clone())?;
+        self.each_store(|fs| fs.dyn_rename_tag(old, new.clone()))?;
+        for id in ids {
+            self.subscribers.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        let ids = FileSystemRead::search_tags(self, TagPredicate::group(old.clone()))?;
+        self.each_store(|fs| fs.dyn_rename_group(old, new.clone()))?;
+        for id in ids {
+            self.subscribers.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        self.with_store(id, |fs, id| fs.dyn_revert(id, version))?;
+        self.subscribers.emit(Event::FileEdited(id));
+        Ok(())
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.default_store()?
+            .dyn_set_special_data(file, data)
+            .map_err(|err| store_err(self.default, err))
+    }
+}
+
+impl ProvidedTags for MultiFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        let provider: Arc<dyn TagProvider> = Arc::new(provider);
+        self.each_store(|fs| {
+            fs.dyn_register_provider(group.clone(), Box::new(Shared(Arc::clone(&provider))))
+        })?;
+        self.registered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .providers
+            .push((group, provider));
+        Ok(())
+    }
+}
+
+impl InferredTags for MultiFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        let inferrer: Arc<dyn TagInferrer> = Arc::new(inferrer);
+        self.each_store(|fs| fs.dyn_register_inferrer(Box::new(Shared(Arc::clone(&inferrer)))))?;
+        self.registered
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .inferrers
+            .push(inferrer);
+        Ok(())
+    }
+}
+
+impl StreamRead for MultiFs {
+    type Reader<'a>
+        = Box<dyn Read + 'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.with_store(id, DynFileSystem::dyn_read_file)
+    }
+}
+
+impl StreamWrite for MultiFs {
+    type Writer<'a>
+        = Writer<'a>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let inner = self
+            .default_store()?
+            .dyn_create_file(&tags.into_iter().collect::<Vec<_>>())
+            .map_err(|err| store_err(self.default, err))?;
+        Ok(Writer {
+            fs: self,
+            store: self.default,
+            inner,
+        })
+    }
+}
+
+/// A lazy search over a [`MultiFs`], searching each store in turn
+pub struct SearchIter<'a> {
+    fs: &'a MultiFs,
+    pattern: TagPredicate,
+    store: usize,
+    inner: Option<DynSearchIter<'a>>,
+}
+
+impl Iterator for SearchIter<'_> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(inner) = &mut self.inner {
+                match inner.next() {
+                    Some(Ok(id)) => return Some(MultiFs::join(self.store, id)),
+                    Some(Err(err)) => return Some(Err(store_err(self.store, err))),
+                    None => {
+                        self.inner = None;
+                        self.store += 1;
+                    }
+                }
+            }
+            let fs = self.fs.store(self.store)?;
+            self.inner = Some(fs.dyn_search_tags_iter(&self.pattern));
+        }
+    }
+}
+
+/// A handle streaming data into a new file of a [`MultiFs`], which is a handle of the default
+/// store
+pub struct Writer<'a> {
+    fs: &'a MultiFs,
+    store: usize,
+    inner: Box<dyn DynFileWriter + 'a>,
+}
+
+impl io::Write for Writer<'_> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl FileWriter for Writer<'_> {
+    type Error = Error;
+
+    fn commit(self) -> Result<FileId, Self::Error> {
+        let Writer { fs, store, inner } = self;
+        let local = inner.commit().map_err(|err| store_err(store, err))?;
+        let id = MultiFs::join(store, local)?;
+        fs.subscribers.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Write;
+
+    use super::{Error, MultiFs};
+    use crate::{
+        FileSystemRead, FileSystemWrite, FileWriter, Group, InMemoryFs, ProvidedTags, StreamWrite,
+        Tag,
+    };
+
+    #[test]
+    fn test_multi() {
+        let first = InMemoryFs::new();
+        let a = first.add_file(&[1], [Tag::named("a")]).unwrap();
+        let second = InMemoryFs::new();
+        let b = second.add_file(&[2], [Tag::named("a")]).unwrap();
+        second.add_file(&[3], [Tag::named("c")]).unwrap();
+
+        let mut fs = MultiFs::new();
+        fs.add_store(first).unwrap();
+        fs.add_store(second).unwrap();
+
+        // Both stores gave their file the same ID, but they're told apart
+        let b = MultiFs::join_id(1, b).unwrap();
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a, b]);
+        assert_eq!(
+            fs.search_tags_iter(Tag::named("a"))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            [a, b]
+        );
+        assert_eq!(fs.get_info(b).unwrap().data(), &[2]);
+        assert_eq!(fs.get_info(b).unwrap().id(), b);
+        assert_eq!(fs.tag_counts(Tag::named("a")).unwrap()[&Tag::named("a")], 2);
+        assert!(matches!(
+            fs.get_info(MultiFs::join_id(2, a).unwrap()),
+            Err(Error::FileNotFound(_))
+        ));
+
+        // Changes go to the store holding the file
+        fs.edit_file(b, Some(&[4]), None::<[Tag; 0]>).unwrap();
+        assert_eq!(
+            fs.store(1)
+                .unwrap()
+                .dyn_get_data(MultiFs::split_id(b).1)
+                .unwrap(),
+            [4]
+        );
+        let c = fs.add_file(&[5], []).unwrap();
+        assert_eq!(MultiFs::split_id(c).0, 0);
+
+        fs.set_default_store(1);
+        let mut writer = fs.create_file([Tag::named("a")]).unwrap();
+        writer.write_all(&[6]).unwrap();
+        let d = writer.commit().unwrap();
+        assert_eq!(MultiFs::split_id(d).0, 1);
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a, b, d]);
+    }
+
+    #[test]
+    fn test_multi_transaction() {
+        let mut fs = MultiFs::new();
+        fs.add_store(InMemoryFs::new()).unwrap();
+        fs.add_store(InMemoryFs::new()).unwrap();
+        fs.register_provider(Group::custom("len"), |data: &[u8]| {
+            vec![Tag::named(data.len().to_string())]
+        })
+        .unwrap();
+        let a = fs.add_file(&[1], []).unwrap();
+
+        let res = fs.transaction(|fs| {
+            fs.add_file_with_id(MultiFs::join_id(1, a).unwrap(), &[2], [])?;
+            fs.remove_file(a)?;
+            fs.remove_file(a)
+        });
+        assert!(matches!(res, Err(Error::FileNotFound(id)) if id == a));
+        assert_eq!(
+            fs.search_tags(Tag::new(Group::custom("len"), "1")).unwrap(),
+            [a]
+        );
+    }
+}
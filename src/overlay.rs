@@ -0,0 +1,768 @@
+//! Layered filesystem, with changes to a read-only lower TBF kept in an upper one
+
+use alloc::collections::BTreeSet;
+use core::fmt;
+use core::iter::Fuse;
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+
+use crate::error::{is_not_found, ErrorKind};
+use crate::events::{Event, Subscribers};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::Metadata;
+use crate::provider::{provide_tags, Providers};
+use crate::{
+    exists, BufferedWriter, Error as _, FileId, FileInfo, FileSystem, FileSystemRead,
+    FileSystemWrite, Group, InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag,
+    TagInferrer, TagPattern, TagPredicate, TagProvider,
+};
+
+/// Group of the tag marking a file in the upper filesystem as a whiteout, hiding the file with
+/// the same ID in the lower filesystem
+const WHITEOUT_GROUP: &str = "tbf-whiteout";
+
+fn whiteout_tag() -> Tag {
+    Tag::new(WHITEOUT_GROUP, "removed")
+}
+
+/// Error for an overlay filesystem
+#[derive(Debug)]
+pub enum Error<U, L> {
+    /// The upper filesystem returned an error
+    Upper(U),
+    /// The lower filesystem returned an error
+    Lower(L),
+}
+
+impl<U: crate::Error, L: crate::Error> crate::Error for Error<U, L> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Upper(U::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Upper(U::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Upper(U::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Upper(U::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Upper(err) => err.generic_kind(),
+            Error::Lower(err) => err.generic_kind(),
+        }
+    }
+}
+
+impl<U: fmt::Display, L: fmt::Display> fmt::Display for Error<U, L> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Upper(err) => write!(f, "upper filesystem: {err}"),
+            Error::Lower(err) => write!(f, "lower filesystem: {err}"),
+        }
+    }
+}
+
+impl<U: std::error::Error, L: std::error::Error> std::error::Error for Error<U, L> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Upper(err) => std::error::Error::source(err),
+            Error::Lower(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// Which layer of an overlay a file is found in
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layer {
+    Upper,
+    Lower,
+}
+
+/// A filesystem made of two layers. Files are looked up in the upper filesystem first, falling
+/// through to the lower one, which is never changed. Every change is made to the upper layer:
+/// editing a file which is only in the lower layer first copies it up, and removing one leaves a
+/// whiteout in the upper layer hiding it. This allows sharing a base set of files, such as a
+/// dataset, between several users who each keep their own changes to it.
+///
+/// New files are given IDs past the last of either layer, so they never clash with files added
+/// to the lower layer before them. Whiteouts are files in the `tbf-whiteout` group, which are
+/// hidden from lookups and searches, so the upper filesystem shouldn't be edited directly while
+/// wrapped. Providers and inferrers registered with the overlay are kept by it, rather than
+/// passed on to either layer.
+pub struct OverlayFs<U, L> {
+    upper: U,
+    lower: L,
+    whiteouts: RwLock<BTreeSet<FileId>>,
+    /// Held while picking a new file's ID and adding it
+    alloc: Mutex<()>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    subscribers: Subscribers,
+}
+
+impl<U: FileSystem, L: FileSystemRead> OverlayFs<U, L> {
+    /// Layer an upper filesystem over a lower one. The upper filesystem should either be empty or
+    /// previously have been the upper layer over the same lower filesystem.
+    pub fn new(upper: U, lower: L) -> Result<OverlayFs<U, L>, U::Error> {
+        let out = OverlayFs {
+            upper,
+            lower,
+            whiteouts: RwLock::new(BTreeSet::new()),
+            alloc: Mutex::new(()),
+            providers: RwLock::new(Providers::new()),
+            inferrers: RwLock::new(Inferrers::new()),
+            subscribers: Subscribers::new(),
+        };
+        out.reload()?;
+        Ok(out)
+    }
+
+    /// Get the upper filesystem, holding every change made
+    pub fn upper(&self) -> &U {
+        &self.upper
+    }
+
+    /// Get the lower filesystem
+    pub fn lower(&self) -> &L {
+        &self.lower
+    }
+
+    /// Take apart the overlay, into its upper and lower filesystems
+    pub fn into_parts(self) -> (U, L) {
+        (self.upper, self.lower)
+    }
+
+    /// Check whether a file has been changed from how it is in the lower layer. This is true of
+    /// files which have been edited, removed, or only exist in the upper layer.
+    pub fn is_modified(&self, id: FileId) -> Result<bool, Error<U::Error, L::Error>> {
+        Ok(self.is_whiteout(id) || exists(&self.upper, id).map_err(Error::Upper)?)
+    }
+
+    /// Throw away any changes made to a file, so it's seen as it is in the lower layer again.
+    /// Files which were added to the upper layer, and so aren't in the lower one, are removed.
+    pub fn discard_changes(&self, id: FileId) -> Result<(), Error<U::Error, L::Error>> {
+        if !self.is_modified(id)? {
+            return Ok(());
+        }
+        let whiteout = self.is_whiteout(id);
+        let in_lower = exists(&self.lower, id).map_err(Error::Lower)?;
+        self.upper.remove_file(id).map_err(Error::Upper)?;
+        self.write_whiteouts().remove(&id);
+
+        if whiteout {
+            self.subscribers.emit(Event::FileAdded(id));
+        } else if in_lower {
+            self.subscribers.emit(Event::FileEdited(id));
+            self.subscribers.emit(Event::TagsChanged(id));
+        } else {
+            self.subscribers.emit(Event::FileRemoved(id));
+        }
+        Ok(())
+    }
+
+    fn read_whiteouts(&self) -> RwLockReadGuard<'_, BTreeSet<FileId>> {
+        self.whiteouts
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn write_whiteouts(&self) -> RwLockWriteGuard<'_, BTreeSet<FileId>> {
+        self.whiteouts
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_whiteout(&self, id: FileId) -> bool {
+        self.read_whiteouts().contains(&id)
+    }
+
+    /// Rebuild the set of whiteouts from the tags in the upper filesystem
+    fn reload(&self) -> Result<(), U::Error> {
+        let whiteouts = self
+            .upper
+            .search_tags(TagPredicate::group(Group::custom(WHITEOUT_GROUP)))?;
+        *self.write_whiteouts() = whiteouts.into_iter().collect();
+        Ok(())
+    }
+
+    /// Find which layer holds a file
+    fn layer(&self, id: FileId) -> Result<Layer, Error<U::Error, L::Error>> {
+        if self.is_whiteout(id) {
+            Err(Error::Upper(U::Error::file_not_found(id)))
+        } else if exists(&self.upper, id).map_err(Error::Upper)? {
+            Ok(Layer::Upper)
+        } else if exists(&self.lower, id).map_err(Error::Lower)? {
+            Ok(Layer::Lower)
+        } else {
+            Err(Error::Lower(L::Error::file_not_found(id)))
+        }
+    }
+
+    /// Look a file up in the upper layer, falling through to the lower one if it isn't there
+    fn lookup<'a, T>(
+        &'a self,
+        id: FileId,
+        upper: impl FnOnce(&'a U) -> Result<T, U::Error>,
+        lower: impl FnOnce(&'a L) -> Result<T, L::Error>,
+    ) -> Result<T, Error<U::Error, L::Error>> {
+        if self.is_whiteout(id) {
+            return Err(Error::Upper(U::Error::file_not_found(id)));
+        }
+        match upper(&self.upper) {
+            Err(err) if is_not_found(&err) => lower(&self.lower).map_err(Error::Lower),
+            out => out.map_err(Error::Upper),
+        }
+    }
+
+    /// Get a file's tags as stored, without any from the overlay's providers
+    fn stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Error<U::Error, L::Error>> {
+        self.lookup(id, |fs| fs.get_tags(id), |fs| fs.get_tags(id))
+    }
+
+    fn inferred_tags<I>(&self, data: &[u8], tags: I) -> Vec<Tag>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<Vec<_>>();
+        tags.extend(infer_tags(
+            &self
+                .inferrers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        ));
+        tags
+    }
+}
+
+impl<U: FileSystem, L: FileSystemRead> FileSystemRead for OverlayFs<U, L> {
+    type Error = Error<U::Error, L::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, U, L, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        let has_providers = !self
+            .providers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_empty();
+
+        // Provided tags depend on the data, so with any providers every file is found and checked
+        // here instead of by the layers
+        let (pattern, filter) = if has_providers {
+            (TagPredicate::And(Vec::new()), Some(tags))
+        } else {
+            (tags.to_predicate(), None)
+        };
+        SearchIter {
+            fs: self,
+            upper: self.upper.search_tags_iter(pattern.clone()).fuse(),
+            lower: self.lower.search_tags_iter(pattern).fuse(),
+            next_upper: None,
+            next_lower: None,
+            filter,
+            done: false,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let (id, mut tags, data) = self
+            .lookup(id, |fs| fs.get_info(id), |fs| fs.get_info(id))?
+            .into_parts();
+        tags.extend(provide_tags(
+            &self
+                .providers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            &data,
+        ));
+        Ok(FileInfo { id, tags, data })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let mut tags = self.stored_tags(id)?;
+        let providers = self
+            .providers
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        if !providers.is_empty() {
+            tags.extend(provide_tags(&providers, &self.get_data(id)?));
+        }
+        Ok(tags)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.stored_tags(id)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.lookup(id, |fs| fs.get_data(id), |fs| fs.get_data(id))
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let upper = range.clone();
+        self.lookup(
+            id,
+            |fs| fs.read_range(id, upper),
+            |fs| fs.read_range(id, range),
+        )
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.lookup(id, |fs| fs.get_metadata(id), |fs| fs.get_metadata(id))
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let upper = self.upper.last_id().map_err(Error::Upper)?;
+        let lower = self.lower.last_id().map_err(Error::Lower)?;
+        Ok(upper.max(lower))
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.lookup(id, |fs| fs.list_versions(id), |fs| fs.list_versions(id))
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.lookup(
+            id,
+            |fs| fs.get_version(id, version),
+            |fs| fs.get_version(id, version),
+        )
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        // Stored special files fall through like file data, until they're set on the overlay
+        let data = self.upper.special_data(file).map_err(Error::Upper)?;
+        if data.is_empty() {
+            self.lower.special_data(file).map_err(Error::Lower)
+        } else {
+            Ok(data)
+        }
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl<U: FileSystem, L: FileSystemRead> FileSystemWrite for OverlayFs<U, L> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.inferred_tags(data, tags);
+
+        let alloc = self.alloc.lock().unwrap_or_else(PoisonError::into_inner);
+        let id = self
+            .last_id()?
+            .map_or(256, |id| id.into_u64_unchecked() + 1);
+        let id = FileId::from_u64_unchecked(id);
+        self.upper
+            .add_file_with_id(id, data, tags)
+            .map_err(Error::Upper)?;
+        drop(alloc);
+
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = self.inferred_tags(data, tags);
+
+        let alloc = self.alloc.lock().unwrap_or_else(PoisonError::into_inner);
+        if self.is_whiteout(id) {
+            // The ID is free again, so the whiteout is replaced
+            self.upper
+                .edit_file(id, Some(data), Some(tags))
+                .map_err(Error::Upper)?;
+            self.write_whiteouts().remove(&id);
+        } else if exists(&self.lower, id).map_err(Error::Lower)? {
+            return Err(Error::Lower(L::Error::already_exists(id)));
+        } else {
+            self.upper
+                .add_file_with_id(id, data, tags)
+                .map_err(Error::Upper)?;
+        }
+        drop(alloc);
+
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let data_changed = data.is_some();
+        let tags_changed = tags.is_some();
+
+        match self.layer(id)? {
+            Layer::Upper => self.upper.edit_file(id, data, tags).map_err(Error::Upper)?,
+            Layer::Lower => {
+                // Copy the file up, with the changes already made
+                let (_, old_tags, old_data) =
+                    self.lower.get_info(id).map_err(Error::Lower)?.into_parts();
+                let tags = match tags {
+                    Some(tags) => tags.into_iter().collect(),
+                    None => old_tags,
+                };
+                self.upper
+                    .add_file_with_id(id, data.unwrap_or(&old_data), tags)
+                    .map_err(Error::Upper)?;
+            }
+        }
+
+        if data_changed {
+            self.subscribers.emit(Event::FileEdited(id));
+        }
+        if tags_changed {
+            self.subscribers.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        match self.layer(id)? {
+            Layer::Upper => {
+                if exists(&self.lower, id).map_err(Error::Lower)? {
+                    self.upper
+                        .edit_file(id, Some(&[]), Some([whiteout_tag()]))
+                        .map_err(Error::Upper)?;
+                    self.write_whiteouts().insert(id);
+                } else {
+                    self.upper.remove_file(id).map_err(Error::Upper)?;
+                }
+            }
+            Layer::Lower => {
+                self.upper
+                    .add_file_with_id(id, &[], [whiteout_tag()])
+                    .map_err(Error::Upper)?;
+                self.write_whiteouts().insert(id);
+            }
+        }
+        self.subscribers.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // Only the upper filesystem is changed, but it needs one of its own errors to undo
+        // changes, so a stand-in is returned to it for errors from the lower one
+        let mut lower_err = None;
+        let out = self.upper.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Upper(err)) => Err(err),
+            Err(Error::Lower(err)) => {
+                lower_err = Some(err);
+                Err(U::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        if out.is_err() {
+            self.reload().map_err(Error::Upper)?;
+        }
+        match (out, lower_err) {
+            (Err(_), Some(err)) => Err(Error::Lower(err)),
+            (out, _) => out.map_err(Error::Upper),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        // Provided tags aren't stored, so the stored ones are edited rather than `get_info`'s
+        let mut new = self.stored_tags(id)?;
+        new.extend(tags);
+        self.edit_file(id, None, Some(new))
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.stored_tags(id)?;
+        for tag in tags {
+            new.remove(&tag);
+        }
+        self.edit_file(id, None, Some(new))
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.upper
+            .set_special_data(file, data)
+            .map_err(Error::Upper)
+    }
+}
+
+impl<U: FileSystem, L: FileSystemRead> ProvidedTags for OverlayFs<U, L> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl<U: FileSystem, L: FileSystemRead> InferredTags for OverlayFs<U, L> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl<U: FileSystem + StreamRead, L: StreamRead> StreamRead for OverlayFs<U, L> {
+    type Reader<'a>
+        = Reader<'a, U, L>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.lookup(
+            id,
+            |fs| fs.read_file(id).map(Reader::Upper),
+            |fs| fs.read_file(id).map(Reader::Lower),
+        )
+    }
+}
+
+impl<U: FileSystem, L: FileSystemRead> StreamWrite for OverlayFs<U, L> {
+    type Writer<'a>
+        = Writer<'a, U, L>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+/// A lazy search over an [`OverlayFs`]. Both layers are searched, and their results merged in ID
+/// order, skipping files of the lower layer which are hidden by the upper one. With any
+/// providers registered, every file is checked in turn instead.
+pub struct SearchIter<'a, U: FileSystem, L: FileSystemRead, P: TagPattern + 'a> {
+    fs: &'a OverlayFs<U, L>,
+    upper: Fuse<U::SearchIter<'a, TagPredicate>>,
+    lower: Fuse<L::SearchIter<'a, TagPredicate>>,
+    next_upper: Option<FileId>,
+    next_lower: Option<FileId>,
+    filter: Option<P>,
+    done: bool,
+}
+
+impl<U: FileSystem, L: FileSystemRead, P: TagPattern> SearchIter<'_, U, L, P> {
+    /// Find the next result of each layer, if it isn't already known
+    fn fill(&mut self) -> Result<(), Error<U::Error, L::Error>> {
+        let fs = self.fs;
+        if self.next_upper.is_none() {
+            // Whiteouts are only there to hide lower files
+            self.next_upper = self
+                .upper
+                .find(|id| !matches!(id, Ok(id) if fs.is_whiteout(*id)))
+                .transpose()
+                .map_err(Error::Upper)?;
+        }
+        while self.next_lower.is_none() {
+            let Some(id) = self.lower.next().transpose().map_err(Error::Lower)? else {
+                break;
+            };
+            if !fs.is_modified(id)? {
+                self.next_lower = Some(id);
+            }
+        }
+        Ok(())
+    }
+
+    fn advance(&mut self) -> Result<Option<FileId>, Error<U::Error, L::Error>> {
+        while !self.done {
+            self.fill()?;
+            // The layers never both return the same ID, as it's skipped in the lower one
+            let id = match (self.next_upper, self.next_lower) {
+                (Some(upper), Some(lower)) if lower < upper => self.next_lower.take(),
+                (Some(_), _) => self.next_upper.take(),
+                (None, _) => self.next_lower.take(),
+            };
+            let Some(id) = id else {
+                self.done = true;
+                break;
+            };
+
+            match &self.filter {
+                Some(pattern) if !pattern.match_tags(self.fs.get_tags(id)?) => (),
+                _ => return Ok(Some(id)),
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<U: FileSystem, L: FileSystemRead, P: TagPattern> Iterator for SearchIter<'_, U, L, P> {
+    type Item = Result<FileId, Error<U::Error, L::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.advance() {
+            Ok(id) => id.map(Ok),
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A handle streaming data out of a file of an [`OverlayFs`], from whichever layer holds it
+pub enum Reader<'a, U: StreamRead + 'a, L: StreamRead + 'a> {
+    /// The file is in the upper layer
+    Upper(U::Reader<'a>),
+    /// The file is in the lower layer
+    Lower(L::Reader<'a>),
+}
+
+impl<U: StreamRead, L: StreamRead> io::Read for Reader<'_, U, L> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Reader::Upper(reader) => reader.read(buf),
+            Reader::Lower(reader) => reader.read(buf),
+        }
+    }
+}
+
+/// A handle streaming data into a new file of an [`OverlayFs`]. Data is buffered until the handle
+/// is flushed.
+pub type Writer<'a, U, L> = BufferedWriter<'a, OverlayFs<U, L>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{ImfsError, InMemoryFs};
+
+    fn lower() -> (InMemoryFs, FileId, FileId) {
+        let lower = InMemoryFs::new();
+        let a = lower.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = lower
+            .add_file(&[2], [Tag::named("a"), Tag::named("b")])
+            .unwrap();
+        (lower, a, b)
+    }
+
+    #[test]
+    fn test_overlay() {
+        let (lower, a, b) = lower();
+        let fs = OverlayFs::new(InMemoryFs::new(), lower).unwrap();
+
+        assert_eq!(fs.get_info(a).unwrap().data(), &[1]);
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a, b]);
+        assert!(!fs.is_modified(a).unwrap());
+
+        // New files don't clash with the lower layer
+        let c = fs.add_file(&[3], [Tag::named("b")]).unwrap();
+        assert!(c > b);
+        assert_eq!(fs.search_tags(Tag::named("b")).unwrap(), [b, c]);
+        assert!(matches!(
+            fs.add_file_with_id(a, &[], []),
+            Err(Error::Lower(ImfsError::AlreadyExists(_)))
+        ));
+
+        // Edits are copied up
+        fs.remove_tags(a, [Tag::named("a")]).unwrap();
+        fs.edit_file(b, Some(&[4]), None::<[Tag; 0]>).unwrap();
+        assert!(fs.is_modified(a).unwrap());
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [b]);
+        assert_eq!(fs.get_data(b).unwrap(), [4]);
+        assert_eq!(fs.lower().get_data(b).unwrap(), [2]);
+        assert_eq!(fs.lower().search_tags(Tag::named("a")).unwrap(), [a, b]);
+
+        // Removed lower files are hidden
+        fs.remove_file(b).unwrap();
+        assert!(matches!(fs.get_info(b), Err(Error::Upper(_))));
+        assert_eq!(
+            fs.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+            [a, c]
+        );
+        assert_eq!(fs.lower().get_data(b).unwrap(), [2]);
+
+        fs.discard_changes(a).unwrap();
+        fs.discard_changes(b).unwrap();
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [a, b]);
+        assert_eq!(fs.get_data(b).unwrap(), [2]);
+    }
+
+    #[test]
+    fn test_overlay_reload() {
+        let (lower, a, b) = lower();
+        let fs = OverlayFs::new(InMemoryFs::new(), lower).unwrap();
+        fs.remove_file(a).unwrap();
+
+        let (upper, lower) = fs.into_parts();
+        let fs = OverlayFs::new(upper, lower).unwrap();
+        assert_eq!(fs.search_tags(TagPredicate::And(Vec::new())).unwrap(), [b]);
+
+        let err = fs.transaction(|fs| {
+            fs.remove_file(b)?;
+            fs.add_file_with_id(a, &[5], [])?;
+            Err::<(), _>(Error::Upper(ImfsError::Poisoned))
+        });
+        assert!(err.is_err());
+        assert_eq!(fs.search_tags(TagPredicate::And(Vec::new())).unwrap(), [b]);
+    }
+
+    #[test]
+    fn test_overlay_providers() {
+        let (lower, a, b) = lower();
+        let fs = OverlayFs::new(InMemoryFs::new(), lower).unwrap();
+        fs.register_provider(Group::custom("len"), |data: &[u8]| {
+            vec![Tag::named(data.len().to_string())]
+        })
+        .unwrap();
+
+        fs.edit_file(a, Some(&[1, 1]), None::<[Tag; 0]>).unwrap();
+        assert_eq!(
+            fs.search_tags(Tag::new(Group::custom("len"), "1")).unwrap(),
+            [b]
+        );
+        assert_eq!(
+            fs.search_tags(Tag::new(Group::custom("len"), "2")).unwrap(),
+            [a]
+        );
+
+        // Provided tags aren't stored when tags are changed
+        fs.add_tags(b, [Tag::named("c")]).unwrap();
+        assert_eq!(fs.upper().get_tags(b).unwrap().len(), 3);
+    }
+}
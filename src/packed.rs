@@ -0,0 +1,1179 @@
+//! Implementation of a TBF packed into a single append-only container file, which can be opened
+//! read-only through a memory map
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::mem;
+use std::borrow::Cow;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Cursor, Read, Seek, SeekFrom, Write};
+use std::ops::{Bound, Range};
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use memmap2::Mmap;
+
+use crate::codec::{
+    read_id, read_string, read_tag, read_u32, read_u64, write_id, write_len, write_string,
+    write_tag, write_u64,
+};
+use crate::error::ErrorKind;
+use crate::events::{Event, Subscribers};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::{hash_data, now, Metadata};
+use crate::provider::{provide_tags, Providers};
+use crate::{
+    check_stored, BatchResult, BufferedWriter, FileId, FileInfo, FileSystemRead, FileSystemWrite,
+    Group, InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer,
+    TagPattern, TagProvider,
+};
+
+/// The start of every container, followed by the version of its format
+const MAGIC: &[u8; 8] = b"TBFPACK\0";
+const VERSION: u32 = 1;
+const HEADER_LEN: u64 = 12;
+/// The end of every container, after the offset and hash of its index
+const END_MAGIC: &[u8; 8] = b"TBFINDEX";
+const TRAILER_LEN: u64 = 48;
+/// Every record starts with its kind and the length of its body, and ends with a hash of both
+const RECORD_HEAD_LEN: u64 = 9;
+const HASH_LEN: u64 = 32;
+
+type Hash = [u8; 32];
+
+/// A new file, with its times, tags and data
+const RECORD_ADD: u8 = 1;
+/// New data for a file, with its modification time
+const RECORD_DATA: u8 = 2;
+/// New tags for a file
+const RECORD_TAGS: u8 = 3;
+/// A file being removed
+const RECORD_REMOVE: u8 = 4;
+/// New data for a special file, keyed by its name
+const RECORD_SPECIAL: u8 = 5;
+/// The start of a transaction, whose records are only kept once it commits
+const RECORD_BEGIN: u8 = 6;
+/// The end of a transaction, keeping its records
+const RECORD_COMMIT: u8 = 7;
+
+/// Error for a packed container filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// The requested file did not exist
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// The container was opened read-only
+    ReadOnly,
+    /// The container couldn't be read or written, or is malformed
+    IoError(io::Error),
+    /// A thread panic poisoned the state
+    Poisoned,
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Error {
+        Error::Poisoned
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl crate::error::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Self::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Self::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Self::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Self::IoError(io::ErrorKind::OutOfMemory.into())
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Self::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Self::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Self::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Self::ReadOnly => ErrorKind::ReadOnly,
+            Self::IoError(e) => ErrorKind::Source(e),
+            Self::Poisoned => ErrorKind::State,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::ReadOnly => write!(f, "container was opened read-only"),
+            Error::IoError(err) => write!(f, "container couldn't be accessed: {err}"),
+            Error::Poisoned => write!(f, "filesystem was poisoned by a thread panic"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::IoError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+/// A tag-based filesystem stored in a single container file, suited to shipping a fixed,
+/// tagged bundle of assets alongside an application.
+///
+/// The container is a header, a log of records which is only ever appended to, and an index of
+/// where the current data of every file is, rewritten after the records each time a change is
+/// committed. Outside of [`transaction`](FileSystemWrite::transaction), every change is
+/// committed as soon as it's made. If a crash leaves the index torn, the next open rebuilds it
+/// from the records, dropping any record which wasn't completely written, along with those of a
+/// transaction which never committed.
+///
+/// Replaced data stays in the container until it's [compacted](PackedFs::compact). A bundle is
+/// usually built with [`open`](PackedFs::open), compacted, then shipped and opened with
+/// [`open_read_only`](PackedFs::open_read_only), which maps the container into memory so file
+/// data is read without copying.
+///
+/// File IDs are never reused, and prior versions of file data aren't kept.
+pub struct PackedFs {
+    storage: Storage,
+    inner: RwLock<Inner>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    subscribers: Subscribers,
+}
+
+enum Storage {
+    /// Opened for writing, with the path kept so the container can be compacted
+    File { file: Mutex<File>, path: PathBuf },
+    /// Opened read-only and mapped into memory
+    Mapped(Mmap),
+}
+
+struct Inner {
+    state: State,
+    /// The state from before the running call to `transaction`, restored if it fails
+    rollback: Option<State>,
+    /// Events for changes made in the running transaction, sent once it commits
+    events: Vec<Event>,
+}
+
+/// Where everything in the container is, as held in its index
+#[derive(Clone)]
+struct State {
+    files: BTreeMap<FileId, Entry>,
+    special: BTreeMap<String, Span>,
+    next_id: u64,
+    /// The end of the last record, where the next one is written
+    end: u64,
+}
+
+#[derive(Clone)]
+struct Entry {
+    tags: BTreeSet<Tag>,
+    data: Span,
+    /// Nanoseconds since the Unix epoch
+    created: u64,
+    modified: u64,
+}
+
+/// A run of bytes in the container
+#[derive(Copy, Clone)]
+struct Span {
+    offset: u64,
+    len: u64,
+}
+
+impl PackedFs {
+    /// Open a container for reading and writing, creating it if it doesn't exist
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<PackedFs, Error> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(path)?;
+
+        let len = file.metadata()?.len();
+        let state = if len == 0 {
+            file.write_all(MAGIC)?;
+            file.write_all(&VERSION.to_le_bytes())?;
+            let state = State::new();
+            write_index(&mut file, &state)?;
+            state
+        } else {
+            let (state, intact) = State::load(&mut file, len)?;
+            if !intact {
+                write_index(&mut file, &state)?;
+            }
+            state
+        };
+
+        Ok(PackedFs::with_storage(
+            Storage::File {
+                file: Mutex::new(file),
+                path: path.to_path_buf(),
+            },
+            state,
+        ))
+    }
+
+    /// Open a container read-only, mapping it into memory. Any change fails with
+    /// [`Error::ReadOnly`].
+    ///
+    /// The container must not be changed while it's open, including by another [`PackedFs`].
+    pub fn open_read_only<P: AsRef<Path>>(path: P) -> Result<PackedFs, Error> {
+        let file = File::open(path)?;
+        // SAFETY: Containers opened read-only are expected to be sealed, so the mapped file
+        //         is never modified, short of another program changing it directly.
+        let map = unsafe { Mmap::map(&file)? };
+        let (state, _) = State::load(&mut Cursor::new(&*map), map.len() as u64)?;
+        Ok(PackedFs::with_storage(Storage::Mapped(map), state))
+    }
+
+    fn with_storage(storage: Storage, state: State) -> PackedFs {
+        PackedFs {
+            storage,
+            inner: RwLock::new(Inner {
+                state,
+                rollback: None,
+                events: Vec::new(),
+            }),
+            providers: RwLock::new(BTreeMap::new()),
+            inferrers: RwLock::new(Vec::new()),
+            subscribers: Subscribers::new(),
+        }
+    }
+
+    /// Whether the container was opened with [`open_read_only`](PackedFs::open_read_only)
+    pub fn is_read_only(&self) -> bool {
+        matches!(self.storage, Storage::Mapped(_))
+    }
+
+    /// Get the data of a file, borrowed straight from the map if the container was opened
+    /// read-only, otherwise read into a new buffer
+    pub fn get_data_ref(&self, id: FileId) -> Result<Cow<'_, [u8]>, Error> {
+        let span = self.read()?.state.entry(id)?.data;
+        self.read_span(span)
+    }
+
+    /// The size of the container, including data which has been replaced or removed
+    pub fn container_size(&self) -> Result<u64, Error> {
+        let state = &self.read()?.state;
+        Ok(state.end + state.encode_index()?.len() as u64 + TRAILER_LEN)
+    }
+
+    /// Rewrite the container with only the current data of each file, dropping everything
+    /// which has been replaced or removed. The new container is written alongside the old one
+    /// and renamed over it once complete, so a crash leaves one or the other intact.
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let Storage::File { file, path } = &mut self.storage else {
+            return Err(Error::ReadOnly);
+        };
+        let file = file.get_mut()?;
+        let state = &mut self.inner.get_mut()?.state;
+
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".compact");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        let mut out = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&tmp_path)?;
+        out.write_all(MAGIC)?;
+        out.write_all(&VERSION.to_le_bytes())?;
+
+        let mut packed = State::new();
+        packed.next_id = state.next_id;
+        for (&id, entry) in &state.files {
+            let data = read_file_span(file, entry.data)?;
+            let head = encode_add(id, entry.created, entry.modified, &entry.tags)?;
+            let offset = append(&mut out, &mut packed, RECORD_ADD, &head, &data)?;
+            packed.files.insert(
+                id,
+                Entry {
+                    data: Span::new(offset, &data),
+                    ..entry.clone()
+                },
+            );
+        }
+        for (name, &span) in &state.special {
+            let data = read_file_span(file, span)?;
+            let mut head = Vec::new();
+            write_string(&mut head, name)?;
+            let offset = append(&mut out, &mut packed, RECORD_SPECIAL, &head, &data)?;
+            packed
+                .special
+                .insert(name.clone(), Span::new(offset, &data));
+        }
+        write_index(&mut out, &packed)?;
+        out.sync_all()?;
+        drop(out);
+
+        fs::rename(&tmp_path, &*path)?;
+        // Sync the directory too, so the rename itself can't be lost
+        #[cfg(unix)]
+        if let Some(dir) = path.parent() {
+            File::open(dir)?.sync_all()?;
+        }
+        *file = OpenOptions::new().read(true).write(true).open(&*path)?;
+        *state = packed;
+        Ok(())
+    }
+
+    fn read(&self) -> Result<RwLockReadGuard<'_, Inner>, Error> {
+        Ok(self.inner.read()?)
+    }
+
+    fn read_span(&self, span: Span) -> Result<Cow<'_, [u8]>, Error> {
+        match &self.storage {
+            Storage::File { file, .. } => Ok(Cow::Owned(read_file_span(&mut *file.lock()?, span)?)),
+            Storage::Mapped(map) => {
+                let start = usize::try_from(span.offset).map_err(|_| malformed())?;
+                let len = usize::try_from(span.len).map_err(|_| malformed())?;
+                let data = start
+                    .checked_add(len)
+                    .and_then(|end| map.get(start..end))
+                    .ok_or_else(malformed)?;
+                Ok(Cow::Borrowed(data))
+            }
+        }
+    }
+
+    /// Run a closure making a change to the container. It gets the file to append records to,
+    /// and the state to update to match. The index is written once it succeeds, unless a
+    /// transaction is running.
+    fn with_write<T>(
+        &self,
+        f: impl FnOnce(&mut File, &mut State) -> Result<T, Error>,
+    ) -> Result<T, Error> {
+        let Storage::File { file, .. } = &self.storage else {
+            return Err(Error::ReadOnly);
+        };
+        let mut inner = self.write()?;
+        let mut file = file.lock()?;
+        let out = f(&mut file, &mut inner.state)?;
+        if inner.rollback.is_none() {
+            write_index(&mut file, &inner.state)?;
+        }
+        Ok(out)
+    }
+
+    fn write(&self) -> Result<RwLockWriteGuard<'_, Inner>, Error> {
+        Ok(self.inner.write()?)
+    }
+
+    /// Send an event to subscribers, or hold it until the running transaction commits
+    fn emit(&self, event: Event) {
+        match self.inner.write() {
+            Ok(mut inner) if inner.rollback.is_some() => inner.events.push(event),
+            _ => self.subscribers.emit(event),
+        }
+    }
+}
+
+/// The running transaction of a [`PackedFs`]. If it's dropped without being ended, such as when
+/// its closure panics, its changes are undone.
+struct Transaction<'a> {
+    fs: &'a PackedFs,
+    file: &'a Mutex<File>,
+    ended: bool,
+}
+
+impl Transaction<'_> {
+    /// Commit the changes made in the transaction, or undo them, then write the index
+    fn end(&mut self, commit: bool) -> Result<(), Error> {
+        self.ended = true;
+        let mut inner = self.fs.write()?;
+        let mut file = self.file.lock()?;
+        let Some(state) = inner.rollback.take() else {
+            return Ok(());
+        };
+        let events = mem::take(&mut inner.events);
+
+        let committed = if commit {
+            append(&mut file, &mut inner.state, RECORD_COMMIT, &[], &[]).map(|_| ())
+        } else {
+            Ok(())
+        };
+        if !commit || committed.is_err() {
+            // Records past the old end are overwritten by the index, dropping them
+            inner.state = state;
+        }
+        write_index(&mut file, &inner.state)?;
+        committed?;
+        drop((inner, file));
+
+        if commit {
+            for event in events {
+                self.fs.subscribers.emit(event);
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Transaction<'_> {
+    fn drop(&mut self) {
+        if !self.ended {
+            let _ = self.end(false);
+        }
+    }
+}
+
+impl FileSystemRead for PackedFs {
+    type Error = Error;
+    type SearchIter<'a, P: TagPattern + 'a> = SearchIter<'a, P>;
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags,
+            cursor: Bound::Unbounded,
+            done: false,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let Entry { mut tags, data, .. } = self.read()?.state.entry(id)?.clone();
+        let data = self.read_span(data)?;
+        tags.extend(provide_tags(&*self.providers.read()?, &data));
+
+        Ok(FileInfo {
+            id,
+            tags,
+            data: Box::from(data),
+        })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        if !self.providers.read()?.is_empty() {
+            return Ok(self.get_info(id)?.tags);
+        }
+        Ok(self.read()?.state.entry(id)?.tags.clone())
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.read()?.state.entry(id)?.tags.clone())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        if !self.providers.read()?.is_empty() {
+            return Ok(self
+                .get_info_many(ids)?
+                .into_iter()
+                .map(|info| info.map(|info| info.tags))
+                .collect());
+        }
+        let inner = self.read()?;
+        Ok(ids
+            .iter()
+            .map(|&id| inner.state.entry(id).map(|entry| entry.tags.clone()))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.get_data_ref(id).map(Cow::into_owned)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let span = self.read()?.state.entry(id)?.data;
+        let len = usize::try_from(span.len).map_err(|_| malformed())?;
+        let range = crate::clamp_range(range, len);
+        let part = Span {
+            offset: span.offset + range.start as u64,
+            len: range.len() as u64,
+        };
+        self.read_span(part).map(Cow::into_owned)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let entry = self.read()?.state.entry(id)?.clone();
+        let data = self.read_span(entry.data)?;
+
+        Ok(Metadata {
+            created: from_nanos(entry.created),
+            modified: from_nanos(entry.modified),
+            size: entry.data.len,
+            hash: hash_data(&data),
+        })
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let next = self.read()?.state.next_id;
+        if next > 256 {
+            Ok(Some(FileId::from_u64_unchecked(next - 1)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self
+            .read()?
+            .state
+            .files
+            .range((Bound::Excluded(after), Bound::Unbounded))
+            .map(|(&id, _)| id)
+            .collect())
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        let span = self.read()?.state.special.get(file.name()).copied();
+        match span {
+            Some(span) => self.read_span(span).map(Cow::into_owned),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl FileSystemWrite for PackedFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.inferrers.read()?, data));
+
+        let id = self.with_write(|file, state| {
+            let id = FileId::from_u64_unchecked(state.next_id);
+            insert(file, state, id, data, tags)?;
+            Ok(id)
+        })?;
+        self.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if id.into_u64_unchecked() < 256 {
+            return Err(Error::AlreadyExists(id));
+        }
+
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.inferrers.read()?, data));
+
+        self.with_write(|file, state| {
+            if state.files.contains_key(&id) {
+                return Err(Error::AlreadyExists(id));
+            }
+            insert(file, state, id, data, tags)
+        })?;
+        self.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| tags.into_iter().collect::<BTreeSet<_>>());
+        self.with_write(|file, state| {
+            state.entry(id)?;
+            if let Some(data) = data {
+                set_data(file, state, id, data)?;
+            }
+            if let Some(tags) = &tags {
+                set_tags(file, state, id, tags.clone())?;
+            }
+            Ok(())
+        })?;
+
+        if data.is_some() {
+            self.emit(Event::FileEdited(id));
+        }
+        if tags.is_some() {
+            self.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.with_write(|file, state| {
+            let mut file_tags = state.entry(id)?.tags.clone();
+            file_tags.extend(tags);
+            set_tags(file, state, id, file_tags)
+        })?;
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.with_write(|file, state| {
+            let mut file_tags = state.entry(id)?.tags.clone();
+            for tag in tags {
+                file_tags.remove(&tag);
+            }
+            set_tags(file, state, id, file_tags)
+        })?;
+        self.emit(Event::TagsChanged(id));
+        Ok(())
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.with_write(|file, state| {
+            state.entry(id)?;
+            let mut head = Vec::new();
+            write_id(&mut head, id)?;
+            append(file, state, RECORD_REMOVE, &head, &[])?;
+            state.files.remove(&id);
+            Ok(())
+        })?;
+        self.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // Nothing can change in a read-only container, so there's nothing to roll back
+        let Storage::File { file, .. } = &self.storage else {
+            return f(self);
+        };
+        {
+            let mut inner = self.write()?;
+            if inner.rollback.is_some() {
+                drop(inner);
+                return f(self);
+            }
+            let state = inner.state.clone();
+            append(&mut *file.lock()?, &mut inner.state, RECORD_BEGIN, &[], &[])?;
+            inner.rollback = Some(state);
+        }
+
+        let mut transaction = Transaction {
+            fs: self,
+            file,
+            ended: false,
+        };
+        let out = f(self);
+        transaction.end(out.is_ok())?;
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        check_stored::<Self::Error>(file)?;
+        self.with_write(|out, state| {
+            let mut head = Vec::new();
+            write_string(&mut head, file.name())?;
+            let offset = append(out, state, RECORD_SPECIAL, &head, data)?;
+            state
+                .special
+                .insert(file.name().to_owned(), Span::new(offset, data));
+            Ok(())
+        })
+    }
+}
+
+impl ProvidedTags for PackedFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers.write()?.insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl InferredTags for PackedFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers.write()?.push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl StreamRead for PackedFs {
+    type Reader<'a> = Cursor<Cow<'a, [u8]>>;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.get_data_ref(id).map(Cursor::new)
+    }
+}
+
+impl StreamWrite for PackedFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if self.is_read_only() {
+            return Err(Error::ReadOnly);
+        }
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+impl Span {
+    /// The span of some data written at an offset
+    fn new(offset: u64, data: &[u8]) -> Span {
+        Span {
+            offset,
+            len: data.len() as u64,
+        }
+    }
+}
+
+impl State {
+    fn new() -> State {
+        State {
+            files: BTreeMap::new(),
+            special: BTreeMap::new(),
+            next_id: 256,
+            end: HEADER_LEN,
+        }
+    }
+
+    fn entry(&self, id: FileId) -> Result<&Entry, Error> {
+        self.files.get(&id).ok_or(Error::FileNotFound(id))
+    }
+
+    /// Read the state of a container from its index, or from its records if the index isn't
+    /// intact, which is returned alongside it
+    fn load<R: Read + Seek>(input: &mut R, len: u64) -> io::Result<(State, bool)> {
+        let mut magic = [0; 8];
+        input.seek(SeekFrom::Start(0))?;
+        input.read_exact(&mut magic).map_err(|_| malformed())?;
+        if magic != *MAGIC {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Not a TBF container",
+            ));
+        }
+        if read_u32(input).map_err(|_| malformed())? != VERSION {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Unsupported TBF container version",
+            ));
+        }
+
+        if let Some(state) = State::read_index(input, len)? {
+            return Ok((state, true));
+        }
+        State::scan(input, len).map(|state| (state, false))
+    }
+
+    /// Read the index at the end of a container, or `None` if it isn't intact
+    fn read_index<R: Read + Seek>(input: &mut R, len: u64) -> io::Result<Option<State>> {
+        let Some(trailer_at) = len.checked_sub(TRAILER_LEN).filter(|&at| at >= HEADER_LEN) else {
+            return Ok(None);
+        };
+        input.seek(SeekFrom::Start(trailer_at))?;
+        let offset = read_u64(input)?;
+        let mut hash = Hash::default();
+        input.read_exact(&mut hash)?;
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+        if magic != *END_MAGIC || offset < HEADER_LEN || offset > trailer_at {
+            return Ok(None);
+        }
+
+        let mut index = vec![0; usize::try_from(trailer_at - offset).map_err(|_| malformed())?];
+        input.seek(SeekFrom::Start(offset))?;
+        input.read_exact(&mut index)?;
+        if hash_data(&index) != hash {
+            return Ok(None);
+        }
+        State::decode_index(&mut &*index, offset).map(Some)
+    }
+
+    /// Rebuild the state of a container by replaying its records, stopping at the first one
+    /// which wasn't completely written. The records of a transaction which never committed are
+    /// dropped.
+    fn scan<R: Read + Seek>(input: &mut R, len: u64) -> io::Result<State> {
+        let mut state = State::new();
+        // The state from before the transaction being replayed, if there is one
+        let mut before = None;
+        input.seek(SeekFrom::Start(HEADER_LEN))?;
+        while len - state.end >= RECORD_HEAD_LEN {
+            let mut kind = [0; 1];
+            input.read_exact(&mut kind)?;
+            let body_len = read_u64(input)?;
+            if body_len > len - state.end - RECORD_HEAD_LEN
+                || HASH_LEN > len - state.end - RECORD_HEAD_LEN - body_len
+            {
+                break;
+            }
+
+            let mut body = vec![0; usize::try_from(body_len).map_err(|_| malformed())?];
+            input.read_exact(&mut body)?;
+            let mut hash = Hash::default();
+            input.read_exact(&mut hash)?;
+            if record_hash(kind[0], &body, &[]) != hash {
+                break;
+            }
+
+            match kind[0] {
+                RECORD_BEGIN => before = Some(state.clone()),
+                RECORD_COMMIT => before = None,
+                kind => state.apply(kind, &body)?,
+            }
+            state.end += RECORD_HEAD_LEN + body_len + HASH_LEN;
+        }
+        Ok(before.unwrap_or(state))
+    }
+
+    /// Apply a record starting at the current end of the state
+    fn apply(&mut self, kind: u8, body: &[u8]) -> io::Result<()> {
+        let mut input = body;
+        let start = self.end + RECORD_HEAD_LEN;
+        // Data always comes last in a record, so runs to the end of its body
+        let data_span = |rest: &[u8]| Span {
+            offset: start + (body.len() - rest.len()) as u64,
+            len: rest.len() as u64,
+        };
+
+        match kind {
+            RECORD_ADD => {
+                let id = read_id(&mut input)?;
+                let created = read_u64(&mut input)?;
+                let modified = read_u64(&mut input)?;
+                let tags = read_tags(&mut input)?;
+                let data = data_span(input);
+                self.next_id = self.next_id.max(id.into_u64_unchecked() + 1);
+                self.files.insert(
+                    id,
+                    Entry {
+                        tags,
+                        data,
+                        created,
+                        modified,
+                    },
+                );
+            }
+            RECORD_DATA => {
+                let id = read_id(&mut input)?;
+                let modified = read_u64(&mut input)?;
+                let data = data_span(input);
+                let entry = self.files.get_mut(&id).ok_or_else(malformed)?;
+                entry.data = data;
+                entry.modified = modified;
+            }
+            RECORD_TAGS => {
+                let id = read_id(&mut input)?;
+                let tags = read_tags(&mut input)?;
+                self.files.get_mut(&id).ok_or_else(malformed)?.tags = tags;
+            }
+            RECORD_REMOVE => {
+                let id = read_id(&mut input)?;
+                self.files.remove(&id).ok_or_else(malformed)?;
+            }
+            RECORD_SPECIAL => {
+                let name = read_string(&mut input)?;
+                let data = data_span(input);
+                self.special.insert(name, data);
+            }
+            _ => return Err(malformed()),
+        }
+        Ok(())
+    }
+
+    fn encode_index(&self) -> io::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        write_u64(&mut out, self.next_id)?;
+        write_u64(&mut out, self.files.len() as u64)?;
+        for (&id, entry) in &self.files {
+            write_id(&mut out, id)?;
+            write_u64(&mut out, entry.created)?;
+            write_u64(&mut out, entry.modified)?;
+            write_u64(&mut out, entry.data.offset)?;
+            write_u64(&mut out, entry.data.len)?;
+            write_tags(&mut out, &entry.tags)?;
+        }
+        write_len(&mut out, self.special.len())?;
+        for (name, span) in &self.special {
+            write_string(&mut out, name)?;
+            write_u64(&mut out, span.offset)?;
+            write_u64(&mut out, span.len)?;
+        }
+        Ok(out)
+    }
+
+    fn decode_index(input: &mut &[u8], end: u64) -> io::Result<State> {
+        let mut state = State::new();
+        state.end = end;
+        state.next_id = read_u64(input)?;
+        for _ in 0..read_u64(input)? {
+            let id = read_id(input)?;
+            let created = read_u64(input)?;
+            let modified = read_u64(input)?;
+            let data = Span {
+                offset: read_u64(input)?,
+                len: read_u64(input)?,
+            };
+            let tags = read_tags(input)?;
+            state.files.insert(
+                id,
+                Entry {
+                    tags,
+                    data,
+                    created,
+                    modified,
+                },
+            );
+        }
+        for _ in 0..read_u32(input)? {
+            let name = read_string(input)?;
+            let span = Span {
+                offset: read_u64(input)?,
+                len: read_u64(input)?,
+            };
+            state.special.insert(name, span);
+        }
+        Ok(state)
+    }
+}
+
+/// Add a file to the container, recording its ID as used
+fn insert(
+    file: &mut File,
+    state: &mut State,
+    id: FileId,
+    data: &[u8],
+    tags: BTreeSet<Tag>,
+) -> Result<(), Error> {
+    let next = id
+        .into_u64_unchecked()
+        .checked_add(1)
+        .ok_or(Error::AlreadyExists(id))?;
+    let now = to_nanos(now());
+    let head = encode_add(id, now, now, &tags)?;
+    let offset = append(file, state, RECORD_ADD, &head, data)?;
+    state.next_id = state.next_id.max(next);
+    state.files.insert(
+        id,
+        Entry {
+            tags,
+            data: Span::new(offset, data),
+            created: now,
+            modified: now,
+        },
+    );
+    Ok(())
+}
+
+fn set_data(file: &mut File, state: &mut State, id: FileId, data: &[u8]) -> Result<(), Error> {
+    let now = to_nanos(now());
+    let mut head = Vec::new();
+    write_id(&mut head, id)?;
+    write_u64(&mut head, now)?;
+    let offset = append(file, state, RECORD_DATA, &head, data)?;
+    let entry = state.files.get_mut(&id).ok_or(Error::FileNotFound(id))?;
+    entry.data = Span::new(offset, data);
+    entry.modified = now;
+    Ok(())
+}
+
+fn set_tags(
+    file: &mut File,
+    state: &mut State,
+    id: FileId,
+    tags: BTreeSet<Tag>,
+) -> Result<(), Error> {
+    let mut head = Vec::new();
+    write_id(&mut head, id)?;
+    write_tags(&mut head, &tags)?;
+    append(file, state, RECORD_TAGS, &head, &[])?;
+    state
+        .files
+        .get_mut(&id)
+        .ok_or(Error::FileNotFound(id))?
+        .tags = tags;
+    Ok(())
+}
+
+fn encode_add(
+    id: FileId,
+    created: u64,
+    modified: u64,
+    tags: &BTreeSet<Tag>,
+) -> io::Result<Vec<u8>> {
+    let mut head = Vec::new();
+    write_id(&mut head, id)?;
+    write_u64(&mut head, created)?;
+    write_u64(&mut head, modified)?;
+    write_tags(&mut head, tags)?;
+    Ok(head)
+}
+
+/// Write a record at the end of the state, made of the start of its body followed by data,
+/// returning the offset the data was written at
+fn append(
+    file: &mut File,
+    state: &mut State,
+    kind: u8,
+    body: &[u8],
+    data: &[u8],
+) -> io::Result<u64> {
+    file.seek(SeekFrom::Start(state.end))?;
+    file.write_all(&[kind])?;
+    write_u64(file, (body.len() + data.len()) as u64)?;
+    file.write_all(body)?;
+    file.write_all(data)?;
+    file.write_all(&record_hash(kind, body, data))?;
+
+    let offset = state.end + RECORD_HEAD_LEN + body.len() as u64;
+    state.end = offset + data.len() as u64 + HASH_LEN;
+    Ok(offset)
+}
+
+/// Write the index of the state after its last record, followed by the trailer, and cut off
+/// anything left past them
+fn write_index(file: &mut File, state: &State) -> io::Result<()> {
+    let index = state.encode_index()?;
+    file.seek(SeekFrom::Start(state.end))?;
+    file.write_all(&index)?;
+    write_u64(file, state.end)?;
+    file.write_all(&hash_data(&index))?;
+    file.write_all(END_MAGIC)?;
+    file.set_len(state.end + index.len() as u64 + TRAILER_LEN)?;
+    file.sync_data()
+}
+
+fn read_file_span(file: &mut File, span: Span) -> io::Result<Vec<u8>> {
+    let mut data = vec![0; usize::try_from(span.len).map_err(|_| malformed())?];
+    file.seek(SeekFrom::Start(span.offset))?;
+    file.read_exact(&mut data)?;
+    Ok(data)
+}
+
+/// Hash a record, whose body is made of two parts
+fn record_hash(kind: u8, body: &[u8], data: &[u8]) -> Hash {
+    let len = (body.len() + data.len()) as u64;
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&[kind]);
+    hasher.update(&len.to_le_bytes());
+    hasher.update(body);
+    hasher.update(data);
+    *hasher.finalize().as_bytes()
+}
+
+fn write_tags(out: &mut Vec<u8>, tags: &BTreeSet<Tag>) -> io::Result<()> {
+    write_len(out, tags.len())?;
+    for tag in tags {
+        write_tag(out, tag)?;
+    }
+    Ok(())
+}
+
+fn read_tags(input: &mut &[u8]) -> io::Result<BTreeSet<Tag>> {
+    let mut tags = BTreeSet::new();
+    for _ in 0..read_u32(input)? {
+        tags.insert(read_tag(input)?.ok_or(io::ErrorKind::UnexpectedEof)?);
+    }
+    Ok(tags)
+}
+
+fn malformed() -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, "Malformed TBF container")
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0)
+}
+
+fn from_nanos(nanos: u64) -> SystemTime {
+    UNIX_EPOCH + Duration::from_nanos(nanos)
+}
+
+/// A lazy search over a [`PackedFs`]. No locks are held between calls to `next`, so files added
+/// or removed during iteration may or may not be seen.
+pub struct SearchIter<'a, P> {
+    fs: &'a PackedFs,
+    pattern: P,
+    cursor: Bound<FileId>,
+    done: bool,
+}
+
+impl<P: TagPattern> SearchIter<'_, P> {
+    fn advance(&mut self) -> Result<Option<FileId>, Error> {
+        let providers = self.fs.providers.read()?;
+        let inner = self.fs.read()?;
+        for (&id, entry) in inner.state.files.range((self.cursor, Bound::Unbounded)) {
+            self.cursor = Bound::Excluded(id);
+
+            let matched = if providers.is_empty() {
+                self.pattern.match_tags(&entry.tags)
+            } else {
+                let data = self.fs.read_span(entry.data)?;
+                let provided = provide_tags(&providers, &data);
+                self.pattern.match_tags(entry.tags.iter().chain(&provided))
+            };
+
+            if matched {
+                return Ok(Some(id));
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl<P: TagPattern> Iterator for SearchIter<'_, P> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let out = self.advance().transpose();
+        self.done = !matches!(out, Some(Ok(_)));
+        out
+    }
+}
+
+/// A handle streaming data into a new file of a [`PackedFs`]. Data is buffered until the handle
+/// is flushed, which adds the file or updates its data if it already exists.
+pub type Writer<'a> = BufferedWriter<'a, PackedFs>;
@@ -0,0 +1,77 @@
+//! Matching tags with arbitrary functions
+
+use alloc::sync::Arc;
+use core::borrow::Borrow;
+use core::fmt;
+
+use super::{sealed, TagPattern, TagPredicate};
+use crate::Tag;
+
+/// A function deciding whether a tag matches, for matching logic [`TagPredicate`] can't express.
+/// A file matches if any of its tags do. Two functions are equal only if they're clones of the
+/// same `MatchFn`.
+///
+/// Functions run wherever the search does, so they can't be sent to remote filesystems or stored,
+/// such as in saved searches. Backends with an index can't use it to narrow down the files a
+/// function is checked against.
+///
+/// ```
+/// # use tbf::{FileSystemRead, FileSystemWrite, InMemoryFs, MatchFn, Tag};
+/// let fs = InMemoryFs::new();
+/// let long = fs.add_file(&[], [Tag::named("a_very_long_name")]).unwrap();
+/// fs.add_file(&[], [Tag::named("short")]).unwrap();
+///
+/// let pattern = MatchFn::new(|tag: &Tag| tag.name().len() > 10);
+/// assert_eq!(fs.search_tags(pattern).unwrap(), [long]);
+/// ```
+#[derive(Clone)]
+pub struct MatchFn(Arc<dyn Fn(&Tag) -> bool + Send + Sync>);
+
+impl MatchFn {
+    /// Create a matcher from a function
+    pub fn new<F>(f: F) -> MatchFn
+    where
+        F: Fn(&Tag) -> bool + Send + Sync + 'static,
+    {
+        MatchFn(Arc::new(f))
+    }
+
+    /// Check whether a single tag matches
+    pub fn matches(&self, tag: &Tag) -> bool {
+        (self.0)(tag)
+    }
+}
+
+impl fmt::Debug for MatchFn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MatchFn").finish_non_exhaustive()
+    }
+}
+
+impl PartialEq for MatchFn {
+    fn eq(&self, other: &MatchFn) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl From<MatchFn> for TagPredicate {
+    fn from(f: MatchFn) -> TagPredicate {
+        TagPredicate::Custom(f)
+    }
+}
+
+impl sealed::Sealed for MatchFn {
+    fn to_predicate(&self) -> TagPredicate {
+        TagPredicate::Custom(self.clone())
+    }
+}
+
+impl TagPattern for MatchFn {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        tags.into_iter().any(|tag| self.matches(tag.borrow()))
+    }
+}
@@ -0,0 +1,1050 @@
+mod custom;
+mod parse;
+mod plan;
+pub mod predicate;
+#[cfg(feature = "regex")]
+mod regex;
+#[cfg(feature = "serde")]
+mod serialize;
+
+use super::{Group, Tag, TagValue};
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::cmp::Ordering;
+
+#[cfg(feature = "regex")]
+pub use self::regex::TagRegex;
+pub use custom::MatchFn;
+pub use parse::{ParseError, ParseErrorKind};
+pub use plan::{Lookup, QueryPlan, TagIndex};
+
+pub(crate) mod sealed {
+    use super::{Tag, TagPredicate};
+    use alloc::collections::BTreeSet;
+    use alloc::vec::Vec;
+
+    pub trait Sealed {
+        /// Get a predicate matching the same tags as this pattern
+        fn to_predicate(&self) -> TagPredicate;
+    }
+
+    impl Sealed for Tag {
+        fn to_predicate(&self) -> TagPredicate {
+            TagPredicate::Tag(self.clone())
+        }
+    }
+
+    impl Sealed for [Tag] {
+        fn to_predicate(&self) -> TagPredicate {
+            TagPredicate::And(self.iter().cloned().map(TagPredicate::Tag).collect())
+        }
+    }
+
+    impl<const N: usize> Sealed for [Tag; N] {
+        fn to_predicate(&self) -> TagPredicate {
+            self[..].to_predicate()
+        }
+    }
+
+    impl Sealed for Vec<Tag> {
+        fn to_predicate(&self) -> TagPredicate {
+            self[..].to_predicate()
+        }
+    }
+
+    impl Sealed for BTreeSet<Tag> {
+        fn to_predicate(&self) -> TagPredicate {
+            TagPredicate::And(self.iter().cloned().map(TagPredicate::Tag).collect())
+        }
+    }
+
+    impl<P: Sealed + ?Sized> Sealed for &P {
+        fn to_predicate(&self) -> TagPredicate {
+            (**self).to_predicate()
+        }
+    }
+
+    macro_rules! tuple_sealed {
+        ($($name:ident),+) => {
+            impl<$($name: Sealed),+> Sealed for ($($name,)+) {
+                #[allow(non_snake_case)]
+                fn to_predicate(&self) -> TagPredicate {
+                    let ($($name,)+) = self;
+                    TagPredicate::And(alloc::vec![$($name.to_predicate()),+])
+                }
+            }
+        };
+    }
+
+    tuple_sealed!(A);
+    tuple_sealed!(A, B);
+    tuple_sealed!(A, B, C);
+    tuple_sealed!(A, B, C, D);
+    tuple_sealed!(A, B, C, D, E);
+    tuple_sealed!(A, B, C, D, E, F);
+    tuple_sealed!(A, B, C, D, E, F, G);
+    tuple_sealed!(A, B, C, D, E, F, G, H);
+
+    impl Sealed for TagPredicate {
+        fn to_predicate(&self) -> TagPredicate {
+            self.clone()
+        }
+    }
+}
+
+/// Any type that can be used to match a file's tags on
+///
+/// Single tags match files with that tag, and collections of tags, such as slices, arrays,
+/// [`Vec`]s and [`BTreeSet`]s, match files with every tag in them. Tuples of patterns match files
+/// which every pattern in them matches. References to any pattern can be used in its place.
+///
+/// ```
+/// # use std::collections::BTreeSet;
+/// # use tbf::{FileSystemRead, FileSystemWrite, InMemoryFs, Group, Tag, TagPredicate};
+/// let fs = InMemoryFs::new();
+/// let id = fs.add_file(&[], [Tag::named("a"), Tag::named("b")]).unwrap();
+///
+/// let tags = vec![Tag::named("a"), Tag::named("b")];
+/// assert_eq!(fs.search_tags(&tags).unwrap(), [id]);
+/// assert_eq!(fs.search_tags(tags.iter().cloned().collect::<BTreeSet<_>>()).unwrap(), [id]);
+/// assert_eq!(fs.search_tags((&tags[0], TagPredicate::group(Group::Default))).unwrap(), [id]);
+/// ```
+pub trait TagPattern: sealed::Sealed {
+    /// Match this item against an iterator of tags
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>;
+}
+
+impl TagPattern for Tag {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        tags.into_iter().any(|tag| tag.borrow() == self)
+    }
+}
+
+impl TagPattern for [Tag] {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        self.iter()
+            .all(|tag| tags.iter().any(|t| tag == t.borrow()))
+    }
+}
+
+impl<const N: usize> TagPattern for [Tag; N] {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        <[Tag]>::match_tags(self, tags)
+    }
+}
+
+impl TagPattern for Vec<Tag> {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        <[Tag]>::match_tags(self, tags)
+    }
+}
+
+impl TagPattern for BTreeSet<Tag> {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        self.iter()
+            .all(|tag| tags.iter().any(|t| tag == t.borrow()))
+    }
+}
+
+impl<P: TagPattern + ?Sized> TagPattern for &P {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        (**self).match_tags(tags)
+    }
+}
+
+/// Tuples of patterns match if every pattern in them does
+macro_rules! tuple_pattern {
+    ($($name:ident),+) => {
+        impl<$($name: TagPattern),+> TagPattern for ($($name,)+) {
+            #[allow(non_snake_case)]
+            fn match_tags<T, I>(&self, tags: I) -> bool
+            where
+                T: Borrow<Tag>,
+                I: IntoIterator<Item = T>,
+            {
+                let tags = tags.into_iter().collect::<Vec<_>>();
+                let ($($name,)+) = self;
+                $($name.match_tags(tags.iter().map(Borrow::borrow)))&&+
+            }
+        }
+    };
+}
+
+tuple_pattern!(A);
+tuple_pattern!(A, B);
+tuple_pattern!(A, B, C);
+tuple_pattern!(A, B, C, D);
+tuple_pattern!(A, B, C, D, E);
+tuple_pattern!(A, B, C, D, E, F);
+tuple_pattern!(A, B, C, D, E, F, G);
+tuple_pattern!(A, B, C, D, E, F, G, H);
+
+/// Complex support for matching binary expressions against tags
+///
+/// With the `serde` feature, predicates serialize to the same shape the HTTP server and client
+/// use, which is kept stable so predicates can be stored, such as for saved searches. Each
+/// predicate is an object with a single key naming its kind in snake case, like `{"and": [...]}`,
+/// `{"name_glob": "*.png"}`, or `{"value_gt": {"group": "photo", "name": "rating", "value":
+/// {"int": 3}}}`. Groups are strings, empty for the default group. Tags are objects with a
+/// `name`, a `group` unless it's the default, and a `value` if they have one. Values are objects
+/// with a single key naming their type, one of `string`, `int`, `float`, `bool`, or `timestamp`.
+/// Regular expressions are strings, compiled again when deserialized. [`TagPredicate::Custom`]
+/// predicates can't be serialized.
+#[derive(Debug, Clone, PartialEq)]
+#[cfg_attr(
+    feature = "serde",
+    derive(serde::Serialize, serde::Deserialize),
+    serde(rename_all = "snake_case")
+)]
+pub enum TagPredicate {
+    /// And predicates together
+    And(Vec<TagPredicate>),
+    /// Or predicates together
+    Or(Vec<TagPredicate>),
+    /// Inverse a predicate
+    Not(Box<TagPredicate>),
+
+    /// Match just the group of a tag
+    Group(#[cfg_attr(feature = "serde", serde(with = "serialize::group"))] Group),
+    /// Match just the name of a tag
+    Name(String),
+    /// Match tags whose name contains a substring
+    NameContains(String),
+    /// Match tags whose name matches a glob, where `*` matches any run of characters and `?`
+    /// matches a single character
+    NameGlob(String),
+    /// Match tags whose group matches a glob, like [`TagPredicate::NameGlob`]. The default group
+    /// is treated as having an empty name.
+    GroupGlob(String),
+    /// Match tags whose name matches a regular expression
+    #[cfg(feature = "regex")]
+    NameRegex(TagRegex),
+    /// Match tags whose group matches a regular expression. The default group is treated as
+    /// having an empty name.
+    #[cfg(feature = "regex")]
+    GroupRegex(TagRegex),
+    /// Match a tag exactly
+    Tag(#[cfg_attr(feature = "serde", serde(with = "serialize::tag"))] Tag),
+    /// Match tags with a group and name whose value is greater than the given one
+    ValueGt {
+        /// The group of tags to compare
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "serialize::group",
+                default,
+                skip_serializing_if = "serialize::is_default_group"
+            )
+        )]
+        group: Group,
+        /// The name of tags to compare
+        name: String,
+        /// The value tags must be greater than
+        #[cfg_attr(feature = "serde", serde(with = "serialize::value"))]
+        value: TagValue,
+    },
+    /// Match tags with a group and name whose value is less than the given one
+    ValueLt {
+        /// The group of tags to compare
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "serialize::group",
+                default,
+                skip_serializing_if = "serialize::is_default_group"
+            )
+        )]
+        group: Group,
+        /// The name of tags to compare
+        name: String,
+        /// The value tags must be less than
+        #[cfg_attr(feature = "serde", serde(with = "serialize::value"))]
+        value: TagValue,
+    },
+    /// Match tags with a group and name whose value is within an inclusive range
+    ValueRange {
+        /// The group of tags to compare
+        #[cfg_attr(
+            feature = "serde",
+            serde(
+                with = "serialize::group",
+                default,
+                skip_serializing_if = "serialize::is_default_group"
+            )
+        )]
+        group: Group,
+        /// The name of tags to compare
+        name: String,
+        /// The lowest value to match
+        #[cfg_attr(feature = "serde", serde(with = "serialize::value"))]
+        min: TagValue,
+        /// The highest value to match
+        #[cfg_attr(feature = "serde", serde(with = "serialize::value"))]
+        max: TagValue,
+    },
+    /// Match tags a function accepts. These only run locally, so remote filesystems reject them.
+    #[cfg_attr(feature = "serde", serde(skip))]
+    Custom(MatchFn),
+}
+
+impl From<Tag> for TagPredicate {
+    fn from(tag: Tag) -> TagPredicate {
+        TagPredicate::Tag(tag)
+    }
+}
+
+impl From<Group> for TagPredicate {
+    fn from(group: Group) -> Self {
+        TagPredicate::Group(group)
+    }
+}
+
+impl TagPredicate {
+    /// Create an and predicate from an iterator of predicate items
+    pub fn and<T, I>(preds: I) -> TagPredicate
+    where
+        T: Into<TagPredicate>,
+        I: IntoIterator<Item = T>,
+    {
+        TagPredicate::And(preds.into_iter().map(T::into).collect())
+    }
+
+    /// Create an or predicate from an iterator of predicate items
+    pub fn or<T, I>(preds: I) -> TagPredicate
+    where
+        T: Into<TagPredicate>,
+        I: IntoIterator<Item = T>,
+    {
+        TagPredicate::Or(preds.into_iter().map(T::into).collect())
+    }
+
+    /// Create a not predicate from some other predicate item
+    pub fn not<T>(pred: T) -> TagPredicate
+    where
+        T: Into<TagPredicate>,
+    {
+        TagPredicate::Not(Box::new(pred.into()))
+    }
+
+    /// Create a predicate for a group
+    pub fn group(group: Group) -> TagPredicate {
+        TagPredicate::Group(group)
+    }
+
+    /// Create a predicate for a name
+    pub fn name(name: &str) -> TagPredicate {
+        TagPredicate::Name(name.to_string())
+    }
+
+    /// Create a predicate for names containing a substring
+    pub fn name_contains(substr: &str) -> TagPredicate {
+        TagPredicate::NameContains(substr.to_string())
+    }
+
+    /// Create a predicate for names matching a glob
+    pub fn name_glob(glob: &str) -> TagPredicate {
+        TagPredicate::NameGlob(glob.to_string())
+    }
+
+    /// Create a predicate for groups matching a glob
+    pub fn group_glob(glob: &str) -> TagPredicate {
+        TagPredicate::GroupGlob(glob.to_string())
+    }
+
+    /// Create a predicate for names matching a regular expression
+    #[cfg(feature = "regex")]
+    pub fn name_regex(regex: &str) -> Result<TagPredicate, regex_lite::Error> {
+        TagRegex::new(regex).map(TagPredicate::NameRegex)
+    }
+
+    /// Create a predicate for groups matching a regular expression
+    #[cfg(feature = "regex")]
+    pub fn group_regex(regex: &str) -> Result<TagPredicate, regex_lite::Error> {
+        TagRegex::new(regex).map(TagPredicate::GroupRegex)
+    }
+
+    /// Create a predicate to match a tag exactly
+    pub fn tag(tag: Tag) -> TagPredicate {
+        TagPredicate::Tag(tag)
+    }
+
+    /// Create a predicate for files with a link of any kind to a file, as made by
+    /// [`Links::relates`](crate::Links::relates)
+    #[cfg(feature = "std")]
+    pub fn linked_to(id: crate::FileId) -> TagPredicate {
+        TagPredicate::Group(crate::links::link_group(id))
+    }
+
+    /// Create a predicate for tag values greater than a value
+    pub fn value_gt<G, V>(group: G, name: &str, value: V) -> TagPredicate
+    where
+        G: Into<Group>,
+        V: Into<TagValue>,
+    {
+        TagPredicate::ValueGt {
+            group: group.into(),
+            name: name.to_string(),
+            value: value.into(),
+        }
+    }
+
+    /// Create a predicate for tag values less than a value
+    pub fn value_lt<G, V>(group: G, name: &str, value: V) -> TagPredicate
+    where
+        G: Into<Group>,
+        V: Into<TagValue>,
+    {
+        TagPredicate::ValueLt {
+            group: group.into(),
+            name: name.to_string(),
+            value: value.into(),
+        }
+    }
+
+    /// Create a predicate for tag values within an inclusive range
+    pub fn value_range<G, V>(group: G, name: &str, min: V, max: V) -> TagPredicate
+    where
+        G: Into<Group>,
+        V: Into<TagValue>,
+    {
+        TagPredicate::ValueRange {
+            group: group.into(),
+            name: name.to_string(),
+            min: min.into(),
+            max: max.into(),
+        }
+    }
+
+    /// Create a predicate matching tags a function accepts
+    pub fn custom<F>(f: F) -> TagPredicate
+    where
+        F: Fn(&Tag) -> bool + Send + Sync + 'static,
+    {
+        TagPredicate::Custom(MatchFn::new(f))
+    }
+}
+
+/// Which shape [`TagPredicate::normalize`] rewrites a predicate into
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum NormalForm {
+    /// Disjunctive normal form, an or of ands
+    Disjunctive,
+    /// Conjunctive normal form, an and of ors
+    Conjunctive,
+}
+
+impl TagPredicate {
+    /// Whether this predicate matches single tags, rather than combining other predicates
+    pub fn is_atom(&self) -> bool {
+        !matches!(
+            self,
+            TagPredicate::And(_) | TagPredicate::Or(_) | TagPredicate::Not(_)
+        )
+    }
+
+    /// Rewrite this predicate into a normal form which matches the same files. Negations are
+    /// pushed down until they only wrap atoms, and the result is always two levels deep: an `Or`
+    /// of `And`s for [`NormalForm::Disjunctive`], or an `And` of `Or`s for
+    /// [`NormalForm::Conjunctive`], each holding atoms or the `Not` of atoms. Repeated atoms are
+    /// merged, and terms which contradict themselves or are made redundant by others are dropped.
+    ///
+    /// Either form can be exponentially larger than the original, such as an and of many ors
+    /// rewritten into disjunctive form, so this is best kept to predicates built by people.
+    #[must_use]
+    pub fn normalize(&self, form: NormalForm) -> TagPredicate {
+        match form {
+            NormalForm::Disjunctive => TagPredicate::Or(
+                disjuncts(self, false)
+                    .into_iter()
+                    .map(TagPredicate::And)
+                    .collect(),
+            ),
+            // The negation of each term in the disjunctive form of the inverse
+            NormalForm::Conjunctive => TagPredicate::And(
+                disjuncts(self, true)
+                    .into_iter()
+                    .map(|term| TagPredicate::Or(term.iter().map(negate).collect()))
+                    .collect(),
+            ),
+        }
+    }
+}
+
+/// Get the terms of a predicate in disjunctive normal form, each a list of atoms or negated
+/// atoms, inverting the predicate first if `inverted` is set
+fn disjuncts(pred: &TagPredicate, inverted: bool) -> Vec<Vec<TagPredicate>> {
+    match (pred, inverted) {
+        (TagPredicate::Not(pred), _) => disjuncts(pred, !inverted),
+        (TagPredicate::Or(preds), false) | (TagPredicate::And(preds), true) => {
+            let mut out = Vec::new();
+            for pred in preds {
+                for term in disjuncts(pred, inverted) {
+                    add_term(&mut out, term);
+                }
+            }
+            out
+        }
+        (TagPredicate::And(preds), false) | (TagPredicate::Or(preds), true) => {
+            let mut out = alloc::vec![Vec::new()];
+            for pred in preds {
+                let terms = disjuncts(pred, inverted);
+                let mut next = Vec::new();
+                for left in &out {
+                    for right in &terms {
+                        if let Some(term) = conjoin(left, right) {
+                            add_term(&mut next, term);
+                        }
+                    }
+                }
+                out = next;
+                if out.is_empty() {
+                    break;
+                }
+            }
+            out
+        }
+        (atom, false) => alloc::vec![alloc::vec![atom.clone()]],
+        (atom, true) => alloc::vec![alloc::vec![TagPredicate::not(atom.clone())]],
+    }
+}
+
+/// Add a term to an or of terms. Since a term matches whenever one of its subsets does, it's
+/// skipped if it contains another term, and replaces any terms which contain it.
+fn add_term(terms: &mut Vec<Vec<TagPredicate>>, term: Vec<TagPredicate>) {
+    let contains = |outer: &[TagPredicate], inner: &[TagPredicate]| {
+        inner.iter().all(|item| outer.contains(item))
+    };
+    if terms.iter().any(|other| contains(&term, other)) {
+        return;
+    }
+    terms.retain(|other| !contains(other, &term));
+    terms.push(term);
+}
+
+/// And two terms together, or get `None` if the result could never match
+fn conjoin(left: &[TagPredicate], right: &[TagPredicate]) -> Option<Vec<TagPredicate>> {
+    let mut out = left.to_vec();
+    for item in right {
+        if out.contains(&negate(item)) {
+            return None;
+        }
+        if !out.contains(item) {
+            out.push(item.clone());
+        }
+    }
+    Some(out)
+}
+
+/// Invert an atom or negated atom
+fn negate(item: &TagPredicate) -> TagPredicate {
+    match item {
+        TagPredicate::Not(pred) => (**pred).clone(),
+        _ => TagPredicate::not(item.clone()),
+    }
+}
+
+impl TagPattern for TagPredicate {
+    fn match_tags<T, I>(&self, tags: I) -> bool
+    where
+        T: Borrow<Tag>,
+        I: IntoIterator<Item = T>,
+    {
+        use TagPredicate::{
+            And, Custom, Group, GroupGlob, Name, NameContains, NameGlob, Not, Or, Tag, ValueGt,
+            ValueLt, ValueRange,
+        };
+
+        let mut iter = tags.into_iter();
+        match self {
+            And(preds) => {
+                let tags = iter.collect::<Vec<_>>();
+                preds
+                    .iter()
+                    .all(|pred| pred.match_tags(tags.iter().map(Borrow::borrow)))
+            }
+            Or(preds) => {
+                let tags = iter.collect::<Vec<_>>();
+                preds
+                    .iter()
+                    .any(|pred| pred.match_tags(tags.iter().map(Borrow::borrow)))
+            }
+            Not(pred) => !pred.match_tags(iter),
+
+            Group(group) => iter.any(|tag| tag.borrow().group() == group),
+            Name(name) => iter.any(|tag| tag.borrow().name() == name),
+            NameContains(substr) => iter.any(|tag| tag.borrow().name().contains(substr.as_str())),
+            NameGlob(glob) => iter.any(|tag| glob_match(glob, tag.borrow().name())),
+            GroupGlob(glob) => iter.any(|tag| glob_match(glob, group_name(tag.borrow().group()))),
+            #[cfg(feature = "regex")]
+            TagPredicate::NameRegex(regex) => iter.any(|tag| regex.is_match(tag.borrow().name())),
+            #[cfg(feature = "regex")]
+            TagPredicate::GroupRegex(regex) => {
+                iter.any(|tag| regex.is_match(group_name(tag.borrow().group())))
+            }
+            Tag(tag) => tag.match_tags(iter),
+            ValueGt { group, name, value } => iter.any(|tag| {
+                compare_value(tag.borrow(), group, name, value) == Some(Ordering::Greater)
+            }),
+            ValueLt { group, name, value } => iter
+                .any(|tag| compare_value(tag.borrow(), group, name, value) == Some(Ordering::Less)),
+            ValueRange {
+                group,
+                name,
+                min,
+                max,
+            } => iter.any(|tag| {
+                let tag = tag.borrow();
+                matches!(
+                    compare_value(tag, group, name, min),
+                    Some(Ordering::Greater | Ordering::Equal)
+                ) && matches!(
+                    compare_value(tag, group, name, max),
+                    Some(Ordering::Less | Ordering::Equal)
+                )
+            }),
+            Custom(f) => f.match_tags(iter),
+        }
+    }
+}
+
+/// Compare a tag's value to another, if the tag has the right group and name
+fn compare_value(tag: &Tag, group: &Group, name: &str, value: &TagValue) -> Option<Ordering> {
+    if tag.group() == group && tag.name() == name {
+        tag.value()?.compare(value)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn group_name(group: &Group) -> &str {
+    match group {
+        Group::Default => "",
+        Group::Custom(name) => name,
+    }
+}
+
+/// Match a string against a glob, where `*` matches any run of characters and `?` matches a
+/// single character
+pub(crate) fn glob_match(glob: &str, val: &str) -> bool {
+    let glob = glob.chars().collect::<Vec<_>>();
+    let val = val.chars().collect::<Vec<_>>();
+
+    let (mut g, mut v) = (0, 0);
+    // Position of the last `*` seen, and how much of the value it had consumed
+    let mut star = None;
+
+    while v < val.len() {
+        match glob.get(g) {
+            Some('*') => {
+                star = Some((g, v));
+                g += 1;
+            }
+            Some(&c) if c == '?' || c == val[v] => {
+                g += 1;
+                v += 1;
+            }
+            _ => match star {
+                // Let the last star consume one more character, and try again from there
+                Some((star_g, star_v)) => {
+                    star = Some((star_g, star_v + 1));
+                    g = star_g + 1;
+                    v = star_v + 1;
+                }
+                None => return false,
+            },
+        }
+    }
+
+    glob[g..].iter().all(|&c| c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::sealed::Sealed;
+    use super::*;
+
+    #[test]
+    fn test_tag() {
+        let tag_a = Tag::named("a");
+
+        assert!(tag_a.match_tags(&[Tag::named("a"), Tag::named("b"),]));
+        assert!(!tag_a.match_tags(&[Tag::named("b"), Tag::named("c"),]));
+    }
+
+    #[test]
+    fn test_tag_slice() {
+        let tag_slice = &[Tag::named("a"), Tag::named("b")];
+
+        assert!(tag_slice.match_tags(&[
+            Tag::named("b"),
+            Tag::named("c"),
+            Tag::named("d"),
+            Tag::named("a"),
+        ]));
+
+        assert!(!tag_slice.match_tags(&[Tag::named("c"), Tag::named("ab"), Tag::named("d"),]));
+    }
+
+    #[test]
+    fn test_collections() {
+        let tags = [Tag::named("b"), Tag::named("a"), Tag::named("c")];
+        let pattern = alloc::vec![Tag::named("a"), Tag::named("b")];
+
+        assert!(pattern.match_tags(&tags));
+        assert!(!pattern.match_tags(&tags[1..]));
+        assert!(pattern
+            .iter()
+            .cloned()
+            .collect::<BTreeSet<_>>()
+            .match_tags(&tags));
+        assert!(!BTreeSet::from([Tag::named("d")]).match_tags(&tags));
+        assert!((&&pattern[0]).match_tags(&tags));
+        assert_eq!(
+            BTreeSet::from([Tag::named("b"), Tag::named("a")]).to_predicate(),
+            pattern.to_predicate(),
+        );
+    }
+
+    #[test]
+    fn test_tuple() {
+        let tags = [Tag::named("a"), Tag::new(Group::custom("g"), "b")];
+
+        assert!((Tag::named("a"),).match_tags(&tags));
+        assert!((Tag::named("a"), TagPredicate::group(Group::custom("g"))).match_tags(&tags));
+        assert!(!(Tag::named("a"), [Tag::named("c")]).match_tags(&tags));
+        assert!(!(Tag::named("a"), TagPredicate::name("b"), Tag::named("c")).match_tags(&tags));
+        assert_eq!(
+            (Tag::named("a"), TagPredicate::name("b")).to_predicate(),
+            TagPredicate::and([TagPredicate::Tag(Tag::named("a")), TagPredicate::name("b")]),
+        );
+    }
+
+    #[test]
+    fn test_operators() {
+        use predicate::{all, group, none, tag, tag_in};
+
+        assert_eq!(
+            tag("a") & tag("b") & (tag("c") & Tag::named("d")),
+            TagPredicate::and([tag("a"), tag("b"), tag("c"), tag("d")]),
+        );
+        assert_eq!(
+            (tag("a") | group("g")) | tag_in("g", "b"),
+            TagPredicate::or([tag("a"), group("g"), tag_in("g", "b")]),
+        );
+        assert_eq!(
+            tag("a") & (tag("b") | !tag("c")),
+            TagPredicate::and([
+                tag("a"),
+                TagPredicate::or([tag("b"), TagPredicate::not(tag("c"))]),
+            ]),
+        );
+        assert_eq!(!!tag("a"), tag("a"));
+        assert_eq!(all() & tag("a"), TagPredicate::and([tag("a")]));
+        assert_eq!(none() | tag("a"), TagPredicate::or([tag("a")]));
+
+        let mut pred = tag("a");
+        pred &= Group::custom("g");
+        pred |= tag("b");
+        assert_eq!(
+            pred,
+            TagPredicate::or([TagPredicate::and([tag("a"), group("g")]), tag("b"),]),
+        );
+        assert!(pred.match_tags(&[Tag::named("b")]));
+        assert!(!pred.match_tags(&[Tag::named("a")]));
+    }
+
+    #[test]
+    fn test_pred_and() {
+        let pred = TagPredicate::and([Tag::named("a"), Tag::named("b")]);
+
+        assert!(pred.match_tags(&[
+            Tag::named("c"),
+            Tag::named("b"),
+            Tag::named("f"),
+            Tag::named("a"),
+        ]));
+        assert!(!pred.match_tags(&[Tag::named("c"), Tag::named("f"), Tag::named("a"),]));
+    }
+
+    #[test]
+    fn test_pred_or() {
+        let pred = TagPredicate::or([Tag::named("a"), Tag::named("b")]);
+
+        assert!(pred.match_tags(&[Tag::named("a"),]));
+        assert!(pred.match_tags(&[Tag::named("b"), Tag::named("c"),]));
+        assert!(!pred.match_tags(&[Tag::named("c"), Tag::named("d"),]));
+    }
+
+    #[test]
+    fn test_pred_not() {
+        let pred = TagPredicate::not(Tag::named("a"));
+
+        assert!(pred.match_tags(&[Tag::named("b"), Tag::named("c")]));
+        assert!(!pred.match_tags(&[Tag::named("a"), Tag::named("b")]));
+    }
+
+    #[test]
+    fn test_pred_group() {
+        let pred = TagPredicate::group(Group::Default);
+
+        assert!(pred.match_tags(&[Tag::named("a"), Tag::new(Group::custom("group"), "a")]));
+        assert!(!pred.match_tags(&[
+            Tag::new(Group::custom("group"), "a"),
+            Tag::new(Group::custom("group"), "b")
+        ]));
+    }
+
+    #[test]
+    fn test_pred_name() {
+        let pred = TagPredicate::name("a");
+
+        assert!(pred.match_tags(&[
+            Tag::new(Group::custom("group"), "a"),
+            Tag::new(Group::custom("group"), "b"),
+        ]));
+        assert!(pred.match_tags(&[Tag::named("a"), Tag::named("b"),]));
+        assert!(!pred.match_tags(&[Tag::new(Group::custom("group"), "b"), Tag::named("b"),]));
+    }
+
+    #[test]
+    fn test_pred_name_contains() {
+        let pred = TagPredicate::name_contains("ell");
+
+        assert!(pred.match_tags(&[Tag::named("hello"), Tag::named("b")]));
+        assert!(!pred.match_tags(&[Tag::named("help"), Tag::named("b")]));
+    }
+
+    #[test]
+    fn test_pred_glob() {
+        let pred = TagPredicate::name_glob("project-*");
+
+        assert!(pred.match_tags(&[Tag::named("project-tbf")]));
+        assert!(pred.match_tags(&[Tag::named("project-")]));
+        assert!(!pred.match_tags(&[Tag::named("projects")]));
+
+        let pred = TagPredicate::name_glob("a?c*d");
+        assert!(pred.match_tags(&[Tag::named("abcxxd")]));
+        assert!(pred.match_tags(&[Tag::named("abcdd")]));
+        assert!(!pred.match_tags(&[Tag::named("acd")]));
+
+        let pred = TagPredicate::group_glob("ph*");
+        assert!(pred.match_tags(&[Tag::new(Group::custom("photos"), "a")]));
+        assert!(!pred.match_tags(&[Tag::named("photos")]));
+    }
+
+    #[cfg(feature = "regex")]
+    #[test]
+    fn test_pred_regex() {
+        let pred = TagPredicate::name_regex("^v[0-9]+$").unwrap();
+
+        assert!(pred.match_tags(&[Tag::named("v12")]));
+        assert!(!pred.match_tags(&[Tag::named("v1a"), Tag::named("version")]));
+
+        let pred = TagPredicate::group_regex("^$").unwrap();
+        assert!(pred.match_tags(&[Tag::named("a")]));
+        assert!(!pred.match_tags(&[Tag::new(Group::custom("g"), "a")]));
+
+        assert!(TagPredicate::name_regex("(").is_err());
+    }
+
+    #[test]
+    fn test_pred_value() {
+        let tags = [
+            Tag::named("rating").with_value(4),
+            Tag::named("size").with_value(10.5),
+            Tag::named("name").with_value("x"),
+        ];
+
+        assert!(TagPredicate::value_gt(Group::Default, "rating", 3).match_tags(&tags));
+        assert!(!TagPredicate::value_gt(Group::Default, "rating", 4).match_tags(&tags));
+        assert!(TagPredicate::value_lt(Group::Default, "size", 11).match_tags(&tags));
+        assert!(TagPredicate::value_range(Group::Default, "rating", 4, 5).match_tags(&tags));
+        assert!(!TagPredicate::value_range(Group::Default, "rating", 5, 6).match_tags(&tags));
+        assert!(!TagPredicate::value_gt(Group::Default, "name", 1).match_tags(&tags));
+        assert!(!TagPredicate::value_gt(Group::custom("g"), "rating", 1).match_tags(&tags));
+    }
+
+    #[test]
+    fn test_pred_tag() {
+        let pred = TagPredicate::Tag(Tag::named("a"));
+
+        assert!(pred.match_tags(&[Tag::named("c"), Tag::named("a"),]));
+        assert!(!pred.match_tags(&[Tag::named("c"), Tag::named("f"),]));
+    }
+
+    #[test]
+    fn test_pred_custom() {
+        let short = MatchFn::new(|tag: &Tag| tag.name().len() < 3);
+        assert!(short.match_tags(&[Tag::named("long"), Tag::named("ab")]));
+        assert!(!short.match_tags(&[Tag::named("long")]));
+
+        let pred = TagPredicate::and([
+            TagPredicate::Tag(Tag::named("long")),
+            TagPredicate::from(short.clone()),
+        ]);
+        assert!(pred.match_tags(&[Tag::named("long"), Tag::named("ab")]));
+        assert!(!pred.match_tags(&[Tag::named("long"), Tag::named("abc")]));
+        assert!(TagPredicate::not(short.clone()).match_tags(&[Tag::named("abc")]));
+
+        // Only clones of the same function are equal
+        assert_eq!(
+            TagPredicate::from(short.clone()),
+            TagPredicate::Custom(short)
+        );
+        assert_ne!(
+            TagPredicate::custom(|_| true),
+            TagPredicate::custom(|_| true)
+        );
+    }
+
+    #[test]
+    fn test_normalize() {
+        let a = || TagPredicate::tag(Tag::named("a"));
+        let b = || TagPredicate::tag(Tag::named("b"));
+        let c = || TagPredicate::group(Group::custom("c"));
+
+        let pred = TagPredicate::and([
+            TagPredicate::or([a(), b()]),
+            TagPredicate::not(TagPredicate::and([c(), TagPredicate::not(a())])),
+        ]);
+        assert_eq!(
+            pred.normalize(NormalForm::Disjunctive),
+            TagPredicate::or([
+                TagPredicate::and([a()]),
+                TagPredicate::and([b(), TagPredicate::not(c())]),
+            ])
+        );
+        assert_eq!(
+            pred.normalize(NormalForm::Conjunctive),
+            TagPredicate::and([
+                TagPredicate::or([a(), b()]),
+                TagPredicate::or([TagPredicate::not(c()), a()]),
+            ])
+        );
+
+        // Contradictions and tautologies drop out
+        let pred = TagPredicate::and([a(), TagPredicate::not(a())]);
+        assert_eq!(
+            pred.normalize(NormalForm::Disjunctive),
+            TagPredicate::Or(Vec::new())
+        );
+        let pred = TagPredicate::or([a(), TagPredicate::not(a())]);
+        assert_eq!(
+            pred.normalize(NormalForm::Conjunctive),
+            TagPredicate::And(Vec::new())
+        );
+
+        let sets: [&[Tag]; 5] = [
+            &[],
+            &[Tag::named("a")],
+            &[Tag::named("b"), Tag::new(Group::custom("c"), "x")],
+            &[Tag::named("a"), Tag::new(Group::custom("c"), "x")],
+            &[Tag::named("b")],
+        ];
+        let pred = TagPredicate::or([
+            TagPredicate::not(TagPredicate::or([a(), c()])),
+            TagPredicate::and([b(), TagPredicate::not(TagPredicate::not(c()))]),
+        ]);
+        for tags in sets {
+            for form in [NormalForm::Disjunctive, NormalForm::Conjunctive] {
+                assert_eq!(
+                    pred.normalize(form).match_tags(tags),
+                    pred.match_tags(tags),
+                    "{form:?} {tags:?}"
+                );
+            }
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_pred_serde() {
+        use serde_json::json;
+
+        let pred = TagPredicate::and([
+            TagPredicate::or([
+                TagPredicate::Tag(Tag::named("a")),
+                TagPredicate::Tag(Tag::new("g", "b").with_value(1.5)),
+            ]),
+            TagPredicate::not(TagPredicate::group(Group::custom("h"))),
+            TagPredicate::group(Group::Default),
+            TagPredicate::name_glob("*.png"),
+            TagPredicate::value_gt(Group::Default, "rating", 3),
+            TagPredicate::value_range(
+                "date",
+                "taken",
+                TagValue::Timestamp(0),
+                TagValue::Timestamp(10),
+            ),
+        ]);
+        let value = serde_json::to_value(&pred).unwrap();
+        assert_eq!(
+            value,
+            json!({ "and": [
+                { "or": [
+                    { "tag": { "name": "a" } },
+                    { "tag": { "group": "g", "name": "b", "value": { "float": 1.5 } } },
+                ] },
+                { "not": { "group": "h" } },
+                { "group": "" },
+                { "name_glob": "*.png" },
+                { "value_gt": { "name": "rating", "value": { "int": 3 } } },
+                { "value_range": {
+                    "group": "date",
+                    "name": "taken",
+                    "min": { "timestamp": 0 },
+                    "max": { "timestamp": 10 },
+                } },
+            ] })
+        );
+        assert_eq!(serde_json::from_value::<TagPredicate>(value).unwrap(), pred);
+
+        assert!(serde_json::from_value::<TagPredicate>(json!({ "nand": [] })).is_err());
+        assert!(
+            serde_json::from_value::<TagPredicate>(json!({ "tag": { "group": "g" } })).is_err()
+        );
+    }
+
+    #[cfg(all(feature = "serde", feature = "regex"))]
+    #[test]
+    fn test_pred_serde_regex() {
+        let pred = TagPredicate::name_regex(r"^\d+$").unwrap();
+        let json = serde_json::to_string(&pred).unwrap();
+        assert_eq!(json, r#"{"name_regex":"^\\d+$"}"#);
+        assert_eq!(serde_json::from_str::<TagPredicate>(&json).unwrap(), pred);
+        assert!(serde_json::from_str::<TagPredicate>(r#"{"group_regex":"("}"#).is_err());
+    }
+}
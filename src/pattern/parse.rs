@@ -0,0 +1,363 @@
+//! Text syntax for tag predicates
+
+use alloc::borrow::ToOwned;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+use core::fmt;
+use core::ops::Range;
+use core::str::FromStr;
+
+use super::TagPredicate;
+use crate::{Group, Tag};
+
+/// An error from parsing a [`TagPredicate`] from text
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    kind: ParseErrorKind,
+    pos: usize,
+}
+
+impl ParseError {
+    /// The kind of mistake in the query
+    pub fn kind(&self) -> &ParseErrorKind {
+        &self.kind
+    }
+
+    /// The byte offset in the query where the error was found
+    pub fn position(&self) -> usize {
+        self.pos
+    }
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let pos = self.pos;
+        match &self.kind {
+            ParseErrorKind::UnexpectedEnd => write!(f, "unexpected end of query at position {pos}"),
+            ParseErrorKind::UnexpectedToken(token) => {
+                write!(f, "unexpected `{token}` at position {pos}")
+            }
+            ParseErrorKind::UnclosedQuote => write!(f, "unclosed quote starting at position {pos}"),
+            ParseErrorKind::InvalidEscape(c) => {
+                write!(f, "invalid escape `\\{c}` at position {pos}")
+            }
+            ParseErrorKind::UnclosedParen => {
+                write!(f, "unclosed parenthesis starting at position {pos}")
+            }
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl std::error::Error for ParseError {}
+
+/// The ways parsing a query can fail
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum ParseErrorKind {
+    /// The query ended where a term was expected
+    UnexpectedEnd,
+    /// A word or symbol appeared where it isn't allowed
+    UnexpectedToken(String),
+    /// A quoted string was never closed
+    UnclosedQuote,
+    /// A backslash in a quoted string was followed by something other than `"` or `\`
+    InvalidEscape(char),
+    /// An opening parenthesis was never closed
+    UnclosedParen,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum TokenKind {
+    Open,
+    Close,
+    Colon,
+    And,
+    Or,
+    Not,
+    Word(String),
+}
+
+#[derive(Clone)]
+struct Token {
+    kind: TokenKind,
+    span: Range<usize>,
+}
+
+fn is_special(c: char) -> bool {
+    c.is_whitespace() || matches!(c, '(' | ')' | ':' | '"')
+}
+
+fn tokenize(input: &str) -> Result<Vec<Token>, ParseError> {
+    let mut out = Vec::new();
+    let mut chars = input.char_indices().peekable();
+
+    while let Some((start, c)) = chars.next() {
+        let kind = match c {
+            c if c.is_whitespace() => continue,
+            '(' => TokenKind::Open,
+            ')' => TokenKind::Close,
+            ':' => TokenKind::Colon,
+            '"' => {
+                let mut word = String::new();
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((_, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\'))) => word.push(c),
+                            Some((pos, c)) => {
+                                return Err(ParseError {
+                                    kind: ParseErrorKind::InvalidEscape(c),
+                                    pos: pos - 1,
+                                })
+                            }
+                            None => {
+                                return Err(ParseError {
+                                    kind: ParseErrorKind::UnclosedQuote,
+                                    pos: start,
+                                })
+                            }
+                        },
+                        Some((_, c)) => word.push(c),
+                        None => {
+                            return Err(ParseError {
+                                kind: ParseErrorKind::UnclosedQuote,
+                                pos: start,
+                            })
+                        }
+                    }
+                }
+                TokenKind::Word(word)
+            }
+            c => {
+                let mut word = String::from(c);
+                while let Some(&(_, c)) = chars.peek() {
+                    if is_special(c) {
+                        break;
+                    }
+                    word.push(c);
+                    chars.next();
+                }
+
+                if word.eq_ignore_ascii_case("and") {
+                    TokenKind::And
+                } else if word.eq_ignore_ascii_case("or") {
+                    TokenKind::Or
+                } else if word.eq_ignore_ascii_case("not") {
+                    TokenKind::Not
+                } else {
+                    TokenKind::Word(word)
+                }
+            }
+        };
+
+        let end = chars.peek().map_or(input.len(), |&(pos, _)| pos);
+        out.push(Token {
+            kind,
+            span: start..end,
+        });
+    }
+
+    Ok(out)
+}
+
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser<'_> {
+    fn peek(&self) -> Option<&TokenKind> {
+        self.tokens.get(self.pos).map(|token| &token.kind)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let out = self.tokens.get(self.pos).cloned();
+        if out.is_some() {
+            self.pos += 1;
+        }
+        out
+    }
+
+    fn unexpected(&self, token: Option<Token>) -> ParseError {
+        match token {
+            Some(token) => ParseError {
+                kind: ParseErrorKind::UnexpectedToken(self.input[token.span.clone()].to_owned()),
+                pos: token.span.start,
+            },
+            None => ParseError {
+                kind: ParseErrorKind::UnexpectedEnd,
+                pos: self.input.len(),
+            },
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<TagPredicate, ParseError> {
+        let mut preds = alloc::vec![self.parse_and()?];
+        while self.peek() == Some(&TokenKind::Or) {
+            self.pos += 1;
+            preds.push(self.parse_and()?);
+        }
+        Ok(collapse(preds, TagPredicate::Or))
+    }
+
+    fn parse_and(&mut self) -> Result<TagPredicate, ParseError> {
+        let mut preds = alloc::vec![self.parse_not()?];
+        loop {
+            match self.peek() {
+                Some(TokenKind::And) => self.pos += 1,
+                Some(TokenKind::Open | TokenKind::Not | TokenKind::Word(_)) => (),
+                _ => break,
+            }
+            preds.push(self.parse_not()?);
+        }
+        Ok(collapse(preds, TagPredicate::And))
+    }
+
+    fn parse_not(&mut self) -> Result<TagPredicate, ParseError> {
+        if self.peek() == Some(&TokenKind::Not) {
+            self.pos += 1;
+            Ok(TagPredicate::Not(Box::new(self.parse_not()?)))
+        } else {
+            self.parse_term()
+        }
+    }
+
+    fn parse_term(&mut self) -> Result<TagPredicate, ParseError> {
+        let Some(token) = self.next() else {
+            return Err(self.unexpected(None));
+        };
+
+        match token.kind {
+            TokenKind::Open => {
+                let inner = self.parse_or()?;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::Close,
+                        ..
+                    }) => Ok(inner),
+                    Some(other) => Err(self.unexpected(Some(other))),
+                    None => Err(ParseError {
+                        kind: ParseErrorKind::UnclosedParen,
+                        pos: token.span.start,
+                    }),
+                }
+            }
+            TokenKind::Word(first) => {
+                if self.peek() != Some(&TokenKind::Colon) {
+                    return Ok(TagPredicate::Tag(Tag::named(first)));
+                }
+                self.pos += 1;
+                match self.next() {
+                    Some(Token {
+                        kind: TokenKind::Word(name),
+                        ..
+                    }) => Ok(TagPredicate::Tag(Tag::new(Group::from(first), name))),
+                    other => Err(self.unexpected(other)),
+                }
+            }
+            _ => Err(self.unexpected(Some(token))),
+        }
+    }
+}
+
+/// Unwrap a single predicate, rather than wrapping it in a one-item `And` or `Or`
+fn collapse(
+    mut preds: Vec<TagPredicate>,
+    wrap: fn(Vec<TagPredicate>) -> TagPredicate,
+) -> TagPredicate {
+    if preds.len() == 1 {
+        preds.remove(0)
+    } else {
+        wrap(preds)
+    }
+}
+
+impl TagPredicate {
+    /// Parse a predicate from a text query, such as `photos:holiday AND (beach OR NOT city)`.
+    ///
+    /// A query is made of terms, each either a bare tag name like `holiday`, or a group and name
+    /// separated by a colon like `photos:holiday`. Names containing spaces or special characters
+    /// can be quoted, as in `"my group":"a name"`, with `\"` and `\\` escapes inside quotes.
+    ///
+    /// Terms are combined with `NOT`, `AND` and `OR`, in order of decreasing precedence, and
+    /// grouped with parentheses. Terms next to each other with no operator between them are
+    /// `AND`ed, and operators are case-insensitive. To use an operator as a tag name, quote it.
+    pub fn parse(input: &str) -> Result<TagPredicate, ParseError> {
+        let mut parser = Parser {
+            input,
+            tokens: tokenize(input)?,
+            pos: 0,
+        };
+
+        let pred = parser.parse_or()?;
+        match parser.next() {
+            Some(token) => Err(parser.unexpected(Some(token))),
+            None => Ok(pred),
+        }
+    }
+}
+
+impl FromStr for TagPredicate {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        TagPredicate::parse(s)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        let pred = TagPredicate::parse("group:name AND (a OR NOT b) c").unwrap();
+        assert_eq!(
+            pred,
+            TagPredicate::And(alloc::vec![
+                TagPredicate::Tag(Tag::new(Group::custom("group"), "name")),
+                TagPredicate::or([
+                    TagPredicate::tag(Tag::named("a")),
+                    TagPredicate::not(Tag::named("b")),
+                ]),
+                TagPredicate::Tag(Tag::named("c")),
+            ])
+        );
+
+        let pred = TagPredicate::parse(r#"a or b and "and":"x \"y\"""#).unwrap();
+        assert_eq!(
+            pred,
+            TagPredicate::or([
+                TagPredicate::tag(Tag::named("a")),
+                TagPredicate::and([Tag::named("b"), Tag::new(Group::custom("and"), "x \"y\"")]),
+            ])
+        );
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        let err = TagPredicate::parse("a AND").unwrap_err();
+        assert_eq!(
+            (err.kind(), err.position()),
+            (&ParseErrorKind::UnexpectedEnd, 5)
+        );
+
+        let err = TagPredicate::parse("(a OR b").unwrap_err();
+        assert_eq!(
+            (err.kind(), err.position()),
+            (&ParseErrorKind::UnclosedParen, 0)
+        );
+
+        let err = TagPredicate::parse("a ) b").unwrap_err();
+        assert_eq!(err.kind(), &ParseErrorKind::UnexpectedToken(")".to_owned()));
+
+        let err = TagPredicate::parse("g:\"name").unwrap_err();
+        assert_eq!(
+            (err.kind(), err.position()),
+            (&ParseErrorKind::UnclosedQuote, 2)
+        );
+    }
+}
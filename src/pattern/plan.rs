@@ -0,0 +1,232 @@
+//! Planning searches as set operations over an index, instead of checking every file
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+
+use super::{NormalForm, TagPredicate};
+use crate::FileId;
+
+/// What's known about which files match a predicate
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Lookup {
+    /// Exactly the files which match
+    Exact(BTreeSet<FileId>),
+    /// Every file which matches, and maybe some which don't, so each still needs checking
+    Superset(BTreeSet<FileId>),
+    /// Nothing, so every file needs checking
+    Unknown,
+}
+
+/// An index from tags to the files which have them, which a [`QueryPlan`] can be run against.
+/// Implemented by backends that keep such an index, so searches can be answered with set
+/// intersections and unions rather than a full scan.
+pub trait TagIndex {
+    /// The error type for reading the index
+    type Error;
+
+    /// Find the files with some tag matching a predicate. Only called with predicates for which
+    /// [`TagPredicate::is_atom`] holds.
+    fn lookup(&self, atom: &TagPredicate) -> Result<Lookup, Self::Error>;
+
+    /// Get every file, or `None` if the index doesn't know them. Only needed for terms with
+    /// nothing to look up, such as ones only made of negations.
+    fn all_files(&self) -> Result<Option<BTreeSet<FileId>>, Self::Error>;
+}
+
+/// One and of atoms in a plan, split by whether they're negated
+#[derive(Debug, Clone)]
+struct Term {
+    include: Vec<TagPredicate>,
+    exclude: Vec<TagPredicate>,
+}
+
+/// A predicate broken down for running against a [`TagIndex`]. The predicate is put in
+/// disjunctive normal form, then each term intersects the files of its atoms and subtracts the
+/// files of its negated atoms, and the terms are unioned together.
+///
+/// Atoms the index can't answer exactly are left for the caller to check, by matching the
+/// original predicate against each file in the result.
+#[derive(Debug, Clone)]
+pub struct QueryPlan {
+    terms: Vec<Term>,
+}
+
+impl QueryPlan {
+    /// Plan how to run a predicate against an index
+    pub fn new(pred: &TagPredicate) -> QueryPlan {
+        let TagPredicate::Or(terms) = pred.normalize(NormalForm::Disjunctive) else {
+            unreachable!("Disjunctive normal form is always an or");
+        };
+
+        let terms = terms
+            .into_iter()
+            .map(|term| {
+                let TagPredicate::And(items) = term else {
+                    unreachable!("Disjunctive normal form terms are always ands");
+                };
+                let mut out = Term {
+                    include: Vec::new(),
+                    exclude: Vec::new(),
+                };
+                for item in items {
+                    match item {
+                        TagPredicate::Not(atom) => out.exclude.push(*atom),
+                        atom => out.include.push(atom),
+                    }
+                }
+                out
+            })
+            .collect();
+
+        QueryPlan { terms }
+    }
+
+    /// Whether the predicate can never match, so there's no need to look anything up
+    pub fn is_empty(&self) -> bool {
+        self.terms.is_empty()
+    }
+
+    /// Run this plan against an index, finding which files could match
+    pub fn execute<I: TagIndex + ?Sized>(&self, index: &I) -> Result<Lookup, I::Error> {
+        let mut out = BTreeSet::new();
+        let mut exact = true;
+
+        for term in &self.terms {
+            let mut found: Option<BTreeSet<FileId>> = None;
+
+            for atom in &term.include {
+                if found.as_ref().is_some_and(BTreeSet::is_empty) {
+                    break;
+                }
+                let ids = match index.lookup(atom)? {
+                    Lookup::Exact(ids) => ids,
+                    Lookup::Superset(ids) => {
+                        exact = false;
+                        ids
+                    }
+                    Lookup::Unknown => {
+                        exact = false;
+                        continue;
+                    }
+                };
+                found = Some(match found {
+                    Some(found) => found.intersection(&ids).copied().collect(),
+                    None => ids,
+                });
+            }
+
+            let mut found = match found {
+                Some(found) => found,
+                None => match index.all_files()? {
+                    Some(all) => all,
+                    None => return Ok(Lookup::Unknown),
+                },
+            };
+
+            for atom in &term.exclude {
+                if found.is_empty() {
+                    break;
+                }
+                // Only files known to match can be taken out
+                match index.lookup(atom)? {
+                    Lookup::Exact(ids) => found.retain(|id| !ids.contains(id)),
+                    Lookup::Superset(_) | Lookup::Unknown => exact = false,
+                }
+            }
+
+            out.extend(found);
+        }
+
+        Ok(if exact {
+            Lookup::Exact(out)
+        } else {
+            Lookup::Superset(out)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Group, Tag, TagPattern};
+    use alloc::collections::BTreeMap;
+    use core::convert::Infallible;
+
+    /// Files and their tags, which can only look up exact tags and groups
+    struct Files(BTreeMap<FileId, Vec<Tag>>);
+
+    impl TagIndex for Files {
+        type Error = Infallible;
+
+        fn lookup(&self, atom: &TagPredicate) -> Result<Lookup, Infallible> {
+            Ok(match atom {
+                TagPredicate::Tag(_) | TagPredicate::Group(_) => Lookup::Exact(
+                    self.0
+                        .iter()
+                        .filter(|(_, tags)| atom.match_tags(tags.iter()))
+                        .map(|(id, _)| *id)
+                        .collect(),
+                ),
+                TagPredicate::Name(_) => Lookup::Superset(self.0.keys().copied().collect()),
+                _ => Lookup::Unknown,
+            })
+        }
+
+        fn all_files(&self) -> Result<Option<BTreeSet<FileId>>, Infallible> {
+            Ok(Some(self.0.keys().copied().collect()))
+        }
+    }
+
+    #[test]
+    fn test_plan() {
+        let id = FileId::from_u64_unchecked;
+        let files = Files(BTreeMap::from([
+            (id(1), alloc::vec![Tag::named("a")]),
+            (id(2), alloc::vec![Tag::named("a"), Tag::named("b")]),
+            (id(3), alloc::vec![Tag::new(Group::custom("g"), "b")]),
+            (id(4), Vec::new()),
+        ]));
+        let run = |pred: TagPredicate| QueryPlan::new(&pred).execute(&files).unwrap();
+        let ids = |ids: &[u64]| ids.iter().copied().map(id).collect::<BTreeSet<_>>();
+
+        assert_eq!(
+            run(TagPredicate::tag(Tag::named("a"))),
+            Lookup::Exact(ids(&[1, 2]))
+        );
+        assert_eq!(
+            run(TagPredicate::and([
+                TagPredicate::tag(Tag::named("a")),
+                TagPredicate::not(Tag::named("b")),
+            ])),
+            Lookup::Exact(ids(&[1]))
+        );
+        assert_eq!(
+            run(TagPredicate::or([
+                TagPredicate::group(Group::custom("g")),
+                TagPredicate::not(TagPredicate::group(Group::Default)),
+            ])),
+            Lookup::Exact(ids(&[3, 4]))
+        );
+        assert_eq!(
+            run(TagPredicate::And(Vec::new())),
+            Lookup::Exact(ids(&[1, 2, 3, 4]))
+        );
+        assert_eq!(run(TagPredicate::Or(Vec::new())), Lookup::Exact(ids(&[])));
+
+        // Atoms which can't be looked up exactly leave files to check
+        assert_eq!(
+            run(TagPredicate::and([
+                TagPredicate::tag(Tag::named("b")),
+                TagPredicate::name_glob("*"),
+            ])),
+            Lookup::Superset(ids(&[2]))
+        );
+        assert_eq!(
+            run(TagPredicate::and([
+                TagPredicate::tag(Tag::named("a")),
+                TagPredicate::not(TagPredicate::name("b")),
+            ])),
+            Lookup::Superset(ids(&[1, 2]))
+        );
+    }
+}
@@ -0,0 +1,151 @@
+//! Shorthand for building [`TagPredicate`]s
+//!
+//! Predicates can be combined with `&`, `|` and `!`, and the functions here make the common
+//! leaves short enough that a query reads like the logic it expresses:
+//!
+//! ```
+//! use tbf::predicate::{group, tag};
+//! use tbf::TagPredicate;
+//!
+//! let pred = tag("a") & (tag("b") | !group("temp"));
+//! assert_eq!(
+//!     pred,
+//!     TagPredicate::and([
+//!         tag("a"),
+//!         TagPredicate::or([tag("b"), TagPredicate::not(group("temp"))]),
+//!     ]),
+//! );
+//! ```
+//!
+//! Chains of the same operator build a single flat predicate, so `a & b & c` is one
+//! [`TagPredicate::And`] of three predicates rather than two nested ones, and `!!a` is just `a`.
+//! Either side can be anything which converts into a predicate, such as a [`Tag`] or a [`Group`].
+
+use alloc::borrow::Cow;
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::ops::{BitAnd, BitAndAssign, BitOr, BitOrAssign, Not};
+
+use super::TagPredicate;
+use crate::{Group, Tag, TagValue};
+
+/// Match files with a tag in the default group, without a value
+pub fn tag<N: Into<Cow<'static, str>>>(name: N) -> TagPredicate {
+    TagPredicate::Tag(Tag::named(name))
+}
+
+/// Match files with a tag in a group, without a value
+pub fn tag_in<G, N>(group: G, name: N) -> TagPredicate
+where
+    G: Into<Group>,
+    N: Into<Cow<'static, str>>,
+{
+    TagPredicate::Tag(Tag::new(group, name))
+}
+
+/// Match files with any tag in a group. An empty name is the default group.
+pub fn group<G: Into<Group>>(group: G) -> TagPredicate {
+    TagPredicate::Group(group.into())
+}
+
+/// Match files with a tag of a name, in any group
+pub fn name(name: &str) -> TagPredicate {
+    TagPredicate::name(name)
+}
+
+/// Match files with a tag whose name contains a substring
+pub fn name_contains(substr: &str) -> TagPredicate {
+    TagPredicate::name_contains(substr)
+}
+
+/// Match files with a tag whose name matches a glob
+pub fn name_glob(glob: &str) -> TagPredicate {
+    TagPredicate::name_glob(glob)
+}
+
+/// Match files with a tag whose group matches a glob
+pub fn group_glob(glob: &str) -> TagPredicate {
+    TagPredicate::group_glob(glob)
+}
+
+/// Match files with a tag in the default group whose value is greater than the given one
+pub fn value_gt<V: Into<TagValue>>(name: &str, value: V) -> TagPredicate {
+    TagPredicate::value_gt(Group::Default, name, value)
+}
+
+/// Match files with a tag in the default group whose value is less than the given one
+pub fn value_lt<V: Into<TagValue>>(name: &str, value: V) -> TagPredicate {
+    TagPredicate::value_lt(Group::Default, name, value)
+}
+
+/// Match files with a tag in the default group whose value is within an inclusive range
+pub fn value_range<V: Into<TagValue>>(name: &str, min: V, max: V) -> TagPredicate {
+    TagPredicate::value_range(Group::Default, name, min, max)
+}
+
+/// Match every file
+pub fn all() -> TagPredicate {
+    TagPredicate::And(Vec::new())
+}
+
+/// Match no files
+pub fn none() -> TagPredicate {
+    TagPredicate::Or(Vec::new())
+}
+
+impl<T: Into<TagPredicate>> BitAnd<T> for TagPredicate {
+    type Output = TagPredicate;
+
+    fn bitand(mut self, rhs: T) -> TagPredicate {
+        self &= rhs;
+        self
+    }
+}
+
+impl<T: Into<TagPredicate>> BitAndAssign<T> for TagPredicate {
+    fn bitand_assign(&mut self, rhs: T) {
+        let mut preds = match core::mem::replace(self, all()) {
+            TagPredicate::And(preds) => preds,
+            pred => alloc::vec![pred],
+        };
+        match rhs.into() {
+            TagPredicate::And(more) => preds.extend(more),
+            pred => preds.push(pred),
+        }
+        *self = TagPredicate::And(preds);
+    }
+}
+
+impl<T: Into<TagPredicate>> BitOr<T> for TagPredicate {
+    type Output = TagPredicate;
+
+    fn bitor(mut self, rhs: T) -> TagPredicate {
+        self |= rhs;
+        self
+    }
+}
+
+impl<T: Into<TagPredicate>> BitOrAssign<T> for TagPredicate {
+    fn bitor_assign(&mut self, rhs: T) {
+        let mut preds = match core::mem::replace(self, none()) {
+            TagPredicate::Or(preds) => preds,
+            pred => alloc::vec![pred],
+        };
+        match rhs.into() {
+            TagPredicate::Or(more) => preds.extend(more),
+            pred => preds.push(pred),
+        }
+        *self = TagPredicate::Or(preds);
+    }
+}
+
+impl Not for TagPredicate {
+    type Output = TagPredicate;
+
+    fn not(self) -> TagPredicate {
+        match self {
+            TagPredicate::Not(pred) => *pred,
+            pred => TagPredicate::Not(Box::new(pred)),
+        }
+    }
+}
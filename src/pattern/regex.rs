@@ -0,0 +1,55 @@
+//! Regular expressions usable in tag predicates
+
+use core::ops::Deref;
+
+use regex_lite::Regex;
+
+/// A compiled regular expression, for [`TagPredicate::NameRegex`](super::TagPredicate::NameRegex)
+/// and [`TagPredicate::GroupRegex`](super::TagPredicate::GroupRegex). Two regexes are equal if
+/// they were compiled from the same pattern.
+#[derive(Debug, Clone)]
+pub struct TagRegex(Regex);
+
+impl TagRegex {
+    /// Compile a new regular expression
+    pub fn new(regex: &str) -> Result<TagRegex, regex_lite::Error> {
+        Regex::new(regex).map(TagRegex)
+    }
+}
+
+impl From<Regex> for TagRegex {
+    fn from(regex: Regex) -> TagRegex {
+        TagRegex(regex)
+    }
+}
+
+impl Deref for TagRegex {
+    type Target = Regex;
+
+    fn deref(&self) -> &Regex {
+        &self.0
+    }
+}
+
+impl PartialEq for TagRegex {
+    fn eq(&self, other: &TagRegex) -> bool {
+        self.0.as_str() == other.0.as_str()
+    }
+}
+
+/// Serialized as the pattern it was compiled from
+#[cfg(feature = "serde")]
+impl serde::Serialize for TagRegex {
+    fn serialize<S: serde::Serializer>(&self, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(self.0.as_str())
+    }
+}
+
+/// Compiled again from the serialized pattern, failing if it isn't valid
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for TagRegex {
+    fn deserialize<D: serde::Deserializer<'de>>(d: D) -> Result<TagRegex, D::Error> {
+        let regex = <alloc::string::String as serde::Deserialize>::deserialize(d)?;
+        TagRegex::new(&regex).map_err(serde::de::Error::custom)
+    }
+}
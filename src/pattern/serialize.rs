@@ -0,0 +1,113 @@
+//! The serialized shape of the parts of a predicate, matching the JSON used over HTTP
+
+use alloc::borrow::Cow;
+use alloc::string::String;
+
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::group_name;
+use crate::{Group, Tag, TagValue};
+
+/// A value as an object with a single key naming its type
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ValueRepr<S> {
+    String(S),
+    Int(i64),
+    Float(f64),
+    Bool(bool),
+    Timestamp(i64),
+}
+
+impl<'a> From<&'a TagValue> for ValueRepr<&'a str> {
+    fn from(value: &'a TagValue) -> ValueRepr<&'a str> {
+        match value {
+            TagValue::String(val) => ValueRepr::String(val),
+            TagValue::Int(val) => ValueRepr::Int(*val),
+            TagValue::Float(val) => ValueRepr::Float(*val),
+            TagValue::Bool(val) => ValueRepr::Bool(*val),
+            TagValue::Timestamp(val) => ValueRepr::Timestamp(*val),
+        }
+    }
+}
+
+impl From<ValueRepr<String>> for TagValue {
+    fn from(value: ValueRepr<String>) -> TagValue {
+        match value {
+            ValueRepr::String(val) => TagValue::String(Cow::Owned(val)),
+            ValueRepr::Int(val) => TagValue::Int(val),
+            ValueRepr::Float(val) => TagValue::Float(val),
+            ValueRepr::Bool(val) => TagValue::Bool(val),
+            ValueRepr::Timestamp(val) => TagValue::Timestamp(val),
+        }
+    }
+}
+
+/// A tag as an object with a `name`, a `group` unless it's the default, and a `value` if it has
+/// one
+#[derive(Serialize, Deserialize)]
+struct TagRepr<S: AsRef<str>> {
+    #[serde(default, skip_serializing_if = "str_is_empty")]
+    group: S,
+    name: S,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    value: Option<ValueRepr<S>>,
+}
+
+fn str_is_empty<S: AsRef<str>>(s: &S) -> bool {
+    s.as_ref().is_empty()
+}
+
+pub(super) fn is_default_group(group: &Group) -> bool {
+    *group == Group::Default
+}
+
+/// Groups as their name, which is empty for the default group
+pub(super) mod group {
+    use super::{group_name, Deserialize, Deserializer, Group, Serializer, String};
+
+    pub(crate) fn serialize<S: Serializer>(group: &Group, s: S) -> Result<S::Ok, S::Error> {
+        s.serialize_str(group_name(group))
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Group, D::Error> {
+        String::deserialize(d).map(Group::from)
+    }
+}
+
+pub(super) mod value {
+    use super::{Deserialize, Deserializer, Serialize, Serializer, String, TagValue, ValueRepr};
+
+    pub(crate) fn serialize<S: Serializer>(value: &TagValue, s: S) -> Result<S::Ok, S::Error> {
+        ValueRepr::from(value).serialize(s)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<TagValue, D::Error> {
+        ValueRepr::<String>::deserialize(d).map(TagValue::from)
+    }
+}
+
+pub(super) mod tag {
+    use super::{
+        group_name, Deserialize, Deserializer, Group, Serialize, Serializer, String, Tag, TagRepr,
+        TagValue, ValueRepr,
+    };
+
+    pub(crate) fn serialize<S: Serializer>(tag: &Tag, s: S) -> Result<S::Ok, S::Error> {
+        TagRepr {
+            group: group_name(tag.group()),
+            name: tag.name(),
+            value: tag.value().map(ValueRepr::from),
+        }
+        .serialize(s)
+    }
+
+    pub(crate) fn deserialize<'de, D: Deserializer<'de>>(d: D) -> Result<Tag, D::Error> {
+        let repr = TagRepr::<String>::deserialize(d)?;
+        let tag = Tag::new(Group::from(repr.group), repr.name);
+        Ok(match repr.value {
+            Some(value) => tag.with_value(TagValue::from(value)),
+            None => tag,
+        })
+    }
+}
@@ -0,0 +1,1286 @@
+//! Implementation of a TBF stored in a `PostgreSQL` database
+
+use core::convert::TryFrom;
+use core::fmt::{self, Write as _};
+use core::ops::Range;
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::io::{self, Cursor};
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+use std::time::SystemTime;
+
+use postgres::types::ToSql;
+use postgres::{Client, NoTls, Row};
+
+use crate::codec::{read_value, write_value};
+use crate::error::ErrorKind;
+use crate::events::{Event, Subscribers};
+use crate::inference::{infer_tags, Inferrers};
+use crate::metadata::{hash_data, Metadata};
+use crate::provider::Providers;
+use crate::search::{self, SearchOptions, SortBy, SortKey};
+use crate::{
+    check_stored, provider, BatchResult, BufferedWriter, FileId, FileInfo, FileSystemRead,
+    FileSystemWrite, Group, InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag,
+    TagInferrer, TagPattern, TagPredicate, TagProvider, TagValue,
+};
+
+/// Tables are only created if they don't already exist, so opening an existing database leaves
+/// its contents alone
+const SCHEMA: &str = "
+CREATE SEQUENCE IF NOT EXISTS tbf_ids MINVALUE 256 START 256;
+CREATE TABLE IF NOT EXISTS tbf_files (
+    id BIGINT PRIMARY KEY,
+    data BYTEA NOT NULL,
+    size BIGINT NOT NULL,
+    hash BYTEA NOT NULL,
+    created TIMESTAMPTZ NOT NULL,
+    modified TIMESTAMPTZ NOT NULL
+);
+CREATE TABLE IF NOT EXISTS tbf_tags (
+    file BIGINT NOT NULL REFERENCES tbf_files (id) ON DELETE CASCADE,
+    grp TEXT,
+    name TEXT NOT NULL,
+    value BYTEA,
+    kind SMALLINT,
+    text_value TEXT,
+    int_value BIGINT,
+    float_value DOUBLE PRECISION,
+    bool_value BOOLEAN
+);
+CREATE INDEX IF NOT EXISTS tbf_tags_file ON tbf_tags (file);
+CREATE INDEX IF NOT EXISTS tbf_tags_tag ON tbf_tags (grp, name, value);
+CREATE INDEX IF NOT EXISTS tbf_tags_name ON tbf_tags (name);
+CREATE INDEX IF NOT EXISTS tbf_files_hash ON tbf_files (hash);
+CREATE TABLE IF NOT EXISTS tbf_settings (
+    name TEXT PRIMARY KEY,
+    value BYTEA NOT NULL
+);
+";
+
+/// How many IDs a lazy search fetches at once
+const PAGE_LEN: usize = 256;
+
+/// Error for a `PostgreSQL` filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// The requested file did not exist
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// The ID is too large to store, as Postgres only has signed 64-bit integers
+    InvalidId(FileId),
+    /// The database returned an error
+    Postgres(postgres::Error),
+    /// A value in the database couldn't be decoded
+    IoError(io::Error),
+    /// A thread panic poisoned the connection
+    Poisoned,
+}
+
+impl<T> From<PoisonError<T>> for Error {
+    fn from(_: PoisonError<T>) -> Error {
+        Error::Poisoned
+    }
+}
+
+impl From<postgres::Error> for Error {
+    fn from(err: postgres::Error) -> Error {
+        Error::Postgres(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl crate::error::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Self::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Self::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Self::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Self::IoError(io::ErrorKind::OutOfMemory.into())
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Self::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Self::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Self::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Self::Postgres(e) => ErrorKind::Source(e),
+            Self::IoError(e) => ErrorKind::Source(e),
+            Self::InvalidId(_) => ErrorKind::Other,
+            Self::Poisoned => ErrorKind::State,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::InvalidId(id) => write!(f, "file ID {id} is too large to store"),
+            Error::Postgres(err) => write!(f, "database error: {err}"),
+            Error::IoError(err) => write!(f, "value in the database is malformed: {err}"),
+            Error::Poisoned => write!(f, "connection was poisoned by a thread panic"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Postgres(err) => std::error::Error::source(err),
+            Error::IoError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+/// The connection, and whether a call to `transaction` is running on it
+struct Conn {
+    client: Client,
+    in_txn: bool,
+}
+
+/// A tag-based filesystem stored in a `PostgreSQL` database. File data is stored as `bytea`, and
+/// each tag as a row of its own, with values in typed columns.
+///
+/// Searches are translated into SQL and run by the server, so only the IDs of matching files are
+/// sent back. Parts of a search which can't be expressed in SQL, such as regular expressions, are
+/// checked locally against the files the server finds. While a [`TagProvider`] is registered,
+/// every file is checked locally instead, as provided tags aren't stored.
+///
+/// Tables are named with a `tbf_` prefix, and created in the connection's current schema if they
+/// don't exist yet. File IDs are never reused, and prior versions of file data aren't kept.
+pub struct PostgresFs {
+    conn: Mutex<Conn>,
+    providers: RwLock<Providers>,
+    inferrers: RwLock<Inferrers>,
+    subscribers: Subscribers,
+}
+
+impl PostgresFs {
+    /// Connect to a database, without TLS. The parameters are either a URL or a list of
+    /// `key=value` pairs, as described by [`postgres::Config`].
+    pub fn connect(params: &str) -> Result<PostgresFs, Error> {
+        PostgresFs::from_client(Client::connect(params, NoTls)?)
+    }
+
+    /// Use an existing connection to a database, such as one using TLS
+    pub fn from_client(mut client: Client) -> Result<PostgresFs, Error> {
+        client.batch_execute(SCHEMA)?;
+        Ok(PostgresFs {
+            conn: Mutex::new(Conn {
+                client,
+                in_txn: false,
+            }),
+            providers: RwLock::new(BTreeMap::new()),
+            inferrers: RwLock::new(Vec::new()),
+            subscribers: Subscribers::new(),
+        })
+    }
+
+    fn client(&self) -> Result<MutexGuard<'_, Conn>, Error> {
+        Ok(self.conn.lock()?)
+    }
+
+    /// Run a closure changing the database, all at once. It joins the running transaction if
+    /// there is one, otherwise its changes are committed once it succeeds.
+    fn with_write<T>(&self, f: impl FnOnce(&mut Client) -> Result<T, Error>) -> Result<T, Error> {
+        let mut conn = self.client()?;
+        // A savepoint lets a failed change be undone without aborting the whole transaction
+        let (begin, commit, rollback) = if conn.in_txn {
+            (
+                "SAVEPOINT tbf_change",
+                "RELEASE SAVEPOINT tbf_change",
+                "ROLLBACK TO SAVEPOINT tbf_change; RELEASE SAVEPOINT tbf_change",
+            )
+        } else {
+            ("BEGIN", "COMMIT", "ROLLBACK")
+        };
+
+        conn.client.batch_execute(begin)?;
+        let out = f(&mut conn.client);
+        conn.client
+            .batch_execute(if out.is_ok() { commit } else { rollback })?;
+        out
+    }
+
+    /// Translate a search into a condition on files, or one matching every file if the search
+    /// needs provided tags
+    fn condition<P: TagPattern>(&self, pattern: &P) -> Result<Where, Error> {
+        if self.providers.read()?.is_empty() {
+            Ok(Where::new(&pattern.to_predicate()))
+        } else {
+            Ok(Where::any())
+        }
+    }
+
+    /// Check whether a file found by an inexact condition really matches a search, skipping it if
+    /// it's been removed since
+    fn recheck<P: TagPattern>(&self, pattern: &P, id: FileId) -> Result<bool, Error> {
+        match self.get_tags(id) {
+            Ok(tags) => Ok(pattern.match_tags(&tags)),
+            Err(Error::FileNotFound(_)) => Ok(false),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn emit(&self, event: Event) {
+        self.subscribers.emit(event);
+    }
+}
+
+impl FileSystemRead for PostgresFs {
+    type Error = Error;
+    type SearchIter<'a, P: TagPattern + 'a> = SearchIter<'a, P>;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let cond = self.condition(&tags)?;
+        let rows = self.client()?.client.query(
+            &format!(
+                "SELECT f.id FROM tbf_files f WHERE {} ORDER BY f.id",
+                cond.sql
+            ),
+            &cond.params(),
+        )?;
+
+        let mut out = Vec::with_capacity(rows.len());
+        for row in rows {
+            let id = file_id(row.get(0));
+            if cond.exact || self.recheck(&tags, id)? {
+                out.push(id);
+            }
+        }
+        Ok(out)
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        if options.is_lazy() {
+            let cond = self.condition(&tags)?;
+            if !cond.exact {
+                return search::page_lazy(self.search_tags_iter(tags), options);
+            }
+
+            // The whole page can be found by the server. IDs past those Postgres can store have
+            // no files after them.
+            let Some(after) = options.cursor().map_or(Some(-1), sql_id) else {
+                return Ok(Vec::new());
+            };
+            let offset = i64::try_from(options.skipped()).unwrap_or(i64::MAX);
+            let limit = options
+                .max_len()
+                .map(|limit| i64::try_from(limit).unwrap_or(i64::MAX));
+            let mut params = cond.params();
+            let sql = format!(
+                "SELECT f.id FROM tbf_files f WHERE f.id > ${} AND {} ORDER BY f.id OFFSET ${} LIMIT ${}",
+                params.len() + 1,
+                cond.sql,
+                params.len() + 2,
+                params.len() + 3,
+            );
+            params.extend::<[&(dyn ToSql + Sync); 3]>([&after, &offset, &limit]);
+            let rows = self.client()?.client.query(&sql, &params)?;
+            return Ok(rows.into_iter().map(|row| file_id(row.get(0))).collect());
+        }
+
+        let ids = self.search_tags(tags)?;
+        let sql_ids = ids.iter().filter_map(|&id| sql_id(id)).collect::<Vec<_>>();
+        let mut keys = BTreeMap::new();
+        match options.sort() {
+            SortBy::Id => (),
+            SortBy::Created | SortBy::Size => {
+                let rows = self.client()?.client.query(
+                    "SELECT id, created, size FROM tbf_files WHERE id = ANY($1)",
+                    &[&sql_ids],
+                )?;
+                for row in rows {
+                    let key = if let SortBy::Created = options.sort() {
+                        SortKey::Time(row.get(1))
+                    } else {
+                        SortKey::Size(u64::try_from(row.get::<_, i64>(2)).unwrap_or_default())
+                    };
+                    keys.insert(file_id(row.get(0)), key);
+                }
+            }
+            SortBy::Value { group, name } if self.providers.read()?.is_empty() => {
+                let mut cond = Where::any();
+                let sql = format!(
+                    "SELECT t.file, t.value FROM tbf_tags t WHERE {} AND t.name = {} \
+                     AND t.value IS NOT NULL AND t.file = ANY({})",
+                    cond.group(group),
+                    cond.param(name.clone()),
+                    cond.param(sql_ids),
+                );
+                let mut values = BTreeMap::<FileId, TagValue>::new();
+                for row in self.client()?.client.query(&sql, &cond.params())? {
+                    let value = read_value(&mut row.get::<_, &[u8]>(1))?;
+                    values
+                        .entry(file_id(row.get(0)))
+                        .and_modify(|min| {
+                            if value < *min {
+                                *min = value.clone();
+                            }
+                        })
+                        .or_insert(value);
+                }
+                for (id, value) in values {
+                    keys.insert(id, SortKey::Value(Some(value)));
+                }
+            }
+            SortBy::Value { group, name } => {
+                for &id in &ids {
+                    keys.insert(id, search::value_key(&self.get_tags(id)?, group, name));
+                }
+            }
+        }
+
+        search::sort_results(ids, options, |id| match options.sort() {
+            SortBy::Id => Ok(SortKey::Id),
+            SortBy::Value { .. } => Ok(keys.remove(&id).unwrap_or(SortKey::Value(None))),
+            _ => keys.remove(&id).ok_or(Error::FileNotFound(id)),
+        })
+    }
+
+    fn count_tags<P>(&self, tags: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let cond = self.condition(&tags)?;
+        if !cond.exact {
+            return Ok(self.search_tags(tags)?.len() as u64);
+        }
+        let row = self.client()?.client.query_one(
+            &format!("SELECT count(*) FROM tbf_files f WHERE {}", cond.sql),
+            &cond.params(),
+        )?;
+        Ok(u64::try_from(row.get::<_, i64>(0)).unwrap_or_default())
+    }
+
+    fn any_match<P>(&self, tags: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let cond = self.condition(&tags)?;
+        if !cond.exact {
+            return Ok(self.search_tags_iter(tags).next().transpose()?.is_some());
+        }
+        let row = self.client()?.client.query_one(
+            &format!(
+                "SELECT EXISTS (SELECT 1 FROM tbf_files f WHERE {})",
+                cond.sql
+            ),
+            &cond.params(),
+        )?;
+        Ok(row.get(0))
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags,
+            cond: None,
+            page: VecDeque::new(),
+            cursor: -1,
+            exhausted: false,
+            done: false,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let mut tags = self.stored_tags(id)?;
+        let data = self.get_data(id)?;
+        tags.extend(provider::provide_tags(&*self.providers.read()?, &data));
+
+        Ok(FileInfo {
+            id,
+            tags,
+            data: data.into_boxed_slice(),
+        })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        if !self.providers.read()?.is_empty() {
+            return Ok(self.get_info(id)?.tags);
+        }
+        self.stored_tags(id)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.stored_tags(id)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        let tags = self.stored_tags_many(ids)?;
+        let sql = ids.iter().copied().filter_map(sql_id).collect::<Vec<_>>();
+        let data = self
+            .client()?
+            .client
+            .query("SELECT id, data FROM tbf_files WHERE id = ANY($1)", &[&sql])?
+            .into_iter()
+            .map(|row| (file_id(row.get(0)), row.get::<_, Vec<u8>>(1)))
+            .collect::<BTreeMap<_, _>>();
+        let providers = self.providers.read()?;
+
+        Ok(ids
+            .iter()
+            .map(|&id| {
+                // A file removed between the queries is missing from one of them
+                let (Some(tags), Some(data)) = (tags.get(&id), data.get(&id)) else {
+                    return Err(Error::FileNotFound(id));
+                };
+                let mut tags = tags.clone();
+                tags.extend(provider::provide_tags(&providers, data));
+                Ok(FileInfo {
+                    id,
+                    tags,
+                    data: data.as_slice().into(),
+                })
+            })
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        if !self.providers.read()?.is_empty() {
+            return Ok(self
+                .get_info_many(ids)?
+                .into_iter()
+                .map(|info| info.map(|info| info.tags))
+                .collect());
+        }
+        let tags = self.stored_tags_many(ids)?;
+        Ok(ids
+            .iter()
+            .map(|&id| tags.get(&id).cloned().ok_or(Error::FileNotFound(id)))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        let sql = sql_id(id).ok_or(Error::FileNotFound(id))?;
+        self.client()?
+            .client
+            .query_opt("SELECT data FROM tbf_files WHERE id = $1", &[&sql])?
+            .map(|row| row.get(0))
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        let sql = sql_id(id).ok_or(Error::FileNotFound(id))?;
+        // Data is at most a gigabyte, so anything past what fits is past the end anyway
+        let start = i32::try_from(range.start.saturating_add(1)).unwrap_or(i32::MAX);
+        let len = i32::try_from(range.end.saturating_sub(range.start)).unwrap_or(i32::MAX);
+        self.client()?
+            .client
+            .query_opt(
+                "SELECT substring(data FROM $2 FOR $3) FROM tbf_files WHERE id = $1",
+                &[&sql, &start, &len],
+            )?
+            .map(|row| row.get(0))
+            .ok_or(Error::FileNotFound(id))
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let sql = sql_id(id).ok_or(Error::FileNotFound(id))?;
+        let row = self
+            .client()?
+            .client
+            .query_opt(
+                "SELECT created, modified, size, hash FROM tbf_files WHERE id = $1",
+                &[&sql],
+            )?
+            .ok_or(Error::FileNotFound(id))?;
+
+        let hash = <[u8; 32]>::try_from(row.get::<_, &[u8]>(3))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Invalid file hash"))?;
+        Ok(Metadata {
+            created: row.get(0),
+            modified: row.get(1),
+            size: u64::try_from(row.get::<_, i64>(2)).unwrap_or_default(),
+            hash,
+        })
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        let row = self
+            .client()?
+            .client
+            .query_one("SELECT last_value, is_called FROM tbf_ids", &[])?;
+        Ok(row.get::<_, bool>(1).then(|| file_id(row.get(0))))
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        let Some(after) = sql_id(after) else {
+            return Ok(Vec::new());
+        };
+        let rows = self.client()?.client.query(
+            "SELECT id FROM tbf_files WHERE id > $1 ORDER BY id",
+            &[&after],
+        )?;
+        Ok(rows.into_iter().map(|row| file_id(row.get(0))).collect())
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let cond = self.condition(&pattern)?;
+        let mut out = BTreeMap::new();
+        if !cond.exact {
+            for id in self.search_tags(pattern)? {
+                for tag in self.get_tags(id)? {
+                    *out.entry(tag).or_insert(0) += 1;
+                }
+            }
+            return Ok(out);
+        }
+
+        let rows = self.client()?.client.query(
+            &format!(
+                "SELECT t.grp, t.name, t.value, count(*) FROM tbf_tags t \
+                 JOIN tbf_files f ON f.id = t.file WHERE {} GROUP BY t.grp, t.name, t.value",
+                cond.sql
+            ),
+            &cond.params(),
+        )?;
+        for row in rows {
+            let count = usize::try_from(row.get::<_, i64>(3)).unwrap_or_default();
+            out.insert(tag_from_row(&row)?, count);
+        }
+        Ok(out)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        let rows = self.client()?.client.query(
+            "SELECT array_agg(id ORDER BY id) FROM tbf_files GROUP BY hash \
+             HAVING count(*) > 1 ORDER BY min(id)",
+            &[],
+        )?;
+        Ok(rows
+            .into_iter()
+            .map(|row| row.get::<_, Vec<i64>>(0).into_iter().map(file_id).collect())
+            .collect())
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        let row = self.client()?.client.query_opt(
+            "SELECT id FROM tbf_files WHERE hash = $1 ORDER BY id LIMIT 1",
+            &[&&hash[..]],
+        )?;
+        Ok(row.map(|row| file_id(row.get(0))))
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self
+            .client()?
+            .client
+            .query_opt(
+                "SELECT value FROM tbf_settings WHERE name = $1",
+                &[&file.name()],
+            )?
+            .map(|row| row.get(0))
+            .unwrap_or_default())
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl PostgresFs {
+    /// Get the tags stored for a file, without any provided ones
+    /// Read the tags stored for several files in one query. Files which don't exist are left out.
+    fn stored_tags_many(&self, ids: &[FileId]) -> Result<BTreeMap<FileId, BTreeSet<Tag>>, Error> {
+        let sql = ids.iter().copied().filter_map(sql_id).collect::<Vec<_>>();
+        let rows = self.client()?.client.query(
+            "SELECT t.grp, t.name, t.value, f.id FROM tbf_files f \
+             LEFT JOIN tbf_tags t ON t.file = f.id WHERE f.id = ANY($1)",
+            &[&sql],
+        )?;
+
+        let mut out = BTreeMap::<_, BTreeSet<_>>::new();
+        for row in rows {
+            let tags = out.entry(file_id(row.get(3))).or_default();
+            if row.get::<_, Option<&str>>(1).is_some() {
+                tags.insert(tag_from_row(&row)?);
+            }
+        }
+        Ok(out)
+    }
+
+    fn stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Error> {
+        let sql = sql_id(id).ok_or(Error::FileNotFound(id))?;
+        let mut conn = self.client()?;
+        // Files without tags have no rows in the tag table, so their existence is checked too
+        let rows = conn.client.query(
+            "SELECT t.grp, t.name, t.value FROM tbf_files f \
+             LEFT JOIN tbf_tags t ON t.file = f.id WHERE f.id = $1",
+            &[&sql],
+        )?;
+        if rows.is_empty() {
+            return Err(Error::FileNotFound(id));
+        }
+
+        let mut tags = BTreeSet::new();
+        for row in rows {
+            if row.get::<_, Option<&str>>(1).is_some() {
+                tags.insert(tag_from_row(&row)?);
+            }
+        }
+        Ok(tags)
+    }
+}
+
+impl FileSystemWrite for PostgresFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.inferrers.read()?, data));
+
+        let id = self.with_write(|client| {
+            let id = client
+                .query_one("SELECT nextval('tbf_ids')", &[])?
+                .get::<_, i64>(0);
+            insert_file(client, id, data)?;
+            insert_tags(client, id, &tags)?;
+            Ok(file_id(id))
+        })?;
+        self.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        if id.into_u64_unchecked() < 256 {
+            return Err(Error::AlreadyExists(id));
+        }
+        let sql = sql_id(id).ok_or(Error::InvalidId(id))?;
+
+        let mut tags = tags.into_iter().collect::<BTreeSet<_>>();
+        tags.extend(infer_tags(&*self.inferrers.read()?, data));
+
+        self.with_write(|client| {
+            if insert_file(client, sql, data)? == 0 {
+                return Err(Error::AlreadyExists(id));
+            }
+            insert_tags(client, sql, &tags)?;
+            client.execute(
+                "SELECT setval('tbf_ids', GREATEST($1, last_value)) FROM tbf_ids",
+                &[&sql],
+            )?;
+            Ok(())
+        })?;
+        self.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let sql = sql_id(id).ok_or(Error::FileNotFound(id))?;
+        let tags = tags.map(|tags| tags.into_iter().collect::<BTreeSet<_>>());
+        self.with_write(|client| {
+            let changed = match data {
+                Some(data) => client.execute(
+                    "UPDATE tbf_files SET data = $2, size = $3, hash = $4, modified = $5 \
+                     WHERE id = $1",
+                    &[
+                        &sql,
+                        &data,
+                        &data_len(data),
+                        &&hash_data(data)[..],
+                        &SystemTime::now(),
+                    ],
+                )?,
+                None => client.execute("SELECT 1 FROM tbf_files WHERE id = $1", &[&sql])?,
+            };
+            if changed == 0 {
+                return Err(Error::FileNotFound(id));
+            }
+
+            if let Some(tags) = &tags {
+                client.execute("DELETE FROM tbf_tags WHERE file = $1", &[&sql])?;
+                insert_tags(client, sql, tags)?;
+            }
+            Ok(())
+        })?;
+
+        if data.is_some() {
+            self.emit(Event::FileEdited(id));
+        }
+        if tags.is_some() {
+            self.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.stored_tags(id)?;
+        new.extend(tags);
+        self.edit_file(id, None, Some(new))
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut new = self.stored_tags(id)?;
+        for tag in tags {
+            new.remove(&tag);
+        }
+        self.edit_file(id, None, Some(new))
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let sql = sql_id(id).ok_or(Error::FileNotFound(id))?;
+        self.with_write(|client| {
+            // Tags are removed along with the file
+            if client.execute("DELETE FROM tbf_files WHERE id = $1", &[&sql])? == 0 {
+                return Err(Error::FileNotFound(id));
+            }
+            Ok(())
+        })?;
+        self.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut conn = self.client()?;
+            if conn.in_txn {
+                drop(conn);
+                return f(self);
+            }
+            conn.client.batch_execute("BEGIN")?;
+            conn.in_txn = true;
+        }
+
+        let out = f(self);
+
+        let mut conn = self.client()?;
+        conn.in_txn = false;
+        conn.client
+            .batch_execute(if out.is_ok() { "COMMIT" } else { "ROLLBACK" })?;
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        check_stored::<Self::Error>(file)?;
+        self.with_write(|client| {
+            client.execute(
+                "INSERT INTO tbf_settings (name, value) VALUES ($1, $2) \
+                 ON CONFLICT (name) DO UPDATE SET value = EXCLUDED.value",
+                &[&file.name(), &data],
+            )?;
+            Ok(())
+        })
+    }
+}
+
+impl ProvidedTags for PostgresFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.providers.write()?.insert(group, Box::new(provider));
+        Ok(())
+    }
+}
+
+impl InferredTags for PostgresFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers.write()?.push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl StreamRead for PostgresFs {
+    type Reader<'a> = Cursor<Vec<u8>>;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.get_data(id).map(Cursor::new)
+    }
+}
+
+impl StreamWrite for PostgresFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+fn sql_id(id: FileId) -> Option<i64> {
+    i64::try_from(id.into_u64_unchecked()).ok()
+}
+
+fn file_id(id: i64) -> FileId {
+    FileId::from_u64_unchecked(u64::try_from(id).expect("Stored file IDs are never negative"))
+}
+
+fn data_len(data: &[u8]) -> i64 {
+    i64::try_from(data.len()).unwrap_or(i64::MAX)
+}
+
+/// Insert a new file's row, returning how many rows were inserted, which is 0 if the ID is
+/// already in use
+fn insert_file(client: &mut Client, id: i64, data: &[u8]) -> Result<u64, Error> {
+    let now = SystemTime::now();
+    Ok(client.execute(
+        "INSERT INTO tbf_files (id, data, size, hash, created, modified) \
+         VALUES ($1, $2, $3, $4, $5, $5) ON CONFLICT (id) DO NOTHING",
+        &[&id, &data, &data_len(data), &&hash_data(data)[..], &now],
+    )?)
+}
+
+fn insert_tags(client: &mut Client, id: i64, tags: &BTreeSet<Tag>) -> Result<(), Error> {
+    if tags.is_empty() {
+        return Ok(());
+    }
+    let stmt = client.prepare(
+        "INSERT INTO tbf_tags \
+         (file, grp, name, value, kind, text_value, int_value, float_value, bool_value) \
+         VALUES ($1, $2, $3, $4, $5, $6, $7, $8, $9)",
+    )?;
+    for tag in tags {
+        let value = ValueColumns::new(tag.value())?;
+        client.execute(
+            &stmt,
+            &[
+                &id,
+                &group_column(tag.group()),
+                &tag.name(),
+                &value.encoded,
+                &value.kind,
+                &value.text,
+                &value.int,
+                &value.float,
+                &value.bool,
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// The group column of a tag, which is null for the default group
+fn group_column(group: &Group) -> Option<&str> {
+    match group {
+        Group::Default => None,
+        Group::Custom(group) => Some(group),
+    }
+}
+
+/// Read a tag from the group, name, and value columns of a row
+fn tag_from_row(row: &Row) -> Result<Tag, Error> {
+    let group = match row.get::<_, Option<String>>(0) {
+        Some(group) => Group::custom(group),
+        None => Group::Default,
+    };
+    let tag = Tag::new(group, row.get::<_, String>(1));
+    match row.get::<_, Option<&[u8]>>(2) {
+        Some(mut value) => Ok(tag.with_value(read_value(&mut value)?)),
+        None => Ok(tag),
+    }
+}
+
+/// The columns a tag's value is stored in. The encoded value is used for exact matches and reading
+/// the value back, while the typed columns are used for comparisons.
+#[derive(Default)]
+struct ValueColumns {
+    encoded: Option<Vec<u8>>,
+    kind: Option<i16>,
+    text: Option<String>,
+    int: Option<i64>,
+    float: Option<f64>,
+    bool: Option<bool>,
+}
+
+impl ValueColumns {
+    fn new(value: Option<&TagValue>) -> io::Result<ValueColumns> {
+        let Some(value) = value else {
+            return Ok(ValueColumns::default());
+        };
+
+        let mut encoded = Vec::new();
+        write_value(&mut encoded, value)?;
+        let mut out = ValueColumns {
+            encoded: Some(encoded),
+            kind: Some(i16::from(value.kind())),
+            ..ValueColumns::default()
+        };
+        match value {
+            TagValue::String(val) => out.text = Some(val.to_string()),
+            TagValue::Int(val) | TagValue::Timestamp(val) => out.int = Some(*val),
+            TagValue::Float(val) => out.float = Some(*val),
+            TagValue::Bool(val) => out.bool = Some(*val),
+        }
+        Ok(out)
+    }
+}
+
+/// A SQL condition on the files `f` matching a predicate, along with its parameters
+struct Where {
+    sql: String,
+    params: Vec<Box<dyn ToSql + Sync>>,
+    /// Whether the condition matches exactly the files the predicate does. Otherwise it matches
+    /// more, and files it finds need checking against the predicate.
+    exact: bool,
+}
+
+impl Where {
+    fn new(pred: &TagPredicate) -> Where {
+        let mut out = Where {
+            sql: String::new(),
+            params: Vec::new(),
+            exact: true,
+        };
+        out.push(pred, true);
+        out
+    }
+
+    /// A condition matching every file
+    fn any() -> Where {
+        Where {
+            sql: "TRUE".to_owned(),
+            params: Vec::new(),
+            exact: false,
+        }
+    }
+
+    fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params.iter().map(|param| &**param as _).collect()
+    }
+
+    /// Add a parameter, returning its placeholder
+    fn param<T: ToSql + Sync + 'static>(&mut self, val: T) -> String {
+        self.params.push(Box::new(val));
+        format!("${}", self.params.len())
+    }
+
+    /// Add a predicate to the condition. Parts which can't be expressed in SQL are replaced with
+    /// whichever of `TRUE` or `FALSE` makes the condition match more files, depending on whether
+    /// the part is `positive` or inside an odd number of `Not`s.
+    fn push(&mut self, pred: &TagPredicate, positive: bool) {
+        match pred {
+            TagPredicate::And(preds) => self.push_all(preds, " AND ", "TRUE", positive),
+            TagPredicate::Or(preds) => self.push_all(preds, " OR ", "FALSE", positive),
+            TagPredicate::Not(pred) => {
+                self.sql.push_str("NOT ");
+                self.push(pred, !positive);
+            }
+            _ => {
+                if let Some(cond) = self.tag_condition(pred) {
+                    let _ = write!(
+                        self.sql,
+                        "EXISTS (SELECT 1 FROM tbf_tags t WHERE t.file = f.id AND {cond})"
+                    );
+                } else {
+                    self.exact = false;
+                    self.sql.push_str(if positive { "TRUE" } else { "FALSE" });
+                }
+            }
+        }
+    }
+
+    fn push_all(&mut self, preds: &[TagPredicate], op: &str, empty: &str, positive: bool) {
+        if preds.is_empty() {
+            self.sql.push_str(empty);
+            return;
+        }
+        self.sql.push('(');
+        for (idx, pred) in preds.iter().enumerate() {
+            if idx > 0 {
+                self.sql.push_str(op);
+            }
+            self.push(pred, positive);
+        }
+        self.sql.push(')');
+    }
+
+    /// Translate a predicate on single tags into a condition on a tag `t`, or `None` if it can't
+    /// be expressed in SQL
+    fn tag_condition(&mut self, pred: &TagPredicate) -> Option<String> {
+        Some(match pred {
+            TagPredicate::Group(group) => self.group(group),
+            TagPredicate::Name(name) => format!("t.name = {}", self.param(name.clone())),
+            TagPredicate::NameContains(substr) => {
+                format!("strpos(t.name, {}) > 0", self.param(substr.clone()))
+            }
+            TagPredicate::NameGlob(glob) => {
+                format!("t.name LIKE {}", self.param(glob_to_like(glob)))
+            }
+            TagPredicate::GroupGlob(glob) => {
+                format!(
+                    "COALESCE(t.grp, '') LIKE {}",
+                    self.param(glob_to_like(glob))
+                )
+            }
+            TagPredicate::Tag(tag) => {
+                let value = match tag.value() {
+                    Some(value) => {
+                        let mut encoded = Vec::new();
+                        write_value(&mut encoded, value).ok()?;
+                        format!("t.value = {}", self.param(encoded))
+                    }
+                    None => "t.value IS NULL".to_owned(),
+                };
+                format!("{} AND {value}", self.name(tag.group(), tag.name()))
+            }
+            TagPredicate::ValueGt { group, name, value } => {
+                let name = self.name(group, name);
+                format!("{name} AND {}", self.compare(">", value))
+            }
+            TagPredicate::ValueLt { group, name, value } => {
+                let name = self.name(group, name);
+                format!("{name} AND {}", self.compare("<", value))
+            }
+            TagPredicate::ValueRange {
+                group,
+                name,
+                min,
+                max,
+            } => {
+                let name = self.name(group, name);
+                let min = self.compare(">=", min);
+                let max = self.compare("<=", max);
+                format!("{name} AND {min} AND {max}")
+            }
+            // Regex syntax differs from Postgres', so is checked locally
+            _ => return None,
+        })
+    }
+
+    fn group(&mut self, group: &Group) -> String {
+        match group {
+            Group::Default => "t.grp IS NULL".to_owned(),
+            Group::Custom(group) => format!("t.grp = {}", self.param(group.to_string())),
+        }
+    }
+
+    fn name(&mut self, group: &Group, name: &str) -> String {
+        let group = self.group(group);
+        format!("{group} AND t.name = {}", self.param(name.to_owned()))
+    }
+
+    /// Compare a tag's value with another, matching [`TagValue::compare`]: ints and floats
+    /// compare with each other, every other kind only with itself, and `NaN` with nothing
+    fn compare(&mut self, op: &str, value: &TagValue) -> String {
+        match value {
+            // `C` collation compares by code point, like Rust does
+            TagValue::String(val) => format!(
+                "(t.kind = 0 AND t.text_value COLLATE \"C\" {op} {})",
+                self.param(val.to_string())
+            ),
+            #[allow(clippy::cast_precision_loss)]
+            TagValue::Int(val) => {
+                let int = self.param(*val);
+                let float = self.param(*val as f64);
+                format!(
+                    "((t.kind = 1 AND t.int_value {op} {int}) OR \
+                     (t.kind = 2 AND t.float_value <> 'NaN' AND t.float_value {op} {float}))"
+                )
+            }
+            TagValue::Float(val) if val.is_nan() => "FALSE".to_owned(),
+            TagValue::Float(val) => {
+                let float = self.param(*val);
+                format!(
+                    "((t.kind = 2 AND t.float_value <> 'NaN' AND t.float_value {op} {float}) OR \
+                     (t.kind = 1 AND t.int_value::float8 {op} {float}))"
+                )
+            }
+            TagValue::Bool(val) => {
+                format!("(t.kind = 3 AND t.bool_value {op} {})", self.param(*val))
+            }
+            TagValue::Timestamp(val) => {
+                format!("(t.kind = 4 AND t.int_value {op} {})", self.param(*val))
+            }
+        }
+    }
+}
+
+/// Translate a glob into a `LIKE` pattern
+fn glob_to_like(glob: &str) -> String {
+    let mut out = String::with_capacity(glob.len());
+    for c in glob.chars() {
+        match c {
+            '*' => out.push('%'),
+            '?' => out.push('_'),
+            '%' | '_' | '\\' => {
+                out.push('\\');
+                out.push(c);
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// A lazy search over a [`PostgresFs`]. IDs are fetched from the server a page at a time, so
+/// files added or removed during iteration may or may not be seen.
+pub struct SearchIter<'a, P> {
+    fs: &'a PostgresFs,
+    pattern: P,
+    /// The search's condition, found on the first call to `next`
+    cond: Option<Where>,
+    page: VecDeque<FileId>,
+    /// The last ID fetched
+    cursor: i64,
+    /// Whether the last page has been fetched
+    exhausted: bool,
+    done: bool,
+}
+
+impl<P: TagPattern> SearchIter<'_, P> {
+    fn advance(&mut self) -> Result<Option<FileId>, Error> {
+        loop {
+            let cond = match &self.cond {
+                Some(cond) => cond,
+                None => self.cond.insert(self.fs.condition(&self.pattern)?),
+            };
+
+            if let Some(id) = self.page.pop_front() {
+                if cond.exact || self.fs.recheck(&self.pattern, id)? {
+                    return Ok(Some(id));
+                }
+                continue;
+            }
+            if self.exhausted {
+                return Ok(None);
+            }
+
+            let mut params = cond.params();
+            let sql = format!(
+                "SELECT f.id FROM tbf_files f WHERE f.id > ${} AND {} ORDER BY f.id LIMIT {PAGE_LEN}",
+                params.len() + 1,
+                cond.sql
+            );
+            params.push(&self.cursor);
+            let rows = self.fs.client()?.client.query(&sql, &params)?;
+
+            self.exhausted = rows.len() < PAGE_LEN;
+            self.page
+                .extend(rows.into_iter().map(|row| file_id(row.get(0))));
+            if let Some(last) = self.page.back() {
+                self.cursor = sql_id(*last).unwrap_or(i64::MAX);
+            }
+        }
+    }
+}
+
+impl<P: TagPattern> Iterator for SearchIter<'_, P> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let out = self.advance().transpose();
+        self.done = !matches!(out, Some(Ok(_)));
+        out
+    }
+}
+
+/// A handle streaming data into a new file of a [`PostgresFs`]. Data is buffered until the handle
+/// is flushed, which adds the file or updates its data if it already exists.
+pub type Writer<'a> = BufferedWriter<'a, PostgresFs>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_translate() {
+        let cond = Where::new(&TagPredicate::And(Vec::new()));
+        assert_eq!(cond.sql, "TRUE");
+        assert!(cond.exact);
+
+        let cond = Where::new(&TagPredicate::or([
+            TagPredicate::tag(Tag::named("a")),
+            TagPredicate::not(TagPredicate::group(Group::custom("g"))),
+        ]));
+        assert_eq!(
+            cond.sql,
+            "(EXISTS (SELECT 1 FROM tbf_tags t WHERE t.file = f.id AND t.grp IS NULL AND \
+             t.name = $1 AND t.value IS NULL) OR NOT EXISTS (SELECT 1 FROM tbf_tags t WHERE \
+             t.file = f.id AND t.grp = $2))"
+        );
+        assert_eq!(cond.params.len(), 2);
+        assert!(cond.exact);
+    }
+
+    #[test]
+    #[cfg(feature = "regex")]
+    fn test_translate_inexact() {
+        let regex = TagPredicate::name_regex("^a").unwrap();
+
+        let cond = Where::new(&TagPredicate::and([regex.clone(), TagPredicate::name("b")]));
+        assert_eq!(
+            cond.sql,
+            "(TRUE AND EXISTS (SELECT 1 FROM tbf_tags t WHERE t.file = f.id AND t.name = $1))"
+        );
+        assert!(!cond.exact);
+
+        // Under a `Not`, the condition must still match at least every file the search does
+        let cond = Where::new(&TagPredicate::not(regex));
+        assert_eq!(cond.sql, "NOT FALSE");
+        assert!(!cond.exact);
+    }
+
+    #[test]
+    fn test_glob_to_like() {
+        assert_eq!(glob_to_like("*.png"), "%.png");
+        assert_eq!(glob_to_like("a?_%\\"), "a_\\_\\%\\\\");
+    }
+}
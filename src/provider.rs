@@ -0,0 +1,53 @@
+//! Lazily computed tags, derived from file data on demand
+
+use alloc::boxed::Box;
+use alloc::collections::BTreeMap;
+use alloc::vec::Vec;
+
+use crate::{FileSystemRead, Group, Tag};
+
+/// A source of tags which are computed from a file's data whenever they're needed, instead of
+/// being stored. This keeps expensive derived tags (sizes, dimensions, etc.) in sync with the
+/// data without having to re-tag a file on every edit.
+///
+/// Any function or closure of the form `Fn(&[u8]) -> Vec<Tag>` is a provider.
+pub trait TagProvider: Send + Sync {
+    /// Compute the tags for a file with the given data
+    fn provide(&self, data: &[u8]) -> Vec<Tag>;
+}
+
+impl<F> TagProvider for F
+where
+    F: Fn(&[u8]) -> Vec<Tag> + Send + Sync,
+{
+    fn provide(&self, data: &[u8]) -> Vec<Tag> {
+        self(data)
+    }
+}
+
+/// Filesystems which can have [`TagProvider`]s registered with them
+pub trait ProvidedTags: FileSystemRead {
+    /// Register a provider which computes tags in the given group from a file's data. Provided
+    /// tags are never persisted, but take part in `search_tags` and are returned by `get_info`.
+    /// Registering a second provider for the same group replaces the first.
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static;
+}
+
+/// Registered providers, keyed by the group they provide tags for
+pub(crate) type Providers = BTreeMap<Group, Box<dyn TagProvider>>;
+
+/// Run every registered provider over some data. Tags are always placed in the group the
+/// provider was registered for, whatever group the provider itself returned.
+pub(crate) fn provide_tags(providers: &Providers, data: &[u8]) -> Vec<Tag> {
+    providers
+        .iter()
+        .flat_map(|(group, provider)| {
+            provider
+                .provide(data)
+                .into_iter()
+                .map(move |tag| tag.in_group(group.clone()))
+        })
+        .collect()
+}
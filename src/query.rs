@@ -0,0 +1,130 @@
+//! Builder for the common case of narrowing a search one constraint at a time
+
+use alloc::vec::Vec;
+
+use crate::{FileId, FileSystemRead, Group, Tag, TagPredicate};
+
+/// A search over a filesystem, built up from individual constraints. Every constraint must hold
+/// for a file to match, and the whole query compiles down to a [`TagPredicate`].
+///
+/// Created with [`FileSystemRead::query`].
+pub struct Query<'a, F: ?Sized> {
+    fs: &'a F,
+    preds: Vec<TagPredicate>,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> Query<'a, F> {
+    /// Create a new query over a filesystem, which matches all files
+    pub fn new(fs: &'a F) -> Query<'a, F> {
+        Query {
+            fs,
+            preds: Vec::new(),
+        }
+    }
+
+    /// Require files to have a tag
+    #[must_use]
+    pub fn with_tag(mut self, tag: Tag) -> Self {
+        self.preds.push(TagPredicate::tag(tag));
+        self
+    }
+
+    /// Require files to not have a tag
+    #[must_use]
+    pub fn without_tag(mut self, tag: Tag) -> Self {
+        self.preds.push(TagPredicate::not(tag));
+        self
+    }
+
+    /// Require files to have any tag in a group
+    #[must_use]
+    pub fn with_group(mut self, group: Group) -> Self {
+        self.preds.push(TagPredicate::group(group));
+        self
+    }
+
+    /// Require files to have no tags in a group
+    #[must_use]
+    pub fn without_group(mut self, group: Group) -> Self {
+        self.preds.push(TagPredicate::not(group));
+        self
+    }
+
+    /// Require files to have a tag whose name contains a substring
+    #[must_use]
+    pub fn name_like(mut self, substr: &str) -> Self {
+        self.preds.push(TagPredicate::name_contains(substr));
+        self
+    }
+
+    /// Get the predicate equivalent to this query
+    pub fn predicate(&self) -> TagPredicate {
+        TagPredicate::and(self.preds.clone())
+    }
+
+    /// Run this query, returning all matching files
+    pub fn run(&self) -> Result<Vec<FileId>, F::Error> {
+        self.fs.search_tags(self.predicate())
+    }
+
+    /// Run this query, returning only the number of matching files
+    pub fn count(&self) -> Result<u64, F::Error> {
+        self.fs.count_tags(self.predicate())
+    }
+
+    /// Check whether any file matches this query
+    pub fn exists(&self) -> Result<bool, F::Error> {
+        self.fs.any_match(self.predicate())
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{FileSystemWrite, InMemoryFs};
+
+    #[test]
+    fn test_query_equivalent() {
+        let ifs = InMemoryFs::new();
+        let temp = Group::custom("temp");
+
+        ifs.add_file(&[], [Tag::named("a"), Tag::named("b")])
+            .unwrap();
+        ifs.add_file(
+            &[],
+            [
+                Tag::named("a"),
+                Tag::named("b"),
+                Tag::new(temp.clone(), "x"),
+            ],
+        )
+        .unwrap();
+        ifs.add_file(&[], [Tag::named("a"), Tag::named("bcd")])
+            .unwrap();
+        ifs.add_file(&[], [Tag::named("b"), Tag::named("c")])
+            .unwrap();
+
+        let query = ifs
+            .query()
+            .with_tag(Tag::named("a"))
+            .without_group(temp.clone())
+            .name_like("b");
+        let pred = TagPredicate::and([
+            TagPredicate::tag(Tag::named("a")),
+            TagPredicate::not(temp),
+            TagPredicate::name_contains("b"),
+        ]);
+
+        assert_eq!(query.predicate(), pred);
+        assert_eq!(query.run().unwrap(), ifs.search_tags(pred).unwrap());
+        assert_eq!(query.count().unwrap(), 2);
+
+        let query = ifs
+            .query()
+            .with_group(Group::Default)
+            .without_tag(Tag::named("c"));
+        assert_eq!(query.count().unwrap(), 3);
+        assert!(query.exists().unwrap());
+        assert!(!query.with_tag(Tag::named("missing")).exists().unwrap());
+    }
+}
@@ -0,0 +1,664 @@
+//! Wrapper limiting how much another TBF can store
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::ops::Range;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::error::{ErrorKind, GroupName};
+use crate::events::Event;
+use crate::metadata::Metadata;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, BufferedWriter, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite,
+    Group, InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer,
+    TagPattern, TagProvider, UsageReport, WrappedSearchIter,
+};
+
+/// A limit of a [`QuotaFs`] which a change would have gone over
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Quota {
+    /// The total size of file data
+    Bytes,
+    /// The number of files
+    Files,
+    /// The total size of the data of files with a tag in this group
+    Group(Group),
+}
+
+/// Error for a filesystem with quotas
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner filesystem returned an error
+    Fs(E),
+    /// A change would have gone over a limit
+    QuotaExceeded(Quota),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::QuotaExceeded(_) => ErrorKind::QuotaExceeded,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::QuotaExceeded(Quota::Bytes) => {
+                write!(f, "change would go over the quota on total data size")
+            }
+            Error::QuotaExceeded(Quota::Files) => {
+                write!(f, "change would go over the quota on number of files")
+            }
+            Error::QuotaExceeded(Quota::Group(group)) => write!(
+                f,
+                "change would go over the quota on data in {}",
+                GroupName(group)
+            ),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::QuotaExceeded(_) => None,
+        }
+    }
+}
+
+/// The limits of a [`QuotaFs`]. By default nothing is limited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct QuotaLimits {
+    bytes: Option<u64>,
+    files: Option<u64>,
+    groups: BTreeMap<Group, u64>,
+}
+
+impl QuotaLimits {
+    /// Create limits which don't limit anything
+    pub fn new() -> QuotaLimits {
+        QuotaLimits::default()
+    }
+
+    /// Limit the total size of file data, in bytes
+    #[must_use]
+    pub fn max_bytes(mut self, bytes: u64) -> Self {
+        self.bytes = Some(bytes);
+        self
+    }
+
+    /// Limit the number of files
+    #[must_use]
+    pub fn max_files(mut self, files: u64) -> Self {
+        self.files = Some(files);
+        self
+    }
+
+    /// Limit the total size of the data of files with a tag in a group, in bytes
+    #[must_use]
+    pub fn max_group_bytes(mut self, group: Group, bytes: u64) -> Self {
+        self.groups.insert(group, bytes);
+        self
+    }
+
+    /// Check usage after a change against the limits. Usage which is over a limit, but no more
+    /// than it was before, is allowed, so files can still be shrunk or removed once over.
+    fn check(&self, before: &UsageReport, after: &UsageReport) -> Result<(), Quota> {
+        let over = |limit: Option<u64>, before: u64, after: u64| {
+            limit.is_some_and(|limit| after > limit && after > before)
+        };
+
+        if over(self.bytes, before.bytes(), after.bytes()) {
+            return Err(Quota::Bytes);
+        }
+        if over(self.files, before.files(), after.files()) {
+            return Err(Quota::Files);
+        }
+        for (group, limit) in &self.groups {
+            let before = before.group(group).bytes();
+            let after = after.group(group).bytes();
+            if over(Some(*limit), before, after) {
+                return Err(Quota::Group(group.clone()));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// A wrapper around another filesystem which limits how much can be stored in it, in total or in
+/// files with tags in some group. Adding or changing a file so usage goes over a limit fails with
+/// an error of kind [`ErrorKind::QuotaExceeded`], and nothing is passed on to the inner
+/// filesystem.
+///
+/// Usage is found once when wrapping, then kept up to date as files change. Limits are checked
+/// against the tags a change gives, so tags inferred or provided for a file are counted once it's
+/// changed, but never cause the change to fail. Renaming tags and groups moves usage between
+/// groups without being checked.
+pub struct QuotaFs<F> {
+    inner: F,
+    limits: QuotaLimits,
+    usage: Mutex<UsageReport>,
+}
+
+impl<F: FileSystem> QuotaFs<F> {
+    /// Wrap a filesystem, limiting how much can be stored in it
+    pub fn new(inner: F, limits: QuotaLimits) -> Result<QuotaFs<F>, F::Error> {
+        let usage = inner.usage()?;
+        Ok(QuotaFs {
+            inner,
+            limits,
+            usage: Mutex::new(usage),
+        })
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, removing the limits
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Get the limits of the filesystem
+    pub fn limits(&self) -> &QuotaLimits {
+        &self.limits
+    }
+
+    /// Change the limits of the filesystem. If usage is already over the new limits, nothing is
+    /// removed, but it can't grow any further.
+    pub fn set_limits(&mut self, limits: QuotaLimits) {
+        self.limits = limits;
+    }
+
+    fn lock_usage(&self) -> MutexGuard<'_, UsageReport> {
+        self.usage.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Get the size and tags a file is counted with
+    fn counted(&self, id: FileId) -> Result<(u64, BTreeSet<Tag>), F::Error> {
+        Ok((
+            self.inner.get_metadata(id)?.size(),
+            self.inner.get_tags(id)?,
+        ))
+    }
+
+    /// Make a change to one file, if usage after it would be within the limits. The file is
+    /// counted as it was before, and with the size and tags given, then recounted once changed.
+    fn change<T>(
+        &self,
+        before: Option<&(u64, BTreeSet<Tag>)>,
+        after: (u64, &BTreeSet<Tag>),
+        f: impl FnOnce(&F) -> Result<T, F::Error>,
+        id: impl FnOnce(&T) -> FileId,
+    ) -> Result<T, Error<F::Error>> {
+        let mut usage = self.lock_usage();
+        let mut projected = usage.clone();
+        if let Some((size, tags)) = before {
+            projected.remove(*size, tags);
+        }
+        projected.add(after.0, after.1);
+        self.limits
+            .check(&usage, &projected)
+            .map_err(Error::QuotaExceeded)?;
+
+        let out = f(&self.inner)?;
+        if let Some((size, tags)) = before {
+            usage.remove(*size, tags);
+        }
+        let (size, tags) = self.counted(id(&out))?;
+        usage.add(size, &tags);
+        Ok(out)
+    }
+
+    /// Change the tags of a file without checking limits, for changes which can only shrink usage
+    fn retag(
+        &self,
+        id: FileId,
+        f: impl FnOnce(&F) -> Result<(), F::Error>,
+    ) -> Result<(), Error<F::Error>> {
+        let mut usage = self.lock_usage();
+        let (size, tags) = self.counted(id)?;
+        f(&self.inner)?;
+        usage.remove(size, &tags);
+        let (size, tags) = self.counted(id)?;
+        usage.add(size, &tags);
+        Ok(())
+    }
+
+    /// Count usage again from scratch, after changes it couldn't be kept up to date with
+    fn recount(&self) -> Result<(), F::Error> {
+        *self.lock_usage() = self.inner.usage()?;
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for QuotaFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.search_tags_with(tags, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        WrappedSearchIter::new(self.inner.search_tags_iter(tags))
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.tag_counts(pattern)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.count_tags(pattern)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.any_match(pattern)?)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        Ok(self.lock_usage().clone())
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for QuotaFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.change(
+            None,
+            (data.len() as u64, &tags),
+            |fs| fs.add_file(data, tags.iter().cloned()),
+            |id| *id,
+        )
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.change(
+            None,
+            (data.len() as u64, &tags),
+            |fs| fs.add_file_with_id(id, data, tags.iter().cloned()),
+            |()| id,
+        )
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let before = self.counted(id)?;
+        let size = data.map_or(before.0, |data| data.len() as u64);
+        let tags = tags.map(|tags| tags.into_iter().collect::<BTreeSet<_>>());
+        let after = tags.clone().unwrap_or_else(|| before.1.clone());
+        self.change(
+            Some(&before),
+            (size, &after),
+            |fs| fs.edit_file(id, data, tags),
+            |()| id,
+        )
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        let mut usage = self.lock_usage();
+        let (size, tags) = self.counted(id)?;
+        self.inner.remove_file(id)?;
+        usage.remove(size, &tags);
+        Ok(())
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done
+        let mut exceeded = None;
+        let out = self.inner.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Fs(err)) => Err(err),
+            Err(Error::QuotaExceeded(quota)) => {
+                exceeded = Some(quota);
+                Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        if out.is_err() {
+            // Changes counted during the transaction were undone
+            self.recount()?;
+        }
+        match (out, exceeded) {
+            (Err(_), Some(quota)) => Err(Error::QuotaExceeded(quota)),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let before = self.counted(id)?;
+        let mut after = before.1.clone();
+        let tags = tags.into_iter().collect::<Vec<_>>();
+        after.extend(tags.iter().cloned());
+        let size = before.0;
+        self.change(
+            Some(&before),
+            (size, &after),
+            |fs| fs.add_tags(id, tags),
+            |()| id,
+        )
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.retag(id, |fs| fs.remove_tags(id, tags))
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        self.inner.rename_tag(old, new)?;
+        Ok(self.recount()?)
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        self.inner.rename_group(old, new)?;
+        Ok(self.recount()?)
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        let before = self.counted(id)?;
+        let size = self.inner.get_version(id, version)?.len() as u64;
+        let tags = before.1.clone();
+        self.change(
+            Some(&before),
+            (size, &tags),
+            |fs| fs.revert(id, version),
+            |()| id,
+        )
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        Ok(self.inner.set_special_data(file, data)?)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for QuotaFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.inner.register_provider(group, provider)?;
+        // Provided tags count towards the usage of their group
+        Ok(self.recount()?)
+    }
+}
+
+impl<F: InferredTags> InferredTags for QuotaFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for QuotaFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl<F: FileSystem> StreamWrite for QuotaFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+/// A lazy search over a [`QuotaFs`], which is a search of the inner filesystem
+pub type SearchIter<'a, F, P> = WrappedSearchIter<
+    <F as FileSystemRead>::SearchIter<'a, P>,
+    Error<<F as FileSystemRead>::Error>,
+>;
+
+/// A handle streaming data into a new file of a [`QuotaFs`]. Data is buffered, and checked
+/// against the limits once flushed or committed.
+pub type Writer<'a, F> = BufferedWriter<'a, QuotaFs<F>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{Error as _, FileWriter, InMemoryFs};
+
+    #[test]
+    fn test_quota() {
+        let imfs = InMemoryFs::new();
+        imfs.add_file(&[1; 4], [Tag::named("a")]).unwrap();
+        let fs = QuotaFs::new(imfs, QuotaLimits::new().max_bytes(10).max_files(3)).unwrap();
+        assert_eq!(fs.usage().unwrap().bytes(), 4);
+
+        let id = fs.add_file(&[2; 4], [Tag::new("kind", "b")]).unwrap();
+        let err = fs.add_file(&[3; 4], []).unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::QuotaExceeded));
+        assert!(matches!(err, Error::QuotaExceeded(Quota::Bytes)));
+        assert!(matches!(
+            fs.edit_file(id, Some(&[2; 8]), None::<[Tag; 0]>),
+            Err(Error::QuotaExceeded(Quota::Bytes))
+        ));
+        assert_eq!(fs.inner().get_data(id).unwrap(), [2; 4]);
+
+        // Shrinking makes room again
+        fs.edit_file(id, Some(&[2]), None::<[Tag; 0]>).unwrap();
+        fs.add_file(&[3; 4], []).unwrap();
+        assert!(matches!(
+            fs.add_file(&[], []),
+            Err(Error::QuotaExceeded(Quota::Files))
+        ));
+
+        let usage = fs.usage().unwrap();
+        assert_eq!((usage.bytes(), usage.files()), (9, 3));
+        assert_eq!(usage.group(&Group::custom("kind")).bytes(), 1);
+        assert_eq!(usage, fs.inner().usage().unwrap());
+
+        fs.remove_file(id).unwrap();
+        assert_eq!(fs.usage().unwrap().files(), 2);
+        assert_eq!(fs.usage().unwrap().group(&Group::custom("kind")).files(), 0);
+    }
+
+    #[test]
+    fn test_quota_groups() {
+        let limits = QuotaLimits::new().max_group_bytes(Group::custom("video"), 4);
+        let fs = QuotaFs::new(InMemoryFs::new(), limits).unwrap();
+
+        let id = fs.add_file(&[1; 3], [Tag::new("video", "a")]).unwrap();
+        let other = fs.add_file(&[1; 8], [Tag::named("b")]).unwrap();
+        assert!(matches!(
+            fs.add_tags(other, [Tag::new("video", "b")]),
+            Err(Error::QuotaExceeded(Quota::Group(_)))
+        ));
+
+        let mut writer = fs.create_file([Tag::new("video", "c")]).unwrap();
+        writer.write_all(&[2; 2]).unwrap();
+        assert!(matches!(
+            writer.commit(),
+            Err(Error::QuotaExceeded(Quota::Group(_)))
+        ));
+
+        let res = fs.transaction(|fs| {
+            fs.remove_file(id)?;
+            fs.add_file(&[3; 5], [Tag::new("video", "d")])
+        });
+        assert!(matches!(res, Err(Error::QuotaExceeded(Quota::Group(_)))));
+        assert_eq!(fs.search_tags(Tag::new("video", "a")).unwrap(), [id]);
+        assert_eq!(fs.usage().unwrap(), fs.inner().usage().unwrap());
+        assert_eq!(
+            fs.usage().unwrap().group(&Group::custom("video")).bytes(),
+            3
+        );
+    }
+}
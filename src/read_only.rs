@@ -0,0 +1,419 @@
+//! Read-only wrapper around another TBF
+
+use alloc::boxed::Box;
+use alloc::collections::{BTreeMap, BTreeSet};
+use alloc::vec::Vec;
+#[cfg(feature = "std")]
+use core::convert::Infallible;
+use core::fmt;
+#[cfg(feature = "std")]
+use core::marker::PhantomData;
+use core::ops::Range;
+
+use crate::error::ErrorKind;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, FileId, FileInfo, FileSystemRead, FileSystemWrite, Group, InferredTags,
+    ProvidedTags, SpecialFile, Tag, TagInferrer, TagPattern, TagProvider, WrappedSearchIter,
+};
+#[cfg(feature = "std")]
+use crate::{Event, FileWriter, Metadata, StreamRead, StreamWrite};
+
+/// Error for a read-only filesystem
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner filesystem returned an error
+    Fs(E),
+    /// A change was attempted
+    ReadOnly,
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::ReadOnly => ErrorKind::ReadOnly,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::ReadOnly => write!(f, "filesystem is read-only"),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::ReadOnly => None,
+        }
+    }
+}
+
+/// A wrapper around another filesystem which only allows looking files up. Every change fails
+/// with an error of kind [`ErrorKind::ReadOnly`], and nothing is passed on to the inner
+/// filesystem, so it's guaranteed to be left as it was.
+pub struct ReadOnly<F> {
+    inner: F,
+}
+
+impl<F: FileSystemRead> ReadOnly<F> {
+    /// Wrap a filesystem, allowing only lookups
+    pub fn new(inner: F) -> ReadOnly<F> {
+        ReadOnly { inner }
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, allowing changes again
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+}
+
+impl<F: FileSystemRead> FileSystemRead for ReadOnly<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.search_tags_with(tags, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        WrappedSearchIter::new(self.inner.search_tags_iter(tags))
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    #[cfg(feature = "std")]
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.tag_counts(pattern)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.count_tags(pattern)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.any_match(pattern)?)
+    }
+
+    #[cfg(feature = "std")]
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    #[cfg(feature = "std")]
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    #[cfg(feature = "std")]
+    fn subscribe(&self) -> Result<std::sync::mpsc::Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystemRead> FileSystemWrite for ReadOnly<F> {
+    fn add_file<I>(&self, _: &[u8], _: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Err(Error::ReadOnly)
+    }
+
+    fn add_file_with_id<I>(&self, _: FileId, _: &[u8], _: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Err(Error::ReadOnly)
+    }
+
+    fn edit_file<I>(&self, _: FileId, _: Option<&[u8]>, _: Option<I>) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Err(Error::ReadOnly)
+    }
+
+    fn remove_file(&self, _: FileId) -> Result<(), Self::Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // Nothing can be changed, so there's never anything to undo
+        f(self)
+    }
+
+    fn add_tags<I>(&self, _: FileId, _: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Err(Error::ReadOnly)
+    }
+
+    fn remove_tags<I>(&self, _: FileId, _: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Err(Error::ReadOnly)
+    }
+
+    fn rename_tag(&self, _: &Tag, _: Tag) -> Result<(), Self::Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn rename_group(&self, _: &Group, _: Group) -> Result<(), Self::Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn revert(&self, _: FileId, _: u32) -> Result<(), Self::Error> {
+        Err(Error::ReadOnly)
+    }
+
+    fn set_special_data(&self, _: SpecialFile, _: &[u8]) -> Result<(), Self::Error> {
+        Err(Error::ReadOnly)
+    }
+}
+
+impl<F: ProvidedTags> ProvidedTags for ReadOnly<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl<F: FileSystemRead> InferredTags for ReadOnly<F> {
+    fn register_inferrer<I>(&self, _: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        // Inferrers only run when files are added, which never happens
+        Ok(())
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F: StreamRead> StreamRead for ReadOnly<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+#[cfg(feature = "std")]
+impl<F: FileSystemRead> StreamWrite for ReadOnly<F> {
+    type Writer<'a>
+        = Writer<F::Error>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, _: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Err(Error::ReadOnly)
+    }
+}
+
+/// A lazy search over a [`ReadOnly`] filesystem, which is a search of the inner filesystem
+pub type SearchIter<'a, F, P> = WrappedSearchIter<
+    <F as FileSystemRead>::SearchIter<'a, P>,
+    Error<<F as FileSystemRead>::Error>,
+>;
+
+/// The file writer of a [`ReadOnly`] filesystem. New files can't be created, so this is never
+/// actually made.
+#[cfg(feature = "std")]
+pub struct Writer<E>(Infallible, PhantomData<E>);
+
+#[cfg(feature = "std")]
+impl<E> std::io::Write for Writer<E> {
+    fn write(&mut self, _: &[u8]) -> std::io::Result<usize> {
+        match self.0 {}
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0 {}
+    }
+}
+
+#[cfg(feature = "std")]
+impl<E> FileWriter for Writer<E> {
+    type Error = Error<E>;
+
+    fn commit(self) -> Result<FileId, Self::Error> {
+        match self.0 {}
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{Error as _, InMemoryFs};
+
+    #[test]
+    fn test_read_only() {
+        let imfs = InMemoryFs::new();
+        let id = imfs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+        let fs = ReadOnly::new(imfs);
+
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [id]);
+        assert_eq!(fs.get_info(id).unwrap().data(), &[1, 2]);
+        assert!(matches!(
+            fs.get_info(FileId::from_u64_unchecked(1000)),
+            Err(Error::Fs(_))
+        ));
+
+        let err = fs.add_file(&[3], []).unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::ReadOnly));
+        assert!(matches!(
+            fs.add_tags(id, [Tag::named("b")]),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(fs.remove_file(id), Err(Error::ReadOnly)));
+        assert!(matches!(
+            fs.rename_tag(&Tag::named("a"), Tag::named("c")),
+            Err(Error::ReadOnly)
+        ));
+        assert!(matches!(fs.set_config(&[1]), Err(Error::ReadOnly)));
+
+        let imfs = fs.into_inner();
+        assert_eq!(imfs.get_info(id).unwrap().tags(), &[Tag::named("a")].into());
+    }
+}
@@ -0,0 +1,704 @@
+//! Descriptions and rules of groups, kept inside a filesystem, and a wrapper enforcing them
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::ops::Range;
+use std::io::{self, Read, Write};
+use std::sync::mpsc::Receiver;
+
+use crate::codec;
+use crate::error::{ErrorKind, GroupName};
+use crate::events::Event;
+use crate::metadata::Metadata;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, Group,
+    InferredTags, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer, TagPattern,
+    TagPredicate, TagProvider, UsageReport, WrappedSearchIter, WrappedWriter,
+};
+
+/// Group flag set when a file may only have one tag in the group
+const FLAG_EXCLUSIVE: u8 = 1;
+/// Group flag set when the group has a color, stored after its flags
+const FLAG_COLOR: u8 = 2;
+
+/// Error for a tag registry, or a filesystem enforcing one
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The filesystem returned an error
+    Fs(E),
+    /// The stored registry couldn't be read, as it was malformed
+    Corrupt(io::Error),
+    /// A file would have had more than one tag in this exclusive group
+    Exclusive(Group),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+            Error::Exclusive(_) => ErrorKind::InvalidTags,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored registry is malformed: {err}"),
+            Error::Exclusive(group) => write!(
+                f,
+                "file would have more than one tag in exclusive {}",
+                GroupName(group)
+            ),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+            Error::Exclusive(_) => None,
+        }
+    }
+}
+
+/// What's known about a group. By default a group has no description or color, and isn't
+/// exclusive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GroupInfo {
+    description: String,
+    color: Option<[u8; 3]>,
+    exclusive: bool,
+}
+
+impl GroupInfo {
+    /// Create info for a group with nothing set
+    pub fn new() -> GroupInfo {
+        GroupInfo::default()
+    }
+
+    /// Set a description of what the group is for
+    #[must_use]
+    pub fn with_description(mut self, description: impl Into<String>) -> Self {
+        self.description = description.into();
+        self
+    }
+
+    /// Set the color to show tags in the group with, as red, green and blue
+    #[must_use]
+    pub fn with_color(mut self, color: [u8; 3]) -> Self {
+        self.color = Some(color);
+        self
+    }
+
+    /// Set whether a file may only have one tag in the group, like a rating or a status
+    #[must_use]
+    pub fn with_exclusive(mut self, exclusive: bool) -> Self {
+        self.exclusive = exclusive;
+        self
+    }
+
+    /// The description of what the group is for, which is empty if it hasn't been set
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    /// The color to show tags in the group with, as red, green and blue
+    pub fn color(&self) -> Option<[u8; 3]> {
+        self.color
+    }
+
+    /// Whether a file may only have one tag in the group
+    pub fn is_exclusive(&self) -> bool {
+        self.exclusive
+    }
+}
+
+/// The known groups of a filesystem, stored in its [`SpecialFile::Groups`] file. Not to be
+/// confused with [`SpecialFile::TagRegistry`], the generated list of every tag in use.
+///
+/// Groups needn't be registered to be used, and registering one doesn't change any files. Rules
+/// such as exclusivity are only enforced by a [`RegistryFs`].
+pub struct TagRegistry<'a, F: ?Sized> {
+    fs: &'a F,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> TagRegistry<'a, F> {
+    /// Get the registry of a filesystem
+    pub fn new(fs: &'a F) -> TagRegistry<'a, F> {
+        TagRegistry { fs }
+    }
+
+    /// Get every registered group, and what's known about it
+    pub fn groups(&self) -> Result<BTreeMap<Group, GroupInfo>, Error<F::Error>> {
+        let data = self.fs.special_data(SpecialFile::Groups)?;
+        decode(&data).map_err(Error::Corrupt)
+    }
+
+    /// Get what's known about a group, if it's registered
+    pub fn group(&self, group: &Group) -> Result<Option<GroupInfo>, Error<F::Error>> {
+        Ok(self.groups()?.remove(group))
+    }
+
+    /// Check that a set of tags could be given to a file, failing with the first exclusive group
+    /// it has more than one tag in
+    pub fn check(&self, tags: &BTreeSet<Tag>) -> Result<(), Error<F::Error>> {
+        check(&self.groups()?, tags, |_| true)
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> TagRegistry<'_, F> {
+    fn store(&self, groups: &BTreeMap<Group, GroupInfo>) -> Result<(), Error<F::Error>> {
+        let data = encode(groups).map_err(Error::Corrupt)?;
+        self.fs.set_special_data(SpecialFile::Groups, &data)?;
+        Ok(())
+    }
+
+    /// Register a group, replacing what was known about it if it already was
+    pub fn set_group(&self, group: Group, info: GroupInfo) -> Result<(), Error<F::Error>> {
+        let mut groups = self.groups()?;
+        groups.insert(group, info);
+        self.store(&groups)
+    }
+
+    /// Forget about a group. Returns whether it was registered.
+    pub fn remove_group(&self, group: &Group) -> Result<bool, Error<F::Error>> {
+        let mut groups = self.groups()?;
+        if groups.remove(group).is_none() {
+            return Ok(false);
+        }
+        self.store(&groups)?;
+        Ok(true)
+    }
+}
+
+fn encode(groups: &BTreeMap<Group, GroupInfo>) -> io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    for (group, info) in groups {
+        codec::write_group(&mut out, group)?;
+        codec::write_string(&mut out, &info.description)?;
+        let mut flags = 0;
+        if info.exclusive {
+            flags |= FLAG_EXCLUSIVE;
+        }
+        if info.color.is_some() {
+            flags |= FLAG_COLOR;
+        }
+        out.write_all(&[flags])?;
+        if let Some(color) = info.color {
+            out.write_all(&color)?;
+        }
+    }
+    Ok(out)
+}
+
+fn decode(mut input: &[u8]) -> io::Result<BTreeMap<Group, GroupInfo>> {
+    let mut groups = BTreeMap::new();
+    while !input.is_empty() {
+        let group = codec::read_group(&mut input)?;
+        let description = codec::read_string(&mut input)?;
+        let mut flags = [0; 1];
+        input.read_exact(&mut flags)?;
+        let flags = flags[0];
+        if flags & !(FLAG_EXCLUSIVE | FLAG_COLOR) != 0 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Invalid group flags",
+            ));
+        }
+        let color = if flags & FLAG_COLOR == 0 {
+            None
+        } else {
+            let mut color = [0; 3];
+            input.read_exact(&mut color)?;
+            Some(color)
+        };
+        groups.insert(
+            group,
+            GroupInfo {
+                description,
+                color,
+                exclusive: flags & FLAG_EXCLUSIVE != 0,
+            },
+        );
+    }
+    Ok(groups)
+}
+
+/// Check a set of tags against the exclusive groups, out of those `touched` accepts
+fn check<E>(
+    groups: &BTreeMap<Group, GroupInfo>,
+    tags: &BTreeSet<Tag>,
+    touched: impl Fn(&Group) -> bool,
+) -> Result<(), Error<E>> {
+    let mut seen = BTreeSet::new();
+    for group in tags.iter().map(Tag::group) {
+        let exclusive = groups.get(group).is_some_and(GroupInfo::is_exclusive);
+        if exclusive && touched(group) && !seen.insert(group) {
+            return Err(Error::Exclusive(group.clone()));
+        }
+    }
+    Ok(())
+}
+
+/// A wrapper around another filesystem which enforces the rules of its [`TagRegistry`]. Giving
+/// a file more than one tag in an exclusive group fails with an error of kind
+/// [`ErrorKind::InvalidTags`], and nothing is passed on to the inner filesystem.
+///
+/// The registry is read again for every change, so changes to it apply straight away, whether
+/// made through this wrapper or not. Only the groups a change gives tags in are checked, so files
+/// which broke a rule before it was registered can still be changed otherwise. Tags are checked
+/// as given, so inferred and provided tags are never rejected.
+pub struct RegistryFs<F> {
+    inner: F,
+}
+
+impl<F: FileSystem> RegistryFs<F> {
+    /// Wrap a filesystem, enforcing the rules of its registry
+    pub fn new(inner: F) -> RegistryFs<F> {
+        RegistryFs { inner }
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, no longer enforcing its registry
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    fn check(
+        &self,
+        tags: &BTreeSet<Tag>,
+        touched: impl Fn(&Group) -> bool,
+    ) -> Result<(), Error<F::Error>> {
+        check(&TagRegistry::new(&self.inner).groups()?, tags, touched)
+    }
+
+    /// Check every file some tags would be renamed on, with their tags once renamed
+    fn check_rename(
+        &self,
+        pattern: TagPredicate,
+        group: &Group,
+        rename: impl Fn(Tag) -> Tag,
+    ) -> Result<(), Error<F::Error>> {
+        let groups = TagRegistry::new(&self.inner).groups()?;
+        if !groups.get(group).is_some_and(GroupInfo::is_exclusive) {
+            return Ok(());
+        }
+        for id in self.inner.search_tags(pattern)? {
+            let tags = self.inner.get_tags(id)?.into_iter().map(&rename).collect();
+            check(&groups, &tags, |touched| touched == group)?;
+        }
+        Ok(())
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for RegistryFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.search_tags_with(tags, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        WrappedSearchIter::new(self.inner.search_tags_iter(tags))
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.tag_counts(pattern)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.count_tags(pattern)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.any_match(pattern)?)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        Ok(self.inner.usage()?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for RegistryFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.check(&tags, |_| true)?;
+        Ok(self.inner.add_file(data, tags)?)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.check(&tags, |_| true)?;
+        Ok(self.inner.add_file_with_id(id, data, tags)?)
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.map(|tags| tags.into_iter().collect::<BTreeSet<_>>());
+        if let Some(tags) = &tags {
+            self.check(tags, |_| true)?;
+        }
+        Ok(self.inner.edit_file(id, data, tags)?)
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        Ok(self.inner.remove_file(id)?)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done
+        let mut failed = None;
+        let out = self.inner.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Fs(err)) => Err(err),
+            Err(err) => {
+                failed = Some(err);
+                Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        match (out, failed) {
+            (Err(_), Some(err)) => Err(err),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        let added = tags.iter().map(Tag::group).collect::<BTreeSet<_>>();
+        let mut after = self.inner.get_tags(id)?;
+        after.extend(tags.iter().cloned());
+        self.check(&after, |group| added.contains(group))?;
+        Ok(self.inner.add_tags(id, tags)?)
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(self.inner.remove_tags(id, tags)?)
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        self.check_rename(TagPredicate::Tag(old.clone()), new.group(), |tag| {
+            if tag == *old {
+                new.clone()
+            } else {
+                tag
+            }
+        })?;
+        Ok(self.inner.rename_tag(old, new)?)
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        self.check_rename(TagPredicate::Group(old.clone()), &new, |tag| {
+            crate::rename_group(tag, old, &new)
+        })?;
+        Ok(self.inner.rename_group(old, new)?)
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        Ok(self.inner.revert(id, version)?)
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        Ok(self.inner.set_special_data(file, data)?)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for RegistryFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl<F: InferredTags> InferredTags for RegistryFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for RegistryFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for RegistryFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let tags = tags.into_iter().collect::<BTreeSet<_>>();
+        self.check(&tags, |_| true)?;
+        Ok(WrappedWriter::new(self.inner.create_file(tags)?))
+    }
+}
+
+/// A lazy search over a [`RegistryFs`], which is a search of the inner filesystem
+pub type SearchIter<'a, F, P> = WrappedSearchIter<
+    <F as FileSystemRead>::SearchIter<'a, P>,
+    Error<<F as FileSystemRead>::Error>,
+>;
+
+/// A handle streaming data into a new file of a [`RegistryFs`], which is a handle of the inner
+/// filesystem. Its tags were checked when it was created.
+pub type Writer<'a, F> =
+    WrappedWriter<<F as StreamWrite>::Writer<'a>, Error<<F as FileSystemRead>::Error>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{Error as _, InMemoryFs};
+
+    #[test]
+    fn test_registry() {
+        let fs = InMemoryFs::new();
+        let registry = TagRegistry::new(&fs);
+        assert!(registry.groups().unwrap().is_empty());
+
+        let status = Group::custom("status");
+        let info = GroupInfo::new()
+            .with_description("Where a file is in review")
+            .with_color([255, 128, 0])
+            .with_exclusive(true);
+        registry.set_group(status.clone(), info.clone()).unwrap();
+        registry
+            .set_group(Group::Default, GroupInfo::new())
+            .unwrap();
+        assert_eq!(registry.group(&status).unwrap(), Some(info));
+        assert_eq!(registry.groups().unwrap().len(), 2);
+
+        let tags = BTreeSet::from([Tag::new("status", "draft"), Tag::new("status", "done")]);
+        assert!(matches!(registry.check(&tags), Err(Error::Exclusive(_))));
+
+        assert!(registry.remove_group(&Group::Default).unwrap());
+        assert!(!registry.remove_group(&Group::Default).unwrap());
+        assert_eq!(registry.groups().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_registry_fs() {
+        let fs = RegistryFs::new(InMemoryFs::new());
+        let status = Group::custom("status");
+        let draft = Tag::new("status", "draft");
+        let done = Tag::new("status", "done");
+
+        // Nothing is enforced until the group is registered
+        let both = fs.add_file(&[], [draft.clone(), done.clone()]).unwrap();
+        TagRegistry::new(&fs)
+            .set_group(status.clone(), GroupInfo::new().with_exclusive(true))
+            .unwrap();
+
+        let err = fs.add_file(&[], [draft.clone(), done.clone()]).unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::InvalidTags));
+        assert!(matches!(err, Error::Exclusive(group) if group == status));
+
+        let id = fs.add_file(&[], [draft.clone(), Tag::named("a")]).unwrap();
+        assert!(matches!(
+            fs.add_tags(id, [done.clone()]),
+            Err(Error::Exclusive(_))
+        ));
+        assert!(fs.create_file([draft.clone(), done.clone()]).is_err());
+        fs.edit_file(id, None, Some([done.clone()])).unwrap();
+        assert_eq!(fs.get_tags(id).unwrap(), BTreeSet::from([done.clone()]));
+
+        // Files which already broke the rule can still be changed in other groups
+        fs.add_tags(both, [Tag::named("b")]).unwrap();
+
+        let other = fs.add_file(&[], [Tag::new("old", "x")]).unwrap();
+        fs.add_tags(other, [Tag::new("old", "y")]).unwrap();
+        assert!(matches!(
+            fs.rename_group(&Group::custom("old"), status.clone()),
+            Err(Error::Exclusive(_))
+        ));
+        assert!(matches!(
+            fs.rename_tag(&Tag::new("old", "x"), draft.clone()),
+            Ok(())
+        ));
+
+        let res = fs.transaction(|fs| {
+            fs.remove_file(id)?;
+            fs.add_tags(other, [done.clone()])
+        });
+        assert!(matches!(res, Err(Error::Exclusive(_))));
+        assert!(fs.get_info(id).is_ok());
+    }
+}
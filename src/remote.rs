@@ -0,0 +1,829 @@
+//! A filesystem on another machine, accessed through the HTTP server in the `server` module
+
+use core::convert::TryFrom;
+use core::fmt;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, Read};
+use std::sync::{Mutex, PoisonError, RwLock};
+use std::time::{Duration, UNIX_EPOCH};
+use std::vec;
+
+use serde_json::Value;
+use ureq::http::Response;
+use ureq::{Agent, Body, BodyReader};
+
+use crate::error::ErrorKind;
+use crate::events::Subscribers;
+use crate::inference::{infer_tags, Inferrers};
+use crate::json;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, BufferedWriter, Event, FileId, FileInfo, FileSystemRead, FileSystemWrite, Group,
+    InferredTags, Metadata, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag, TagInferrer,
+    TagPattern, TagProvider,
+};
+
+/// How many IDs a lazy search asks for at once
+const PAGE_LEN: usize = 256;
+
+/// Error for a remote filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// A file wasn't found
+    FileNotFound(FileId),
+    /// A file with the given ID already exists, or the ID is reserved
+    AlreadyExists(FileId),
+    /// The requested prior version of a file isn't kept
+    VersionNotFound(FileId, u32),
+    /// The remote filesystem is read-only
+    ReadOnly,
+    /// The remote filesystem is full
+    QuotaExceeded,
+    /// The remote filesystem rejected the tags given to a file
+    InvalidTags,
+    /// The remote filesystem denied the caller permission
+    PermissionDenied,
+    /// The remote filesystem ran out of room
+    StorageFull,
+    /// The remote filesystem is in an invalid state
+    State,
+    /// The remote filesystem failed in some other way, with this HTTP status
+    Server(u16),
+    /// The server didn't understand a request, giving this reason
+    BadRequest(String),
+    /// The server sent a response which couldn't be understood, such as if it isn't a TBF server
+    InvalidResponse,
+    /// The request can't be made to a remote filesystem, such as registering a tag provider or
+    /// searching with a [`TagPredicate::Custom`](crate::TagPredicate::Custom) predicate, as
+    /// they'd have to run on the server
+    Unsupported,
+    /// The server couldn't be reached, or the connection failed
+    Http(ureq::Error),
+    /// An I/O error occured while reading a response
+    IoError(io::Error),
+}
+
+impl Error {
+    /// Get the error described by an error response
+    fn from_response(status: u16, body: &Value) -> Error {
+        let id = body
+            .get("id")
+            .and_then(Value::as_str)
+            .and_then(json::parse_id);
+        let version = body
+            .get("version")
+            .and_then(Value::as_u64)
+            .and_then(|version| u32::try_from(version).ok());
+        match (body.get("error").and_then(Value::as_str), id, version) {
+            (Some("file_not_found"), Some(id), _) => Error::FileNotFound(id),
+            (Some("already_exists"), Some(id), _) => Error::AlreadyExists(id),
+            (Some("version_not_found"), Some(id), Some(version)) => {
+                Error::VersionNotFound(id, version)
+            }
+            (Some("read_only"), ..) => Error::ReadOnly,
+            (Some("quota_exceeded"), ..) => Error::QuotaExceeded,
+            (Some("invalid_tags"), ..) => Error::InvalidTags,
+            (Some("permission_denied"), ..) => Error::PermissionDenied,
+            (Some("storage_full"), ..) => Error::StorageFull,
+            (Some("state"), ..) => Error::State,
+            (Some("bad_request"), ..) => Error::BadRequest(
+                body.get("message")
+                    .and_then(Value::as_str)
+                    .unwrap_or_default()
+                    .to_owned(),
+            ),
+            _ => Error::Server(status),
+        }
+    }
+}
+
+impl From<ureq::Error> for Error {
+    fn from(err: ureq::Error) -> Error {
+        Error::Http(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl crate::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Error::FileNotFound(id)
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::AlreadyExists(id)
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::VersionNotFound(id, version)
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Error::StorageFull
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::FileNotFound(id) => ErrorKind::FileNotFound(*id),
+            Error::AlreadyExists(id) => ErrorKind::AlreadyExists(*id),
+            Error::VersionNotFound(id, version) => ErrorKind::VersionNotFound(*id, *version),
+            Error::ReadOnly => ErrorKind::ReadOnly,
+            Error::QuotaExceeded => ErrorKind::QuotaExceeded,
+            Error::InvalidTags => ErrorKind::InvalidTags,
+            Error::PermissionDenied => ErrorKind::PermissionDenied,
+            Error::StorageFull => ErrorKind::StorageFull,
+            Error::State => ErrorKind::State,
+            Error::Http(err) => ErrorKind::Source(err),
+            Error::IoError(err) => ErrorKind::Source(err),
+            Error::Server(_)
+            | Error::BadRequest(_)
+            | Error::InvalidResponse
+            | Error::Unsupported => ErrorKind::Other,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::FileNotFound(id) => write!(f, "file {id} not found"),
+            Error::AlreadyExists(id) => write!(f, "file {id} already exists"),
+            Error::VersionNotFound(id, version) => {
+                write!(f, "version {version} of file {id} isn't kept")
+            }
+            Error::ReadOnly => write!(f, "remote filesystem is read-only"),
+            Error::QuotaExceeded => {
+                write!(f, "change would go over a quota of the remote filesystem")
+            }
+            Error::InvalidTags => write!(f, "remote filesystem rejected the tags"),
+            Error::PermissionDenied => write!(f, "remote filesystem denied permission"),
+            Error::StorageFull => write!(f, "remote filesystem is out of room"),
+            Error::State => write!(f, "remote filesystem is in an invalid state"),
+            Error::Server(status) => write!(f, "server failed with HTTP status {status}"),
+            Error::BadRequest(reason) => write!(f, "server rejected the request: {reason}"),
+            Error::InvalidResponse => {
+                write!(f, "server sent a response which couldn't be understood")
+            }
+            Error::Unsupported => write!(f, "request can't be made to a remote filesystem"),
+            Error::Http(err) => write!(f, "couldn't reach the server: {err}"),
+            Error::IoError(err) => write!(f, "couldn't read a response: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Http(err) => std::error::Error::source(err),
+            Error::IoError(err) => std::error::Error::source(err),
+            _ => None,
+        }
+    }
+}
+
+/// How to undo a change made during a transaction
+enum Undo {
+    /// Remove a file which was added
+    Remove(FileId),
+    /// Put back a file as it was before being edited or removed
+    Restore(FileInfo),
+    /// Put back a stored special file
+    Special(SpecialFile, Vec<u8>),
+}
+
+/// A tag-based filesystem served by another process, likely on another machine, through the
+/// HTTP server, from the `server` module. Every operation is a request to the server, so can
+/// fail if it can't be reached.
+///
+/// Transactions are carried out by the client, which undoes the changes it made if one fails.
+/// They aren't isolated from other clients of the same server, and are left part way through
+/// if the client loses its connection or stops while undoing them. Events are only sent for
+/// changes made through this client, and tag inferrers run on the client before data is sent.
+pub struct RemoteFs {
+    url: String,
+    agent: Agent,
+    inferrers: RwLock<Inferrers>,
+    undo: Mutex<Option<Vec<Undo>>>,
+    subscribers: Subscribers,
+}
+
+impl RemoteFs {
+    /// Create a filesystem for the server at a base URL, like `http://localhost:8080`. Nothing
+    /// is sent to the server until the filesystem is used.
+    pub fn new(url: &str) -> RemoteFs {
+        let config = Agent::config_builder().http_status_as_error(false).build();
+        RemoteFs {
+            url: url.trim_end_matches('/').to_owned(),
+            agent: Agent::new_with_config(config),
+            inferrers: RwLock::new(Vec::new()),
+            undo: Mutex::new(None),
+            subscribers: Subscribers::new(),
+        }
+    }
+
+    /// Get the base URL of the server
+    pub fn url(&self) -> &str {
+        &self.url
+    }
+
+    fn path(&self, path: &str) -> String {
+        format!("{}{path}", self.url)
+    }
+
+    fn file_path(&self, id: FileId, rest: &str) -> String {
+        format!("{}/files/{}{rest}", self.url, json::id_to_string(id))
+    }
+
+    /// Turn error responses into errors
+    fn check(response: Result<Response<Body>, ureq::Error>) -> Result<Response<Body>, Error> {
+        let response = response?;
+        let status = response.status().as_u16();
+        if status < 400 {
+            return Ok(response);
+        }
+        let body = read_json(response).unwrap_or(Value::Null);
+        Err(Error::from_response(status, &body))
+    }
+
+    fn get_json(&self, url: &str) -> Result<Value, Error> {
+        read_json(RemoteFs::check(self.agent.get(url).call())?)
+    }
+
+    fn search_json(&self, search: &Value) -> Result<Vec<FileId>, Error> {
+        let response = self
+            .agent
+            .post(self.path("/search"))
+            .content_type("application/json")
+            .send(search.to_string());
+        let ids = read_json(RemoteFs::check(response)?)?;
+        json::ids_from_json(&ids).ok_or(Error::InvalidResponse)
+    }
+
+    /// Add a file, letting the server pick the ID unless one is given
+    fn put_new(&self, id: Option<FileId>, data: &[u8], tags: &[Tag]) -> Result<FileId, Error> {
+        let tags = json::to_ascii(&json::tags_to_json(tags));
+        let response = match id {
+            Some(id) => self.agent.put(self.file_path(id, "")),
+            None => self.agent.post(self.path("/files")),
+        }
+        .header(json::TAGS_HEADER, tags)
+        .send(data);
+        let body = read_json(RemoteFs::check(response)?)?;
+        body.get("id")
+            .and_then(Value::as_str)
+            .and_then(json::parse_id)
+            .ok_or(Error::InvalidResponse)
+    }
+
+    fn put_data(&self, id: FileId, data: &[u8], tags: Option<&[Tag]>) -> Result<(), Error> {
+        let mut request = self.agent.put(self.file_path(id, "/data"));
+        if let Some(tags) = tags {
+            request = request.header(json::TAGS_HEADER, json::to_ascii(&json::tags_to_json(tags)));
+        }
+        RemoteFs::check(request.send(data))?;
+        Ok(())
+    }
+
+    fn put_tags(&self, id: FileId, tags: &[Tag]) -> Result<(), Error> {
+        let response = self
+            .agent
+            .put(self.file_path(id, "/tags"))
+            .content_type("application/json")
+            .send(json::tags_to_json(tags).to_string());
+        RemoteFs::check(response)?;
+        Ok(())
+    }
+
+    fn special_path(&self, file: SpecialFile) -> String {
+        format!("{}/special/{}", self.url, file.name())
+    }
+
+    fn put_special(&self, file: SpecialFile, data: &[u8]) -> Result<(), Error> {
+        RemoteFs::check(self.agent.put(self.special_path(file)).send(data))?;
+        Ok(())
+    }
+
+    /// Remember how to undo a change, if a transaction is running
+    fn record<U>(&self, undo: U) -> Result<(), Error>
+    where
+        U: FnOnce() -> Result<Undo, Error>,
+    {
+        let mut log = self.undo.lock().unwrap_or_else(PoisonError::into_inner);
+        if let Some(log) = &mut *log {
+            log.push(undo()?);
+        }
+        Ok(())
+    }
+
+    fn apply(&self, undo: Undo) -> Result<(), Error> {
+        match undo {
+            Undo::Remove(id) => {
+                RemoteFs::check(self.agent.delete(self.file_path(id, "")).call())?;
+                Ok(())
+            }
+            Undo::Restore(info) => {
+                let tags = info.tags.into_iter().collect::<Vec<_>>();
+                match self.put_data(info.id, &info.data, Some(&tags)) {
+                    Err(Error::FileNotFound(_)) => {
+                        self.put_new(Some(info.id), &info.data, &tags).map(|_| ())
+                    }
+                    res => res,
+                }
+            }
+            Undo::Special(file, data) => self.put_special(file, &data),
+        }
+    }
+
+    fn snapshot(&self, id: FileId) -> Result<Undo, Error> {
+        Ok(Undo::Restore(self.get_info(id)?))
+    }
+}
+
+impl FileSystemRead for RemoteFs {
+    type Error = Error;
+    type SearchIter<'a, P>
+        = SearchIter<'a>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.search_tags_with(tags, &SearchOptions::new())
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let search = json::search_to_json(&tags.to_predicate(), options);
+        self.search_json(&search.ok_or(Error::Unsupported)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            fs: self,
+            pattern: tags.to_predicate(),
+            page: Vec::new().into_iter(),
+            after: None,
+            done: false,
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        let tags = self.get_tags(id)?;
+        let data = self.get_data(id)?;
+        Ok(FileInfo {
+            id,
+            tags,
+            data: data.into_boxed_slice(),
+        })
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        let tags = self.get_json(&self.file_path(id, "/tags"))?;
+        json::tags_from_json(&tags)
+            .map(|tags| tags.into_iter().collect())
+            .ok_or(Error::InvalidResponse)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        let path = format!("/hashes/{}", json::hash_to_string(hash));
+        match self.get_json(&self.path(&path))?.get("id") {
+            Some(Value::Null) => Ok(None),
+            Some(id) => id
+                .as_str()
+                .and_then(json::parse_id)
+                .map(Some)
+                .ok_or(Error::InvalidResponse),
+            None => Err(Error::InvalidResponse),
+        }
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        // Tags are found in one request, but each file's data still takes its own
+        let tags = self.get_tags_many(ids)?;
+        Ok(ids
+            .iter()
+            .zip(tags)
+            .map(|(&id, tags)| {
+                Ok(FileInfo {
+                    id,
+                    tags: tags?,
+                    data: self.get_data(id)?.into_boxed_slice(),
+                })
+            })
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        let response = self
+            .agent
+            .post(self.path("/tags"))
+            .content_type("application/json")
+            .send(json::ids_to_json(ids).to_string());
+        let body = read_json(RemoteFs::check(response)?)?;
+        let found = body
+            .as_array()
+            .filter(|found| found.len() == ids.len())
+            .ok_or(Error::InvalidResponse)?;
+
+        found
+            .iter()
+            .map(|item| {
+                if let Some(tags) = item.get("tags") {
+                    return json::tags_from_json(tags)
+                        .map(|tags| Ok(tags.into_iter().collect()))
+                        .ok_or(Error::InvalidResponse);
+                }
+                let status = item
+                    .get("status")
+                    .and_then(Value::as_u64)
+                    .and_then(|status| u16::try_from(status).ok())
+                    .unwrap_or(500);
+                Ok(Err(Error::from_response(status, item)))
+            })
+            .collect()
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        let mut data = Vec::new();
+        self.read_file(id)?.read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        let info = self.get_json(&self.file_path(id, ""))?;
+        let time = |key: &str| {
+            let secs = info.get(key)?.as_u64()?;
+            Some(UNIX_EPOCH + Duration::from_secs(secs))
+        };
+        let meta = (|| {
+            Some(Metadata {
+                created: time("created")?,
+                modified: time("modified")?,
+                size: info.get("size")?.as_u64()?,
+                hash: json::parse_hash(info.get("hash")?.as_str()?)?,
+            })
+        })();
+        meta.ok_or(Error::InvalidResponse)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        let mut out = BTreeMap::new();
+        for id in self.search_tags(pattern)? {
+            for tag in self.get_tags(id)? {
+                *out.entry(tag).or_insert(0) += 1;
+            }
+        }
+        Ok(out)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        let response = RemoteFs::check(self.agent.get(self.special_path(file)).call())?;
+        let mut data = Vec::new();
+        response.into_body().into_reader().read_to_end(&mut data)?;
+        Ok(data)
+    }
+
+    fn subscribe(&self) -> Result<std::sync::mpsc::Receiver<Event>, Self::Error> {
+        Ok(self.subscribers.subscribe())
+    }
+}
+
+impl FileSystemWrite for RemoteFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<Vec<_>>();
+        tags.extend(infer_tags(
+            &self
+                .inferrers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        ));
+        let id = self.put_new(None, data, &tags)?;
+        self.record(|| Ok(Undo::Remove(id)))?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let mut tags = tags.into_iter().collect::<Vec<_>>();
+        tags.extend(infer_tags(
+            &self
+                .inferrers
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        ));
+        self.put_new(Some(id), data, &tags)?;
+        self.record(|| Ok(Undo::Remove(id)))?;
+        self.subscribers.emit(Event::FileAdded(id));
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.record(|| self.snapshot(id))?;
+        let tags = tags.map(|tags| tags.into_iter().collect::<Vec<_>>());
+        match (data, &tags) {
+            (Some(data), tags) => self.put_data(id, data, tags.as_deref())?,
+            (None, Some(tags)) => self.put_tags(id, tags)?,
+            // Nothing changes, but the file still has to exist
+            (None, None) => drop(self.get_json(&self.file_path(id, ""))?),
+        }
+
+        if data.is_some() {
+            self.subscribers.emit(Event::FileEdited(id));
+        }
+        if tags.is_some() {
+            self.subscribers.emit(Event::TagsChanged(id));
+        }
+        Ok(())
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.record(|| self.snapshot(id))?;
+        RemoteFs::check(self.agent.delete(self.file_path(id, "")).call())?;
+        self.subscribers.emit(Event::FileRemoved(id));
+        Ok(())
+    }
+
+    fn transaction<T, F>(&self, f: F) -> Result<T, Self::Error>
+    where
+        F: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut log = self.undo.lock().unwrap_or_else(PoisonError::into_inner);
+            if log.is_some() {
+                drop(log);
+                return f(self);
+            }
+            *log = Some(Vec::new());
+        }
+
+        let out = f(self);
+
+        let log = self
+            .undo
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let (Err(_), Some(log)) = (&out, log) {
+            // Undoing is best effort, as other clients may have changed the same files since
+            for undo in log.into_iter().rev() {
+                let _ = self.apply(undo);
+            }
+        }
+        out
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.record(|| Ok(Undo::Special(file, self.special_data(file)?)))?;
+        self.put_special(file, data)
+    }
+}
+
+impl ProvidedTags for RemoteFs {
+    fn register_provider<P>(&self, _: Group, _: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Err(Error::Unsupported)
+    }
+}
+
+impl InferredTags for RemoteFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inferrers
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(inferrer));
+        Ok(())
+    }
+}
+
+impl StreamRead for RemoteFs {
+    type Reader<'a> = Reader;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        let response = RemoteFs::check(self.agent.get(self.file_path(id, "/data")).call())?;
+        Ok(Reader(response.into_body().into_reader()))
+    }
+}
+
+impl StreamWrite for RemoteFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+fn read_json(response: Response<Body>) -> Result<Value, Error> {
+    let mut body = Vec::new();
+    response.into_body().into_reader().read_to_end(&mut body)?;
+    serde_json::from_slice(&body).map_err(|_| Error::InvalidResponse)
+}
+
+/// A lazy search over a [`RemoteFs`], which asks the server for a page of results at a time
+pub struct SearchIter<'a> {
+    fs: &'a RemoteFs,
+    pattern: crate::TagPredicate,
+    page: vec::IntoIter<FileId>,
+    after: Option<FileId>,
+    done: bool,
+}
+
+impl Iterator for SearchIter<'_> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(id) = self.page.next() {
+            self.after = Some(id);
+            return Some(Ok(id));
+        }
+        if self.done {
+            return None;
+        }
+
+        let mut options = SearchOptions::new().limit(PAGE_LEN);
+        if let Some(after) = self.after {
+            options = options.after(after);
+        }
+        let search = json::search_to_json(&self.pattern, &options).ok_or(Error::Unsupported);
+        match search.and_then(|search| self.fs.search_json(&search)) {
+            Ok(page) => {
+                self.done = page.len() < PAGE_LEN;
+                self.page = page.into_iter();
+                self.next()
+            }
+            Err(err) => {
+                self.done = true;
+                Some(Err(err))
+            }
+        }
+    }
+}
+
+/// A handle streaming the data of a file of a [`RemoteFs`] out of the response to a request
+pub struct Reader(BodyReader<'static>);
+
+impl Read for Reader {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0.read(buf)
+    }
+}
+
+/// A handle streaming data into a new file of a [`RemoteFs`]. Data is buffered until the handle
+/// is flushed, then sent in a single request.
+pub type Writer<'a> = BufferedWriter<'a, RemoteFs>;
+
+#[cfg(all(test, feature = "imfs", feature = "server"))]
+mod tests {
+    use std::io::Write;
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::server::Server;
+    use crate::{Error as _, FileWriter, InMemoryFs, TagPredicate};
+
+    fn serve() -> (Arc<Server<InMemoryFs>>, RemoteFs) {
+        let server = Arc::new(Server::bind(InMemoryFs::new(), "127.0.0.1:0").unwrap());
+        let addr = server.local_addr().unwrap();
+        // The thread is left running until the test exits
+        let handler = Arc::clone(&server);
+        std::thread::spawn(move || handler.run());
+        (server, RemoteFs::new(&format!("http://{addr}/")))
+    }
+
+    #[test]
+    fn test_remote() {
+        let (server, fs) = serve();
+        let events = fs.subscribe().unwrap();
+
+        let tags = [
+            Tag::new("g", "rating").with_value(4),
+            Tag::named("ünïcode").with_value(1.5),
+        ];
+        let id = fs.add_file(&[1, 2, 3], tags.clone()).unwrap();
+        assert_eq!(
+            server.fs().get_info(id).unwrap().tags(),
+            &tags.clone().into()
+        );
+        assert_eq!(fs.get_info(id).unwrap().data(), &[1, 2, 3]);
+        assert_eq!(fs.get_metadata(id).unwrap().size(), 3);
+        assert_eq!(events.try_recv(), Ok(Event::FileAdded(id)));
+
+        let other = fs.add_file(&[4], [Tag::named("other")]).unwrap();
+        assert_eq!(fs.search_tags(Tag::named("other")).unwrap(), [other]);
+        assert_eq!(
+            fs.search_tags(TagPredicate::value_gt("g", "rating", 3))
+                .unwrap(),
+            [id]
+        );
+        assert_eq!(
+            fs.search_tags_iter(TagPredicate::And(Vec::new()))
+                .collect::<Result<Vec<_>, _>>()
+                .unwrap(),
+            [id, other]
+        );
+        assert!(matches!(
+            fs.search_tags(TagPredicate::custom(|_| true)),
+            Err(Error::Unsupported)
+        ));
+
+        let missing = FileId::from_u64_unchecked(1000);
+        let many = fs.get_tags_many(&[other, missing, id]).unwrap();
+        assert_eq!(many[0].as_ref().unwrap(), &[Tag::named("other")].into());
+        assert!(matches!(many[1], Err(Error::FileNotFound(found)) if found == missing));
+        assert_eq!(many[2].as_ref().unwrap(), &tags.clone().into());
+        let many = fs.get_info_many(&[id]).unwrap();
+        assert_eq!(many[0].as_ref().unwrap().data(), &[1, 2, 3]);
+
+        let hash = *fs.get_metadata(other).unwrap().hash();
+        assert_eq!(fs.find_by_hash(&hash).unwrap(), Some(other));
+        assert_eq!(fs.find_by_hash(&[0; 32]).unwrap(), None);
+
+        fs.edit_file(id, Some(&[5]), Some([Tag::named("b")]))
+            .unwrap();
+        let info = server.fs().get_info(id).unwrap();
+        assert_eq!(info.data(), &[5]);
+        assert_eq!(info.tags(), &[Tag::named("b")].into());
+
+        let mut writer = fs.create_file([Tag::named("c")]).unwrap();
+        writer.write_all(&[6, 7]).unwrap();
+        let new = writer.commit().unwrap();
+        assert_eq!(server.fs().get_info(new).unwrap().data(), &[6, 7]);
+
+        fs.set_config(&[8]).unwrap();
+        assert_eq!(fs.config().unwrap(), [8]);
+
+        let err = fs
+            .remove_file(FileId::from_u64_unchecked(1000))
+            .unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::FileNotFound(_)));
+        fs.remove_file(other).unwrap();
+        assert!(matches!(fs.get_info(other), Err(Error::FileNotFound(_))));
+    }
+
+    #[test]
+    fn test_remote_transaction() {
+        let (server, fs) = serve();
+        let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        let removed = fs.add_file(&[2], [Tag::named("b")]).unwrap();
+
+        let res = fs.transaction(|fs| {
+            fs.add_file(&[3], [Tag::named("c")])?;
+            fs.add_tags(id, [Tag::named("d")])?;
+            fs.remove_file(removed)?;
+            fs.set_config(&[4])?;
+            fs.remove_file(FileId::from_u64_unchecked(1000))
+        });
+        assert!(res.is_err());
+
+        let inner = server.fs();
+        assert!(inner.search_tags(Tag::named("c")).unwrap().is_empty());
+        assert_eq!(
+            inner.get_info(id).unwrap().tags(),
+            &[Tag::named("a")].into()
+        );
+        assert_eq!(inner.get_info(removed).unwrap().data(), &[2]);
+        assert!(inner.config().unwrap().is_empty());
+    }
+}
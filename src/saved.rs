@@ -0,0 +1,231 @@
+//! Named predicates kept inside a filesystem, for tag-based smart folders
+
+use alloc::collections::BTreeMap;
+use core::fmt;
+use std::io;
+
+use crate::error::ErrorKind;
+use crate::{codec, FileId, FileSystemRead, FileSystemWrite, SpecialFile, TagPredicate};
+
+/// Error while reading or changing the saved searches of a filesystem
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The filesystem returned an error
+    Fs(E),
+    /// The stored searches couldn't be read, as they were malformed
+    Corrupt(io::Error),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Corrupt(_) => ErrorKind::State,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Corrupt(err) => write!(f, "stored searches are malformed: {err}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Corrupt(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// A predicate saved under a name
+#[derive(Debug, Clone, PartialEq)]
+pub struct SavedSearch {
+    name: String,
+    predicate: TagPredicate,
+}
+
+impl SavedSearch {
+    /// The name the search was saved under
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// The predicate files are matched against
+    pub fn predicate(&self) -> &TagPredicate {
+        &self.predicate
+    }
+}
+
+/// The named searches of a filesystem, stored in its [`SpecialFile::Searches`] file, so they're
+/// kept along with the files they find. Saving a search stores its predicate, not its results,
+/// so running it again finds files added since.
+///
+/// Changes read every search and write them all back, so two saved at once from different
+/// handles can lose one of them.
+pub struct SavedSearches<'a, F: ?Sized> {
+    fs: &'a F,
+}
+
+impl<'a, F: FileSystemRead + ?Sized> SavedSearches<'a, F> {
+    /// Get the saved searches of a filesystem
+    pub fn new(fs: &'a F) -> SavedSearches<'a, F> {
+        SavedSearches { fs }
+    }
+
+    fn load(&self) -> Result<BTreeMap<String, TagPredicate>, Error<F::Error>> {
+        let data = self.fs.special_data(SpecialFile::Searches)?;
+        let mut input = &*data;
+        let mut searches = BTreeMap::new();
+        while !input.is_empty() {
+            let name = codec::read_string(&mut input).map_err(Error::Corrupt)?;
+            let pred = codec::read_predicate(&mut input).map_err(Error::Corrupt)?;
+            searches.insert(name, pred);
+        }
+        Ok(searches)
+    }
+
+    /// List every saved search, in order of name
+    pub fn list_searches(&self) -> Result<Vec<SavedSearch>, Error<F::Error>> {
+        Ok(self
+            .load()?
+            .into_iter()
+            .map(|(name, predicate)| SavedSearch { name, predicate })
+            .collect())
+    }
+
+    /// Get the predicate saved under a name, if there is one
+    pub fn get_search(&self, name: &str) -> Result<Option<TagPredicate>, Error<F::Error>> {
+        Ok(self.load()?.remove(name))
+    }
+
+    /// Find the files matching the search saved under a name, or `None` if there's no such
+    /// search
+    pub fn run_search(&self, name: &str) -> Result<Option<Vec<FileId>>, Error<F::Error>> {
+        match self.get_search(name)? {
+            Some(pred) => Ok(Some(self.fs.search_tags(pred)?)),
+            None => Ok(None),
+        }
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> SavedSearches<'_, F> {
+    fn store(&self, searches: &BTreeMap<String, TagPredicate>) -> Result<(), Error<F::Error>> {
+        let mut data = Vec::new();
+        for (name, pred) in searches {
+            codec::write_string(&mut data, name).map_err(Error::Corrupt)?;
+            codec::write_predicate(&mut data, pred).map_err(Error::Corrupt)?;
+        }
+        self.fs.set_special_data(SpecialFile::Searches, &data)?;
+        Ok(())
+    }
+
+    /// Save a search under a name, replacing any already saved under it
+    pub fn save_search(&self, name: &str, pred: TagPredicate) -> Result<(), Error<F::Error>> {
+        let mut searches = self.load()?;
+        searches.insert(name.to_owned(), pred);
+        self.store(&searches)
+    }
+
+    /// Remove the search saved under a name. Returns whether there was one.
+    pub fn remove_search(&self, name: &str) -> Result<bool, Error<F::Error>> {
+        let mut searches = self.load()?;
+        if searches.remove(name).is_none() {
+            return Ok(false);
+        }
+        self.store(&searches)?;
+        Ok(true)
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{Group, InMemoryFs, Tag, TagValue};
+
+    #[test]
+    fn test_saved_searches() {
+        let ifs = InMemoryFs::new();
+        let photos = Group::custom("photos");
+        let beach = ifs
+            .add_file(&[1], [Tag::new(photos.clone(), "beach")])
+            .unwrap();
+        let rated = ifs
+            .add_file(&[2], [Tag::new(photos.clone(), "rating").with_value(4)])
+            .unwrap();
+
+        let searches = SavedSearches::new(&ifs);
+        assert!(searches.list_searches().unwrap().is_empty());
+        assert_eq!(searches.run_search("beach").unwrap(), None);
+
+        let good = TagPredicate::ValueGt {
+            group: photos.clone(),
+            name: "rating".to_owned(),
+            value: TagValue::Int(3),
+        };
+        searches
+            .save_search("beach", TagPredicate::and([Tag::new(photos, "beach")]))
+            .unwrap();
+        searches.save_search("good", good.clone()).unwrap();
+
+        assert_eq!(searches.run_search("beach").unwrap(), Some(vec![beach]));
+        assert_eq!(searches.run_search("good").unwrap(), Some(vec![rated]));
+        assert_eq!(searches.get_search("good").unwrap(), Some(good));
+
+        let names = searches.list_searches().unwrap();
+        let names = names.iter().map(SavedSearch::name).collect::<Vec<_>>();
+        assert_eq!(names, ["beach", "good"]);
+
+        // Searches are found again later, as they're stored in the filesystem
+        let later = ifs.add_file(&[3], [Tag::named("other")]).unwrap();
+        searches
+            .save_search("beach", TagPredicate::name("other"))
+            .unwrap();
+        assert_eq!(
+            SavedSearches::new(&ifs).run_search("beach").unwrap(),
+            Some(vec![later])
+        );
+
+        assert!(searches.remove_search("beach").unwrap());
+        assert!(!searches.remove_search("beach").unwrap());
+        assert_eq!(searches.list_searches().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_generated_special() {
+        let ifs = InMemoryFs::new();
+        assert!(ifs.set_special_data(SpecialFile::Manifest, b"x").is_err());
+        ifs.set_special_data(SpecialFile::Searches, b"x").unwrap();
+        assert!(matches!(
+            SavedSearches::new(&ifs).list_searches(),
+            Err(Error::Corrupt(_))
+        ));
+    }
+}
@@ -0,0 +1,363 @@
+//! Options controlling how search results are returned, and details of what they matched on
+
+use alloc::collections::BTreeSet;
+use alloc::string::{String, ToString};
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::time::SystemTime;
+
+use crate::{FileId, Group, Tag, TagPattern, TagPredicate, TagValue};
+
+/// What to sort search results by
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum SortBy {
+    /// Sort by file ID, which is the order files were added in for most backends
+    #[default]
+    Id,
+    /// Sort by when files were added
+    #[cfg(feature = "std")]
+    Created,
+    /// Sort by the size of files' data
+    Size,
+    /// Sort by the value of a tag. Files without a valued tag with this group and name come after
+    /// all others, whichever order is used. If a file has several values, the lowest is used.
+    Value {
+        /// The group of the tag to sort by
+        group: Group,
+        /// The name of the tag to sort by
+        name: String,
+    },
+}
+
+impl SortBy {
+    /// Sort by the value of a tag
+    pub fn value<G: Into<Group>>(group: G, name: &str) -> SortBy {
+        SortBy::Value {
+            group: group.into(),
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Options for [`FileSystemRead::search_tags_with`](crate::FileSystemRead::search_tags_with)
+///
+/// Results can be paged through either by offset, or with a cursor: the last ID of the previous
+/// page, which keeps pages stable when files are added or removed between searches. When sorting
+/// by ascending ID, the default, pages are found without finding every match first.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct SearchOptions {
+    sort: SortBy,
+    descending: bool,
+    after: Option<FileId>,
+    offset: usize,
+    limit: Option<usize>,
+}
+
+impl SearchOptions {
+    /// Create the default options, which sort results by ascending ID
+    pub fn new() -> SearchOptions {
+        SearchOptions::default()
+    }
+
+    /// Set what results are sorted by
+    #[must_use]
+    pub fn sort_by(mut self, sort: SortBy) -> Self {
+        self.sort = sort;
+        self
+    }
+
+    /// Return results from largest to smallest, instead of the other way around
+    #[must_use]
+    pub fn descending(mut self) -> Self {
+        self.descending = true;
+        self
+    }
+
+    /// Only return results after this file, in the sorted order. When sorting by anything other
+    /// than ID, the file must itself match the search, otherwise no results are returned.
+    #[must_use]
+    pub fn after(mut self, id: FileId) -> Self {
+        self.after = Some(id);
+        self
+    }
+
+    /// Skip this many results, after applying any cursor
+    #[must_use]
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Return at most this many results
+    #[must_use]
+    pub fn limit(mut self, limit: usize) -> Self {
+        self.limit = Some(limit);
+        self
+    }
+
+    /// Get what results are sorted by
+    pub fn sort(&self) -> &SortBy {
+        &self.sort
+    }
+
+    /// Check whether results are sorted largest first
+    pub fn is_descending(&self) -> bool {
+        self.descending
+    }
+
+    /// Get the file results must come after, if any
+    #[cfg(any(feature = "remote", feature = "grpc", feature = "postgres"))]
+    pub(crate) fn cursor(&self) -> Option<FileId> {
+        self.after
+    }
+
+    /// Get how many results are skipped
+    #[cfg(any(feature = "remote", feature = "grpc", feature = "postgres"))]
+    pub(crate) fn skipped(&self) -> usize {
+        self.offset
+    }
+
+    /// Get the most results returned, if limited
+    #[cfg(any(feature = "remote", feature = "grpc", feature = "postgres"))]
+    pub(crate) fn max_len(&self) -> Option<usize> {
+        self.limit
+    }
+
+    /// Check whether results are in the order lazy searches find them, so a page can be found
+    /// without finding every match
+    pub(crate) fn is_lazy(&self) -> bool {
+        self.sort == SortBy::Id && !self.descending
+    }
+
+    fn page_len(&self) -> usize {
+        self.limit.unwrap_or(usize::MAX)
+    }
+}
+
+/// A file found by [`FileSystemRead::search_detailed`](crate::FileSystemRead::search_detailed),
+/// with its tags and which parts of the pattern matched it
+///
+/// Parts of a pattern are named by their path from the whole predicate, as the index of each
+/// child of an and, or, or not on the way down. The whole predicate is the empty path, so in
+/// `a & (b | c)`, `[1, 0]` is `b`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SearchHit {
+    id: FileId,
+    tags: BTreeSet<Tag>,
+    matched: Vec<Vec<usize>>,
+    matching: BTreeSet<Tag>,
+}
+
+impl SearchHit {
+    /// Work out which parts of a predicate match a file with the given tags. Every part is
+    /// checked, even once the result of the whole predicate is known.
+    pub fn new(id: FileId, tags: BTreeSet<Tag>, pattern: &TagPredicate) -> SearchHit {
+        let mut hit = SearchHit {
+            id,
+            tags,
+            matched: Vec::new(),
+            matching: BTreeSet::new(),
+        };
+        explain(&mut hit, pattern, &mut Vec::new(), false);
+        // Parts are found after their children, but sorting the paths puts them first
+        hit.matched.sort();
+        hit
+    }
+
+    /// Get the ID of the file
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// Get every tag of the file
+    pub fn tags(&self) -> &BTreeSet<Tag> {
+        &self.tags
+    }
+
+    /// Get the path of every part of the pattern which matched the file, in the order they
+    /// appear in the pattern
+    pub fn matched(&self) -> &[Vec<usize>] {
+        &self.matched
+    }
+
+    /// Check whether the part of the pattern at a path matched the file
+    pub fn is_matched(&self, path: &[usize]) -> bool {
+        self.matched.iter().any(|matched| matched == path)
+    }
+
+    /// Get the tags of the file which a part of the pattern matched on, such as to highlight
+    /// them. Tags only matched inside a not aren't included, since they count against the file.
+    pub fn matching_tags(&self) -> &BTreeSet<Tag> {
+        &self.matching
+    }
+
+    /// Take the tags of the file
+    pub fn into_tags(self) -> BTreeSet<Tag> {
+        self.tags
+    }
+}
+
+/// Check a part of a pattern against a hit's tags, recording it and its children if they match
+fn explain(hit: &mut SearchHit, pred: &TagPredicate, path: &mut Vec<usize>, negated: bool) -> bool {
+    let mut child = |hit: &mut SearchHit, idx, pred, negated| {
+        path.push(idx);
+        let matched = explain(hit, pred, path, negated);
+        path.pop();
+        matched
+    };
+    let matched = match pred {
+        TagPredicate::And(preds) => preds.iter().enumerate().fold(true, |all, (idx, pred)| {
+            child(hit, idx, pred, negated) && all
+        }),
+        TagPredicate::Or(preds) => preds.iter().enumerate().fold(false, |any, (idx, pred)| {
+            child(hit, idx, pred, negated) || any
+        }),
+        TagPredicate::Not(pred) => !child(hit, 0, pred, !negated),
+        atom => {
+            let matching = hit
+                .tags
+                .iter()
+                .filter(|tag| atom.match_tags([*tag]))
+                .cloned()
+                .collect::<Vec<_>>();
+            let matched = !matching.is_empty();
+            if !negated {
+                hit.matching.extend(matching);
+            }
+            matched
+        }
+    };
+    if matched {
+        hit.matched.push(path.clone());
+    }
+    matched
+}
+
+/// The key found for a single file when sorting results
+pub(crate) enum SortKey {
+    /// No key beyond the file's ID
+    Id,
+    #[cfg(feature = "std")]
+    Time(SystemTime),
+    Size(u64),
+    Value(Option<TagValue>),
+}
+
+impl SortKey {
+    fn compare(&self, other: &SortKey) -> Option<Ordering> {
+        match (self, other) {
+            #[cfg(feature = "std")]
+            (SortKey::Time(a), SortKey::Time(b)) => Some(a.cmp(b)),
+            (SortKey::Size(a), SortKey::Size(b)) => Some(a.cmp(b)),
+            (SortKey::Value(Some(a)), SortKey::Value(Some(b))) => Some(a.cmp(b)),
+            _ => None,
+        }
+    }
+}
+
+/// Find the key to sort a file by from its tags, for [`SortBy::Value`]
+pub(crate) fn value_key<'a, I>(tags: I, group: &Group, name: &str) -> SortKey
+where
+    I: IntoIterator<Item = &'a Tag>,
+{
+    SortKey::Value(
+        tags.into_iter()
+            .filter(|tag| tag.group() == group && tag.name() == name)
+            .filter_map(Tag::value)
+            .min()
+            .cloned(),
+    )
+}
+
+/// Find a page of results from a lazy search, stopping once the page is full. Only valid when
+/// [`SearchOptions::is_lazy`] holds, so results come in ascending ID order.
+pub(crate) fn page_lazy<E, I>(iter: I, options: &SearchOptions) -> Result<Vec<FileId>, E>
+where
+    I: Iterator<Item = Result<FileId, E>>,
+{
+    let mut out = Vec::new();
+    if options.page_len() == 0 {
+        return Ok(out);
+    }
+
+    let mut skipped = 0;
+    for id in iter {
+        let id = id?;
+        if options.after.is_some_and(|after| id <= after) {
+            continue;
+        }
+        if skipped < options.offset {
+            skipped += 1;
+            continue;
+        }
+
+        out.push(id);
+        if out.len() == options.page_len() {
+            break;
+        }
+    }
+    Ok(out)
+}
+
+/// Find a page of results from every result in sorted order
+fn page_sorted(ids: Vec<FileId>, options: &SearchOptions) -> Vec<FileId> {
+    let start = match (options.after, &options.sort) {
+        (None, _) => 0,
+        (Some(after), SortBy::Id) if options.descending => {
+            ids.iter().take_while(|&&id| id >= after).count()
+        }
+        (Some(after), SortBy::Id) => ids.iter().take_while(|&&id| id <= after).count(),
+        (Some(after), _) => ids
+            .iter()
+            .position(|&id| id == after)
+            .map_or(ids.len(), |pos| pos + 1),
+    };
+
+    ids.into_iter()
+        .skip(start)
+        .skip(options.offset)
+        .take(options.page_len())
+        .collect()
+}
+
+/// Sort search results as requested by some options, then find the requested page. Keys are
+/// only looked up when results aren't being sorted by ID. Ties are always broken by ascending ID.
+pub(crate) fn sort_results<E, F>(
+    mut ids: Vec<FileId>,
+    options: &SearchOptions,
+    mut key: F,
+) -> Result<Vec<FileId>, E>
+where
+    F: FnMut(FileId) -> Result<SortKey, E>,
+{
+    if options.sort == SortBy::Id {
+        ids.sort_unstable();
+        if options.descending {
+            ids.reverse();
+        }
+        return Ok(page_sorted(ids, options));
+    }
+
+    let mut keyed = ids
+        .into_iter()
+        .map(|id| Ok((key(id)?, id)))
+        .collect::<Result<Vec<_>, E>>()?;
+    keyed.sort_by(|(a, a_id), (b, b_id)| {
+        let ord = match a.compare(b) {
+            Some(ord) if options.descending => ord.reverse(),
+            Some(ord) => ord,
+            // Missing values always go last
+            None => match (a, b) {
+                (SortKey::Value(None), SortKey::Value(Some(_))) => Ordering::Greater,
+                (SortKey::Value(Some(_)), SortKey::Value(None)) => Ordering::Less,
+                _ => Ordering::Equal,
+            },
+        };
+        ord.then(a_id.cmp(b_id))
+    });
+    Ok(page_sorted(
+        keyed.into_iter().map(|(_, id)| id).collect(),
+        options,
+    ))
+}
@@ -0,0 +1,513 @@
+//! Serving a filesystem over HTTP, so it can be used from other languages and machines
+//!
+//! Requests and responses use JSON, except for file data, which is always sent raw. IDs are
+//! strings of hex digits, and tags are objects like
+//! `{"group": "photos", "name": "rating", "value": {"int": 5}}`, where the group is left out if
+//! it's the default, and the value if there isn't one. Values are tagged with one of `string`,
+//! `int`, `float`, `bool`, or `timestamp`.
+//!
+//! | Request                  | Body                      | Response                        |
+//! |--------------------------|---------------------------|---------------------------------|
+//! | `GET /files`             |                           | The IDs of matching files       |
+//! | `POST /search`           | A search                  | The IDs of matching files       |
+//! | `POST /files`            | The file's data           | `{"id": ID}` of the new file    |
+//! | `GET /files/ID`          |                           | The file's tags and metadata    |
+//! | `PUT /files/ID`          | The file's data           | `{"id": ID}` of the new file    |
+//! | `DELETE /files/ID`       |                           | Nothing                         |
+//! | `GET /files/ID/data`     |                           | The file's data                 |
+//! | `PUT /files/ID/data`     | The file's new data       | Nothing                         |
+//! | `GET /files/ID/tags`     |                           | The file's tags                 |
+//! | `PUT /files/ID/tags`     | The file's new tags       | Nothing                         |
+//! | `POST /tags`             | A list of IDs             | Each file's tags, or an error   |
+//! | `GET /hashes/HASH`       |                           | `{"id": ID}` of a matching file |
+//! | `GET /special/NAME`      |                           | A stored special file's data    |
+//! | `PUT /special/NAME`      | The special file's data   | Nothing                         |
+//!
+//! `GET /files` takes the pattern in the `q` query parameter, in the syntax parsed by
+//! [`TagPredicate::parse`], and matches every file without one. Results can be paged through
+//! with the `offset`, `limit`, and `after` parameters, as in [`SearchOptions`]. Patterns which
+//! can't be written in that syntax are searched for with `POST /search` instead, whose body is
+//! an object like `{"pattern": {"name_glob": "*.png"}, "sort": "size", "limit": 10}`.
+//!
+//! Files are added with `POST /files`, or `PUT /files/ID` to pick their ID, taking their tags
+//! as a JSON list in the [`TAGS_HEADER`] header, escaped to ASCII. The header can also be sent
+//! when replacing a file's data, to replace its tags at the same time.
+//!
+//! `POST /tags` looks up many files at once, as with [`FileSystemRead::get_tags_many`]. Each
+//! file gets either an object like `{"tags": [...]}`, or an error object like those below, with
+//! the `status` it would have been sent with.
+//!
+//! `GET /hashes/HASH` finds a file by the BLAKE3 hash of its data, given as 64 hex digits, as
+//! with [`FileSystemRead::find_by_hash`]. The ID is `null` if no file has that data.
+//!
+//! Special files are named as by [`SpecialFile::name`], and `/config` is kept as a shorthand for
+//! `/special/config`. Only special files which aren't generated can be set, and reading a
+//! generated one gives nothing, as with [`FileSystemRead::special_data`].
+//!
+//! Errors have a 4xx or 5xx status, with a body like `{"error": "file_not_found", "id": ID}`.
+
+use std::io::{self, Read};
+use std::net::{SocketAddr, TcpListener, ToSocketAddrs};
+use std::time::UNIX_EPOCH;
+
+use serde_json::{json, Value};
+use tiny_http::{Header, Method, Request, Response};
+
+use crate::json;
+#[cfg(doc)]
+use crate::FileSystemRead;
+use crate::{
+    FileId, FileWriter, SearchOptions, SpecialFile, StreamRead, StreamWrite, Tag, TagPredicate,
+};
+
+/// The header holding the tags of a new file
+pub const TAGS_HEADER: &str = json::TAGS_HEADER;
+
+/// A response which hasn't been sent yet
+enum Reply<'a> {
+    Json(u16, Value),
+    Data(Box<dyn Read + 'a>),
+    Empty(u16),
+}
+
+impl Reply<'_> {
+    fn error(err: &impl crate::Error) -> Reply<'static> {
+        let kind = err.generic_kind();
+        Reply::Json(json::error_status(&kind), json::error_to_json(&kind))
+    }
+
+    fn bad_request(message: &str) -> Reply<'static> {
+        Reply::Json(400, json::bad_request(message))
+    }
+
+    fn not_allowed() -> Reply<'static> {
+        Reply::Json(405, json::bad_request("Method not allowed"))
+    }
+}
+
+/// An HTTP server for a filesystem
+pub struct Server<F> {
+    fs: F,
+    http: tiny_http::Server,
+}
+
+impl<F: StreamRead + StreamWrite> Server<F> {
+    /// Create a server for a filesystem, listening on an address. No requests are handled until
+    /// [`Server::run`] is called.
+    pub fn bind<A: ToSocketAddrs>(fs: F, addr: A) -> io::Result<Server<F>> {
+        let listener = TcpListener::bind(addr)?;
+        let http = tiny_http::Server::from_listener(listener, None).map_err(io::Error::other)?;
+        Ok(Server { fs, http })
+    }
+
+    /// Get the served filesystem
+    pub fn fs(&self) -> &F {
+        &self.fs
+    }
+
+    /// Get the address the server is listening on, such as to find the port picked when bound
+    /// to port 0
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.http.server_addr().to_ip()
+    }
+
+    /// Handle requests until an error occurs while receiving one. Requests are handled one at a
+    /// time, but this can be called from several threads at once to handle them concurrently.
+    pub fn run(&self) -> io::Result<()> {
+        loop {
+            self.handle_one()?;
+        }
+    }
+
+    /// Wait for a single request and handle it
+    pub fn handle_one(&self) -> io::Result<()> {
+        let mut request = self.http.recv()?;
+        let reply = self.route(&mut request);
+        match reply {
+            Reply::Json(status, body) => request.respond(
+                Response::from_data(body.to_string())
+                    .with_status_code(status)
+                    .with_header(content_type("application/json")),
+            ),
+            Reply::Data(reader) => request.respond(
+                Response::new(200.into(), Vec::new(), reader, None, None)
+                    .with_header(content_type("application/octet-stream")),
+            ),
+            Reply::Empty(status) => request.respond(Response::empty(status)),
+        }
+    }
+
+    fn route(&self, request: &mut Request) -> Reply<'_> {
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        let parts = path
+            .split('/')
+            .filter(|part| !part.is_empty())
+            .collect::<Vec<_>>();
+
+        let method = request.method().clone();
+        match (parts.as_slice(), method) {
+            (["files"], Method::Get) => self.search(query),
+            (["files"], Method::Post) => self.add(request, None),
+            (["search"], Method::Post) => {
+                let Some((pattern, options)) =
+                    read_json(request).as_ref().and_then(json::search_from_json)
+                else {
+                    return Reply::bad_request("Invalid search");
+                };
+                match self.fs.search_tags_with(pattern, &options) {
+                    Ok(ids) => Reply::Json(200, json::ids_to_json(&ids)),
+                    Err(err) => Reply::error(&err),
+                }
+            }
+            (["tags"], Method::Post) => {
+                let Some(ids) = read_json(request).as_ref().and_then(json::ids_from_json) else {
+                    return Reply::bad_request("Invalid file IDs");
+                };
+                match self.fs.get_tags_many(&ids) {
+                    Ok(found) => Reply::Json(200, json::tags_many_to_json(found)),
+                    Err(err) => Reply::error(&err),
+                }
+            }
+            (["hashes", hash], Method::Get) => {
+                let Some(hash) = json::parse_hash(hash) else {
+                    return Reply::bad_request("Invalid hash");
+                };
+                match self.fs.find_by_hash(&hash) {
+                    Ok(id) => Reply::Json(200, json!({ "id": id.map(json::id_to_string) })),
+                    Err(err) => Reply::error(&err),
+                }
+            }
+            (["config"], method) => self.route_special(request, SpecialFile::Config, &method),
+            (["special", name], method) => match SpecialFile::from_name(name) {
+                Some(file) => self.route_special(request, file, &method),
+                None => Reply::Json(404, json::bad_request("No such special file")),
+            },
+            (["files", id, rest @ ..], method) if rest.len() <= 1 => {
+                let Some(id) = json::parse_id(id) else {
+                    return Reply::bad_request("Invalid file ID");
+                };
+                self.route_file(request, id, rest.first().copied(), &method)
+            }
+            (["files" | "search" | "tags"] | ["hashes", _], _) => Reply::not_allowed(),
+            _ => Reply::Json(404, json::bad_request("No such endpoint")),
+        }
+    }
+
+    fn route_special(
+        &self,
+        request: &mut Request,
+        file: SpecialFile,
+        method: &Method,
+    ) -> Reply<'_> {
+        match method {
+            Method::Get => match self.fs.special_data(file) {
+                Ok(data) => Reply::Data(Box::new(io::Cursor::new(data))),
+                Err(err) => Reply::error(&err),
+            },
+            Method::Put => match read_body(request) {
+                Ok(data) => match self.fs.set_special_data(file, &data) {
+                    Ok(()) => Reply::Empty(204),
+                    Err(err) => Reply::error(&err),
+                },
+                Err(reply) => reply,
+            },
+            _ => Reply::not_allowed(),
+        }
+    }
+
+    fn route_file(
+        &self,
+        request: &mut Request,
+        id: FileId,
+        part: Option<&str>,
+        method: &Method,
+    ) -> Reply<'_> {
+        let done = |res: Result<(), F::Error>| match res {
+            Ok(()) => Reply::Empty(204),
+            Err(err) => Reply::error(&err),
+        };
+        match (part, method) {
+            (None, Method::Get) => self.info(id),
+            (None, Method::Put) => self.add(request, Some(id)),
+            (None, Method::Delete) => done(self.fs.remove_file(id)),
+            (Some("data"), Method::Get) => match self.fs.read_file(id) {
+                Ok(reader) => Reply::Data(Box::new(reader)),
+                Err(err) => Reply::error(&err),
+            },
+            (Some("data"), Method::Put) => {
+                let Ok(tags) = header_tags(request) else {
+                    return Reply::bad_request("Invalid tags");
+                };
+                match read_body(request) {
+                    Ok(data) => done(self.fs.edit_file(id, Some(&data), tags)),
+                    Err(reply) => reply,
+                }
+            }
+            (Some("tags"), Method::Get) => match self.fs.get_tags(id) {
+                Ok(tags) => Reply::Json(200, json::tags_to_json(&tags)),
+                Err(err) => Reply::error(&err),
+            },
+            (Some("tags"), Method::Put) => {
+                let Some(tags) = read_json(request).as_ref().and_then(json::tags_from_json) else {
+                    return Reply::bad_request("Invalid tags");
+                };
+                done(self.fs.edit_file(id, None, Some(tags)))
+            }
+            (None | Some("data" | "tags"), _) => Reply::not_allowed(),
+            _ => Reply::Json(404, json::bad_request("No such endpoint")),
+        }
+    }
+
+    fn search(&self, query: &str) -> Reply<'_> {
+        let mut pattern = TagPredicate::And(Vec::new());
+        let mut options = SearchOptions::new();
+        for (key, value) in query.split('&').filter_map(|pair| pair.split_once('=')) {
+            let Some(value) = percent_decode(value) else {
+                return Reply::bad_request("Invalid query string");
+            };
+            match key {
+                "q" => match TagPredicate::parse(&value) {
+                    Ok(parsed) => pattern = parsed,
+                    Err(err) => return Reply::bad_request(&err.to_string()),
+                },
+                "offset" | "limit" => match value.parse() {
+                    Ok(num) if key == "offset" => options = options.offset(num),
+                    Ok(num) => options = options.limit(num),
+                    Err(_) => return Reply::bad_request("Invalid number"),
+                },
+                "after" => match json::parse_id(&value) {
+                    Some(id) => options = options.after(id),
+                    None => return Reply::bad_request("Invalid file ID"),
+                },
+                _ => (),
+            }
+        }
+
+        match self.fs.search_tags_with(pattern, &options) {
+            Ok(ids) => Reply::Json(200, json::ids_to_json(&ids)),
+            Err(err) => Reply::error(&err),
+        }
+    }
+
+    /// Add a file, with the ID the filesystem picks unless one is given
+    fn add(&self, request: &mut Request, id: Option<FileId>) -> Reply<'_> {
+        let Ok(tags) = header_tags(request) else {
+            return Reply::bad_request("Invalid tags");
+        };
+        let tags = tags.unwrap_or_default();
+
+        let added = if let Some(id) = id {
+            match read_body(request) {
+                Ok(data) => self.fs.add_file_with_id(id, &data, tags).map(|()| id),
+                Err(reply) => return reply,
+            }
+        } else {
+            // New files are streamed in, so they needn't fit in memory
+            let mut writer = match self.fs.create_file(tags) {
+                Ok(writer) => writer,
+                Err(err) => return Reply::error(&err),
+            };
+            if let Err(err) = io::copy(request.as_reader(), &mut writer) {
+                return Reply::bad_request(&err.to_string());
+            }
+            writer.commit()
+        };
+        match added {
+            Ok(id) => Reply::Json(201, json!({ "id": json::id_to_string(id) })),
+            Err(err) => Reply::error(&err),
+        }
+    }
+
+    fn info(&self, id: FileId) -> Reply<'_> {
+        let info = self.fs.get_tags(id).and_then(|tags| {
+            let meta = self.fs.get_metadata(id)?;
+            Ok((tags, meta))
+        });
+        match info {
+            Ok((tags, meta)) => {
+                let secs = |time: std::time::SystemTime| {
+                    time.duration_since(UNIX_EPOCH)
+                        .map_or(0, |time| time.as_secs())
+                };
+                Reply::Json(
+                    200,
+                    json!({
+                        "id": json::id_to_string(id),
+                        "tags": json::tags_to_json(&tags),
+                        "size": meta.size(),
+                        "created": secs(meta.created()),
+                        "modified": secs(meta.modified()),
+                        "hash": json::hash_to_string(meta.hash()),
+                    }),
+                )
+            }
+            Err(err) => Reply::error(&err),
+        }
+    }
+}
+
+fn content_type(value: &str) -> Header {
+    Header::from_bytes(&b"Content-Type"[..], value.as_bytes()).expect("Header should be valid")
+}
+
+fn read_body(request: &mut Request) -> Result<Vec<u8>, Reply<'static>> {
+    let mut body = Vec::new();
+    match request.as_reader().read_to_end(&mut body) {
+        Ok(_) => Ok(body),
+        Err(err) => Err(Reply::bad_request(&err.to_string())),
+    }
+}
+
+fn read_json(request: &mut Request) -> Option<Value> {
+    serde_json::from_slice(&read_body(request).ok()?).ok()
+}
+
+/// Get the tags in the [`TAGS_HEADER`] header, if there is one
+fn header_tags(request: &Request) -> Result<Option<Vec<Tag>>, ()> {
+    let Some(header) = request
+        .headers()
+        .iter()
+        .find(|header| header.field.equiv(TAGS_HEADER))
+    else {
+        return Ok(None);
+    };
+    serde_json::from_str(header.value.as_str())
+        .ok()
+        .as_ref()
+        .and_then(json::tags_from_json)
+        .map(Some)
+        .ok_or(())
+}
+
+/// Decode a percent-encoded query string value, where `+` is a space
+fn percent_decode(value: &str) -> Option<String> {
+    let mut out = Vec::with_capacity(value.len());
+    let mut bytes = value.bytes();
+    while let Some(b) = bytes.next() {
+        match b {
+            b'%' => {
+                let hex = [bytes.next()?, bytes.next()?];
+                out.push(u8::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?);
+            }
+            b'+' => out.push(b' '),
+            b => out.push(b),
+        }
+    }
+    String::from_utf8(out).ok()
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{FileSystemRead, FileSystemWrite, InMemoryFs, Tag};
+    use std::io::Write;
+    use std::net::TcpStream;
+    use std::sync::Arc;
+
+    /// Send a request, returning the status and body of the response
+    fn send(addr: SocketAddr, request: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        send_with(addr, request, "", body)
+    }
+
+    fn send_with(addr: SocketAddr, request: &str, headers: &str, body: &[u8]) -> (u16, Vec<u8>) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{request} HTTP/1.1\r\nHost: localhost\r\nConnection: close\r\n{headers}Content-Length: {}\r\n\r\n",
+            body.len()
+        )
+        .unwrap();
+        stream.write_all(body).unwrap();
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response).unwrap();
+        let split = response.windows(4).position(|w| w == b"\r\n\r\n").unwrap();
+        let head = String::from_utf8_lossy(&response[..split]).into_owned();
+        let status = head[9..12].parse().unwrap();
+        let mut body = response[split + 4..].to_vec();
+        if head
+            .to_ascii_lowercase()
+            .contains("transfer-encoding: chunked")
+        {
+            body = dechunk(&body);
+        }
+        (status, body)
+    }
+
+    fn dechunk(mut body: &[u8]) -> Vec<u8> {
+        let mut out = Vec::new();
+        loop {
+            let line = body.windows(2).position(|w| w == b"\r\n").unwrap();
+            let len =
+                usize::from_str_radix(std::str::from_utf8(&body[..line]).unwrap(), 16).unwrap();
+            if len == 0 {
+                return out;
+            }
+            out.extend_from_slice(&body[line + 2..line + 2 + len]);
+            body = &body[line + 4 + len..];
+        }
+    }
+
+    #[test]
+    fn test_server() {
+        let server = Arc::new(Server::bind(InMemoryFs::new(), "127.0.0.1:0").unwrap());
+        let addr = server.local_addr().unwrap();
+        // The thread is left running until the test exits
+        let handler = Arc::clone(&server);
+        std::thread::spawn(move || handler.run());
+        let id = server
+            .fs()
+            .add_file(
+                &[1, 2, 3],
+                [Tag::new("g", "a").with_value(5), Tag::new("g", "b")],
+            )
+            .unwrap();
+        let hex = json::id_to_string(id);
+
+        let (status, body) = send(addr, "GET /files?q=g%3Ab", &[]);
+        assert_eq!(status, 200);
+        assert_eq!(body, format!("[\"{hex}\"]").as_bytes());
+
+        let (status, body) = send(addr, &format!("GET /files/{hex}/data"), &[]);
+        assert_eq!((status, body), (200, vec![1, 2, 3]));
+
+        let (status, body) = send(addr, &format!("GET /files/{hex}/tags"), &[]);
+        assert_eq!(status, 200);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!([
+                { "group": "g", "name": "a", "value": { "int": 5 } },
+                { "group": "g", "name": "b" },
+            ])
+        );
+
+        let tags = r#"[{"name": "b"}]"#;
+        let (status, _) = send(addr, &format!("PUT /files/{hex}/tags"), tags.as_bytes());
+        assert_eq!(status, 204);
+        let (status, _) = send(addr, &format!("PUT /files/{hex}/data"), &[4, 5]);
+        assert_eq!(status, 204);
+        let info = server.fs().get_info(id).unwrap();
+        assert_eq!(info.data(), &[4, 5]);
+        assert_eq!(info.tags(), &[Tag::named("b")].into());
+
+        let headers = format!("{TAGS_HEADER}: [{{\"name\": \"c\"}}]\r\n");
+        let (status, body) = send_with(addr, "POST /files", &headers, &[6]);
+        assert_eq!(status, 201);
+        let new = serde_json::from_slice::<Value>(&body).unwrap();
+        let new = json::parse_id(new["id"].as_str().unwrap()).unwrap();
+        assert_eq!(server.fs().search_tags(Tag::named("c")).unwrap(), [new]);
+
+        let (status, _) = send(addr, &format!("DELETE /files/{hex}"), &[]);
+        assert_eq!(status, 204);
+        let (status, body) = send(addr, &format!("GET /files/{hex}"), &[]);
+        assert_eq!(status, 404);
+        assert_eq!(
+            serde_json::from_slice::<Value>(&body).unwrap(),
+            json!({ "error": "file_not_found", "id": hex })
+        );
+
+        let (status, _) = send(addr, "GET /files?q=(", &[]);
+        assert_eq!(status, 400);
+    }
+}
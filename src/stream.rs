@@ -0,0 +1,165 @@
+//! Support for streaming file data in and out of a filesystem
+
+use std::io::{self, Read, Write};
+use std::marker::PhantomData;
+use std::mem;
+
+use crate::{Error, FileId, FileSystemRead, FileSystemWrite, Tag};
+
+/// A handle which streams data into a new file. The file is committed, becoming visible to
+/// searches, when the handle is flushed or dropped. Prefer calling [`FileWriter::commit`], as
+/// errors while committing on drop are lost.
+pub trait FileWriter: Write {
+    /// The error type returned when committing fails
+    type Error;
+
+    /// Commit any remaining data to the file, returning its ID
+    fn commit(self) -> Result<FileId, Self::Error>;
+}
+
+/// Streaming data out of the files of a filesystem, for those which can read part of a file
+/// without loading all of it
+pub trait StreamRead: FileSystemRead {
+    /// The handle used to stream data out of a file
+    type Reader<'a>: Read
+    where
+        Self: 'a;
+
+    /// Open an existing file's data for streaming, rather than loading it all at once
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error>;
+}
+
+/// Streaming data into new files of a filesystem, for those which can take a file's data a piece
+/// at a time
+pub trait StreamWrite: FileSystemWrite {
+    /// The handle used to stream data into a new file
+    type Writer<'a>: FileWriter<Error = Self::Error>
+    where
+        Self: 'a;
+
+    /// Create a new file with the given tags, returning a handle to stream its data into. The
+    /// file isn't visible until the handle is committed.
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>;
+}
+
+/// How a [`BufferedWriter`] replaces the data of a file it already added
+type Replace<F> = fn(&F, FileId, &[u8]) -> Result<(), <F as FileSystemRead>::Error>;
+
+/// A [`FileWriter`] for filesystems which can't take a file's data a piece at a time. Data is
+/// buffered until the handle is flushed, which adds the file or replaces its data if it was
+/// already added.
+pub struct BufferedWriter<'a, F: FileSystemWrite + ?Sized> {
+    fs: &'a F,
+    /// Set once the file has been added
+    id: Option<FileId>,
+    data: Vec<u8>,
+    /// Only taken when the file is added
+    tags: Vec<Tag>,
+    /// Whether data was written since the file was last committed
+    dirty: bool,
+    replace: Replace<F>,
+}
+
+impl<'a, F: FileSystemWrite + ?Sized> BufferedWriter<'a, F> {
+    /// Create a handle which will add a file with the given tags to a filesystem
+    pub fn new<I>(fs: &'a F, tags: I) -> BufferedWriter<'a, F>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        BufferedWriter {
+            fs,
+            id: None,
+            data: Vec::new(),
+            tags: tags.into_iter().collect(),
+            dirty: false,
+            replace: |fs, id, data| fs.edit_file(id, Some(data), None::<[Tag; 0]>),
+        }
+    }
+
+    /// Set how the data of the file is replaced when it's flushed again, instead of editing it
+    pub(crate) fn with_replace(mut self, replace: Replace<F>) -> BufferedWriter<'a, F> {
+        self.replace = replace;
+        self
+    }
+
+    fn commit_data(&mut self) -> Result<FileId, F::Error> {
+        let id = if let Some(id) = self.id {
+            (self.replace)(self.fs, id, &self.data)?;
+            id
+        } else {
+            let id = self.fs.add_file(&self.data, mem::take(&mut self.tags))?;
+            self.id = Some(id);
+            id
+        };
+        self.dirty = false;
+        Ok(id)
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> Write for BufferedWriter<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.data.extend_from_slice(buf);
+        self.dirty = true;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.commit_data()
+            .map(|_| ())
+            .map_err(|err| io::Error::other(err.generic_kind().to_string()))
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> FileWriter for BufferedWriter<'_, F> {
+    type Error = F::Error;
+
+    fn commit(mut self) -> Result<FileId, Self::Error> {
+        self.commit_data()
+    }
+}
+
+impl<F: FileSystemWrite + ?Sized> Drop for BufferedWriter<'_, F> {
+    fn drop(&mut self) {
+        // Committing while unwinding could store a file which was only partly written
+        if (self.id.is_none() || self.dirty) && !std::thread::panicking() {
+            let _ = self.commit_data();
+        }
+    }
+}
+
+/// A [`FileWriter`] for filesystems wrapping another, which is a handle of the inner filesystem
+/// whose errors are converted when committing
+pub struct WrappedWriter<W, E> {
+    inner: W,
+    _error: PhantomData<fn() -> E>,
+}
+
+impl<W: FileWriter, E: From<W::Error>> WrappedWriter<W, E> {
+    /// Wrap a handle of the inner filesystem
+    pub fn new(inner: W) -> WrappedWriter<W, E> {
+        WrappedWriter {
+            inner,
+            _error: PhantomData,
+        }
+    }
+}
+
+impl<W: FileWriter, E> Write for WrappedWriter<W, E> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<W: FileWriter, E: From<W::Error>> FileWriter for WrappedWriter<W, E> {
+    type Error = E;
+
+    fn commit(self) -> Result<FileId, Self::Error> {
+        Ok(self.inner.commit()?)
+    }
+}
@@ -0,0 +1,731 @@
+//! Finding what changed in a filesystem since it was last synced, and copying just those changes
+//! into another
+//!
+//! A [`SyncCursor`] records what every file of a filesystem looked like as of a sync. [`diff`]
+//! compares the filesystem against one to find the files added, edited, and removed since, and
+//! [`apply`] makes the same changes to another filesystem, keeping file IDs. The cursor in the
+//! resulting [`Delta`] is kept for the next sync, so an app can work against a local filesystem
+//! and catch a remote one up whenever it's next reachable.
+//!
+//! Files are compared by a hash of their data and of their tags, so a file edited and then put
+//! back as it was doesn't count as changed.
+//!
+//! [`sync_both`] syncs two filesystems both ways. A file changed on both sides since the last sync
+//! is a [`Conflict`], settled by a [`ConflictResolver`] such as [`LastWriterWins`], [`TagUnion`],
+//! or any closure taking the conflict and returning a [`Resolution`]. Files added on both sides
+//! under the same ID aren't a conflict, as they're different files, so both are kept.
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use std::io;
+use std::time::SystemTime;
+
+use crate::error::is_not_found;
+use crate::metadata::hash_data;
+use crate::{
+    codec, FileId, FileInfo, FileSystemRead, FileSystemWrite, Metadata, Tag, TagPredicate,
+};
+
+/// Error while syncing one filesystem into another
+#[derive(Debug)]
+pub enum Error<S, D> {
+    /// The filesystem being synced from returned an error
+    Source(S),
+    /// The filesystem being synced to returned an error
+    Dest(D),
+}
+
+impl<S: fmt::Display, D: fmt::Display> fmt::Display for Error<S, D> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Source(err) => write!(f, "source filesystem: {err}"),
+            Error::Dest(err) => write!(f, "destination filesystem: {err}"),
+        }
+    }
+}
+
+impl<S: std::error::Error, D: std::error::Error> std::error::Error for Error<S, D> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Source(err) => std::error::Error::source(err),
+            Error::Dest(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+impl<S, D> Error<S, D> {
+    fn flip(self) -> Error<D, S> {
+        match self {
+            Error::Source(err) => Error::Dest(err),
+            Error::Dest(err) => Error::Source(err),
+        }
+    }
+}
+
+/// What a file looked like as of a sync
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+struct Fingerprint {
+    data: [u8; 32],
+    tags: [u8; 32],
+}
+
+/// What every file of a filesystem looked like as of a sync, to find what's changed since. A new
+/// cursor is from before anything was added, so every file counts as added since it.
+///
+/// Cursors can be saved with [`SyncCursor::to_bytes`] to carry on syncing after a restart.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SyncCursor {
+    files: BTreeMap<FileId, Fingerprint>,
+}
+
+impl SyncCursor {
+    /// Create a cursor from before anything was added
+    pub fn new() -> SyncCursor {
+        SyncCursor::default()
+    }
+
+    /// Encode the cursor, to be decoded again with [`SyncCursor::from_bytes`]
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut out = Vec::with_capacity(self.files.len() * 72);
+        for (&id, print) in &self.files {
+            out.extend(id.into_u64_unchecked().to_le_bytes());
+            out.extend(print.data);
+            out.extend(print.tags);
+        }
+        out
+    }
+
+    /// Decode a cursor encoded by [`SyncCursor::to_bytes`]
+    pub fn from_bytes(mut data: &[u8]) -> io::Result<SyncCursor> {
+        let mut files = BTreeMap::new();
+        while !data.is_empty() {
+            let id = codec::read_id(&mut data)?;
+            let mut print = Fingerprint {
+                data: [0; 32],
+                tags: [0; 32],
+            };
+            io::Read::read_exact(&mut data, &mut print.data)?;
+            io::Read::read_exact(&mut data, &mut print.tags)?;
+            files.insert(id, print);
+        }
+        Ok(SyncCursor { files })
+    }
+}
+
+/// A change made to a file since a sync
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Change {
+    /// The file was added
+    Added(FileId),
+    /// The file's data, tags, or both were changed
+    Edited {
+        /// The file changed
+        id: FileId,
+        /// Whether its data changed
+        data: bool,
+        /// Whether its tags changed
+        tags: bool,
+    },
+    /// The file was removed
+    Removed(FileId),
+}
+
+impl Change {
+    /// The file which changed
+    pub fn id(&self) -> FileId {
+        match *self {
+            Change::Added(id) | Change::Edited { id, .. } | Change::Removed(id) => id,
+        }
+    }
+}
+
+/// Every change made to a filesystem since a sync, along with the cursor for the next one
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Delta {
+    changes: Vec<Change>,
+    cursor: SyncCursor,
+}
+
+impl Delta {
+    /// The changes made, in order of the file they're to
+    pub fn changes(&self) -> &[Change] {
+        &self.changes
+    }
+
+    /// Whether nothing changed
+    pub fn is_empty(&self) -> bool {
+        self.changes.is_empty()
+    }
+
+    /// The cursor as of when the changes were found, to find what changes after them
+    pub fn cursor(&self) -> &SyncCursor {
+        &self.cursor
+    }
+
+    /// Take the cursor as of when the changes were found
+    pub fn into_cursor(self) -> SyncCursor {
+        self.cursor
+    }
+}
+
+/// Find what changed in a filesystem since a cursor. This reads the metadata and tags of every
+/// file, but not their data.
+pub fn diff<F>(fs: &F, since: &SyncCursor) -> Result<Delta, F::Error>
+where
+    F: FileSystemRead + ?Sized,
+{
+    let mut cursor = SyncCursor::new();
+    let mut changes = Vec::new();
+    for id in fs.search_tags(TagPredicate::And(Vec::new()))? {
+        let print = match fingerprint(fs, id) {
+            Ok(print) => print,
+            // Removed since it was found
+            Err(err) if is_not_found(&err) => continue,
+            Err(err) => return Err(err),
+        };
+        match since.files.get(&id) {
+            None => changes.push(Change::Added(id)),
+            Some(old) if *old != print => changes.push(Change::Edited {
+                id,
+                data: old.data != print.data,
+                tags: old.tags != print.tags,
+            }),
+            Some(_) => (),
+        }
+        cursor.files.insert(id, print);
+    }
+    changes.extend(
+        since
+            .files
+            .keys()
+            .filter(|id| !cursor.files.contains_key(id))
+            .map(|&id| Change::Removed(id)),
+    );
+    changes.sort_unstable_by_key(Change::id);
+    Ok(Delta { changes, cursor })
+}
+
+/// Make the changes found in one filesystem to another, keeping file IDs. Files are copied as
+/// they are in the source now, which may be newer than when the changes were found.
+///
+/// Files are changed one at a time, so if this fails part way through, changes already made are
+/// kept. Applying the same changes again finishes the job, as files already changed are copied
+/// over again and those already removed are skipped.
+pub fn apply<S, D>(src: &S, dest: &D, delta: &Delta) -> Result<(), Error<S::Error, D::Error>>
+where
+    S: FileSystemRead + ?Sized,
+    D: FileSystemWrite + ?Sized,
+{
+    for change in &delta.changes {
+        let id = change.id();
+        let exists = crate::exists(dest, id).map_err(Error::Dest)?;
+        let info = match change {
+            Change::Removed(_) => None,
+            _ => match src.get_info(id) {
+                Ok(info) => Some(info),
+                // Removed since the changes were found
+                Err(err) if is_not_found(&err) => None,
+                Err(err) => return Err(Error::Source(err)),
+            },
+        };
+
+        match (info, exists) {
+            (None, true) => dest.remove_file(id).map_err(Error::Dest)?,
+            (None, false) => (),
+            (Some(info), false) => dest
+                .add_file_with_id(id, &info.data, info.tags)
+                .map_err(Error::Dest)?,
+            (Some(info), true) => {
+                let (data, tags) = match *change {
+                    Change::Edited { data, tags, .. } => (data, tags),
+                    _ => (true, true),
+                };
+                dest.edit_file(id, data.then_some(&*info.data), tags.then_some(info.tags))
+                    .map_err(Error::Dest)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Copy every change made to one filesystem since a cursor into another, returning the cursor
+/// to sync from next time. See [`diff`] and [`apply`].
+pub fn sync<S, D>(
+    src: &S,
+    dest: &D,
+    since: &SyncCursor,
+) -> Result<SyncCursor, Error<S::Error, D::Error>>
+where
+    S: FileSystemRead + ?Sized,
+    D: FileSystemWrite + ?Sized,
+{
+    let delta = diff(src, since).map_err(Error::Source)?;
+    apply(src, dest, &delta)?;
+    Ok(delta.into_cursor())
+}
+
+/// One side's version of a file in a [`Conflict`]
+#[derive(Debug)]
+pub struct Version {
+    info: FileInfo,
+    metadata: Metadata,
+}
+
+impl Version {
+    fn read<F: FileSystemRead + ?Sized>(fs: &F, id: FileId) -> Result<Option<Version>, F::Error> {
+        let found = fs
+            .get_info(id)
+            .and_then(|info| Ok((info, fs.get_metadata(id)?)));
+        match found {
+            Ok((info, metadata)) => Ok(Some(Version { info, metadata })),
+            Err(err) if is_not_found(&err) => Ok(None),
+            Err(err) => Err(err),
+        }
+    }
+
+    /// The file's data and tags
+    pub fn info(&self) -> &FileInfo {
+        &self.info
+    }
+
+    /// The file's metadata
+    pub fn metadata(&self) -> &Metadata {
+        &self.metadata
+    }
+
+    fn modified(version: Option<&Version>) -> Option<SystemTime> {
+        version.map(|version| version.metadata.modified())
+    }
+}
+
+/// A file changed on both sides since the last sync, in different ways
+#[derive(Debug)]
+pub struct Conflict {
+    id: FileId,
+    local: Option<Version>,
+    remote: Option<Version>,
+}
+
+impl Conflict {
+    /// The file changed
+    pub fn id(&self) -> FileId {
+        self.id
+    }
+
+    /// The local version of the file, or `None` if it was removed locally
+    pub fn local(&self) -> Option<&Version> {
+        self.local.as_ref()
+    }
+
+    /// The remote version of the file, or `None` if it was removed remotely
+    pub fn remote(&self) -> Option<&Version> {
+        self.remote.as_ref()
+    }
+}
+
+/// How a [`Conflict`] is settled. Whatever's picked ends up on both sides.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Resolution {
+    /// Keep the local version, or removal
+    Local,
+    /// Keep the remote version, or removal
+    Remote,
+    /// Replace both versions with new data and tags
+    Merged {
+        /// The merged data
+        data: Box<[u8]>,
+        /// The merged tags
+        tags: BTreeSet<Tag>,
+    },
+    /// Remove the file from both sides
+    Removed,
+}
+
+/// A way to settle files changed on both sides of a sync. Any closure taking a [`Conflict`] and
+/// returning a [`Resolution`] is a resolver.
+pub trait ConflictResolver {
+    /// Decide how to settle a conflict
+    fn resolve(&self, conflict: &Conflict) -> Resolution;
+}
+
+impl<F: Fn(&Conflict) -> Resolution + ?Sized> ConflictResolver for F {
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        self(conflict)
+    }
+}
+
+/// Keep whichever version was modified last, preferring the local one if they were modified at
+/// the same time. An edit always beats a removal, as removals aren't timed.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct LastWriterWins;
+
+impl ConflictResolver for LastWriterWins {
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        if Version::modified(conflict.local()) >= Version::modified(conflict.remote()) {
+            Resolution::Local
+        } else {
+            Resolution::Remote
+        }
+    }
+}
+
+/// Keep the data of whichever version was modified last, as [`LastWriterWins`] does, but with the
+/// tags of both versions. An edit always beats a removal.
+#[derive(Debug, Copy, Clone, Default)]
+pub struct TagUnion;
+
+impl ConflictResolver for TagUnion {
+    fn resolve(&self, conflict: &Conflict) -> Resolution {
+        match (conflict.local(), conflict.remote()) {
+            (Some(local), Some(remote)) => {
+                let newer = match LastWriterWins.resolve(conflict) {
+                    Resolution::Remote => remote,
+                    _ => local,
+                };
+                Resolution::Merged {
+                    data: newer.info.data().into(),
+                    tags: local
+                        .info
+                        .tags()
+                        .union(remote.info.tags())
+                        .cloned()
+                        .collect(),
+                }
+            }
+            (_, None) => Resolution::Local,
+            (None, Some(_)) => Resolution::Remote,
+        }
+    }
+}
+
+/// Sync two filesystems both ways, so each gets the changes made to the other since a cursor,
+/// returning the cursor to sync from next time. The cursor should be one returned by this, as it
+/// expects both sides to have been the same as of it.
+///
+/// Files changed on only one side are copied to the other as by [`apply`]. Files changed on both
+/// sides, unless changed the same way, are settled by the resolver. Files added on both sides under
+/// the same ID are different files rather than a conflict, so both are kept: the local one keeps
+/// the ID, and the remote one is moved to a new ID on both sides. Errors from `local` are
+/// [`Error::Source`], and those from `remote` [`Error::Dest`].
+pub fn sync_both<L, R, C>(
+    local: &L,
+    remote: &R,
+    since: &SyncCursor,
+    resolver: &C,
+) -> Result<SyncCursor, Error<L::Error, R::Error>>
+where
+    L: FileSystemWrite + ?Sized,
+    R: FileSystemWrite + ?Sized,
+    C: ConflictResolver + ?Sized,
+{
+    let local_delta = diff(local, since).map_err(Error::Source)?;
+    let remote_delta = diff(remote, since).map_err(Error::Dest)?;
+    let remote_changed = remote_delta
+        .changes
+        .iter()
+        .map(|change| (change.id(), change))
+        .collect::<BTreeMap<_, _>>();
+
+    let (conflicts, to_remote) = local_delta
+        .changes
+        .iter()
+        .partition::<Vec<_>, _>(|change| remote_changed.contains_key(&change.id()));
+    // Maps each ID changed on both sides to whether both sides added it
+    let conflicts = conflicts
+        .into_iter()
+        .map(|change: &Change| {
+            let id = change.id();
+            let both_added = matches!(
+                (change, remote_changed[&id]),
+                (Change::Added(_), Change::Added(_))
+            );
+            (id, both_added)
+        })
+        .collect::<BTreeMap<_, _>>();
+    let to_local = remote_delta
+        .changes
+        .iter()
+        .filter(|change| !conflicts.contains_key(&change.id()))
+        .copied()
+        .collect();
+
+    let mut cursor = local_delta.cursor.clone();
+    apply(
+        local,
+        remote,
+        &Delta {
+            changes: to_remote.into_iter().copied().collect(),
+            cursor: SyncCursor::new(),
+        },
+    )?;
+    let to_local = Delta {
+        changes: to_local,
+        cursor: SyncCursor::new(),
+    };
+    apply(remote, local, &to_local).map_err(Error::flip)?;
+    for change in &to_local.changes {
+        let id = change.id();
+        match remote_delta.cursor.files.get(&id) {
+            Some(&print) => cursor.files.insert(id, print),
+            None => cursor.files.remove(&id),
+        };
+    }
+
+    for (id, both_added) in conflicts {
+        let print = local_delta.cursor.files.get(&id);
+        // Changed the same way on both sides
+        if print == remote_delta.cursor.files.get(&id) {
+            continue;
+        }
+        if both_added {
+            let moved = keep_both(local, remote, id)?;
+            let print = fingerprint(local, moved).map_err(Error::Source)?;
+            cursor.files.insert(moved, print);
+            continue;
+        }
+        let conflict = Conflict {
+            id,
+            local: Version::read(local, id).map_err(Error::Source)?,
+            remote: Version::read(remote, id).map_err(Error::Dest)?,
+        };
+        match resolver.resolve(&conflict) {
+            Resolution::Local => {
+                let target = conflict.local.map(|version| version.info);
+                put(remote, id, target.as_ref()).map_err(Error::Dest)?;
+            }
+            Resolution::Remote => {
+                let target = conflict.remote.map(|version| version.info);
+                put(local, id, target.as_ref()).map_err(Error::Source)?;
+            }
+            Resolution::Merged { data, tags } => {
+                let target = FileInfo::new(id, tags, data);
+                put(local, id, Some(&target)).map_err(Error::Source)?;
+                put(remote, id, Some(&target)).map_err(Error::Dest)?;
+            }
+            Resolution::Removed => {
+                put(local, id, None).map_err(Error::Source)?;
+                put(remote, id, None).map_err(Error::Dest)?;
+            }
+        }
+        match fingerprint(local, id) {
+            Ok(print) => cursor.files.insert(id, print),
+            Err(err) if is_not_found(&err) => cursor.files.remove(&id),
+            Err(err) => return Err(Error::Source(err)),
+        };
+    }
+
+    Ok(cursor)
+}
+
+/// Keep both copies of a file added on both sides under the same ID. The local copy keeps the ID
+/// on both sides, while the remote copy moves to the next ID free on both, which is returned.
+fn keep_both<L, R>(local: &L, remote: &R, id: FileId) -> Result<FileId, Error<L::Error, R::Error>>
+where
+    L: FileSystemWrite + ?Sized,
+    R: FileSystemWrite + ?Sized,
+{
+    let local_info = local.get_info(id).map_err(Error::Source)?;
+    let remote_info = remote.get_info(id).map_err(Error::Dest)?;
+
+    let last = local
+        .last_id()
+        .map_err(Error::Source)?
+        .max(remote.last_id().map_err(Error::Dest)?)
+        .map_or(255, FileId::into_u64_unchecked);
+    let mut moved = FileId::from_u64_unchecked(last.saturating_add(1));
+    while crate::exists(local, moved).map_err(Error::Source)?
+        || crate::exists(remote, moved).map_err(Error::Dest)?
+    {
+        moved = FileId::from_u64_unchecked(moved.into_u64_unchecked().saturating_add(1));
+    }
+
+    let tags = remote_info.tags().iter().cloned();
+    local
+        .add_file_with_id(moved, remote_info.data(), tags.clone())
+        .map_err(Error::Source)?;
+    remote
+        .add_file_with_id(moved, remote_info.data(), tags)
+        .map_err(Error::Dest)?;
+    put(remote, id, Some(&local_info)).map_err(Error::Dest)?;
+    Ok(moved)
+}
+
+/// Make a file in a filesystem match the given info, or remove it for `None`
+fn put<F>(fs: &F, id: FileId, target: Option<&FileInfo>) -> Result<(), F::Error>
+where
+    F: FileSystemWrite + ?Sized,
+{
+    match (target, crate::exists(fs, id)?) {
+        (None, true) => fs.remove_file(id),
+        (None, false) => Ok(()),
+        (Some(info), false) => fs.add_file_with_id(id, info.data(), info.tags().iter().cloned()),
+        (Some(info), true) => {
+            fs.edit_file(id, Some(info.data()), Some(info.tags().iter().cloned()))
+        }
+    }
+}
+
+fn fingerprint<F: FileSystemRead + ?Sized>(fs: &F, id: FileId) -> Result<Fingerprint, F::Error> {
+    let data = *fs.get_metadata(id)?.hash();
+    let mut tags = Vec::new();
+    for tag in fs.get_tags(id)? {
+        // Writing to memory only fails for a tag too long to have been stored
+        codec::write_tag(&mut tags, &tag).expect("Stored tags can be encoded");
+    }
+    Ok(Fingerprint {
+        data,
+        tags: hash_data(&tags),
+    })
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{InMemoryFs, Tag};
+
+    fn assert_same(src: &InMemoryFs, dest: &InMemoryFs) {
+        let all = TagPredicate::And(Vec::new());
+        let ids = src.search_tags(all.clone()).unwrap();
+        assert_eq!(ids, dest.search_tags(all).unwrap());
+        for id in ids {
+            assert_eq!(src.get_data(id).unwrap(), dest.get_data(id).unwrap());
+            assert_eq!(src.get_tags(id).unwrap(), dest.get_tags(id).unwrap());
+        }
+    }
+
+    #[test]
+    fn test_sync() {
+        let src = InMemoryFs::new();
+        let dest = InMemoryFs::new();
+        let a = src.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = src.add_file(&[2], [Tag::named("b")]).unwrap();
+        let c = src.add_file(&[3], []).unwrap();
+
+        let cursor = sync(&src, &dest, &SyncCursor::new()).unwrap();
+        assert_same(&src, &dest);
+        assert!(diff(&src, &cursor).unwrap().is_empty());
+
+        src.edit_file(a, Some(&[4]), None::<[Tag; 0]>).unwrap();
+        src.add_tags(b, [Tag::named("c")]).unwrap();
+        src.remove_file(c).unwrap();
+        let d = src.add_file(&[5], []).unwrap();
+        // Changes which are undone don't count
+        src.add_tags(a, [Tag::named("x")]).unwrap();
+        src.remove_tags(a, [Tag::named("x")]).unwrap();
+
+        let cursor = SyncCursor::from_bytes(&cursor.to_bytes()).unwrap();
+        let delta = diff(&src, &cursor).unwrap();
+        assert_eq!(
+            delta.changes(),
+            [
+                Change::Edited {
+                    id: a,
+                    data: true,
+                    tags: false
+                },
+                Change::Edited {
+                    id: b,
+                    data: false,
+                    tags: true
+                },
+                Change::Removed(c),
+                Change::Added(d),
+            ]
+        );
+
+        apply(&src, &dest, &delta).unwrap();
+        assert_same(&src, &dest);
+        // Applying again changes nothing
+        apply(&src, &dest, &delta).unwrap();
+        assert_same(&src, &dest);
+        assert!(diff(&src, delta.cursor()).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_both() {
+        let local = InMemoryFs::new();
+        let remote = InMemoryFs::new();
+        let a = local.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = local.add_file(&[2], []).unwrap();
+        let c = local.add_file(&[3], []).unwrap();
+        let cursor = sync_both(&local, &remote, &SyncCursor::new(), &LastWriterWins).unwrap();
+        assert_same(&local, &remote);
+
+        // Changes on one side are copied to the other
+        local.add_tags(a, [Tag::named("b")]).unwrap();
+        let d = remote.add_file(&[4], []).unwrap();
+        // Edits beat removals, and later edits beat earlier ones
+        local.remove_file(b).unwrap();
+        remote.edit_file(b, Some(&[5]), None::<[Tag; 0]>).unwrap();
+        local.edit_file(c, Some(&[6]), None::<[Tag; 0]>).unwrap();
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        remote.edit_file(c, Some(&[7]), None::<[Tag; 0]>).unwrap();
+
+        let cursor = sync_both(&local, &remote, &cursor, &LastWriterWins).unwrap();
+        assert_same(&local, &remote);
+        assert_eq!(
+            local.get_tags(a).unwrap(),
+            BTreeSet::from([Tag::named("a"), Tag::named("b")])
+        );
+        assert_eq!(&*local.get_data(b).unwrap(), [5]);
+        assert_eq!(&*local.get_data(c).unwrap(), [7]);
+        assert_eq!(&*local.get_data(d).unwrap(), [4]);
+        assert!(diff(&local, &cursor).unwrap().is_empty());
+        assert!(diff(&remote, &cursor).unwrap().is_empty());
+
+        local.add_tags(a, [Tag::named("c")]).unwrap();
+        remote.remove_tags(a, [Tag::named("b")]).unwrap();
+        let cursor = sync_both(&local, &remote, &cursor, &TagUnion).unwrap();
+        assert_same(&local, &remote);
+        assert_eq!(
+            local.get_tags(a).unwrap(),
+            BTreeSet::from([Tag::named("a"), Tag::named("b"), Tag::named("c")])
+        );
+
+        local.edit_file(d, Some(&[8]), None::<[Tag; 0]>).unwrap();
+        remote.edit_file(d, Some(&[9]), None::<[Tag; 0]>).unwrap();
+        let resolver = |conflict: &Conflict| {
+            assert_eq!(conflict.id(), d);
+            assert_eq!(conflict.local().unwrap().info().data(), [8]);
+            assert_eq!(conflict.remote().unwrap().info().data(), [9]);
+            Resolution::Removed
+        };
+        let cursor = sync_both(&local, &remote, &cursor, &resolver).unwrap();
+        assert_same(&local, &remote);
+        assert!(!crate::exists(&local, d).unwrap());
+        assert!(diff(&local, &cursor).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_sync_both_added() {
+        let local = InMemoryFs::new();
+        let remote = InMemoryFs::new();
+        let cursor = sync_both(&local, &remote, &SyncCursor::new(), &LastWriterWins).unwrap();
+
+        // Both sides pick the same ID for different files, and neither is lost
+        let a = local.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = remote.add_file(&[2], [Tag::named("b")]).unwrap();
+        assert_eq!(a, b);
+        let resolver = |_: &Conflict| -> Resolution { panic!("Added files don't conflict") };
+        let cursor = sync_both(&local, &remote, &cursor, &resolver).unwrap();
+        assert_same(&local, &remote);
+        assert_eq!(&*local.get_data(a).unwrap(), [1]);
+        let moved = local.ids_after(a).unwrap();
+        assert_eq!(moved.len(), 1);
+        assert_eq!(&*local.get_data(moved[0]).unwrap(), [2]);
+        assert_eq!(
+            local.get_tags(moved[0]).unwrap(),
+            BTreeSet::from([Tag::named("b")])
+        );
+        assert!(diff(&local, &cursor).unwrap().is_empty());
+        assert!(diff(&remote, &cursor).unwrap().is_empty());
+
+        // The same file added on both sides is still one file
+        let c = local.add_file(&[3], []).unwrap();
+        remote.add_file_with_id(c, &[3], []).unwrap();
+        let cursor = sync_both(&local, &remote, &cursor, &resolver).unwrap();
+        assert_same(&local, &remote);
+        assert_eq!(local.ids_after(moved[0]).unwrap(), [c]);
+        assert!(diff(&remote, &cursor).unwrap().is_empty());
+    }
+}
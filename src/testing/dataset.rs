@@ -0,0 +1,165 @@
+//! Synthetic stores, generated the same way every time
+
+use alloc::collections::BTreeSet;
+use alloc::format;
+use alloc::vec::Vec;
+use core::convert::TryFrom;
+
+use crate::{FileId, FileSystemWrite, Group, Tag};
+
+/// How many groups tags are spread over
+const GROUPS: usize = 8;
+
+/// A description of a synthetic store, which generates its files on demand.
+///
+/// Tags are picked from a fixed vocabulary of [`Dataset::cardinality`] tags, found with
+/// [`Dataset::tag`]. Tag popularity is skewed like real stores tend to be, so lower numbered
+/// tags are on many files and higher numbered ones on few, which makes searches for both common
+/// and rare tags easy to set up.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Dataset {
+    files: usize,
+    cardinality: usize,
+    tags_per_file: usize,
+    data_len: usize,
+    seed: u64,
+}
+
+impl Dataset {
+    /// Describe a store with some number of files, each with up to four of a hundred tags and
+    /// 64 bytes of data
+    pub fn new(files: usize) -> Dataset {
+        Dataset {
+            files,
+            cardinality: 100,
+            tags_per_file: 4,
+            data_len: 64,
+            seed: 0,
+        }
+    }
+
+    /// Set how many distinct tags files are tagged from
+    #[must_use]
+    pub fn cardinality(mut self, tags: usize) -> Self {
+        self.cardinality = tags.max(1);
+        self
+    }
+
+    /// Set how many tags each file is given. Files can end up with fewer, since the same tag can
+    /// be picked twice.
+    #[must_use]
+    pub fn tags_per_file(mut self, tags: usize) -> Self {
+        self.tags_per_file = tags;
+        self
+    }
+
+    /// Set how many bytes of data each file has
+    #[must_use]
+    pub fn data_len(mut self, len: usize) -> Self {
+        self.data_len = len;
+        self
+    }
+
+    /// Set the seed files are generated from, to get a different store with the same shape
+    #[must_use]
+    pub fn seed(mut self, seed: u64) -> Self {
+        self.seed = seed;
+        self
+    }
+
+    /// Get how many files this dataset has
+    pub fn len(&self) -> usize {
+        self.files
+    }
+
+    /// Check whether this dataset has no files
+    pub fn is_empty(&self) -> bool {
+        self.files == 0
+    }
+
+    /// Get a tag from the vocabulary, wrapping around past the cardinality. Tag 0 is the most
+    /// common.
+    pub fn tag(&self, n: usize) -> Tag {
+        let n = n % self.cardinality;
+        Tag::new(
+            Group::custom(format!("group{}", n % GROUPS)),
+            format!("tag{n}"),
+        )
+    }
+
+    /// Generate the data and tags of a single file
+    pub fn file(&self, n: usize) -> (Vec<u8>, BTreeSet<Tag>) {
+        let mut rng = SplitMix(self.seed ^ (n as u64).wrapping_mul(0x9E37_79B9_7F4A_7C15));
+
+        let data = (0..self.data_len)
+            .map(|_| rng.next().to_le_bytes()[0])
+            .collect();
+        let tags = (0..self.tags_per_file)
+            .map(|_| {
+                // The lower of two picks skews towards low numbers
+                let pick = rng.below(self.cardinality).min(rng.below(self.cardinality));
+                self.tag(pick)
+            })
+            .collect();
+        (data, tags)
+    }
+
+    /// Generate every file, in order
+    pub fn files(&self) -> impl Iterator<Item = (Vec<u8>, BTreeSet<Tag>)> + '_ {
+        (0..self.files).map(move |n| self.file(n))
+    }
+
+    /// Add every file to a filesystem, returning their IDs in order. Wrapping this in a
+    /// transaction is often much faster for stores which write to disk.
+    pub fn populate<F>(&self, fs: &F) -> Result<Vec<FileId>, F::Error>
+    where
+        F: FileSystemWrite + ?Sized,
+    {
+        self.files()
+            .map(|(data, tags)| fs.add_file(&data, tags))
+            .collect()
+    }
+}
+
+/// A small, fast generator, good enough for spreading tags and filling data
+struct SplitMix(u64);
+
+impl SplitMix {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    /// Get a number from zero up to a limit
+    fn below(&mut self, limit: usize) -> usize {
+        // The remainder is less than the limit, so always fits
+        usize::try_from(self.next() % limit as u64).unwrap_or(0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dataset() {
+        let set = Dataset::new(200).cardinality(20).data_len(8);
+
+        assert_eq!(set.files().count(), 200);
+        assert_eq!(set.file(7), set.clone().file(7));
+        assert_ne!(set.file(7), set.clone().seed(1).file(7));
+        assert_eq!(set.file(3).0.len(), 8);
+        assert!(set.files().all(|(_, tags)| (1..=4).contains(&tags.len())));
+
+        let count = |n| {
+            set.files()
+                .filter(|(_, tags)| tags.contains(&set.tag(n)))
+                .count()
+        };
+        assert!(count(0) > count(19));
+        assert_eq!(set.tag(21), set.tag(1));
+    }
+}
@@ -0,0 +1,712 @@
+//! A wrapper failing operations on demand
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::fmt;
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+use crate::error::ErrorKind;
+use crate::events::Event;
+use crate::metadata::Metadata;
+use crate::search::SearchOptions;
+#[cfg(feature = "imfs")]
+use crate::InMemoryFs;
+use crate::{
+    BatchResult, DedupePolicy, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite,
+    Group, InferredTags, Merge, ProvidedTags, SpecialFile, StreamRead, StreamWrite, Tag,
+    TagInferrer, TagPattern, TagProvider, UsageReport, WrappedWriter,
+};
+
+/// A kind of operation on a [`MockFs`], which failures are scripted for
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Op {
+    /// Adding a file, whether all at once or by streaming
+    Add,
+    /// Changing a file's data or tags, including renaming tags and reverting
+    Edit,
+    /// Removing a file
+    Remove,
+    /// Reading a single file, its metadata, versions, or a special file
+    Get,
+    /// Searching, counting tags, or listing what's stored
+    Search,
+    /// Changing a special file
+    SetSpecial,
+    /// Starting a transaction
+    Transaction,
+}
+
+/// An error a [`MockFs`] can be scripted to fail with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    /// An I/O error of some kind, as a disk-backed store would fail with
+    Io(io::ErrorKind),
+    /// A lock was poisoned by a panic in another thread
+    Poisoned,
+    /// The store is read-only
+    ReadOnly,
+    /// The change would go over a quota
+    QuotaExceeded,
+    /// The store is out of room
+    StorageFull,
+    /// The caller isn't allowed to do this
+    PermissionDenied,
+}
+
+impl Failure {
+    fn into_error<E>(self) -> Error<E> {
+        match self {
+            Failure::Io(kind) => Error::Io(io::Error::new(kind, "Scripted failure")),
+            failure => Error::Injected(failure),
+        }
+    }
+}
+
+/// Error for a [`MockFs`]
+#[derive(Debug)]
+pub enum Error<E> {
+    /// The inner filesystem returned an error
+    Fs(E),
+    /// A scripted I/O failure
+    Io(io::Error),
+    /// Any other scripted failure
+    Injected(Failure),
+}
+
+impl<E> From<E> for Error<E> {
+    fn from(err: E) -> Error<E> {
+        Error::Fs(err)
+    }
+}
+
+impl<E: crate::Error> crate::Error for Error<E> {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Fs(E::file_not_found(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Fs(E::already_exists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Fs(E::version_not_found(id, version))
+    }
+
+    fn storage_full(id: FileId) -> Self {
+        Error::Fs(E::storage_full(id))
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Fs(err) => err.generic_kind(),
+            Error::Io(err) => ErrorKind::Source(err),
+            Error::Injected(Failure::Io(_) | Failure::Poisoned) => ErrorKind::State,
+            Error::Injected(Failure::ReadOnly) => ErrorKind::ReadOnly,
+            Error::Injected(Failure::QuotaExceeded) => ErrorKind::QuotaExceeded,
+            Error::Injected(Failure::StorageFull) => ErrorKind::StorageFull,
+            Error::Injected(Failure::PermissionDenied) => ErrorKind::PermissionDenied,
+        }
+    }
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Fs(err) => err.fmt(f),
+            Error::Io(err) => write!(f, "scripted I/O failure: {err}"),
+            Error::Injected(failure) => write!(f, "scripted failure: {failure:?}"),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Fs(err) => std::error::Error::source(err),
+            Error::Io(err) => std::error::Error::source(err),
+            Error::Injected(_) => None,
+        }
+    }
+}
+
+/// A scripted failure, waiting for its call
+struct Rule {
+    op: Op,
+    /// Which call to fail, counting every call of the operation, or `None` to fail them all
+    call: Option<usize>,
+    failure: Failure,
+}
+
+#[derive(Default)]
+struct Script {
+    calls: BTreeMap<Op, usize>,
+    rules: Vec<Rule>,
+}
+
+/// A wrapper around another filesystem, by default an [`InMemoryFs`], which can be scripted to
+/// fail chosen operations. Calls which aren't failed are passed on unchanged, and failed calls
+/// never reach the inner filesystem, so it's left as it was.
+///
+/// Every call of each [`Op`] is counted, whether it fails or not, and failures can be scripted
+/// for a single upcoming call or for every call. Operations built from others, such as adding
+/// tags to every matching file, count as one call of their own kind.
+///
+/// ```
+/// # use tbf::testing::{Failure, MockFs, Op};
+/// # use tbf::{FileSystemRead, FileSystemWrite, Tag};
+/// let fs = MockFs::new();
+/// fs.fail_nth(Op::Add, 1, Failure::Io(std::io::ErrorKind::Other));
+/// fs.fail_always(Op::Search, Failure::Poisoned);
+///
+/// assert!(fs.add_file(&[], [Tag::named("a")]).is_ok());
+/// assert!(fs.add_file(&[], [Tag::named("b")]).is_err());
+/// assert!(fs.add_file(&[], [Tag::named("c")]).is_ok());
+/// assert!(fs.search_tags(Tag::named("a")).is_err());
+/// ```
+pub struct MockFs<F> {
+    inner: F,
+    script: Mutex<Script>,
+}
+
+#[cfg(feature = "imfs")]
+impl MockFs<InMemoryFs> {
+    /// Create a mock over a new, empty in-memory filesystem
+    pub fn new() -> MockFs<InMemoryFs> {
+        MockFs::wrap(InMemoryFs::new())
+    }
+}
+
+#[cfg(feature = "imfs")]
+impl Default for MockFs<InMemoryFs> {
+    fn default() -> Self {
+        MockFs::new()
+    }
+}
+
+impl<F> MockFs<F> {
+    /// Wrap a filesystem, with no failures scripted yet
+    pub fn wrap(inner: F) -> MockFs<F> {
+        MockFs {
+            inner,
+            script: Mutex::new(Script::default()),
+        }
+    }
+
+    /// Get the wrapped filesystem, to set up or check its state without counting calls or
+    /// failing any
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Fail a single upcoming call of an operation, counting from zero for the next call
+    pub fn fail_nth(&self, op: Op, n: usize, failure: Failure) {
+        let mut script = self.script();
+        let call = script.calls.get(&op).copied().unwrap_or(0) + n;
+        script.rules.push(Rule {
+            op,
+            call: Some(call),
+            failure,
+        });
+    }
+
+    /// Fail the next call of an operation
+    pub fn fail_next(&self, op: Op, failure: Failure) {
+        self.fail_nth(op, 0, failure);
+    }
+
+    /// Fail every call of an operation from now on, until cleared
+    pub fn fail_always(&self, op: Op, failure: Failure) {
+        self.script().rules.push(Rule {
+            op,
+            call: None,
+            failure,
+        });
+    }
+
+    /// Remove every scripted failure which hasn't happened yet. Call counts are kept.
+    pub fn clear_failures(&self) {
+        self.script().rules.clear();
+    }
+
+    /// Get how many times an operation has been called, including calls which failed
+    pub fn calls(&self, op: Op) -> usize {
+        self.script().calls.get(&op).copied().unwrap_or(0)
+    }
+
+    fn script(&self) -> MutexGuard<'_, Script> {
+        // The script is never left half-changed, so a panic while it was held doesn't matter
+        self.script.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Count a call of an operation, failing it if a rule says to
+    fn call<E>(&self, op: Op) -> Result<(), Error<E>> {
+        let mut script = self.script();
+        let count = script.calls.entry(op).or_insert(0);
+        let call = *count;
+        *count += 1;
+
+        let pos = script
+            .rules
+            .iter()
+            .position(|rule| rule.op == op && rule.call.is_none_or(|n| n == call));
+        match pos {
+            Some(pos) if script.rules[pos].call.is_some() => {
+                Err(script.rules.remove(pos).failure.into_error())
+            }
+            Some(pos) => Err(script.rules[pos].failure.into_error()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<F: FileSystemRead> FileSystemRead for MockFs<F> {
+    type Error = Error<F::Error>;
+    type SearchIter<'a, P>
+        = SearchIter<'a, F, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.call(Op::Search)?;
+        Ok(self.inner.search_tags(tags)?)
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.call(Op::Search)?;
+        Ok(self.inner.search_tags_with(tags, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        match self.call(Op::Search) {
+            Ok(()) => SearchIter {
+                inner: Some(self.inner.search_tags_iter(tags)),
+                failed: None,
+            },
+            Err(err) => SearchIter {
+                inner: None,
+                failed: Some(err),
+            },
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.call(Op::Search)?;
+        Ok(self.inner.tag_counts(pattern)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.call(Op::Search)?;
+        Ok(self.inner.count_tags(pattern)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.call(Op::Search)?;
+        Ok(self.inner.any_match(pattern)?)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn usage(&self) -> Result<UsageReport, Self::Error> {
+        self.call(Op::Search)?;
+        Ok(self.inner.usage()?)
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.list_versions(id)?)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.get_version(id, version)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for MockFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Add)?;
+        Ok(self.inner.add_file(data, tags)?)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Add)?;
+        Ok(self.inner.add_file_with_id(id, data, tags)?)
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Edit)?;
+        Ok(self.inner.edit_file(id, data, tags)?)
+    }
+
+    fn write_at(&self, id: FileId, offset: u64, data: &[u8]) -> Result<(), Self::Error> {
+        self.call(Op::Edit)?;
+        Ok(self.inner.write_at(id, offset, data)?)
+    }
+
+    fn truncate(&self, id: FileId, len: u64) -> Result<(), Self::Error> {
+        self.call(Op::Edit)?;
+        Ok(self.inner.truncate(id, len)?)
+    }
+
+    fn supports_partial_writes(&self) -> bool {
+        self.inner.supports_partial_writes()
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.call(Op::Remove)?;
+        Ok(self.inner.remove_file(id)?)
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        self.call(Op::Transaction)?;
+        // The inner filesystem needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done
+        let mut failed = None;
+        let out = self.inner.transaction(|_| match f(self) {
+            Ok(val) => Ok(val),
+            Err(Error::Fs(err)) => Err(err),
+            Err(err) => {
+                failed = Some(err);
+                Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+            }
+        });
+        match (out, failed) {
+            (Err(_), Some(err)) => Err(err),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Edit)?;
+        Ok(self.inner.add_tags(id, tags)?)
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Edit)?;
+        Ok(self.inner.remove_tags(id, tags)?)
+    }
+
+    fn add_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Edit)?;
+        Ok(self.inner.add_tags_matching(pattern, tags)?)
+    }
+
+    fn remove_tags_matching<P, I>(&self, pattern: P, tags: I) -> Result<usize, Self::Error>
+    where
+        P: TagPattern,
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Edit)?;
+        Ok(self.inner.remove_tags_matching(pattern, tags)?)
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        self.call(Op::Edit)?;
+        Ok(self.inner.rename_tag(old, new)?)
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        self.call(Op::Edit)?;
+        Ok(self.inner.rename_group(old, new)?)
+    }
+
+    fn dedupe(&self, policy: DedupePolicy) -> Result<Vec<Merge>, Self::Error> {
+        self.call(Op::Edit)?;
+        Ok(self.inner.dedupe(policy)?)
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        self.call(Op::Edit)?;
+        Ok(self.inner.revert(id, version)?)
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.call(Op::SetSpecial)?;
+        Ok(self.inner.set_special_data(file, data)?)
+    }
+}
+
+impl<F: ProvidedTags> ProvidedTags for MockFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl<F: InferredTags> InferredTags for MockFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl<F: StreamRead> StreamRead for MockFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.call(Op::Get)?;
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for MockFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.call(Op::Add)?;
+        Ok(WrappedWriter::new(self.inner.create_file(tags)?))
+    }
+}
+
+/// A lazy search over a [`MockFs`]. A scripted failure is returned by the first call to `next`,
+/// otherwise this is a search of the inner filesystem.
+pub struct SearchIter<'a, F: FileSystemRead + 'a, P: TagPattern + 'a> {
+    inner: Option<F::SearchIter<'a, P>>,
+    failed: Option<Error<F::Error>>,
+}
+
+impl<F: FileSystemRead, P: TagPattern> Iterator for SearchIter<'_, F, P> {
+    type Item = Result<FileId, Error<F::Error>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(err) = self.failed.take() {
+            return Some(Err(err));
+        }
+        self.inner.as_mut()?.next().map(|id| id.map_err(Error::Fs))
+    }
+}
+
+/// A handle streaming data into a new file of a [`MockFs`], which is a handle of the inner
+/// filesystem
+pub type Writer<'a, F> =
+    WrappedWriter<<F as StreamWrite>::Writer<'a>, Error<<F as FileSystemRead>::Error>>;
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{Error as _, FileWriter, TagPredicate};
+
+    #[test]
+    fn test_conformance() {
+        crate::testkit::assert_filesystem_conformance(MockFs::new);
+    }
+
+    #[test]
+    fn test_mock() {
+        let fs = MockFs::new();
+        let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+
+        fs.fail_nth(Op::Add, 1, Failure::Io(io::ErrorKind::Other));
+        fs.add_file(&[2], []).unwrap();
+        let err = fs.add_file(&[3], []).unwrap_err();
+        assert!(matches!(err.generic_kind(), ErrorKind::Source(_)));
+        fs.add_file(&[4], []).unwrap();
+        assert_eq!(fs.calls(Op::Add), 4);
+        assert_eq!(
+            fs.inner()
+                .search_tags(TagPredicate::And(Vec::new()))
+                .unwrap()
+                .len(),
+            3
+        );
+
+        fs.fail_always(Op::Search, Failure::Poisoned);
+        assert!(matches!(
+            fs.search_tags(Tag::named("a")),
+            Err(Error::Injected(Failure::Poisoned))
+        ));
+        let mut iter = fs.search_tags_iter(Tag::named("a"));
+        assert!(matches!(
+            iter.next(),
+            Some(Err(Error::Injected(Failure::Poisoned)))
+        ));
+        assert!(iter.next().is_none());
+        fs.clear_failures();
+        assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [id]);
+
+        // A failure inside a transaction undoes what came before it
+        fs.fail_next(Op::Remove, Failure::StorageFull);
+        let res = fs.transaction(|fs| {
+            fs.edit_file(id, Some(&[5]), None::<[Tag; 0]>)?;
+            fs.remove_file(id)
+        });
+        assert!(matches!(res, Err(Error::Injected(Failure::StorageFull))));
+        assert_eq!(fs.get_data(id).unwrap(), [1]);
+
+        fs.fail_next(Op::Get, Failure::PermissionDenied);
+        assert!(matches!(
+            fs.get_info(id).unwrap_err().generic_kind(),
+            ErrorKind::PermissionDenied
+        ));
+        assert_eq!(fs.get_info(id).unwrap().data(), &[1]);
+
+        let mut writer = fs.create_file([Tag::named("b")]).unwrap();
+        writer.write_all(&[6]).unwrap();
+        let new = writer.commit().unwrap();
+        assert_eq!(fs.into_inner().get_data(new).unwrap(), [6]);
+    }
+}
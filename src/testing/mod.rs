@@ -0,0 +1,18 @@
+//! Helpers for benchmarking filesystem implementations and testing code built on them
+//!
+//! A [`Dataset`] describes a store by how many files it holds and how their tags are spread,
+//! and generates the same files every time for the same settings, so results from different
+//! backends, or different versions of one backend, can be compared directly.
+//!
+//! A [`MockFs`] wraps another filesystem and fails chosen operations on demand, so applications
+//! can test how they handle errors without needing a real disk to go wrong.
+
+mod dataset;
+#[cfg(feature = "std")]
+mod mock;
+
+pub use dataset::Dataset;
+#[cfg(feature = "std")]
+pub use mock::{
+    Error as MockError, Failure, MockFs, Op, SearchIter as MockSearchIter, Writer as MockWriter,
+};
@@ -0,0 +1,444 @@
+//! A conformance suite for implementations of [`FileSystem`]
+//!
+//! [`assert_filesystem_conformance`] checks the behavior every implementation is expected to
+//! share, so third-party backends can be tested against the same rules as the builtin ones.
+//! Each check is also available on its own, for backends which deliberately differ somewhere.
+//!
+//! Every check panics when the filesystem breaks a rule or returns an error, like an assertion.
+// Panicking is what every function here is for
+#![allow(clippy::missing_panics_doc)]
+
+use alloc::collections::BTreeSet;
+use alloc::vec::Vec;
+use core::fmt::Debug;
+
+use crate::error::{Error, ErrorKind};
+#[cfg(feature = "std")]
+use crate::DedupePolicy;
+use crate::{FileId, FileSystem, Group, SearchHit, Tag, TagPredicate};
+
+/// Run every check in this module against a filesystem, panicking on the first which fails.
+/// Each check is given a fresh, empty filesystem from `make_fs`.
+pub fn assert_filesystem_conformance<F, M>(mut make_fs: M)
+where
+    F: FileSystem,
+    F::Error: Debug,
+    M: FnMut() -> F,
+{
+    check_add_get(&make_fs());
+    check_edit(&make_fs());
+    check_partial_writes(&make_fs());
+    check_huge_offsets(&make_fs());
+    check_remove(&make_fs());
+    check_ids(&make_fs());
+    check_add_with_id(&make_fs());
+    check_search(&make_fs());
+    check_tag_edits(&make_fs());
+    check_transaction(&make_fs());
+    #[cfg(feature = "std")]
+    check_duplicates(&make_fs());
+}
+
+/// Assert that a result failed because a file wasn't found
+fn assert_not_found<T: Debug, E: Error + Debug>(res: Result<T, E>, id: FileId) {
+    match res {
+        Err(err) => assert!(
+            matches!(err.generic_kind(), ErrorKind::FileNotFound(found) if found == id),
+            "Expected file {:?} to not be found, got {:?}",
+            id,
+            err
+        ),
+        Ok(val) => panic!("Expected file {:?} to not be found, got {:?}", id, val),
+    }
+}
+
+/// Assert that a result failed because an ID was in use
+fn assert_exists<T: Debug, E: Error + Debug>(res: Result<T, E>, id: FileId) {
+    match res {
+        Err(err) => assert!(
+            matches!(err.generic_kind(), ErrorKind::AlreadyExists(found) if found == id),
+            "Expected file {:?} to already exist, got {:?}",
+            id,
+            err
+        ),
+        Ok(val) => panic!("Expected file {:?} to already exist, got {:?}", id, val),
+    }
+}
+
+fn tags<const N: usize>(tags: [Tag; N]) -> BTreeSet<Tag> {
+    BTreeSet::from(tags)
+}
+
+/// Check that added files can be read back exactly, including empty ones and parts of them
+pub fn check_add_get<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let a = Tag::named("a");
+    let b = Tag::new(Group::custom("g"), "b").with_value(3);
+
+    let id = fs.add_file(&[1, 2, 3], [a.clone(), b.clone()]).unwrap();
+    let info = fs.get_info(id).unwrap();
+    assert_eq!(info.id(), id);
+    assert_eq!(info.data(), &[1, 2, 3]);
+    assert_eq!(info.tags(), &tags([a.clone(), b.clone()]));
+    assert_eq!(fs.get_data(id).unwrap(), [1, 2, 3]);
+    assert_eq!(fs.get_tags(id).unwrap(), tags([a, b]));
+    assert_eq!(fs.read_range(id, 1..2).unwrap(), [2]);
+    assert_eq!(fs.read_range(id, 1..10).unwrap(), [2, 3]);
+    assert!(fs.read_range(id, 1..1).unwrap().is_empty());
+    assert!(fs.read_range(id, 5..u64::MAX).unwrap().is_empty());
+    #[cfg(feature = "std")]
+    assert_eq!(fs.get_metadata(id).unwrap().size(), 3);
+
+    let empty = fs.add_file(&[], []).unwrap();
+    assert_ne!(empty, id, "Files were given the same ID");
+    assert!(fs.get_data(empty).unwrap().is_empty());
+    assert!(fs.get_tags(empty).unwrap().is_empty());
+
+    let many = fs.get_info_many(&[empty, id, empty]).unwrap();
+    let many = many
+        .into_iter()
+        .map(|info| info.unwrap().id())
+        .collect::<Vec<_>>();
+    assert_eq!(many, [empty, id, empty]);
+    let many = fs.get_tags_many(&[id, empty]).unwrap();
+    assert_eq!(many.len(), 2);
+    assert_eq!(many[0].as_ref().unwrap(), info.tags());
+    assert!(many[1].as_ref().unwrap().is_empty());
+}
+
+/// Check that edits change only what they're given
+pub fn check_edit<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+
+    fs.edit_file(id, Some(&[2, 3]), None::<[Tag; 0]>).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [2, 3]);
+    assert_eq!(fs.get_tags(id).unwrap(), tags([Tag::named("a")]));
+
+    fs.edit_file(id, None, Some([Tag::named("b")])).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [2, 3]);
+    assert_eq!(fs.get_tags(id).unwrap(), tags([Tag::named("b")]));
+
+    fs.edit_file(id, Some(&[]), Some([])).unwrap();
+    assert!(fs.get_data(id).unwrap().is_empty());
+    assert!(fs.get_tags(id).unwrap().is_empty());
+}
+
+/// Check that [`write_at`](crate::FileSystemWrite::write_at) and
+/// [`truncate`](crate::FileSystemWrite::truncate) change only the bytes they cover, filling any
+/// gap with zeroes
+pub fn check_partial_writes<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let id = fs.add_file(&[1, 2, 3, 4], [Tag::named("a")]).unwrap();
+
+    fs.write_at(id, 1, &[5, 6]).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [1, 5, 6, 4]);
+    fs.write_at(id, 3, &[7, 8]).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [1, 5, 6, 7, 8]);
+    fs.write_at(id, 7, &[9]).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [1, 5, 6, 7, 8, 0, 0, 9]);
+    fs.write_at(id, 20, &[]).unwrap();
+    assert_eq!(fs.get_data(id).unwrap().len(), 8);
+
+    fs.truncate(id, 2).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [1, 5]);
+    fs.truncate(id, 4).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [1, 5, 0, 0]);
+    fs.truncate(id, 0).unwrap();
+    assert!(fs.get_data(id).unwrap().is_empty());
+    assert_eq!(fs.get_tags(id).unwrap(), tags([Tag::named("a")]));
+
+    fs.remove_file(id).unwrap();
+    assert_not_found(fs.write_at(id, 0, &[1]), id);
+    assert_not_found(fs.write_at(id, 0, &[]), id);
+    assert_not_found(fs.truncate(id, 0), id);
+}
+
+/// Check that writing or truncating past what could ever be stored fails, rather than panicking
+/// or aborting, and leaves the file as it was
+pub fn check_huge_offsets<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let id = fs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+
+    assert!(fs.write_at(id, u64::MAX, &[4]).is_err());
+    assert!(fs.write_at(id, u64::MAX - 1, &[4, 5]).is_err());
+    assert!(fs.truncate(id, u64::MAX).is_err());
+    assert_eq!(fs.get_data(id).unwrap(), [1, 2, 3]);
+    assert_eq!(fs.get_tags(id).unwrap(), tags([Tag::named("a")]));
+}
+
+/// Check that removed files are gone, and that using them fails with
+/// [`ErrorKind::FileNotFound`]
+pub fn check_remove<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let kept = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+    let removed = fs.add_file(&[2], [Tag::named("a")]).unwrap();
+
+    fs.remove_file(removed).unwrap();
+    assert_not_found(fs.get_info(removed), removed);
+    assert_not_found(fs.get_data(removed), removed);
+    assert_not_found(fs.get_tags(removed), removed);
+    assert_not_found(fs.read_range(removed, 0..1), removed);
+    let mut many = fs.get_info_many(&[removed, kept]).unwrap().into_iter();
+    assert_not_found(many.next().unwrap(), removed);
+    assert_eq!(many.next().unwrap().unwrap().data(), &[1]);
+    let mut many = fs.get_tags_many(&[kept, removed]).unwrap().into_iter();
+    assert_eq!(many.next().unwrap().unwrap(), tags([Tag::named("a")]));
+    assert_not_found(many.next().unwrap(), removed);
+    assert_not_found(fs.remove_file(removed), removed);
+    assert_not_found(fs.edit_file(removed, Some(&[3]), None::<[Tag; 0]>), removed);
+    assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [kept]);
+    assert_eq!(fs.get_data(kept).unwrap(), [1]);
+}
+
+/// Check that IDs are unique, outside the reserved range, and never change for a file
+pub fn check_ids<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let ids = (0..8u8)
+        .map(|n| fs.add_file(&[n], []).unwrap())
+        .collect::<Vec<_>>();
+    assert_eq!(
+        ids.iter().collect::<BTreeSet<_>>().len(),
+        ids.len(),
+        "Files were given the same ID"
+    );
+    assert!(
+        ids.iter().all(|id| id.is_file()),
+        "Files were given reserved IDs"
+    );
+
+    fs.remove_file(ids[2]).unwrap();
+    fs.edit_file(ids[3], Some(&[9]), Some([Tag::named("edited")]))
+        .unwrap();
+    fs.add_file(&[10], []).unwrap();
+    for (n, &id) in (0..8u8).zip(&ids).filter(|&(n, _)| n != 2 && n != 3) {
+        assert_eq!(fs.get_data(id).unwrap(), [n], "File {id:?} moved");
+    }
+    assert_eq!(fs.get_data(ids[3]).unwrap(), [9]);
+
+    let after = fs.ids_after(ids[4]).unwrap();
+    assert!(after.windows(2).all(|pair| pair[0] < pair[1]));
+    assert!(after.iter().all(|&id| id > ids[4]));
+    assert!(ids[5..].iter().all(|id| after.contains(id)));
+    assert!(fs.last_id().unwrap().is_some());
+}
+
+/// Check that files can be added with chosen IDs, except ones in use or reserved, which fail
+/// with [`ErrorKind::AlreadyExists`]
+pub fn check_add_with_id<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let id = FileId::from_u64_unchecked(1000);
+    fs.add_file_with_id(id, &[1], [Tag::named("a")]).unwrap();
+    assert_eq!(fs.get_data(id).unwrap(), [1]);
+    assert_exists(fs.add_file_with_id(id, &[2], []), id);
+    assert_eq!(fs.get_data(id).unwrap(), [1]);
+
+    let reserved = FileId::from_u64_unchecked(1);
+    assert_exists(fs.add_file_with_id(reserved, &[], []), reserved);
+
+    // Later files never clash with the chosen one
+    let next = fs.add_file(&[2], []).unwrap();
+    assert_ne!(next, id);
+    assert_eq!(fs.get_data(id).unwrap(), [1]);
+}
+
+/// Check that searches find exactly the matching files, in ascending ID order, whether run all
+/// at once, lazily, or with details of what matched, and that they're counted correctly
+pub fn check_search<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let a = || Tag::named("a");
+    let b = || Tag::new(Group::custom("g"), "b");
+    let rating = |val: i64| Tag::named("rating").with_value(val);
+
+    let ab = fs.add_file(&[], [a(), b(), rating(1)]).unwrap();
+    let only_a = fs.add_file(&[], [a(), rating(5)]).unwrap();
+    let only_b = fs.add_file(&[], [b()]).unwrap();
+    let none = fs.add_file(&[], []).unwrap();
+    let sorted = |mut ids: Vec<FileId>| {
+        ids.sort();
+        ids
+    };
+
+    let searches = [
+        (TagPredicate::tag(a()), sorted(alloc::vec![ab, only_a])),
+        (TagPredicate::and([a(), b()]), sorted(alloc::vec![ab])),
+        (
+            TagPredicate::or([TagPredicate::tag(b()), TagPredicate::not(a())]),
+            sorted(alloc::vec![ab, only_b, none]),
+        ),
+        (
+            TagPredicate::group(Group::custom("g")),
+            sorted(alloc::vec![ab, only_b]),
+        ),
+        (
+            TagPredicate::value_gt(Group::Default, "rating", 2),
+            sorted(alloc::vec![only_a]),
+        ),
+        (
+            TagPredicate::And(Vec::new()),
+            sorted(alloc::vec![ab, only_a, only_b, none]),
+        ),
+        (TagPredicate::Or(Vec::new()), Vec::new()),
+    ];
+    for (pred, expected) in searches {
+        assert_eq!(fs.search_tags(pred.clone()).unwrap(), expected, "{pred:?}");
+        let lazy = fs
+            .search_tags_iter(pred.clone())
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        assert_eq!(lazy, expected, "Lazy {pred:?}");
+        assert_eq!(
+            fs.count_tags(pred.clone()).unwrap(),
+            expected.len() as u64,
+            "Count {pred:?}"
+        );
+        assert_eq!(
+            fs.any_match(pred.clone()).unwrap(),
+            !expected.is_empty(),
+            "Any {pred:?}"
+        );
+        let detailed = fs.search_detailed(pred.clone()).unwrap();
+        assert_eq!(
+            detailed.iter().map(SearchHit::id).collect::<Vec<_>>(),
+            expected,
+            "Detailed {pred:?}"
+        );
+        assert!(
+            detailed.iter().all(|hit| hit.is_matched(&[])),
+            "Detailed {:?}",
+            pred
+        );
+    }
+
+    assert_eq!(
+        fs.search_tags(a()).unwrap(),
+        sorted(alloc::vec![ab, only_a])
+    );
+    assert_eq!(fs.search_tags([a(), b()]).unwrap(), sorted(alloc::vec![ab]));
+    let counts = fs.tag_counts(a()).unwrap();
+    assert_eq!(counts.get(&a()), Some(&2));
+    assert_eq!(counts.get(&b()), Some(&1));
+}
+
+/// Check that tags can be added to and removed from files
+pub fn check_tag_edits<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let id = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+
+    fs.add_tags(id, [Tag::named("b"), Tag::named("a")]).unwrap();
+    assert_eq!(
+        fs.get_tags(id).unwrap(),
+        tags([Tag::named("a"), Tag::named("b")])
+    );
+    fs.remove_tags(id, [Tag::named("a"), Tag::named("c")])
+        .unwrap();
+    assert_eq!(fs.get_tags(id).unwrap(), tags([Tag::named("b")]));
+    assert_eq!(fs.search_tags(Tag::named("b")).unwrap(), [id]);
+    assert!(fs.search_tags(Tag::named("a")).unwrap().is_empty());
+    assert_eq!(fs.get_data(id).unwrap(), [1]);
+}
+
+/// Check that failed transactions undo every change made in them, and successful ones keep them
+pub fn check_transaction<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let edited = fs.add_file(&[0], [Tag::named("a")]).unwrap();
+    let removed = fs.add_file(&[1], [Tag::named("b")]).unwrap();
+
+    let res = fs.transaction(|fs| {
+        fs.add_file(&[2], [Tag::named("a")])?;
+        fs.edit_file(edited, Some(&[3]), Some([Tag::named("c")]))?;
+        fs.remove_file(removed)?;
+        fs.remove_file(removed)
+    });
+    assert_not_found(res, removed);
+    assert_eq!(fs.search_tags(Tag::named("a")).unwrap(), [edited]);
+    assert_eq!(fs.get_data(edited).unwrap(), [0]);
+    assert_eq!(fs.get_data(removed).unwrap(), [1]);
+    assert!(fs.search_tags(Tag::named("c")).unwrap().is_empty());
+
+    let added = fs
+        .transaction(|fs| {
+            fs.remove_file(removed)?;
+            fs.add_file(&[4], [Tag::named("b")])
+        })
+        .unwrap();
+    assert_eq!(fs.search_tags(Tag::named("b")).unwrap(), [added]);
+    assert_not_found(fs.get_info(removed), removed);
+}
+
+/// Check that files with the same data are found, and merged into one with all of their tags
+#[cfg(feature = "std")]
+pub fn check_duplicates<F>(fs: &F)
+where
+    F: FileSystem,
+    F::Error: Debug,
+{
+    let first = fs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+    let unique = fs.add_file(&[3], [Tag::named("a")]).unwrap();
+    let second = fs.add_file(&[1, 2], [Tag::named("b")]).unwrap();
+    let empty = fs.add_file(&[], []).unwrap();
+    let third = fs
+        .add_file(&[1, 2], [Tag::named("a"), Tag::named("c")])
+        .unwrap();
+    let tagged_empty = fs.add_file(&[], [Tag::named("d")]).unwrap();
+
+    assert_eq!(
+        fs.find_duplicates().unwrap(),
+        [vec![first, second, third], vec![empty, tagged_empty]]
+    );
+    let hash = *fs.get_metadata(second).unwrap().hash();
+    assert_eq!(fs.find_by_hash(&hash).unwrap(), Some(first));
+    assert_eq!(fs.find_by_hash(&[0; 32]).unwrap(), None);
+
+    let merges = fs.dedupe(DedupePolicy::KeepMostTags).unwrap();
+    assert_eq!(merges.len(), 2);
+    assert_eq!(merges[0].kept(), third);
+    assert_eq!(merges[0].removed(), [first, second]);
+    assert_eq!(merges[1].kept(), tagged_empty);
+    assert_eq!(merges[1].removed(), [empty]);
+    assert_eq!(
+        fs.get_tags(third).unwrap(),
+        tags([Tag::named("a"), Tag::named("b"), Tag::named("c")])
+    );
+    assert_eq!(fs.get_data(third).unwrap(), [1, 2]);
+    assert_not_found(fs.get_info(first), first);
+    assert_not_found(fs.get_info(empty), empty);
+    assert_eq!(
+        fs.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+        [unique, third, tagged_empty]
+    );
+
+    assert!(fs.find_duplicates().unwrap().is_empty());
+    assert_eq!(fs.find_by_hash(&hash).unwrap(), Some(third));
+    assert!(fs.dedupe(DedupePolicy::default()).unwrap().is_empty());
+}
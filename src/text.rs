@@ -0,0 +1,664 @@
+//! Full-text search of file contents, through an index kept up to date by a wrapper
+
+use alloc::collections::{BTreeMap, BTreeSet};
+use core::ops::Range;
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError, RwLock};
+
+use crate::extract::{extract_all, Extraction, Extractor, Extractors, PlainTextExtractor};
+use crate::metadata::Metadata;
+use crate::{
+    BatchResult, FileId, FileInfo, FileSystem, FileSystemRead, FileSystemWrite, FileWriter, Group,
+    InferredTags, ProvidedTags, SearchOptions, SpecialFile, StreamRead, StreamWrite, Tag,
+    TagInferrer, TagPattern, TagPredicate, TagProvider,
+};
+
+/// Split text into the terms it's indexed by, which are its runs of letters and digits in
+/// lowercase
+fn terms(text: &str) -> BTreeSet<String> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|term| !term.is_empty())
+        .map(str::to_lowercase)
+        .collect()
+}
+
+/// An inverted index from each term to the files containing it
+#[derive(Default)]
+struct TextIndex {
+    terms: BTreeMap<String, BTreeSet<FileId>>,
+    files: BTreeMap<FileId, BTreeSet<String>>,
+    /// Set when files may have changed in ways the index doesn't know, so it has to be built
+    /// again before it's next used
+    stale: bool,
+}
+
+impl TextIndex {
+    fn insert(&mut self, id: FileId, text: &str) {
+        self.remove(id);
+        let terms = terms(text);
+        for term in &terms {
+            self.terms.entry(term.clone()).or_default().insert(id);
+        }
+        self.files.insert(id, terms);
+    }
+
+    fn remove(&mut self, id: FileId) {
+        for term in self.files.remove(&id).unwrap_or_default() {
+            if let Some(ids) = self.terms.get_mut(&term) {
+                ids.remove(&id);
+                if ids.is_empty() {
+                    self.terms.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Find the files containing every term of a query
+    fn find(&self, query: &str) -> BTreeSet<FileId> {
+        let mut found: Option<BTreeSet<FileId>> = None;
+        for term in terms(query) {
+            let Some(ids) = self.terms.get(&term) else {
+                return BTreeSet::new();
+            };
+            found = Some(match found {
+                Some(found) => found.intersection(ids).copied().collect(),
+                None => ids.clone(),
+            });
+        }
+        found.unwrap_or_default()
+    }
+}
+
+/// A wrapper around another filesystem which indexes the text in its files, so they can be
+/// searched for by their contents as well as their tags.
+///
+/// The text of a file is found by the registered [`Extractor`]s, which start with just a
+/// [`PlainTextExtractor`], so files whose data is UTF-8 are found by their words. Extractors can
+/// also derive tags, which are stored with a file when its data is added or edited through this
+/// wrapper, replacing any extracted from its old data. Files already stored when an extractor is
+/// registered are indexed again, but don't get its tags.
+///
+/// The index is held in memory, built when the wrapper is created and kept up to date as files
+/// are changed through it. Text is split into runs of letters and digits, and matched without
+/// regard to case. If the inner filesystem is changed directly, [`TextIndexFs::rebuild`] must be
+/// called. A failed transaction, or data stored by a writer before it's committed, causes the
+/// whole index to be built again when next searched.
+pub struct TextIndexFs<F> {
+    inner: F,
+    index: Mutex<TextIndex>,
+    extractors: RwLock<Extractors>,
+}
+
+impl<F: FileSystem> TextIndexFs<F> {
+    /// Wrap a filesystem, indexing every file already in it
+    pub fn new(inner: F) -> Result<TextIndexFs<F>, F::Error> {
+        let fs = TextIndexFs {
+            inner,
+            index: Mutex::new(TextIndex::default()),
+            extractors: RwLock::new(vec![Box::new(PlainTextExtractor)]),
+        };
+        fs.rebuild()?;
+        Ok(fs)
+    }
+
+    /// Get the wrapped filesystem
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+
+    /// Unwrap the inner filesystem, dropping the index
+    pub fn into_inner(self) -> F {
+        self.inner
+    }
+
+    /// Build the index again from every file of the inner filesystem
+    pub fn rebuild(&self) -> Result<(), F::Error> {
+        let mut index = self.lock();
+        self.build(&mut index)
+    }
+
+    /// Register an extractor to run over the data of files as it's added or edited. Every file
+    /// is indexed again to include the text it finds.
+    pub fn register_extractor<E>(&self, extractor: E) -> Result<(), F::Error>
+    where
+        E: Extractor + 'static,
+    {
+        self.extractors
+            .write()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(Box::new(extractor));
+        self.rebuild()
+    }
+
+    /// Find the files containing every term of a query, in order of ID. A query with no terms
+    /// finds nothing.
+    pub fn search_content(&self, query: &str) -> Result<Vec<FileId>, F::Error> {
+        Ok(self.index()?.find(query).into_iter().collect())
+    }
+
+    /// Find the files containing every term of a query which also match a tag pattern, in order
+    /// of ID
+    pub fn search_content_with<P>(&self, query: &str, tags: P) -> Result<Vec<FileId>, F::Error>
+    where
+        P: TagPattern,
+    {
+        let found = self.index()?.find(query);
+        if found.is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut ids = self.inner.search_tags(tags)?;
+        ids.retain(|id| found.contains(id));
+        Ok(ids)
+    }
+
+    fn lock(&self) -> MutexGuard<'_, TextIndex> {
+        self.index.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn extract(&self, data: &[u8]) -> Extraction {
+        extract_all(
+            &self
+                .extractors
+                .read()
+                .unwrap_or_else(PoisonError::into_inner),
+            data,
+        )
+    }
+
+    fn build(&self, index: &mut TextIndex) -> Result<(), F::Error> {
+        let mut built = TextIndex::default();
+        for id in self.inner.search_tags(TagPredicate::And(Vec::new()))? {
+            built.insert(id, self.extract(&self.inner.get_data(id)?).text());
+        }
+        *index = built;
+        Ok(())
+    }
+
+    /// Get the index, building it again first if it's stale
+    fn index(&self) -> Result<MutexGuard<'_, TextIndex>, F::Error> {
+        let mut index = self.lock();
+        if index.stale {
+            self.build(&mut index)?;
+        }
+        Ok(index)
+    }
+
+    /// The tags extracted from the data a file has now
+    fn extracted_tags(&self, id: FileId) -> Result<Vec<Tag>, F::Error> {
+        Ok(self.extract(&self.inner.get_data(id)?).tags().to_vec())
+    }
+
+    /// Replace the tags extracted from a file's old data with those from its current data, and
+    /// index it again
+    fn update(&self, id: FileId, old: &[Tag]) -> Result<(), F::Error> {
+        let new = self.extract(&self.inner.get_data(id)?);
+        let stale = old
+            .iter()
+            .filter(|tag| !new.tags().contains(tag))
+            .cloned()
+            .collect::<Vec<_>>();
+        if !stale.is_empty() {
+            self.inner.remove_tags(id, stale)?;
+        }
+        if !new.tags().is_empty() {
+            self.inner.add_tags(id, new.tags().iter().cloned())?;
+        }
+        self.lock().insert(id, new.text());
+        Ok(())
+    }
+
+    fn mark_stale(&self) {
+        self.lock().stale = true;
+    }
+}
+
+impl<F: FileSystem> FileSystemRead for TextIndexFs<F> {
+    type Error = F::Error;
+    type SearchIter<'a, P>
+        = F::SearchIter<'a, P>
+    where
+        Self: 'a,
+        P: TagPattern + 'a;
+
+    fn search_tags<P>(&self, tags: P) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.search_tags(tags)
+    }
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.search_tags_with(tags, options)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        self.inner.search_tags_iter(tags)
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        self.inner.get_info(id)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.get_tags(id)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.get_stored_tags(id)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        self.inner.get_info_many(ids)
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        self.inner.get_tags_many(ids)
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        self.inner.get_data(id)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        self.inner.read_range(id, range)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        self.inner.get_metadata(id)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        self.inner.last_id()
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        self.inner.ids_after(after)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.tag_counts(pattern)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.count_tags(pattern)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        self.inner.any_match(pattern)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        self.inner.find_duplicates()
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        self.inner.find_by_hash(hash)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        self.inner.list_groups()
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        self.inner.list_tags(group)
+    }
+
+    fn usage(&self) -> Result<crate::UsageReport, Self::Error> {
+        self.inner.usage()
+    }
+
+    fn list_versions(&self, id: FileId) -> Result<Vec<u32>, Self::Error> {
+        self.inner.list_versions(id)
+    }
+
+    fn get_version(&self, id: FileId, version: u32) -> Result<Box<[u8]>, Self::Error> {
+        self.inner.get_version(id, version)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        self.inner.special(file)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        self.inner.special_data(file)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<crate::Event>, Self::Error> {
+        self.inner.subscribe()
+    }
+}
+
+impl<F: FileSystem> FileSystemWrite for TextIndexFs<F> {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let found = self.extract(data);
+        let id = self
+            .inner
+            .add_file(data, tags.into_iter().chain(found.tags().iter().cloned()))?;
+        self.lock().insert(id, found.text());
+        Ok(id)
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let found = self.extract(data);
+        self.inner.add_file_with_id(
+            id,
+            data,
+            tags.into_iter().chain(found.tags().iter().cloned()),
+        )?;
+        self.lock().insert(id, found.text());
+        Ok(())
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        let Some(data) = data else {
+            return self.inner.edit_file(id, data, tags);
+        };
+        let found = self.extract(data);
+        // If all the file's tags are replaced, the old extracted ones go with them
+        if let Some(tags) = tags {
+            let tags = tags.into_iter().chain(found.tags().iter().cloned());
+            self.inner.edit_file(id, Some(data), Some(tags))?;
+            self.lock().insert(id, found.text());
+            Ok(())
+        } else {
+            let old = self.extracted_tags(id)?;
+            self.inner.edit_file(id, Some(data), tags)?;
+            self.update(id, &old)
+        }
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.inner.remove_file(id)?;
+        self.lock().remove(id);
+        Ok(())
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        let out = self.inner.transaction(|_| f(self));
+        if out.is_err() {
+            // Anything indexed while it ran may have been undone
+            self.mark_stale();
+        }
+        out
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner.add_tags(id, tags)
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.inner.remove_tags(id, tags)
+    }
+
+    fn rename_tag(&self, old: &Tag, new: Tag) -> Result<(), Self::Error> {
+        self.inner.rename_tag(old, new)
+    }
+
+    fn rename_group(&self, old: &Group, new: Group) -> Result<(), Self::Error> {
+        self.inner.rename_group(old, new)
+    }
+
+    fn revert(&self, id: FileId, version: u32) -> Result<(), Self::Error> {
+        let old = self.extracted_tags(id)?;
+        self.inner.revert(id, version)?;
+        self.update(id, &old)
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.inner.set_special_data(file, data)
+    }
+}
+
+impl<F: FileSystem + ProvidedTags> ProvidedTags for TextIndexFs<F> {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        self.inner.register_provider(group, provider)
+    }
+}
+
+impl<F: InferredTags> InferredTags for TextIndexFs<F> {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        self.inner.register_inferrer(inferrer)
+    }
+}
+
+impl<F: FileSystem + StreamRead> StreamRead for TextIndexFs<F> {
+    type Reader<'a>
+        = F::Reader<'a>
+    where
+        Self: 'a;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        self.inner.read_file(id)
+    }
+}
+
+impl<F: StreamWrite> StreamWrite for TextIndexFs<F> {
+    type Writer<'a>
+        = Writer<'a, F>
+    where
+        Self: 'a;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(Writer {
+            fs: self,
+            inner: Some(self.inner.create_file(tags)?),
+        })
+    }
+}
+
+/// A handle streaming data into a new file of a [`TextIndexFs`], which is a handle of the inner
+/// filesystem. Tags are extracted from the file and it's indexed once committed.
+pub struct Writer<'a, F: StreamWrite> {
+    fs: &'a TextIndexFs<F>,
+    /// Only taken when the handle is committed or dropped
+    inner: Option<F::Writer<'a>>,
+}
+
+impl<'a, F: StreamWrite> Writer<'a, F> {
+    fn inner(&mut self) -> &mut F::Writer<'a> {
+        self.inner
+            .as_mut()
+            .expect("Writer is only taken when committed or dropped")
+    }
+}
+
+impl<F: StreamWrite> io::Write for Writer<'_, F> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.inner().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        let out = self.inner().flush();
+        // The file's ID isn't known until it's committed, so the whole index has to be rebuilt
+        self.fs.mark_stale();
+        out
+    }
+}
+
+impl<F: StreamWrite> FileWriter for Writer<'_, F> {
+    type Error = F::Error;
+
+    fn commit(mut self) -> Result<FileId, Self::Error> {
+        let id = self
+            .inner
+            .take()
+            .expect("Writer is only taken when committed or dropped")
+            .commit()?;
+        self.fs.update(id, &[])?;
+        Ok(id)
+    }
+}
+
+impl<F: StreamWrite> Drop for Writer<'_, F> {
+    fn drop(&mut self) {
+        // The inner handle may store its data when dropped
+        if let Some(inner) = self.inner.take() {
+            drop(inner);
+            self.fs.mark_stale();
+        }
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+    use crate::{Extraction, InMemoryFs, Retention};
+
+    #[test]
+    fn test_text_index() {
+        let inner = InMemoryFs::new();
+        let old = inner
+            .add_file(b"An old note about cats", [Tag::named("note")])
+            .unwrap();
+        let fs = TextIndexFs::new(inner).unwrap();
+
+        let a = fs
+            .add_file(b"Cats and dogs, living together", [Tag::named("note")])
+            .unwrap();
+        let b = fs
+            .add_file(b"A list: dogs, birds.", [Tag::named("list")])
+            .unwrap();
+        let binary = fs.add_file(&[0xFF, b'c', b'a', b't', b's'], []).unwrap();
+
+        assert_eq!(fs.search_content("cats").unwrap(), [old, a]);
+        assert_eq!(fs.search_content("DOGS").unwrap(), [a, b]);
+        assert_eq!(fs.search_content("cats dogs").unwrap(), [a]);
+        assert!(fs.search_content("fish").unwrap().is_empty());
+        assert!(fs.search_content(" ,. ").unwrap().is_empty());
+        assert_eq!(
+            fs.search_content_with("dogs", Tag::named("list")).unwrap(),
+            [b]
+        );
+        assert!(!fs.search_content("cats").unwrap().contains(&binary));
+
+        fs.edit_file(a, Some(b"Fish only"), None::<[Tag; 0]>)
+            .unwrap();
+        assert_eq!(fs.search_content("cats").unwrap(), [old]);
+        assert_eq!(fs.search_content("fish").unwrap(), [a]);
+        fs.remove_file(old).unwrap();
+        assert!(fs.search_content("cats").unwrap().is_empty());
+
+        let mut writer = fs.create_file([]).unwrap();
+        writer.write_all(b"Streamed cats").unwrap();
+        let c = writer.commit().unwrap();
+        assert_eq!(fs.search_content("streamed").unwrap(), [c]);
+
+        // Undone changes are forgotten
+        let res = fs.transaction(|fs| {
+            fs.edit_file(b, Some(b"Cats"), None::<[Tag; 0]>)?;
+            fs.remove_file(FileId::from_u64_unchecked(0xFFFF))
+        });
+        assert!(res.is_err());
+        assert_eq!(fs.search_content("cats").unwrap(), [c]);
+        assert_eq!(fs.search_content("birds").unwrap(), [b]);
+
+        // Changes to the inner filesystem are found once rebuilt
+        let d = fs.inner().add_file(b"birds", []).unwrap();
+        fs.rebuild().unwrap();
+        assert_eq!(fs.search_content("birds").unwrap(), [b, d]);
+    }
+
+    #[test]
+    fn test_extractors() {
+        let fs = TextIndexFs::new(InMemoryFs::new().retention(Retention::Last(1))).unwrap();
+        let before = fs.add_file(b"x=before", []).unwrap();
+
+        // Finds `key=value` pairs, tagging files with them and indexing their values
+        fs.register_extractor(|data: &[u8]| {
+            let text = core::str::from_utf8(data).unwrap_or_default();
+            text.split_whitespace()
+                .filter_map(|pair| pair.split_once('='))
+                .fold(Extraction::new(), |out, (key, value)| {
+                    out.with_tags([Tag::new(Group::custom("kv"), key.to_owned())])
+                        .with_text(value)
+                })
+        })
+        .unwrap();
+        // Files already stored are indexed again, but not tagged
+        assert_eq!(fs.search_content("before").unwrap(), [before]);
+        assert_eq!(fs.get_tags(before).unwrap(), BTreeSet::new());
+
+        let kv = |key: &'static str| Tag::new(Group::custom("kv"), key);
+        let id = fs.add_file(b"a=1 b=2", [Tag::named("t")]).unwrap();
+        assert_eq!(
+            fs.get_tags(id).unwrap(),
+            BTreeSet::from([Tag::named("t"), kv("a"), kv("b")])
+        );
+
+        // Editing the data replaces the extracted tags, but keeps the others
+        fs.edit_file(id, Some(b"b=3 c=4"), None::<[Tag; 0]>)
+            .unwrap();
+        assert_eq!(
+            fs.get_tags(id).unwrap(),
+            BTreeSet::from([Tag::named("t"), kv("b"), kv("c")])
+        );
+        assert_eq!(fs.search_content("4").unwrap(), [id]);
+        assert!(fs.search_content("1").unwrap().is_empty());
+
+        fs.revert(id, 1).unwrap();
+        assert_eq!(
+            fs.get_tags(id).unwrap(),
+            BTreeSet::from([Tag::named("t"), kv("a"), kv("b")])
+        );
+
+        let mut writer = fs.create_file([]).unwrap();
+        writer.write_all(b"d=5").unwrap();
+        let id = writer.commit().unwrap();
+        assert_eq!(fs.get_tags(id).unwrap(), BTreeSet::from([kv("d")]));
+        assert_eq!(fs.search_content("5").unwrap(), [id]);
+    }
+}
@@ -0,0 +1,9 @@
+//! Helpers for the spans implementations are instrumented with, which do nothing without the
+//! tracing feature
+
+/// Record a count on the current span, if it has a field with this name
+#[cfg_attr(not(feature = "tracing"), allow(unused_variables))]
+pub(crate) fn record(field: &'static str, count: usize) {
+    #[cfg(feature = "tracing")]
+    tracing::Span::current().record(field, count);
+}
@@ -0,0 +1,362 @@
+//! Conversion between a tag-based filesystem and a classic directory tree
+//!
+//! Importing walks a directory, adding every regular file it finds. The directories a file is
+//! in, relative to the root of the import, become tags in the `dir` group, and its extension a
+//! tag in the `ext` group. For example, importing `photos/2020/beach.JPG` gives the tags
+//! `dir:photos`, `dir:2020` and `ext:jpg`.
+//!
+//! Exporting writes matching files out into a directory, laid out as chosen by a [`Layout`].
+
+use std::fmt::{self, Write as _};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::vfs::{node_name, Node};
+use crate::{FileId, FileWriter, Group, StreamRead, StreamWrite, Tag, TagPattern};
+
+/// The name of the manifest written by [`Layout::Flat`]
+pub const MANIFEST_NAME: &str = "manifest.json";
+
+/// Error while converting between a filesystem and a directory tree
+#[derive(Debug)]
+pub enum Error<E> {
+    /// An I/O error occurred in the directory tree
+    Io(io::Error),
+    /// The filesystem returned an error
+    Fs(E),
+}
+
+impl<E: fmt::Display> fmt::Display for Error<E> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "directory tree error: {err}"),
+            Error::Fs(err) => err.fmt(f),
+        }
+    }
+}
+
+impl<E: std::error::Error> std::error::Error for Error<E> {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Io(err) => std::error::Error::source(err),
+            Error::Fs(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+impl<E> From<io::Error> for Error<E> {
+    fn from(err: io::Error) -> Error<E> {
+        Error::Io(err)
+    }
+}
+
+/// Options for [`import_tree`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImportOptions {
+    dir_group: Option<Group>,
+    ext_group: Option<Group>,
+    tags: Vec<Tag>,
+}
+
+impl ImportOptions {
+    /// Create the default options, which tag files with the `dir` and `ext` groups
+    pub fn new() -> ImportOptions {
+        ImportOptions::default()
+    }
+
+    /// Set the group directory names are tagged in, or `None` to not tag them
+    #[must_use]
+    pub fn dir_group(mut self, group: Option<Group>) -> Self {
+        self.dir_group = group;
+        self
+    }
+
+    /// Set the group file extensions are tagged in, or `None` to not tag them
+    #[must_use]
+    pub fn ext_group(mut self, group: Option<Group>) -> Self {
+        self.ext_group = group;
+        self
+    }
+
+    /// Add tags which are given to every imported file, such as one recording where they came
+    /// from
+    #[must_use]
+    pub fn tags<I: IntoIterator<Item = Tag>>(mut self, tags: I) -> Self {
+        self.tags.extend(tags);
+        self
+    }
+
+    /// The tags to give a file at a path, relative to the root of the import
+    fn tags_for(&self, path: &Path) -> Vec<Tag> {
+        let mut out = self.tags.clone();
+        if let (Some(group), Some(parent)) = (&self.dir_group, path.parent()) {
+            out.extend(
+                parent
+                    .iter()
+                    .map(|dir| Tag::new(group.clone(), dir.to_string_lossy().into_owned())),
+            );
+        }
+        if let (Some(group), Some(ext)) = (&self.ext_group, path.extension()) {
+            out.push(Tag::new(
+                group.clone(),
+                ext.to_string_lossy().to_lowercase(),
+            ));
+        }
+        out
+    }
+}
+
+impl Default for ImportOptions {
+    fn default() -> Self {
+        ImportOptions {
+            dir_group: Some(Group::custom("dir")),
+            ext_group: Some(Group::custom("ext")),
+            tags: Vec::new(),
+        }
+    }
+}
+
+/// Import every regular file under a directory into a filesystem, returning the path of each
+/// file relative to the directory along with its new ID. Files are streamed in, so they needn't
+/// fit in memory. Symbolic links aren't followed.
+///
+/// The import isn't atomic: if it fails part way through, files already imported are kept.
+pub fn import_tree<F, P>(
+    fs: &F,
+    path: P,
+    options: &ImportOptions,
+) -> Result<Vec<(PathBuf, FileId)>, Error<F::Error>>
+where
+    F: StreamWrite,
+    P: AsRef<Path>,
+{
+    let root = path.as_ref();
+    let mut out = Vec::new();
+    let mut dirs = vec![PathBuf::new()];
+
+    while let Some(rel_dir) = dirs.pop() {
+        let mut entries = fs::read_dir(root.join(&rel_dir))?.collect::<Result<Vec<_>, _>>()?;
+        entries.sort_by_key(fs::DirEntry::file_name);
+
+        // Pushed in reverse, so subdirectories are visited in name order
+        for entry in entries.iter().rev() {
+            if entry.file_type()?.is_dir() {
+                dirs.push(rel_dir.join(entry.file_name()));
+            }
+        }
+
+        for entry in entries {
+            if !entry.file_type()?.is_file() {
+                continue;
+            }
+            let rel_path = rel_dir.join(entry.file_name());
+
+            let mut writer = fs
+                .create_file(options.tags_for(&rel_path))
+                .map_err(Error::Fs)?;
+            io::copy(&mut fs::File::open(entry.path())?, &mut writer)?;
+            let id = writer.commit().map_err(Error::Fs)?;
+
+            out.push((rel_path, id));
+        }
+    }
+
+    Ok(out)
+}
+
+/// How exported files are laid out in the destination directory
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Layout {
+    /// A directory per group, containing a directory per tag name, containing every file with
+    /// that tag, the same as the [`VirtualTree`](crate::vfs::VirtualTree) view. A file is copied
+    /// once for each of its tags, and files without tags are written at the top level.
+    #[default]
+    Hierarchy,
+    /// Every file at the top level, named by its ID, alongside a JSON manifest named
+    /// [`MANIFEST_NAME`] mapping each ID to a list of its tags
+    Flat,
+}
+
+/// Export every file matching a pattern into a directory, which is created if it doesn't exist,
+/// returning the number of files exported. Files are named by their ID, and are streamed out,
+/// so they needn't fit in memory.
+pub fn export_tree<F, P, D>(
+    fs: &F,
+    pattern: P,
+    dest: D,
+    layout: Layout,
+) -> Result<usize, Error<F::Error>>
+where
+    F: StreamRead,
+    P: TagPattern,
+    D: AsRef<Path>,
+{
+    let dest = dest.as_ref();
+    fs::create_dir_all(dest)?;
+
+    let ids = fs.search_tags(pattern).map_err(Error::Fs)?;
+    let mut manifest = String::from("{");
+
+    for (pos, &id) in ids.iter().enumerate() {
+        let file_name = node_name(&Node::File(id)).unwrap_or_default();
+        let tags = fs.get_info(id).map_err(Error::Fs)?.tags;
+
+        let dirs = match layout {
+            Layout::Hierarchy => tags
+                .iter()
+                .filter_map(|tag| {
+                    let group = node_name(&Node::Group(tag.group().clone()))?;
+                    let name = node_name(&Node::Tag(tag.clone()))?;
+                    Some(dest.join(group).join(name))
+                })
+                .collect(),
+            Layout::Flat => {
+                let sep = if pos == 0 { "" } else { "," };
+                let _ = write!(manifest, "{sep}\n  {}: [", json_string(&file_name));
+                for (pos, tag) in tags.iter().enumerate() {
+                    let sep = if pos == 0 { "" } else { ", " };
+                    let _ = write!(manifest, "{sep}{}", json_string(&tag.to_string()));
+                }
+                manifest.push(']');
+                Vec::new()
+            }
+        };
+
+        let Some((first, rest)) = dirs.split_first() else {
+            write_file(fs, id, &dest.join(&file_name))?;
+            continue;
+        };
+        fs::create_dir_all(first)?;
+        let first = first.join(&file_name);
+        write_file(fs, id, &first)?;
+        for dir in rest {
+            fs::create_dir_all(dir)?;
+            fs::copy(&first, dir.join(&file_name))?;
+        }
+    }
+
+    if layout == Layout::Flat {
+        manifest.push_str(if ids.is_empty() { "}\n" } else { "\n}\n" });
+        fs::write(dest.join(MANIFEST_NAME), manifest)?;
+    }
+
+    Ok(ids.len())
+}
+
+/// Stream the data of a file out to a path
+fn write_file<F: StreamRead>(fs: &F, id: FileId, path: &Path) -> Result<(), Error<F::Error>> {
+    let mut reader = fs.read_file(id).map_err(Error::Fs)?;
+    io::copy(&mut reader, &mut fs::File::create(path)?)?;
+    Ok(())
+}
+
+/// Quote and escape a string for JSON
+fn json_string(val: &str) -> String {
+    let mut out = String::from("\"");
+    for c in val.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            c if c.is_control() => {
+                let _ = write!(out, "\\u{:04x}", u32::from(c));
+            }
+            c => out.push(c),
+        }
+    }
+    out.push('"');
+    out
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{FileSystemRead, FileSystemWrite, InMemoryFs};
+    use std::collections::BTreeSet;
+    use tempdir::TempDir;
+
+    #[test]
+    fn test_import() {
+        let dir = TempDir::new("test_tree").unwrap();
+        fs::create_dir_all(dir.path().join("photos/2020")).unwrap();
+        fs::write(dir.path().join("photos/2020/beach.JPG"), [1, 2]).unwrap();
+        fs::write(dir.path().join("notes"), [3]).unwrap();
+
+        let ifs = InMemoryFs::new();
+        let options = ImportOptions::new().tags([Tag::named("imported")]);
+        let imported = import_tree(&ifs, dir.path(), &options).unwrap();
+
+        let paths = imported
+            .iter()
+            .map(|(path, _)| path.clone())
+            .collect::<Vec<_>>();
+        assert_eq!(
+            paths,
+            [
+                PathBuf::from("notes"),
+                PathBuf::from("photos/2020/beach.JPG")
+            ]
+        );
+
+        let info = ifs.get_info(imported[1].1).unwrap();
+        assert_eq!(info.data(), &[1, 2]);
+        assert_eq!(
+            info.tags(),
+            &BTreeSet::from([
+                Tag::named("imported"),
+                Tag::new(Group::custom("dir"), "photos"),
+                Tag::new(Group::custom("dir"), "2020"),
+                Tag::new(Group::custom("ext"), "jpg"),
+            ])
+        );
+        assert_eq!(
+            ifs.get_info(imported[0].1).unwrap().tags(),
+            &BTreeSet::from([Tag::named("imported")])
+        );
+    }
+
+    #[test]
+    fn test_export() {
+        let ifs = InMemoryFs::new();
+        ifs.add_file(&[1], [Tag::named("a"), Tag::new(Group::custom("g"), "b")])
+            .unwrap();
+        ifs.add_file(&[2], [Tag::named("quote\"d")]).unwrap();
+        ifs.add_file(&[3], []).unwrap();
+
+        let dir = TempDir::new("test_tree").unwrap();
+        let count = export_tree(
+            &ifs,
+            Tag::new(Group::custom("g"), "b"),
+            dir.path(),
+            Layout::Hierarchy,
+        )
+        .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(
+            fs::read(dir.path().join("_/a/0000000000000100")).unwrap(),
+            [1]
+        );
+        assert_eq!(
+            fs::read(dir.path().join("g/b/0000000000000100")).unwrap(),
+            [1]
+        );
+
+        let dir = TempDir::new("test_tree").unwrap();
+        let count = export_tree(
+            &ifs,
+            crate::TagPredicate::And(Vec::new()),
+            dir.path(),
+            Layout::Flat,
+        )
+        .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(fs::read(dir.path().join("0000000000000102")).unwrap(), [3]);
+        assert_eq!(
+            fs::read_to_string(dir.path().join(MANIFEST_NAME)).unwrap(),
+            "{\n  \"0000000000000100\": [\"a\", \"g:b\"],\
+             \n  \"0000000000000101\": [\"quote\\\"d\"],\
+             \n  \"0000000000000102\": []\n}\n"
+        );
+    }
+}
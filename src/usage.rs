@@ -0,0 +1,88 @@
+//! Accounting of how much a filesystem stores
+
+use alloc::collections::{BTreeMap, BTreeSet};
+
+use crate::{Group, Tag};
+
+/// How much is stored in files with a tag in some group
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GroupUsage {
+    pub(crate) bytes: u64,
+    pub(crate) files: u64,
+}
+
+impl GroupUsage {
+    /// The total size of the data of files with a tag in the group, in bytes
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// How many files have a tag in the group
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+}
+
+/// How much a filesystem stores, in total and broken down by group. A file with several tags in
+/// a group is only counted once for it, and a file with tags in several groups is counted for
+/// each of them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct UsageReport {
+    pub(crate) bytes: u64,
+    pub(crate) files: u64,
+    pub(crate) groups: BTreeMap<Group, GroupUsage>,
+}
+
+impl UsageReport {
+    /// The total size of the data of every file, in bytes
+    pub fn bytes(&self) -> u64 {
+        self.bytes
+    }
+
+    /// How many files there are
+    pub fn files(&self) -> u64 {
+        self.files
+    }
+
+    /// The usage of every group with a tag on at least one file
+    pub fn groups(&self) -> &BTreeMap<Group, GroupUsage> {
+        &self.groups
+    }
+
+    /// The usage of a single group, which is empty if no file has a tag in it
+    pub fn group(&self, group: &Group) -> GroupUsage {
+        self.groups.get(group).copied().unwrap_or_default()
+    }
+
+    /// Count a file with data of some size and these tags
+    pub(crate) fn add(&mut self, size: u64, tags: &BTreeSet<Tag>) {
+        self.bytes += size;
+        self.files += 1;
+        for group in groups(tags) {
+            let usage = self.groups.entry(group.clone()).or_default();
+            usage.bytes += size;
+            usage.files += 1;
+        }
+    }
+
+    /// Stop counting a file previously counted with [`UsageReport::add`]
+    #[cfg(feature = "std")]
+    pub(crate) fn remove(&mut self, size: u64, tags: &BTreeSet<Tag>) {
+        self.bytes = self.bytes.saturating_sub(size);
+        self.files = self.files.saturating_sub(1);
+        for group in groups(tags) {
+            if let Some(usage) = self.groups.get_mut(group) {
+                usage.bytes = usage.bytes.saturating_sub(size);
+                usage.files = usage.files.saturating_sub(1);
+                if usage.files == 0 {
+                    self.groups.remove(group);
+                }
+            }
+        }
+    }
+}
+
+/// The distinct groups of a set of tags
+fn groups(tags: &BTreeSet<Tag>) -> BTreeSet<&Group> {
+    tags.iter().map(Tag::group).collect()
+}
@@ -0,0 +1,31 @@
+//! Retention of prior versions of file data
+
+/// How many prior versions of a file's data to keep when it's edited. Versions are numbered per
+/// file, counting up from 1, and numbers aren't reused once older versions are dropped.
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub enum Retention {
+    /// Don't keep prior versions
+    #[default]
+    Off,
+    /// Keep at most this many of the most recent prior versions, dropping the oldest first
+    Last(usize),
+    /// Keep every prior version
+    All,
+}
+
+impl Retention {
+    /// Check whether any prior versions are kept
+    pub fn is_enabled(&self) -> bool {
+        !matches!(self, Retention::Off | Retention::Last(0))
+    }
+
+    /// How many of the oldest versions to drop, when `count` versions are stored
+    #[cfg_attr(not(feature = "imfs"), allow(dead_code))]
+    pub(crate) fn excess(self, count: usize) -> usize {
+        match self {
+            Retention::Off => count,
+            Retention::Last(keep) => count.saturating_sub(keep),
+            Retention::All => 0,
+        }
+    }
+}
@@ -0,0 +1,157 @@
+//! Mount a tag-based filesystem as a read-only FUSE filesystem
+
+use core::convert::TryFrom;
+use std::ffi::OsStr;
+use std::io;
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use fuser::{
+    FileAttr, FileType, Filesystem, MountOption, ReplyAttr, ReplyData, ReplyDirectory, ReplyEntry,
+    Request,
+};
+
+use super::{VirtualTree, ROOT_INODE};
+use crate::FileSystemRead;
+
+/// How long the kernel may cache entries and attributes
+const TTL: Duration = Duration::from_secs(1);
+
+/// A FUSE adapter exposing a [`VirtualTree`] of a filesystem
+pub struct FuseAdapter<F> {
+    tree: VirtualTree<F>,
+}
+
+impl<F: FileSystemRead> FuseAdapter<F> {
+    /// Create a new adapter for a filesystem
+    pub fn new(fs: F) -> FuseAdapter<F> {
+        FuseAdapter {
+            tree: VirtualTree::new(fs),
+        }
+    }
+
+    fn attr(&self, inode: u64) -> Option<FileAttr> {
+        let node = self.tree.node(inode)?;
+        let (kind, perm, size) = if node.is_dir() {
+            (FileType::Directory, 0o555, 0)
+        } else {
+            (
+                FileType::RegularFile,
+                0o444,
+                self.tree.file_size(inode).ok()??,
+            )
+        };
+
+        Some(FileAttr {
+            ino: inode,
+            size,
+            blocks: size.div_ceil(512),
+            atime: UNIX_EPOCH,
+            mtime: UNIX_EPOCH,
+            ctime: UNIX_EPOCH,
+            crtime: UNIX_EPOCH,
+            kind,
+            perm,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            flags: 0,
+        })
+    }
+}
+
+impl<F: FileSystemRead> Filesystem for FuseAdapter<F> {
+    fn lookup(&mut self, _req: &Request<'_>, parent: u64, name: &OsStr, reply: ReplyEntry) {
+        let Some(name) = name.to_str() else {
+            return reply.error(libc::ENOENT);
+        };
+        match self.tree.lookup(parent, name) {
+            Ok(Some(entry)) => match self.attr(entry.inode) {
+                Some(attr) => reply.entry(&TTL, &attr, 0),
+                None => reply.error(libc::EIO),
+            },
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn getattr(&mut self, _req: &Request<'_>, ino: u64, _fh: Option<u64>, reply: ReplyAttr) {
+        match self.attr(ino) {
+            Some(attr) => reply.attr(&TTL, &attr),
+            None => reply.error(libc::ENOENT),
+        }
+    }
+
+    fn read(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        size: u32,
+        _flags: i32,
+        _lock_owner: Option<u64>,
+        reply: ReplyData,
+    ) {
+        let Ok(offset) = u64::try_from(offset) else {
+            return reply.error(libc::EINVAL);
+        };
+        match self.tree.read(ino, offset, size as usize) {
+            Ok(Some(data)) => reply.data(&data),
+            Ok(None) => reply.error(libc::ENOENT),
+            Err(_) => reply.error(libc::EIO),
+        }
+    }
+
+    fn readdir(
+        &mut self,
+        _req: &Request<'_>,
+        ino: u64,
+        _fh: u64,
+        offset: i64,
+        mut reply: ReplyDirectory,
+    ) {
+        let entries = match self.tree.read_dir(ino) {
+            Ok(Some(entries)) => entries,
+            Ok(None) => return reply.error(libc::ENOTDIR),
+            Err(_) => return reply.error(libc::EIO),
+        };
+
+        let parent = self.tree.parent(ino).unwrap_or(ROOT_INODE);
+        let dots = vec![(ino, ".".to_owned(), true), (parent, "..".to_owned(), true)];
+        let all = dots.into_iter().chain(
+            entries
+                .into_iter()
+                .map(|entry| (entry.inode, entry.name, entry.is_dir)),
+        );
+
+        let skip = usize::try_from(offset).unwrap_or(0);
+        for (pos, (inode, name, is_dir)) in all.enumerate().skip(skip) {
+            let kind = if is_dir {
+                FileType::Directory
+            } else {
+                FileType::RegularFile
+            };
+            let next = i64::try_from(pos + 1).unwrap_or(i64::MAX);
+            if reply.add(inode, next, kind, name) {
+                break;
+            }
+        }
+        reply.ok();
+    }
+}
+
+/// Mount a filesystem read-only at a path, blocking until it's unmounted
+pub fn mount<F, P>(fs: F, mountpoint: P) -> io::Result<()>
+where
+    F: FileSystemRead,
+    P: AsRef<Path>,
+{
+    fuser::mount2(
+        FuseAdapter::new(fs),
+        mountpoint,
+        &[MountOption::RO, MountOption::FSName("tbf".to_owned())],
+    )
+}
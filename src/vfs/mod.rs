@@ -0,0 +1,247 @@
+//! A virtual directory tree view over a tag-based filesystem
+//!
+//! The tree has three levels: tag groups at the root, a directory per tag name in each group,
+//! and in those the files carrying that tag, named by their ID. For example, a file with the tag
+//! `photos:holiday` appears at `/photos/holiday/0000000000000100`. Tags in the default group live
+//! under a directory named `_`, and tags with a value are named like `rating=5`. A file appears
+//! once for every tag it has.
+//!
+//! Every entry in the tree is identified by an inode number, so the view can back adapters
+//! like the FUSE mount in [`fuse`].
+
+#[cfg(feature = "fuse")]
+pub mod fuse;
+
+use alloc::borrow::Cow;
+use std::collections::BTreeMap;
+
+use crate::{FileId, FileSystemRead, Group, Tag};
+
+/// The inode number of the root directory
+pub const ROOT_INODE: u64 = 1;
+
+/// Directory inodes are allocated from here up, so they never clash with file IDs
+const FIRST_DIR_INODE: u64 = 1 << 63;
+
+/// The name of the directory holding tags in the default group
+const DEFAULT_GROUP_NAME: &str = "_";
+
+/// A single entry in a [`VirtualTree`]
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Node {
+    /// The root directory, containing a directory per group
+    Root,
+    /// A directory for a group, containing a directory per tag name
+    Group(Group),
+    /// A directory for a tag, containing every file with that tag
+    Tag(Tag),
+    /// A file
+    File(FileId),
+}
+
+impl Node {
+    /// Check whether this node is a directory
+    pub fn is_dir(&self) -> bool {
+        !matches!(self, Node::File(_))
+    }
+}
+
+/// An entry in a directory listing
+#[derive(Debug, Clone, PartialEq)]
+pub struct DirEntry {
+    /// The inode of the entry
+    pub inode: u64,
+    /// The name of the entry within its directory
+    pub name: String,
+    /// Whether the entry is a directory
+    pub is_dir: bool,
+}
+
+/// A directory tree view of a filesystem, which assigns inodes to groups, tags, and files as they
+/// are discovered. Files use their ID as an inode, while directories use numbers above `2^63`.
+pub struct VirtualTree<F> {
+    fs: F,
+    nodes: BTreeMap<u64, Node>,
+    inodes: BTreeMap<Node, u64>,
+    next_dir: u64,
+}
+
+impl<F: FileSystemRead> VirtualTree<F> {
+    /// Create a new tree view of a filesystem
+    pub fn new(fs: F) -> VirtualTree<F> {
+        let mut out = VirtualTree {
+            fs,
+            nodes: BTreeMap::new(),
+            inodes: BTreeMap::new(),
+            next_dir: FIRST_DIR_INODE,
+        };
+        out.nodes.insert(ROOT_INODE, Node::Root);
+        out.inodes.insert(Node::Root, ROOT_INODE);
+        out
+    }
+
+    /// Get the filesystem this is a view of
+    pub fn inner(&self) -> &F {
+        &self.fs
+    }
+
+    /// Get the node for an inode, if it's been discovered
+    pub fn node(&self, inode: u64) -> Option<&Node> {
+        self.nodes.get(&inode)
+    }
+
+    fn inode(&mut self, node: Node) -> u64 {
+        if let Some(inode) = self.inodes.get(&node) {
+            return *inode;
+        }
+
+        let inode = if let Node::File(id) = node {
+            id.into_u64_unchecked()
+        } else {
+            self.next_dir += 1;
+            self.next_dir - 1
+        };
+        self.nodes.insert(inode, node.clone());
+        self.inodes.insert(node, inode);
+        inode
+    }
+
+    /// List the entries of a directory. Returns `None` if the inode isn't a known directory.
+    pub fn read_dir(&mut self, inode: u64) -> Result<Option<Vec<DirEntry>>, F::Error> {
+        let children: Vec<Node> = match self.nodes.get(&inode) {
+            Some(Node::Root) => self
+                .fs
+                .list_groups()?
+                .into_iter()
+                .map(Node::Group)
+                .collect(),
+            Some(Node::Group(group)) => self
+                .fs
+                .list_tags(group)?
+                .into_iter()
+                .map(Node::Tag)
+                .collect(),
+            Some(Node::Tag(tag)) => self
+                .fs
+                .search_tags(tag.clone())?
+                .into_iter()
+                .map(Node::File)
+                .collect(),
+            Some(Node::File(_)) | None => return Ok(None),
+        };
+
+        Ok(Some(
+            children
+                .into_iter()
+                .filter_map(|node| {
+                    let name = node_name(&node)?;
+                    let is_dir = node.is_dir();
+                    Some(DirEntry {
+                        inode: self.inode(node),
+                        name,
+                        is_dir,
+                    })
+                })
+                .collect(),
+        ))
+    }
+
+    /// Get the inode of the directory containing a directory, which for the root is itself.
+    /// Returns `None` for files, which are in a directory for each of their tags, and unknown
+    /// inodes.
+    pub fn parent(&mut self, inode: u64) -> Option<u64> {
+        match self.nodes.get(&inode)? {
+            Node::Root | Node::Group(_) => Some(ROOT_INODE),
+            Node::Tag(tag) => {
+                let group = Node::Group(tag.group().clone());
+                Some(self.inode(group))
+            }
+            Node::File(_) => None,
+        }
+    }
+
+    /// Find an entry by name within a directory
+    pub fn lookup(&mut self, parent: u64, name: &str) -> Result<Option<DirEntry>, F::Error> {
+        Ok(self
+            .read_dir(parent)?
+            .and_then(|entries| entries.into_iter().find(|entry| entry.name == name)))
+    }
+
+    /// Get the size in bytes of a file, or `None` for directories and unknown inodes
+    pub fn file_size(&self, inode: u64) -> Result<Option<u64>, F::Error> {
+        match self.nodes.get(&inode) {
+            Some(Node::File(id)) => Ok(Some(self.fs.get_metadata(*id)?.size())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Read up to `size` bytes of a file, starting at `offset`
+    pub fn read(&self, inode: u64, offset: u64, size: usize) -> Result<Option<Vec<u8>>, F::Error> {
+        let Some(Node::File(id)) = self.nodes.get(&inode) else {
+            return Ok(None);
+        };
+        let end = offset.saturating_add(size as u64);
+        self.fs.read_range(*id, offset..end).map(Some)
+    }
+}
+
+/// The name of a node within its parent, or `None` if it can't be represented as a path
+pub(crate) fn node_name(node: &Node) -> Option<String> {
+    let name: Cow<'_, str> = match node {
+        Node::Root => return None,
+        Node::Group(Group::Default) => Cow::Borrowed(DEFAULT_GROUP_NAME),
+        Node::Group(Group::Custom(name)) => Cow::Borrowed(name),
+        Node::Tag(tag) => match tag.value() {
+            Some(value) => Cow::Owned(format!("{}={value}", tag.name())),
+            None => Cow::Borrowed(tag.name()),
+        },
+        Node::File(id) => Cow::Owned(format!("{:016X}", id.into_u64_unchecked())),
+    };
+
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        None
+    } else {
+        Some(name.into_owned())
+    }
+}
+
+#[cfg(all(test, feature = "imfs"))]
+mod tests {
+    use super::*;
+    use crate::{FileSystemWrite, InMemoryFs};
+
+    #[test]
+    fn test_tree() {
+        let ifs = InMemoryFs::new();
+        let id = ifs
+            .add_file(
+                &[0, 1, 2, 3],
+                [Tag::named("a"), Tag::new(Group::custom("g"), "b")],
+            )
+            .unwrap();
+        let mut tree = VirtualTree::new(ifs);
+
+        let names = tree
+            .read_dir(ROOT_INODE)
+            .unwrap()
+            .unwrap()
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect::<Vec<_>>();
+        assert_eq!(names, ["_", "g"]);
+
+        let group = tree.lookup(ROOT_INODE, "g").unwrap().unwrap();
+        let tag = tree.lookup(group.inode, "b").unwrap().unwrap();
+        let file = tree.lookup(tag.inode, "0000000000000100").unwrap().unwrap();
+
+        assert!(!file.is_dir);
+        assert_eq!(tree.node(file.inode), Some(&Node::File(id)));
+        assert_eq!(tree.file_size(file.inode).unwrap(), Some(4));
+        assert_eq!(tree.read(file.inode, 1, 2).unwrap().unwrap(), [1, 2]);
+
+        assert_eq!(tree.parent(tag.inode), Some(group.inode));
+        assert_eq!(tree.parent(group.inode), Some(ROOT_INODE));
+        assert_eq!(tree.parent(ROOT_INODE), Some(ROOT_INODE));
+        assert_eq!(tree.parent(file.inode), None);
+    }
+}
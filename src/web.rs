@@ -0,0 +1,951 @@
+//! Implementation of a TBF in the browser, persisted to the Origin Private File System
+//!
+//! Browsers only allow reading and writing files synchronously from a dedicated worker, so a
+//! [`WebFs`] has to be opened in one. Every file is kept in memory for lookups, while each change
+//! is appended to a log in a single file of the origin's private storage, which is replayed when
+//! the store is opened again.
+//!
+//! The log file starts with the offset of the first record, as a little-endian `u64`. Each record
+//! is then its length as a `u32`, followed by a kind byte and the change's fields, in the same
+//! encoding as other stores. A record cut short when the page was closed is dropped on opening.
+
+use core::convert::TryFrom;
+use core::fmt;
+use core::ops::Range;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io;
+use std::sync::mpsc::Receiver;
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use wasm_bindgen::{JsCast, JsValue};
+use wasm_bindgen_futures::JsFuture;
+use web_sys::{
+    FileSystemDirectoryHandle, FileSystemFileHandle, FileSystemGetFileOptions,
+    FileSystemReadWriteOptions, FileSystemSyncAccessHandle, WorkerGlobalScope,
+};
+
+use crate::codec;
+use crate::error::ErrorKind;
+use crate::search::SearchOptions;
+use crate::{
+    BatchResult, BufferedWriter, Event, FileId, FileInfo, FileSystemRead, FileSystemWrite, Group,
+    ImfsError, ImfsSearchIter, InMemoryFs, InferredTags, Metadata, ProvidedTags, SpecialFile,
+    StreamRead, StreamWrite, Tag, TagInferrer, TagPattern, TagProvider,
+};
+
+/// Length of the header holding the offset of the first record
+const HEADER_LEN: u64 = 8;
+
+const ADD: u8 = 0;
+const EDIT: u8 = 1;
+const REMOVE: u8 = 2;
+const SPECIAL: u8 = 3;
+/// A file ID which was handed out, for a compacted log to keep IDs of removed files from being
+/// handed out again
+const RESERVE: u8 = 4;
+
+const EDIT_DATA: u8 = 1;
+const EDIT_TAGS: u8 = 2;
+
+/// Error for a browser filesystem
+#[derive(Debug)]
+pub enum Error {
+    /// The in-memory copy of the store returned an error
+    Imfs(ImfsError),
+    /// The browser returned an error, with its description
+    Browser(String),
+    /// The log of changes couldn't be read
+    IoError(io::Error),
+}
+
+impl From<ImfsError> for Error {
+    fn from(err: ImfsError) -> Error {
+        Error::Imfs(err)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(err: io::Error) -> Error {
+        Error::IoError(err)
+    }
+}
+
+impl From<JsValue> for Error {
+    fn from(err: JsValue) -> Error {
+        Error::Browser(format!("{err:?}"))
+    }
+}
+
+impl crate::Error for Error {
+    fn file_not_found(id: FileId) -> Self {
+        Error::Imfs(ImfsError::FileNotFound(id))
+    }
+
+    fn already_exists(id: FileId) -> Self {
+        Error::Imfs(ImfsError::AlreadyExists(id))
+    }
+
+    fn version_not_found(id: FileId, version: u32) -> Self {
+        Error::Imfs(ImfsError::VersionNotFound(id, version))
+    }
+
+    fn storage_full(_: FileId) -> Self {
+        Error::Imfs(ImfsError::StorageFull)
+    }
+
+    fn generic_kind(&self) -> ErrorKind<'_> {
+        match self {
+            Error::Imfs(err) => err.generic_kind(),
+            Error::Browser(_) => ErrorKind::Other,
+            Error::IoError(err) => ErrorKind::Source(err),
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Imfs(err) => write!(f, "{err}"),
+            Error::Browser(msg) => write!(f, "browser error: {msg}"),
+            Error::IoError(err) => write!(f, "change log is malformed: {err}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Imfs(err) => std::error::Error::source(err),
+            Error::Browser(_) => None,
+            Error::IoError(err) => std::error::Error::source(err),
+        }
+    }
+}
+
+/// The file a [`WebFs`] keeps its log in
+trait Storage {
+    fn size(&self) -> Result<u64, Error>;
+
+    /// Fill a buffer from an offset, failing if the file ends first
+    fn read_at(&self, at: u64, buf: &mut [u8]) -> Result<(), Error>;
+
+    fn write_at(&self, at: u64, data: &[u8]) -> Result<(), Error>;
+
+    fn truncate(&self, len: u64) -> Result<(), Error>;
+
+    fn flush(&self) -> Result<(), Error>;
+}
+
+/// A file of the origin's private storage, closed when dropped so it can be opened again
+struct Opfs(FileSystemSyncAccessHandle);
+
+impl Storage for Opfs {
+    fn size(&self) -> Result<u64, Error> {
+        Ok(from_offset(self.0.get_size()?))
+    }
+
+    fn read_at(&self, at: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(to_offset(at));
+        let read = self.0.read_with_u8_array_and_options(buf, &options)?;
+        if from_offset(read) < buf.len() as u64 {
+            return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+        }
+        Ok(())
+    }
+
+    fn write_at(&self, at: u64, data: &[u8]) -> Result<(), Error> {
+        let options = FileSystemReadWriteOptions::new();
+        options.set_at(to_offset(at));
+        let written = self.0.write_with_u8_array_and_options(data, &options)?;
+        if from_offset(written) < data.len() as u64 {
+            return Err(io::Error::from(io::ErrorKind::WriteZero).into());
+        }
+        Ok(())
+    }
+
+    fn truncate(&self, len: u64) -> Result<(), Error> {
+        Ok(self.0.truncate_with_f64(to_offset(len))?)
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        Ok(self.0.flush()?)
+    }
+}
+
+impl Drop for Opfs {
+    fn drop(&mut self) {
+        self.0.close();
+    }
+}
+
+/// Offsets in JavaScript are floats, which are exact up to 2^53 bytes
+#[allow(clippy::cast_precision_loss)]
+fn to_offset(at: u64) -> f64 {
+    at as f64
+}
+
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn from_offset(at: f64) -> u64 {
+    at as u64
+}
+
+/// Where the records of the log are, and those of a running transaction waiting to be written
+struct Log {
+    start: u64,
+    end: u64,
+    pending: Option<Vec<u8>>,
+}
+
+/// A tag-based filesystem for web apps, persisted to a file of the browser's
+/// [Origin Private File System](https://developer.mozilla.org/en-US/docs/Web/API/File_System_API/Origin_private_file_system).
+///
+/// Files are kept in an [`InMemoryFs`], which serves every lookup, and every change is appended
+/// to a log which is replayed when the store is opened again. The log only grows, so should be
+/// [compacted](WebFs::compact) every so often. If saving a change fails, it's still made in
+/// memory, so the store should be reopened to get back to what's saved.
+///
+/// File IDs are never reused, and prior versions of file data aren't kept.
+pub struct WebFs {
+    inner: InMemoryFs,
+    storage: Box<dyn Storage>,
+    log: Mutex<Log>,
+}
+
+impl WebFs {
+    /// Open the store kept in a file of the origin's private storage, creating it if it doesn't
+    /// exist. Only works in a dedicated worker, and only one worker can have a store open at a
+    /// time.
+    pub async fn open(name: &str) -> Result<WebFs, Error> {
+        let scope = js_sys::global()
+            .dyn_into::<WorkerGlobalScope>()
+            .map_err(|_| Error::Browser("WebFs can only be opened in a worker".to_owned()))?;
+        let root = JsFuture::from(scope.navigator().storage().get_directory())
+            .await?
+            .unchecked_into::<FileSystemDirectoryHandle>();
+
+        let options = FileSystemGetFileOptions::new();
+        options.set_create(true);
+        let file = JsFuture::from(root.get_file_handle_with_options(name, &options))
+            .await?
+            .unchecked_into::<FileSystemFileHandle>();
+        let handle = JsFuture::from(file.create_sync_access_handle())
+            .await?
+            .unchecked_into::<FileSystemSyncAccessHandle>();
+        WebFs::load(Box::new(Opfs(handle)))
+    }
+
+    /// Load a store from the log in its storage, starting a new one if it's empty
+    fn load(storage: Box<dyn Storage>) -> Result<WebFs, Error> {
+        let size = storage.size()?;
+        let start = if size < HEADER_LEN {
+            storage.write_at(0, &HEADER_LEN.to_le_bytes())?;
+            storage.truncate(HEADER_LEN)?;
+            storage.flush()?;
+            HEADER_LEN
+        } else {
+            let mut header = [0; 8];
+            storage.read_at(0, &mut header)?;
+            u64::from_le_bytes(header)
+        };
+
+        let len = usize::try_from(size.saturating_sub(start))
+            .map_err(|_| io::Error::new(io::ErrorKind::InvalidData, "Log too large to load"))?;
+        let mut records = vec![0; len];
+        storage.read_at(start, &mut records)?;
+
+        let inner = InMemoryFs::new();
+        let mut rest = &records[..];
+        while let Some(record) = next_record(&mut rest) {
+            replay(&inner, record)?;
+        }
+
+        // Drop a record cut short while it was written
+        let end = start + (records.len() - rest.len()) as u64;
+        if end < size {
+            storage.truncate(end)?;
+            storage.flush()?;
+        }
+
+        Ok(WebFs {
+            inner,
+            storage,
+            log: Mutex::new(Log {
+                start,
+                end,
+                pending: None,
+            }),
+        })
+    }
+
+    /// Rewrite the log as just the files there are now, dropping the history of how they got
+    /// there. The old log is kept until the new one is written in full, so nothing's lost if the
+    /// page is closed part way through. Does nothing while a transaction is running.
+    pub fn compact(&self) -> Result<(), Error> {
+        let mut log = self.lock();
+        if log.pending.is_some() {
+            return Ok(());
+        }
+
+        let mut records = Vec::new();
+        for id in self.inner.ids_after(FileId::from_u64_unchecked(0))? {
+            let data = self.inner.get_data(id)?;
+            let tags = self.inner.get_stored_tags(id)?;
+            let body = encode_add(id, &data, &tags, self.inner.times(id)?)?;
+            frame(&mut records, &body)?;
+        }
+        for &file in SpecialFile::ALL.iter().filter(|file| !file.is_generated()) {
+            let data = self.inner.special_data(file)?;
+            if !data.is_empty() {
+                frame(&mut records, &encode_special(file, &data)?)?;
+            }
+        }
+        if let Some(last) = self.inner.last_id()? {
+            frame(&mut records, &encode_id(RESERVE, last)?)?;
+        }
+
+        // Write the new log after the old one and switch to it, then move it to the front
+        let tail = log.end;
+        self.storage.write_at(tail, &records)?;
+        self.storage.flush()?;
+        self.set_start(tail)?;
+        log.start = tail;
+        log.end = tail + records.len() as u64;
+
+        if HEADER_LEN + records.len() as u64 <= tail {
+            self.storage.write_at(HEADER_LEN, &records)?;
+            self.storage.flush()?;
+            self.set_start(HEADER_LEN)?;
+            log.start = HEADER_LEN;
+            log.end = HEADER_LEN + records.len() as u64;
+            self.storage.truncate(log.end)?;
+            self.storage.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Get how many bytes the log takes up, to tell when it's worth compacting
+    pub fn log_size(&self) -> u64 {
+        self.lock().end
+    }
+
+    /// Get the in-memory copy of the store
+    pub fn inner(&self) -> &InMemoryFs {
+        &self.inner
+    }
+
+    fn set_start(&self, start: u64) -> Result<(), Error> {
+        self.storage.write_at(0, &start.to_le_bytes())?;
+        self.storage.flush()
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Log> {
+        self.log.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Write records to the end of the log
+    fn write(&self, log: &mut Log, records: &[u8]) -> Result<(), Error> {
+        if records.is_empty() {
+            return Ok(());
+        }
+        self.storage.write_at(log.end, records)?;
+        self.storage.flush()?;
+        log.end += records.len() as u64;
+        Ok(())
+    }
+
+    /// Make a change to the in-memory copy, then log the record it returns. The log is held
+    /// throughout, so changes are logged in the order they're made. While a transaction is
+    /// running, the record is held on to until it's done.
+    fn logged<T, G>(&self, f: G) -> Result<T, Error>
+    where
+        G: FnOnce(&InMemoryFs) -> Result<(T, Vec<u8>), Error>,
+    {
+        let mut log = self.lock();
+        let (out, body) = f(&self.inner)?;
+        let mut record = Vec::new();
+        frame(&mut record, &body)?;
+        match &mut log.pending {
+            Some(pending) => pending.extend(record),
+            None => self.write(&mut log, &record)?,
+        }
+        Ok(out)
+    }
+
+    /// Log the current tags of a file
+    fn log_tags<G>(&self, id: FileId, f: G) -> Result<(), Error>
+    where
+        G: FnOnce(&InMemoryFs) -> Result<(), ImfsError>,
+    {
+        self.logged(|inner| {
+            f(inner)?;
+            let tags = inner.get_stored_tags(id)?;
+            Ok(((), encode_edit(id, None, Some(&tags))?))
+        })
+    }
+}
+
+impl FileSystemRead for WebFs {
+    type Error = Error;
+    type SearchIter<'a, P>
+        = SearchIter<'a, P>
+    where
+        P: TagPattern + 'a;
+
+    fn search_tags_with<P>(
+        &self,
+        tags: P,
+        options: &SearchOptions,
+    ) -> Result<Vec<FileId>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.search_tags_with(tags, options)?)
+    }
+
+    fn search_tags_iter<'a, P>(&'a self, tags: P) -> Self::SearchIter<'a, P>
+    where
+        P: TagPattern + 'a,
+    {
+        SearchIter {
+            inner: self.inner.search_tags_iter(tags),
+        }
+    }
+
+    fn get_info(&self, id: FileId) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.get_info(id)?)
+    }
+
+    fn get_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_tags(id)?)
+    }
+
+    fn get_stored_tags(&self, id: FileId) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.get_stored_tags(id)?)
+    }
+
+    fn get_info_many(&self, ids: &[FileId]) -> BatchResult<FileInfo, Self::Error> {
+        Ok(self
+            .inner
+            .get_info_many(ids)?
+            .into_iter()
+            .map(|info| Ok(info?))
+            .collect())
+    }
+
+    fn get_tags_many(&self, ids: &[FileId]) -> BatchResult<BTreeSet<Tag>, Self::Error> {
+        Ok(self
+            .inner
+            .get_tags_many(ids)?
+            .into_iter()
+            .map(|tags| Ok(tags?))
+            .collect())
+    }
+
+    fn get_data(&self, id: FileId) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.get_data(id)?)
+    }
+
+    fn read_range(&self, id: FileId, range: Range<u64>) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.read_range(id, range)?)
+    }
+
+    fn get_metadata(&self, id: FileId) -> Result<Metadata, Self::Error> {
+        Ok(self.inner.get_metadata(id)?)
+    }
+
+    fn last_id(&self) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.last_id()?)
+    }
+
+    fn ids_after(&self, after: FileId) -> Result<Vec<FileId>, Self::Error> {
+        Ok(self.inner.ids_after(after)?)
+    }
+
+    fn tag_counts<P>(&self, pattern: P) -> Result<BTreeMap<Tag, usize>, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.tag_counts(pattern)?)
+    }
+
+    fn count_tags<P>(&self, pattern: P) -> Result<u64, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.count_tags(pattern)?)
+    }
+
+    fn any_match<P>(&self, pattern: P) -> Result<bool, Self::Error>
+    where
+        P: TagPattern,
+    {
+        Ok(self.inner.any_match(pattern)?)
+    }
+
+    fn find_duplicates(&self) -> Result<Vec<Vec<FileId>>, Self::Error> {
+        Ok(self.inner.find_duplicates()?)
+    }
+
+    fn find_by_hash(&self, hash: &[u8; 32]) -> Result<Option<FileId>, Self::Error> {
+        Ok(self.inner.find_by_hash(hash)?)
+    }
+
+    fn list_groups(&self) -> Result<BTreeSet<Group>, Self::Error> {
+        Ok(self.inner.list_groups()?)
+    }
+
+    fn list_tags(&self, group: &Group) -> Result<BTreeSet<Tag>, Self::Error> {
+        Ok(self.inner.list_tags(group)?)
+    }
+
+    fn special(&self, file: SpecialFile) -> Result<FileInfo, Self::Error> {
+        Ok(self.inner.special(file)?)
+    }
+
+    fn special_data(&self, file: SpecialFile) -> Result<Vec<u8>, Self::Error> {
+        Ok(self.inner.special_data(file)?)
+    }
+
+    fn subscribe(&self) -> Result<Receiver<Event>, Self::Error> {
+        Ok(self.inner.subscribe()?)
+    }
+}
+
+impl FileSystemWrite for WebFs {
+    fn add_file<I>(&self, data: &[u8], tags: I) -> Result<FileId, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.logged(|inner| {
+            let id = inner.add_file(data, tags)?;
+            let body = encode_add(id, data, &inner.get_stored_tags(id)?, inner.times(id)?)?;
+            Ok((id, body))
+        })
+    }
+
+    fn add_file_with_id<I>(&self, id: FileId, data: &[u8], tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.logged(|inner| {
+            inner.add_file_with_id(id, data, tags)?;
+            let body = encode_add(id, data, &inner.get_stored_tags(id)?, inner.times(id)?)?;
+            Ok(((), body))
+        })
+    }
+
+    fn edit_file<I>(
+        &self,
+        id: FileId,
+        data: Option<&[u8]>,
+        tags: Option<I>,
+    ) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.logged(|inner| {
+            let has_tags = tags.is_some();
+            inner.edit_file(id, data, tags)?;
+            let data = match data {
+                Some(data) => Some((inner.times(id)?.1, data)),
+                None => None,
+            };
+            let tags = if has_tags {
+                Some(inner.get_stored_tags(id)?)
+            } else {
+                None
+            };
+            Ok(((), encode_edit(id, data, tags.as_ref())?))
+        })
+    }
+
+    fn add_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.log_tags(id, |inner| inner.add_tags(id, tags))
+    }
+
+    fn remove_tags<I>(&self, id: FileId, tags: I) -> Result<(), Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        self.log_tags(id, |inner| inner.remove_tags(id, tags))
+    }
+
+    fn remove_file(&self, id: FileId) -> Result<(), Self::Error> {
+        self.logged(|inner| {
+            inner.remove_file(id)?;
+            Ok(((), encode_id(REMOVE, id)?))
+        })
+    }
+
+    fn transaction<T, G>(&self, f: G) -> Result<T, Self::Error>
+    where
+        G: FnOnce(&Self) -> Result<T, Self::Error>,
+    {
+        {
+            let mut log = self.lock();
+            if log.pending.is_some() {
+                drop(log);
+                return f(self);
+            }
+            log.pending = Some(Vec::new());
+        }
+
+        // The in-memory copy needs one of its own errors to undo changes, so a stand-in is
+        // returned to it, and the real error returned once it's done. The changes are logged
+        // last, so a failure to log them undoes them too.
+        let mut failed = None;
+        let out = self.inner.transaction(|_| {
+            let out = f(self).and_then(|val| {
+                let mut log = self.lock();
+                let records = log.pending.take().unwrap_or_default();
+                self.write(&mut log, &records)?;
+                Ok(val)
+            });
+            match out {
+                Ok(val) => Ok(val),
+                Err(Error::Imfs(err)) => Err(err),
+                Err(err) => {
+                    failed = Some(err);
+                    Err(crate::Error::file_not_found(FileId::from_u64_unchecked(0)))
+                }
+            }
+        });
+        self.lock().pending = None;
+        match (out, failed) {
+            (Err(_), Some(err)) => Err(err),
+            (out, _) => Ok(out?),
+        }
+    }
+
+    fn set_special_data(&self, file: SpecialFile, data: &[u8]) -> Result<(), Self::Error> {
+        self.logged(|inner| {
+            inner.set_special_data(file, data)?;
+            Ok(((), encode_special(file, data)?))
+        })
+    }
+}
+
+impl ProvidedTags for WebFs {
+    fn register_provider<P>(&self, group: Group, provider: P) -> Result<(), Self::Error>
+    where
+        P: TagProvider + 'static,
+    {
+        Ok(self.inner.register_provider(group, provider)?)
+    }
+}
+
+impl InferredTags for WebFs {
+    fn register_inferrer<I>(&self, inferrer: I) -> Result<(), Self::Error>
+    where
+        I: TagInferrer + 'static,
+    {
+        Ok(self.inner.register_inferrer(inferrer)?)
+    }
+}
+
+impl StreamRead for WebFs {
+    type Reader<'a> = <InMemoryFs as StreamRead>::Reader<'a>;
+
+    fn read_file(&self, id: FileId) -> Result<Self::Reader<'_>, Self::Error> {
+        Ok(self.inner.read_file(id)?)
+    }
+}
+
+impl StreamWrite for WebFs {
+    type Writer<'a> = Writer<'a>;
+
+    fn create_file<I>(&self, tags: I) -> Result<Self::Writer<'_>, Self::Error>
+    where
+        I: IntoIterator<Item = Tag>,
+    {
+        Ok(BufferedWriter::new(self, tags))
+    }
+}
+
+fn to_nanos(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .ok()
+        .and_then(|since| u64::try_from(since.as_nanos()).ok())
+        .unwrap_or(0)
+}
+
+fn read_time(input: &mut &[u8]) -> io::Result<SystemTime> {
+    Ok(UNIX_EPOCH + Duration::from_nanos(codec::read_u64(input)?))
+}
+
+fn write_bytes(out: &mut Vec<u8>, data: &[u8]) -> io::Result<()> {
+    codec::write_len(out, data.len())?;
+    out.extend_from_slice(data);
+    Ok(())
+}
+
+fn read_bytes<'a>(input: &mut &'a [u8]) -> io::Result<&'a [u8]> {
+    let len = codec::read_u32(input)? as usize;
+    if input.len() < len {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    let (data, rest) = input.split_at(len);
+    *input = rest;
+    Ok(data)
+}
+
+fn write_tags(out: &mut Vec<u8>, tags: &BTreeSet<Tag>) -> io::Result<()> {
+    codec::write_len(out, tags.len())?;
+    for tag in tags {
+        codec::write_tag(out, tag)?;
+    }
+    Ok(())
+}
+
+fn read_tags(input: &mut &[u8]) -> io::Result<BTreeSet<Tag>> {
+    let len = codec::read_u32(input)?;
+    (0..len)
+        .map(|_| codec::read_tag(input)?.ok_or_else(|| io::ErrorKind::UnexpectedEof.into()))
+        .collect()
+}
+
+fn read_byte(input: &mut &[u8]) -> io::Result<u8> {
+    let (&byte, rest) = input.split_first().ok_or(io::ErrorKind::UnexpectedEof)?;
+    *input = rest;
+    Ok(byte)
+}
+
+/// Add a record to the end of a log, prefixed by its length
+fn frame(out: &mut Vec<u8>, body: &[u8]) -> io::Result<()> {
+    write_bytes(out, body)
+}
+
+/// Take the next whole record from a log, or `None` at its end or a record cut short
+fn next_record<'a>(input: &mut &'a [u8]) -> Option<&'a [u8]> {
+    let mut rest = *input;
+    let record = read_bytes(&mut rest).ok()?;
+    *input = rest;
+    Some(record)
+}
+
+fn encode_add(
+    id: FileId,
+    data: &[u8],
+    tags: &BTreeSet<Tag>,
+    (created, modified): (SystemTime, SystemTime),
+) -> io::Result<Vec<u8>> {
+    let mut out = vec![ADD];
+    codec::write_id(&mut out, id)?;
+    codec::write_u64(&mut out, to_nanos(created))?;
+    codec::write_u64(&mut out, to_nanos(modified))?;
+    write_bytes(&mut out, data)?;
+    write_tags(&mut out, tags)?;
+    Ok(out)
+}
+
+fn encode_edit(
+    id: FileId,
+    data: Option<(SystemTime, &[u8])>,
+    tags: Option<&BTreeSet<Tag>>,
+) -> io::Result<Vec<u8>> {
+    let mut flags = 0;
+    if data.is_some() {
+        flags |= EDIT_DATA;
+    }
+    if tags.is_some() {
+        flags |= EDIT_TAGS;
+    }
+    let mut out = vec![EDIT];
+    codec::write_id(&mut out, id)?;
+    out.push(flags);
+    if let Some((modified, data)) = data {
+        codec::write_u64(&mut out, to_nanos(modified))?;
+        write_bytes(&mut out, data)?;
+    }
+    if let Some(tags) = tags {
+        write_tags(&mut out, tags)?;
+    }
+    Ok(out)
+}
+
+fn encode_special(file: SpecialFile, data: &[u8]) -> io::Result<Vec<u8>> {
+    let mut out = vec![SPECIAL];
+    codec::write_id(&mut out, file.id())?;
+    write_bytes(&mut out, data)?;
+    Ok(out)
+}
+
+fn encode_id(kind: u8, id: FileId) -> io::Result<Vec<u8>> {
+    let mut out = vec![kind];
+    codec::write_id(&mut out, id)?;
+    Ok(out)
+}
+
+/// Make the change a record describes to the in-memory copy of a store being loaded
+fn replay(fs: &InMemoryFs, mut record: &[u8]) -> Result<(), Error> {
+    let input = &mut record;
+    match read_byte(input)? {
+        ADD => {
+            let id = codec::read_id(input)?;
+            let created = read_time(input)?;
+            let modified = read_time(input)?;
+            let data = read_bytes(input)?;
+            fs.add_file_with_id(id, data, read_tags(input)?)?;
+            fs.set_times(id, created, modified)?;
+        }
+        EDIT => {
+            let id = codec::read_id(input)?;
+            let flags = read_byte(input)?;
+            let data = if flags & EDIT_DATA != 0 {
+                Some((read_time(input)?, read_bytes(input)?))
+            } else {
+                None
+            };
+            let tags = if flags & EDIT_TAGS != 0 {
+                Some(read_tags(input)?)
+            } else {
+                None
+            };
+            fs.edit_file(id, data.map(|(_, data)| data), tags)?;
+            if let Some((modified, _)) = data {
+                let (created, _) = fs.times(id)?;
+                fs.set_times(id, created, modified)?;
+            }
+        }
+        REMOVE => fs.remove_file(codec::read_id(input)?)?,
+        SPECIAL => {
+            let file = SpecialFile::from_id(codec::read_id(input)?).ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "Unknown special file in log")
+            })?;
+            fs.set_special_data(file, read_bytes(input)?)?;
+        }
+        RESERVE => {
+            let id = codec::read_id(input)?;
+            if !crate::exists(fs, id)? {
+                fs.add_file_with_id(id, &[], [])?;
+                fs.remove_file(id)?;
+            }
+        }
+        _ => {
+            return Err(
+                io::Error::new(io::ErrorKind::InvalidData, "Unknown kind of record in log").into(),
+            )
+        }
+    }
+    Ok(())
+}
+
+/// A lazy search over a [`WebFs`], which is a search of its in-memory copy
+pub struct SearchIter<'a, P> {
+    inner: ImfsSearchIter<'a, P>,
+}
+
+impl<P: TagPattern> Iterator for SearchIter<'_, P> {
+    type Item = Result<FileId, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.inner.next().map(|id| id.map_err(Error::Imfs))
+    }
+}
+
+/// A handle streaming data into a new file of a [`WebFs`]. Data is buffered until the handle is
+/// flushed, which adds the file or updates its data if it already exists.
+pub type Writer<'a> = BufferedWriter<'a, WebFs>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    /// A log kept in memory, shared so it can be loaded again
+    #[derive(Clone, Default)]
+    struct Memory(Rc<RefCell<Vec<u8>>>);
+
+    impl Storage for Memory {
+        fn size(&self) -> Result<u64, Error> {
+            Ok(self.0.borrow().len() as u64)
+        }
+
+        fn read_at(&self, at: u64, buf: &mut [u8]) -> Result<(), Error> {
+            let at = usize::try_from(at).unwrap();
+            let data = self.0.borrow();
+            let src = data
+                .get(at..at + buf.len())
+                .ok_or(io::Error::from(io::ErrorKind::UnexpectedEof))?;
+            buf.copy_from_slice(src);
+            Ok(())
+        }
+
+        fn write_at(&self, at: u64, data: &[u8]) -> Result<(), Error> {
+            let at = usize::try_from(at).unwrap();
+            let mut file = self.0.borrow_mut();
+            if file.len() < at + data.len() {
+                file.resize(at + data.len(), 0);
+            }
+            file[at..at + data.len()].copy_from_slice(data);
+            Ok(())
+        }
+
+        fn truncate(&self, len: u64) -> Result<(), Error> {
+            self.0.borrow_mut().resize(usize::try_from(len).unwrap(), 0);
+            Ok(())
+        }
+
+        fn flush(&self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    fn assert_same(a: &WebFs, b: &WebFs) {
+        let all = crate::TagPredicate::And(Vec::new());
+        let ids = a.search_tags(all.clone()).unwrap();
+        assert_eq!(ids, b.search_tags(all).unwrap());
+        for id in ids {
+            assert_eq!(a.get_data(id).unwrap(), b.get_data(id).unwrap());
+            assert_eq!(a.get_tags(id).unwrap(), b.get_tags(id).unwrap());
+            assert_eq!(a.get_metadata(id).unwrap(), b.get_metadata(id).unwrap());
+        }
+        assert_eq!(a.last_id().unwrap(), b.last_id().unwrap());
+        assert_eq!(a.config().unwrap(), b.config().unwrap());
+    }
+
+    #[test]
+    fn test_web_fs() {
+        let storage = Memory::default();
+        let fs = WebFs::load(Box::new(storage.clone())).unwrap();
+        let a = fs.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = fs.add_file(&[2], [Tag::named("b")]).unwrap();
+        fs.edit_file(a, Some(&[3]), Some([Tag::named("c")]))
+            .unwrap();
+        fs.add_tags(b, [Tag::named("d")]).unwrap();
+        fs.set_config(b"config").unwrap();
+        let c = fs.add_file(&[4], []).unwrap();
+        fs.remove_file(c).unwrap();
+        // Changes in a failed transaction aren't kept
+        fs.transaction(|fs| {
+            fs.remove_file(a)?;
+            fs.remove_file(c)
+        })
+        .unwrap_err();
+
+        let loaded = WebFs::load(Box::new(storage.clone())).unwrap();
+        assert_same(&fs, &loaded);
+        assert!(loaded.get_info(c).is_err());
+        drop(loaded);
+
+        // Compacting keeps everything, including which IDs were handed out
+        let size = fs.log_size();
+        fs.compact().unwrap();
+        assert!(fs.log_size() < size);
+        let loaded = WebFs::load(Box::new(storage.clone())).unwrap();
+        assert_same(&fs, &loaded);
+        assert!(loaded.add_file(&[], []).unwrap() > c);
+
+        // A record cut short is dropped
+        storage.0.borrow_mut().extend([20, 0, 0, 0, ADD]);
+        let loaded = WebFs::load(Box::new(storage.clone())).unwrap();
+        assert_eq!(loaded.get_data(a).unwrap(), [3]);
+        assert_eq!(storage.size().unwrap(), loaded.log_size());
+    }
+}
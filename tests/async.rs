@@ -0,0 +1,34 @@
+#![cfg(feature = "async")]
+
+use tbf::{AsyncDirectoryBackedFs, AsyncFileSystem, InMemoryFs, Tag};
+use tempdir::TempDir;
+
+#[tokio::test]
+async fn imfs_async() {
+    let ifs = InMemoryFs::new();
+
+    let id = AsyncFileSystem::add_file(&ifs, &[0, 1, 2], [Tag::named("a")])
+        .await
+        .unwrap();
+
+    let found = AsyncFileSystem::search_tags(&ifs, Tag::named("a"))
+        .await
+        .unwrap();
+    assert_eq!(found, [id]);
+}
+
+#[tokio::test]
+async fn dfs_async() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = AsyncDirectoryBackedFs::new(test_dir.path()).await.unwrap();
+
+    let id = dfs.add_file(&[0, 1, 2], [Tag::named("a")]).await.unwrap();
+    dfs.edit_file(id, Some(&[3, 4]), None::<[Tag; 0]>)
+        .await
+        .unwrap();
+
+    let info = dfs.get_info(id).await.unwrap();
+    assert_eq!(info.data(), &[3, 4]);
+    assert_eq!(dfs.search_tags(Tag::named("a")).await.unwrap(), [id]);
+}
@@ -1,24 +1,1640 @@
 use std::collections::BTreeSet;
+use tbf::{
+    DirectoryBackedFs, FileSystemRead, FileSystemWrite, Group, InferredTags, ProvidedTags,
+    StreamRead, StreamWrite, Tag,
+};
 use tempdir::TempDir;
-use tbf::{DirectoryBackedFs, FileSystem, Group, Tag};
+
+#[cfg(feature = "testing")]
+#[test]
+fn conformance() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+    let mut count = 0;
+    tbf::testkit::assert_filesystem_conformance(|| {
+        count += 1;
+        DirectoryBackedFs::new(test_dir.path().join(count.to_string())).unwrap()
+    });
+}
 
 #[test]
 fn rw_file() {
-    let test_dir = TempDir::new("test_dfs")
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let id = dfs
+        .add_file(
+            &[0, 1, 2, 3],
+            [Tag::named("a"), Tag::new(Group::custom("g"), "b")],
+        )
+        .unwrap();
+
+    let info = dfs.get_info(id).unwrap();
+
+    assert_eq!(info.data(), &[0, 1, 2, 3]);
+    assert_eq!(
+        info.tags(),
+        &BTreeSet::from([Tag::named("a"), Tag::new(Group::custom("g"), "b"),])
+    );
+}
+
+#[test]
+fn provided_tags() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    dfs.register_provider(Group::custom("len"), |data: &[u8]| {
+        vec![Tag::named(data.len().to_string())]
+    })
+    .unwrap();
+
+    let short = dfs.add_file(&[0, 1], [Tag::named("a")]).unwrap();
+    let long = dfs.add_file(&[0, 1, 2, 3], [Tag::named("a")]).unwrap();
+
+    assert_eq!(
+        dfs.search_tags(Tag::new(Group::custom("len"), "2"))
+            .unwrap(),
+        [short]
+    );
+    assert_eq!(
+        dfs.search_tags(Tag::new(Group::custom("len"), "4"))
+            .unwrap(),
+        [long]
+    );
+}
+
+#[test]
+fn last_id() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    assert_eq!(dfs.last_id().unwrap(), None);
+
+    dfs.add_file(&[0], []).unwrap();
+    dfs.add_file(&[1], []).unwrap();
+    let third = dfs.add_file(&[2], []).unwrap();
+    dfs.remove_file(third).unwrap();
+
+    assert_eq!(dfs.last_id().unwrap(), Some(third));
+}
+
+#[test]
+fn ids_after() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    dfs.add_file(&[0], []).unwrap();
+    let cursor = dfs.add_file(&[1], []).unwrap();
+
+    let third = dfs.add_file(&[2], []).unwrap();
+    let fourth = dfs.add_file(&[3], []).unwrap();
+
+    assert_eq!(dfs.ids_after(cursor).unwrap(), [third, fourth]);
+}
+
+#[test]
+fn stream_file() {
+    use std::io::{Read, Write};
+    use tbf::FileWriter;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let mut writer = dfs.create_file([Tag::named("a")]).unwrap();
+    writer.write_all(&[0, 1, 2]).unwrap();
+    assert!(dfs.search_tags(Tag::named("a")).unwrap().is_empty());
+
+    writer.write_all(&[3, 4]).unwrap();
+    let id = writer.commit().unwrap();
+
+    let mut data = Vec::new();
+    dfs.read_file(id).unwrap().read_to_end(&mut data).unwrap();
+    assert_eq!(data, [0, 1, 2, 3, 4]);
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [id]);
+
+    // A handle dropped while panicking adds nothing
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut writer = dfs.create_file([Tag::named("b")]).unwrap();
+        writer.write_all(&[5]).unwrap();
+        panic!("failed part way through");
+    }));
+    assert!(res.is_err());
+    assert!(dfs.search_tags(Tag::named("b")).unwrap().is_empty());
+
+    // Its unfinished data is cleaned up when the store is next opened
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.check_and_repair(false).unwrap(), []);
+}
+
+#[test]
+fn index_rebuild() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let (first, second) = {
+        let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+        let first = dfs.add_file(&[0], [Tag::named("a")]).unwrap();
+        let second = dfs
+            .add_file(&[1], [Tag::named("a"), Tag::named("b")])
+            .unwrap();
+        (first, second)
+    };
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [first, second]);
+    drop(dfs);
+
+    std::fs::write(test_dir.path().join("tbf.idx"), b"garbage").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("b")).unwrap(), [second]);
+}
+
+#[test]
+fn index_log() {
+    use std::io::Write;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+    let (index, log) = (
+        test_dir.path().join("tbf.idx"),
+        test_dir.path().join("tbf.idx.log"),
+    );
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    let saved = std::fs::read(&index).unwrap();
+    let first = dfs.add_file(&[0], [Tag::named("a")]).unwrap();
+    let second = dfs.add_file(&[1], [Tag::named("b")]).unwrap();
+    dfs.add_tags(first, [Tag::named("c")]).unwrap();
+    dfs.remove_file(second).unwrap();
+
+    // Changes are logged rather than saving the whole index
+    assert_eq!(std::fs::read(&index).unwrap(), saved);
+    let logged = std::fs::metadata(&log).unwrap().len();
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("c")).unwrap(), [first]);
+    assert!(dfs.search_tags(Tag::named("b")).unwrap().is_empty());
+
+    // A log from before the index was last saved in full is ignored
+    let stale = std::fs::read(&log).unwrap();
+    dfs.remove_tags(first, [Tag::named("c")]).unwrap();
+    dfs.rebuild_index().unwrap();
+    assert!(!log.exists());
+    drop(dfs);
+    std::fs::write(&log, &stale).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [first]);
+    assert!(dfs.search_tags(Tag::named("c")).unwrap().is_empty());
+
+    // Once enough changes build up, the index is saved in full and the log started again
+    let saved = std::fs::read(&index).unwrap();
+    for i in 0..1100 {
+        if i % 2 == 0 {
+            dfs.add_tags(first, [Tag::named("t")]).unwrap();
+        } else {
+            dfs.remove_tags(first, [Tag::named("t")]).unwrap();
+        }
+    }
+    assert_ne!(std::fs::read(&index).unwrap(), saved);
+    assert!(std::fs::metadata(&log).unwrap().len() < logged * 100);
+    drop(dfs);
+
+    // A torn log is rebuilt from the tag files
+    let mut torn = std::fs::OpenOptions::new().append(true).open(&log).unwrap();
+    torn.write_all(&[1, 0, 1]).unwrap();
+    drop(torn);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert!(dfs.search_tags(Tag::named("t")).unwrap().is_empty());
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [first]);
+}
+
+#[test]
+fn search_iter() {
+    use tbf::TagPredicate;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs.add_file(&[0], [Tag::named("a")]).unwrap();
+    let second = dfs.add_file(&[1], [Tag::named("b")]).unwrap();
+    let third = dfs.add_file(&[2], [Tag::named("a")]).unwrap();
+    let fourth = dfs.add_file(&[3], [Tag::named("a")]).unwrap();
+
+    let page = dfs
+        .search_tags_iter(Tag::named("a"))
+        .take(2)
+        .collect::<Result<Vec<_>, _>>()
+        .unwrap();
+    assert_eq!(page, [first, third]);
+
+    // Files removed after the index is first checked aren't returned
+    let mut iter = dfs.search_tags_iter(TagPredicate::or([
+        TagPredicate::not(TagPredicate::name_glob("?")),
+        TagPredicate::and([
+            TagPredicate::tag(Tag::named("a")),
+            TagPredicate::not(TagPredicate::tag(Tag::named("b"))),
+        ]),
+    ]));
+    assert_eq!(iter.next().unwrap().unwrap(), first);
+    dfs.remove_file(third).unwrap();
+    assert_eq!(iter.next().unwrap().unwrap(), fourth);
+    assert!(iter.next().is_none());
+    assert_eq!(
+        dfs.search_tags(TagPredicate::not(Tag::named("a"))).unwrap(),
+        [second]
+    );
+}
+
+#[test]
+fn transaction() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let edited = dfs.add_file(&[0], [Tag::named("a")]).unwrap();
+    let removed = dfs.add_file(&[1], [Tag::named("b")]).unwrap();
+
+    let res = dfs.transaction(|fs| {
+        fs.add_file(&[2], [Tag::named("a")])?;
+        fs.edit_file(edited, Some(&[3]), Some([Tag::named("c")]))?;
+        fs.remove_file(removed)?;
+        fs.remove_file(removed)
+    });
+    assert!(res.is_err());
+
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [edited]);
+    assert_eq!(dfs.get_info(edited).unwrap().data(), &[0]);
+    assert_eq!(dfs.get_info(removed).unwrap().data(), &[1]);
+
+    let added = dfs
+        .transaction(|fs| fs.add_file(&[2], [Tag::named("b")]))
         .unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("b")).unwrap(), [removed, added]);
+    assert!(!test_dir.path().join("tbf.journal").exists());
 
+    // A panic part way through rolls back too, leaving no journal behind
+    let res = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        dfs.transaction(|fs| -> Result<(), tbf::DfsError> {
+            fs.remove_file(edited)?;
+            panic!("failed part way through")
+        })
+    }));
+    assert!(res.is_err());
+    assert_eq!(dfs.get_info(edited).unwrap().data(), &[0]);
+    assert!(!test_dir.path().join("tbf.journal").exists());
+}
+
+#[test]
+fn rename() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs
+        .add_file(&[0], [Tag::named("a"), Tag::new(Group::custom("g"), "b")])
+        .unwrap();
+    let second = dfs
+        .add_file(&[1], [Tag::new(Group::custom("g"), "c")])
+        .unwrap();
+
+    dfs.rename_tag(&Tag::named("a"), Tag::named("z")).unwrap();
+    dfs.rename_group(&Group::custom("g"), Group::custom("h"))
+        .unwrap();
+
+    assert_eq!(dfs.search_tags(Tag::named("z")).unwrap(), [first]);
+    assert_eq!(
+        dfs.get_info(second).unwrap().tags(),
+        &BTreeSet::from([Tag::new(Group::custom("h"), "c"),])
+    );
+    assert!(dfs
+        .search_tags(tbf::TagPredicate::group(Group::custom("g")))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn bulk_tags() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs.add_file(&[0], [Tag::named("a")]).unwrap();
+    let second = dfs
+        .add_file(&[1], [Tag::named("a"), Tag::named("b")])
+        .unwrap();
+    dfs.add_file(&[2], [Tag::named("c")]).unwrap();
+
+    assert_eq!(
+        dfs.add_tags_matching(Tag::named("a"), [Tag::named("d")])
+            .unwrap(),
+        2
+    );
+    dfs.remove_tags(second, [Tag::named("a")]).unwrap();
+
+    assert_eq!(dfs.search_tags(Tag::named("d")).unwrap(), [first, second]);
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [first]);
+}
+
+#[test]
+fn reuse_ids() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs.add_file(&[0], []).unwrap();
+    dfs.remove_file(first).unwrap();
+    let second = dfs.add_file(&[1], []).unwrap();
+    assert_ne!(first, second);
+
+    drop(dfs);
     let dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .reuse_ids(true);
+
+    let third = dfs.add_file(&[2], []).unwrap();
+    assert_eq!(third, first);
+    assert_eq!(dfs.get_info(third).unwrap().data(), &[2]);
+
+    let fourth = dfs.add_file(&[3], []).unwrap();
+    assert!(fourth > second);
+}
+
+#[test]
+fn metadata() {
+    use std::io::Write;
+    use tbf::FileWriter;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let id = dfs.add_file(&[0, 1, 2], []).unwrap();
+    let before = dfs.get_metadata(id).unwrap();
+    assert_eq!(before.size(), 3);
+
+    dfs.edit_file(id, Some(&[3, 4]), None::<[Tag; 0]>).unwrap();
+    let after = dfs.get_metadata(id).unwrap();
+    assert_eq!(after.size(), 2);
+    assert!(after.modified() >= before.modified());
+    assert_ne!(after.hash(), before.hash());
+    // The creation time is recorded when the file is added, and kept through edits
+    assert_eq!(after.created(), before.created());
+
+    dfs.write_at(id, 2, &[5]).unwrap();
+    let patched = dfs.get_metadata(id).unwrap();
+    assert_eq!(patched.size(), 3);
+    assert_eq!(patched.created(), before.created());
+    let mut writer = dfs.create_file([]).unwrap();
+    writer.write_all(&[3, 4, 5]).unwrap();
+    let written = writer.commit().unwrap();
+    assert_eq!(dfs.get_metadata(written).unwrap().hash(), patched.hash());
+
+    // Stores from before content was recorded are filled in when opened
+    drop(dfs);
+    let sums = test_dir
+        .path()
+        .join(format!("{:016X}.sum", id.into_u64_unchecked()));
+    let old = std::fs::read(&sums).unwrap();
+    std::fs::write(&sums, &old[..64]).unwrap();
+    let mut version = std::fs::read(test_dir.path().join("tbf.fmt")).unwrap();
+    version[4..8].copy_from_slice(&2u32.to_le_bytes());
+    std::fs::write(test_dir.path().join("tbf.fmt"), version).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(std::fs::read(&sums).unwrap().len(), old.len());
+    assert_eq!(dfs.get_metadata(id).unwrap().hash(), patched.hash());
+}
+
+#[test]
+fn inferred_tags() {
+    use std::io::Write;
+    use tbf::inference::SizeInferrer;
+    use tbf::FileWriter;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    dfs.register_inferrer(SizeInferrer).unwrap();
+
+    let first = dfs.add_file(&[0, 1, 2], []).unwrap();
+
+    let mut writer = dfs.create_file([]).unwrap();
+    writer.write_all(&[]).unwrap();
+    let second = writer.commit().unwrap();
+
+    assert_eq!(
+        dfs.search_tags(Tag::new(Group::custom("size"), "small"))
+            .unwrap(),
+        [first]
+    );
+    assert_eq!(
+        dfs.search_tags(Tag::new(Group::custom("size"), "empty"))
+            .unwrap(),
+        [second]
+    );
+}
+
+#[test]
+fn tag_values() {
+    use tbf::TagPredicate;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs
+        .add_file(&[0], [Tag::named("rating").with_value(2)])
+        .unwrap();
+    let second = dfs
+        .add_file(
+            &[1],
+            [
+                Tag::new(Group::custom("g"), "rating").with_value(4.5),
+                Tag::named("a"),
+            ],
+        )
         .unwrap();
 
-    let id = dfs.add_file(&[0, 1, 2, 3], [Tag::named("a"), Tag::new(Group::custom("g"), "b")])
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    assert_eq!(
+        dfs.get_info(second).unwrap().tags(),
+        &BTreeSet::from([
+            Tag::new(Group::custom("g"), "rating").with_value(4.5),
+            Tag::named("a")
+        ])
+    );
+    assert_eq!(
+        dfs.search_tags(TagPredicate::value_range(Group::Default, "rating", 1, 3))
+            .unwrap(),
+        [first]
+    );
+
+    dfs.rebuild_index().unwrap();
+    assert_eq!(
+        dfs.search_tags(TagPredicate::value_gt(Group::custom("g"), "rating", 4))
+            .unwrap(),
+        [second]
+    );
+}
+
+#[test]
+fn search_sorted() {
+    use tbf::{SearchOptions, SortBy, TagPredicate};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs
+        .add_file(&[0, 0, 0], [Tag::named("a").with_value("y")])
+        .unwrap();
+    let second = dfs
+        .add_file(&[0], [Tag::named("a").with_value("x")])
         .unwrap();
+    let third = dfs.add_file(&[0, 0], []).unwrap();
+    let all = TagPredicate::And(Vec::new());
+
+    let options = SearchOptions::new().sort_by(SortBy::Size).descending();
+    assert_eq!(
+        dfs.search_tags_with(all.clone(), &options).unwrap(),
+        [first, third, second]
+    );
+
+    let options = SearchOptions::new().sort_by(SortBy::value(Group::Default, "a"));
+    assert_eq!(
+        dfs.search_tags_with(all, &options).unwrap(),
+        [second, first, third]
+    );
+}
+
+#[test]
+fn search_paged() {
+    use tbf::SearchOptions;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
 
-    let info = dfs.get_info(id)
+    let ids = (0..5)
+        .map(|i| dfs.add_file(&[i], [Tag::named("a")]).unwrap())
+        .collect::<Vec<_>>();
+
+    let page = dfs
+        .search_tags_with(Tag::named("a"), &SearchOptions::new().limit(2))
         .unwrap();
+    assert_eq!(page, ids[..2]);
 
-    assert_eq!(info.data(), &[0, 1, 2, 3]);
-    assert_eq!(info.tags(), &BTreeSet::from([
-        Tag::named("a"),
-        Tag::new(Group::custom("g"), "b"),
-    ]));
+    let options = SearchOptions::new().after(page[1]).limit(2);
+    assert_eq!(
+        dfs.search_tags_with(Tag::named("a"), &options).unwrap(),
+        ids[2..4]
+    );
+}
+
+#[test]
+fn dedup() {
+    use tbf::DedupFs;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DedupFs::new(DirectoryBackedFs::new(test_dir.path()).unwrap()).unwrap();
+
+    let first = dfs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+    let second = dfs.add_file(&[1, 2, 3], [Tag::named("b")]).unwrap();
+
+    drop(dfs);
+    let dfs = DedupFs::new(DirectoryBackedFs::new(test_dir.path()).unwrap()).unwrap();
+
+    assert_eq!(dfs.find_duplicates().unwrap(), [vec![first, second]]);
+    assert_eq!(dfs.get_info(second).unwrap().data(), &[1, 2, 3]);
+    assert_eq!(dfs.search_tags(Tag::named("b")).unwrap(), [second]);
+}
+
+#[test]
+fn duplicates() {
+    use std::io::Write;
+    use tbf::FileWriter;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    let first = dfs.add_file(&[1, 2, 3], [Tag::named("a")]).unwrap();
+    dfs.add_file(&[4], []).unwrap();
+    let mut writer = dfs.create_file([]).unwrap();
+    writer.write_all(&[1, 2]).unwrap();
+    let second = writer.commit().unwrap();
+    dfs.write_at(second, 2, &[3]).unwrap();
+    assert_eq!(dfs.find_duplicates().unwrap(), [vec![first, second]]);
+
+    // Hashes are kept with the checksums, so are still there once reopened
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.find_duplicates().unwrap(), [vec![first, second]]);
+
+    // Groups are found from the hashes in the index, without reading any checksums
+    drop(dfs);
+    for id in [first, second] {
+        let name = format!("{:016X}.sum", id.into_u64_unchecked());
+        std::fs::remove_file(test_dir.path().join(name)).unwrap();
+    }
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.find_duplicates().unwrap(), [vec![first, second]]);
+    dfs.edit_file(first, Some(&[5]), None::<[Tag; 0]>).unwrap();
+    assert!(dfs.find_duplicates().unwrap().is_empty());
+}
+
+#[test]
+fn find_by_hash() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    let first = dfs.add_file(&[1, 2, 3], []).unwrap();
+    let second = dfs.add_file(&[1, 2, 3], []).unwrap();
+    let hash = *dfs.get_metadata(second).unwrap().hash();
+    assert_eq!(dfs.find_by_hash(&hash).unwrap(), Some(first));
+
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    dfs.remove_file(first).unwrap();
+    assert_eq!(dfs.find_by_hash(&hash).unwrap(), Some(second));
+    dfs.truncate(second, 1).unwrap();
+    assert_eq!(dfs.find_by_hash(&hash).unwrap(), None);
+    let truncated = *dfs.get_metadata(second).unwrap().hash();
+    assert_eq!(dfs.find_by_hash(&truncated).unwrap(), Some(second));
+
+    // Hashes are kept in the index, logged and saved in full alike, so finding files by them
+    // doesn't read their checksums
+    let third = dfs.add_file(&[1, 2, 3], []).unwrap();
+    drop(dfs);
+    let sums = |id: tbf::FileId| {
+        test_dir
+            .path()
+            .join(format!("{:016X}.sum", id.into_u64_unchecked()))
+    };
+    let kept = [second, third].map(|id| std::fs::read(sums(id)).unwrap());
+    for id in [second, third] {
+        std::fs::remove_file(sums(id)).unwrap();
+    }
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.find_by_hash(&hash).unwrap(), Some(third));
+    assert_eq!(dfs.find_by_hash(&truncated).unwrap(), Some(second));
+    for (id, kept) in [second, third].iter().zip(kept) {
+        std::fs::write(sums(*id), kept).unwrap();
+    }
+    dfs.rebuild_index().unwrap();
+    drop(dfs);
+    std::fs::remove_file(sums(third)).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.find_by_hash(&hash).unwrap(), Some(third));
+}
+
+#[test]
+#[cfg(feature = "compress")]
+fn duplicates_compressed() {
+    use tbf::Compression;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    // Data is compared as it was given, not as it's stored
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    let plain = dfs.add_file(&[7; 4096], []).unwrap();
+    let dfs = dfs.compression(Compression::Lz4);
+    let compressed = dfs.add_file(&[7; 4096], []).unwrap();
+    assert_eq!(dfs.find_duplicates().unwrap(), [vec![plain, compressed]]);
+}
+
+#[test]
+fn special() {
+    use tbf::SpecialFile;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs.add_file(&[], [Tag::named("a")]).unwrap();
+    dfs.add_file(&[], [Tag::new(Group::custom("g"), "b")])
+        .unwrap();
+    dfs.remove_file(first).unwrap();
+    dfs.set_config(b"key=value").unwrap();
+
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let manifest = dfs.special(SpecialFile::Manifest).unwrap();
+    assert_eq!(manifest.data(), b"0000000000000101\n");
+    let registry = dfs.special(SpecialFile::TagRegistry).unwrap();
+    assert_eq!(registry.data(), b"g:b\n");
+    let trash = dfs.special(SpecialFile::Trash).unwrap();
+    assert_eq!(trash.data(), b"0000000000000100\n");
+    let config = dfs.special(SpecialFile::Config).unwrap();
+    assert_eq!(config.data(), b"key=value");
+}
+
+#[test]
+fn versions() {
+    use tbf::Retention;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .retention(Retention::Last(2));
+
+    let id = dfs.add_file(&[0], []).unwrap();
+    for data in [[1], [2], [3]] {
+        dfs.edit_file(id, Some(&data), None::<[Tag; 0]>).unwrap();
+    }
+
+    assert_eq!(dfs.list_versions(id).unwrap(), [2, 3]);
+    assert_eq!(&*dfs.get_version(id, 3).unwrap(), &[2]);
+    assert!(dfs.get_version(id, 1).is_err());
+
+    dfs.revert(id, 2).unwrap();
+    assert_eq!(dfs.get_info(id).unwrap().data(), &[1]);
+    assert_eq!(dfs.list_versions(id).unwrap(), [3, 4]);
+
+    dfs.remove_file(id).unwrap();
+    assert!(std::fs::read_dir(test_dir.path()).unwrap().all(|item| !item
+        .unwrap()
+        .file_name()
+        .to_string_lossy()
+        .contains(".v")));
+}
+
+#[test]
+fn compact() {
+    use tbf::Retention;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .retention(Retention::All);
+
+    let id = dfs.add_file(&[0; 4], []).unwrap();
+    let removed = dfs.add_file(&[0; 4], []).unwrap();
+    for data in [[1; 4], [2; 4], [3; 4]] {
+        dfs.edit_file(id, Some(&data), None::<[Tag; 0]>).unwrap();
+    }
+    assert_eq!(dfs.compact().unwrap(), 0);
+
+    // Versions left behind by a removed file, such as by a crash part way through removing it
+    let name = |id: tbf::FileId, ext: &str| {
+        test_dir
+            .path()
+            .join(format!("{:016X}.{ext}", id.into_u64_unchecked()))
+    };
+    dfs.remove_file(removed).unwrap();
+    std::fs::write(name(removed, "v1.dat"), [0; 4]).unwrap();
+
+    let dfs = dfs.retention(Retention::Last(1));
+    assert!(dfs.compact().unwrap() > 0);
+    assert_eq!(dfs.list_versions(id).unwrap(), [3]);
+    assert_eq!(&*dfs.get_version(id, 3).unwrap(), &[2; 4]);
+    assert_eq!(dfs.get_info(id).unwrap().data(), &[3; 4]);
+    assert!(!name(removed, "v1.dat").exists());
+    assert_eq!(dfs.compact().unwrap(), 0);
+
+    // Turning retention off doesn't lose the versions already kept
+    let dfs = dfs.retention(Retention::Off);
+    assert_eq!(dfs.compact().unwrap(), 0);
+    assert_eq!(dfs.list_versions(id).unwrap(), [3]);
+}
+
+#[test]
+fn import_tree() {
+    use tbf::tree::ImportOptions;
+
+    let source = TempDir::new("test_dfs").unwrap();
+    std::fs::create_dir(source.path().join("docs")).unwrap();
+    std::fs::write(source.path().join("docs/a.txt"), [1, 2, 3]).unwrap();
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let imported = dfs
+        .import_tree(source.path(), &ImportOptions::new())
+        .unwrap();
+    assert_eq!(imported.len(), 1);
+
+    let ids = dfs
+        .search_tags(Tag::new(Group::custom("dir"), "docs"))
+        .unwrap();
+    assert_eq!(ids, [imported[0].1]);
+    assert_eq!(dfs.get_info(ids[0]).unwrap().data(), &[1, 2, 3]);
+}
+
+#[test]
+fn export_tree() {
+    use tbf::tree::Layout;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    dfs.add_file(&[1, 2], [Tag::new(Group::custom("g"), "a")])
+        .unwrap();
+    dfs.add_file(&[3], [Tag::named("b")]).unwrap();
+
+    let dest = TempDir::new("test_dfs").unwrap();
+    let count = dfs
+        .export_tree(
+            Tag::new(Group::custom("g"), "a"),
+            dest.path(),
+            Layout::Hierarchy,
+        )
+        .unwrap();
+
+    assert_eq!(count, 1);
+    assert_eq!(
+        std::fs::read(dest.path().join("g/a/0000000000000100")).unwrap(),
+        [1, 2]
+    );
+    assert!(!dest.path().join("_").exists());
+}
+
+#[test]
+fn add_file_with_id() {
+    use tbf::{DfsError, FileId};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let chosen = FileId::from_u64_unchecked(300);
+    dfs.add_file_with_id(chosen, &[1, 2], [Tag::named("a")])
+        .unwrap();
+    assert!(matches!(
+        dfs.add_file_with_id(chosen, &[], []),
+        Err(DfsError::AlreadyExists(_))
+    ));
+    // The ID counter can't move past the highest ID
+    let highest = FileId::from_u64_unchecked(u64::MAX);
+    assert!(matches!(
+        dfs.add_file_with_id(highest, &[], []),
+        Err(DfsError::AlreadyExists(_))
+    ));
+
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    assert_eq!(dfs.get_info(chosen).unwrap().data(), &[1, 2]);
+    assert_eq!(
+        dfs.add_file(&[], []).unwrap(),
+        FileId::from_u64_unchecked(301)
+    );
+}
+
+#[test]
+fn tag_counts() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let group = Group::custom("g");
+    dfs.add_file(&[], [Tag::named("a"), Tag::new(group.clone(), "b")])
+        .unwrap();
+    dfs.add_file(&[], [Tag::named("a")]).unwrap();
+
+    let counts = dfs.tag_counts(Tag::named("a")).unwrap();
+    assert_eq!(counts[&Tag::named("a")], 2);
+    assert_eq!(counts[&Tag::new(group.clone(), "b")], 1);
+
+    assert_eq!(
+        dfs.list_groups().unwrap(),
+        BTreeSet::from([Group::Default, group.clone()])
+    );
+    assert_eq!(
+        dfs.list_tags(&group).unwrap(),
+        BTreeSet::from([Tag::new(group.clone(), "b")])
+    );
+
+    dfs.register_provider(group.clone(), |data: &[u8]| {
+        vec![Tag::named(format!("len{}", data.len()))]
+    })
+    .unwrap();
+    assert_eq!(
+        dfs.list_tags(&group).unwrap(),
+        BTreeSet::from([Tag::new(group.clone(), "b"), Tag::new(group, "len0")])
+    );
+}
+
+#[test]
+fn events() {
+    use std::io::Write;
+    use tbf::{Event, FileWriter};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    let events = dfs.subscribe().unwrap();
+
+    let first = dfs.add_file(&[], [Tag::named("a")]).unwrap();
+    let mut writer = dfs.create_file([Tag::named("b")]).unwrap();
+    writer.write_all(&[1]).unwrap();
+    let second = writer.commit().unwrap();
+    dfs.add_tags(first, [Tag::named("c")]).unwrap();
+    dfs.edit_file(second, Some(&[2]), None::<[Tag; 0]>).unwrap();
+    dfs.remove_file(first).unwrap();
+
+    assert_eq!(
+        events.try_iter().collect::<Vec<_>>(),
+        [
+            Event::FileAdded(first),
+            Event::FileAdded(second),
+            Event::TagsChanged(first),
+            Event::FileEdited(second),
+            Event::FileRemoved(first),
+        ]
+    );
+}
+
+#[test]
+fn concurrent_edits() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let id = dfs.add_file(&[], []).unwrap();
+    std::thread::scope(|scope| {
+        for thread in 0..8 {
+            let dfs = &dfs;
+            scope.spawn(move || {
+                for num in 0..10 {
+                    dfs.add_tags(id, [Tag::named(format!("t{thread}-{num}"))])
+                        .unwrap();
+                }
+            });
+        }
+    });
+
+    assert_eq!(dfs.get_info(id).unwrap().tags().len(), 80);
+}
+
+#[test]
+fn atomic_writes() {
+    use std::io::Write;
+    use tbf::FileWriter;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let id = dfs.add_file(&[1], [Tag::named("a")]).unwrap();
+    dfs.edit_file(id, Some(&[2]), Some([Tag::named("b")]))
+        .unwrap();
+    let mut writer = dfs.create_file([]).unwrap();
+    writer.write_all(&[3]).unwrap();
+    let second = writer.commit().unwrap();
+    assert_eq!(dfs.get_info(second).unwrap().data(), &[3]);
+
+    let has_temp = || {
+        std::fs::read_dir(test_dir.path())
+            .unwrap()
+            .any(|item| item.unwrap().path().extension().unwrap_or_default() == "tmp")
+    };
+    assert!(!has_temp());
+
+    // Left behind by a write interrupted by a crash
+    std::fs::write(test_dir.path().join("0000000000000100.dat.tmp"), [4]).unwrap();
+
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    assert!(!has_temp());
+    assert_eq!(dfs.get_info(id).unwrap().data(), &[2]);
+}
+
+#[test]
+fn state_recovery() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs.add_file(&[1], []).unwrap();
+    let second = dfs.add_file(&[2], []).unwrap();
+
+    // A lost state file
+    drop(dfs);
+    std::fs::remove_file(test_dir.path().join("tbf.dat")).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let third = dfs.add_file(&[3], []).unwrap();
+    assert!(third > second);
+
+    // A state file cut short part way through writing
+    drop(dfs);
+    std::fs::write(test_dir.path().join("tbf.dat"), [0, 0]).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let fourth = dfs.add_file(&[4], []).unwrap();
+    assert!(fourth > third);
+    assert_eq!(dfs.get_info(first).unwrap().data(), &[1]);
+    assert_eq!(dfs.get_info(third).unwrap().data(), &[3]);
+}
+
+#[test]
+fn get_tags_data() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let id = dfs.add_file(&[0, 1, 2], [Tag::named("a")]).unwrap();
+    assert_eq!(dfs.get_tags(id).unwrap(), BTreeSet::from([Tag::named("a")]));
+    assert_eq!(dfs.get_data(id).unwrap(), [0, 1, 2]);
+
+    dfs.register_provider(Group::custom("len"), |data: &[u8]| {
+        vec![Tag::named(data.len().to_string())]
+    })
+    .unwrap();
+    assert!(dfs
+        .get_tags(id)
+        .unwrap()
+        .contains(&Tag::new(Group::custom("len"), "3")));
+
+    dfs.remove_file(id).unwrap();
+    assert!(dfs.get_tags(id).is_err());
+    assert!(dfs.get_data(id).is_err());
+}
+
+#[test]
+#[cfg(feature = "mmap")]
+fn get_data_mmap() {
+    use std::io::Write;
+    use tbf::FileWriter;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let id = dfs.add_file(&[0, 1, 2, 3], []).unwrap();
+    let map = dfs.get_data_mmap(id).unwrap();
+    assert_eq!(&map[..], &[0, 1, 2, 3]);
+
+    // The map keeps the data it was made with
+    dfs.edit_file(id, Some(&[4]), None::<[Tag; 0]>).unwrap();
+    assert_eq!(&map[..], &[0, 1, 2, 3]);
+    assert_eq!(&dfs.get_data_mmap(id).unwrap()[..], &[4]);
+
+    let empty = dfs.add_file(&[], []).unwrap();
+    assert!(dfs.get_data_mmap(empty).unwrap().is_empty());
+
+    let mut writer = dfs.create_file([]).unwrap();
+    writer.write_all(&[5, 6]).unwrap();
+    let streamed = writer.commit().unwrap();
+    assert_eq!(&dfs.get_data_mmap(streamed).unwrap()[..], &[5, 6]);
+}
+
+#[test]
+fn stored_header() {
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    // Plain data which looks like a header
+    let data = b"\x89TBF\x01rest";
+    let id = dfs.add_file(data, []).unwrap();
+    assert_eq!(dfs.get_info(id).unwrap().data(), data);
+    assert_eq!(dfs.get_metadata(id).unwrap().size(), data.len() as u64);
+    assert_eq!(dfs.read_range(id, 0..5).unwrap(), b"\x89TBF\x01");
+    assert_eq!(dfs.read_range(id, 7..20).unwrap(), b"st");
+}
+
+#[test]
+fn partial_writes() {
+    use tbf::Retention;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .retention(Retention::All)
+        .verify_reads(true);
+    // Patches are made to a copy of the whole file
+    assert!(!dfs.supports_partial_writes());
+
+    let id = dfs.add_file(&[0, 1, 2, 3], [Tag::named("a")]).unwrap();
+    dfs.write_at(id, 2, &[4, 5, 6]).unwrap();
+    assert_eq!(dfs.get_data(id).unwrap(), [0, 1, 4, 5, 6]);
+    dfs.truncate(id, 3).unwrap();
+    assert_eq!(dfs.get_data(id).unwrap(), [0, 1, 4]);
+    assert_eq!(&*dfs.get_version(id, 1).unwrap(), &[0, 1, 2, 3]);
+    assert_eq!(&*dfs.get_version(id, 2).unwrap(), &[0, 1, 4, 5, 6]);
+    assert_eq!(dfs.verify_all().unwrap(), []);
+
+    // Writing the magic bytes at the start of plain data makes it need a header
+    dfs.write_at(id, 0, b"\x89TBF\x01").unwrap();
+    assert_eq!(dfs.get_data(id).unwrap(), b"\x89TBF\x01");
+    dfs.write_at(id, 5, &[7]).unwrap();
+    assert_eq!(dfs.get_data(id).unwrap(), b"\x89TBF\x01\x07");
+    dfs.truncate(id, 2).unwrap();
+    assert_eq!(dfs.get_data(id).unwrap(), b"\x89T");
+    assert_eq!(dfs.get_metadata(id).unwrap().size(), 2);
+    assert_eq!(dfs.verify_all().unwrap(), []);
+}
+
+#[test]
+#[cfg(feature = "compress")]
+fn compression() {
+    use std::io::Read;
+    use tbf::Compression;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .compression(Compression::Lz4);
+
+    let data = [7; 4096];
+    let id = dfs.add_file(&data, [Tag::named("a")]).unwrap();
+    let stored = test_dir
+        .path()
+        .join(format!("{:016X}.dat", id.into_u64_unchecked()));
+    assert!(std::fs::metadata(&stored).unwrap().len() < data.len() as u64);
+
+    assert_eq!(dfs.get_info(id).unwrap().data(), &data);
+    assert_eq!(dfs.get_metadata(id).unwrap().size(), data.len() as u64);
+    let mut read = Vec::new();
+    dfs.read_file(id).unwrap().read_to_end(&mut read).unwrap();
+    assert_eq!(read, data);
+    assert_eq!(dfs.read_range(id, 4000..4200).unwrap(), [7; 96]);
+
+    // Data which doesn't shrink is stored as-is
+    let small = dfs.add_file(&[1, 2, 3], []).unwrap();
+    assert_eq!(dfs.get_info(small).unwrap().data(), &[1, 2, 3]);
+
+    dfs.recompress_file(id, Compression::None).unwrap();
+    assert_eq!(std::fs::metadata(&stored).unwrap().len(), data.len() as u64);
+    assert_eq!(dfs.get_info(id).unwrap().data(), &data);
+
+    dfs.recompress().unwrap();
+    assert!(std::fs::metadata(&stored).unwrap().len() < data.len() as u64);
+    assert_eq!(dfs.get_info(id).unwrap().data(), &data);
+
+    // Compressed data is rewritten in full when changed in part
+    dfs.write_at(id, 4095, &[8, 9]).unwrap();
+    assert_eq!(dfs.get_data(id).unwrap().len(), 4097);
+    assert_eq!(dfs.get_data(id).unwrap()[4094..], [7, 8, 9]);
+    dfs.truncate(id, 1).unwrap();
+    assert_eq!(dfs.get_data(id).unwrap(), [7]);
+}
+
+#[test]
+fn verify() {
+    use std::io::Write;
+    use tbf::{CorruptionReport, DfsError, FileWriter};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .verify_reads(true);
+
+    let first = dfs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+    let second = dfs.add_file(&[3], [Tag::named("b")]).unwrap();
+    dfs.edit_file(second, Some(&[4]), Some([Tag::named("c")]))
+        .unwrap();
+    let mut writer = dfs.create_file([]).unwrap();
+    writer.write_all(&[5]).unwrap();
+    writer.flush().unwrap();
+    writer.write_all(&[6]).unwrap();
+    let third = writer.commit().unwrap();
+    let _ = dfs.transaction(|dfs| {
+        dfs.edit_file(first, Some(&[0]), None::<[Tag; 0]>)?;
+        Err::<(), _>(DfsError::FileNotFound(first))
+    });
+    assert_eq!(dfs.verify_all().unwrap(), []);
+    assert_eq!(dfs.get_info(third).unwrap().data(), &[5, 6]);
+
+    let name = |id: tbf::FileId, ext: &str| {
+        test_dir
+            .path()
+            .join(format!("{:016X}.{ext}", id.into_u64_unchecked()))
+    };
+    std::fs::write(name(first, "dat"), [1, 3]).unwrap();
+    std::fs::write(name(second, "tag"), [1]).unwrap();
+    std::fs::remove_file(name(third, "dat")).unwrap();
+
+    assert_eq!(
+        dfs.verify_all().unwrap(),
+        [
+            CorruptionReport::DataChanged(first),
+            CorruptionReport::TagsChanged(second),
+            CorruptionReport::DataMissing(third),
+        ]
+    );
+    assert!(matches!(
+        dfs.get_info(first),
+        Err(DfsError::Corrupted(id)) if id == first
+    ));
+}
+
+#[test]
+fn check_and_repair() {
+    use tbf::{CorruptionReport, FileId};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let first = dfs.add_file(&[1], [Tag::named("a")]).unwrap();
+    let second = dfs
+        .add_file(&[2], [Tag::named("a"), Tag::named("b")])
+        .unwrap();
+    let third = dfs.add_file(&[3], [Tag::named("c")]).unwrap();
+    let high = FileId::from_u64_unchecked(0x1000);
+    let leftover = FileId::from_u64_unchecked(0x2000);
+    assert_eq!(dfs.check_and_repair(false).unwrap(), []);
+
+    let name = |id: FileId, ext: &str| {
+        test_dir
+            .path()
+            .join(format!("{:016X}.{ext}", id.into_u64_unchecked()))
+    };
+    std::fs::remove_file(name(first, "tag")).unwrap();
+    let tags = std::fs::read(name(second, "tag")).unwrap();
+    std::fs::write(name(second, "tag"), &tags[..tags.len() - 1]).unwrap();
+    std::fs::remove_file(name(third, "dat")).unwrap();
+    std::fs::write(name(high, "dat"), [4]).unwrap();
+    std::fs::write(name(leftover, "sum"), [0; 64]).unwrap();
+
+    let report = [
+        CorruptionReport::TagsMissing(first),
+        CorruptionReport::TagsUnreadable(second),
+        CorruptionReport::DataMissing(third),
+        CorruptionReport::IdNotAllocated(high),
+        CorruptionReport::TagsMissing(high),
+        CorruptionReport::IdNotAllocated(leftover),
+        CorruptionReport::Leftover(leftover),
+    ];
+    assert_eq!(dfs.check_and_repair(false).unwrap(), report);
+    assert_eq!(dfs.check_and_repair(true).unwrap(), report);
+    assert_eq!(dfs.check_and_repair(false).unwrap(), []);
+
+    assert_eq!(dfs.get_info(first).unwrap().data(), &[1]);
+    assert_eq!(dfs.get_tags(second).unwrap(), [Tag::named("a")].into());
+    assert!(dfs.get_info(third).is_err());
+    assert_eq!(dfs.get_info(high).unwrap().data(), &[4]);
+    assert!(dfs.search_tags(Tag::named("c")).unwrap().is_empty());
+    assert!(dfs.add_file(&[5], []).unwrap() > high);
+    assert!(!name(leftover, "sum").exists());
+}
+
+#[test]
+fn format_version() {
+    use tbf::DfsError;
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+
+    let version = test_dir.path().join("tbf.fmt");
+    let mut expected = b"TBFS".to_vec();
+    expected.extend(DirectoryBackedFs::FORMAT_VERSION.to_le_bytes());
+    // Not sharded, and not part way through resharding
+    expected.extend([0, 0]);
+    assert_eq!(std::fs::read(&version).unwrap(), expected);
+
+    let id = dfs.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+    let name = format!("{:016X}", id.into_u64_unchecked());
+    for ext in ["dat", "tag", "sum"] {
+        assert!(test_dir.path().join(format!("{name}.{ext}")).is_file());
+    }
+
+    // Files round-trip through their names when reopened, even without the index
+    drop(dfs);
+    std::fs::remove_file(test_dir.path().join("tbf.idx")).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [id]);
+    assert_eq!(dfs.get_info(id).unwrap().data(), &[1, 2]);
+
+    // A store from before the version file, without checksums, is migrated
+    drop(dfs);
+    std::fs::remove_file(&version).unwrap();
+    std::fs::remove_file(test_dir.path().join(format!("{name}.sum"))).unwrap();
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(std::fs::read(&version).unwrap(), expected);
+    assert!(test_dir.path().join(format!("{name}.sum")).is_file());
+    assert_eq!(dfs.verify_all().unwrap(), []);
+
+    // A store from a newer version is refused
+    drop(dfs);
+    let mut newer = b"TBFS".to_vec();
+    newer.extend((DirectoryBackedFs::FORMAT_VERSION + 1).to_le_bytes());
+    std::fs::write(&version, newer).unwrap();
+    assert!(matches!(
+        DirectoryBackedFs::new(test_dir.path()),
+        Err(DfsError::UnsupportedFormat(v)) if v == DirectoryBackedFs::FORMAT_VERSION + 1
+    ));
+}
+
+#[test]
+fn sharding() {
+    use std::io::Write;
+    use tbf::{DfsError, FileId, FileWriter, Retention, Sharding};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let mut dfs = DirectoryBackedFs::new(test_dir.path())
+        .unwrap()
+        .retention(Retention::All);
+
+    let first = dfs.add_file(&[1], [Tag::named("a")]).unwrap();
+    dfs.edit_file(first, Some(&[2]), None::<[Tag; 0]>).unwrap();
+    let mut writer = dfs.create_file([Tag::named("b")]).unwrap();
+    writer.write_all(&[3]).unwrap();
+    let second = writer.commit().unwrap();
+
+    let path = |id: FileId, shards: &[&str], ext: &str| {
+        let mut out = test_dir.path().to_owned();
+        out.extend(shards);
+        out.join(format!("{:016X}.{ext}", id.into_u64_unchecked()))
+    };
+    assert!(path(first, &[], "dat").is_file());
+
+    dfs.reshard(Sharding::Nested(2)).unwrap();
+    for ext in ["dat", "tag", "sum", "v1.dat"] {
+        assert!(path(first, &["00", "01"], ext).is_file());
+        assert!(!path(first, &[], ext).exists());
+    }
+    assert!(path(second, &["01", "01"], "dat").is_file());
+    assert_eq!(dfs.get_info(first).unwrap().data(), &[2]);
+    assert_eq!(&*dfs.get_version(first, 1).unwrap(), &[1]);
+    assert_eq!(dfs.search_tags(Tag::named("b")).unwrap(), [second]);
+    assert_eq!(dfs.check_and_repair(false).unwrap(), []);
+
+    let third = dfs.add_file(&[4], []).unwrap();
+    assert!(path(third, &["02", "01"], "dat").is_file());
+    let _ = dfs.transaction(|dfs| {
+        dfs.remove_file(third)?;
+        dfs.add_file(&[5], [])?;
+        Err::<(), _>(DfsError::FileNotFound(third))
+    });
+    assert_eq!(dfs.get_info(third).unwrap().data(), &[4]);
+    assert_eq!(dfs.verify_all().unwrap(), []);
+
+    // The sharding is kept when reopened, and an interrupted reshard is finished
+    drop(dfs);
+    let fmt = test_dir.path().join("tbf.fmt");
+    let mut format = std::fs::read(&fmt).unwrap();
+    *format.last_mut().unwrap() = 1;
+    std::fs::write(&fmt, format).unwrap();
+    std::fs::rename(path(third, &["02", "01"], "dat"), path(third, &[], "dat")).unwrap();
+    let mut dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert!(path(third, &["02", "01"], "dat").is_file());
+    assert_eq!(dfs.get_info(third).unwrap().data(), &[4]);
+    let fourth = dfs.add_file(&[6], []).unwrap();
+    assert!(fourth > third);
+    assert!(path(
+        fourth,
+        &[&format!("{:02X}", fourth.into_u64_unchecked() & 0xFF), "01"],
+        "dat"
+    )
+    .is_file());
+
+    dfs.reshard(Sharding::Flat).unwrap();
+    assert!(path(first, &[], "v1.dat").is_file());
+    assert!(!test_dir.path().join("00").exists());
+    assert_eq!(dfs.get_info(fourth).unwrap().data(), &[6]);
+}
+
+#[test]
+fn builder() {
+    use tbf::{DfsConfigError, DfsError, IndexMode, Sharding, SyncPolicy};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let store = test_dir.path().join("store");
+    assert!(matches!(
+        DirectoryBackedFs::builder().read_only(true).open(&store),
+        Err(DfsError::Config(DfsConfigError::MissingDirectory(_)))
+    ));
+    assert!(!store.exists());
+    assert!(matches!(
+        DirectoryBackedFs::builder()
+            .sharding(Sharding::Nested(9))
+            .open(&store),
+        Err(DfsError::Config(DfsConfigError::TooManyShardLevels(9)))
+    ));
+
+    let dfs = DirectoryBackedFs::builder()
+        .sync(SyncPolicy::Always)
+        .open(&store)
+        .unwrap();
+    let id = dfs.add_file(&[1], [Tag::named("a")]).unwrap();
+    drop(dfs);
+
+    // Opening with sharding reshards an existing flat store
+    let dfs = DirectoryBackedFs::builder()
+        .sharding(Sharding::Nested(1))
+        .index(IndexMode::Rebuild)
+        .open(&store)
+        .unwrap();
+    assert_eq!(dfs.get_info(id).unwrap().data(), &[1]);
+    assert!(store
+        .join(format!("{:02X}", id.into_u64_unchecked() & 0xFF))
+        .is_dir());
+    drop(dfs);
+
+    // Read-only stores can't be changed, or resharded when opened
+    let files = || std::fs::read_dir(&store).unwrap().count();
+    let before = files();
+    std::fs::remove_file(store.join("tbf.idx")).unwrap();
+    let dfs = DirectoryBackedFs::builder()
+        .read_only(true)
+        .open(&store)
+        .unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [id]);
+    assert!(matches!(dfs.add_file(&[2], []), Err(DfsError::ReadOnly)));
+    assert!(matches!(
+        dfs.add_tags(id, [Tag::named("b")]),
+        Err(DfsError::ReadOnly)
+    ));
+    assert!(matches!(dfs.remove_file(id), Err(DfsError::ReadOnly)));
+    assert!(matches!(dfs.set_config(&[]), Err(DfsError::ReadOnly)));
+    assert!(matches!(
+        dfs.transaction(|_| Ok(())),
+        Err(DfsError::ReadOnly)
+    ));
+    assert_eq!(files(), before - 1);
+    assert!(matches!(
+        DirectoryBackedFs::builder()
+            .read_only(true)
+            .sharding(Sharding::Flat)
+            .open(&store),
+        Err(DfsError::Config(DfsConfigError::ShardingMismatch { .. }))
+    ));
+
+    assert!(matches!(
+        DirectoryBackedFs::new(store.join("tbf.fmt")),
+        Err(DfsError::Config(DfsConfigError::NotADirectory(_)))
+    ));
+}
+
+#[test]
+fn sync_policies() {
+    use std::io::Write;
+    use tbf::{DfsError, FileWriter, SyncPolicy};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    for (n, &sync) in [SyncPolicy::Relaxed, SyncPolicy::OnBatch, SyncPolicy::Always]
+        .iter()
+        .enumerate()
+    {
+        let store = test_dir.path().join(n.to_string());
+        let dfs = DirectoryBackedFs::builder()
+            .sync(sync)
+            .open(&store)
+            .unwrap();
+
+        let kept = dfs.add_file(&[1], [Tag::named("a")]).unwrap();
+        let (added, streamed) = dfs
+            .transaction(|dfs| {
+                let added = dfs.add_file(&[2], [Tag::named("b")])?;
+                dfs.write_at(kept, 1, &[3])?;
+                let mut writer = dfs.create_file([Tag::named("c")])?;
+                writer.write_all(&[4]).unwrap();
+                let streamed = writer.commit()?;
+                dfs.set_config(b"config")?;
+                Ok::<_, DfsError>((added, streamed))
+            })
+            .unwrap();
+        let _ = dfs.transaction(|dfs| {
+            dfs.remove_file(added)?;
+            Err::<(), _>(DfsError::FileNotFound(added))
+        });
+        drop(dfs);
+
+        let dfs = DirectoryBackedFs::builder()
+            .sync(sync)
+            .open(&store)
+            .unwrap();
+        assert_eq!(dfs.get_data(kept).unwrap(), [1, 3]);
+        assert_eq!(dfs.get_data(added).unwrap(), [2]);
+        assert_eq!(dfs.get_data(streamed).unwrap(), [4]);
+        assert_eq!(dfs.config().unwrap(), b"config");
+        assert_eq!(dfs.verify_all().unwrap(), []);
+        assert!(!store.join("tbf.journal").exists());
+    }
+}
+
+#[test]
+fn audit_log() {
+    use std::time::UNIX_EPOCH;
+    use tbf::{AuditAction, AuditedFs};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = AuditedFs::new(DirectoryBackedFs::new(test_dir.path()).unwrap(), "alice");
+    let id = dfs.add_file(&[1], [Tag::named("a")]).unwrap();
+    dfs.remove_file(id).unwrap();
+
+    drop(dfs);
+    let dfs = AuditedFs::new(DirectoryBackedFs::new(test_dir.path()).unwrap(), "bob");
+
+    let log = dfs.audit_log(UNIX_EPOCH).unwrap().collect::<Vec<_>>();
+    assert_eq!(log.len(), 2);
+    assert!(log
+        .iter()
+        .all(|entry| entry.actor() == "alice" && entry.id() == id));
+    assert_eq!(log[1].action(), &AuditAction::Removed);
+}
+
+#[test]
+fn snapshots() {
+    use tbf::{DfsError, Retention, Sharding};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::builder()
+        .sharding(Sharding::Nested(1))
+        .open(test_dir.path())
+        .unwrap()
+        .retention(Retention::Last(1));
+
+    let kept = dfs.add_file(&[0], [Tag::named("a")]).unwrap();
+    dfs.set_config(b"old").unwrap();
+    dfs.snapshot("first").unwrap();
+
+    dfs.edit_file(kept, Some(&[1]), Some([Tag::named("b")]))
+        .unwrap();
+    let added = dfs.add_file(&[2], []).unwrap();
+    dfs.set_config(b"new").unwrap();
+    dfs.snapshot("second").unwrap();
+    assert_eq!(dfs.list_snapshots().unwrap(), ["first", "second"]);
+
+    dfs.restore("first").unwrap();
+    assert_eq!(dfs.search_tags(Tag::named("a")).unwrap(), [kept]);
+    assert_eq!(dfs.get_info(kept).unwrap().data(), &[0]);
+    assert!(dfs.list_versions(kept).unwrap().is_empty());
+    assert!(dfs.get_info(added).is_err());
+    assert_eq!(dfs.config().unwrap(), b"old");
+    assert!(dfs.verify_all().unwrap().is_empty());
+    // IDs handed out since aren't reused
+    assert!(dfs.add_file(&[], []).unwrap() > added);
+
+    // Restoring survives reopening the store
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    dfs.restore("second").unwrap();
+    assert_eq!(dfs.get_info(added).unwrap().data(), &[2]);
+    assert_eq!(dfs.get_info(kept).unwrap().data(), &[1]);
+    assert_eq!(dfs.list_versions(kept).unwrap(), [1]);
+    assert_eq!(dfs.config().unwrap(), b"new");
+
+    assert!(dfs.remove_snapshot("first").unwrap());
+    assert!(!dfs.remove_snapshot("first").unwrap());
+    assert!(matches!(
+        dfs.restore("first"),
+        Err(DfsError::SnapshotNotFound(label)) if label == "first"
+    ));
+    assert!(dfs.snapshot("../escape").is_err());
+}
+
+#[test]
+fn change_feed() {
+    use tbf::{DfsError, Event};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.last_seq().unwrap(), 0);
+    let id = dfs.add_file(&[1], []).unwrap();
+    dfs.edit_file(id, Some(&[2]), Some([Tag::named("a")]))
+        .unwrap();
+
+    // The feed survives reopening the store, carrying on from the last change
+    drop(dfs);
+    let dfs = DirectoryBackedFs::new(test_dir.path()).unwrap();
+    assert_eq!(dfs.last_seq().unwrap(), 3);
+    dfs.remove_file(id).unwrap();
+
+    let changes = dfs.changes_since(1).unwrap();
+    assert_eq!(
+        changes
+            .iter()
+            .map(|c| (c.seq(), c.event()))
+            .collect::<Vec<_>>(),
+        [
+            (2, Event::FileEdited(id)),
+            (3, Event::TagsChanged(id)),
+            (4, Event::FileRemoved(id)),
+        ]
+    );
+    assert_eq!(
+        dfs.changes_since(0).unwrap()[0].event(),
+        Event::FileAdded(id)
+    );
+    assert!(dfs.changes_since(4).unwrap().is_empty());
+
+    // Changes in a transaction are only recorded, and sent to subscribers, once it commits
+    let events = dfs.subscribe().unwrap();
+    let _ = dfs.transaction(|dfs| {
+        dfs.add_file(&[3], [])?;
+        Err::<(), _>(DfsError::FileNotFound(id))
+    });
+    assert_eq!(dfs.last_seq().unwrap(), 4);
+    assert!(events.try_recv().is_err());
+    let added = dfs
+        .transaction(|dfs| {
+            let added = dfs.add_file(&[4], [])?;
+            assert_eq!(dfs.last_seq()?, 4);
+            Ok::<_, DfsError>(added)
+        })
+        .unwrap();
+    assert_eq!(
+        dfs.changes_since(4).unwrap()[0].event(),
+        Event::FileAdded(added)
+    );
+    assert_eq!(events.try_recv().unwrap(), Event::FileAdded(added));
+}
+
+#[test]
+fn error_messages() {
+    use tbf::{DfsError, FileId, InMemoryFs, ReadOnly, Sharding};
+
+    let test_dir = TempDir::new("test_dfs").unwrap();
+    let dfs = DirectoryBackedFs::builder()
+        .sharding(Sharding::Nested(2))
+        .open(test_dir.path())
+        .unwrap();
+    let missing = FileId::from_u64_unchecked(1000);
+
+    let err: Box<dyn std::error::Error + Send + Sync> =
+        Box::new(dfs.get_info(missing).unwrap_err());
+    assert_eq!(err.to_string(), "file 1000 not found");
+    assert!(err.source().is_none());
+
+    drop(dfs);
+    let err = DirectoryBackedFs::builder()
+        .read_only(true)
+        .sharding(Sharding::Flat)
+        .open(test_dir.path())
+        .map(drop)
+        .unwrap_err();
+    assert!(matches!(err, DfsError::Config(_)));
+    assert_eq!(
+        err.to_string(),
+        "store is sharded nested 2 levels deep, not flat, and can't be resharded read-only"
+    );
+
+    // Wrappers show the message of the error they wrap
+    let fs = ReadOnly::new(InMemoryFs::new());
+    assert_eq!(
+        fs.get_data(missing).unwrap_err().to_string(),
+        "file 1000 not found"
+    );
+    assert_eq!(
+        fs.add_file(&[], []).unwrap_err().to_string(),
+        "filesystem is read-only"
+    );
 }
@@ -0,0 +1,127 @@
+#![cfg(all(feature = "grpc", feature = "imfs"))]
+
+use std::sync::Arc;
+
+use tbf::grpc::{Error, GrpcFs, GrpcService};
+use tbf::{
+    AsyncFileSystem, ErrorKind, FileId, FileSystemRead, InMemoryFs, SearchOptions, SortBy, Tag,
+    TagPredicate,
+};
+use tokio::net::TcpListener;
+use tonic::transport::server::TcpIncoming;
+use tonic::transport::Server;
+
+async fn serve() -> (Arc<InMemoryFs>, GrpcFs) {
+    let fs = Arc::new(InMemoryFs::new());
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let incoming = TcpIncoming::from_listener(listener, true, None).unwrap();
+    tokio::spawn(
+        Server::builder()
+            .add_service(GrpcService::from_arc(Arc::clone(&fs)))
+            .serve_with_incoming(incoming),
+    );
+    let client = GrpcFs::connect(format!("http://{addr}")).await.unwrap();
+    (fs, client)
+}
+
+#[tokio::test]
+async fn grpc_round_trip() {
+    let (inner, fs) = serve().await;
+
+    let tags = [
+        Tag::new("g", "rating").with_value(4),
+        Tag::named("photo.png").with_value(1.5),
+    ];
+    let id = fs.add_file(&[1, 2, 3], tags.clone()).await.unwrap();
+    assert_eq!(
+        FileSystemRead::get_info(&*inner, id).unwrap().tags(),
+        &tags.clone().into()
+    );
+    let info = fs.get_info(id).await.unwrap();
+    assert_eq!(info.data(), &[1, 2, 3]);
+    assert_eq!(info.tags(), &tags.into());
+
+    fs.edit_file(id, Some(&[4]), None::<[Tag; 0]>)
+        .await
+        .unwrap();
+    fs.edit_file(id, None, Some([Tag::named("b")]))
+        .await
+        .unwrap();
+    let info = FileSystemRead::get_info(&*inner, id).unwrap();
+    assert_eq!(info.data(), &[4]);
+    assert_eq!(fs.get_tags(id).await.unwrap(), [Tag::named("b")].into());
+
+    fs.remove_file(id).await.unwrap();
+    let err = fs.get_info(id).await.unwrap_err();
+    assert!(matches!(err, Error::FileNotFound(missing) if missing == id));
+    assert!(matches!(
+        tbf::Error::generic_kind(&err),
+        ErrorKind::FileNotFound(_)
+    ));
+}
+
+#[tokio::test]
+async fn grpc_search() {
+    let (_, fs) = serve().await;
+
+    let a = fs
+        .add_file(&[1], [Tag::named("a.png").with_value(3)])
+        .await
+        .unwrap();
+    let b = fs
+        .add_file(&[2, 2], [Tag::named("b.png").with_value(1)])
+        .await
+        .unwrap();
+    let c = fs.add_file(&[3], [Tag::named("c.txt")]).await.unwrap();
+
+    let all = TagPredicate::And(Vec::new());
+    assert_eq!(fs.search_tags(all.clone()).await.unwrap(), [a, b, c]);
+    assert_eq!(
+        fs.search_tags(TagPredicate::NameGlob("*.png".to_owned()))
+            .await
+            .unwrap(),
+        [a, b]
+    );
+
+    let options = SearchOptions::new().after(a).limit(1);
+    assert_eq!(
+        fs.search_tags_with(all.clone(), &options).await.unwrap(),
+        [b]
+    );
+    let options = SearchOptions::new().sort_by(SortBy::Size).descending();
+    assert_eq!(
+        fs.search_tags_with(all.clone(), &options).await.unwrap()[0],
+        b
+    );
+
+    let mut stream = fs.search_stream(all, &SearchOptions::new()).await.unwrap();
+    assert_eq!(stream.next_id().await.unwrap().unwrap(), a);
+    assert_eq!(stream.next_id().await.unwrap().unwrap(), b);
+    assert_eq!(stream.next_id().await.unwrap().unwrap(), c);
+    assert!(stream.next_id().await.is_none());
+}
+
+#[tokio::test]
+async fn grpc_streaming_data() {
+    let (_, fs) = serve().await;
+
+    // Large enough to be sent in several chunks
+    let data = (0..200_000).map(|i| i as u8).collect::<Vec<_>>();
+    let id = fs.add_file(&data, []).await.unwrap();
+
+    let mut stream = fs.read_file(id).await.unwrap();
+    let first = stream.next_chunk().await.unwrap().unwrap();
+    assert!(first.len() < data.len());
+    let mut read = first;
+    read.extend(stream.read_to_end().await.unwrap());
+    assert_eq!(read, data);
+
+    let err = fs.add_file_with_id(id, &[1], []).await.unwrap_err();
+    assert!(matches!(err, Error::AlreadyExists(_)));
+    let missing = FileId::from_u64_unchecked(1000);
+    let Err(err) = fs.read_file(missing).await else {
+        panic!("Reading a missing file succeeded");
+    };
+    assert!(matches!(err, Error::FileNotFound(id) if id == missing));
+}
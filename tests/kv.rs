@@ -0,0 +1,229 @@
+#![cfg(feature = "kv")]
+
+use std::collections::BTreeSet;
+use std::io::Write;
+
+use tbf::{
+    FileId, FileSystemRead, FileSystemWrite, FileWriter, Group, KvError, KvFs, ProvidedTags,
+    StreamWrite, Tag, TagPredicate,
+};
+use tempdir::TempDir;
+
+#[cfg(feature = "testing")]
+#[test]
+fn conformance() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+    let mut count = 0;
+    tbf::testkit::assert_filesystem_conformance(|| {
+        count += 1;
+        KvFs::open(test_dir.path().join(format!("{count}.redb"))).unwrap()
+    });
+}
+
+#[test]
+fn rw_file() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+
+    let kv = KvFs::open(test_dir.path().join("tbf.redb")).unwrap();
+
+    let id = kv
+        .add_file(
+            &[0, 1, 2, 3],
+            [Tag::named("a"), Tag::new(Group::custom("g"), "b")],
+        )
+        .unwrap();
+
+    let info = kv.get_info(id).unwrap();
+    assert_eq!(info.data(), &[0, 1, 2, 3]);
+    assert_eq!(
+        info.tags(),
+        &BTreeSet::from([Tag::named("a"), Tag::new(Group::custom("g"), "b")])
+    );
+    assert_eq!(kv.get_metadata(id).unwrap().size(), 4);
+
+    kv.edit_file(id, Some(&[4]), Some([Tag::named("c")]))
+        .unwrap();
+    assert_eq!(kv.get_data(id).unwrap(), [4]);
+    assert_eq!(kv.get_tags(id).unwrap(), BTreeSet::from([Tag::named("c")]));
+
+    kv.remove_file(id).unwrap();
+    assert!(matches!(kv.get_info(id), Err(KvError::FileNotFound(_))));
+    assert!(matches!(kv.add_file_with_id(id, &[], []), Ok(())));
+    assert!(matches!(
+        kv.add_file_with_id(id, &[], []),
+        Err(KvError::AlreadyExists(_))
+    ));
+    let highest = FileId::from_u64_unchecked(u64::MAX);
+    assert!(matches!(
+        kv.add_file_with_id(highest, &[], []),
+        Err(KvError::AlreadyExists(_))
+    ));
+    assert!(kv.ids_after(highest).unwrap().is_empty());
+}
+
+#[test]
+fn reopen() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+    let path = test_dir.path().join("tbf.redb");
+
+    let (a, b) = {
+        let kv = KvFs::open(&path).unwrap();
+        let a = kv.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = kv.add_file(&[2], [Tag::named("b")]).unwrap();
+        kv.remove_file(b).unwrap();
+        kv.set_config(b"config").unwrap();
+        (a, b)
+    };
+
+    let kv = KvFs::open(&path).unwrap();
+    assert_eq!(kv.get_data(a).unwrap(), [1]);
+    assert_eq!(kv.search_tags(Tag::named("a")).unwrap(), [a]);
+    assert_eq!(kv.config().unwrap(), b"config");
+    assert_eq!(kv.last_id().unwrap(), Some(b));
+
+    // Removed IDs aren't handed out again, even after reopening
+    let c = kv.add_file(&[3], []).unwrap();
+    assert!(c > b);
+}
+
+#[test]
+fn indexed_search() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+
+    let kv = KvFs::open(test_dir.path().join("tbf.redb")).unwrap();
+
+    let rating = |val: i64| Tag::new(Group::custom("rating"), "stars").with_value(val);
+    let a = kv.add_file(&[], [Tag::named("photo"), rating(2)]).unwrap();
+    let b = kv.add_file(&[], [Tag::named("photo"), rating(5)]).unwrap();
+    let c = kv
+        .add_file(
+            &[],
+            [
+                Tag::named("text"),
+                Tag::new(Group::custom("ratings"), "stars"),
+            ],
+        )
+        .unwrap();
+
+    assert_eq!(kv.search_tags(Tag::named("photo")).unwrap(), [a, b]);
+    assert_eq!(kv.search_tags(rating(5)).unwrap(), [b]);
+    assert_eq!(
+        kv.search_tags(TagPredicate::group(Group::custom("rating")))
+            .unwrap(),
+        [a, b]
+    );
+    assert_eq!(
+        kv.search_tags(TagPredicate::value_gt(Group::custom("rating"), "stars", 3))
+            .unwrap(),
+        [b]
+    );
+    assert_eq!(
+        kv.search_tags(TagPredicate::or([
+            TagPredicate::tag(Tag::named("text")),
+            TagPredicate::value_lt(Group::custom("rating"), "stars", 3),
+        ]))
+        .unwrap(),
+        [a, c]
+    );
+    // Searches the index can't narrow check every file
+    assert_eq!(
+        kv.search_tags(TagPredicate::and([
+            TagPredicate::name("stars"),
+            TagPredicate::not(TagPredicate::tag(Tag::named("photo"))),
+        ]))
+        .unwrap(),
+        [c]
+    );
+    // Negated tags are taken out of the files found
+    assert_eq!(
+        kv.search_tags(TagPredicate::and([
+            TagPredicate::tag(Tag::named("photo")),
+            TagPredicate::not(rating(5)),
+        ]))
+        .unwrap(),
+        [a]
+    );
+    assert_eq!(
+        kv.search_tags(TagPredicate::not(TagPredicate::or([
+            TagPredicate::not(Tag::named("photo")),
+            TagPredicate::value_gt(Group::custom("rating"), "stars", 3),
+        ])))
+        .unwrap(),
+        [a]
+    );
+
+    // Changed tags are reflected in the index
+    kv.remove_tags(a, [Tag::named("photo")]).unwrap();
+    kv.add_tags(c, [Tag::named("photo")]).unwrap();
+    assert_eq!(kv.search_tags(Tag::named("photo")).unwrap(), [b, c]);
+    assert_eq!(
+        kv.tag_counts(Tag::named("photo"))
+            .unwrap()
+            .get(&Tag::named("photo")),
+        Some(&2)
+    );
+}
+
+#[test]
+fn provided_tags() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+
+    let kv = KvFs::open(test_dir.path().join("tbf.redb")).unwrap();
+
+    kv.register_provider(Group::custom("len"), |data: &[u8]| {
+        vec![Tag::named(data.len().to_string())]
+    })
+    .unwrap();
+
+    let short = kv.add_file(&[0, 1], [Tag::named("a")]).unwrap();
+    let long = kv.add_file(&[0, 1, 2, 3], [Tag::named("a")]).unwrap();
+
+    assert_eq!(
+        kv.search_tags(Tag::new(Group::custom("len"), "2")).unwrap(),
+        [short]
+    );
+    assert_eq!(
+        kv.search_tags(TagPredicate::group(Group::custom("len")))
+            .unwrap(),
+        [short, long]
+    );
+}
+
+#[test]
+fn transaction() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+
+    let kv = KvFs::open(test_dir.path().join("tbf.redb")).unwrap();
+    let kept = kv.add_file(&[1], [Tag::named("a")]).unwrap();
+
+    let res: Result<(), KvError> = kv.transaction(|kv| {
+        let id = kv.add_file(&[2], [Tag::named("a")])?;
+        assert_eq!(kv.search_tags(Tag::named("a"))?, [kept, id]);
+        kv.edit_file(kept, Some(&[3]), None::<[Tag; 0]>)?;
+        kv.remove_file(FileId::from_u64_unchecked(1000))
+    });
+    assert!(matches!(res, Err(KvError::FileNotFound(_))));
+    assert_eq!(kv.search_tags(Tag::named("a")).unwrap(), [kept]);
+    assert_eq!(kv.get_data(kept).unwrap(), [1]);
+
+    let id = kv
+        .transaction(|kv| kv.add_file(&[4], [Tag::named("b")]))
+        .unwrap();
+    assert_eq!(kv.search_tags(Tag::named("b")).unwrap(), [id]);
+}
+
+#[test]
+fn stream_file() {
+    let test_dir = TempDir::new("test_kv").unwrap();
+
+    let kv = KvFs::open(test_dir.path().join("tbf.redb")).unwrap();
+
+    let mut writer = kv.create_file([Tag::named("a")]).unwrap();
+    writer.write_all(&[1, 2]).unwrap();
+    writer.flush().unwrap();
+    writer.write_all(&[3]).unwrap();
+    let id = writer.commit().unwrap();
+
+    assert_eq!(kv.get_data(id).unwrap(), [1, 2, 3]);
+    assert_eq!(kv.search_tags(Tag::named("a")).unwrap(), [id]);
+}
@@ -0,0 +1,367 @@
+#![cfg(feature = "packed")]
+
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fs::OpenOptions;
+use std::io::{Read, Write};
+use std::panic::AssertUnwindSafe;
+
+use tbf::{
+    Event, FileId, FileSystemRead, FileSystemWrite, FileWriter, Group, PackedError, PackedFs,
+    SpecialFile, StreamRead, StreamWrite, Tag, TagPredicate,
+};
+use tempdir::TempDir;
+
+#[cfg(feature = "testing")]
+#[test]
+fn conformance() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let mut count = 0;
+    tbf::testkit::assert_filesystem_conformance(|| {
+        count += 1;
+        PackedFs::open(test_dir.path().join(format!("{count}.tbfpack"))).unwrap()
+    });
+}
+
+#[test]
+fn rw_file() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+
+    let packed = PackedFs::open(test_dir.path().join("assets.tbfpack")).unwrap();
+
+    let id = packed
+        .add_file(
+            &[0, 1, 2, 3],
+            [Tag::named("a"), Tag::new(Group::custom("g"), "b")],
+        )
+        .unwrap();
+
+    let info = packed.get_info(id).unwrap();
+    assert_eq!(info.data(), &[0, 1, 2, 3]);
+    assert_eq!(
+        info.tags(),
+        &BTreeSet::from([Tag::named("a"), Tag::new(Group::custom("g"), "b")])
+    );
+    assert_eq!(packed.get_metadata(id).unwrap().size(), 4);
+    assert_eq!(packed.read_range(id, 1..3).unwrap(), [1, 2]);
+
+    packed
+        .edit_file(id, Some(&[4]), Some([Tag::named("c")]))
+        .unwrap();
+    assert_eq!(packed.get_data(id).unwrap(), [4]);
+    assert_eq!(
+        packed.get_tags(id).unwrap(),
+        BTreeSet::from([Tag::named("c")])
+    );
+
+    let mut writer = packed.create_file([Tag::named("written")]).unwrap();
+    writer.write_all(&[5, 6]).unwrap();
+    let written = writer.commit().unwrap();
+    let mut data = Vec::new();
+    packed
+        .read_file(written)
+        .unwrap()
+        .read_to_end(&mut data)
+        .unwrap();
+    assert_eq!(data, [5, 6]);
+
+    // Data written after a flush is still committed when the handle is dropped
+    let mut writer = packed.create_file([]).unwrap();
+    writer.write_all(&[7]).unwrap();
+    writer.flush().unwrap();
+    writer.write_all(&[8]).unwrap();
+    drop(writer);
+    let flushed = packed.last_id().unwrap().unwrap();
+    assert_eq!(packed.get_data(flushed).unwrap(), [7, 8]);
+
+    packed.remove_file(id).unwrap();
+    assert!(matches!(
+        packed.get_info(id),
+        Err(PackedError::FileNotFound(_))
+    ));
+    assert!(matches!(packed.add_file_with_id(id, &[], []), Ok(())));
+    assert!(matches!(
+        packed.add_file_with_id(id, &[], []),
+        Err(PackedError::AlreadyExists(_))
+    ));
+    assert!(matches!(
+        packed.add_file_with_id(FileId::from_u64_unchecked(u64::MAX), &[], []),
+        Err(PackedError::AlreadyExists(_))
+    ));
+}
+
+#[test]
+fn reopen() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+
+    let (a, b) = {
+        let packed = PackedFs::open(&path).unwrap();
+        let a = packed.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = packed.add_file(&[2], [Tag::named("b")]).unwrap();
+        packed.edit_file(a, Some(&[3]), None::<[Tag; 0]>).unwrap();
+        packed.remove_file(b).unwrap();
+        packed.set_config(b"config").unwrap();
+        let _ = packed.transaction(|packed| {
+            packed.add_file(&[4], [Tag::named("a")])?;
+            packed.remove_file(b)
+        });
+        (a, b)
+    };
+
+    let packed = PackedFs::open(&path).unwrap();
+    assert_eq!(packed.get_data(a).unwrap(), [3]);
+    assert_eq!(packed.search_tags(Tag::named("a")).unwrap(), [a]);
+    assert_eq!(packed.config().unwrap(), b"config");
+    assert_eq!(packed.last_id().unwrap(), Some(b));
+
+    // Removed IDs aren't handed out again, even after reopening
+    let c = packed.add_file(&[5], []).unwrap();
+    assert!(c > b);
+}
+
+#[test]
+fn read_only() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+
+    let (a, b) = {
+        let packed = PackedFs::open(&path).unwrap();
+        let a = packed
+            .add_file(b"texture", [Tag::named("texture")])
+            .unwrap();
+        let b = packed.add_file(b"sound", [Tag::named("sound")]).unwrap();
+        packed.set_config(b"config").unwrap();
+        (a, b)
+    };
+
+    let packed = PackedFs::open_read_only(&path).unwrap();
+    assert!(packed.is_read_only());
+    assert!(matches!(
+        packed.get_data_ref(a).unwrap(),
+        Cow::Borrowed(b"texture")
+    ));
+    assert_eq!(packed.get_data(b).unwrap(), b"sound");
+    assert_eq!(packed.read_range(a, 3..100).unwrap(), b"ture");
+    assert_eq!(packed.search_tags(Tag::named("sound")).unwrap(), [b]);
+    assert_eq!(packed.config().unwrap(), b"config");
+
+    assert!(matches!(
+        packed.add_file(&[], []),
+        Err(PackedError::ReadOnly)
+    ));
+    assert!(matches!(
+        packed.add_tags(a, [Tag::named("edited")]),
+        Err(PackedError::ReadOnly)
+    ));
+    assert!(matches!(packed.create_file([]), Err(PackedError::ReadOnly)));
+    assert!(matches!(
+        packed.transaction(|packed| packed.remove_file(a)),
+        Err(PackedError::ReadOnly)
+    ));
+    assert_eq!(
+        packed.get_tags(a).unwrap(),
+        BTreeSet::from([Tag::named("texture")])
+    );
+
+    // Containers opened for writing read into new buffers instead
+    let packed = PackedFs::open(&path).unwrap();
+    assert!(matches!(packed.get_data_ref(a).unwrap(), Cow::Owned(_)));
+}
+
+#[test]
+fn compact() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+
+    let mut packed = PackedFs::open(&path).unwrap();
+    let kept = packed.add_file(&[1; 64], [Tag::named("kept")]).unwrap();
+    let removed = packed.add_file(&[2; 64], []).unwrap();
+    packed
+        .edit_file(kept, Some(&[3; 64]), None::<[Tag; 0]>)
+        .unwrap();
+    packed.remove_file(removed).unwrap();
+    packed.set_config(b"config").unwrap();
+
+    let before = packed.container_size().unwrap();
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), before);
+    packed.compact().unwrap();
+    let after = packed.container_size().unwrap();
+    assert!(after < before - 128);
+    assert_eq!(std::fs::metadata(&path).unwrap().len(), after);
+
+    assert_eq!(packed.get_data(kept).unwrap(), [3; 64]);
+    let next = packed.add_file(&[4], []).unwrap();
+    assert!(next > removed);
+    drop(packed);
+
+    let packed = PackedFs::open_read_only(&path).unwrap();
+    assert_eq!(packed.get_data(kept).unwrap(), [3; 64]);
+    assert_eq!(packed.get_data(next).unwrap(), [4]);
+    assert_eq!(packed.config().unwrap(), b"config");
+    assert_eq!(
+        packed.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+        [kept, next]
+    );
+}
+
+#[test]
+fn recover_torn_index() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+
+    let (a, b) = {
+        let packed = PackedFs::open(&path).unwrap();
+        let a = packed.add_file(&[1], [Tag::named("a")]).unwrap();
+        let b = packed.add_file(&[2], [Tag::named("b")]).unwrap();
+        packed.add_tags(b, [Tag::named("c")]).unwrap();
+        (a, b)
+    };
+
+    // A crash part way through writing a record leaves the old index behind it torn
+    let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+    file.write_all(&[1, 200, 0, 0, 0, 0, 0, 0, 0, 7]).unwrap();
+    drop(file);
+
+    let packed = PackedFs::open_read_only(&path).unwrap();
+    assert_eq!(packed.get_data(a).unwrap(), [1]);
+    assert_eq!(packed.search_tags(Tag::named("c")).unwrap(), [b]);
+    drop(packed);
+
+    let packed = PackedFs::open(&path).unwrap();
+    assert_eq!(packed.get_data(b).unwrap(), [2]);
+    assert_eq!(packed.last_id().unwrap(), Some(b));
+    assert_eq!(
+        std::fs::metadata(&path).unwrap().len(),
+        packed.container_size().unwrap()
+    );
+}
+
+#[test]
+fn unfinished_transaction() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+    let crashed = test_dir.path().join("crashed.tbfpack");
+
+    let packed = PackedFs::open(&path).unwrap();
+    let a = packed.add_file(&[1], [Tag::named("a")]).unwrap();
+    packed
+        .transaction(|packed| {
+            packed.add_file(&[2], [Tag::named("b")])?;
+            packed.add_tags(a, [Tag::named("c")])?;
+            // A crash here leaves the transaction's records behind without an index
+            std::fs::copy(&path, &crashed).unwrap();
+            Ok(())
+        })
+        .unwrap();
+    drop(packed);
+
+    let packed = PackedFs::open(&crashed).unwrap();
+    assert_eq!(
+        packed.search_tags(TagPredicate::And(Vec::new())).unwrap(),
+        [a]
+    );
+    assert_eq!(
+        packed.get_tags(a).unwrap(),
+        BTreeSet::from([Tag::named("a")])
+    );
+    assert_eq!(
+        std::fs::metadata(&crashed).unwrap().len(),
+        packed.container_size().unwrap()
+    );
+
+    let packed = PackedFs::open(&path).unwrap();
+    assert_eq!(packed.search_tags(Tag::named("c")).unwrap(), [a]);
+    assert_eq!(packed.search_tags(Tag::named("b")).unwrap().len(), 1);
+}
+
+#[test]
+fn transaction_panic() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+
+    let packed = PackedFs::open(&path).unwrap();
+    let a = packed.add_file(&[1], [Tag::named("a")]).unwrap();
+    let panicked = std::panic::catch_unwind(AssertUnwindSafe(|| {
+        packed.transaction(|packed| -> Result<(), PackedError> {
+            packed.remove_file(a)?;
+            panic!("failed part way through")
+        })
+    }));
+    assert!(panicked.is_err());
+
+    // The change is undone, and later changes are written straight away again
+    assert_eq!(packed.get_data(a).unwrap(), [1]);
+    let b = packed.add_file(&[2], []).unwrap();
+    drop(packed);
+    let packed = PackedFs::open_read_only(&path).unwrap();
+    assert_eq!(packed.get_data(a).unwrap(), [1]);
+    assert_eq!(packed.get_data(b).unwrap(), [2]);
+}
+
+#[test]
+fn transaction_events() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let packed = PackedFs::open(test_dir.path().join("assets.tbfpack")).unwrap();
+    let events = packed.subscribe().unwrap();
+
+    let _ = packed.transaction(|packed| {
+        packed.add_file(&[1], [])?;
+        Err::<(), _>(PackedError::ReadOnly)
+    });
+    assert!(events.try_recv().is_err());
+
+    let id = packed
+        .transaction(|packed| {
+            let id = packed.add_file(&[2], [])?;
+            // Events are held until the transaction commits
+            assert!(events.try_recv().is_err());
+            Ok(id)
+        })
+        .unwrap();
+    assert_eq!(events.try_recv().unwrap(), Event::FileAdded(id));
+    assert!(events.try_recv().is_err());
+}
+
+#[test]
+fn trash() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let packed = PackedFs::open(test_dir.path().join("assets.tbfpack")).unwrap();
+
+    let a = packed.add_file(&[1], []).unwrap();
+    packed.add_file(&[2], []).unwrap();
+    packed.remove_file(a).unwrap();
+    assert_eq!(
+        packed.special(SpecialFile::Trash).unwrap().data(),
+        b"0000000000000100\n"
+    );
+
+    // Unused IDs below a far off chosen ID are only listed up to the limit
+    packed
+        .add_file_with_id(FileId::from_u64_unchecked(1 << 40), &[], [])
+        .unwrap();
+    let trash = packed.special(SpecialFile::Trash).unwrap();
+    assert_eq!(
+        trash.data().split(|&b| b == b'\n').count() - 1,
+        SpecialFile::TRASH_LIMIT
+    );
+    assert!(trash
+        .data()
+        .starts_with(b"0000000000000100\n0000000000000102\n"));
+}
+
+#[test]
+fn not_a_container() {
+    let test_dir = TempDir::new("test_packed").unwrap();
+    let path = test_dir.path().join("assets.tbfpack");
+    std::fs::write(&path, b"definitely not a container").unwrap();
+
+    assert!(matches!(
+        PackedFs::open(&path),
+        Err(PackedError::IoError(_))
+    ));
+    assert!(matches!(
+        PackedFs::open_read_only(&path),
+        Err(PackedError::IoError(_))
+    ));
+}
@@ -0,0 +1,216 @@
+#![cfg(feature = "postgres")]
+
+//! These tests need a database to run against, given by a connection string in
+//! `TBF_POSTGRES_URL`. They're skipped when it isn't set.
+
+use std::collections::BTreeSet;
+
+use postgres::{Client, NoTls};
+use tbf::{
+    DedupePolicy, FileId, FileSystemRead, FileSystemWrite, Group, PostgresError, PostgresFs,
+    SearchOptions, SortBy, Tag, TagPredicate,
+};
+
+/// Connect to the test database, in a new empty schema so tests don't see each other's files
+fn connect(schema: &str) -> Option<PostgresFs> {
+    let url = std::env::var("TBF_POSTGRES_URL").ok()?;
+    let mut client = Client::connect(&url, NoTls).unwrap();
+    client
+        .batch_execute(&format!(
+            "DROP SCHEMA IF EXISTS {schema} CASCADE; CREATE SCHEMA {schema}; \
+             SET search_path TO {schema}"
+        ))
+        .unwrap();
+    Some(PostgresFs::from_client(client).unwrap())
+}
+
+#[test]
+fn pg_rw_file() {
+    let Some(pg) = connect("tbf_test_rw") else {
+        return;
+    };
+
+    let tags = [
+        Tag::named("a"),
+        Tag::new(Group::custom("g"), "b").with_value("val"),
+        Tag::named("c").with_value(1.5),
+    ];
+    let id = pg.add_file(&[0, 1, 2, 3], tags.clone()).unwrap();
+    assert_eq!(pg.last_id().unwrap(), Some(id));
+
+    let info = pg.get_info(id).unwrap();
+    assert_eq!(info.data(), &[0, 1, 2, 3]);
+    assert_eq!(info.tags(), &BTreeSet::from(tags));
+    assert_eq!(pg.get_metadata(id).unwrap().size(), 4);
+    assert_eq!(pg.read_range(id, 1..3).unwrap(), [1, 2]);
+    assert_eq!(pg.read_range(id, 3..u64::MAX).unwrap(), [3]);
+    assert!(pg.read_range(id, 8..9).unwrap().is_empty());
+
+    pg.edit_file(id, Some(&[4]), None::<[Tag; 0]>).unwrap();
+    pg.remove_tags(id, [Tag::named("a")]).unwrap();
+    assert_eq!(pg.get_data(id).unwrap(), [4]);
+    assert_eq!(pg.get_tags(id).unwrap().len(), 2);
+
+    let other = pg.add_file(&[5], []).unwrap();
+    let many = pg.get_info_many(&[other, id, other]).unwrap();
+    let many = many.into_iter().map(Result::unwrap).collect::<Vec<_>>();
+    assert_eq!(many[0].data(), &[5]);
+    assert_eq!(many[1].tags().len(), 2);
+    assert_eq!(many[2].id(), other);
+
+    pg.remove_file(id).unwrap();
+    assert!(matches!(
+        pg.get_info(id),
+        Err(PostgresError::FileNotFound(_))
+    ));
+    assert!(matches!(
+        pg.read_range(id, 0..1),
+        Err(PostgresError::FileNotFound(_))
+    ));
+    let many = pg.get_tags_many(&[id, other]).unwrap();
+    assert!(matches!(many[0], Err(PostgresError::FileNotFound(_))));
+    assert!(many[1].as_ref().unwrap().is_empty());
+
+    let new = FileId::from_u64_unchecked(1000);
+    pg.add_file_with_id(new, &[], []).unwrap();
+    assert!(matches!(
+        pg.add_file_with_id(new, &[], []),
+        Err(PostgresError::AlreadyExists(_))
+    ));
+    assert!(pg.add_file(&[], []).unwrap() > new);
+
+    pg.set_config(b"config").unwrap();
+    assert_eq!(pg.config().unwrap(), b"config");
+}
+
+#[test]
+fn pg_search() {
+    let Some(pg) = connect("tbf_test_search") else {
+        return;
+    };
+
+    let rating = |val: f64| Tag::new(Group::custom("rating"), "stars").with_value(val);
+    let a = pg
+        .add_file(&[1, 1], [Tag::named("a.png"), rating(2.5)])
+        .unwrap();
+    let b = pg
+        .add_file(&[1], [Tag::named("b.png"), rating(4.0)])
+        .unwrap();
+    let c = pg
+        .add_file(&[1, 1, 1], [Tag::named("c_1.txt").with_value(7)])
+        .unwrap();
+
+    let all = TagPredicate::And(Vec::new());
+    assert_eq!(pg.search_tags(all.clone()).unwrap(), [a, b, c]);
+    assert_eq!(pg.search_tags(Tag::named("a.png")).unwrap(), [a]);
+    assert_eq!(
+        pg.search_tags(TagPredicate::name_glob("*.png")).unwrap(),
+        [a, b]
+    );
+    assert_eq!(
+        pg.search_tags(TagPredicate::name_glob("c_?.*")).unwrap(),
+        [c]
+    );
+    assert_eq!(
+        pg.search_tags(TagPredicate::value_gt(Group::custom("rating"), "stars", 3))
+            .unwrap(),
+        [b]
+    );
+    assert_eq!(
+        pg.search_tags(TagPredicate::value_range(
+            Group::Default,
+            "c_1.txt",
+            6.5,
+            7.0
+        ))
+        .unwrap(),
+        [c]
+    );
+    assert_eq!(
+        pg.search_tags(TagPredicate::not(TagPredicate::group(Group::custom(
+            "rating"
+        ))))
+        .unwrap(),
+        [c]
+    );
+    assert_eq!(pg.search_tags_iter(all.clone()).count(), 3);
+    // Regexes are checked locally
+    #[cfg(feature = "regex")]
+    assert_eq!(
+        pg.search_tags(TagPredicate::or([
+            TagPredicate::name_regex(r"^c_\d").unwrap(),
+            TagPredicate::name("a.png"),
+        ]))
+        .unwrap(),
+        [a, c]
+    );
+
+    let options = SearchOptions::new().after(a).limit(1);
+    assert_eq!(pg.search_tags_with(all.clone(), &options).unwrap(), [b]);
+    let options = SearchOptions::new().sort_by(SortBy::Size).descending();
+    assert_eq!(
+        pg.search_tags_with(all.clone(), &options).unwrap(),
+        [c, a, b]
+    );
+    let options = SearchOptions::new().sort_by(SortBy::value(Group::custom("rating"), "stars"));
+    assert_eq!(
+        pg.search_tags_with(all.clone(), &options).unwrap(),
+        [a, b, c]
+    );
+
+    let counts = pg.tag_counts(TagPredicate::name_glob("*.png")).unwrap();
+    assert_eq!(counts.get(&rating(4.0)), Some(&1));
+    assert_eq!(counts.len(), 4);
+
+    assert_eq!(pg.count_tags(all.clone()).unwrap(), 3);
+    assert_eq!(pg.count_tags(TagPredicate::name_glob("*.png")).unwrap(), 2);
+    assert!(pg.any_match(Tag::named("b.png")).unwrap());
+    assert!(!pg.any_match(Tag::named("d.png")).unwrap());
+    #[cfg(feature = "regex")]
+    {
+        let regex = TagPredicate::name_regex(r"^c_\d").unwrap();
+        assert_eq!(pg.count_tags(regex.clone()).unwrap(), 1);
+        assert!(pg.any_match(regex).unwrap());
+    }
+}
+
+#[test]
+fn pg_transaction() {
+    let Some(pg) = connect("tbf_test_transaction") else {
+        return;
+    };
+
+    let kept = pg.add_file(&[1], [Tag::named("a")]).unwrap();
+    let res: Result<(), PostgresError> = pg.transaction(|pg| {
+        let id = pg.add_file(&[2], [Tag::named("a")])?;
+        assert_eq!(pg.search_tags(Tag::named("a"))?, [kept, id]);
+        // A failed change inside a transaction doesn't stop the rest of it working
+        assert!(pg.add_file_with_id(kept, &[], []).is_err());
+        pg.edit_file(kept, Some(&[3]), None::<[Tag; 0]>)?;
+        pg.remove_file(FileId::from_u64_unchecked(5000))
+    });
+    assert!(matches!(res, Err(PostgresError::FileNotFound(_))));
+    assert_eq!(pg.search_tags(Tag::named("a")).unwrap(), [kept]);
+    assert_eq!(pg.get_data(kept).unwrap(), [1]);
+}
+
+#[test]
+fn pg_duplicates() {
+    let Some(pg) = connect("tbf_test_duplicates") else {
+        return;
+    };
+
+    let a = pg.add_file(&[1, 2], [Tag::named("a")]).unwrap();
+    pg.add_file(&[3], []).unwrap();
+    let b = pg.add_file(&[1, 2], [Tag::named("b")]).unwrap();
+    assert_eq!(pg.find_duplicates().unwrap(), [vec![a, b]]);
+    let hash = *pg.get_metadata(b).unwrap().hash();
+    assert_eq!(pg.find_by_hash(&hash).unwrap(), Some(a));
+    assert_eq!(pg.find_by_hash(&[0; 32]).unwrap(), None);
+
+    let merges = pg.dedupe(DedupePolicy::KeepOldest).unwrap();
+    assert_eq!(merges[0].kept(), a);
+    assert_eq!(merges[0].removed(), [b]);
+    assert_eq!(pg.get_tags(a).unwrap().len(), 2);
+    assert!(pg.find_duplicates().unwrap().is_empty());
+}